@@ -0,0 +1,323 @@
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::json::ListTransactionResult;
+use bitcoincore_rpc::{Client, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+// Wallet transactions read per listtransactions call while looking for a page
+const CHAIN_BATCH: usize = 200;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ActivityQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ActivitySource {
+    // A wallet transaction reported by bitcoind
+    Chain,
+    // An action taken through this server
+    Server,
+    // A wallet transaction paying a watched address
+    Watch,
+    // An invoice created for the wallet
+    Invoice,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ActivityItem {
+    // Unique across sources, breaks ties between items of the same second
+    #[serde(skip)]
+    key: String,
+    time: u64,
+    source: ActivitySource,
+    kind: String,
+    txid: Option<String>,
+    amount_sat: Option<i64>,
+    details: Value,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ActivityPage {
+    wallet: String,
+    // Newest first
+    #[serde(flatten)]
+    page: Page<ActivityItem>,
+}
+
+fn cursor(item: &ActivityItem) -> Cursor {
+    Cursor::new(item.time as i64, item.key.as_str())
+}
+
+fn item(time: u64, key: String, source: ActivitySource, kind: &str) -> ActivityItem {
+    ActivityItem {
+        key,
+        time,
+        source,
+        kind: kind.to_string(),
+        txid: None,
+        amount_sat: None,
+        details: Value::Null,
+    }
+}
+
+fn chain_item(tx: ListTransactionResult) -> ActivityItem {
+    let category = serde_json::to_value(tx.detail.category).unwrap_or_default();
+    let category = category.as_str().unwrap_or_default();
+    let mut item = item(
+        tx.info.time,
+        format!("chain-{}-{}-{}", tx.info.txid, tx.detail.vout, category),
+        ActivitySource::Chain,
+        category,
+    );
+    item.txid = Some(tx.info.txid.to_string());
+    item.amount_sat = Some(tx.detail.amount.to_sat());
+    item.details = json!({
+        "address": tx.detail.address.map(|address| address.assume_checked().to_string()),
+        "label": tx.detail.label,
+        "confirmations": tx.info.confirmations,
+        "block_height": tx.info.blockheight,
+    });
+    item
+}
+
+// listtransactions can only skip from the newest entry, so it is read back in batches
+// until a page's worth of entries past the cursor has turned up
+fn chain_items(
+    client: &Client,
+    caller: &Caller,
+    wallet: &str,
+    request: &PageRequest,
+) -> Result<Vec<ActivityItem>, ApiError> {
+    let mut items = Vec::new();
+    let mut skip = 0;
+    while items.len() < request.fetch() {
+        let batch = client
+            .list_transactions(None, Some(CHAIN_BATCH), Some(skip), Some(true))
+            .map_err(|e| {
+                error!("Failed to list transactions for wallet '{}': {}", wallet, e);
+                caller.rpc(e)
+            })?;
+        let read = batch.len();
+        items.extend(
+            batch
+                .into_iter()
+                .map(chain_item)
+                .filter(|item| request.is_older(&cursor(item))),
+        );
+        skip += read;
+        if read < CHAIN_BATCH {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+// The feed cursor as it applies to the items of one source: the items of that source
+// at the cursor's time come before or after it by their key
+fn source_cursor(after: &Cursor, prefix: &str) -> Cursor {
+    match after.id.strip_prefix(prefix) {
+        Some(id) => Cursor::new(after.at, id),
+        // All of them are newer
+        None if after.id.as_str() < prefix => Cursor::new(after.at, ""),
+        // All of them are older
+        None => Cursor::new(after.at.saturating_add(1), ""),
+    }
+}
+
+fn build_feed(
+    data: &AppState,
+    caller: &Caller,
+    wallet: &str,
+    client: &Client,
+    request: &PageRequest,
+) -> Result<Vec<ActivityItem>, ApiError> {
+    let mut items = chain_items(client, caller, wallet, request)?;
+
+    let tx_times: HashMap<String, u64> = items
+        .iter()
+        .filter_map(|item| Some((item.txid.clone()?, item.time)))
+        .collect();
+    let txids: Vec<String> = tx_times.keys().cloned().collect();
+    match data.db.watched_outputs_of(&txids) {
+        Ok(outputs) => items.extend(outputs.into_iter().map(|(address, output)| {
+            let time = tx_times.get(&output.txid).copied().unwrap_or_default();
+            let mut item = item(
+                time,
+                format!("watch-{}-{}", output.txid, output.vout),
+                ActivitySource::Watch,
+                "watched_address_paid",
+            );
+            item.txid = Some(output.txid.clone());
+            item.amount_sat = Some(output.amount_sat as i64);
+            item.details = json!({ "address": address, "block_height": output.block_height });
+            item
+        })),
+        Err(e) => warn!(
+            "Failed to load watched outputs for wallet '{}': {}",
+            wallet, e
+        ),
+    }
+
+    let server_before = request.after.as_ref().map(|after| {
+        let before = source_cursor(after, "server-");
+        (before.at, before.id.parse().unwrap_or_default())
+    });
+    match data.db.activity(wallet, server_before, request.fetch()) {
+        Ok(records) => items.extend(records.into_iter().map(|record| {
+            let mut item = item(
+                record.time as u64,
+                format!("server-{:012}", record.id),
+                ActivitySource::Server,
+                &record.kind,
+            );
+            item.txid = record.details["txid"].as_str().map(str::to_string);
+            item.details = record.details;
+            item
+        })),
+        Err(e) => warn!(
+            "Failed to load server activity for wallet '{}': {}",
+            wallet, e
+        ),
+    }
+
+    let invoices_before = request
+        .after
+        .as_ref()
+        .map(|after| source_cursor(after, "invoice-"));
+    match data
+        .db
+        .invoices_page(wallet, invoices_before.as_ref(), request.fetch())
+    {
+        Ok(invoices) => items.extend(invoices.into_iter().map(|invoice| {
+            let mut item = item(
+                invoice.created_at as u64,
+                format!("invoice-{}", invoice.id),
+                ActivitySource::Invoice,
+                "invoice_created",
+            );
+            item.amount_sat = Some(invoice.amount_sat as i64);
+            item.details = json!({
+                "id": invoice.id,
+                "address": invoice.address,
+                "description": invoice.description,
+            });
+            item
+        })),
+        Err(e) => warn!("Failed to load invoices for wallet '{}': {}", wallet, e),
+    }
+    Ok(items)
+}
+
+// Records wallet-related events from the bus, so the feed can show what was done
+// through the server alongside what happened on chain
+pub(crate) async fn record_wallet_events(data: web::Data<AppState>) {
+    let mut events = data.events.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Activity log lagged behind, skipped {} event(s)", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        // Wallet transactions are already in the feed from the chain source
+        if matches!(event, Event::WalletTransaction { .. }) {
+            continue;
+        }
+        let details = serde_json::to_value(&event).unwrap_or_default();
+        // A transfer between two wallets shows up in both feeds
+        for wallet in event.wallet().into_iter().chain(event.counterparty()) {
+            if let Err(e) = data.db.record_activity(wallet, event.kind(), &details) {
+                error!(
+                    "Failed to record '{}' activity for wallet '{}': {}",
+                    event.kind(),
+                    wallet,
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/activity",
+    tag = "activity",
+    params(ActivityQuery),
+    responses(
+        (status = 200, body = ActivityPage),
+    )
+)]
+pub(crate) async fn wallet_activity(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    query: web::Query<ActivityQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /wallet/{}/activity - limit={:?}, cursor={:?}",
+        walletid, query.limit, query.cursor
+    );
+    let request = PageRequest::new(
+        query.limit,
+        query.cursor.as_deref(),
+        DEFAULT_PAGE_SIZE,
+        MAX_PAGE_SIZE,
+    )?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/activity - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+
+    let page = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let items = build_feed(data, caller, &wallet_name, &client, &request)?;
+        Ok(request.paginate(items, cursor))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(ActivityPage {
+        wallet: walletid.into_inner(),
+        page,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn older(after: &Cursor, prefix: &str, at: i64, id: &str) -> bool {
+        Cursor::new(at, id) < source_cursor(after, prefix)
+    }
+
+    #[test]
+    fn source_cursor_splits_items_of_the_same_second() {
+        let after = Cursor::new(100, "server-000000000007");
+        assert!(older(&after, "server-", 100, "000000000006"));
+        assert!(!older(&after, "server-", 100, "000000000007"));
+        assert!(older(&after, "server-", 99, "000000000099"));
+        // "chain-" sorts before "server-", so the chain items of that second came first
+        let after = Cursor::new(100, "chain-abc-0-receive");
+        assert!(!older(&after, "server-", 100, "000000000001"));
+        assert!(older(&after, "server-", 99, "000000000001"));
+        // and "watch-" after it
+        let after = Cursor::new(100, "watch-abc-0");
+        assert!(older(&after, "server-", 100, "999999999999"));
+        assert!(!older(&after, "server-", 101, ""));
+    }
+}
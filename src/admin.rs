@@ -0,0 +1,190 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::{reload, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, BlockHash};
+use bitcoincore_rpc::json::GetBlockHeaderResult;
+use bitcoincore_rpc::Error as RpcError;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+pub(crate) const MOCKTIME_KEY: &str = "admin:mocktime";
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SetMocktimeRequest {
+    // 0 switches the node back to the system clock
+    timestamp: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct AdvanceMocktimeRequest {
+    seconds: u64,
+    address: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MocktimeResponse {
+    mocktime: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_hash: Option<String>,
+}
+
+async fn set_node_mocktime(
+    data: &AppState,
+    timestamp: u64,
+) -> Result<Result<(), RpcError>, ApiError> {
+    let set = rpc_pool::call_node::<Value>(data, "setmocktime", vec![json!(timestamp)]).await?;
+    Ok(set.map(drop))
+}
+
+// bitcoind cannot report its mocktime, so the last value we set is remembered
+async fn current_mocktime(data: &AppState) -> Result<u64, String> {
+    if let Some(stored) = data.store.get(MOCKTIME_KEY).await? {
+        if let Ok(mocktime) = stored.parse::<u64>() {
+            if mocktime > 0 {
+                return Ok(mocktime);
+            }
+        }
+    }
+    let tip = rpc_pool::call_node::<BlockHash>(data, "getbestblockhash", vec![])
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    let tip_time =
+        rpc_pool::call_node::<GetBlockHeaderResult>(data, "getblockheader", vec![json!(tip)])
+            .await
+            .map_err(|e| e.to_string())?
+            .map(|header| header.time as u64)
+            .map_err(|e| e.to_string())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    Ok(tip_time.max(now))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/mocktime",
+    tag = "admin",
+    responses(
+        (status = 200, body = MocktimeResponse),
+    )
+)]
+pub(crate) async fn set_mocktime(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<SetMocktimeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /admin/mocktime - timestamp={}", req.timestamp);
+    data.config.ensure_regtest("Mocktime")?;
+    if let Err(e) = set_node_mocktime(&data, req.timestamp).await? {
+        error!("Failed to set mocktime: {}", e);
+        return Err(ApiError::rpc(&http_req, e));
+    }
+    if let Err(e) = data
+        .store
+        .set(MOCKTIME_KEY, &req.timestamp.to_string(), None)
+        .await
+    {
+        error!("Failed to remember mocktime: {}", e);
+    }
+    Ok(HttpResponse::Ok().json(MocktimeResponse {
+        mocktime: req.timestamp,
+        block_hash: None,
+    }))
+}
+
+// Moves the node clock forward and mines a block so the new time lands on chain,
+// which is what CLTV/CSV time locks are evaluated against
+#[utoipa::path(
+    post,
+    path = "/admin/mocktime/advance",
+    tag = "admin",
+    responses(
+        (status = 200, body = MocktimeResponse),
+    )
+)]
+pub(crate) async fn advance_mocktime(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<AdvanceMocktimeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /admin/mocktime/advance - seconds={}, address='{}'",
+        req.seconds, req.address
+    );
+    data.config.ensure_regtest("Mocktime")?;
+    let address = match Address::from_str(&req.address)
+        .map_err(|e| e.to_string())
+        .and_then(|addr| {
+            addr.require_network(data.config.network)
+                .map_err(|e| e.to_string())
+        }) {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Advance mocktime invalid address: {}", e);
+            return Err(ApiError::InvalidAddress(format!("Invalid address: {}", e)));
+        }
+    };
+    let mocktime = match current_mocktime(&data).await {
+        Ok(current) => current + req.seconds,
+        Err(e) => {
+            error!("Failed to determine current mocktime: {}", e);
+            return Err(ApiError::Internal(e));
+        }
+    };
+    if let Err(e) = set_node_mocktime(&data, mocktime).await? {
+        error!("Failed to set mocktime: {}", e);
+        return Err(ApiError::rpc(&http_req, e));
+    }
+    if let Err(e) = data
+        .store
+        .set(MOCKTIME_KEY, &mocktime.to_string(), None)
+        .await
+    {
+        error!("Failed to remember mocktime: {}", e);
+    }
+
+    let params = vec![json!(1), json!(address.to_string())];
+    let mined: Result<Vec<BlockHash>, _> =
+        rpc_pool::call_node(&data, "generatetoaddress", params).await?;
+    match mined {
+        Ok(hashes) => {
+            info!("Advanced mocktime to {} and mined {:?}", mocktime, hashes);
+            Ok(HttpResponse::Ok().json(MocktimeResponse {
+                mocktime,
+                block_hash: hashes.first().map(|hash| hash.to_string()),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to mine block after advancing mocktime: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+// Same as sending SIGHUP: re-reads .env and applies CORS origins, log level, faucet
+// caps and rate limits without restarting
+#[utoipa::path(
+    post,
+    path = "/admin/reload-config",
+    tag = "admin",
+    responses(
+        (status = 200, body = crate::Settings),
+    )
+)]
+pub(crate) async fn reload_config(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("POST /admin/reload-config");
+    match reload::reload(&data) {
+        Ok(settings) => Ok(HttpResponse::Ok().json(settings)),
+        Err(e) => {
+            error!("Configuration reload failed: {}", e);
+            Err(ApiError::InvalidRequest(e))
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::Config;
+use bitcoincore_rpc::jsonrpc::error::{Error as JsonRpcError, RpcError as JsonRpcErrorObject};
+use bitcoincore_rpc::Error as RpcError;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// The blocking client's timeout, jsonrpc's default
+const RPC_TIMEOUT: Duration = Duration::from_secs(15);
+
+// JSON-RPC over reqwest, for the `async-rpc` feature. The calls are awaited instead of
+// blocking a thread, and reqwest keeps a pool of connections to bitcoind. Errors are
+// turned into the same errors bitcoincore-rpc reports, so ApiError handles them alike.
+pub(crate) struct AsyncRpcClient {
+    http: reqwest::Client,
+    url: String,
+    user: String,
+    password: String,
+    next_id: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    result: Option<Value>,
+    error: Option<JsonRpcErrorObject>,
+    #[serde(default)]
+    id: Value,
+}
+
+impl Response {
+    fn into_result(self) -> Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(RpcError::JsonRpc(JsonRpcError::Rpc(error))),
+            None => Ok(self.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+impl AsyncRpcClient {
+    pub(crate) fn new(config: &Config) -> Result<Self, RpcError> {
+        let mut builder = reqwest::Client::builder().timeout(RPC_TIMEOUT);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(proxy.async_rpc_proxy().map_err(transport)?);
+        }
+        Ok(Self {
+            http: builder.build().map_err(transport)?,
+            url: config.rpc_url.clone(),
+            user: config.rpc_user.clone(),
+            password: config.rpc_password.clone(),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    // A wallet RPC, or a node one without `wallet`
+    pub(crate) async fn call<T: DeserializeOwned>(
+        &self,
+        wallet: Option<&str>,
+        method: &str,
+        params: &[Value],
+    ) -> Result<T, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let response: Response = self.post(wallet, &request(id, method, params)).await?;
+        serde_json::from_value(response.into_result()?).map_err(RpcError::Json)
+    }
+
+    // Sends the calls as one JSON-RPC batch and returns their results in the same order
+    pub(crate) async fn batch(
+        &self,
+        wallet: Option<&str>,
+        calls: &[(&str, Vec<Value>)],
+    ) -> Result<Vec<Result<Value, RpcError>>, RpcError> {
+        let first_id = self
+            .next_id
+            .fetch_add(calls.len() as u64, Ordering::Relaxed);
+        let body: Vec<Value> = (first_id..)
+            .zip(calls)
+            .map(|(id, (method, params))| request(id, method, params))
+            .collect();
+        let responses: Vec<Response> = self.post(wallet, &Value::Array(body)).await?;
+        // The responses are matched up by id, their order is not guaranteed
+        let mut results: Vec<Option<Result<Value, RpcError>>> =
+            calls.iter().map(|_| None).collect();
+        for response in responses {
+            let slot = response
+                .id
+                .as_u64()
+                .and_then(|id| id.checked_sub(first_id))
+                .and_then(|index| results.get_mut(index as usize));
+            if let Some(slot) = slot {
+                *slot = Some(response.into_result());
+            }
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or_else(|| Err(transport("no response to a call of the batch")))
+            })
+            .collect())
+    }
+
+    async fn post<R: DeserializeOwned>(
+        &self,
+        wallet: Option<&str>,
+        body: &Value,
+    ) -> Result<R, RpcError> {
+        let url = match wallet {
+            Some(wallet) => format!("{}/wallet/{}", self.url, wallet),
+            None => self.url.clone(),
+        };
+        let response = self
+            .http
+            .post(&url)
+            .basic_auth(&self.user, Some(&self.password))
+            .json(body)
+            .send()
+            .await
+            .map_err(transport)?;
+        // bitcoind reports RPC errors with a 4xx or 5xx status and a JSON body, but
+        // a failed login has no body at all
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(transport)?;
+        serde_json::from_slice(&bytes).map_err(|e| {
+            if status.is_success() {
+                RpcError::Json(e)
+            } else {
+                transport(format!("bitcoind answered {status}"))
+            }
+        })
+    }
+}
+
+fn request(id: u64, method: &str, params: &[Value]) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+    })
+}
+
+fn transport(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> RpcError {
+    RpcError::JsonRpc(JsonRpcError::Transport(error.into()))
+}
@@ -0,0 +1,462 @@
+use crate::db::{unix_now, User};
+use crate::errors::ApiError;
+use crate::{routes, ws, AppState};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use bitcoincore_rpc::Error as RpcError;
+use dotenv as env;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+const MIN_SECRET_LEN: usize = 32;
+const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MIN_USERNAME_LEN: usize = 3;
+const MAX_USERNAME_LEN: usize = 32;
+const MIN_PASSWORD_LEN: usize = 8;
+// Reachable without a token; the rest of the API needs one in users mode
+const PUBLIC_PATHS: [&str; 2] = ["/auth/register", "/auth/login"];
+const PUBLIC_PREFIXES: [&str; 5] = ["/metrics", "/health", "/ready", "/api-doc/", "/swagger-ui/"];
+// Reset, mock time, chain rewrites and the instructor side of cohorts; in users mode only
+// the users listed in `admin_users` reach them
+const ADMIN_PREFIXES: [&str; 5] = [
+    "/admin/",
+    "/chain/invalidate/",
+    "/chain/reconsider/",
+    "/chain/reorg",
+    "/cohorts",
+];
+// Students fund their own wallets, see cohort::student_faucet
+const ADMIN_EXCEPTIONS: [&str; 1] = ["/faucet"];
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+// Users mode is opt-in via `jwt_secret`, which signs the session tokens (HS256)
+#[derive(Clone)]
+pub(crate) struct AuthConfig {
+    secret: Vec<u8>,
+    token_ttl: Duration,
+    // `admin_users`, comma separated
+    admins: HashSet<String>,
+}
+
+// Keep the secret out of any debug output
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("token_ttl", &self.token_ttl)
+            .field("admins", &self.admins)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuthConfig {
+    pub(crate) fn from_env() -> Result<Option<Self>, RpcError> {
+        let Ok(secret) = env::var("jwt_secret") else {
+            return Ok(None);
+        };
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(RpcError::ReturnedError(format!(
+                "jwt_secret must be at least {MIN_SECRET_LEN} characters"
+            )));
+        }
+        let token_ttl = match env::var("jwt_ttl_secs") {
+            Ok(value) => match value.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                _ => {
+                    return Err(RpcError::ReturnedError(
+                        "jwt_ttl_secs must be a positive number".into(),
+                    ))
+                }
+            },
+            Err(_) => DEFAULT_TOKEN_TTL,
+        };
+        let admins = env::var("admin_users")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Some(Self {
+            secret: secret.into_bytes(),
+            token_ttl,
+            admins,
+        }))
+    }
+
+    fn issue(&self, username: &str) -> Result<TokenResponse, ApiError> {
+        let issued_at = unix_now();
+        let claims = Claims {
+            sub: username.to_string(),
+            iat: issued_at,
+            exp: issued_at + self.token_ttl.as_secs() as i64,
+        };
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(&self.secret),
+        )
+        .map_err(|e| ApiError::Internal(format!("cannot issue a token: {e}")))?;
+        Ok(TokenResponse {
+            username: claims.sub,
+            token,
+            token_type: "Bearer",
+            expires_at: claims.exp,
+        })
+    }
+
+    // The user a valid, unexpired token was issued to
+    fn verify(&self, token: &str) -> Option<String> {
+        jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(&self.secret),
+            &Validation::default(),
+        )
+        .ok()
+        .map(|data| data.claims.sub)
+    }
+}
+
+// `admin_token`, a shared secret that unlocks the admin routes in `X-Admin-Token`
+pub(crate) struct AdminToken(String);
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AdminToken(..)")
+    }
+}
+
+impl AdminToken {
+    pub(crate) fn from_env() -> Result<Option<Self>, RpcError> {
+        let Ok(token) = env::var("admin_token") else {
+            return Ok(None);
+        };
+        if token.len() < MIN_SECRET_LEN {
+            return Err(RpcError::ReturnedError(format!(
+                "admin_token must be at least {MIN_SECRET_LEN} characters"
+            )));
+        }
+        Ok(Some(Self(token)))
+    }
+
+    fn matches(&self, token: &str) -> bool {
+        ws::constant_time_eq(self.0.as_bytes(), token.as_bytes())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+// The authenticated user, attached to every request by the auth middleware
+#[derive(Clone)]
+struct AuthUser(String);
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TokenResponse {
+    username: String,
+    token: String,
+    token_type: &'static str,
+    // Unix time
+    expires_at: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CurrentUser {
+    username: String,
+    // Listed in `admin_users`
+    admin: bool,
+}
+
+pub(crate) fn user(http_req: &HttpRequest) -> Option<String> {
+    http_req
+        .extensions()
+        .get::<AuthUser>()
+        .map(|user| user.0.clone())
+}
+
+// Whether the caller may use the admin routes: anyone with the admin token, the users
+// listed in `admin_users`, and everyone on a private deployment without either. A
+// public sandbox without `admin_token` has no admins at all.
+pub(crate) fn is_admin(data: &AppState, http_req: &HttpRequest) -> bool {
+    let token = http_req
+        .headers()
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if let (Some(expected), Some(token)) = (&data.config.admin_token, token) {
+        if expected.matches(token) {
+            return true;
+        }
+    }
+    match &data.config.auth {
+        Some(auth) => user(http_req).is_some_and(|user| auth.admins.contains(&user)),
+        None => data.config.admin_token.is_none() && data.sandbox.is_none(),
+    }
+}
+
+fn admin_only(http_req: &HttpRequest) -> bool {
+    is_admin_path(routes::route_path(http_req))
+}
+
+// Both take the path without the version prefix, see routes::route_path
+fn is_public_path(path: &str) -> bool {
+    PUBLIC_PATHS.contains(&path)
+        || PUBLIC_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+}
+
+pub(crate) fn is_admin_path(path: &str) -> bool {
+    ADMIN_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+        && !ADMIN_EXCEPTIONS.iter().any(|suffix| path.ends_with(suffix))
+}
+
+// Wallet names are namespaced per user, so users only ever reach their own wallets.
+// Usernames cannot contain '-', which keeps one user's prefix out of another's.
+pub(crate) fn scoped_name(username: &str, name: &str) -> String {
+    format!("u-{username}-{name}")
+}
+
+fn check_credentials(credentials: &Credentials) -> Result<(), ApiError> {
+    let username = &credentials.username;
+    if !(MIN_USERNAME_LEN..=MAX_USERNAME_LEN).contains(&username.len())
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(ApiError::InvalidRequest(format!(
+            "username must be {MIN_USERNAME_LEN} to {MAX_USERNAME_LEN} letters, digits or '_'"
+        )));
+    }
+    if credentials.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::InvalidRequest(format!(
+            "password must be at least {MIN_PASSWORD_LEN} characters"
+        )));
+    }
+    Ok(())
+}
+
+fn auth_config(data: &AppState) -> Result<&AuthConfig, ApiError> {
+    data.config
+        .auth
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("Users mode is disabled".into()))
+}
+
+// A Bearer header, or `access_token` in the URL for EventSource and WebSocket clients
+fn request_token(req: &ServiceRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.to_string());
+    }
+    web::Query::<AccessTokenQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().access_token)
+}
+
+#[derive(Deserialize)]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+// Turns away requests without a valid token, except to the public paths
+pub(crate) async fn auth_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await;
+    };
+    let Some(auth) = &data.config.auth else {
+        return next.call(req).await;
+    };
+    if is_public_path(routes::route_path(req.request())) {
+        return next.call(req).await;
+    }
+    let Some(token) = request_token(&req) else {
+        return Err(ApiError::Unauthorized(format!(
+            "A token from POST /auth/login is required for {}",
+            req.path()
+        ))
+        .into());
+    };
+    let Some(username) = auth.verify(&token) else {
+        warn!("{} {} - invalid or expired token", req.method(), req.path());
+        return Err(ApiError::Unauthorized("Invalid or expired token".into()).into());
+    };
+    req.extensions_mut().insert(AuthUser(username));
+    next.call(req).await
+}
+
+// Keeps the admin routes to admins. Runs inside auth_middleware, so users are known.
+pub(crate) async fn admin_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await;
+    };
+    if !admin_only(req.request()) || is_admin(&data, req.request()) {
+        return next.call(req).await;
+    }
+    warn!("{} {} - not an admin", req.method(), req.path());
+    let error = if data.config.auth.is_some() {
+        ApiError::Forbidden(format!("{} is only available to admin users", req.path()))
+    } else if data.config.admin_token.is_some() {
+        ApiError::Unauthorized(format!(
+            "{} needs the admin token in {ADMIN_TOKEN_HEADER}",
+            req.path()
+        ))
+    } else {
+        ApiError::Forbidden(format!(
+            "{} is disabled in sandbox mode until admin_token is set",
+            req.path()
+        ))
+    };
+    Err(error.into())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/register",
+    tag = "auth",
+    responses(
+        (status = 201, body = TokenResponse),
+    )
+)]
+pub(crate) async fn register(
+    data: web::Data<AppState>,
+    req: web::Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /auth/register - username='{}'", req.username);
+    let auth = auth_config(&data)?;
+    check_credentials(&req)?;
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(req.password.as_bytes(), &salt)
+        .map_err(|e| ApiError::Internal(format!("cannot hash the password: {e}")))?
+        .to_string();
+    let user = User {
+        username: req.username.clone(),
+        password_hash,
+        created_at: unix_now(),
+    };
+    if !data.db.insert_user(&user)? {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' already exists",
+            req.username
+        )));
+    }
+    info!("Registered user '{}'", user.username);
+    Ok(HttpResponse::Created().json(auth.issue(&user.username)?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    responses(
+        (status = 200, body = TokenResponse),
+    )
+)]
+pub(crate) async fn login(
+    data: web::Data<AppState>,
+    req: web::Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /auth/login - username='{}'", req.username);
+    let auth = auth_config(&data)?;
+    let verified = data.db.user(&req.username)?.is_some_and(|user| {
+        PasswordHash::new(&user.password_hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(req.password.as_bytes(), &hash)
+                .is_ok()
+        })
+    });
+    if !verified {
+        warn!(
+            "POST /auth/login - wrong credentials for '{}'",
+            req.username
+        );
+        return Err(ApiError::Unauthorized(
+            "Invalid username or password".into(),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(auth.issue(&req.username)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, body = CurrentUser),
+    )
+)]
+pub(crate) async fn current_user(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /auth/me");
+    auth_config(&data)?;
+    let username = user(&http_req).ok_or_else(|| ApiError::Unauthorized("Not logged in".into()))?;
+    let admin = is_admin(&data, &http_req);
+    Ok(HttpResponse::Ok().json(CurrentUser { username, admin }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn admin_only_path(path: &str) -> bool {
+        admin_only(&TestRequest::post().uri(path).to_http_request())
+    }
+
+    #[test]
+    fn admin_routes_are_guarded_however_the_path_is_spelled() {
+        for path in [
+            "/admin/reset",
+            "/%61dmin/reset",
+            "/api/v1/%61dmin/reset",
+            "/chain/%69nvalidate/00ff",
+            "/api/v1/chain/reconsider/%30%30ff",
+            "/chain/%72eorg",
+            "/%63ohorts",
+            "/api/v1/%63ohorts/spring",
+        ] {
+            assert!(admin_only_path(path), "{path}");
+        }
+        // Also what the router keeps encoded, so these reach no admin route either
+        for path in [
+            "/cohorts/spring/faucet",
+            "/api/v1/wallet/%61dmin",
+            "/%2561dmin/reset",
+            "/chain/reconsider%2F00ff",
+        ] {
+            assert!(!admin_only_path(path), "{path}");
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::db::unix_now;
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::RpcApi;
+use futures_util::{stream, Stream, StreamExt};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use utoipa::{IntoParams, ToSchema};
+
+const CHUNK_BYTES: usize = 64 * 1024;
+// Descriptor wallets of the comic are far smaller; this only stops runaway uploads
+const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct RestoreQuery {
+    name: String,
+    // Whether bitcoind loads the wallet by itself when it restarts
+    load_on_startup: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RestoreResponse {
+    name: String,
+    warnings: Vec<String>,
+}
+
+// bitcoind writes the backups and reads the uploads, so both sides need the
+// directory, under the same path
+fn backup_dir(data: &AppState) -> Result<PathBuf, ApiError> {
+    let dir = data
+        .config
+        .backup_dir
+        .as_deref()
+        .ok_or_else(|| ApiError::NotFound("Wallet backups are disabled".into()))?;
+    std::fs::create_dir_all(dir)
+        .and_then(|()| std::fs::canonicalize(dir))
+        .map_err(|e| {
+            error!("Cannot use backup directory {}: {}", dir.display(), e);
+            ApiError::Internal(format!("cannot use the backup directory: {e}"))
+        })
+}
+
+// Wallet names may contain slashes, and sandbox names carry the session
+fn file_name(wallet: &str, suffix: &str) -> String {
+    let stem: String = wallet
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{stem}-{}{suffix}", unix_now())
+}
+
+// A backup written for one download; deleted once the download is over, also when
+// the client went away before the end
+struct BackupCopy {
+    file: File,
+    path: PathBuf,
+}
+
+impl Drop for BackupCopy {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to delete backup {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Reads the file a chunk at a time off the async threads
+fn file_stream(copy: BackupCopy) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    stream::try_unfold(copy, |mut copy| async move {
+        let (copy, chunk) = web::block(move || {
+            let mut chunk = vec![0; CHUNK_BYTES];
+            let read = copy.file.read(&mut chunk)?;
+            chunk.truncate(read);
+            Ok::<_, std::io::Error>((copy, chunk))
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        Ok((!chunk.is_empty()).then(|| (Bytes::from(chunk), copy)))
+    })
+}
+
+// Has bitcoind copy the wallet file into the backup directory and sends the copy back
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/backup",
+    tag = "backup",
+    responses(
+        (status = 200, description = "The wallet.dat backup", body = String, content_type = "application/octet-stream"),
+    )
+)]
+pub(crate) async fn backup_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/{}/backup", walletid);
+    let dir = backup_dir(&data)?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("POST /wallet/{}/backup - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let path = dir.join(file_name(&wallet_name, ".dat"));
+    let destination = path.to_string_lossy().into_owned();
+    let backed_up = rpc_pool::blocking(move || client.backup_wallet(Some(&destination))).await?;
+    if let Err(e) = backed_up {
+        error!("Failed to back up wallet '{}': {}", wallet_name, e);
+        return Err(ApiError::rpc(&http_req, e));
+    }
+    let file = File::open(&path).map_err(|e| {
+        error!("Cannot read backup {}: {}", path.display(), e);
+        ApiError::Internal(format!(
+            "bitcoind wrote the backup, but the server cannot read it: {e}"
+        ))
+    })?;
+    let copy = BackupCopy {
+        file,
+        path: path.clone(),
+    };
+    info!("Backed up wallet '{}' to {}", wallet_name, path.display());
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header(header::ContentDisposition::attachment(format!(
+            "{}.dat",
+            walletid
+        )))
+        .streaming(file_stream(copy)))
+}
+
+async fn save_upload(mut payload: web::Payload, path: &Path) -> Result<usize, ApiError> {
+    let mut file = File::create(path).map_err(|e| {
+        error!("Cannot write upload {}: {}", path.display(), e);
+        ApiError::Internal(format!("cannot store the upload: {e}"))
+    })?;
+    let mut size = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| ApiError::InvalidRequest(format!("Upload failed: {e}")))?;
+        size += chunk.len();
+        if size > MAX_UPLOAD_BYTES {
+            return Err(ApiError::InvalidRequest(format!(
+                "Wallet files are limited to {} MiB",
+                MAX_UPLOAD_BYTES / 1024 / 1024
+            )));
+        }
+        file.write_all(&chunk)
+            .map_err(|e| ApiError::Internal(format!("cannot store the upload: {e}")))?;
+    }
+    Ok(size)
+}
+
+// Loads a wallet file made by POST /wallet/{id}/backup (or bitcoind's backupwallet)
+// under a new name. The body is the raw file.
+#[utoipa::path(
+    post,
+    path = "/wallet/restore",
+    tag = "backup",
+    params(RestoreQuery),
+    responses(
+        (status = 200, body = RestoreResponse),
+    )
+)]
+pub(crate) async fn restore_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<RestoreQuery>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/restore - name='{}'", query.name);
+    if query.name.trim().is_empty() {
+        return Err(ApiError::InvalidRequest("name must not be empty".into()));
+    }
+    let dir = backup_dir(&data)?;
+    let wallet_name = data.wallet_name(&http_req, &query.name);
+    if data.wallet_client(&wallet_name).is_some() {
+        return Err(ApiError::Conflict(format!(
+            "Wallet '{}' already exists",
+            query.name
+        )));
+    }
+
+    let path = dir.join(file_name(&wallet_name, ".upload"));
+    let uploaded = save_upload(payload, &path).await;
+    let restored = match uploaded {
+        Ok(0) => Err(ApiError::InvalidRequest(
+            "The body must be a wallet file".into(),
+        )),
+        Ok(_) => {
+            // Restoring rescans the wallet, which takes a while
+            let params = vec![
+                json!(wallet_name),
+                json!(path.to_string_lossy()),
+                json!(query.load_on_startup),
+            ];
+            rpc_pool::call_node::<Value>(&data, "restorewallet", params)
+                .await
+                .and_then(|restored| {
+                    restored.map_err(|e| {
+                        error!("Failed to restore wallet '{}': {}", wallet_name, e);
+                        ApiError::rpc(&http_req, e)
+                    })
+                })
+        }
+        Err(e) => Err(e),
+    };
+    // bitcoind copied the file into its wallet directory
+    if let Err(e) = std::fs::remove_file(&path) {
+        warn!("Failed to delete upload {}: {}", path.display(), e);
+    }
+    let restored = restored?;
+
+    let client = match data.config.create_client(&wallet_name) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create RPC client for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    // A single `warning` before Core 25, a `warnings` array since
+    let warnings = restored["warnings"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .chain(restored.get("warning"))
+        .filter_map(Value::as_str)
+        .filter(|warning| !warning.is_empty())
+        .map(str::to_string)
+        .collect();
+    info!("Restored wallet '{}' from an uploaded backup", wallet_name);
+    data.events.publish(Event::WalletReady {
+        wallet: wallet_name.clone(),
+    });
+    data.register_wallet(wallet_name, client);
+    Ok(HttpResponse::Ok().json(RestoreResponse {
+        name: query.name.clone(),
+        warnings,
+    }))
+}
@@ -0,0 +1,284 @@
+use crate::errors::ApiError;
+use crate::reservations::{self, ReservationSummary};
+use crate::rpc_pool;
+use crate::utxos::{COINBASE_MATURITY_CONFIRMATIONS, RECENT_TRANSACTIONS};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::json::{GetBalancesResult, GetTransactionResultDetailCategory};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::thread;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Default, Clone, Copy, PartialEq, ToSchema)]
+pub(crate) struct BalanceBreakdown {
+    // Confirmed, or unconfirmed change from the wallet itself
+    trusted_sat: u64,
+    // Unconfirmed payments from others
+    untrusted_pending_sat: u64,
+    // Coinbase outputs younger than 100 blocks
+    immature_sat: u64,
+    total_sat: u64,
+}
+
+impl BalanceBreakdown {
+    pub(crate) fn add(&mut self, other: BalanceBreakdown) {
+        self.trusted_sat += other.trusted_sat;
+        self.untrusted_pending_sat += other.untrusted_pending_sat;
+        self.immature_sat += other.immature_sat;
+        self.total_sat += other.total_sat;
+    }
+
+    // Coinbase coins that mature with a later block
+    pub(crate) fn has_immature(&self) -> bool {
+        self.immature_sat > 0
+    }
+}
+
+impl From<GetBalancesResult> for BalanceBreakdown {
+    fn from(balances: GetBalancesResult) -> Self {
+        let trusted_sat = balances.mine.trusted.to_sat();
+        let untrusted_pending_sat = balances.mine.untrusted_pending.to_sat();
+        let immature_sat = balances.mine.immature.to_sat();
+        BalanceBreakdown {
+            trusted_sat,
+            untrusted_pending_sat,
+            immature_sat,
+            total_sat: trusted_sat + untrusted_pending_sat + immature_sat,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct WalletBalance {
+    wallet: String,
+    // Left out when the wallet's balance could not be read, see `error`
+    #[serde(flatten)]
+    balance: Option<BalanceBreakdown>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl WalletBalance {
+    pub(crate) fn total_sat(&self) -> u64 {
+        self.balance.map_or(0, |balance| balance.total_sat)
+    }
+}
+
+// GET /wallet/{walletid}/balance
+#[derive(Serialize, ToSchema)]
+struct BalanceResponse {
+    // Spendable now
+    balance_sat: u64,
+    // Incoming payments waiting for a confirmation
+    unconfirmed_sat: u64,
+    // Mined coins that are not spendable until they are 100 blocks deep
+    immature_sat: u64,
+    total_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MaturingCoinbase {
+    txid: String,
+    amount_sat: u64,
+    confirmations: u32,
+    blocks_until_mature: u32,
+}
+
+// GET /wallet/{walletid}/balances
+#[derive(Serialize, ToSchema)]
+struct WalletBalancesResponse {
+    wallet: String,
+    #[serde(flatten)]
+    balance: BalanceBreakdown,
+    // Coinbase outputs making up immature_sat, the next to mature first
+    maturing: Vec<MaturingCoinbase>,
+    // How much of trusted_sat is set aside in reservation buckets
+    reservations: ReservationSummary,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BalancesResponse {
+    wallets: Vec<WalletBalance>,
+    total: BalanceBreakdown,
+}
+
+// Queries every wallet at once; the RPC client is blocking, so each call gets its own thread
+pub(crate) fn fetch_balances(
+    data: &AppState,
+    wallets: Vec<(String, String)>,
+) -> Vec<WalletBalance> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = wallets
+            .into_iter()
+            .map(|(wallet, shown)| {
+                scope.spawn(move || {
+                    let result = match data.clients.get(&wallet).map(|pool| pool.get()) {
+                        Some(client) => client.get_balances().map_err(|e| e.to_string()),
+                        None => Err("wallet was unloaded".to_string()),
+                    };
+                    match result {
+                        Ok(balances) => WalletBalance {
+                            wallet: shown,
+                            balance: Some(balances.into()),
+                            error: None,
+                        },
+                        Err(e) => {
+                            warn!("Failed to get balances for wallet '{}': {}", wallet, e);
+                            WalletBalance {
+                                wallet: shown,
+                                balance: None,
+                                error: Some(e),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .collect()
+    })
+}
+
+fn maturing_coinbases(client: &Client) -> Result<Vec<MaturingCoinbase>, RpcError> {
+    let transactions =
+        client.list_transactions(None, Some(RECENT_TRANSACTIONS), None, Some(true))?;
+    let mut maturing: Vec<MaturingCoinbase> = transactions
+        .into_iter()
+        .filter(|tx| tx.detail.category == GetTransactionResultDetailCategory::Immature)
+        .filter_map(|tx| {
+            // Orphaned coinbases report negative confirmations and can never mature
+            let confirmations = u32::try_from(tx.info.confirmations).ok()?;
+            Some(MaturingCoinbase {
+                txid: tx.info.txid.to_string(),
+                amount_sat: tx.detail.amount.to_sat().unsigned_abs(),
+                confirmations,
+                blocks_until_mature: COINBASE_MATURITY_CONFIRMATIONS.saturating_sub(confirmations),
+            })
+        })
+        .collect();
+    maturing.sort_by_key(|coinbase| coinbase.blocks_until_mature);
+    Ok(maturing)
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/balance",
+    tag = "balances",
+    responses(
+        (status = 200, body = BalanceResponse),
+    )
+)]
+pub(crate) async fn get_wallet_balance(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/balance", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/balance - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let balances: Result<GetBalancesResult, _> =
+        rpc_pool::call(&data, client, &wallet_name, "getbalances", vec![]).await?;
+    match balances {
+        Ok(balances) => {
+            let balance = BalanceBreakdown::from(balances);
+            debug!(
+                "Wallet '{}' balance: {} sat",
+                wallet_name, balance.trusted_sat
+            );
+            Ok(HttpResponse::Ok().json(BalanceResponse {
+                balance_sat: balance.trusted_sat,
+                unconfirmed_sat: balance.untrusted_pending_sat,
+                immature_sat: balance.immature_sat,
+                total_sat: balance.total_sat,
+            }))
+        }
+        Err(e) => {
+            error!("Failed to get balance for wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/balances",
+    tag = "balances",
+    responses(
+        (status = 200, body = WalletBalancesResponse),
+    )
+)]
+pub(crate) async fn get_wallet_balances(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/balances", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/balances - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let result = rpc_pool::blocking(move || {
+        client.get_balances().and_then(|balances| {
+            let maturing = maturing_coinbases(&client)?;
+            Ok((BalanceBreakdown::from(balances), maturing))
+        })
+    })
+    .await?;
+    match result {
+        Ok((balance, maturing)) => {
+            let buckets = data.db.reservations(&wallet_name)?;
+            Ok(HttpResponse::Ok().json(WalletBalancesResponse {
+                wallet: walletid.into_inner(),
+                balance,
+                maturing,
+                reservations: reservations::summary(buckets, balance.trusted_sat),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to get balances for wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/balances",
+    tag = "balances",
+    responses(
+        (status = 200, body = BalancesResponse),
+    )
+)]
+pub(crate) async fn get_balances(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /balances");
+    // In sandbox mode only the caller's own wallets are included
+    let prefix = data.wallet_name(&http_req, "");
+    let mut wallets: Vec<(String, String)> = data
+        .clients
+        .iter()
+        .filter_map(|entry| {
+            let shown = entry.key().strip_prefix(&prefix)?.to_string();
+            Some((entry.key().clone(), shown))
+        })
+        .collect();
+    wallets.sort();
+
+    let state = data.clone();
+    let wallets = rpc_pool::blocking(move || fetch_balances(&state, wallets)).await?;
+    let mut total = BalanceBreakdown::default();
+    for balance in wallets.iter().filter_map(|wallet| wallet.balance) {
+        total.add(balance);
+    }
+    Ok(HttpResponse::Ok().json(BalancesResponse { wallets, total }))
+}
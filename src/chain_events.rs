@@ -0,0 +1,298 @@
+use crate::events::Event;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::web;
+use bitcoincore_rpc::bitcoin::{BlockHash, Txid};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// With ZMQ, wallets are checked on notifications and otherwise only this often
+const ZMQ_FALLBACK_INTERVAL: Duration = Duration::from_secs(30);
+// After a long outage only the latest blocks are announced one by one
+const MAX_ANNOUNCED_BLOCKS: u64 = 10;
+// Blocks missed while the server was down are replayed up to this many
+const MAX_REPLAYED_BLOCKS: u64 = 1000;
+const BLOCK_CURSOR: &str = "chain_events";
+// How long a replayed batch of blocks waits for slow subscribers
+const REPLAY_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A wallet transaction output as announced; announced again once it confirms
+type Notified = (Txid, u32, String, bool);
+
+struct WalletCursor {
+    since: BlockHash,
+    notified: HashSet<Notified>,
+}
+
+fn announce_blocks(data: &AppState, node: &Client, from: u64, to: u64) -> Result<(), RpcError> {
+    for height in from.max(to.saturating_sub(MAX_ANNOUNCED_BLOCKS - 1))..=to {
+        let hash = node.get_block_hash(height)?;
+        data.events.publish(Event::BlockConnected {
+            height,
+            hash: hash.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn wallet_cursor_name(wallet: &str) -> String {
+    format!("chain_events:wallet:{wallet}")
+}
+
+// The saved block, if the node still knows it; it may have been reorged out since
+fn saved_cursor(data: &AppState, node: &Client, name: &str) -> Option<BlockHash> {
+    let hash = match data.db.chain_cursor(name) {
+        Ok(hash) => BlockHash::from_str(&hash?).ok()?,
+        Err(e) => {
+            error!("Failed to load chain cursor '{}': {}", name, e);
+            return None;
+        }
+    };
+    node.get_block_header_info(&hash).ok().map(|_| hash)
+}
+
+fn save_cursor(data: &AppState, name: &str, hash: &BlockHash) {
+    if let Err(e) = data.db.set_chain_cursor(name, &hash.to_string()) {
+        error!("Failed to save chain cursor '{}': {}", name, e);
+    }
+}
+
+// Height of the last block shared by the active chain and the chain `hash` is on
+fn fork_height(node: &Client, hash: &BlockHash) -> Result<u64, RpcError> {
+    let mut header = node.get_block_header_info(hash)?;
+    while header.confirmations < 0 {
+        let Some(previous) = header.previous_block_hash else {
+            break;
+        };
+        header = node.get_block_header_info(&previous)?;
+    }
+    Ok(header.height as u64)
+}
+
+// The saved block and the range of heights connected after it, with the node
+// client to fetch them with
+fn missed_blocks(data: &AppState) -> Option<(Client, BlockHash, u64, u64)> {
+    let node = match data.config.create_node_client() {
+        Ok(node) => node,
+        Err(e) => {
+            warn!("Cannot create node client to catch up on blocks: {}", e);
+            return None;
+        }
+    };
+    let last = saved_cursor(data, &node, BLOCK_CURSOR)?;
+    match fork_height(&node, &last).and_then(|fork| Ok((fork + 1, node.get_block_count()?))) {
+        Ok((from, tip)) => Some((node, last, from, tip)),
+        Err(e) => {
+            warn!("Cannot determine blocks missed since {}: {}", last, e);
+            None
+        }
+    }
+}
+
+// Publishes the blocks connected since the last processed one, so sinks see what
+// happened while the server was down. Returns the tip the replay ended at.
+async fn replay_missed_blocks(data: &web::Data<AppState>) -> Option<(u64, BlockHash)> {
+    let (mut node, last, from, tip) = rpc_pool::with_state(data, missed_blocks).await.ok()??;
+    let mut tip_hash = last;
+    if from <= tip {
+        let first = from.max(tip.saturating_sub(MAX_REPLAYED_BLOCKS - 1));
+        if first > from {
+            warn!(
+                "Skipping {} missed block(s), only the last {} are replayed",
+                first - from,
+                MAX_REPLAYED_BLOCKS
+            );
+        }
+        info!(
+            "Replaying blocks {}..={} missed while the server was down",
+            first, tip
+        );
+        // The bus only buffers so many events, so the blocks go out a batch at a time
+        // and each batch waits until the sinks have received the one before
+        let batch = data.events.capacity().max(1) as u64;
+        let mut start = first;
+        while start <= tip {
+            let end = tip.min(start + batch - 1);
+            let hashes;
+            (node, hashes) = rpc_pool::blocking(move || {
+                let hashes: Result<Vec<BlockHash>, RpcError> = (start..=end)
+                    .map(|height| node.get_block_hash(height))
+                    .collect();
+                (node, hashes)
+            })
+            .await
+            .ok()?;
+            let hashes = match hashes {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    warn!("Block replay stopped at height {}: {}", start, e);
+                    return None;
+                }
+            };
+            for (height, hash) in (start..).zip(&hashes) {
+                data.events.publish(Event::BlockConnected {
+                    height,
+                    hash: hash.to_string(),
+                });
+                tip_hash = *hash;
+            }
+            if !data.events.drained(REPLAY_DRAIN_TIMEOUT).await {
+                warn!(
+                    "Event subscribers fell behind the block replay at height {}, they may miss blocks",
+                    end
+                );
+            }
+            start = end + 1;
+        }
+        save_cursor(data, BLOCK_CURSOR, &tip_hash);
+    }
+    Some((tip.max(from - 1), tip_hash))
+}
+
+// Publishes wallet transactions seen since the cursor's block, including mempool ones
+fn announce_wallet_transactions(
+    data: &AppState,
+    wallet: &str,
+    client: &Client,
+    cursor: &mut WalletCursor,
+) -> Result<(), RpcError> {
+    let result = client.list_since_block(Some(&cursor.since), None, Some(true), None)?;
+    let mut seen = HashSet::new();
+    for tx in result.transactions {
+        let category = serde_json::to_value(tx.detail.category)
+            .ok()
+            .and_then(|category| category.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let key = (
+            tx.info.txid,
+            tx.detail.vout,
+            category.clone(),
+            tx.info.confirmations > 0,
+        );
+        if !cursor.notified.contains(&key) {
+            data.events.publish(Event::WalletTransaction {
+                wallet: wallet.to_string(),
+                txid: tx.info.txid.to_string(),
+                vout: tx.detail.vout,
+                category,
+                amount_sat: tx.detail.amount.to_sat(),
+                confirmations: tx.info.confirmations,
+            });
+        }
+        seen.insert(key);
+    }
+    // Anything no longer listed is buried below the new cursor and will not come back
+    cursor.notified = seen;
+    if cursor.since != result.lastblock {
+        cursor.since = result.lastblock;
+        save_cursor(data, &wallet_cursor_name(wallet), &cursor.since);
+    }
+    Ok(())
+}
+
+// Waits for a ZMQ block or transaction notification, then drains the backlog so a
+// burst of notifications costs one wallet check
+async fn next_node_notification(events: &mut Receiver<Event>) {
+    let _ = actix_rt::time::timeout(ZMQ_FALLBACK_INTERVAL, async {
+        loop {
+            match events.recv().await {
+                Ok(Event::BlockConnected { .. } | Event::TransactionSeen { .. })
+                | Err(RecvError::Closed) => break,
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+            }
+        }
+    })
+    .await;
+    while !matches!(
+        events.try_recv(),
+        Err(TryRecvError::Empty | TryRecvError::Closed)
+    ) {}
+}
+
+// Turns chain and wallet changes into events, so subscribers need not poll the REST API.
+// With ZMQ block notifications, blocks are announced by the listener and this only
+// follows wallets.
+// Missed blocks and wallet transactions are replayed first after a restart.
+pub(crate) async fn watch_chain(data: web::Data<AppState>) {
+    // Only block notifications replace polling the tip
+    let zmq = data
+        .config
+        .zmq
+        .as_ref()
+        .is_some_and(|zmq| zmq.rawblock.is_some());
+    let mut last_tip = replay_missed_blocks(&data).await;
+    // Subscribed after the replay, which waits for every subscriber to catch up
+    let mut notifications = data.events.subscribe();
+    let mut cursors: HashMap<String, WalletCursor> = HashMap::new();
+    loop {
+        if zmq {
+            next_node_notification(&mut notifications).await;
+        } else {
+            actix_rt::time::sleep(POLL_INTERVAL).await;
+        }
+        let node = match data.config.create_node_client() {
+            Ok(node) => node,
+            Err(e) => {
+                debug!("Cannot create node client for chain events: {}", e);
+                continue;
+            }
+        };
+        let (height, tip) = match node
+            .get_block_count()
+            .and_then(|height| Ok((height, node.get_block_hash(height)?)))
+        {
+            Ok(tip) => tip,
+            Err(e) => {
+                debug!("Cannot read chain tip for chain events: {}", e);
+                continue;
+            }
+        };
+        if let Some((last_height, _)) = last_tip.filter(|(_, last)| !zmq && *last != tip) {
+            // A tip at the same or a lower height means a reorg; only the new tip is announced
+            let from = if height > last_height {
+                last_height + 1
+            } else {
+                height
+            };
+            if let Err(e) = announce_blocks(&data, &node, from, height) {
+                warn!("Failed to announce new blocks: {}", e);
+            }
+        }
+        if last_tip.is_none_or(|(_, last)| last != tip) {
+            save_cursor(&data, BLOCK_CURSOR, &tip);
+        }
+        last_tip = Some((height, tip));
+
+        cursors.retain(|wallet, _| data.clients.contains_key(wallet));
+        let wallets: Vec<String> = data
+            .clients
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for wallet in wallets {
+            let Some(client) = data.clients.get(&wallet).map(|pool| pool.get()) else {
+                continue;
+            };
+            // Newly registered wallets continue where they were left before a restart,
+            // or are followed from the current tip on
+            let cursor = cursors
+                .entry(wallet.clone())
+                .or_insert_with(|| WalletCursor {
+                    since: saved_cursor(&data, &node, &wallet_cursor_name(&wallet)).unwrap_or(tip),
+                    notified: HashSet::new(),
+                });
+            if let Err(e) = announce_wallet_transactions(&data, &wallet, &client, cursor) {
+                warn!(
+                    "Failed to check new transactions of wallet '{}': {}",
+                    wallet, e
+                );
+            }
+        }
+    }
+}
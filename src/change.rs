@@ -0,0 +1,132 @@
+use crate::fees::FeeOptions;
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::hashes::hex::FromHex;
+use bitcoincore_rpc::bitcoin::{Address, Amount, Network, Transaction, Txid};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ChangeType {
+    Legacy,
+    P2shSegwit,
+    Bech32,
+    Bech32m,
+}
+
+// How /send should handle change; when any of it is set, the payment goes through
+// walletcreatefundedpsbt instead of sendtoaddress
+#[derive(Deserialize, Default, Clone, ToSchema)]
+pub(crate) struct ChangeOptions {
+    pub(crate) change_address: Option<String>,
+    pub(crate) change_type: Option<ChangeType>,
+    #[serde(default)]
+    pub(crate) report_change: bool,
+}
+
+impl ChangeOptions {
+    pub(crate) fn is_set(&self) -> bool {
+        self.change_address.is_some() || self.change_type.is_some() || self.report_change
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ChangeOutput {
+    vout: u32,
+    address: Option<String>,
+    amount_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SentPayment {
+    #[schema(value_type = String)]
+    pub(crate) txid: Txid,
+    // None when the coins matched the amount closely enough to skip change
+    pub(crate) change: Option<ChangeOutput>,
+}
+
+// Signs the PSBT from walletcreatefundedpsbt with the wallet's keys, returning the
+// transaction and its hex ready for sendrawtransaction
+pub(crate) fn sign(client: &Client, funded: &Value) -> Result<(Transaction, String), RpcError> {
+    let psbt = funded["psbt"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError("walletcreatefundedpsbt returned no PSBT".into()))?;
+
+    let processed: Value = client.call("walletprocesspsbt", &[json!(psbt), json!(true)])?;
+    let finalized: Value = client.call("finalizepsbt", &[processed["psbt"].clone()])?;
+    let hex = finalized["hex"].as_str().ok_or_else(|| {
+        RpcError::ReturnedError("the wallet could not sign every input of the payment".into())
+    })?;
+    let tx: Transaction = Vec::<u8>::from_hex(hex)
+        .map_err(|e| RpcError::ReturnedError(e.to_string()))
+        .and_then(|bytes| {
+            deserialize(&bytes).map_err(|e| RpcError::ReturnedError(e.to_string()))
+        })?;
+    Ok((tx, hex.to_string()))
+}
+
+pub(crate) fn sign_and_broadcast(
+    client: &Client,
+    funded: &Value,
+) -> Result<(Transaction, Txid), RpcError> {
+    let (tx, hex) = sign(client, funded)?;
+    let txid = client.send_raw_transaction(hex.as_str())?;
+    Ok((tx, txid))
+}
+
+// Funds, signs and broadcasts the payment as a PSBT, so the change output is chosen
+// by the caller and known afterwards
+pub(crate) fn send(
+    client: &Client,
+    network: Network,
+    to: &Address,
+    amount: Amount,
+    change_address: Option<&Address>,
+    options: &ChangeOptions,
+    fees: &FeeOptions,
+) -> Result<SentPayment, RpcError> {
+    let mut funding = Map::new();
+    if let Some(address) = change_address {
+        funding.insert("changeAddress".into(), json!(address.to_string()));
+    }
+    if let Some(change_type) = options.change_type {
+        funding.insert("change_type".into(), json!(change_type));
+    }
+    if let Some(fee_rate) = fees.fee_rate {
+        funding.insert("fee_rate".into(), json!(fee_rate));
+    }
+    if fees.subtract_fee_from_amount {
+        funding.insert("subtractFeeFromOutputs".into(), json!([0]));
+    }
+    if let Some(replaceable) = fees.replaceable {
+        funding.insert("replaceable".into(), json!(replaceable));
+    }
+    let funded: Value = client.call(
+        "walletcreatefundedpsbt",
+        &[
+            json!([]),
+            json!([{ to.to_string(): amount.to_btc() }]),
+            json!(0),
+            Value::Object(funding),
+        ],
+    )?;
+    let (tx, txid) = sign_and_broadcast(client, &funded)?;
+
+    // changepos is -1 when there is no change output
+    let change = funded["changepos"]
+        .as_i64()
+        .and_then(|pos| u32::try_from(pos).ok())
+        .and_then(|vout| {
+            let output = tx.output.get(vout as usize)?;
+            Some(ChangeOutput {
+                vout,
+                address: Address::from_script(&output.script_pubkey, network)
+                    .ok()
+                    .map(|address| address.to_string()),
+                amount_sat: output.value.to_sat(),
+            })
+        });
+    Ok(SentPayment { txid, change })
+}
@@ -0,0 +1,504 @@
+use crate::db::{unix_now, PaymentChannel};
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// Flat fee paid by the refund and commitment transactions, which all have the same shape
+const CLOSE_FEE_SAT: u64 = 1_000;
+const DUST_LIMIT_SAT: u64 = 546;
+const LOCKTIME_ENABLED_SEQUENCE: u32 = 0xffff_fffe;
+// About a year of blocks
+const MAX_TIMEOUT_BLOCKS: u32 = 52_560;
+// Locktimes from here on are unix times, not heights
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+const STATE_OPEN: &str = "open";
+const STATE_CLOSED_COOPERATIVE: &str = "closed_cooperative";
+const STATE_CLOSED_TIMEOUT: &str = "closed_timeout";
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct OpenChannelRequest {
+    payer_wallet: String,
+    payee_wallet: String,
+    capacity_sat: u64,
+    // How long the payer waits before it can reclaim the funds unilaterally
+    timeout_blocks: u32,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ChannelPaymentRequest {
+    amount_sat: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum CloseMode {
+    // The payee signs the latest commitment and broadcasts it
+    Cooperative,
+    // The payer broadcasts the time-locked refund once it is final
+    Timeout,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CloseChannelRequest {
+    mode: CloseMode,
+}
+
+fn btc(sat: u64) -> f64 {
+    Amount::from_sat(sat).to_btc()
+}
+
+// A fresh wallet key as a descriptor key expression with its origin, which lets the
+// wallet recognise and sign for it inside the multisig PSBTs
+fn fresh_key(client: &Client) -> Result<(String, String), RpcError> {
+    let address = client
+        .get_new_address(Some("payment-channel"), Some(AddressType::Bech32))?
+        .assume_checked()
+        .to_string();
+    let info: Value = client.call("getaddressinfo", &[json!(address)])?;
+    let pubkey = info["pubkey"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError(format!("no public key for {address}")))?;
+    let key = match (
+        info["hdmasterfingerprint"].as_str(),
+        info["hdkeypath"].as_str(),
+    ) {
+        (Some(fingerprint), Some(path)) => {
+            format!(
+                "[{}{}]{}",
+                fingerprint,
+                path.trim_start_matches('m'),
+                pubkey
+            )
+        }
+        _ => pubkey.to_string(),
+    };
+    Ok((address, key))
+}
+
+// 2-of-2 P2WSH between payer and payee, returned with its checksum and address
+fn funding_descriptor(
+    node: &Client,
+    payer_key: &str,
+    payee_key: &str,
+) -> Result<(String, String), RpcError> {
+    let descriptor = format!("wsh(multi(2,{payer_key},{payee_key}))");
+    let info: Value = node.call("getdescriptorinfo", &[json!(descriptor)])?;
+    let checksum = info["checksum"].as_str().unwrap_or_default();
+    let descriptor = format!("{descriptor}#{checksum}");
+    let addresses: Vec<String> = node.call("deriveaddresses", &[json!(descriptor)])?;
+    let address = addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| RpcError::ReturnedError("descriptor has no address".into()))?;
+    Ok((descriptor, address))
+}
+
+// Builds a PSBT spending the funding output and fills in the witness script and key
+// origins from the descriptor
+fn spend_funding(
+    node: &Client,
+    channel: &PaymentChannel,
+    outputs: &[(&str, u64)],
+    locktime: u32,
+) -> Result<String, RpcError> {
+    let inputs = json!([{
+        "txid": channel.funding_txid,
+        "vout": channel.funding_vout,
+        "sequence": LOCKTIME_ENABLED_SEQUENCE,
+    }]);
+    let outputs: Vec<Value> = outputs
+        .iter()
+        .map(|(address, sat)| json!({ *address: btc(*sat) }))
+        .collect();
+    let psbt: String = node.call("createpsbt", &[inputs, json!(outputs), json!(locktime)])?;
+    let updated: Value = node.call(
+        "descriptorprocesspsbt",
+        &[
+            json!(psbt),
+            json!([channel.descriptor]),
+            json!("ALL"),
+            json!(true),
+            json!(false),
+        ],
+    )?;
+    Ok(updated["psbt"].as_str().unwrap_or_default().to_string())
+}
+
+fn sign(client: &Client, psbt: &str) -> Result<String, RpcError> {
+    let signed: Value = client.call(
+        "walletprocesspsbt",
+        &[
+            json!(psbt),
+            json!(true),
+            json!("ALL"),
+            json!(true),
+            json!(false),
+        ],
+    )?;
+    Ok(signed["psbt"].as_str().unwrap_or_default().to_string())
+}
+
+fn finalize_and_broadcast(node: &Client, psbt: &str) -> Result<String, RpcError> {
+    let finalized: Value = node.call("finalizepsbt", &[json!(psbt)])?;
+    let hex = finalized["hex"]
+        .as_str()
+        .filter(|_| finalized["complete"] == json!(true))
+        .ok_or_else(|| RpcError::ReturnedError("transaction is missing a signature".into()))?;
+    node.call("sendrawtransaction", &[json!(hex)])
+}
+
+fn funding_vout(payer: &Client, txid: &str, address: &str) -> Result<u32, RpcError> {
+    let tx: Value = payer.call("gettransaction", &[json!(txid)])?;
+    tx["details"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|detail| detail["address"].as_str() == Some(address))
+        .and_then(|detail| detail["vout"].as_u64())
+        .map(|vout| vout as u32)
+        .ok_or_else(|| RpcError::ReturnedError("funding output not found".into()))
+}
+
+// Funds the 2-of-2 and has the payee sign the time-locked refund. A real channel gets
+// the refund signed before broadcasting the funding; the wallets need the funding
+// output in the mempool to sign, and the server runs both sides anyway.
+fn open(
+    node: &Client,
+    payer: &Client,
+    payee: &Client,
+    payer_wallet: &str,
+    payee_wallet: &str,
+    req: &OpenChannelRequest,
+) -> Result<PaymentChannel, RpcError> {
+    let (payer_address, payer_key) = fresh_key(payer)?;
+    let (payee_address, payee_key) = fresh_key(payee)?;
+    let (descriptor, funding_address) = funding_descriptor(node, &payer_key, &payee_key)?;
+
+    let funding_address_checked = funding_address
+        .parse::<Address<_>>()
+        .map_err(|e| RpcError::ReturnedError(e.to_string()))?
+        .assume_checked();
+    let funding_txid = payer
+        .send_to_address(
+            &funding_address_checked,
+            Amount::from_sat(req.capacity_sat),
+            Some("payment channel funding"),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?
+        .to_string();
+    let funding_vout = funding_vout(payer, &funding_txid, &funding_address)?;
+
+    let mut channel = PaymentChannel {
+        id: Uuid::new_v4().simple().to_string(),
+        payer_wallet: payer_wallet.to_string(),
+        payee_wallet: payee_wallet.to_string(),
+        payer_address,
+        payee_address,
+        descriptor,
+        funding_txid,
+        funding_vout,
+        capacity_sat: req.capacity_sat,
+        refund_locktime: refund_locktime(node.get_block_count()?, req.timeout_blocks)?,
+        refund_psbt: String::new(),
+        paid_sat: 0,
+        commitment_count: 0,
+        commitment_psbt: None,
+        state: STATE_OPEN.to_string(),
+        closing_txid: None,
+        created_at: unix_now(),
+    };
+    let refund = spend_funding(
+        node,
+        &channel,
+        &[(&channel.payer_address, channel.capacity_sat - CLOSE_FEE_SAT)],
+        channel.refund_locktime,
+    )?;
+    channel.refund_psbt = sign(payee, &refund)?;
+    Ok(channel)
+}
+
+fn refund_locktime(height: u64, timeout_blocks: u32) -> Result<u32, RpcError> {
+    u32::try_from(height)
+        .ok()
+        .and_then(|height| height.checked_add(timeout_blocks))
+        .filter(|locktime| *locktime < LOCKTIME_THRESHOLD)
+        .ok_or_else(|| RpcError::ReturnedError("refund locktime is out of range".into()))
+}
+
+fn load_open_channel(data: &AppState, id: &str) -> Result<PaymentChannel, ApiError> {
+    match data.db.channel(id) {
+        Ok(Some(channel)) if channel.state == STATE_OPEN => Ok(channel),
+        Ok(Some(channel)) => Err(ApiError::Conflict(format!("Channel is {}", channel.state))),
+        Ok(None) => Err(ApiError::NotFound("No such channel".into())),
+        Err(e) => {
+            error!("Failed to load channel {}: {}", id, e);
+            Err(e.into())
+        }
+    }
+}
+
+pub(crate) fn node_client(data: &AppState, http_req: &HttpRequest) -> Result<Client, ApiError> {
+    data.config.create_node_client().map_err(|e| {
+        error!("Failed to create node RPC client: {}", e);
+        ApiError::rpc(http_req, e)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/channel",
+    tag = "channel",
+    responses(
+        (status = 200, body = PaymentChannel),
+    )
+)]
+pub(crate) async fn open_channel(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<OpenChannelRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /channel - payer='{}', payee='{}', capacity_sat={}, timeout_blocks={}",
+        req.payer_wallet, req.payee_wallet, req.capacity_sat, req.timeout_blocks
+    );
+    if req.capacity_sat < CLOSE_FEE_SAT + DUST_LIMIT_SAT {
+        return Err(ApiError::InvalidRequest(format!(
+            "Capacity must be at least {} sat",
+            CLOSE_FEE_SAT + DUST_LIMIT_SAT
+        )));
+    }
+    if !(1..=MAX_TIMEOUT_BLOCKS).contains(&req.timeout_blocks) {
+        return Err(ApiError::InvalidRequest(format!(
+            "timeout_blocks must be between 1 and {MAX_TIMEOUT_BLOCKS}"
+        )));
+    }
+    let payer_wallet = data.wallet_name(&http_req, &req.payer_wallet);
+    let payee_wallet = data.wallet_name(&http_req, &req.payee_wallet);
+    // Look both wallets up before holding either client, caching one takes a write lock
+    for (wallet, shown) in [
+        (&payer_wallet, &req.payer_wallet),
+        (&payee_wallet, &req.payee_wallet),
+    ] {
+        if data.wallet_client(wallet).is_none() {
+            warn!("POST /channel - wallet '{}' not found", shown);
+            return Err(ApiError::WalletNotFound(shown.clone()));
+        }
+    }
+    let (Some(payer), Some(payee)) = (
+        data.clients.get(&payer_wallet).map(|pool| pool.get()),
+        data.clients.get(&payee_wallet).map(|pool| pool.get()),
+    ) else {
+        return Err(ApiError::WalletNotFound(req.payer_wallet.clone()));
+    };
+    let node = node_client(&data, &http_req)?;
+
+    let req = req.into_inner();
+    let opened =
+        rpc_pool::blocking(move || open(&node, &payer, &payee, &payer_wallet, &payee_wallet, &req))
+            .await?;
+    let channel = match opened {
+        Ok(channel) => channel,
+        Err(e) => {
+            error!("Failed to open payment channel: {}", e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    if let Err(e) = data.db.insert_channel(&channel) {
+        error!("Failed to save channel {}: {}", channel.id, e);
+        return Err(e.into());
+    }
+    info!(
+        "Opened payment channel {} funded by {}",
+        channel.id, channel.funding_txid
+    );
+    Ok(HttpResponse::Ok().json(channel))
+}
+
+#[utoipa::path(
+    get,
+    path = "/channel/{id}",
+    tag = "channel",
+    responses(
+        (status = 200, body = PaymentChannel),
+    )
+)]
+pub(crate) async fn get_channel(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /channel/{}", id);
+    match data.db.channel(&id) {
+        Ok(Some(channel)) => Ok(HttpResponse::Ok().json(channel)),
+        Ok(None) => Err(ApiError::NotFound("No such channel".into())),
+        Err(e) => {
+            error!("Failed to load channel {}: {}", id, e);
+            Err(e.into())
+        }
+    }
+}
+
+// Off-chain payment: the payer signs a new commitment paying the payee the running
+// total. Only the latest commitment matters, as each one pays the payee more.
+#[utoipa::path(
+    post,
+    path = "/channel/{id}/pay",
+    tag = "channel",
+    responses(
+        (status = 200, body = Object),
+    )
+)]
+pub(crate) async fn pay(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+    req: web::Json<ChannelPaymentRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /channel/{}/pay - amount_sat={}", id, req.amount_sat);
+    let channel = load_open_channel(&data, &id)?;
+    let exceeded = || ApiError::InvalidRequest("Payment exceeds the channel capacity".into());
+    let paid = channel
+        .paid_sat
+        .checked_add(req.amount_sat)
+        .ok_or_else(exceeded)?;
+    if paid < DUST_LIMIT_SAT {
+        return Err(ApiError::InvalidRequest(format!(
+            "The payee needs at least {} sat in total",
+            DUST_LIMIT_SAT
+        )));
+    }
+    let remaining = paid
+        .checked_add(CLOSE_FEE_SAT)
+        .and_then(|spent| channel.capacity_sat.checked_sub(spent))
+        .ok_or_else(exceeded)?;
+    let Some(payer) = data.wallet_client(&channel.payer_wallet) else {
+        return Err(ApiError::WalletNotFound(channel.payer_wallet.clone()));
+    };
+    let node = node_client(&data, &http_req)?;
+
+    let funded = channel.clone();
+    let signed = rpc_pool::blocking(move || {
+        // Change below the dust limit is left to the miners
+        let mut outputs = vec![(funded.payee_address.as_str(), paid)];
+        if remaining >= DUST_LIMIT_SAT {
+            outputs.push((funded.payer_address.as_str(), remaining));
+        }
+        spend_funding(&node, &funded, &outputs, 0).and_then(|psbt| sign(&payer, &psbt))
+    })
+    .await?;
+    let commitment = match signed {
+        Ok(commitment) => commitment,
+        Err(e) => {
+            error!(
+                "Failed to sign commitment for channel {}: {}",
+                channel.id, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let count = channel.commitment_count + 1;
+    if let Err(e) = data
+        .db
+        .update_commitment(&channel.id, paid, count, &commitment)
+    {
+        error!(
+            "Failed to save commitment for channel {}: {}",
+            channel.id, e
+        );
+        return Err(e.into());
+    }
+    info!(
+        "Channel {} commitment #{} pays {} sat",
+        channel.id, count, paid
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "channel_id": channel.id,
+        "commitment": count,
+        "paid_sat": paid,
+        "payer_balance_sat": remaining,
+    })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/channel/{id}/close",
+    tag = "channel",
+    responses(
+        (status = 200, body = Object),
+    )
+)]
+pub(crate) async fn close(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+    req: web::Json<CloseChannelRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /channel/{}/close", id);
+    let channel = load_open_channel(&data, &id)?;
+    let node = node_client(&data, &http_req)?;
+
+    let (signer_wallet, psbt, state) = match req.mode {
+        CloseMode::Cooperative => match &channel.commitment_psbt {
+            Some(commitment) => (&channel.payee_wallet, commitment, STATE_CLOSED_COOPERATIVE),
+            None => {
+                return Err(ApiError::InvalidRequest(
+                    "No payments made yet; there is nothing to settle, use the timeout close"
+                        .into(),
+                ))
+            }
+        },
+        CloseMode::Timeout => {
+            let height = rpc_pool::call_node::<u64>(&data, "getblockcount", vec![])
+                .await?
+                .map_err(|e| ApiError::rpc(&http_req, e))?;
+            // The refund's nLockTime must be below the height of the block including it
+            if height < channel.refund_locktime as u64 {
+                return Err(ApiError::Conflict(format!(
+                    "The refund is time-locked until block {}. Mine {} more block(s) first.",
+                    channel.refund_locktime,
+                    channel.refund_locktime as u64 - height
+                )));
+            }
+            (
+                &channel.payer_wallet,
+                &channel.refund_psbt,
+                STATE_CLOSED_TIMEOUT,
+            )
+        }
+    };
+    let Some(signer) = data.wallet_client(signer_wallet) else {
+        return Err(ApiError::WalletNotFound(signer_wallet.clone()));
+    };
+
+    let psbt = psbt.clone();
+    let closed = rpc_pool::blocking(move || {
+        sign(&signer, &psbt).and_then(|signed| finalize_and_broadcast(&node, &signed))
+    })
+    .await?;
+    let txid = match closed {
+        Ok(txid) => txid,
+        Err(e) => {
+            error!("Failed to close channel {}: {}", channel.id, e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    if let Err(e) = data.db.close_channel(&channel.id, state, &txid) {
+        error!("Failed to mark channel {} closed: {}", channel.id, e);
+    }
+    info!("Channel {} {} with {}", channel.id, state, txid);
+    Ok(HttpResponse::Ok()
+        .json(json!({ "channel_id": channel.id, "state": state, "closing_txid": txid })))
+}
@@ -0,0 +1,537 @@
+use crate::balances::{self, WalletBalance};
+use crate::db::{unix_now, Cohort, CohortStudent};
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::{faucet, get_wallet, rpc_pool, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+const MAX_NAME_LEN: usize = 32;
+const MAX_STUDENTS_PER_REQUEST: usize = 100;
+const DEFAULT_FAUCET_ALLOWANCE_SAT: u64 = 10_000_000;
+// Student wallets are named <cohort>-<student>, which must stay out of the users mode
+// (u-<user>-) and sandbox (sb-<session>-) namespaces
+const RESERVED_PREFIXES: [&str; 2] = ["u-", "sb-"];
+// Chapters are read from the activity log, newest entries first
+const PROGRESS_WINDOW: usize = 1000;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateCohortRequest {
+    name: String,
+    faucet_allowance_sat: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ProvisionRequest {
+    count: usize,
+    // Students are named <prefix>-1, <prefix>-2, ... continuing after existing ones
+    prefix: Option<String>,
+    // Starting balance from the faucet, counted against the allowance
+    fund_sat: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct StudentFaucetRequest {
+    amount_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ProvisionedStudent {
+    student: String,
+    wallet: String,
+    funding_txid: Option<String>,
+    // Set when the wallet is ready but could not be funded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ProvisionResponse {
+    cohort: String,
+    students: Vec<ProvisionedStudent>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StudentFaucetResponse {
+    student: String,
+    txid: String,
+    amount_sat: u64,
+    faucet_remaining_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StudentProgress {
+    student: String,
+    #[serde(flatten)]
+    balance: WalletBalance,
+    faucet_used_sat: u64,
+    faucet_remaining_sat: u64,
+    // Oldest first, each chapter once
+    chapters_completed: Vec<String>,
+    // Unix time of the latest entry in the wallet's activity feed
+    last_active_at: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CohortDashboard {
+    #[serde(flatten)]
+    cohort: Cohort,
+    students: Vec<StudentProgress>,
+    // How many students completed each chapter
+    chapters: BTreeMap<String, usize>,
+    total_balance_sat: u64,
+}
+
+// Cohort and student names end up in wallet names
+fn check_name(field: &str, name: &str) -> Result<(), ApiError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(ApiError::InvalidRequest(format!(
+            "{field} must be 1 to {MAX_NAME_LEN} letters, digits, '-' or '_'"
+        )));
+    }
+    Ok(())
+}
+
+// Amounts go into the database as i64, and none above the 21M BTC supply is real
+fn checked_sat(field: &str, amount_sat: u64) -> Result<i64, ApiError> {
+    let max = Amount::MAX_MONEY.to_sat();
+    if amount_sat == 0 || amount_sat > max {
+        return Err(ApiError::InvalidRequest(format!(
+            "{field} must be between 1 and {max} sat"
+        )));
+    }
+    i64::try_from(amount_sat)
+        .map_err(|_| ApiError::InvalidRequest(format!("{field} is out of range")))
+}
+
+fn check_cohort_name(name: &str) -> Result<(), ApiError> {
+    check_name("name", name)?;
+    let wallet_prefix = student_wallet(name, "");
+    if RESERVED_PREFIXES
+        .iter()
+        .any(|reserved| wallet_prefix.starts_with(reserved))
+    {
+        return Err(ApiError::InvalidRequest(format!(
+            "Cohort names cannot start a wallet name with {}",
+            RESERVED_PREFIXES.join(" or ")
+        )));
+    }
+    Ok(())
+}
+
+// Student wallets belong to the instructor's server, not to a visitor's session or a user
+fn ensure_available(data: &AppState) -> Result<(), ApiError> {
+    if data.sandbox.is_some() {
+        return Err(ApiError::NotFound(
+            "Cohorts are not available in sandbox mode".into(),
+        ));
+    }
+    if data.config.auth.is_some() {
+        return Err(ApiError::NotFound(
+            "Cohorts are not available in users mode".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn find_cohort(data: &AppState, name: &str) -> Result<Cohort, ApiError> {
+    data.db
+        .cohort(name)?
+        .ok_or_else(|| ApiError::NotFound(format!("Cohort '{}' not found", name)))
+}
+
+fn student_wallet(cohort: &str, student: &str) -> String {
+    format!("{cohort}-{student}")
+}
+
+fn fund(data: &AppState, client: &Client, amount_sat: u64) -> Result<String, RpcError> {
+    let address = client
+        .get_new_address(Some("faucet"), Some(AddressType::Bech32))?
+        .require_network(data.config.network)
+        .map_err(|e| RpcError::ReturnedError(e.to_string()))?;
+    faucet::fund(&data.config, &address, Amount::from_sat(amount_sat))
+        .map(|funding| funding.txid.to_string())
+}
+
+// Creates (or loads) the student's wallet and hands it its starting balance. The RPCs
+// run on the blocking thread pool, the faucet may have to mine first.
+async fn provision_student(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    cohort: &Cohort,
+    student: String,
+    fund_sat: Option<i64>,
+) -> Result<ProvisionedStudent, ApiError> {
+    let wallet = student_wallet(&cohort.name, &student);
+    let created = {
+        let data = data.clone();
+        let wallet = wallet.clone();
+        rpc_pool::blocking(move || -> Result<Client, RpcError> {
+            let client = data.config.create_client(&wallet)?;
+            get_wallet(&client, &wallet, false)?;
+            Ok(client)
+        })
+        .await?
+    };
+    let client = match created {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create wallet '{}' for a student: {}", wallet, e);
+            return Err(ApiError::rpc(http_req, e));
+        }
+    };
+    data.events.publish(Event::WalletReady {
+        wallet: wallet.clone(),
+    });
+    let record = CohortStudent {
+        student: student.clone(),
+        wallet: wallet.clone(),
+        faucet_used_sat: 0,
+        created_at: unix_now(),
+    };
+    if !data.db.insert_cohort_student(&cohort.name, &record)? {
+        return Err(ApiError::Conflict(format!(
+            "Cohort '{}' already has a student '{}'",
+            cohort.name, student
+        )));
+    }
+
+    let mut provisioned = ProvisionedStudent {
+        student,
+        wallet: wallet.clone(),
+        funding_txid: None,
+        error: None,
+    };
+    let client = match fund_sat {
+        Some(amount) => {
+            data.db.charge_faucet_allowance(
+                &cohort.name,
+                &provisioned.student,
+                amount,
+                cohort.faucet_allowance_sat,
+            )?;
+            let funded = {
+                let data = data.clone();
+                rpc_pool::blocking(move || {
+                    let funded = fund(&data, &client, amount as u64);
+                    (client, funded)
+                })
+                .await?
+            };
+            match funded {
+                (client, Ok(txid)) => {
+                    provisioned.funding_txid = Some(txid);
+                    client
+                }
+                (client, Err(e)) => {
+                    warn!("Failed to fund student wallet '{}': {}", wallet, e);
+                    data.db.charge_faucet_allowance(
+                        &cohort.name,
+                        &provisioned.student,
+                        -amount,
+                        cohort.faucet_allowance_sat,
+                    )?;
+                    provisioned.error = Some(format!("funding failed: {e}"));
+                    client
+                }
+            }
+        }
+        None => client,
+    };
+    data.register_wallet(wallet, client);
+    Ok(provisioned)
+}
+
+fn progress(
+    data: &AppState,
+    cohort: &Cohort,
+    student: CohortStudent,
+    balance: WalletBalance,
+) -> StudentProgress {
+    let activity = data
+        .db
+        .activity(&student.wallet, None, PROGRESS_WINDOW)
+        .unwrap_or_else(|e| {
+            error!(
+                "Failed to read the activity of wallet '{}': {}",
+                student.wallet, e
+            );
+            Vec::new()
+        });
+    let mut chapters_completed: Vec<String> = Vec::new();
+    for record in activity.iter().rev() {
+        if record.kind != "chapter_completed" {
+            continue;
+        }
+        if let Some(chapter) = record.details["chapter"].as_str() {
+            if !chapters_completed.iter().any(|known| known == chapter) {
+                chapters_completed.push(chapter.to_string());
+            }
+        }
+    }
+    StudentProgress {
+        student: student.student,
+        balance,
+        faucet_used_sat: student.faucet_used_sat,
+        faucet_remaining_sat: cohort
+            .faucet_allowance_sat
+            .saturating_sub(student.faucet_used_sat),
+        chapters_completed,
+        last_active_at: activity.first().map(|record| record.time),
+    }
+}
+
+#[utoipa::path(post, path = "/cohorts", tag = "cohort", responses((status = 201, body = Cohort)))]
+pub(crate) async fn create_cohort(
+    data: web::Data<AppState>,
+    req: web::Json<CreateCohortRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /cohorts - name='{}', faucet_allowance_sat={:?}",
+        req.name, req.faucet_allowance_sat
+    );
+    ensure_available(&data)?;
+    check_cohort_name(&req.name)?;
+    let faucet_allowance_sat = req
+        .faucet_allowance_sat
+        .unwrap_or(DEFAULT_FAUCET_ALLOWANCE_SAT);
+    // 0 is a cohort without faucet
+    if faucet_allowance_sat > Amount::MAX_MONEY.to_sat() {
+        return Err(ApiError::InvalidRequest(format!(
+            "faucet_allowance_sat must be at most {} sat",
+            Amount::MAX_MONEY.to_sat()
+        )));
+    }
+    let cohort = Cohort {
+        name: req.name.clone(),
+        faucet_allowance_sat,
+        created_at: unix_now(),
+    };
+    if !data.db.insert_cohort(&cohort)? {
+        return Err(ApiError::Conflict(format!(
+            "Cohort '{}' already exists",
+            req.name
+        )));
+    }
+    info!("Created cohort '{}'", cohort.name);
+    Ok(HttpResponse::Created().json(cohort))
+}
+
+#[utoipa::path(get, path = "/cohorts", tag = "cohort", responses((status = 200, body = [Cohort])))]
+pub(crate) async fn list_cohorts(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("GET /cohorts");
+    ensure_available(&data)?;
+    Ok(HttpResponse::Ok().json(data.db.cohorts()?))
+}
+
+// Creates `count` student wallets, funds them and starts tracking their progress.
+// Students already provisioned keep their wallets; a failed funding is reported per
+// student without stopping the others.
+#[utoipa::path(
+    post,
+    path = "/cohorts/{name}/students",
+    tag = "cohort",
+    responses(
+        (status = 200, body = ProvisionResponse),
+    )
+)]
+pub(crate) async fn provision_students(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    name: web::Path<String>,
+    req: web::Json<ProvisionRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /cohorts/{}/students - count={}, prefix={:?}, fund_sat={:?}",
+        name, req.count, req.prefix, req.fund_sat
+    );
+    ensure_available(&data)?;
+    let cohort = find_cohort(&data, &name)?;
+    check_cohort_name(&cohort.name)?;
+    if !(1..=MAX_STUDENTS_PER_REQUEST).contains(&req.count) {
+        return Err(ApiError::InvalidRequest(format!(
+            "count must be between 1 and {}",
+            MAX_STUDENTS_PER_REQUEST
+        )));
+    }
+    let prefix = req.prefix.as_deref().unwrap_or("student");
+    // Leaves room for the number
+    check_name("prefix", prefix)?;
+    if prefix.len() > MAX_NAME_LEN - 4 {
+        return Err(ApiError::InvalidRequest(format!(
+            "prefix must be at most {} characters",
+            MAX_NAME_LEN - 4
+        )));
+    }
+    let fund_sat = req.fund_sat.filter(|amount| *amount > 0);
+    if let Some(amount) = fund_sat {
+        if amount > cohort.faucet_allowance_sat {
+            return Err(ApiError::InvalidRequest(format!(
+                "fund_sat exceeds the cohort's faucet allowance of {} sat",
+                cohort.faucet_allowance_sat
+            )));
+        }
+    }
+    let fund_sat = fund_sat
+        .map(|amount| checked_sat("fund_sat", amount))
+        .transpose()?;
+
+    let existing = data.db.cohort_students(&cohort.name)?;
+    let mut next = 1;
+    let mut students = Vec::with_capacity(req.count);
+    while students.len() < req.count {
+        let student = format!("{prefix}-{next}");
+        next += 1;
+        if existing.iter().any(|known| known.student == student) {
+            continue;
+        }
+        students.push(provision_student(&data, &http_req, &cohort, student, fund_sat).await?);
+    }
+    info!(
+        "Provisioned {} student(s) in cohort '{}'",
+        students.len(),
+        cohort.name
+    );
+    Ok(HttpResponse::Ok().json(ProvisionResponse {
+        cohort: cohort.name,
+        students,
+    }))
+}
+
+// Progress and balances of every student, for the instructor
+#[utoipa::path(
+    get,
+    path = "/cohorts/{name}",
+    tag = "cohort",
+    responses(
+        (status = 200, body = CohortDashboard),
+    )
+)]
+pub(crate) async fn cohort_dashboard(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /cohorts/{}", name);
+    ensure_available(&data)?;
+    let cohort = find_cohort(&data, &name)?;
+    let students = data.db.cohort_students(&cohort.name)?;
+    let wallets = students
+        .iter()
+        .map(|student| (student.wallet.clone(), student.wallet.clone()))
+        .collect();
+    let balances =
+        rpc_pool::with_state(&data, move |data| balances::fetch_balances(data, wallets)).await?;
+
+    let students: Vec<StudentProgress> = students
+        .into_iter()
+        .zip(balances)
+        .map(|(student, balance)| progress(&data, &cohort, student, balance))
+        .collect();
+    let mut chapters = BTreeMap::new();
+    for chapter in students
+        .iter()
+        .flat_map(|student| &student.chapters_completed)
+    {
+        *chapters.entry(chapter.clone()).or_default() += 1;
+    }
+    let total_balance_sat = students
+        .iter()
+        .map(|student| student.balance.total_sat())
+        .sum();
+    Ok(HttpResponse::Ok().json(CohortDashboard {
+        cohort,
+        students,
+        chapters,
+        total_balance_sat,
+    }))
+}
+
+// Sends coins from the faucet to a student's wallet, within the cohort's allowance
+#[utoipa::path(
+    post,
+    path = "/cohorts/{name}/students/{student}/faucet",
+    tag = "cohort",
+    responses(
+        (status = 200, body = StudentFaucetResponse),
+    )
+)]
+pub(crate) async fn student_faucet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+    req: web::Json<StudentFaucetRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (name, student) = path.into_inner();
+    info!(
+        "POST /cohorts/{}/students/{}/faucet - amount_sat={}",
+        name, student, req.amount_sat
+    );
+    ensure_available(&data)?;
+    let cohort = find_cohort(&data, &name)?;
+    let Some(record) = data
+        .db
+        .cohort_students(&cohort.name)?
+        .into_iter()
+        .find(|known| known.student == student)
+    else {
+        return Err(ApiError::NotFound(format!(
+            "Cohort '{}' has no student '{}'",
+            name, student
+        )));
+    };
+    let amount_sat = checked_sat("amount_sat", req.amount_sat)?;
+    let Some(client) = data.wallet_client(&record.wallet) else {
+        warn!(
+            "POST /cohorts/faucet - wallet '{}' is not loaded",
+            record.wallet
+        );
+        return Err(ApiError::WalletNotFound(record.wallet));
+    };
+    if !data.db.charge_faucet_allowance(
+        &cohort.name,
+        &student,
+        amount_sat,
+        cohort.faucet_allowance_sat,
+    )? {
+        return Err(ApiError::TooManyRequests(format!(
+            "Faucet allowance of {} sat for student '{}' exceeded",
+            cohort.faucet_allowance_sat, student
+        )));
+    }
+    let funded = {
+        let data = data.clone();
+        let amount_sat = req.amount_sat;
+        rpc_pool::blocking(move || fund(&data, &client, amount_sat)).await?
+    };
+    match funded {
+        Ok(txid) => Ok(HttpResponse::Ok().json(StudentFaucetResponse {
+            student,
+            txid,
+            amount_sat: req.amount_sat,
+            faucet_remaining_sat: cohort
+                .faucet_allowance_sat
+                .saturating_sub(record.faucet_used_sat.saturating_add(req.amount_sat)),
+        })),
+        Err(e) => {
+            data.db.charge_faucet_allowance(
+                &cohort.name,
+                &student,
+                -amount_sat,
+                cohort.faucet_allowance_sat,
+            )?;
+            error!("Faucet failed to fund wallet '{}': {}", record.wallet, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
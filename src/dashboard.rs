@@ -0,0 +1,204 @@
+use crate::balances::BalanceBreakdown;
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::json::{GetBalancesResult, ListTransactionResult};
+use bitcoincore_rpc::Error as RpcError;
+use futures_util::future::join_all;
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_TRANSACTIONS: usize = 10;
+const MAX_TRANSACTIONS: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DashboardQuery {
+    // Recent transactions per wallet, 10 by default
+    transactions: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DashboardTransaction {
+    txid: String,
+    category: String,
+    // Negative for sends
+    amount_sat: i64,
+    address: Option<String>,
+    confirmations: i32,
+    time: u64,
+}
+
+impl From<ListTransactionResult> for DashboardTransaction {
+    fn from(tx: ListTransactionResult) -> Self {
+        let category = serde_json::to_value(tx.detail.category).unwrap_or_default();
+        DashboardTransaction {
+            txid: tx.info.txid.to_string(),
+            category: category.as_str().unwrap_or_default().to_string(),
+            amount_sat: tx.detail.amount.to_sat(),
+            address: tx
+                .detail
+                .address
+                .map(|address| address.assume_checked().to_string()),
+            confirmations: tx.info.confirmations,
+            time: tx.info.time,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct WalletDashboard {
+    wallet: String,
+    // Left out when the wallet could not be read, see `error`
+    #[serde(flatten)]
+    balance: Option<BalanceBreakdown>,
+    // Last block the wallet has processed; behind `height` while it catches up
+    synced_height: Option<u64>,
+    tx_count: Option<u64>,
+    // Newest first
+    recent_transactions: Vec<DashboardTransaction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl WalletDashboard {
+    fn failed(wallet: String, error: String) -> Self {
+        WalletDashboard {
+            wallet,
+            balance: None,
+            synced_height: None,
+            tx_count: None,
+            recent_transactions: Vec::new(),
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct DashboardResponse {
+    // Node block height
+    height: u64,
+    wallets: Vec<WalletDashboard>,
+    total: BalanceBreakdown,
+}
+
+fn parse<T: DeserializeOwned>(result: Option<Result<Value, RpcError>>) -> Result<T, String> {
+    let value = result
+        .ok_or("missing response")?
+        .map_err(|e| e.to_string())?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+// One batched round trip per wallet instead of a call per figure
+async fn wallet_dashboard(
+    data: &AppState,
+    wallet: String,
+    shown: String,
+    transactions: usize,
+) -> WalletDashboard {
+    let Some(client) = data.clients.get(&wallet).map(|pool| pool.get()) else {
+        return WalletDashboard::failed(shown, "wallet was unloaded".to_string());
+    };
+    let calls = vec![
+        ("getbalances", vec![]),
+        ("getwalletinfo", vec![]),
+        (
+            "listtransactions",
+            vec![json!("*"), json!(transactions), json!(0), json!(true)],
+        ),
+    ];
+    let results = match rpc_pool::batch(data, client, &wallet, calls).await {
+        Ok(Ok(results)) => results,
+        Ok(Err(e)) => {
+            warn!("Failed to read the dashboard of wallet '{}': {}", wallet, e);
+            return WalletDashboard::failed(shown, e.to_string());
+        }
+        Err(e) => return WalletDashboard::failed(shown, e.to_string()),
+    };
+    let mut results = results.into_iter();
+    let balances = parse::<GetBalancesResult>(results.next());
+    // Read untyped, see node::fetch_policy
+    let info = parse::<Value>(results.next());
+    let history = parse::<Vec<ListTransactionResult>>(results.next());
+    let (balances, info, mut history) = match (balances, info, history) {
+        (Ok(balances), Ok(info), Ok(history)) => (balances, info, history),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            warn!("Failed to read the dashboard of wallet '{}': {}", wallet, e);
+            return WalletDashboard::failed(shown, e);
+        }
+    };
+    // listtransactions lists the oldest first
+    history.reverse();
+    WalletDashboard {
+        wallet: shown,
+        balance: Some(balances.into()),
+        synced_height: info["lastprocessedblock"]["height"].as_u64(),
+        tx_count: info["txcount"].as_u64(),
+        recent_transactions: history
+            .into_iter()
+            .map(DashboardTransaction::from)
+            .collect(),
+        error: None,
+    }
+}
+
+// Everything the overview screen shows, for all of the caller's wallets at once
+#[utoipa::path(
+    get,
+    path = "/dashboard",
+    tag = "balances",
+    params(DashboardQuery),
+    responses(
+        (status = 200, body = DashboardResponse),
+    )
+)]
+pub(crate) async fn get_dashboard(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /dashboard");
+    let transactions = query.transactions.unwrap_or(DEFAULT_TRANSACTIONS);
+    if transactions > MAX_TRANSACTIONS {
+        return Err(ApiError::InvalidRequest(format!(
+            "transactions must be at most {MAX_TRANSACTIONS}"
+        )));
+    }
+    // In sandbox and users mode only the caller's own wallets are included
+    let prefix = data.wallet_name(&http_req, "");
+    let mut wallets: Vec<(String, String)> = data
+        .clients
+        .iter()
+        .filter_map(|entry| {
+            let shown = entry.key().strip_prefix(&prefix)?.to_string();
+            Some((entry.key().clone(), shown))
+        })
+        .collect();
+    wallets.sort();
+
+    // The node and the wallets are asked in parallel
+    let height = rpc_pool::call_node::<u64>(&data, "getblockcount", vec![]);
+    let wallets = join_all(
+        wallets
+            .into_iter()
+            .map(|(wallet, shown)| wallet_dashboard(&data, wallet, shown, transactions)),
+    );
+    let (height, wallets) = futures_util::join!(height, wallets);
+    let height = height?.map_err(|e| {
+        error!("Failed to get the block height for the dashboard: {}", e);
+        ApiError::rpc(&http_req, e)
+    })?;
+    let mut total = BalanceBreakdown::default();
+    for balance in wallets.iter().filter_map(|wallet| wallet.balance) {
+        total.add(balance);
+    }
+    Ok(HttpResponse::Ok().json(DashboardResponse {
+        height,
+        wallets,
+        total,
+    }))
+}
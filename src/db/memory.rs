@@ -0,0 +1,817 @@
+use super::{
+    unix_now, ActivityRecord, BlockSighting, Cohort, CohortStudent, ConfirmationTier, Cursor,
+    Invoice, MinerStats, MultisigWallet, OutboxEntry, PaymentChannel, Reservation, Storage,
+    StorageResult, TxWatcher, User, WatchedAddress, WatchedOutput,
+};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+struct Confirmation {
+    block_hash: String,
+    block_height: u64,
+}
+
+struct Replacement {
+    original: String,
+    replacement: String,
+    detected_at: i64,
+}
+
+struct MinedBlock {
+    wallet: String,
+    source: String,
+    mined_at: i64,
+}
+
+// One field per sqlite table, keyed by its primary key
+#[derive(Default)]
+struct Tables {
+    address_labels: HashMap<(String, String), String>,
+    confirmations: HashMap<(String, String), Confirmation>,
+    // In the order they were added
+    watched_addresses: Vec<WatchedAddress>,
+    watched_outputs: BTreeMap<(String, u32), (String, WatchedOutput)>,
+    channels: HashMap<String, PaymentChannel>,
+    activity: Vec<(String, ActivityRecord)>,
+    replacements: Vec<Replacement>,
+    chain_cursors: HashMap<String, String>,
+    registered_wallets: HashMap<String, i64>,
+    outbox: Vec<OutboxEntry>,
+    miners: HashMap<String, (u32, i64)>,
+    mined_blocks: HashMap<String, MinedBlock>,
+    multisig_wallets: HashMap<String, MultisigWallet>,
+    invoices: HashMap<String, Invoice>,
+    confirmation_policies: HashMap<String, Vec<ConfirmationTier>>,
+    block_sightings: HashMap<(String, String), BlockSighting>,
+    reservations: BTreeMap<(String, String), Reservation>,
+    // In the order they were added
+    cohorts: Vec<Cohort>,
+    cohort_students: Vec<(String, CohortStudent)>,
+    tx_watchers: Vec<TxWatcher>,
+    users: HashMap<String, User>,
+}
+
+// Keeps the app data in the process, so every start is a clean slate. Behaves like
+// SqliteStorage, down to the ordering of the lists; the tests in db/tests.rs run
+// against both.
+#[derive(Default)]
+pub(crate) struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStorage {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Like ORDER BY created_at, rowid: the sort is stable, so ties stay in insertion order
+fn oldest_first<T>(mut rows: Vec<T>, created_at: impl Fn(&T) -> i64) -> Vec<T> {
+    rows.sort_by_key(|row| created_at(row));
+    rows
+}
+
+fn duplicate<T>(table: &str, key: &str) -> StorageResult<T> {
+    Err(format!("{table} already has a row for '{key}'").into())
+}
+
+impl Storage for MemoryStorage {
+    fn set_label(&self, wallet: &str, address: &str, label: &str) -> StorageResult<()> {
+        self.tables()
+            .address_labels
+            .insert((wallet.to_string(), address.to_string()), label.to_string());
+        Ok(())
+    }
+
+    fn record_confirmation(
+        &self,
+        wallet: &str,
+        txid: &str,
+        block_hash: &str,
+        block_height: u64,
+    ) -> StorageResult<()> {
+        self.tables().confirmations.insert(
+            (wallet.to_string(), txid.to_string()),
+            Confirmation {
+                block_hash: block_hash.to_string(),
+                block_height,
+            },
+        );
+        Ok(())
+    }
+
+    fn confirmations(&self, wallet: &str) -> StorageResult<Vec<(String, String, u64)>> {
+        let mut confirmations: Vec<(String, String, u64)> = self
+            .tables()
+            .confirmations
+            .iter()
+            .filter(|((owner, _), _)| owner == wallet)
+            .map(|((_, txid), confirmation)| {
+                (
+                    txid.clone(),
+                    confirmation.block_hash.clone(),
+                    confirmation.block_height,
+                )
+            })
+            .collect();
+        confirmations.sort_by_key(|(_, _, height)| Reverse(*height));
+        Ok(confirmations)
+    }
+
+    fn watch_address(&self, watched: &WatchedAddress) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        if tables
+            .watched_addresses
+            .iter()
+            .any(|known| known.address == watched.address)
+        {
+            return Ok(false);
+        }
+        tables.watched_addresses.push(watched.clone());
+        Ok(true)
+    }
+
+    fn watched_addresses(&self) -> StorageResult<Vec<WatchedAddress>> {
+        Ok(oldest_first(
+            self.tables().watched_addresses.clone(),
+            |watched| watched.created_at,
+        ))
+    }
+
+    fn record_watched_output(&self, address: &str, output: &WatchedOutput) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        let key = (output.txid.clone(), output.vout);
+        if tables.watched_outputs.contains_key(&key) {
+            return Ok(false);
+        }
+        tables
+            .watched_outputs
+            .insert(key, (address.to_string(), output.clone()));
+        Ok(true)
+    }
+
+    fn watched_outputs(&self, address: &str) -> StorageResult<Vec<WatchedOutput>> {
+        // The map is already in (txid, vout) order, and the sort is stable
+        let mut outputs: Vec<WatchedOutput> = self
+            .tables()
+            .watched_outputs
+            .values()
+            .filter(|(owner, _)| owner == address)
+            .map(|(_, output)| output.clone())
+            .collect();
+        outputs.sort_by_key(|output| Reverse(output.block_height));
+        Ok(outputs)
+    }
+
+    fn watched_outputs_of(&self, txids: &[String]) -> StorageResult<Vec<(String, WatchedOutput)>> {
+        Ok(self
+            .tables()
+            .watched_outputs
+            .values()
+            .filter(|(_, output)| txids.contains(&output.txid))
+            .cloned()
+            .collect())
+    }
+
+    fn record_activity(&self, wallet: &str, kind: &str, details: &Value) -> StorageResult<()> {
+        let mut tables = self.tables();
+        let id = tables.activity.len() as i64 + 1;
+        tables.activity.push((
+            wallet.to_string(),
+            ActivityRecord {
+                id,
+                kind: kind.to_string(),
+                time: unix_now(),
+                details: details.clone(),
+            },
+        ));
+        Ok(())
+    }
+
+    fn activity(
+        &self,
+        wallet: &str,
+        before: Option<(i64, i64)>,
+        limit: usize,
+    ) -> StorageResult<Vec<ActivityRecord>> {
+        let mut records: Vec<ActivityRecord> = self
+            .tables()
+            .activity
+            .iter()
+            .filter(|(owner, record)| {
+                owner == wallet && before.is_none_or(|before| (record.time, record.id) < before)
+            })
+            .map(|(_, record)| record.clone())
+            .collect();
+        records.sort_by_key(|record| Reverse((record.time, record.id)));
+        records.truncate(limit);
+        Ok(records)
+    }
+
+    fn record_replacement(
+        &self,
+        _wallet: &str,
+        original: &str,
+        replacement: &str,
+    ) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        if tables
+            .replacements
+            .iter()
+            .any(|row| row.original == original && row.replacement == replacement)
+        {
+            return Ok(false);
+        }
+        tables.replacements.push(Replacement {
+            original: original.to_string(),
+            replacement: replacement.to_string(),
+            detected_at: unix_now(),
+        });
+        Ok(true)
+    }
+
+    fn replaced_by(&self, txid: &str) -> StorageResult<Vec<(String, i64)>> {
+        let mut replacements: Vec<(String, i64)> = self
+            .tables()
+            .replacements
+            .iter()
+            .filter(|row| row.original == txid)
+            .map(|row| (row.replacement.clone(), row.detected_at))
+            .collect();
+        replacements.sort_by(|(a_txid, a_at), (b_txid, b_at)| (a_at, a_txid).cmp(&(b_at, b_txid)));
+        Ok(replacements)
+    }
+
+    fn replaces(&self, txid: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .tables()
+            .replacements
+            .iter()
+            .find(|row| row.replacement == txid)
+            .map(|row| row.original.clone()))
+    }
+
+    fn register_wallet(&self, name: &str) -> StorageResult<()> {
+        self.tables()
+            .registered_wallets
+            .entry(name.to_string())
+            .or_insert_with(unix_now);
+        Ok(())
+    }
+
+    fn unregister_wallet(&self, name: &str) -> StorageResult<()> {
+        self.tables().registered_wallets.remove(name);
+        Ok(())
+    }
+
+    fn clear_chain_state(&self) -> StorageResult<()> {
+        let mut tables = self.tables();
+        let watched_addresses = std::mem::take(&mut tables.watched_addresses);
+        let users = std::mem::take(&mut tables.users);
+        *tables = Tables {
+            watched_addresses,
+            users,
+            ..Tables::default()
+        };
+        for watched in &mut tables.watched_addresses {
+            watched.scanned_height = 0;
+        }
+        Ok(())
+    }
+
+    fn registered_wallets(&self) -> StorageResult<Vec<String>> {
+        let mut wallets: Vec<(i64, String)> = self
+            .tables()
+            .registered_wallets
+            .iter()
+            .map(|(name, registered_at)| (*registered_at, name.clone()))
+            .collect();
+        wallets.sort();
+        Ok(wallets.into_iter().map(|(_, name)| name).collect())
+    }
+
+    fn chain_cursor(&self, name: &str) -> StorageResult<Option<String>> {
+        Ok(self.tables().chain_cursors.get(name).cloned())
+    }
+
+    fn set_chain_cursor(&self, name: &str, block_hash: &str) -> StorageResult<()> {
+        self.tables()
+            .chain_cursors
+            .insert(name.to_string(), block_hash.to_string());
+        Ok(())
+    }
+
+    fn insert_channel(&self, channel: &PaymentChannel) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if tables.channels.contains_key(&channel.id) {
+            return duplicate("payment_channels", &channel.id);
+        }
+        tables.channels.insert(channel.id.clone(), channel.clone());
+        Ok(())
+    }
+
+    fn channel(&self, id: &str) -> StorageResult<Option<PaymentChannel>> {
+        Ok(self.tables().channels.get(id).cloned())
+    }
+
+    fn update_commitment(
+        &self,
+        id: &str,
+        paid_sat: u64,
+        commitment_count: u32,
+        commitment_psbt: &str,
+    ) -> StorageResult<()> {
+        if let Some(channel) = self.tables().channels.get_mut(id) {
+            channel.paid_sat = paid_sat;
+            channel.commitment_count = commitment_count;
+            channel.commitment_psbt = Some(commitment_psbt.to_string());
+        }
+        Ok(())
+    }
+
+    fn close_channel(&self, id: &str, state: &str, closing_txid: &str) -> StorageResult<()> {
+        if let Some(channel) = self.tables().channels.get_mut(id) {
+            channel.state = state.to_string();
+            channel.closing_txid = Some(closing_txid.to_string());
+        }
+        Ok(())
+    }
+
+    fn set_miner(&self, wallet: &str, weight: u32) -> StorageResult<()> {
+        self.tables()
+            .miners
+            .entry(wallet.to_string())
+            .and_modify(|(current, _)| *current = weight)
+            .or_insert_with(|| (weight, unix_now()));
+        Ok(())
+    }
+
+    fn remove_miner(&self, wallet: &str) -> StorageResult<bool> {
+        Ok(self.tables().miners.remove(wallet).is_some())
+    }
+
+    fn miners(&self) -> StorageResult<Vec<(String, u32)>> {
+        let mut miners: Vec<(i64, String, u32)> = self
+            .tables()
+            .miners
+            .iter()
+            .map(|(wallet, (weight, registered_at))| (*registered_at, wallet.clone(), *weight))
+            .collect();
+        miners.sort();
+        Ok(miners
+            .into_iter()
+            .map(|(_, wallet, weight)| (wallet, weight))
+            .collect())
+    }
+
+    fn record_mined_block(
+        &self,
+        block_hash: &str,
+        _height: u64,
+        wallet: &str,
+        source: &str,
+    ) -> StorageResult<()> {
+        self.tables()
+            .mined_blocks
+            .entry(block_hash.to_string())
+            .or_insert_with(|| MinedBlock {
+                wallet: wallet.to_string(),
+                source: source.to_string(),
+                mined_at: unix_now(),
+            });
+        Ok(())
+    }
+
+    fn block_miner(&self, block_hash: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .tables()
+            .mined_blocks
+            .get(block_hash)
+            .map(|block| block.wallet.clone()))
+    }
+
+    fn miner_stats(&self) -> StorageResult<Vec<MinerStats>> {
+        let mut stats: BTreeMap<String, MinerStats> = BTreeMap::new();
+        for block in self.tables().mined_blocks.values() {
+            let entry = stats
+                .entry(block.wallet.clone())
+                .or_insert_with(|| MinerStats {
+                    wallet: block.wallet.clone(),
+                    blocks: 0,
+                    auto_blocks: 0,
+                    last_mined_at: block.mined_at,
+                });
+            entry.blocks += 1;
+            entry.auto_blocks += u64::from(block.source == "auto");
+            entry.last_mined_at = entry.last_mined_at.max(block.mined_at);
+        }
+        let mut stats: Vec<MinerStats> = stats.into_values().collect();
+        stats.sort_by_key(|stat| (Reverse(stat.blocks), stat.last_mined_at));
+        Ok(stats)
+    }
+
+    fn insert_multisig(&self, multisig: &MultisigWallet) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if tables.multisig_wallets.contains_key(&multisig.wallet) {
+            return duplicate("multisig_wallets", &multisig.wallet);
+        }
+        tables
+            .multisig_wallets
+            .insert(multisig.wallet.clone(), multisig.clone());
+        Ok(())
+    }
+
+    fn multisig(&self, wallet: &str) -> StorageResult<Option<MultisigWallet>> {
+        Ok(self.tables().multisig_wallets.get(wallet).cloned())
+    }
+
+    fn insert_invoice(&self, invoice: &Invoice) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if tables.invoices.contains_key(&invoice.id) {
+            return duplicate("invoices", &invoice.id);
+        }
+        tables.invoices.insert(invoice.id.clone(), invoice.clone());
+        Ok(())
+    }
+
+    fn invoice(&self, wallet: &str, id: &str) -> StorageResult<Option<Invoice>> {
+        Ok(self
+            .tables()
+            .invoices
+            .get(id)
+            .filter(|invoice| invoice.wallet == wallet)
+            .cloned())
+    }
+
+    fn invoices_page(
+        &self,
+        wallet: &str,
+        before: Option<&Cursor>,
+        limit: usize,
+    ) -> StorageResult<Vec<Invoice>> {
+        let key = |invoice: &Invoice| Cursor::new(invoice.created_at, invoice.id.as_str());
+        let mut invoices: Vec<Invoice> = self
+            .tables()
+            .invoices
+            .values()
+            .filter(|invoice| {
+                invoice.wallet == wallet && before.is_none_or(|before| key(invoice) < *before)
+            })
+            .cloned()
+            .collect();
+        invoices.sort_by_key(|invoice| Reverse(key(invoice)));
+        invoices.truncate(limit);
+        Ok(invoices)
+    }
+
+    fn set_confirmation_policy(
+        &self,
+        wallet: &str,
+        tiers: &[ConfirmationTier],
+    ) -> StorageResult<()> {
+        self.tables()
+            .confirmation_policies
+            .insert(wallet.to_string(), tiers.to_vec());
+        Ok(())
+    }
+
+    fn remove_confirmation_policy(&self, wallet: &str) -> StorageResult<()> {
+        self.tables().confirmation_policies.remove(wallet);
+        Ok(())
+    }
+
+    fn confirmation_policy(&self, wallet: &str) -> StorageResult<Option<Vec<ConfirmationTier>>> {
+        Ok(self.tables().confirmation_policies.get(wallet).cloned())
+    }
+
+    fn set_reservation(&self, wallet: &str, bucket: &str, amount_sat: u64) -> StorageResult<()> {
+        let key = (wallet.to_string(), bucket.to_string());
+        let mut tables = self.tables();
+        if amount_sat == 0 {
+            tables.reservations.remove(&key);
+        } else {
+            tables.reservations.insert(
+                key,
+                Reservation {
+                    bucket: bucket.to_string(),
+                    amount_sat,
+                    updated_at: unix_now(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn release_reservation(
+        &self,
+        wallet: &str,
+        bucket: &str,
+        amount_sat: u64,
+    ) -> StorageResult<()> {
+        let key = (wallet.to_string(), bucket.to_string());
+        let mut tables = self.tables();
+        if let Some(reservation) = tables.reservations.get_mut(&key) {
+            reservation.amount_sat = reservation.amount_sat.saturating_sub(amount_sat);
+            reservation.updated_at = unix_now();
+            if reservation.amount_sat == 0 {
+                tables.reservations.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    fn reservations(&self, wallet: &str) -> StorageResult<Vec<Reservation>> {
+        Ok(self
+            .tables()
+            .reservations
+            .iter()
+            .filter(|((owner, _), _)| owner == wallet)
+            .map(|(_, reservation)| reservation.clone())
+            .collect())
+    }
+
+    fn record_block_sighting(
+        &self,
+        block_hash: &str,
+        node: &str,
+        seen_at_ms: i64,
+        source: &str,
+    ) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        let key = (block_hash.to_string(), node.to_string());
+        if tables.block_sightings.contains_key(&key) {
+            return Ok(false);
+        }
+        tables.block_sightings.insert(
+            key,
+            BlockSighting {
+                node: node.to_string(),
+                seen_at_ms,
+                source: source.to_string(),
+            },
+        );
+        Ok(true)
+    }
+
+    fn block_sightings(&self, block_hash: &str) -> StorageResult<Vec<BlockSighting>> {
+        let mut sightings: Vec<BlockSighting> = self
+            .tables()
+            .block_sightings
+            .iter()
+            .filter(|((hash, _), _)| hash == block_hash)
+            .map(|(_, sighting)| sighting.clone())
+            .collect();
+        sightings.sort_by(|a, b| (a.seen_at_ms, &a.node).cmp(&(b.seen_at_ms, &b.node)));
+        Ok(sightings)
+    }
+
+    fn enqueue_outbox(&self, entry: &OutboxEntry) -> StorageResult<OutboxEntry> {
+        let mut tables = self.tables();
+        if let Some(key) = &entry.idempotency_key {
+            if let Some(queued) = tables
+                .outbox
+                .iter()
+                .find(|queued| queued.idempotency_key.as_ref() == Some(key))
+            {
+                return Ok(queued.clone());
+            }
+        }
+        if tables.outbox.iter().any(|queued| queued.id == entry.id) {
+            return duplicate("outbox", &entry.id);
+        }
+        tables.outbox.push(entry.clone());
+        Ok(entry.clone())
+    }
+
+    fn outbox_entries(&self, statuses: &[&str], limit: usize) -> StorageResult<Vec<OutboxEntry>> {
+        let mut entries: Vec<OutboxEntry> = self
+            .tables()
+            .outbox
+            .iter()
+            .filter(|entry| statuses.is_empty() || statuses.contains(&entry.status.as_str()))
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| (Reverse(a.created_at), &a.id).cmp(&(Reverse(b.created_at), &b.id)));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    fn outbox_backlog(&self, statuses: &[&str], limit: usize) -> StorageResult<Vec<OutboxEntry>> {
+        let mut entries: Vec<OutboxEntry> = self
+            .tables()
+            .outbox
+            .iter()
+            .filter(|entry| statuses.contains(&entry.status.as_str()))
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    fn sign_outbox(&self, id: &str, txid: &str, tx_hex: &str) -> StorageResult<()> {
+        if let Some(entry) = self.tables().outbox.iter_mut().find(|entry| entry.id == id) {
+            entry.status = "signed".to_string();
+            entry.txid = Some(txid.to_string());
+            entry.tx_hex = Some(tx_hex.to_string());
+            entry.last_error = None;
+            entry.updated_at = unix_now();
+        }
+        Ok(())
+    }
+
+    fn update_outbox(
+        &self,
+        id: &str,
+        status: &str,
+        txid: Option<&str>,
+        last_error: Option<&str>,
+        attempted: bool,
+    ) -> StorageResult<()> {
+        if let Some(entry) = self.tables().outbox.iter_mut().find(|entry| entry.id == id) {
+            entry.status = status.to_string();
+            if let Some(txid) = txid {
+                entry.txid = Some(txid.to_string());
+            }
+            entry.last_error = last_error.map(str::to_string);
+            entry.attempts += u32::from(attempted);
+            entry.updated_at = unix_now();
+        }
+        Ok(())
+    }
+
+    fn insert_cohort(&self, cohort: &Cohort) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        if tables.cohorts.iter().any(|known| known.name == cohort.name) {
+            return Ok(false);
+        }
+        tables.cohorts.push(cohort.clone());
+        Ok(true)
+    }
+
+    fn cohort(&self, name: &str) -> StorageResult<Option<Cohort>> {
+        Ok(self
+            .tables()
+            .cohorts
+            .iter()
+            .find(|cohort| cohort.name == name)
+            .cloned())
+    }
+
+    fn cohorts(&self) -> StorageResult<Vec<Cohort>> {
+        Ok(oldest_first(self.tables().cohorts.clone(), |cohort| {
+            cohort.created_at
+        }))
+    }
+
+    fn insert_cohort_student(&self, cohort: &str, student: &CohortStudent) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        if tables
+            .cohort_students
+            .iter()
+            .any(|(owner, known)| owner == cohort && known.student == student.student)
+        {
+            return Ok(false);
+        }
+        tables
+            .cohort_students
+            .push((cohort.to_string(), student.clone()));
+        Ok(true)
+    }
+
+    fn cohort_students(&self, cohort: &str) -> StorageResult<Vec<CohortStudent>> {
+        let students = self
+            .tables()
+            .cohort_students
+            .iter()
+            .filter(|(owner, _)| owner == cohort)
+            .map(|(_, student)| student.clone())
+            .collect();
+        Ok(oldest_first(students, |student| student.created_at))
+    }
+
+    fn charge_faucet_allowance(
+        &self,
+        cohort: &str,
+        student: &str,
+        amount_sat: i64,
+        allowance_sat: u64,
+    ) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        let Some((_, entry)) = tables
+            .cohort_students
+            .iter_mut()
+            .find(|(owner, known)| owner == cohort && known.student == student)
+        else {
+            return Ok(false);
+        };
+        let used = entry.faucet_used_sat as i64 + amount_sat;
+        if used > allowance_sat as i64 {
+            return Ok(false);
+        }
+        entry.faucet_used_sat = used.max(0) as u64;
+        Ok(true)
+    }
+
+    fn insert_tx_watcher(&self, watcher: &TxWatcher) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if tables
+            .tx_watchers
+            .iter()
+            .any(|known| known.id == watcher.id)
+        {
+            return duplicate("tx_watchers", &watcher.id);
+        }
+        tables.tx_watchers.push(watcher.clone());
+        Ok(())
+    }
+
+    fn tx_watcher(&self, id: &str) -> StorageResult<Option<TxWatcher>> {
+        Ok(self
+            .tables()
+            .tx_watchers
+            .iter()
+            .find(|watcher| watcher.id == id)
+            .cloned())
+    }
+
+    fn tx_watchers(&self, statuses: &[&str]) -> StorageResult<Vec<TxWatcher>> {
+        let watchers = self
+            .tables()
+            .tx_watchers
+            .iter()
+            .filter(|watcher| statuses.is_empty() || statuses.contains(&watcher.status.as_str()))
+            .cloned()
+            .collect();
+        Ok(oldest_first(watchers, |watcher| watcher.created_at))
+    }
+
+    fn tx_watchers_page(
+        &self,
+        statuses: &[&str],
+        wallet_prefix: &str,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> StorageResult<Vec<TxWatcher>> {
+        let key = |watcher: &TxWatcher| Cursor::new(watcher.created_at, watcher.id.as_str());
+        let mut watchers: Vec<TxWatcher> = self
+            .tables()
+            .tx_watchers
+            .iter()
+            .filter(|watcher| statuses.is_empty() || statuses.contains(&watcher.status.as_str()))
+            .filter(|watcher| watcher.wallet.starts_with(wallet_prefix))
+            .filter(|watcher| after.is_none_or(|after| key(watcher) > *after))
+            .cloned()
+            .collect();
+        watchers.sort_by_key(key);
+        watchers.truncate(limit);
+        Ok(watchers)
+    }
+
+    fn update_tx_watcher(
+        &self,
+        id: &str,
+        status: &str,
+        last_error: Option<&str>,
+        attempted: bool,
+    ) -> StorageResult<()> {
+        let mut tables = self.tables();
+        if let Some(watcher) = tables
+            .tx_watchers
+            .iter_mut()
+            .find(|watcher| watcher.id == id)
+        {
+            watcher.status = status.to_string();
+            watcher.last_error = last_error.map(str::to_string);
+            watcher.attempts += u32::from(attempted);
+            if status == "notified" {
+                watcher.notified_at = Some(unix_now());
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_tx_watcher(&self, id: &str) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        let before = tables.tx_watchers.len();
+        tables.tx_watchers.retain(|watcher| watcher.id != id);
+        Ok(tables.tx_watchers.len() < before)
+    }
+
+    fn insert_user(&self, user: &User) -> StorageResult<bool> {
+        let mut tables = self.tables();
+        if tables.users.contains_key(&user.username) {
+            return Ok(false);
+        }
+        tables.users.insert(user.username.clone(), user.clone());
+        Ok(true)
+    }
+
+    fn user(&self, username: &str) -> StorageResult<Option<User>> {
+        Ok(self.tables().users.get(username).cloned())
+    }
+}
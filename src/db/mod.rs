@@ -0,0 +1,474 @@
+mod memory;
+mod sqlite;
+#[cfg(test)]
+mod tests;
+
+use crate::pagination::Cursor;
+use bitcoincore_rpc::Error as RpcError;
+use dotenv as env;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+pub(crate) use memory::MemoryStorage;
+pub(crate) use sqlite::SqliteStorage;
+
+const DEFAULT_SQLITE_PATH: &str = "coin-comic-tales.db";
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct WatchedAddress {
+    pub(crate) address: String,
+    pub(crate) label: Option<String>,
+    pub(crate) created_at: i64,
+    // Only blocks above this height are scanned for the address
+    pub(crate) scanned_height: u64,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct WatchedOutput {
+    pub(crate) txid: String,
+    pub(crate) vout: u32,
+    pub(crate) amount_sat: u64,
+    pub(crate) block_hash: String,
+    pub(crate) block_height: u64,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct PaymentChannel {
+    pub(crate) id: String,
+    pub(crate) payer_wallet: String,
+    pub(crate) payee_wallet: String,
+    pub(crate) payer_address: String,
+    pub(crate) payee_address: String,
+    pub(crate) descriptor: String,
+    pub(crate) funding_txid: String,
+    pub(crate) funding_vout: u32,
+    pub(crate) capacity_sat: u64,
+    pub(crate) refund_locktime: u32,
+    pub(crate) refund_psbt: String,
+    pub(crate) paid_sat: u64,
+    pub(crate) commitment_count: u32,
+    pub(crate) commitment_psbt: Option<String>,
+    pub(crate) state: String,
+    pub(crate) closing_txid: Option<String>,
+    pub(crate) created_at: i64,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct OutboxEntry {
+    pub(crate) id: String,
+    pub(crate) wallet: String,
+    pub(crate) to_address: String,
+    pub(crate) amount_sat: u64,
+    pub(crate) message: Option<String>,
+    pub(crate) status: String,
+    pub(crate) txid: Option<String>,
+    pub(crate) attempts: u32,
+    pub(crate) last_error: Option<String>,
+    #[serde(skip)]
+    pub(crate) idempotency_key: Option<String>,
+    pub(crate) created_at: i64,
+    pub(crate) updated_at: i64,
+    // The signed payment, kept so a retry broadcasts the same transaction
+    #[serde(skip)]
+    pub(crate) tx_hex: Option<String>,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct TxWatcher {
+    pub(crate) id: String,
+    pub(crate) wallet: String,
+    pub(crate) txid: String,
+    pub(crate) confirmations: u32,
+    pub(crate) webhook_url: String,
+    pub(crate) status: String,
+    // Failed webhook deliveries
+    pub(crate) attempts: u32,
+    pub(crate) last_error: Option<String>,
+    pub(crate) created_at: i64,
+    pub(crate) notified_at: Option<i64>,
+}
+
+// An account of users mode; the hash is an argon2 PHC string
+#[derive(Clone)]
+pub(crate) struct User {
+    pub(crate) username: String,
+    pub(crate) password_hash: String,
+    pub(crate) created_at: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct MinerStats {
+    pub(crate) wallet: String,
+    pub(crate) blocks: u64,
+    pub(crate) auto_blocks: u64,
+    pub(crate) last_mined_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct MultisigParticipant {
+    pub(crate) wallet: String,
+    pub(crate) public_key: String,
+    // [fingerprint/derivation path] of the key in the participant's wallet
+    pub(crate) key_origin: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct MultisigWallet {
+    pub(crate) wallet: String,
+    pub(crate) descriptor: String,
+    pub(crate) address: String,
+    pub(crate) required: u32,
+    pub(crate) participants: Vec<MultisigParticipant>,
+    pub(crate) created_at: i64,
+}
+
+#[derive(Clone)]
+pub(crate) struct Invoice {
+    pub(crate) id: String,
+    pub(crate) wallet: String,
+    pub(crate) address: String,
+    pub(crate) amount_sat: u64,
+    pub(crate) description: Option<String>,
+    pub(crate) created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct ConfirmationTier {
+    // Exclusive upper bound of the invoice amounts in this tier; None for the last tier
+    pub(crate) below_sat: Option<u64>,
+    pub(crate) confirmations: u32,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct Reservation {
+    pub(crate) bucket: String,
+    pub(crate) amount_sat: u64,
+    pub(crate) updated_at: i64,
+}
+
+#[derive(Clone)]
+pub(crate) struct BlockSighting {
+    pub(crate) node: String,
+    pub(crate) seen_at_ms: i64,
+    pub(crate) source: String,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct Cohort {
+    pub(crate) name: String,
+    // What each student may draw from the faucet in total, starting balance included
+    pub(crate) faucet_allowance_sat: u64,
+    pub(crate) created_at: i64,
+}
+
+#[derive(Serialize, Clone)]
+pub(crate) struct CohortStudent {
+    pub(crate) student: String,
+    pub(crate) wallet: String,
+    pub(crate) faucet_used_sat: u64,
+    pub(crate) created_at: i64,
+}
+
+#[derive(Clone)]
+pub(crate) struct ActivityRecord {
+    pub(crate) id: i64,
+    pub(crate) kind: String,
+    pub(crate) time: i64,
+    pub(crate) details: Value,
+}
+
+#[derive(Debug)]
+pub(crate) struct StorageError(String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+impl From<String> for StorageError {
+    fn from(message: String) -> Self {
+        StorageError(message)
+    }
+}
+
+pub(crate) type StorageResult<T> = Result<T, StorageError>;
+
+// App data that bitcoind does not keep for us. Feature code only sees this trait,
+// so another database only needs a new implementation.
+pub(crate) trait Storage: Send + Sync {
+    fn set_label(&self, wallet: &str, address: &str, label: &str) -> StorageResult<()>;
+
+    fn record_confirmation(
+        &self,
+        wallet: &str,
+        txid: &str,
+        block_hash: &str,
+        block_height: u64,
+    ) -> StorageResult<()>;
+
+    // (txid, block hash, block height) of every transaction seen confirmed in the wallet
+    fn confirmations(&self, wallet: &str) -> StorageResult<Vec<(String, String, u64)>>;
+
+    // Returns false when the address was already watched
+    fn watch_address(&self, watched: &WatchedAddress) -> StorageResult<bool>;
+
+    // The durable watch list; replicas share it through the shared state, see
+    // watch::import_stored
+    fn watched_addresses(&self) -> StorageResult<Vec<WatchedAddress>>;
+
+    fn record_watched_output(&self, address: &str, output: &WatchedOutput) -> StorageResult<bool>;
+
+    fn watched_outputs(&self, address: &str) -> StorageResult<Vec<WatchedOutput>>;
+
+    // Outputs to watched addresses paid by any of the given transactions, with the address
+    fn watched_outputs_of(&self, txids: &[String]) -> StorageResult<Vec<(String, WatchedOutput)>>;
+
+    fn record_activity(&self, wallet: &str, kind: &str, details: &Value) -> StorageResult<()>;
+
+    // Most recent first, at most `limit` records older than `before` (time, id)
+    fn activity(
+        &self,
+        wallet: &str,
+        before: Option<(i64, i64)>,
+        limit: usize,
+    ) -> StorageResult<Vec<ActivityRecord>>;
+
+    fn record_replacement(
+        &self,
+        wallet: &str,
+        original: &str,
+        replacement: &str,
+    ) -> StorageResult<bool>;
+
+    // Transactions that replaced `txid`, with when each replacement was seen
+    fn replaced_by(&self, txid: &str) -> StorageResult<Vec<(String, i64)>>;
+
+    fn replaces(&self, txid: &str) -> StorageResult<Option<String>>;
+
+    fn register_wallet(&self, name: &str) -> StorageResult<()>;
+
+    fn unregister_wallet(&self, name: &str) -> StorageResult<()>;
+
+    // Forgets everything tied to wallets or blocks, for POST /admin/reset. Watched
+    // addresses stay and are scanned again from the start of the new chain; users stay
+    // too.
+    fn clear_chain_state(&self) -> StorageResult<()>;
+
+    fn registered_wallets(&self) -> StorageResult<Vec<String>>;
+
+    fn chain_cursor(&self, name: &str) -> StorageResult<Option<String>>;
+
+    fn set_chain_cursor(&self, name: &str, block_hash: &str) -> StorageResult<()>;
+
+    fn insert_channel(&self, channel: &PaymentChannel) -> StorageResult<()>;
+
+    fn channel(&self, id: &str) -> StorageResult<Option<PaymentChannel>>;
+
+    fn update_commitment(
+        &self,
+        id: &str,
+        paid_sat: u64,
+        commitment_count: u32,
+        commitment_psbt: &str,
+    ) -> StorageResult<()>;
+
+    fn close_channel(&self, id: &str, state: &str, closing_txid: &str) -> StorageResult<()>;
+
+    fn set_miner(&self, wallet: &str, weight: u32) -> StorageResult<()>;
+
+    // Returns false when the wallet was not a miner
+    fn remove_miner(&self, wallet: &str) -> StorageResult<bool>;
+
+    // (wallet, weight) in registration order
+    fn miners(&self) -> StorageResult<Vec<(String, u32)>>;
+
+    fn record_mined_block(
+        &self,
+        block_hash: &str,
+        height: u64,
+        wallet: &str,
+        source: &str,
+    ) -> StorageResult<()>;
+
+    fn block_miner(&self, block_hash: &str) -> StorageResult<Option<String>>;
+
+    // Most blocks first
+    fn miner_stats(&self) -> StorageResult<Vec<MinerStats>>;
+
+    fn insert_multisig(&self, multisig: &MultisigWallet) -> StorageResult<()>;
+
+    fn multisig(&self, wallet: &str) -> StorageResult<Option<MultisigWallet>>;
+
+    fn insert_invoice(&self, invoice: &Invoice) -> StorageResult<()>;
+
+    fn invoice(&self, wallet: &str, id: &str) -> StorageResult<Option<Invoice>>;
+
+    // Ordered by (created_at, id), newest first, at most `limit` before `before`
+    fn invoices_page(
+        &self,
+        wallet: &str,
+        before: Option<&Cursor>,
+        limit: usize,
+    ) -> StorageResult<Vec<Invoice>>;
+
+    fn set_confirmation_policy(
+        &self,
+        wallet: &str,
+        tiers: &[ConfirmationTier],
+    ) -> StorageResult<()>;
+
+    fn remove_confirmation_policy(&self, wallet: &str) -> StorageResult<()>;
+
+    // None when the wallet uses the default policy
+    fn confirmation_policy(&self, wallet: &str) -> StorageResult<Option<Vec<ConfirmationTier>>>;
+
+    // An amount of 0 removes the bucket
+    fn set_reservation(&self, wallet: &str, bucket: &str, amount_sat: u64) -> StorageResult<()>;
+
+    // Takes a spent amount out of a bucket; a bucket spent down to nothing is removed
+    fn release_reservation(&self, wallet: &str, bucket: &str, amount_sat: u64)
+        -> StorageResult<()>;
+
+    fn reservations(&self, wallet: &str) -> StorageResult<Vec<Reservation>>;
+
+    // Keeps the first sighting; returns false when the node had already been seen with the block
+    fn record_block_sighting(
+        &self,
+        block_hash: &str,
+        node: &str,
+        seen_at_ms: i64,
+        source: &str,
+    ) -> StorageResult<bool>;
+
+    // Earliest first
+    fn block_sightings(&self, block_hash: &str) -> StorageResult<Vec<BlockSighting>>;
+
+    // Returns the entry already queued under the same idempotency key instead, if any
+    fn enqueue_outbox(&self, entry: &OutboxEntry) -> StorageResult<OutboxEntry>;
+
+    // Newest first; `statuses` empty means all of them
+    fn outbox_entries(&self, statuses: &[&str], limit: usize) -> StorageResult<Vec<OutboxEntry>>;
+
+    // Oldest first, for the worker
+    fn outbox_backlog(&self, statuses: &[&str], limit: usize) -> StorageResult<Vec<OutboxEntry>>;
+
+    // Stores the signed transaction and moves the entry to `signed`
+    fn sign_outbox(&self, id: &str, txid: &str, tx_hex: &str) -> StorageResult<()>;
+
+    fn update_outbox(
+        &self,
+        id: &str,
+        status: &str,
+        txid: Option<&str>,
+        last_error: Option<&str>,
+        attempted: bool,
+    ) -> StorageResult<()>;
+
+    // Returns false when a cohort of that name exists
+    fn insert_cohort(&self, cohort: &Cohort) -> StorageResult<bool>;
+
+    fn cohort(&self, name: &str) -> StorageResult<Option<Cohort>>;
+
+    // Oldest first
+    fn cohorts(&self) -> StorageResult<Vec<Cohort>>;
+
+    // Returns false when the cohort already has a student of that name
+    fn insert_cohort_student(&self, cohort: &str, student: &CohortStudent) -> StorageResult<bool>;
+
+    // In the order they were provisioned
+    fn cohort_students(&self, cohort: &str) -> StorageResult<Vec<CohortStudent>>;
+
+    // Counts `amount_sat` against the student's faucet allowance unless that would exceed
+    // `allowance_sat`; returns whether it did. A negative amount gives some back.
+    fn charge_faucet_allowance(
+        &self,
+        cohort: &str,
+        student: &str,
+        amount_sat: i64,
+        allowance_sat: u64,
+    ) -> StorageResult<bool>;
+
+    fn insert_tx_watcher(&self, watcher: &TxWatcher) -> StorageResult<()>;
+
+    fn tx_watcher(&self, id: &str) -> StorageResult<Option<TxWatcher>>;
+
+    // Oldest first; `statuses` empty means all of them
+    fn tx_watchers(&self, statuses: &[&str]) -> StorageResult<Vec<TxWatcher>>;
+
+    // Ordered by (created_at, id), at most `limit` past `after`, of wallets starting with
+    // `wallet_prefix`
+    fn tx_watchers_page(
+        &self,
+        statuses: &[&str],
+        wallet_prefix: &str,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> StorageResult<Vec<TxWatcher>>;
+
+    // `notified_at` is set when the status becomes notified
+    fn update_tx_watcher(
+        &self,
+        id: &str,
+        status: &str,
+        last_error: Option<&str>,
+        attempted: bool,
+    ) -> StorageResult<()>;
+
+    fn remove_tx_watcher(&self, id: &str) -> StorageResult<bool>;
+
+    // Returns false when a user of that name exists
+    fn insert_user(&self, user: &User) -> StorageResult<bool>;
+
+    fn user(&self, username: &str) -> StorageResult<Option<User>>;
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum StorageBackend {
+    Sqlite { path: String },
+    // Forgets everything on restart, for tests and throwaway servers
+    Memory,
+}
+
+impl StorageBackend {
+    // `storage_backend` is sqlite or memory; `sqlite_path` defaults to
+    // coin-comic-tales.db in the working directory
+    pub(crate) fn from_env() -> Result<Self, RpcError> {
+        match env::var("storage_backend").as_deref().map(str::trim) {
+            Err(_) | Ok("") | Ok("sqlite") => Ok(Self::Sqlite {
+                path: env::var("sqlite_path").unwrap_or_else(|_| DEFAULT_SQLITE_PATH.into()),
+            }),
+            Ok("memory") => Ok(Self::Memory),
+            Ok(other) => Err(RpcError::ReturnedError(format!(
+                "unknown storage_backend '{other}', expected sqlite or memory"
+            ))),
+        }
+    }
+}
+
+pub(crate) fn open(backend: &StorageBackend) -> Result<Box<dyn Storage>, String> {
+    match backend {
+        StorageBackend::Sqlite { path } => Ok(Box::new(SqliteStorage::open(path)?)),
+        StorageBackend::Memory => {
+            info!("Using in-memory storage, nothing is kept across restarts");
+            Ok(Box::new(MemoryStorage::default()))
+        }
+    }
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,1280 @@
+use super::{
+    unix_now, ActivityRecord, BlockSighting, Cohort, CohortStudent, ConfirmationTier, Cursor,
+    Invoice, MinerStats, MultisigWallet, OutboxEntry, PaymentChannel, Reservation, Storage,
+    StorageResult, TxWatcher, User, WatchedAddress, WatchedOutput,
+};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::sync::{Mutex, MutexGuard};
+
+// Applied in order; the schema version is the number of migrations already run.
+// Append new entries, never edit existing ones.
+const MIGRATIONS: &[&str] = &[
+    // Address book mirroring the wallet labels in bitcoind
+    "CREATE TABLE address_labels (
+        wallet TEXT NOT NULL,
+        address TEXT NOT NULL,
+        label TEXT NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (wallet, address)
+    )",
+    // Block each wallet transaction was last seen confirmed in, to spot reorgs
+    "CREATE TABLE wallet_confirmations (
+        wallet TEXT NOT NULL,
+        txid TEXT NOT NULL,
+        block_hash TEXT NOT NULL,
+        block_height INTEGER NOT NULL,
+        recorded_at INTEGER NOT NULL,
+        PRIMARY KEY (wallet, txid)
+    )",
+    // Addresses outside any wallet that are watched for incoming outputs
+    "CREATE TABLE watched_addresses (
+        address TEXT PRIMARY KEY,
+        label TEXT,
+        created_at INTEGER NOT NULL,
+        scanned_height INTEGER NOT NULL
+    );
+    CREATE TABLE watched_outputs (
+        txid TEXT NOT NULL,
+        vout INTEGER NOT NULL,
+        address TEXT NOT NULL,
+        amount_sat INTEGER NOT NULL,
+        block_hash TEXT NOT NULL,
+        block_height INTEGER NOT NULL,
+        PRIMARY KEY (txid, vout)
+    )",
+    // Unidirectional payment channels of the channel demo
+    "CREATE TABLE payment_channels (
+        id TEXT PRIMARY KEY,
+        payer_wallet TEXT NOT NULL,
+        payee_wallet TEXT NOT NULL,
+        payer_address TEXT NOT NULL,
+        payee_address TEXT NOT NULL,
+        descriptor TEXT NOT NULL,
+        funding_txid TEXT NOT NULL,
+        funding_vout INTEGER NOT NULL,
+        capacity_sat INTEGER NOT NULL,
+        refund_locktime INTEGER NOT NULL,
+        refund_psbt TEXT NOT NULL,
+        paid_sat INTEGER NOT NULL,
+        commitment_count INTEGER NOT NULL,
+        commitment_psbt TEXT,
+        state TEXT NOT NULL,
+        closing_txid TEXT,
+        created_at INTEGER NOT NULL
+    )",
+    // Server-side happenings per wallet for the activity feed; details is a JSON object
+    "CREATE TABLE wallet_activity (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        wallet TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        details TEXT NOT NULL
+    );
+    CREATE INDEX wallet_activity_by_wallet ON wallet_activity (wallet, time)",
+    // RBF lineage: each row is one replacement of `original_txid`
+    "CREATE TABLE tx_replacements (
+        original_txid TEXT NOT NULL,
+        replacement_txid TEXT NOT NULL,
+        wallet TEXT NOT NULL,
+        detected_at INTEGER NOT NULL,
+        PRIMARY KEY (original_txid, replacement_txid)
+    );
+    CREATE INDEX tx_replacements_by_replacement ON tx_replacements (replacement_txid)",
+    // Last block processed by each event producer, to catch up after a restart
+    "CREATE TABLE chain_cursors (
+        name TEXT PRIMARY KEY,
+        block_hash TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    )",
+    // Wallets registered through the API, registered again on startup
+    "CREATE TABLE registered_wallets (
+        name TEXT PRIMARY KEY,
+        registered_at INTEGER NOT NULL
+    )",
+    // Payments queued by /send in outbox mode, broadcast by a background worker
+    "CREATE TABLE outbox (
+        id TEXT PRIMARY KEY,
+        wallet TEXT NOT NULL,
+        to_address TEXT NOT NULL,
+        amount_sat INTEGER NOT NULL,
+        message TEXT,
+        status TEXT NOT NULL,
+        txid TEXT,
+        attempts INTEGER NOT NULL,
+        last_error TEXT,
+        idempotency_key TEXT UNIQUE,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX outbox_by_status ON outbox (status)",
+    // Wallets sharing the auto-miner's blocks in proportion to their weight
+    "CREATE TABLE auto_miners (
+        wallet TEXT PRIMARY KEY,
+        weight INTEGER NOT NULL,
+        registered_at INTEGER NOT NULL
+    )",
+    // Who mined each block through this server; source is 'auto' or 'manual'
+    "CREATE TABLE mined_blocks (
+        block_hash TEXT PRIMARY KEY,
+        height INTEGER NOT NULL,
+        wallet TEXT NOT NULL,
+        source TEXT NOT NULL,
+        mined_at INTEGER NOT NULL
+    );
+    CREATE INDEX mined_blocks_by_wallet ON mined_blocks (wallet)",
+    // Watch-only wallets holding a multisig address; participants is a JSON array
+    "CREATE TABLE multisig_wallets (
+        wallet TEXT PRIMARY KEY,
+        descriptor TEXT NOT NULL,
+        address TEXT NOT NULL,
+        required INTEGER NOT NULL,
+        participants TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    )",
+    // Payment requests for an amount to a fresh wallet address
+    "CREATE TABLE invoices (
+        id TEXT PRIMARY KEY,
+        wallet TEXT NOT NULL,
+        address TEXT NOT NULL,
+        amount_sat INTEGER NOT NULL,
+        description TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX invoices_by_wallet ON invoices (wallet)",
+    // Confirmations a wallet requires before an invoice counts as paid; tiers is a JSON array
+    "CREATE TABLE confirmation_policies (
+        wallet TEXT PRIMARY KEY,
+        tiers TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    )",
+    // When each node of the toy network first had a block; source is 'zmq' or 'poll'
+    "CREATE TABLE block_sightings (
+        block_hash TEXT NOT NULL,
+        node TEXT NOT NULL,
+        seen_at_ms INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        PRIMARY KEY (block_hash, node)
+    )",
+    // Parts of a wallet's balance set aside under a named bucket, a soft limit on sends
+    "CREATE TABLE wallet_reservations (
+        wallet TEXT NOT NULL,
+        bucket TEXT NOT NULL,
+        amount_sat INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (wallet, bucket)
+    )",
+    // Classes provisioned by an instructor; every student gets a wallet of their own
+    "CREATE TABLE cohorts (
+        name TEXT PRIMARY KEY,
+        faucet_allowance_sat INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE cohort_students (
+        cohort TEXT NOT NULL,
+        student TEXT NOT NULL,
+        wallet TEXT NOT NULL,
+        faucet_used_sat INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (cohort, student)
+    )",
+    // Webhooks to call once a wallet transaction has enough confirmations
+    "CREATE TABLE tx_watchers (
+        id TEXT PRIMARY KEY,
+        wallet TEXT NOT NULL,
+        txid TEXT NOT NULL,
+        confirmations INTEGER NOT NULL,
+        webhook_url TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        last_error TEXT,
+        created_at INTEGER NOT NULL,
+        notified_at INTEGER
+    );
+    CREATE INDEX tx_watchers_by_status ON tx_watchers (status)",
+    // Lets the watch listings seek to their cursor
+    "CREATE INDEX watched_addresses_by_creation ON watched_addresses (created_at, address);
+    CREATE INDEX tx_watchers_by_creation ON tx_watchers (created_at, id)",
+    // Accounts of users mode; they outlive POST /admin/reset
+    "CREATE TABLE users (
+        username TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    )",
+    // Queued payments are signed once and the same transaction is broadcast on retries
+    "ALTER TABLE outbox ADD COLUMN tx_hex TEXT",
+];
+
+const OUTBOX_COLUMNS: &str = "id, wallet, to_address, amount_sat, message, status, txid, attempts,
+    last_error, idempotency_key, created_at, updated_at, tx_hex";
+
+fn cohort(row: &rusqlite::Row) -> rusqlite::Result<Cohort> {
+    Ok(Cohort {
+        name: row.get(0)?,
+        faucet_allowance_sat: row.get::<_, i64>(1)? as u64,
+        created_at: row.get(2)?,
+    })
+}
+
+const TX_WATCHER_COLUMNS: &str =
+    "id, wallet, txid, confirmations, webhook_url, status, attempts, last_error, created_at, notified_at";
+
+fn watched_address(row: &rusqlite::Row) -> rusqlite::Result<WatchedAddress> {
+    Ok(WatchedAddress {
+        address: row.get(0)?,
+        label: row.get(1)?,
+        created_at: row.get(2)?,
+        scanned_height: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+fn invoice(row: &rusqlite::Row) -> rusqlite::Result<Invoice> {
+    Ok(Invoice {
+        id: row.get(0)?,
+        wallet: row.get(1)?,
+        address: row.get(2)?,
+        amount_sat: row.get::<_, i64>(3)? as u64,
+        description: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+fn tx_watcher(row: &rusqlite::Row) -> rusqlite::Result<TxWatcher> {
+    Ok(TxWatcher {
+        id: row.get(0)?,
+        wallet: row.get(1)?,
+        txid: row.get(2)?,
+        confirmations: row.get(3)?,
+        webhook_url: row.get(4)?,
+        status: row.get(5)?,
+        attempts: row.get(6)?,
+        last_error: row.get(7)?,
+        created_at: row.get(8)?,
+        notified_at: row.get(9)?,
+    })
+}
+
+fn outbox_entry(row: &rusqlite::Row) -> rusqlite::Result<OutboxEntry> {
+    Ok(OutboxEntry {
+        id: row.get(0)?,
+        wallet: row.get(1)?,
+        to_address: row.get(2)?,
+        amount_sat: row.get::<_, i64>(3)? as u64,
+        message: row.get(4)?,
+        status: row.get(5)?,
+        txid: row.get(6)?,
+        attempts: row.get(7)?,
+        last_error: row.get(8)?,
+        idempotency_key: row.get(9)?,
+        created_at: row.get(10)?,
+        updated_at: row.get(11)?,
+        tx_hex: row.get(12)?,
+    })
+}
+
+// Keeps the app data in a sqlite file, so it survives restarts
+pub(crate) struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub(crate) fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(|e| format!("cannot open {path}: {e}"))?;
+        let db = Self {
+            conn: Mutex::new(conn),
+        };
+        db.migrate()
+            .map_err(|e| format!("cannot migrate {path}: {e}"))?;
+        info!("Using sqlite database at {}", path);
+        Ok(db)
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn migrate(&self) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn set_label(&self, wallet: &str, address: &str, label: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO address_labels (wallet, address, label, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (wallet, address) DO UPDATE SET label = excluded.label, updated_at = excluded.updated_at",
+            params![wallet, address, label, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn record_confirmation(
+        &self,
+        wallet: &str,
+        txid: &str,
+        block_hash: &str,
+        block_height: u64,
+    ) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO wallet_confirmations (wallet, txid, block_hash, block_height, recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (wallet, txid) DO UPDATE SET block_hash = excluded.block_hash,
+                 block_height = excluded.block_height, recorded_at = excluded.recorded_at
+             WHERE block_hash != excluded.block_hash",
+            params![wallet, txid, block_hash, block_height as i64, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn confirmations(&self, wallet: &str) -> StorageResult<Vec<(String, String, u64)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT txid, block_hash, block_height FROM wallet_confirmations
+             WHERE wallet = ?1 ORDER BY block_height DESC",
+        )?;
+        let rows = stmt.query_map(params![wallet], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn watch_address(&self, watched: &WatchedAddress) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO watched_addresses (address, label, created_at, scanned_height)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                watched.address,
+                watched.label,
+                watched.created_at,
+                watched.scanned_height as i64
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn watched_addresses(&self) -> StorageResult<Vec<WatchedAddress>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT address, label, created_at, scanned_height FROM watched_addresses
+             ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map([], watched_address)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn record_watched_output(&self, address: &str, output: &WatchedOutput) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO watched_outputs (txid, vout, address, amount_sat, block_hash, block_height)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                output.txid,
+                output.vout,
+                address,
+                output.amount_sat as i64,
+                output.block_hash,
+                output.block_height as i64
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn watched_outputs(&self, address: &str) -> StorageResult<Vec<WatchedOutput>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT txid, vout, amount_sat, block_hash, block_height FROM watched_outputs
+             WHERE address = ?1 ORDER BY block_height DESC, txid, vout",
+        )?;
+        let rows = stmt.query_map(params![address], |row| {
+            Ok(WatchedOutput {
+                txid: row.get(0)?,
+                vout: row.get(1)?,
+                amount_sat: row.get::<_, i64>(2)? as u64,
+                block_hash: row.get(3)?,
+                block_height: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn watched_outputs_of(&self, txids: &[String]) -> StorageResult<Vec<(String, WatchedOutput)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT address, txid, vout, amount_sat, block_hash, block_height FROM watched_outputs
+             WHERE txid IN (SELECT value FROM json_each(?1))",
+        )?;
+        let txids = serde_json::to_string(txids).unwrap_or_default();
+        let rows = stmt.query_map(params![txids], |row| {
+            Ok((
+                row.get(0)?,
+                WatchedOutput {
+                    txid: row.get(1)?,
+                    vout: row.get(2)?,
+                    amount_sat: row.get::<_, i64>(3)? as u64,
+                    block_hash: row.get(4)?,
+                    block_height: row.get::<_, i64>(5)? as u64,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn record_activity(&self, wallet: &str, kind: &str, details: &Value) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO wallet_activity (wallet, kind, time, details) VALUES (?1, ?2, ?3, ?4)",
+            params![wallet, kind, unix_now(), details.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn activity(
+        &self,
+        wallet: &str,
+        before: Option<(i64, i64)>,
+        limit: usize,
+    ) -> StorageResult<Vec<ActivityRecord>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, kind, time, details FROM wallet_activity
+             WHERE wallet = ?1 AND (?2 IS NULL OR time < ?2 OR (time = ?2 AND id < ?3))
+             ORDER BY time DESC, id DESC LIMIT ?4",
+        )?;
+        let params = params![
+            wallet,
+            before.map(|(time, _)| time),
+            before.map(|(_, id)| id),
+            limit as i64
+        ];
+        let rows = stmt.query_map(params, |row| {
+            Ok(ActivityRecord {
+                id: row.get(0)?,
+                kind: row.get(1)?,
+                time: row.get(2)?,
+                details: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or(Value::Null),
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn record_replacement(
+        &self,
+        wallet: &str,
+        original: &str,
+        replacement: &str,
+    ) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO tx_replacements (original_txid, replacement_txid, wallet, detected_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![original, replacement, wallet, unix_now()],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn replaced_by(&self, txid: &str) -> StorageResult<Vec<(String, i64)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT replacement_txid, detected_at FROM tx_replacements
+             WHERE original_txid = ?1 ORDER BY detected_at, replacement_txid",
+        )?;
+        let rows = stmt.query_map(params![txid], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn replaces(&self, txid: &str) -> StorageResult<Option<String>> {
+        self.conn()
+            .query_row(
+                "SELECT original_txid FROM tx_replacements WHERE replacement_txid = ?1 LIMIT 1",
+                params![txid],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn register_wallet(&self, name: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT OR IGNORE INTO registered_wallets (name, registered_at) VALUES (?1, ?2)",
+            params![name, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn unregister_wallet(&self, name: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "DELETE FROM registered_wallets WHERE name = ?1",
+            params![name],
+        )?;
+        Ok(())
+    }
+
+    fn clear_chain_state(&self) -> StorageResult<()> {
+        self.conn().execute_batch(
+            "DELETE FROM address_labels;
+             DELETE FROM wallet_confirmations;
+             DELETE FROM watched_outputs;
+             UPDATE watched_addresses SET scanned_height = 0;
+             DELETE FROM payment_channels;
+             DELETE FROM wallet_activity;
+             DELETE FROM tx_replacements;
+             DELETE FROM chain_cursors;
+             DELETE FROM registered_wallets;
+             DELETE FROM outbox;
+             DELETE FROM auto_miners;
+             DELETE FROM mined_blocks;
+             DELETE FROM multisig_wallets;
+             DELETE FROM invoices;
+             DELETE FROM confirmation_policies;
+             DELETE FROM block_sightings;
+             DELETE FROM wallet_reservations;
+             DELETE FROM cohorts;
+             DELETE FROM cohort_students;
+             DELETE FROM tx_watchers;",
+        )?;
+        Ok(())
+    }
+
+    fn registered_wallets(&self) -> StorageResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT name FROM registered_wallets ORDER BY registered_at, name")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn chain_cursor(&self, name: &str) -> StorageResult<Option<String>> {
+        self.conn()
+            .query_row(
+                "SELECT block_hash FROM chain_cursors WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn set_chain_cursor(&self, name: &str, block_hash: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO chain_cursors (name, block_hash, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (name) DO UPDATE SET block_hash = ?2, updated_at = ?3",
+            params![name, block_hash, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn insert_channel(&self, channel: &PaymentChannel) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO payment_channels (id, payer_wallet, payee_wallet, payer_address, payee_address,
+                 descriptor, funding_txid, funding_vout, capacity_sat, refund_locktime, refund_psbt,
+                 paid_sat, commitment_count, commitment_psbt, state, closing_txid, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            params![
+                channel.id,
+                channel.payer_wallet,
+                channel.payee_wallet,
+                channel.payer_address,
+                channel.payee_address,
+                channel.descriptor,
+                channel.funding_txid,
+                channel.funding_vout,
+                channel.capacity_sat as i64,
+                channel.refund_locktime,
+                channel.refund_psbt,
+                channel.paid_sat as i64,
+                channel.commitment_count,
+                channel.commitment_psbt,
+                channel.state,
+                channel.closing_txid,
+                channel.created_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn channel(&self, id: &str) -> StorageResult<Option<PaymentChannel>> {
+        self.conn()
+            .query_row(
+                "SELECT id, payer_wallet, payee_wallet, payer_address, payee_address, descriptor,
+                     funding_txid, funding_vout, capacity_sat, refund_locktime, refund_psbt, paid_sat,
+                     commitment_count, commitment_psbt, state, closing_txid, created_at
+                 FROM payment_channels WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok(PaymentChannel {
+                        id: row.get(0)?,
+                        payer_wallet: row.get(1)?,
+                        payee_wallet: row.get(2)?,
+                        payer_address: row.get(3)?,
+                        payee_address: row.get(4)?,
+                        descriptor: row.get(5)?,
+                        funding_txid: row.get(6)?,
+                        funding_vout: row.get(7)?,
+                        capacity_sat: row.get::<_, i64>(8)? as u64,
+                        refund_locktime: row.get(9)?,
+                        refund_psbt: row.get(10)?,
+                        paid_sat: row.get::<_, i64>(11)? as u64,
+                        commitment_count: row.get(12)?,
+                        commitment_psbt: row.get(13)?,
+                        state: row.get(14)?,
+                        closing_txid: row.get(15)?,
+                        created_at: row.get(16)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn update_commitment(
+        &self,
+        id: &str,
+        paid_sat: u64,
+        commitment_count: u32,
+        commitment_psbt: &str,
+    ) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE payment_channels SET paid_sat = ?2, commitment_count = ?3, commitment_psbt = ?4
+             WHERE id = ?1",
+            params![id, paid_sat as i64, commitment_count, commitment_psbt],
+        )?;
+        Ok(())
+    }
+
+    fn close_channel(&self, id: &str, state: &str, closing_txid: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE payment_channels SET state = ?2, closing_txid = ?3 WHERE id = ?1",
+            params![id, state, closing_txid],
+        )?;
+        Ok(())
+    }
+
+    fn set_miner(&self, wallet: &str, weight: u32) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO auto_miners (wallet, weight, registered_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (wallet) DO UPDATE SET weight = excluded.weight",
+            params![wallet, weight, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_miner(&self, wallet: &str) -> StorageResult<bool> {
+        let removed = self
+            .conn()
+            .execute("DELETE FROM auto_miners WHERE wallet = ?1", params![wallet])?;
+        Ok(removed > 0)
+    }
+
+    fn miners(&self) -> StorageResult<Vec<(String, u32)>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT wallet, weight FROM auto_miners ORDER BY registered_at, wallet")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn record_mined_block(
+        &self,
+        block_hash: &str,
+        height: u64,
+        wallet: &str,
+        source: &str,
+    ) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT OR IGNORE INTO mined_blocks (block_hash, height, wallet, source, mined_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![block_hash, height as i64, wallet, source, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn block_miner(&self, block_hash: &str) -> StorageResult<Option<String>> {
+        self.conn()
+            .query_row(
+                "SELECT wallet FROM mined_blocks WHERE block_hash = ?1",
+                params![block_hash],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn miner_stats(&self) -> StorageResult<Vec<MinerStats>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT wallet, COUNT(*), SUM(source = 'auto'), MAX(mined_at) FROM mined_blocks
+             GROUP BY wallet ORDER BY COUNT(*) DESC, MAX(mined_at), wallet",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(MinerStats {
+                wallet: row.get(0)?,
+                blocks: row.get::<_, i64>(1)? as u64,
+                auto_blocks: row.get::<_, i64>(2)? as u64,
+                last_mined_at: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn insert_multisig(&self, multisig: &MultisigWallet) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO multisig_wallets (wallet, descriptor, address, required, participants, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                multisig.wallet,
+                multisig.descriptor,
+                multisig.address,
+                multisig.required,
+                serde_json::to_string(&multisig.participants).unwrap_or_else(|_| "[]".into()),
+                multisig.created_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn multisig(&self, wallet: &str) -> StorageResult<Option<MultisigWallet>> {
+        self.conn()
+            .query_row(
+                "SELECT wallet, descriptor, address, required, participants, created_at
+                 FROM multisig_wallets WHERE wallet = ?1",
+                params![wallet],
+                |row| {
+                    Ok(MultisigWallet {
+                        wallet: row.get(0)?,
+                        descriptor: row.get(1)?,
+                        address: row.get(2)?,
+                        required: row.get(3)?,
+                        participants: serde_json::from_str(&row.get::<_, String>(4)?)
+                            .unwrap_or_default(),
+                        created_at: row.get(5)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn insert_invoice(&self, invoice: &Invoice) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO invoices (id, wallet, address, amount_sat, description, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                invoice.id,
+                invoice.wallet,
+                invoice.address,
+                invoice.amount_sat as i64,
+                invoice.description,
+                invoice.created_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn invoice(&self, wallet: &str, id: &str) -> StorageResult<Option<Invoice>> {
+        self.conn()
+            .query_row(
+                "SELECT id, wallet, address, amount_sat, description, created_at
+                 FROM invoices WHERE wallet = ?1 AND id = ?2",
+                params![wallet, id],
+                invoice,
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn invoices_page(
+        &self,
+        wallet: &str,
+        before: Option<&Cursor>,
+        limit: usize,
+    ) -> StorageResult<Vec<Invoice>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, wallet, address, amount_sat, description, created_at FROM invoices
+             WHERE wallet = ?1
+               AND (?2 IS NULL OR created_at < ?2 OR (created_at = ?2 AND id < ?3))
+             ORDER BY created_at DESC, id DESC LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                wallet,
+                before.map(|before| before.at),
+                before.map(|before| &before.id),
+                limit as i64
+            ],
+            invoice,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_confirmation_policy(
+        &self,
+        wallet: &str,
+        tiers: &[ConfirmationTier],
+    ) -> StorageResult<()> {
+        self.conn().execute(
+            "INSERT INTO confirmation_policies (wallet, tiers, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (wallet) DO UPDATE SET tiers = excluded.tiers, updated_at = excluded.updated_at",
+            params![
+                wallet,
+                serde_json::to_string(tiers).unwrap_or_else(|_| "[]".into()),
+                unix_now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_confirmation_policy(&self, wallet: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "DELETE FROM confirmation_policies WHERE wallet = ?1",
+            params![wallet],
+        )?;
+        Ok(())
+    }
+
+    fn confirmation_policy(&self, wallet: &str) -> StorageResult<Option<Vec<ConfirmationTier>>> {
+        let tiers: Option<String> = self
+            .conn()
+            .query_row(
+                "SELECT tiers FROM confirmation_policies WHERE wallet = ?1",
+                params![wallet],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(tiers.and_then(|tiers| serde_json::from_str(&tiers).ok()))
+    }
+
+    fn set_reservation(&self, wallet: &str, bucket: &str, amount_sat: u64) -> StorageResult<()> {
+        if amount_sat == 0 {
+            self.conn().execute(
+                "DELETE FROM wallet_reservations WHERE wallet = ?1 AND bucket = ?2",
+                params![wallet, bucket],
+            )?;
+            return Ok(());
+        }
+        self.conn().execute(
+            "INSERT INTO wallet_reservations (wallet, bucket, amount_sat, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (wallet, bucket) DO UPDATE SET amount_sat = excluded.amount_sat, updated_at = excluded.updated_at",
+            params![wallet, bucket, amount_sat as i64, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn release_reservation(
+        &self,
+        wallet: &str,
+        bucket: &str,
+        amount_sat: u64,
+    ) -> StorageResult<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE wallet_reservations SET amount_sat = MAX(amount_sat - ?3, 0), updated_at = ?4
+             WHERE wallet = ?1 AND bucket = ?2",
+            params![wallet, bucket, amount_sat as i64, unix_now()],
+        )?;
+        conn.execute(
+            "DELETE FROM wallet_reservations WHERE wallet = ?1 AND bucket = ?2 AND amount_sat = 0",
+            params![wallet, bucket],
+        )?;
+        Ok(())
+    }
+
+    fn reservations(&self, wallet: &str) -> StorageResult<Vec<Reservation>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT bucket, amount_sat, updated_at FROM wallet_reservations WHERE wallet = ?1 ORDER BY bucket",
+        )?;
+        let rows = stmt.query_map(params![wallet], |row| {
+            Ok(Reservation {
+                bucket: row.get(0)?,
+                amount_sat: row.get::<_, i64>(1)? as u64,
+                updated_at: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn record_block_sighting(
+        &self,
+        block_hash: &str,
+        node: &str,
+        seen_at_ms: i64,
+        source: &str,
+    ) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO block_sightings (block_hash, node, seen_at_ms, source)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![block_hash, node, seen_at_ms, source],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn block_sightings(&self, block_hash: &str) -> StorageResult<Vec<BlockSighting>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT node, seen_at_ms, source FROM block_sightings
+             WHERE block_hash = ?1 ORDER BY seen_at_ms, node",
+        )?;
+        let rows = stmt.query_map(params![block_hash], |row| {
+            Ok(BlockSighting {
+                node: row.get(0)?,
+                seen_at_ms: row.get(1)?,
+                source: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn enqueue_outbox(&self, entry: &OutboxEntry) -> StorageResult<OutboxEntry> {
+        let conn = self.conn();
+        conn.execute(
+            &format!(
+                "INSERT INTO outbox ({OUTBOX_COLUMNS})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                 ON CONFLICT (idempotency_key) DO NOTHING"
+            ),
+            params![
+                entry.id,
+                entry.wallet,
+                entry.to_address,
+                entry.amount_sat as i64,
+                entry.message,
+                entry.status,
+                entry.txid,
+                entry.attempts,
+                entry.last_error,
+                entry.idempotency_key,
+                entry.created_at,
+                entry.updated_at,
+                entry.tx_hex
+            ],
+        )?;
+        match &entry.idempotency_key {
+            Some(key) => Ok(conn.query_row(
+                &format!("SELECT {OUTBOX_COLUMNS} FROM outbox WHERE idempotency_key = ?1"),
+                params![key],
+                outbox_entry,
+            )?),
+            None => Ok(entry.clone()),
+        }
+    }
+
+    fn outbox_entries(&self, statuses: &[&str], limit: usize) -> StorageResult<Vec<OutboxEntry>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {OUTBOX_COLUMNS} FROM outbox
+             WHERE ?1 = '[]' OR status IN (SELECT value FROM json_each(?1))
+             ORDER BY created_at DESC, id LIMIT ?2"
+        ))?;
+        let statuses = serde_json::to_string(statuses).unwrap_or_else(|_| "[]".into());
+        let rows = stmt.query_map(params![statuses, limit as i64], outbox_entry)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn outbox_backlog(&self, statuses: &[&str], limit: usize) -> StorageResult<Vec<OutboxEntry>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {OUTBOX_COLUMNS} FROM outbox
+             WHERE status IN (SELECT value FROM json_each(?1))
+             ORDER BY created_at, id LIMIT ?2"
+        ))?;
+        let statuses = serde_json::to_string(statuses).unwrap_or_else(|_| "[]".into());
+        let rows = stmt.query_map(params![statuses, limit as i64], outbox_entry)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn sign_outbox(&self, id: &str, txid: &str, tx_hex: &str) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE outbox SET status = 'signed', txid = ?2, tx_hex = ?3, last_error = NULL,
+                 updated_at = ?4
+             WHERE id = ?1",
+            params![id, txid, tx_hex, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn update_outbox(
+        &self,
+        id: &str,
+        status: &str,
+        txid: Option<&str>,
+        last_error: Option<&str>,
+        attempted: bool,
+    ) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE outbox SET status = ?2, txid = COALESCE(?3, txid), last_error = ?4,
+                 attempts = attempts + ?5, updated_at = ?6
+             WHERE id = ?1",
+            params![id, status, txid, last_error, attempted as i64, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn insert_cohort(&self, cohort: &Cohort) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO cohorts (name, faucet_allowance_sat, created_at) VALUES (?1, ?2, ?3)",
+            params![cohort.name, cohort.faucet_allowance_sat as i64, cohort.created_at],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn cohort(&self, name: &str) -> StorageResult<Option<Cohort>> {
+        self.conn()
+            .query_row(
+                "SELECT name, faucet_allowance_sat, created_at FROM cohorts WHERE name = ?1",
+                params![name],
+                cohort,
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn cohorts(&self) -> StorageResult<Vec<Cohort>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT name, faucet_allowance_sat, created_at FROM cohorts ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map([], cohort)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn insert_cohort_student(&self, cohort: &str, student: &CohortStudent) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO cohort_students (cohort, student, wallet, faucet_used_sat, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                cohort,
+                student.student,
+                student.wallet,
+                student.faucet_used_sat as i64,
+                student.created_at
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn cohort_students(&self, cohort: &str) -> StorageResult<Vec<CohortStudent>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT student, wallet, faucet_used_sat, created_at FROM cohort_students
+             WHERE cohort = ?1 ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map(params![cohort], |row| {
+            Ok(CohortStudent {
+                student: row.get(0)?,
+                wallet: row.get(1)?,
+                faucet_used_sat: row.get::<_, i64>(2)? as u64,
+                created_at: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn charge_faucet_allowance(
+        &self,
+        cohort: &str,
+        student: &str,
+        amount_sat: i64,
+        allowance_sat: u64,
+    ) -> StorageResult<bool> {
+        let updated = self.conn().execute(
+            "UPDATE cohort_students SET faucet_used_sat = MAX(faucet_used_sat + ?3, 0)
+             WHERE cohort = ?1 AND student = ?2 AND faucet_used_sat + ?3 <= ?4",
+            params![cohort, student, amount_sat, allowance_sat as i64],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn insert_tx_watcher(&self, watcher: &TxWatcher) -> StorageResult<()> {
+        self.conn().execute(
+            &format!("INSERT INTO tx_watchers ({TX_WATCHER_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"),
+            params![
+                watcher.id,
+                watcher.wallet,
+                watcher.txid,
+                watcher.confirmations,
+                watcher.webhook_url,
+                watcher.status,
+                watcher.attempts,
+                watcher.last_error,
+                watcher.created_at,
+                watcher.notified_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn tx_watcher(&self, id: &str) -> StorageResult<Option<TxWatcher>> {
+        self.conn()
+            .query_row(
+                &format!("SELECT {TX_WATCHER_COLUMNS} FROM tx_watchers WHERE id = ?1"),
+                params![id],
+                tx_watcher,
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    fn tx_watchers(&self, statuses: &[&str]) -> StorageResult<Vec<TxWatcher>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {TX_WATCHER_COLUMNS} FROM tx_watchers
+             WHERE ?1 = '[]' OR status IN (SELECT value FROM json_each(?1))
+             ORDER BY created_at, rowid"
+        ))?;
+        let statuses = serde_json::to_string(statuses).unwrap_or_else(|_| "[]".into());
+        let rows = stmt.query_map(params![statuses], tx_watcher)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn tx_watchers_page(
+        &self,
+        statuses: &[&str],
+        wallet_prefix: &str,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> StorageResult<Vec<TxWatcher>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {TX_WATCHER_COLUMNS} FROM tx_watchers
+             WHERE (?1 = '[]' OR status IN (SELECT value FROM json_each(?1)))
+               AND substr(wallet, 1, length(?2)) = ?2
+               AND (?3 IS NULL OR created_at > ?3 OR (created_at = ?3 AND id > ?4))
+             ORDER BY created_at, id LIMIT ?5"
+        ))?;
+        let statuses = serde_json::to_string(statuses).unwrap_or_else(|_| "[]".into());
+        let rows = stmt.query_map(
+            params![
+                statuses,
+                wallet_prefix,
+                after.map(|after| after.at),
+                after.map(|after| &after.id),
+                limit as i64
+            ],
+            tx_watcher,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn update_tx_watcher(
+        &self,
+        id: &str,
+        status: &str,
+        last_error: Option<&str>,
+        attempted: bool,
+    ) -> StorageResult<()> {
+        self.conn().execute(
+            "UPDATE tx_watchers SET status = ?2, last_error = ?3, attempts = attempts + ?4,
+                 notified_at = CASE WHEN ?2 = 'notified' THEN ?5 ELSE notified_at END
+             WHERE id = ?1",
+            params![id, status, last_error, attempted as i64, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_tx_watcher(&self, id: &str) -> StorageResult<bool> {
+        let removed = self
+            .conn()
+            .execute("DELETE FROM tx_watchers WHERE id = ?1", params![id])?;
+        Ok(removed > 0)
+    }
+
+    fn insert_user(&self, user: &User) -> StorageResult<bool> {
+        let inserted = self.conn().execute(
+            "INSERT OR IGNORE INTO users (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+            params![user.username, user.password_hash, user.created_at],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn user(&self, username: &str) -> StorageResult<Option<User>> {
+        self.conn()
+            .query_row(
+                "SELECT username, password_hash, created_at FROM users WHERE username = ?1",
+                params![username],
+                |row| {
+                    Ok(User {
+                        username: row.get(0)?,
+                        password_hash: row.get(1)?,
+                        created_at: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(db: &SqliteStorage) -> usize {
+        db.conn()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_new_database_gets_every_migration_once() {
+        let db = SqliteStorage::open(":memory:").unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+        db.migrate().unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn an_older_database_is_migrated_in_place() {
+        // A database written before queued payments kept their signed transaction
+        let conn = Connection::open_in_memory().unwrap();
+        let previous = MIGRATIONS.len() - 1;
+        for migration in &MIGRATIONS[..previous] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", previous).unwrap();
+        conn.execute(
+            "INSERT INTO outbox (id, wallet, to_address, amount_sat, status, attempts, created_at,
+                 updated_at)
+             VALUES ('e1', 'alice', 'bob', 1000, 'queued', 0, 10, 10)",
+            [],
+        )
+        .unwrap();
+
+        let db = SqliteStorage {
+            conn: Mutex::new(conn),
+        };
+        db.migrate().unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+        let entries = db.outbox_entries(&[], 10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            (entries[0].id.as_str(), entries[0].amount_sat),
+            ("e1", 1000)
+        );
+        assert_eq!(entries[0].tx_hex, None);
+        db.sign_outbox("e1", "txid", "rawtx").unwrap();
+        let entries = db.outbox_entries(&["signed"], 10).unwrap();
+        assert_eq!(entries[0].tx_hex.as_deref(), Some("rawtx"));
+    }
+
+    #[test]
+    fn a_failed_migration_leaves_the_version_alone() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Taken already, so the first migration cannot create it
+        conn.execute_batch("CREATE TABLE address_labels (x)")
+            .unwrap();
+        let db = SqliteStorage {
+            conn: Mutex::new(conn),
+        };
+        assert!(db.migrate().is_err());
+        assert_eq!(user_version(&db), 0);
+    }
+}
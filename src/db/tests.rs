@@ -0,0 +1,684 @@
+// The Storage contract: every check runs against each backend, so MemoryStorage and
+// SqliteStorage cannot drift apart.
+
+use super::*;
+use serde_json::json;
+use std::panic::{self, AssertUnwindSafe};
+
+fn backends() -> Vec<(&'static str, Box<dyn Storage>)> {
+    vec![
+        ("memory", Box::new(MemoryStorage::default())),
+        ("sqlite", Box::new(SqliteStorage::open(":memory:").unwrap())),
+    ]
+}
+
+fn contract(check: impl Fn(&dyn Storage)) {
+    for (name, storage) in backends() {
+        if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| check(storage.as_ref()))) {
+            eprintln!("the {name} storage breaks the contract");
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+fn watched(address: &str, created_at: i64) -> WatchedAddress {
+    WatchedAddress {
+        address: address.to_string(),
+        label: Some(format!("{address} label")),
+        created_at,
+        scanned_height: 100,
+    }
+}
+
+fn output(txid: &str, vout: u32, block_height: u64) -> WatchedOutput {
+    WatchedOutput {
+        txid: txid.to_string(),
+        vout,
+        amount_sat: 1_000 + u64::from(vout),
+        block_hash: format!("block-{block_height}"),
+        block_height,
+    }
+}
+
+fn channel(id: &str) -> PaymentChannel {
+    PaymentChannel {
+        id: id.to_string(),
+        payer_wallet: "alice".into(),
+        payee_wallet: "bob".into(),
+        payer_address: "payer".into(),
+        payee_address: "payee".into(),
+        descriptor: "wsh(...)".into(),
+        funding_txid: "funding".into(),
+        funding_vout: 1,
+        capacity_sat: 100_000,
+        refund_locktime: 500,
+        refund_psbt: "refund".into(),
+        paid_sat: 0,
+        commitment_count: 0,
+        commitment_psbt: None,
+        state: "open".into(),
+        closing_txid: None,
+        created_at: 10,
+    }
+}
+
+fn invoice(id: &str, wallet: &str, created_at: i64) -> Invoice {
+    Invoice {
+        id: id.to_string(),
+        wallet: wallet.to_string(),
+        address: format!("address-{id}"),
+        amount_sat: 5_000,
+        description: Some(format!("invoice {id}")),
+        created_at,
+    }
+}
+
+fn outbox(id: &str, created_at: i64, idempotency_key: Option<&str>) -> OutboxEntry {
+    OutboxEntry {
+        id: id.to_string(),
+        wallet: "alice".into(),
+        to_address: "bob".into(),
+        amount_sat: 2_000,
+        message: None,
+        status: "queued".into(),
+        txid: None,
+        attempts: 0,
+        last_error: None,
+        idempotency_key: idempotency_key.map(str::to_string),
+        created_at,
+        updated_at: created_at,
+        tx_hex: None,
+    }
+}
+
+fn student(name: &str, created_at: i64) -> CohortStudent {
+    CohortStudent {
+        student: name.to_string(),
+        wallet: format!("spring-{name}"),
+        faucet_used_sat: 0,
+        created_at,
+    }
+}
+
+fn tx_watcher(id: &str, wallet: &str, created_at: i64) -> TxWatcher {
+    TxWatcher {
+        id: id.to_string(),
+        wallet: wallet.to_string(),
+        txid: format!("tx-{id}"),
+        confirmations: 3,
+        webhook_url: "https://example.com/hook".into(),
+        status: "pending".into(),
+        attempts: 0,
+        last_error: None,
+        created_at,
+        notified_at: None,
+    }
+}
+
+fn user(username: &str) -> User {
+    User {
+        username: username.to_string(),
+        password_hash: format!("$argon2id$hash-of-{username}"),
+        created_at: 10,
+    }
+}
+
+fn ids<T>(rows: &[T], id: impl Fn(&T) -> &str) -> Vec<String> {
+    rows.iter().map(|row| id(row).to_string()).collect()
+}
+
+#[test]
+fn labels_and_confirmations() {
+    contract(|db| {
+        db.set_label("alice", "addr", "rent").unwrap();
+        db.set_label("alice", "addr", "food").unwrap();
+
+        db.record_confirmation("alice", "tx1", "block-5", 5)
+            .unwrap();
+        db.record_confirmation("alice", "tx2", "block-7", 7)
+            .unwrap();
+        db.record_confirmation("alice", "tx3", "block-6", 6)
+            .unwrap();
+        db.record_confirmation("bob", "tx4", "block-8", 8).unwrap();
+        let heights: Vec<u64> = db
+            .confirmations("alice")
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, height)| height)
+            .collect();
+        assert_eq!(heights, [7, 6, 5]);
+
+        // A reorg moves the transaction to another block
+        db.record_confirmation("alice", "tx1", "block-9b", 9)
+            .unwrap();
+        let confirmations = db.confirmations("alice").unwrap();
+        assert_eq!(
+            confirmations[0],
+            ("tx1".to_string(), "block-9b".to_string(), 9)
+        );
+        assert_eq!(confirmations.len(), 3);
+        assert!(db.confirmations("carol").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn watch_list() {
+    contract(|db| {
+        assert!(db.watch_address(&watched("b", 20)).unwrap());
+        assert!(db.watch_address(&watched("a", 10)).unwrap());
+        assert!(db.watch_address(&watched("c", 20)).unwrap());
+        let mut again = watched("b", 30);
+        again.label = None;
+        assert!(!db.watch_address(&again).unwrap());
+
+        let list = db.watched_addresses().unwrap();
+        assert_eq!(ids(&list, |watched| &watched.address), ["a", "b", "c"]);
+        // The first entry stays
+        assert_eq!(list[1].label.as_deref(), Some("b label"));
+        assert_eq!((list[1].created_at, list[1].scanned_height), (20, 100));
+    });
+}
+
+#[test]
+fn watched_outputs() {
+    contract(|db| {
+        assert!(db.record_watched_output("a", &output("t1", 0, 5)).unwrap());
+        assert!(!db.record_watched_output("a", &output("t1", 0, 5)).unwrap());
+        assert!(db.record_watched_output("a", &output("t2", 1, 7)).unwrap());
+        assert!(db.record_watched_output("a", &output("t1", 1, 5)).unwrap());
+        assert!(db.record_watched_output("b", &output("t3", 0, 6)).unwrap());
+
+        let outputs = db.watched_outputs("a").unwrap();
+        let keys: Vec<(&str, u32)> = outputs
+            .iter()
+            .map(|output| (output.txid.as_str(), output.vout))
+            .collect();
+        assert_eq!(keys, [("t2", 1), ("t1", 0), ("t1", 1)]);
+        assert_eq!(outputs[0].amount_sat, 1_001);
+        assert_eq!(outputs[0].block_hash, "block-7");
+        assert!(db.watched_outputs("c").unwrap().is_empty());
+
+        let mut paid = db
+            .watched_outputs_of(&["t1".to_string(), "t3".to_string()])
+            .unwrap();
+        paid.sort_by(|(_, a), (_, b)| (&a.txid, a.vout).cmp(&(&b.txid, b.vout)));
+        let paid: Vec<(&str, &str, u32)> = paid
+            .iter()
+            .map(|(address, output)| (address.as_str(), output.txid.as_str(), output.vout))
+            .collect();
+        assert_eq!(paid, [("a", "t1", 0), ("a", "t1", 1), ("b", "t3", 0)]);
+        assert!(db.watched_outputs_of(&[]).unwrap().is_empty());
+    });
+}
+
+#[test]
+fn activity_pages_newest_first() {
+    contract(|db| {
+        for kind in ["first", "second", "third"] {
+            db.record_activity("alice", kind, &json!({ "kind": kind }))
+                .unwrap();
+        }
+        db.record_activity("bob", "other", &json!({})).unwrap();
+
+        let all = db.activity("alice", None, 10).unwrap();
+        assert_eq!(
+            ids(&all, |record| &record.kind),
+            ["third", "second", "first"]
+        );
+        assert_eq!(all[0].details, json!({ "kind": "third" }));
+
+        let page = db.activity("alice", None, 2).unwrap();
+        assert_eq!(ids(&page, |record| &record.kind), ["third", "second"]);
+        let last = &page[1];
+        let rest = db.activity("alice", Some((last.time, last.id)), 2).unwrap();
+        assert_eq!(ids(&rest, |record| &record.kind), ["first"]);
+    });
+}
+
+#[test]
+fn replacements() {
+    contract(|db| {
+        assert!(db.record_replacement("alice", "orig", "r1").unwrap());
+        assert!(!db.record_replacement("alice", "orig", "r1").unwrap());
+        assert!(db.record_replacement("alice", "orig", "r2").unwrap());
+
+        let replaced_by = db.replaced_by("orig").unwrap();
+        assert_eq!(ids(&replaced_by, |(txid, _)| txid), ["r1", "r2"]);
+        assert!(replaced_by[0].1 > 0);
+        assert!(db.replaced_by("r1").unwrap().is_empty());
+        assert_eq!(db.replaces("r2").unwrap().as_deref(), Some("orig"));
+        assert_eq!(db.replaces("orig").unwrap(), None);
+    });
+}
+
+#[test]
+fn registered_wallets_and_chain_cursors() {
+    contract(|db| {
+        db.register_wallet("alice").unwrap();
+        db.register_wallet("bob").unwrap();
+        db.register_wallet("alice").unwrap();
+        assert_eq!(db.registered_wallets().unwrap(), ["alice", "bob"]);
+        db.unregister_wallet("alice").unwrap();
+        db.unregister_wallet("carol").unwrap();
+        assert_eq!(db.registered_wallets().unwrap(), ["bob"]);
+
+        assert_eq!(db.chain_cursor("events").unwrap(), None);
+        db.set_chain_cursor("events", "hash1").unwrap();
+        db.set_chain_cursor("events", "hash2").unwrap();
+        db.set_chain_cursor("watch", "hash3").unwrap();
+        assert_eq!(db.chain_cursor("events").unwrap().as_deref(), Some("hash2"));
+        assert_eq!(db.chain_cursor("watch").unwrap().as_deref(), Some("hash3"));
+    });
+}
+
+#[test]
+fn payment_channels() {
+    contract(|db| {
+        db.insert_channel(&channel("ch1")).unwrap();
+        assert!(db.insert_channel(&channel("ch1")).is_err());
+        assert!(db.channel("ch2").unwrap().is_none());
+
+        let stored = db.channel("ch1").unwrap().unwrap();
+        assert_eq!(
+            (
+                stored.payer_wallet.as_str(),
+                stored.capacity_sat,
+                stored.refund_locktime
+            ),
+            ("alice", 100_000, 500)
+        );
+        assert_eq!(stored.commitment_psbt, None);
+
+        db.update_commitment("ch1", 2_500, 2, "commitment").unwrap();
+        db.close_channel("ch1", "closed", "closing").unwrap();
+        db.update_commitment("ch2", 1, 1, "nothing").unwrap();
+        let stored = db.channel("ch1").unwrap().unwrap();
+        assert_eq!((stored.paid_sat, stored.commitment_count), (2_500, 2));
+        assert_eq!(stored.commitment_psbt.as_deref(), Some("commitment"));
+        assert_eq!(stored.state, "closed");
+        assert_eq!(stored.closing_txid.as_deref(), Some("closing"));
+    });
+}
+
+#[test]
+fn miners_and_mined_blocks() {
+    contract(|db| {
+        db.set_miner("alice", 1).unwrap();
+        db.set_miner("bob", 2).unwrap();
+        db.set_miner("alice", 3).unwrap();
+        assert_eq!(
+            db.miners().unwrap(),
+            [("alice".to_string(), 3), ("bob".to_string(), 2)]
+        );
+        assert!(db.remove_miner("alice").unwrap());
+        assert!(!db.remove_miner("alice").unwrap());
+        assert_eq!(db.miners().unwrap(), [("bob".to_string(), 2)]);
+
+        db.record_mined_block("h1", 1, "bob", "auto").unwrap();
+        db.record_mined_block("h2", 2, "bob", "manual").unwrap();
+        db.record_mined_block("h3", 3, "carol", "auto").unwrap();
+        // The first record of a block stays
+        db.record_mined_block("h1", 1, "carol", "manual").unwrap();
+        assert_eq!(db.block_miner("h1").unwrap().as_deref(), Some("bob"));
+        assert_eq!(db.block_miner("h4").unwrap(), None);
+
+        let stats = db.miner_stats().unwrap();
+        let stats: Vec<(&str, u64, u64)> = stats
+            .iter()
+            .map(|stat| (stat.wallet.as_str(), stat.blocks, stat.auto_blocks))
+            .collect();
+        assert_eq!(stats, [("bob", 2, 1), ("carol", 1, 1)]);
+    });
+}
+
+#[test]
+fn multisig_wallets() {
+    contract(|db| {
+        let multisig = MultisigWallet {
+            wallet: "shared".into(),
+            descriptor: "wsh(multi(2,...))".into(),
+            address: "bcrt1q...".into(),
+            required: 2,
+            participants: vec![
+                MultisigParticipant {
+                    wallet: "alice".into(),
+                    public_key: "02aa".into(),
+                    key_origin: Some("[d34db33f/48h/1h/0h/2h]".into()),
+                },
+                MultisigParticipant {
+                    wallet: "bob".into(),
+                    public_key: "03bb".into(),
+                    key_origin: None,
+                },
+            ],
+            created_at: 10,
+        };
+        db.insert_multisig(&multisig).unwrap();
+        assert!(db.insert_multisig(&multisig).is_err());
+        assert!(db.multisig("alice").unwrap().is_none());
+
+        let stored = db.multisig("shared").unwrap().unwrap();
+        assert_eq!((stored.required, stored.created_at), (2, 10));
+        assert_eq!(ids(&stored.participants, |p| &p.wallet), ["alice", "bob"]);
+        assert_eq!(stored.participants[1].key_origin, None);
+    });
+}
+
+#[test]
+fn invoices_page_newest_first() {
+    contract(|db| {
+        db.insert_invoice(&invoice("a", "alice", 10)).unwrap();
+        db.insert_invoice(&invoice("b", "alice", 20)).unwrap();
+        db.insert_invoice(&invoice("c", "alice", 20)).unwrap();
+        db.insert_invoice(&invoice("d", "bob", 30)).unwrap();
+        assert!(db.insert_invoice(&invoice("a", "alice", 10)).is_err());
+
+        let stored = db.invoice("alice", "a").unwrap().unwrap();
+        assert_eq!((stored.amount_sat, stored.created_at), (5_000, 10));
+        assert_eq!(stored.description.as_deref(), Some("invoice a"));
+        assert!(db.invoice("bob", "a").unwrap().is_none());
+
+        let page = db.invoices_page("alice", None, 2).unwrap();
+        assert_eq!(ids(&page, |invoice| &invoice.id), ["c", "b"]);
+        let rest = db
+            .invoices_page("alice", Some(&Cursor::new(20, "b")), 2)
+            .unwrap();
+        assert_eq!(ids(&rest, |invoice| &invoice.id), ["a"]);
+    });
+}
+
+#[test]
+fn confirmation_policies() {
+    contract(|db| {
+        assert!(db.confirmation_policy("alice").unwrap().is_none());
+        let tiers = [
+            ConfirmationTier {
+                below_sat: Some(10_000),
+                confirmations: 0,
+            },
+            ConfirmationTier {
+                below_sat: None,
+                confirmations: 3,
+            },
+        ];
+        db.set_confirmation_policy("alice", &tiers).unwrap();
+        db.set_confirmation_policy("alice", &tiers[1..]).unwrap();
+        let stored = db.confirmation_policy("alice").unwrap().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!((stored[0].below_sat, stored[0].confirmations), (None, 3));
+
+        db.remove_confirmation_policy("alice").unwrap();
+        db.remove_confirmation_policy("bob").unwrap();
+        assert!(db.confirmation_policy("alice").unwrap().is_none());
+    });
+}
+
+#[test]
+fn reservations() {
+    contract(|db| {
+        db.set_reservation("alice", "rent", 100).unwrap();
+        db.set_reservation("alice", "food", 50).unwrap();
+        db.set_reservation("bob", "rent", 10).unwrap();
+        let amounts = |db: &dyn Storage| -> Vec<(String, u64)> {
+            db.reservations("alice")
+                .unwrap()
+                .into_iter()
+                .map(|reservation| (reservation.bucket, reservation.amount_sat))
+                .collect()
+        };
+        assert_eq!(
+            amounts(db),
+            [("food".to_string(), 50), ("rent".to_string(), 100)]
+        );
+
+        db.release_reservation("alice", "rent", 30).unwrap();
+        db.release_reservation("alice", "food", 80).unwrap();
+        db.release_reservation("alice", "travel", 5).unwrap();
+        assert_eq!(amounts(db), [("rent".to_string(), 70)]);
+        db.set_reservation("alice", "rent", 0).unwrap();
+        assert!(amounts(db).is_empty());
+        assert_eq!(db.reservations("bob").unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn block_sightings_keep_the_first() {
+    contract(|db| {
+        assert!(db.record_block_sighting("h", "n2", 200, "zmq").unwrap());
+        assert!(db.record_block_sighting("h", "n3", 100, "poll").unwrap());
+        assert!(db.record_block_sighting("h", "n1", 100, "poll").unwrap());
+        assert!(!db.record_block_sighting("h", "n2", 50, "poll").unwrap());
+        assert!(db.record_block_sighting("other", "n1", 10, "zmq").unwrap());
+
+        let sightings = db.block_sightings("h").unwrap();
+        let sightings: Vec<(&str, i64, &str)> = sightings
+            .iter()
+            .map(|s| (s.node.as_str(), s.seen_at_ms, s.source.as_str()))
+            .collect();
+        assert_eq!(
+            sightings,
+            [("n1", 100, "poll"), ("n3", 100, "poll"), ("n2", 200, "zmq")]
+        );
+        assert!(db.block_sightings("missing").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn outbox_queue() {
+    contract(|db| {
+        db.enqueue_outbox(&outbox("e1", 10, Some("k1"))).unwrap();
+        db.enqueue_outbox(&outbox("e2", 20, None)).unwrap();
+        db.enqueue_outbox(&outbox("e3", 30, Some("k3"))).unwrap();
+        // The same idempotency key gets the entry already queued
+        let queued = db.enqueue_outbox(&outbox("e4", 40, Some("k1"))).unwrap();
+        assert_eq!((queued.id.as_str(), queued.created_at), ("e1", 10));
+        assert!(db.enqueue_outbox(&outbox("e2", 50, None)).is_err());
+
+        let newest = db.outbox_entries(&[], 10).unwrap();
+        assert_eq!(ids(&newest, |entry| &entry.id), ["e3", "e2", "e1"]);
+        assert_eq!(db.outbox_entries(&[], 1).unwrap().len(), 1);
+
+        db.sign_outbox("e1", "txid1", "rawtx").unwrap();
+        let signed = db.outbox_entries(&["signed"], 10).unwrap();
+        assert_eq!(ids(&signed, |entry| &entry.id), ["e1"]);
+        assert_eq!(signed[0].txid.as_deref(), Some("txid1"));
+        assert_eq!(signed[0].tx_hex.as_deref(), Some("rawtx"));
+
+        let backlog = db.outbox_backlog(&["queued", "signed"], 10).unwrap();
+        assert_eq!(ids(&backlog, |entry| &entry.id), ["e1", "e2", "e3"]);
+        assert!(db.outbox_backlog(&[], 10).unwrap().is_empty());
+
+        db.update_outbox("e1", "failed", None, Some("no fee"), true)
+            .unwrap();
+        let failed = &db.outbox_entries(&["failed"], 10).unwrap()[0];
+        assert_eq!(failed.txid.as_deref(), Some("txid1"));
+        assert_eq!(failed.last_error.as_deref(), Some("no fee"));
+        assert_eq!(failed.attempts, 1);
+
+        db.update_outbox("e1", "broadcast", Some("txid2"), None, false)
+            .unwrap();
+        let sent = &db.outbox_entries(&["broadcast"], 10).unwrap()[0];
+        assert_eq!(sent.txid.as_deref(), Some("txid2"));
+        assert_eq!((sent.last_error.as_deref(), sent.attempts), (None, 1));
+        assert_eq!(sent.idempotency_key.as_deref(), Some("k1"));
+    });
+}
+
+#[test]
+fn cohorts_and_faucet_allowance() {
+    contract(|db| {
+        let cohort = |name: &str, created_at| Cohort {
+            name: name.to_string(),
+            faucet_allowance_sat: 100,
+            created_at,
+        };
+        assert!(db.insert_cohort(&cohort("spring", 20)).unwrap());
+        assert!(!db.insert_cohort(&cohort("spring", 30)).unwrap());
+        assert!(db.insert_cohort(&cohort("autumn", 10)).unwrap());
+        assert_eq!(
+            ids(&db.cohorts().unwrap(), |cohort| &cohort.name),
+            ["autumn", "spring"]
+        );
+        assert_eq!(db.cohort("spring").unwrap().unwrap().created_at, 20);
+        assert!(db.cohort("winter").unwrap().is_none());
+
+        assert!(db
+            .insert_cohort_student("spring", &student("zoe", 20))
+            .unwrap());
+        assert!(db
+            .insert_cohort_student("spring", &student("bob", 10))
+            .unwrap());
+        assert!(db
+            .insert_cohort_student("spring", &student("amy", 20))
+            .unwrap());
+        assert!(!db
+            .insert_cohort_student("spring", &student("zoe", 5))
+            .unwrap());
+        assert!(db
+            .insert_cohort_student("autumn", &student("zoe", 5))
+            .unwrap());
+        let students = db.cohort_students("spring").unwrap();
+        assert_eq!(ids(&students, |s| &s.student), ["bob", "zoe", "amy"]);
+        assert_eq!(students[1].wallet, "spring-zoe");
+
+        let used = |db: &dyn Storage| db.cohort_students("spring").unwrap()[1].faucet_used_sat;
+        assert!(db
+            .charge_faucet_allowance("spring", "zoe", 60, 100)
+            .unwrap());
+        assert!(!db
+            .charge_faucet_allowance("spring", "zoe", 50, 100)
+            .unwrap());
+        assert_eq!(used(db), 60);
+        assert!(db
+            .charge_faucet_allowance("spring", "zoe", 40, 100)
+            .unwrap());
+        assert_eq!(used(db), 100);
+        // Giving back more than was used stops at zero
+        assert!(db
+            .charge_faucet_allowance("spring", "zoe", -150, 100)
+            .unwrap());
+        assert_eq!(used(db), 0);
+        assert!(!db.charge_faucet_allowance("spring", "eve", 1, 100).unwrap());
+        assert_eq!(db.cohort_students("autumn").unwrap()[0].faucet_used_sat, 0);
+    });
+}
+
+#[test]
+fn tx_watchers() {
+    contract(|db| {
+        db.insert_tx_watcher(&tx_watcher("w1", "u-al-x", 20))
+            .unwrap();
+        db.insert_tx_watcher(&tx_watcher("w2", "u-al-y", 10))
+            .unwrap();
+        db.insert_tx_watcher(&tx_watcher("w3", "sb-z", 20)).unwrap();
+        db.insert_tx_watcher(&tx_watcher("w0", "u-al-z", 20))
+            .unwrap();
+        assert!(db
+            .insert_tx_watcher(&tx_watcher("w1", "u-al-x", 30))
+            .is_err());
+        assert_eq!(db.tx_watcher("w3").unwrap().unwrap().wallet, "sb-z");
+        assert!(db.tx_watcher("w9").unwrap().is_none());
+
+        let all = db.tx_watchers(&[]).unwrap();
+        assert_eq!(ids(&all, |w| &w.id), ["w2", "w1", "w3", "w0"]);
+
+        // Pages go by (created_at, id)
+        let page = db.tx_watchers_page(&[], "u-al-", None, 2).unwrap();
+        assert_eq!(ids(&page, |w| &w.id), ["w2", "w0"]);
+        let after = Cursor::new(20, "w0");
+        let rest = db.tx_watchers_page(&[], "u-al-", Some(&after), 2).unwrap();
+        assert_eq!(ids(&rest, |w| &w.id), ["w1"]);
+
+        db.update_tx_watcher("w1", "failing", Some("timeout"), true)
+            .unwrap();
+        let failing = db.tx_watcher("w1").unwrap().unwrap();
+        assert_eq!((failing.status.as_str(), failing.attempts), ("failing", 1));
+        assert_eq!(failing.last_error.as_deref(), Some("timeout"));
+        assert_eq!(failing.notified_at, None);
+
+        db.update_tx_watcher("w1", "notified", None, true).unwrap();
+        let notified = db.tx_watcher("w1").unwrap().unwrap();
+        assert_eq!((notified.attempts, notified.last_error), (2, None));
+        assert!(notified.notified_at.is_some());
+        let notified = db.tx_watchers(&["notified"]).unwrap();
+        assert_eq!(ids(&notified, |w| &w.id), ["w1"]);
+        let pending = db.tx_watchers_page(&["pending"], "", None, 10).unwrap();
+        assert_eq!(ids(&pending, |w| &w.id), ["w2", "w0", "w3"]);
+
+        assert!(db.remove_tx_watcher("w1").unwrap());
+        assert!(!db.remove_tx_watcher("w1").unwrap());
+        assert_eq!(db.tx_watchers(&[]).unwrap().len(), 3);
+    });
+}
+
+#[test]
+fn users() {
+    contract(|db| {
+        assert!(db.insert_user(&user("alice")).unwrap());
+        let mut again = user("alice");
+        again.password_hash = "other".into();
+        assert!(!db.insert_user(&again).unwrap());
+        let stored = db.user("alice").unwrap().unwrap();
+        assert_eq!(stored.password_hash, "$argon2id$hash-of-alice");
+        assert_eq!(stored.created_at, 10);
+        assert!(db.user("bob").unwrap().is_none());
+    });
+}
+
+#[test]
+fn reset_keeps_the_watch_list_and_users() {
+    contract(|db| {
+        db.set_label("alice", "addr", "rent").unwrap();
+        db.record_confirmation("alice", "tx", "block", 1).unwrap();
+        db.watch_address(&watched("a", 10)).unwrap();
+        db.record_watched_output("a", &output("t1", 0, 5)).unwrap();
+        db.record_activity("alice", "sent", &json!({})).unwrap();
+        db.record_replacement("alice", "orig", "r1").unwrap();
+        db.register_wallet("alice").unwrap();
+        db.set_chain_cursor("events", "hash").unwrap();
+        db.insert_channel(&channel("ch1")).unwrap();
+        db.set_miner("alice", 1).unwrap();
+        db.record_mined_block("h1", 1, "alice", "auto").unwrap();
+        db.insert_invoice(&invoice("i1", "alice", 10)).unwrap();
+        db.set_confirmation_policy("alice", &[]).unwrap();
+        db.set_reservation("alice", "rent", 10).unwrap();
+        db.record_block_sighting("h1", "n1", 1, "zmq").unwrap();
+        db.enqueue_outbox(&outbox("e1", 10, None)).unwrap();
+        db.insert_cohort(&Cohort {
+            name: "spring".into(),
+            faucet_allowance_sat: 1,
+            created_at: 1,
+        })
+        .unwrap();
+        db.insert_cohort_student("spring", &student("zoe", 1))
+            .unwrap();
+        db.insert_tx_watcher(&tx_watcher("w1", "alice", 1)).unwrap();
+        db.insert_user(&user("alice")).unwrap();
+
+        db.clear_chain_state().unwrap();
+
+        assert!(db.confirmations("alice").unwrap().is_empty());
+        assert!(db.watched_outputs("a").unwrap().is_empty());
+        assert!(db.activity("alice", None, 10).unwrap().is_empty());
+        assert!(db.replaces("r1").unwrap().is_none());
+        assert!(db.registered_wallets().unwrap().is_empty());
+        assert!(db.chain_cursor("events").unwrap().is_none());
+        assert!(db.channel("ch1").unwrap().is_none());
+        assert!(db.miners().unwrap().is_empty());
+        assert!(db.miner_stats().unwrap().is_empty());
+        assert!(db.invoice("alice", "i1").unwrap().is_none());
+        assert!(db.confirmation_policy("alice").unwrap().is_none());
+        assert!(db.reservations("alice").unwrap().is_empty());
+        assert!(db.block_sightings("h1").unwrap().is_empty());
+        assert!(db.outbox_entries(&[], 10).unwrap().is_empty());
+        assert!(db.cohorts().unwrap().is_empty());
+        assert!(db.cohort_students("spring").unwrap().is_empty());
+        assert!(db.tx_watchers(&[]).unwrap().is_empty());
+
+        // Watched addresses are scanned again from the start of the new chain
+        let list = db.watched_addresses().unwrap();
+        assert_eq!(ids(&list, |watched| &watched.address), ["a"]);
+        assert_eq!(list[0].scanned_height, 0);
+        assert!(db.user("alice").unwrap().is_some());
+    });
+}
@@ -0,0 +1,259 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_ADDRESS_LIMIT: usize = 10;
+const MAX_ADDRESS_LIMIT: usize = 100;
+// RPC_WALLET_ERROR: listdescriptors on a legacy wallet
+const RPC_WALLET_ERROR: i32 = -4;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DerivationQuery {
+    // Most recently handed out addresses shown per descriptor
+    addresses: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DerivedAddress {
+    index: u64,
+    address: String,
+    // Received coins at some point
+    used: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DescriptorState {
+    descriptor: String,
+    // receive or change
+    kind: &'static str,
+    script_type: &'static str,
+    // Path template of the addresses, e.g. m/84h/1h/0h/0/*
+    path: Option<String>,
+    active: bool,
+    ranged: bool,
+    // Index of the next address the wallet hands out
+    next_index: u64,
+    used_count: usize,
+    last_used_index: Option<u64>,
+    // Addresses handed out since the last used one; wallets restoring from a seed stop
+    // scanning after a long enough run of unused addresses
+    gap: u64,
+    // Addresses derived ahead of next_index, which the wallet already watches
+    lookahead: u64,
+    addresses: Vec<DerivedAddress>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DerivationResponse {
+    wallet: String,
+    descriptors: Vec<DescriptorState>,
+}
+
+fn script_type(descriptor: &str) -> &'static str {
+    if descriptor.starts_with("wpkh(") {
+        "p2wpkh"
+    } else if descriptor.starts_with("tr(") {
+        "p2tr"
+    } else if descriptor.starts_with("sh(wpkh(") {
+        "p2sh-p2wpkh"
+    } else if descriptor.starts_with("pkh(") {
+        "p2pkh"
+    } else if descriptor.starts_with("wsh(") {
+        "p2wsh"
+    } else {
+        "other"
+    }
+}
+
+// [d34db33f/84h/1h/0h]tpubD6.../0/* becomes m/84h/1h/0h/0/*
+fn path(descriptor: &str) -> Option<String> {
+    let start = descriptor.find('[')?;
+    let end = start + descriptor[start..].find(']')?;
+    let origin = descriptor[start + 1..end].split('/').skip(1);
+    let key = descriptor[end + 1..].split([')', ',']).next()?;
+    let steps: Vec<&str> = origin.chain(key.split('/').skip(1)).collect();
+    Some(format!("m/{}", steps.join("/")))
+}
+
+// Addresses that ever received coins, change included
+fn used_addresses(client: &Client) -> Result<HashSet<String>, RpcError> {
+    // listreceivedbyaddress minconf include_empty include_watchonly
+    let received: Vec<Value> = client.call(
+        "listreceivedbyaddress",
+        &[json!(0), json!(false), json!(true)],
+    )?;
+    let mut used: HashSet<String> = received
+        .iter()
+        .filter_map(|entry| entry["address"].as_str().map(str::to_string))
+        .collect();
+    // Groupings also list change addresses, which listreceivedbyaddress leaves out
+    let groupings: Vec<Vec<Vec<Value>>> = client.call("listaddressgroupings", &[])?;
+    used.extend(
+        groupings
+            .iter()
+            .flatten()
+            .filter_map(|entry| entry.first()?.as_str().map(str::to_string)),
+    );
+    Ok(used)
+}
+
+fn derive(
+    client: &Client,
+    descriptor: &str,
+    range: Option<(u64, u64)>,
+) -> Result<Vec<String>, RpcError> {
+    match range {
+        Some((start, end)) => {
+            client.call("deriveaddresses", &[json!(descriptor), json!([start, end])])
+        }
+        None => client.call("deriveaddresses", &[json!(descriptor)]),
+    }
+}
+
+fn descriptor_state(
+    client: &Client,
+    entry: &Value,
+    used: &HashSet<String>,
+    limit: usize,
+) -> Result<DescriptorState, RpcError> {
+    let descriptor = entry["desc"].as_str().unwrap_or_default().to_string();
+    let range = entry["range"]
+        .as_array()
+        .and_then(|range| Some((range.first()?.as_u64()?, range.get(1)?.as_u64()?)));
+    // Core 26 renamed `next` to `next_index`
+    let next_index = entry["next_index"]
+        .as_u64()
+        .or_else(|| entry["next"].as_u64())
+        .unwrap_or(if range.is_some() { 0 } else { 1 });
+
+    // Every handed out address is checked, the most recent `limit` are listed
+    let derived = match range {
+        Some(_) if next_index == 0 => Vec::new(),
+        Some(_) => derive(client, &descriptor, Some((0, next_index - 1)))?,
+        None => derive(client, &descriptor, None)?,
+    };
+    let used_indexes: Vec<u64> = derived
+        .iter()
+        .zip(0..)
+        .filter(|(address, _)| used.contains(*address))
+        .map(|(_, index)| index)
+        .collect();
+    let last_used_index = used_indexes.last().copied();
+    let first_listed = derived.len().saturating_sub(limit);
+    let addresses = derived
+        .into_iter()
+        .enumerate()
+        .skip(first_listed)
+        .map(|(index, address)| DerivedAddress {
+            index: index as u64,
+            used: used.contains(&address),
+            address,
+        })
+        .rev()
+        .collect();
+
+    Ok(DescriptorState {
+        kind: if entry["internal"].as_bool().unwrap_or(false) {
+            "change"
+        } else {
+            "receive"
+        },
+        script_type: script_type(&descriptor),
+        path: path(&descriptor),
+        active: entry["active"].as_bool().unwrap_or(false),
+        ranged: range.is_some(),
+        next_index,
+        used_count: used_indexes.len(),
+        last_used_index,
+        gap: match last_used_index {
+            Some(index) => next_index.saturating_sub(index + 1),
+            None => next_index,
+        },
+        lookahead: range.map_or(0, |(_, end)| (end + 1).saturating_sub(next_index)),
+        addresses,
+        descriptor,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/derivation",
+    tag = "derivation",
+    params(DerivationQuery),
+    responses(
+        (status = 200, body = DerivationResponse),
+    )
+)]
+pub(crate) async fn get_derivation(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    query: web::Query<DerivationQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = query
+        .addresses
+        .unwrap_or(DEFAULT_ADDRESS_LIMIT)
+        .min(MAX_ADDRESS_LIMIT);
+    info!("GET /wallet/{}/derivation - addresses={}", walletid, limit);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/derivation - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+
+    // The outer result is listdescriptors, the inner one inspecting what it listed
+    let inspected = rpc_pool::blocking(move || {
+        let listed: Value = client.call("listdescriptors", &[])?;
+        Ok(used_addresses(&client).and_then(|used| {
+            listed["descriptors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|entry| descriptor_state(&client, entry, &used, limit))
+                .collect::<Result<Vec<_>, _>>()
+        }))
+    })
+    .await?;
+    let descriptors = match inspected {
+        Ok(descriptors) => descriptors,
+        Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == RPC_WALLET_ERROR => {
+            return Err(ApiError::InvalidRequest(format!(
+                "Wallet '{}' is a legacy wallet without descriptors: {}",
+                walletid, e.message
+            )))
+        }
+        Err(e) => {
+            error!(
+                "Failed to list descriptors of wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    match descriptors {
+        Ok(mut descriptors) => {
+            // Active ones first, receive before change
+            descriptors.sort_by_key(|state| (!state.active, state.kind == "change"));
+            Ok(HttpResponse::Ok().json(DerivationResponse {
+                wallet: walletid.into_inner(),
+                descriptors,
+            }))
+        }
+        Err(e) => {
+            error!(
+                "Failed to inspect derivation of wallet '{}': {}",
+                wallet_name, e
+            );
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
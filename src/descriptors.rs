@@ -0,0 +1,188 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpResponse};
+use log::info;
+use miniscript::bitcoin::secp256k1::Secp256k1;
+use miniscript::descriptor::checksum::desc_checksum;
+use miniscript::Descriptor;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+const TOP_LEVEL_FUNCTIONS: &[&str] = &[
+    "sh",
+    "wsh",
+    "pk",
+    "pkh",
+    "wpkh",
+    "combo",
+    "multi",
+    "sortedmulti",
+    "multi_a",
+    "sortedmulti_a",
+    "tr",
+    "addr",
+    "raw",
+    "rawtr",
+];
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ValidateDescriptorRequest {
+    descriptor: String,
+}
+
+#[derive(Serialize, Default, ToSchema)]
+struct DescriptorReport {
+    valid: bool,
+    // Normalized by the node, with its checksum
+    descriptor: Option<String>,
+    checksum: Option<String>,
+    // Whether the checksum given with the input matched; null when none was given
+    checksum_matches: Option<bool>,
+    script_type: Option<String>,
+    // Contains wildcards, so it describes a range of scripts
+    is_range: Option<bool>,
+    // Enough is known to sign for it, given the private keys
+    is_solvable: Option<bool>,
+    has_private_keys: Option<bool>,
+    errors: Vec<String>,
+}
+
+// Script types the node knows but miniscript does not parse
+const NODE_ONLY_FUNCTIONS: &[&str] = &["combo", "addr", "raw", "rawtr"];
+
+// The eight character checksum that follows `#`
+fn checksum(descriptor: &str) -> Result<String, String> {
+    desc_checksum(descriptor).map_err(|e| e.to_string())
+}
+
+// Structural checks that give clearer messages than the node does
+fn check_locally(descriptor: &str, report: &mut DescriptorReport) {
+    let (body, given) = match descriptor.split_once('#') {
+        Some((body, given)) => (body, Some(given)),
+        None => (descriptor, None),
+    };
+    match checksum(body) {
+        Ok(expected) => {
+            if let Some(given) = given {
+                let matches = given == expected;
+                if !matches {
+                    report.errors.push(format!(
+                        "Checksum mismatch: got '{given}', the descriptor's checksum is '{expected}'"
+                    ));
+                }
+                report.checksum_matches = Some(matches);
+            }
+            report.checksum = Some(expected);
+        }
+        Err(e) => report.errors.push(e),
+    }
+
+    let function = body.split('(').next().unwrap_or_default();
+    if !TOP_LEVEL_FUNCTIONS.contains(&function) {
+        report.errors.push(format!(
+            "Unknown script type '{function}', expected one of: {}",
+            TOP_LEVEL_FUNCTIONS.join(", ")
+        ));
+        return;
+    }
+    report.script_type = Some(function.to_string());
+    // Keys, derivation paths and miniscript fragments; the rest is left to the node
+    if !NODE_ONLY_FUNCTIONS.contains(&function) {
+        if let Err(e) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), body) {
+            report.errors.push(e.to_string());
+        }
+    }
+}
+
+// Validates a descriptor before import: local checks first, then the node's parser
+#[utoipa::path(
+    post,
+    path = "/validate-descriptor",
+    tag = "descriptors",
+    responses(
+        (status = 200, body = DescriptorReport),
+    )
+)]
+pub(crate) async fn validate_descriptor(
+    data: web::Data<AppState>,
+    req: web::Json<ValidateDescriptorRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /validate-descriptor");
+    let descriptor = req.descriptor.trim();
+    let mut report = DescriptorReport::default();
+    check_locally(descriptor, &mut report);
+    // The node would only report the first of these problems, less clearly
+    if !report.errors.is_empty() {
+        return Ok(HttpResponse::Ok().json(report));
+    }
+
+    let checked =
+        rpc_pool::call_node::<Value>(&data, "getdescriptorinfo", vec![json!(descriptor)]).await?;
+    match checked {
+        Ok(info) => {
+            report.valid = true;
+            report.descriptor = info["descriptor"].as_str().map(str::to_string);
+            report.is_range = info["isrange"].as_bool();
+            report.is_solvable = info["issolvable"].as_bool();
+            report.has_private_keys = info["hasprivatekeys"].as_bool();
+        }
+        Err(e) => report.errors.push(e.to_string()),
+    }
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPRV_MULTI: &str = "sh(multi(2,[00000000/111'/222]xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc,xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L/0))";
+    const XPUB_MULTI: &str = "sh(multi(2,[00000000/111'/222]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL,xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0))";
+
+    fn report(descriptor: &str) -> DescriptorReport {
+        let mut report = DescriptorReport::default();
+        check_locally(descriptor, &mut report);
+        report
+    }
+
+    // Bitcoin Core's descriptor_tests and the BIP380 test vectors
+    #[test]
+    fn checksum_vectors() {
+        assert_eq!(checksum(XPRV_MULTI).unwrap(), "ggrsrxfy");
+        assert_eq!(checksum(XPUB_MULTI).unwrap(), "tjg09x5t");
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert!(checksum("raw(Ü)").is_err());
+    }
+
+    #[test]
+    fn reports_checksum_mismatch() {
+        let valid = report(&format!("{XPUB_MULTI}#tjg09x5t"));
+        assert!(valid.errors.is_empty(), "{:?}", valid.errors);
+        assert_eq!(valid.checksum_matches, Some(true));
+        assert_eq!(valid.script_type.as_deref(), Some("sh"));
+
+        for wrong in ["tjq09x4t", "tjg09x5", "tjg09x5tt", ""] {
+            let mismatch = report(&format!("{XPUB_MULTI}#{wrong}"));
+            assert_eq!(mismatch.checksum_matches, Some(false), "#{wrong}");
+            assert_eq!(mismatch.checksum.as_deref(), Some("tjg09x5t"));
+        }
+        assert_eq!(
+            report("raw(deadbeef)#89f8spxn").checksum_matches,
+            Some(false)
+        );
+        assert_eq!(report("raw(deadbeef)").checksum_matches, None);
+    }
+
+    #[test]
+    fn reports_structural_errors() {
+        assert!(report(XPRV_MULTI).errors.is_empty());
+        assert!(!report("wpkh(not-a-key)").errors.is_empty());
+        assert!(!report("wsh(multi(2,").errors.is_empty());
+        assert!(report("foo(bar)").script_type.is_none());
+        // Left to the node, which knows these script types
+        assert!(report("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)")
+            .errors
+            .is_empty());
+    }
+}
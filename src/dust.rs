@@ -0,0 +1,440 @@
+use crate::errors::ApiError;
+use crate::fees::FeeOptions;
+use crate::node::sat_per_vb;
+use crate::reservations::{self, ReservationOptions};
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount, Script};
+use bitcoincore_rpc::json::{AddressType, ListUnspentResultEntry};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+const DEFAULT_THRESHOLD_SAT: u64 = 1_000;
+// Above this it is no longer dust, just a payment
+const MAX_THRESHOLD_SAT: u64 = 100_000;
+// Used when the node cannot estimate yet, as on a fresh regtest chain
+const FALLBACK_FEE_RATE_SAT_VB: f64 = 1.0;
+const ESTIMATE_CONF_TARGET: u16 = 6;
+// Version, locktime, counts and the segwit marker
+const TX_OVERHEAD_VSIZE: u64 = 11;
+// Size of spending a witness output vs. a legacy one, as in Bitcoin Core's GetDustThreshold
+const WITNESS_SPEND_VSIZE: u64 = 67;
+const LEGACY_SPEND_VSIZE: u64 = 148;
+const DUST_RELAY_FEE_SAT_VB: u64 = 3;
+
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct SweepDustRequest {
+    // Outputs below this many sat count as dust
+    threshold_sat: Option<u64>,
+    // sat/vB; estimated for 6 blocks when missing
+    fee_rate: Option<f64>,
+    // Defaults to a new address of the wallet
+    to_address: Option<String>,
+    // Only report what a sweep would do
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(flatten)]
+    reservations: ReservationOptions,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Swept,
+    // dry_run: would be swept
+    Sweepable,
+    // Spending it costs more in fees than it is worth
+    Uneconomical,
+    // Worth spending on its own, but all sweepable dust together is below the dust limit
+    BatchTooSmall,
+    // Watch-only or otherwise not signable by the wallet
+    NotSpendable,
+    // An input whose size cannot be estimated, e.g. a P2WSH script
+    UnsupportedScript,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DustOutput {
+    txid: String,
+    vout: u32,
+    address: Option<String>,
+    amount_sat: u64,
+    script_type: &'static str,
+    input_vsize: Option<u64>,
+    // Fee this input adds at the chosen fee rate
+    spend_cost_sat: Option<u64>,
+    outcome: Outcome,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SweepStatus {
+    Swept,
+    DryRun,
+    NothingToSweep,
+    Uneconomical,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SweepResponse {
+    wallet: String,
+    threshold_sat: u64,
+    fee_rate_sat_vb: f64,
+    status: SweepStatus,
+    dust_count: usize,
+    dust_total_sat: u64,
+    to_address: Option<String>,
+    swept_count: usize,
+    swept_sat: u64,
+    // Estimated for dry runs, as paid otherwise
+    fee_sat: u64,
+    received_sat: u64,
+    txid: Option<String>,
+    message: String,
+    utxos: Vec<DustOutput>,
+}
+
+// (script type, vsize of an input spending it) for the scripts whose spend size is fixed
+fn input_vsize(script: &Script) -> (&'static str, Option<u64>) {
+    if script.is_p2wpkh() {
+        ("p2wpkh", Some(68))
+    } else if script.is_p2tr() {
+        // Key path spend with a default sighash signature
+        ("p2tr", Some(58))
+    } else if script.is_p2pkh() {
+        ("p2pkh", Some(148))
+    } else if script.is_p2sh() {
+        // Assumes P2SH-wrapped P2WPKH, the only P2SH script a wallet makes on its own
+        ("p2sh", Some(91))
+    } else if script.is_p2wsh() {
+        ("p2wsh", None)
+    } else {
+        ("other", None)
+    }
+}
+
+fn output_vsize(script: &Script) -> u64 {
+    // value, script length and script
+    8 + 1 + script.len() as u64
+}
+
+fn dust_limit(script: &Script) -> u64 {
+    let spend = if script.is_witness_program() {
+        WITNESS_SPEND_VSIZE
+    } else {
+        LEGACY_SPEND_VSIZE
+    };
+    (output_vsize(script) + spend) * DUST_RELAY_FEE_SAT_VB
+}
+
+fn fee_for(vsize: u64, fee_rate: f64) -> u64 {
+    (vsize as f64 * fee_rate).ceil() as u64
+}
+
+fn default_fee_rate(client: &Client) -> f64 {
+    match client.estimate_smart_fee(ESTIMATE_CONF_TARGET, None) {
+        Ok(estimate) => estimate
+            .fee_rate
+            .map(sat_per_vb)
+            .unwrap_or(FALLBACK_FEE_RATE_SAT_VB),
+        Err(e) => {
+            warn!("Fee estimation failed, using the fallback rate: {}", e);
+            FALLBACK_FEE_RATE_SAT_VB
+        }
+    }
+}
+
+fn classify(entry: &ListUnspentResultEntry, fee_rate: f64) -> DustOutput {
+    let (script_type, vsize) = input_vsize(&entry.script_pub_key);
+    let spend_cost_sat = vsize.map(|vsize| fee_for(vsize, fee_rate));
+    let amount_sat = entry.amount.to_sat();
+    let outcome = match spend_cost_sat {
+        _ if !entry.spendable => Outcome::NotSpendable,
+        None => Outcome::UnsupportedScript,
+        Some(cost) if cost >= amount_sat => Outcome::Uneconomical,
+        Some(_) => Outcome::Sweepable,
+    };
+    DustOutput {
+        txid: entry.txid.to_string(),
+        vout: entry.vout,
+        address: entry
+            .address
+            .clone()
+            .map(|address| address.assume_checked().to_string()),
+        amount_sat,
+        script_type,
+        input_vsize: vsize,
+        spend_cost_sat,
+        outcome,
+    }
+}
+
+// Spends exactly the given coins to one output, paying the fee from it
+fn sweep(
+    client: &Client,
+    inputs: &[Value],
+    to: &Address,
+    total: Amount,
+    fee_rate: f64,
+) -> Result<(String, u64), RpcError> {
+    let funded: Value = client.call(
+        "walletcreatefundedpsbt",
+        &[
+            json!(inputs),
+            json!([{ to.to_string(): total.to_btc() }]),
+            json!(0),
+            json!({
+                "add_inputs": false,
+                "fee_rate": fee_rate,
+                "subtractFeeFromOutputs": [0],
+            }),
+        ],
+    )?;
+    let psbt = funded["psbt"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError("walletcreatefundedpsbt returned no PSBT".into()))?;
+    let fee_sat = funded["fee"]
+        .as_f64()
+        .and_then(|btc| Amount::from_btc(btc).ok())
+        .map(Amount::to_sat)
+        .unwrap_or_default();
+
+    let processed: Value = client.call("walletprocesspsbt", &[json!(psbt), json!(true)])?;
+    let finalized: Value = client.call("finalizepsbt", &[processed["psbt"].clone()])?;
+    let hex = finalized["hex"].as_str().ok_or_else(|| {
+        RpcError::ReturnedError("the wallet could not sign every dust output".into())
+    })?;
+    let txid: String = client.call("sendrawtransaction", &[json!(hex)])?;
+    Ok((txid, fee_sat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/sweep-dust",
+    tag = "dust",
+    responses(
+        (status = 200, body = SweepResponse),
+    )
+)]
+pub(crate) async fn sweep_dust(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: Option<web::Json<SweepDustRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.map(web::Json::into_inner).unwrap_or_default();
+    let threshold_sat = req.threshold_sat.unwrap_or(DEFAULT_THRESHOLD_SAT);
+    info!(
+        "POST /wallet/{}/sweep-dust - threshold={} sat, fee_rate={:?}, dry_run={}",
+        walletid, threshold_sat, req.fee_rate, req.dry_run
+    );
+    if threshold_sat == 0 || threshold_sat > MAX_THRESHOLD_SAT {
+        return Err(ApiError::InvalidRequest(format!(
+            "threshold_sat must be between 1 and {}",
+            MAX_THRESHOLD_SAT
+        )));
+    }
+    FeeOptions {
+        fee_rate: req.fee_rate,
+        ..FeeOptions::default()
+    }
+    .validate()?;
+    let to_address = req
+        .to_address
+        .as_deref()
+        .map(|address| {
+            Address::from_str(address)
+                .map_err(|e| ApiError::InvalidAddress(format!("Invalid address: {}", e)))?
+                .require_network(data.config.network)
+                .map_err(|e| ApiError::InvalidAddress(format!("Invalid network: {}", e)))
+        })
+        .transpose()?;
+
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("POST /wallet/{}/sweep-dust - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let requested_fee_rate = req.fee_rate;
+    let (client, fee_rate, unspent) = rpc_pool::blocking(move || {
+        let fee_rate = requested_fee_rate.unwrap_or_else(|| default_fee_rate(&client));
+        // Confirmed coins only, so the sweep cannot be invalidated by a replaced parent
+        let unspent = client.list_unspent(Some(1), None, None, Some(true), None);
+        (client, fee_rate, unspent)
+    })
+    .await?;
+    let unspent = unspent.map_err(|e| {
+        error!("Failed to list UTXOs for wallet '{}': {}", wallet_name, e);
+        ApiError::rpc(&http_req, e)
+    })?;
+    let mut utxos: Vec<DustOutput> = unspent
+        .iter()
+        .filter(|entry| entry.amount.to_sat() < threshold_sat)
+        .map(|entry| classify(entry, fee_rate))
+        .collect();
+    utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.amount_sat));
+    let dust_count = utxos.len();
+    let dust_total_sat = utxos.iter().map(|utxo| utxo.amount_sat).sum();
+
+    let mut response = SweepResponse {
+        wallet: walletid.into_inner(),
+        threshold_sat,
+        fee_rate_sat_vb: fee_rate,
+        status: SweepStatus::NothingToSweep,
+        dust_count,
+        dust_total_sat,
+        to_address: None,
+        swept_count: 0,
+        swept_sat: 0,
+        fee_sat: 0,
+        received_sat: 0,
+        txid: None,
+        message: String::new(),
+        utxos: Vec::new(),
+    };
+    let sweepable: Vec<&DustOutput> = utxos
+        .iter()
+        .filter(|utxo| utxo.outcome == Outcome::Sweepable)
+        .collect();
+    if sweepable.is_empty() {
+        response.message = if dust_count == 0 {
+            format!("No outputs below {} sat", threshold_sat)
+        } else {
+            format!(
+                "None of the {} dust output(s) is worth spending at {} sat/vB",
+                dust_count, fee_rate
+            )
+        };
+        response.utxos = utxos;
+        return Ok(HttpResponse::Ok().json(response));
+    }
+
+    let (client, to_address) = match to_address {
+        Some(address) => (client, address),
+        None => {
+            let network = data.config.network;
+            let (client, generated) = rpc_pool::blocking(move || {
+                let generated = client
+                    .get_new_address(Some("dust sweep"), Some(AddressType::Bech32))
+                    .map_err(|e| e.to_string())
+                    .and_then(|address| {
+                        address.require_network(network).map_err(|e| e.to_string())
+                    });
+                (client, generated)
+            })
+            .await?;
+            let address = generated.map_err(|e| {
+                error!("Failed to get a sweep address: {}", e);
+                ApiError::Internal(e)
+            })?;
+            (client, address)
+        }
+    };
+    let script = to_address.script_pubkey();
+    let swept_sat: u64 = sweepable.iter().map(|utxo| utxo.amount_sat).sum();
+    let vsize = TX_OVERHEAD_VSIZE
+        + output_vsize(&script)
+        + sweepable
+            .iter()
+            .filter_map(|utxo| utxo.input_vsize)
+            .sum::<u64>();
+    let fee_sat = fee_for(vsize, fee_rate);
+    response.to_address = Some(to_address.to_string());
+    response.swept_count = sweepable.len();
+    response.swept_sat = swept_sat;
+    response.fee_sat = fee_sat;
+    response.received_sat = swept_sat.saturating_sub(fee_sat);
+
+    if response.received_sat < dust_limit(&script) {
+        response.status = SweepStatus::Uneconomical;
+        response.message = format!(
+            "Together the dust would leave {} sat after a {} sat fee, below the {} sat dust limit. Wait for lower fees.",
+            response.received_sat,
+            fee_sat,
+            dust_limit(&script)
+        );
+        response.swept_count = 0;
+        response.swept_sat = 0;
+        response.received_sat = 0;
+        for utxo in utxos.iter_mut() {
+            if utxo.outcome == Outcome::Sweepable {
+                utxo.outcome = Outcome::BatchTooSmall;
+            }
+        }
+        response.utxos = utxos;
+        return Ok(HttpResponse::Ok().json(response));
+    }
+
+    // Swept to the wallet's own address the coins stay; sent elsewhere they are spent
+    let swept_away = if req.to_address.is_some() {
+        swept_sat
+    } else {
+        0
+    };
+    reservations::check_spend(
+        &data,
+        &http_req,
+        &wallet_name,
+        swept_away,
+        &req.reservations,
+    )
+    .await?;
+
+    if req.dry_run {
+        response.status = SweepStatus::DryRun;
+        response.message = format!(
+            "Sweeping {} output(s) would consolidate {} sat into one output of about {} sat",
+            response.swept_count, swept_sat, response.received_sat
+        );
+        response.utxos = utxos;
+        return Ok(HttpResponse::Ok().json(response));
+    }
+
+    let inputs: Vec<Value> = sweepable
+        .iter()
+        .map(|utxo| json!({ "txid": utxo.txid, "vout": utxo.vout }))
+        .collect();
+    let swept = rpc_pool::blocking(move || {
+        sweep(
+            &client,
+            &inputs,
+            &to_address,
+            Amount::from_sat(swept_sat),
+            fee_rate,
+        )
+    })
+    .await?;
+    match swept {
+        Ok((txid, fee_sat)) => {
+            info!(
+                "Swept {} dust output(s) of wallet '{}' in {}",
+                response.swept_count, wallet_name, txid
+            );
+            reservations::consume(&data, &wallet_name, swept_away, &req.reservations);
+            response.status = SweepStatus::Swept;
+            response.fee_sat = fee_sat;
+            response.received_sat = swept_sat.saturating_sub(fee_sat);
+            response.message = format!(
+                "Consolidated {} output(s) into {} sat",
+                response.swept_count, response.received_sat
+            );
+            response.txid = Some(txid);
+            for utxo in utxos.iter_mut() {
+                if utxo.outcome == Outcome::Sweepable {
+                    utxo.outcome = Outcome::Swept;
+                }
+            }
+            response.utxos = utxos;
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            error!("Failed to sweep dust of wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
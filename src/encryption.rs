@@ -0,0 +1,275 @@
+use crate::errors::ApiError;
+use crate::rpc_pool::{self, Caller, PooledClient};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+const DEFAULT_UNLOCK_SECS: u64 = 300;
+// walletpassphrase caps the timeout at this many seconds
+const MAX_UNLOCK_SECS: u64 = 100_000_000;
+
+// Passphrases are never logged; the recorder redacts these fields too
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct EncryptRequest {
+    passphrase: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UnlockRequest {
+    passphrase: String,
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ChangePassphraseRequest {
+    passphrase: String,
+    new_passphrase: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct EncryptionStatus {
+    wallet: String,
+    encrypted: bool,
+    locked: bool,
+    // Unix time the wallet locks itself again
+    unlocked_until: Option<u64>,
+}
+
+fn status(client: &Client, wallet: &str) -> Result<EncryptionStatus, RpcError> {
+    // Unencrypted wallets have no unlocked_until; 0 means locked
+    let info: Value = client.call("getwalletinfo", &[])?;
+    let unlocked_until = info["unlocked_until"].as_u64();
+    Ok(EncryptionStatus {
+        wallet: wallet.to_string(),
+        encrypted: unlocked_until.is_some(),
+        locked: unlocked_until == Some(0),
+        unlocked_until: unlocked_until.filter(|until| *until > 0),
+    })
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), ApiError> {
+    if passphrase.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "Passphrase must not be empty".into(),
+        ));
+    }
+    Ok(())
+}
+
+// Looks up the wallet and its current encryption state, for the handlers below
+fn wallet_status(
+    data: &AppState,
+    caller: &Caller,
+    walletid: &str,
+    endpoint: &str,
+) -> Result<(PooledClient, EncryptionStatus), ApiError> {
+    let wallet_name = caller.wallet_name(walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("{} - wallet '{}' not found", endpoint, walletid);
+        return Err(ApiError::WalletNotFound(walletid.to_string()));
+    };
+    match status(&client, walletid) {
+        Ok(status) => Ok((client, status)),
+        Err(e) => {
+            error!(
+                "Failed to read encryption state of wallet '{}': {}",
+                wallet_name, e
+            );
+            Err(caller.rpc(e))
+        }
+    }
+}
+
+// The state after a change, for the response
+fn changed(caller: &Caller, client: &Client, walletid: &str) -> Result<EncryptionStatus, ApiError> {
+    status(client, walletid).map_err(|e| caller.rpc(e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/encryption",
+    tag = "encryption",
+    responses(
+        (status = 200, body = EncryptionStatus),
+    )
+)]
+pub(crate) async fn get_encryption(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/encryption", walletid);
+    let walletid = walletid.into_inner();
+    let status = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let (_, status) = wallet_status(data, caller, &walletid, "GET /wallet/encryption")?;
+        Ok(status)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+// Encrypts the private keys with the passphrase; the wallet is locked afterwards.
+// There is no way back to an unencrypted wallet.
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/encrypt",
+    tag = "encryption",
+    responses(
+        (status = 200, body = EncryptionStatus),
+    )
+)]
+pub(crate) async fn encrypt_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<EncryptRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/{}/encrypt", walletid);
+    check_passphrase(&req.passphrase)?;
+    let (walletid, req) = (walletid.into_inner(), req.into_inner());
+    let status = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let (client, status) = wallet_status(data, caller, &walletid, "POST /wallet/encrypt")?;
+        if status.encrypted {
+            return Err(ApiError::Conflict(format!(
+                "Wallet '{}' is already encrypted; change the passphrase with POST /wallet/{}/passphrase",
+                walletid, walletid
+            )));
+        }
+        if let Err(e) = client.encrypt_wallet(&req.passphrase) {
+            error!("Failed to encrypt wallet '{}': {}", walletid, e);
+            return Err(caller.rpc(e));
+        }
+        info!("Encrypted wallet '{}'", walletid);
+        changed(caller, &client, &walletid)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+// Keeps the keys in memory for `timeout_secs`, so the wallet can sign
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/unlock",
+    tag = "encryption",
+    responses(
+        (status = 200, body = EncryptionStatus),
+    )
+)]
+pub(crate) async fn unlock_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<UnlockRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let timeout = req.timeout_secs.unwrap_or(DEFAULT_UNLOCK_SECS);
+    info!(
+        "POST /wallet/{}/unlock - timeout_secs={}",
+        walletid, timeout
+    );
+    check_passphrase(&req.passphrase)?;
+    if !(1..=MAX_UNLOCK_SECS).contains(&timeout) {
+        return Err(ApiError::InvalidRequest(format!(
+            "timeout_secs must be between 1 and {}",
+            MAX_UNLOCK_SECS
+        )));
+    }
+    let (walletid, req) = (walletid.into_inner(), req.into_inner());
+    let status = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let (client, status) = wallet_status(data, caller, &walletid, "POST /wallet/unlock")?;
+        if !status.encrypted {
+            return Err(ApiError::Conflict(format!(
+                "Wallet '{}' is not encrypted",
+                walletid
+            )));
+        }
+        if let Err(e) =
+            client.call::<Value>("walletpassphrase", &[json!(req.passphrase), json!(timeout)])
+        {
+            warn!("Failed to unlock wallet '{}': {}", walletid, e);
+            return Err(caller.rpc(e));
+        }
+        info!("Unlocked wallet '{}' for {} s", walletid, timeout);
+        changed(caller, &client, &walletid)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/lock",
+    tag = "encryption",
+    responses(
+        (status = 200, body = EncryptionStatus),
+    )
+)]
+pub(crate) async fn lock_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/{}/lock", walletid);
+    let walletid = walletid.into_inner();
+    let status = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let (client, status) = wallet_status(data, caller, &walletid, "POST /wallet/lock")?;
+        if !status.encrypted {
+            return Err(ApiError::Conflict(format!(
+                "Wallet '{}' is not encrypted",
+                walletid
+            )));
+        }
+        if let Err(e) = client.call::<Value>("walletlock", &[]) {
+            error!("Failed to lock wallet '{}': {}", walletid, e);
+            return Err(caller.rpc(e));
+        }
+        changed(caller, &client, &walletid)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/passphrase",
+    tag = "encryption",
+    responses(
+        (status = 200, body = EncryptionStatus),
+    )
+)]
+pub(crate) async fn change_passphrase(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<ChangePassphraseRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/{}/passphrase", walletid);
+    check_passphrase(&req.new_passphrase)?;
+    let (walletid, req) = (walletid.into_inner(), req.into_inner());
+    let status = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let (client, status) = wallet_status(data, caller, &walletid, "POST /wallet/passphrase")?;
+        if !status.encrypted {
+            return Err(ApiError::Conflict(format!(
+                "Wallet '{}' is not encrypted; encrypt it with POST /wallet/{}/encrypt",
+                walletid, walletid
+            )));
+        }
+        if let Err(e) = client.call::<Value>(
+            "walletpassphrasechange",
+            &[json!(req.passphrase), json!(req.new_passphrase)],
+        ) {
+            warn!(
+                "Failed to change the passphrase of wallet '{}': {}",
+                walletid, e
+            );
+            return Err(caller.rpc(e));
+        }
+        info!("Changed the passphrase of wallet '{}'", walletid);
+        changed(caller, &client, &walletid)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(status))
+}
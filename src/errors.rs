@@ -0,0 +1,759 @@
+use crate::db::StorageError;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::Error as RpcError;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Language {
+    En,
+    Pl,
+}
+
+impl Language {
+    fn tag(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Pl => "pl",
+        }
+    }
+
+    // Picks the supported language with the highest q-value in Accept-Language,
+    // falling back to English
+    pub(crate) fn from_request(http_req: &HttpRequest) -> Self {
+        Self::from_accept_language(
+            http_req
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    fn from_accept_language(accept: Option<&str>) -> Self {
+        let Some(accept) = accept else {
+            return Language::En;
+        };
+
+        let mut best = (Language::En, 0.0);
+        for entry in accept.split(',') {
+            let mut parts = entry.trim().split(';');
+            let tag = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let language = match tag.split('-').next() {
+                Some("en") => Language::En,
+                Some("pl") => Language::Pl,
+                _ => continue,
+            };
+            if quality > best.1 {
+                best = (language, quality);
+            }
+        }
+        best.0
+    }
+}
+
+struct Translation {
+    rpc_code: i32,
+    // Narrows codes that Bitcoin Core reuses for unrelated failures
+    message_contains: Option<&'static str>,
+    en: (&'static str, &'static str),
+    pl: (&'static str, &'static str),
+}
+
+// Checked in order, so the specific -26 entries come before the generic one
+const TRANSLATIONS: &[Translation] = &[
+    Translation {
+        rpc_code: -6,
+        message_contains: None,
+        en: (
+            "The wallet does not have enough spendable coins for this payment.",
+            "Mined coins can only be spent after 100 confirmations. Mine 100 more blocks first, or send a smaller amount.",
+        ),
+        pl: (
+            "Portfel nie ma wystarczającej ilości monet do wydania na tę płatność.",
+            "Wykopane monety można wydać dopiero po 100 potwierdzeniach. Najpierw wykop 100 kolejnych bloków albo wyślij mniejszą kwotę.",
+        ),
+    },
+    Translation {
+        rpc_code: -26,
+        message_contains: Some("dust"),
+        en: (
+            "The amount is too small to be relayed (dust).",
+            "Send at least a few hundred satoshis; outputs below the dust limit are rejected by the network.",
+        ),
+        pl: (
+            "Kwota jest zbyt mała, aby sieć ją przekazała (dust).",
+            "Wyślij co najmniej kilkaset satoshi; wyjścia poniżej limitu dust są odrzucane przez sieć.",
+        ),
+    },
+    Translation {
+        rpc_code: -26,
+        message_contains: Some("fee"),
+        en: (
+            "The transaction fee is too low for the node to accept it.",
+            "Pay a higher fee rate; check GET /node/policy for the node's minimum relay fee.",
+        ),
+        pl: (
+            "Opłata transakcyjna jest zbyt niska, aby węzeł ją przyjął.",
+            "Zapłać wyższą stawkę opłaty; minimalną opłatę węzła sprawdzisz w GET /node/policy.",
+        ),
+    },
+    Translation {
+        rpc_code: -26,
+        message_contains: None,
+        en: (
+            "The node rejected the transaction.",
+            "The transaction breaks a relay policy rule; the original node message below explains which one.",
+        ),
+        pl: (
+            "Węzeł odrzucił transakcję.",
+            "Transakcja narusza regułę przekazywania; oryginalny komunikat węzła poniżej wyjaśnia którą.",
+        ),
+    },
+    Translation {
+        rpc_code: -25,
+        message_contains: None,
+        en: (
+            "The transaction spends coins that do not exist or are already spent.",
+            "Another transaction may have spent the same coins. Refresh the wallet balance and try again.",
+        ),
+        pl: (
+            "Transakcja wydaje monety, które nie istnieją lub zostały już wydane.",
+            "Inna transakcja mogła wydać te same monety. Odśwież saldo portfela i spróbuj ponownie.",
+        ),
+    },
+    Translation {
+        rpc_code: -12,
+        message_contains: None,
+        en: (
+            "The wallet has run out of pre-generated keys for new addresses.",
+            "Refill the keypool with POST /wallet/{walletid}/keypool/refill; unlock the wallet first if it is encrypted.",
+        ),
+        pl: (
+            "W portfelu skończyły się wcześniej wygenerowane klucze dla nowych adresów.",
+            "Uzupełnij pulę kluczy przez POST /wallet/{walletid}/keypool/refill; jeśli portfel jest zaszyfrowany, najpierw go odblokuj.",
+        ),
+    },
+    Translation {
+        rpc_code: -13,
+        message_contains: None,
+        en: (
+            "The wallet is encrypted and locked, so it cannot sign.",
+            "Unlock it with POST /wallet/{walletid}/unlock and its passphrase, then try again.",
+        ),
+        pl: (
+            "Portfel jest zaszyfrowany i zablokowany, więc nie może podpisywać.",
+            "Odblokuj go przez POST /wallet/{walletid}/unlock, podając hasło, i spróbuj ponownie.",
+        ),
+    },
+    Translation {
+        rpc_code: -14,
+        message_contains: None,
+        en: (
+            "The wallet passphrase is wrong.",
+            "Check the passphrase; it is case-sensitive and cannot be recovered if lost.",
+        ),
+        pl: (
+            "Hasło portfela jest nieprawidłowe.",
+            "Sprawdź hasło; wielkość liter ma znaczenie, a zgubionego hasła nie da się odzyskać.",
+        ),
+    },
+    Translation {
+        rpc_code: -4,
+        message_contains: None,
+        en: (
+            "The wallet is busy or could not complete the operation.",
+            "Wait a moment and retry; the wallet may still be rescanning or loading.",
+        ),
+        pl: (
+            "Portfel jest zajęty lub nie mógł dokończyć operacji.",
+            "Odczekaj chwilę i spróbuj ponownie; portfel może wciąż się skanować lub wczytywać.",
+        ),
+    },
+];
+
+// What to do about an error, for every code an error response can have
+struct CodeHint {
+    code: &'static str,
+    en: &'static str,
+    pl: &'static str,
+}
+
+const CODE_HINTS: &[CodeHint] = &[
+    CodeHint {
+        code: "WALLET_NOT_FOUND",
+        en: "Check the wallet name, or create the wallet with POST /wallet first.",
+        pl: "Sprawdź nazwę portfela albo najpierw utwórz portfel przez POST /wallet.",
+    },
+    CodeHint {
+        code: "NOT_FOUND",
+        en: "Check the path and the ids in it.",
+        pl: "Sprawdź ścieżkę i zawarte w niej identyfikatory.",
+    },
+    CodeHint {
+        code: "UNAUTHORIZED",
+        en: "Log in and send the token in the Authorization header.",
+        pl: "Zaloguj się i wyślij token w nagłówku Authorization.",
+    },
+    CodeHint {
+        code: "FORBIDDEN",
+        en: "This needs an admin account or the admin token.",
+        pl: "To wymaga konta administratora lub tokenu administratora.",
+    },
+    CodeHint {
+        code: "INVALID_ADDRESS",
+        en: "Check the address, and that it is for the node's network.",
+        pl: "Sprawdź adres i czy jest przeznaczony dla sieci węzła.",
+    },
+    CodeHint {
+        code: "INVALID_TXID",
+        en: "A transaction id is 64 hexadecimal characters.",
+        pl: "Identyfikator transakcji ma 64 znaki szesnastkowe.",
+    },
+    CodeHint {
+        code: "INVALID_REQUEST",
+        en: "Check the request body and parameters against the API documentation.",
+        pl: "Porównaj treść i parametry żądania z dokumentacją API.",
+    },
+    CodeHint {
+        code: "CONFLICT",
+        en: "It already exists or is in another state; fetch it and try again.",
+        pl: "To już istnieje lub jest w innym stanie; pobierz to i spróbuj ponownie.",
+    },
+    CodeHint {
+        code: "TOO_MANY_REQUESTS",
+        en: "Slow down and try again later.",
+        pl: "Zwolnij i spróbuj ponownie później.",
+    },
+    CodeHint {
+        code: "DEVICE_ERROR",
+        en: "Check that the hardware wallet is connected and unlocked, and confirm on the device.",
+        pl: "Sprawdź, czy portfel sprzętowy jest podłączony i odblokowany, i potwierdź na urządzeniu.",
+    },
+    CodeHint {
+        code: "UNSUPPORTED_ON_NETWORK",
+        en: "This is not available on the node's network, e.g. mining outside regtest.",
+        pl: "To nie jest dostępne w sieci węzła, np. kopanie poza regtestem.",
+    },
+    CodeHint {
+        code: "STORAGE_ERROR",
+        en: "The server's database failed; check the server logs.",
+        pl: "Baza danych serwera zawiodła; sprawdź logi serwera.",
+    },
+    CodeHint {
+        code: "INTERNAL_ERROR",
+        en: "Something went wrong on the server; check the server logs.",
+        pl: "Coś poszło nie tak po stronie serwera; sprawdź logi serwera.",
+    },
+    CodeHint {
+        code: "INSUFFICIENT_FUNDS",
+        en: "Fund the wallet, e.g. with POST /faucet, or send less.",
+        pl: "Zasil portfel, np. przez POST /faucet, albo wyślij mniej.",
+    },
+    CodeHint {
+        code: "INVALID_ADDRESS_OR_KEY",
+        en: "The node does not know this address, key, transaction or block.",
+        pl: "Węzeł nie zna tego adresu, klucza, transakcji ani bloku.",
+    },
+    CodeHint {
+        code: "INVALID_PARAMETER",
+        en: "The node refused a parameter; check the values sent.",
+        pl: "Węzeł odrzucił parametr; sprawdź wysłane wartości.",
+    },
+    CodeHint {
+        code: "TRANSACTION_REJECTED",
+        en: "The node did not accept the transaction; rpc_message has the reason.",
+        pl: "Węzeł nie przyjął transakcji; powód jest w rpc_message.",
+    },
+    CodeHint {
+        code: "WALLET_LOCKED",
+        en: "Unlock the wallet with its passphrase first.",
+        pl: "Najpierw odblokuj portfel hasłem.",
+    },
+    CodeHint {
+        code: "KEYPOOL_EXHAUSTED",
+        en: "Unlock the wallet so it can refill its keypool.",
+        pl: "Odblokuj portfel, aby mógł uzupełnić pulę kluczy.",
+    },
+    CodeHint {
+        code: "WALLET_ERROR",
+        en: "Wait a moment and try again.",
+        pl: "Odczekaj chwilę i spróbuj ponownie.",
+    },
+    CodeHint {
+        code: "RPC_UNAVAILABLE",
+        en: "bitcoind cannot be reached or is still starting; try again in a moment.",
+        pl: "Nie można połączyć się z bitcoind albo wciąż się uruchamia; spróbuj za chwilę.",
+    },
+    CodeHint {
+        code: "RPC_ERROR",
+        en: "rpc_message has the node's error.",
+        pl: "Błąd węzła jest w rpc_message.",
+    },
+];
+
+// Rebuilds API errors in the caller's Accept-Language, also the ones from middleware
+pub(crate) async fn localize_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let language = Language::from_request(req.request());
+    let res = match next.call(req).await {
+        Ok(res) => res.map_into_boxed_body(),
+        Err(e) if language == Language::En => return Err(e),
+        // Errors from middleware have no request to answer with yet
+        Err(e) => {
+            let Some(response) = e
+                .as_error::<ApiError>()
+                .map(|error| error.localized_response(language))
+            else {
+                return Err(e);
+            };
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+    if language == Language::En {
+        return Ok(res);
+    }
+    let Some(response) = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<ApiError>())
+        .map(|error| error.localized_response(language))
+    else {
+        return Ok(res);
+    };
+    Ok(res.into_response(response))
+}
+
+fn code_hint(code: &str) -> Option<&'static CodeHint> {
+    CODE_HINTS.iter().find(|hint| hint.code == code)
+}
+
+fn find_translation(code: i32, message: &str) -> Option<&'static Translation> {
+    let message = message.to_ascii_lowercase();
+    TRANSLATIONS.iter().find(|translation| {
+        translation.rpc_code == code
+            && translation
+                .message_contains
+                .is_none_or(|needle| message.contains(needle))
+    })
+}
+
+// Every handler error, with a machine-readable code clients can branch on
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    WalletNotFound(String),
+    NotFound(String),
+    // Missing or unknown access token
+    Unauthorized(String),
+    // Authenticated, but not allowed to do this, e.g. a non-admin on an admin route
+    Forbidden(String),
+    InvalidAddress(String),
+    InvalidTxid(String),
+    InvalidRequest(String),
+    Conflict(String),
+    TooManyRequests(String),
+    // A rate limit was hit; the client may try again after the wait
+    RateLimited { retry_after: Duration },
+    // The hardware wallet, reached through HWI, failed or refused
+    Device(String),
+    // The operation only makes sense on another network, e.g. mining outside regtest
+    UnsupportedOnNetwork(String),
+    // Bitcoin Core answered with an error, or could not be reached
+    Rpc { error: RpcError, language: Language },
+    Storage(String),
+    Internal(String),
+}
+
+// Body of every error response
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc_message: Option<&'a str>,
+}
+
+impl ApiError {
+    // Keeps the caller's language so known node errors can be explained in it
+    pub(crate) fn rpc(http_req: &HttpRequest, error: RpcError) -> Self {
+        ApiError::Rpc {
+            error,
+            language: Language::from_request(http_req),
+        }
+    }
+
+    // For lookups: -5 (no such transaction or block) and -18 (no such wallet) mean the
+    // thing is not there, any other failure is reported as the node error it is
+    pub(crate) fn rpc_not_found(http_req: &HttpRequest, error: RpcError) -> Self {
+        match &error {
+            RpcError::JsonRpc(JsonRpcError::Rpc(rpc)) if matches!(rpc.code, -5 | -18) => {
+                ApiError::NotFound(error.to_string())
+            }
+            _ => ApiError::rpc(http_req, error),
+        }
+    }
+
+    fn rpc_code(&self) -> Option<(i32, &str)> {
+        match self {
+            ApiError::Rpc {
+                error: RpcError::JsonRpc(JsonRpcError::Rpc(rpc)),
+                ..
+            } => Some((rpc.code, &rpc.message)),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::WalletNotFound(_) => "WALLET_NOT_FOUND",
+            ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+            ApiError::InvalidAddress(_) => "INVALID_ADDRESS",
+            ApiError::InvalidTxid(_) => "INVALID_TXID",
+            ApiError::InvalidRequest(_) => "INVALID_REQUEST",
+            ApiError::Conflict(_) => "CONFLICT",
+            ApiError::TooManyRequests(_) | ApiError::RateLimited { .. } => "TOO_MANY_REQUESTS",
+            ApiError::Device(_) => "DEVICE_ERROR",
+            ApiError::UnsupportedOnNetwork(_) => "UNSUPPORTED_ON_NETWORK",
+            ApiError::Storage(_) => "STORAGE_ERROR",
+            ApiError::Internal(_) => "INTERNAL_ERROR",
+            ApiError::Rpc { error, .. } => match self.rpc_code() {
+                // Codes from Bitcoin Core's rpc/protocol.h
+                Some((-6, _)) => "INSUFFICIENT_FUNDS",
+                Some((-18, _)) => "WALLET_NOT_FOUND",
+                Some((-5, _)) => "INVALID_ADDRESS_OR_KEY",
+                Some((-3 | -8 | -22, _)) => "INVALID_PARAMETER",
+                Some((-27..=-25, _)) => "TRANSACTION_REJECTED",
+                Some((-13 | -14, _)) => "WALLET_LOCKED",
+                Some((-12, _)) => "KEYPOOL_EXHAUSTED",
+                Some((-4, _)) => "WALLET_ERROR",
+                Some((-28, _)) => "RPC_UNAVAILABLE",
+                Some(_) => "RPC_ERROR",
+                None => match error {
+                    RpcError::JsonRpc(JsonRpcError::Transport(_)) | RpcError::Io(_) => {
+                        "RPC_UNAVAILABLE"
+                    }
+                    _ => "RPC_ERROR",
+                },
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::WalletNotFound(wallet) => write!(f, "No such wallet: {wallet}"),
+            ApiError::NotFound(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::InvalidAddress(message)
+            | ApiError::InvalidTxid(message)
+            | ApiError::InvalidRequest(message)
+            | ApiError::Conflict(message)
+            | ApiError::TooManyRequests(message)
+            | ApiError::Device(message)
+            | ApiError::UnsupportedOnNetwork(message)
+            | ApiError::Storage(message)
+            | ApiError::Internal(message) => f.write_str(message),
+            ApiError::Rpc { error, .. } => write!(f, "{error}"),
+            ApiError::RateLimited { retry_after } => write!(
+                f,
+                "Rate limit exceeded, try again in {} s",
+                retry_after_secs(*retry_after)
+            ),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self.code() {
+            "WALLET_NOT_FOUND" | "NOT_FOUND" => StatusCode::NOT_FOUND,
+            "INVALID_ADDRESS"
+            | "INVALID_TXID"
+            | "INVALID_REQUEST"
+            | "INSUFFICIENT_FUNDS"
+            | "INVALID_ADDRESS_OR_KEY"
+            | "INVALID_PARAMETER"
+            | "TRANSACTION_REJECTED" => StatusCode::BAD_REQUEST,
+            "CONFLICT" | "UNSUPPORTED_ON_NETWORK" | "KEYPOOL_EXHAUSTED" => StatusCode::CONFLICT,
+            "TOO_MANY_REQUESTS" => StatusCode::TOO_MANY_REQUESTS,
+            "UNAUTHORIZED" => StatusCode::UNAUTHORIZED,
+            "FORBIDDEN" | "WALLET_LOCKED" => StatusCode::FORBIDDEN,
+            "DEVICE_ERROR" => StatusCode::BAD_GATEWAY,
+            "RPC_UNAVAILABLE" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let language = match self {
+            ApiError::Rpc { language, .. } => *language,
+            _ => Language::En,
+        };
+        self.localized_response(language)
+    }
+}
+
+impl ApiError {
+    pub(crate) fn localized_response(&self, language: Language) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        let message = self.to_string();
+        let mut body = ErrorBody {
+            code: self.code(),
+            message: &message,
+            hint: None,
+            rpc_code: None,
+            rpc_message: None,
+        };
+        if let Some((code, rpc_message)) = self.rpc_code() {
+            body.rpc_code = Some(code);
+            body.rpc_message = Some(rpc_message);
+            // Known node errors get a friendly, localized message and hint
+            if let Some(translation) = find_translation(code, rpc_message) {
+                let (error, hint) = match language {
+                    Language::En => translation.en,
+                    Language::Pl => translation.pl,
+                };
+                body.message = error;
+                body.hint = Some(hint);
+            }
+        }
+        if body.hint.is_none() {
+            body.hint = code_hint(body.code).map(|hint| match language {
+                Language::En => hint.en,
+                Language::Pl => hint.pl,
+            });
+        }
+        if body.hint.is_some() {
+            response.insert_header((header::CONTENT_LANGUAGE, language.tag()));
+        }
+        if let ApiError::RateLimited { retry_after } = self {
+            response.insert_header((header::RETRY_AFTER, retry_after_secs(*retry_after)));
+        }
+        response.json(body)
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(e: StorageError) -> Self {
+        ApiError::Storage(e.to_string())
+    }
+}
+
+// Retry-After takes whole seconds, rounded up so a retry is not turned away again
+fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::Service;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App};
+    use bitcoincore_rpc::jsonrpc::error::RpcError as NodeError;
+
+    fn node_error(code: i32, message: &str) -> RpcError {
+        RpcError::JsonRpc(JsonRpcError::Rpc(NodeError {
+            code,
+            message: message.into(),
+            data: None,
+        }))
+    }
+
+    fn rpc(error: RpcError) -> ApiError {
+        ApiError::Rpc {
+            error,
+            language: Language::En,
+        }
+    }
+
+    #[test]
+    fn accept_language_falls_back_to_english() {
+        let cases = [
+            (None, Language::En),
+            (Some(""), Language::En),
+            (Some("pl"), Language::Pl),
+            (Some("pl-PL"), Language::Pl),
+            (Some("PL-pl"), Language::Pl),
+            (Some("de-DE"), Language::En),
+            (Some("*"), Language::En),
+            (Some("de, pl;q=0.5"), Language::Pl),
+            (Some("pl;q=0.4, en;q=0.9"), Language::En),
+            (Some("en-GB, pl;q=0.8"), Language::En),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(
+                Language::from_accept_language(accept),
+                expected,
+                "{accept:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_error_code_has_a_hint_in_both_languages() {
+        let errors = [
+            ApiError::WalletNotFound(String::new()),
+            ApiError::NotFound(String::new()),
+            ApiError::Unauthorized(String::new()),
+            ApiError::Forbidden(String::new()),
+            ApiError::InvalidAddress(String::new()),
+            ApiError::InvalidTxid(String::new()),
+            ApiError::InvalidRequest(String::new()),
+            ApiError::Conflict(String::new()),
+            ApiError::TooManyRequests(String::new()),
+            ApiError::RateLimited {
+                retry_after: Duration::from_secs(1),
+            },
+            ApiError::Device(String::new()),
+            ApiError::UnsupportedOnNetwork(String::new()),
+            rpc(node_error(-6, "Insufficient funds")),
+            rpc(node_error(-18, "Requested wallet does not exist")),
+            rpc(node_error(-5, "Invalid address")),
+            rpc(node_error(-3, "Expected type")),
+            rpc(node_error(-8, "Invalid parameter")),
+            rpc(node_error(-22, "TX decode failed")),
+            rpc(node_error(-25, "bad-txns-inputs-missingorspent")),
+            rpc(node_error(-26, "min relay fee not met")),
+            rpc(node_error(-27, "Transaction already in block chain")),
+            rpc(node_error(-13, "Please enter the wallet passphrase")),
+            rpc(node_error(
+                -14,
+                "The wallet passphrase entered was incorrect",
+            )),
+            rpc(node_error(-12, "Keypool ran out")),
+            rpc(node_error(-4, "Wallet is currently rescanning")),
+            rpc(node_error(-28, "Loading block index")),
+            rpc(node_error(-1, "Unknown")),
+            rpc(RpcError::Io(std::io::Error::other("connection refused"))),
+            rpc(RpcError::ReturnedError("unexpected".into())),
+            ApiError::Storage(String::new()),
+            ApiError::Internal(String::new()),
+        ];
+        for error in &errors {
+            // A new variant fails to compile here until it is added to the list above
+            match error {
+                ApiError::WalletNotFound(_)
+                | ApiError::NotFound(_)
+                | ApiError::Unauthorized(_)
+                | ApiError::Forbidden(_)
+                | ApiError::InvalidAddress(_)
+                | ApiError::InvalidTxid(_)
+                | ApiError::InvalidRequest(_)
+                | ApiError::Conflict(_)
+                | ApiError::TooManyRequests(_)
+                | ApiError::RateLimited { .. }
+                | ApiError::Device(_)
+                | ApiError::UnsupportedOnNetwork(_)
+                | ApiError::Rpc { .. }
+                | ApiError::Storage(_)
+                | ApiError::Internal(_) => {}
+            }
+            let hint =
+                code_hint(error.code()).unwrap_or_else(|| panic!("{} has no hint", error.code()));
+            assert!(!hint.en.is_empty() && !hint.pl.is_empty(), "{}", hint.code);
+        }
+        for hint in CODE_HINTS {
+            assert!(
+                errors.iter().any(|error| error.code() == hint.code),
+                "{} is not produced by any error",
+                hint.code
+            );
+        }
+    }
+
+    #[test]
+    fn node_error_translations_are_complete() {
+        for translation in TRANSLATIONS {
+            for (message, hint) in [translation.en, translation.pl] {
+                assert!(
+                    !message.is_empty() && !hint.is_empty(),
+                    "{}",
+                    translation.rpc_code
+                );
+            }
+        }
+    }
+
+    async fn guard(
+        req: ServiceRequest,
+        next: Next<impl MessageBody>,
+    ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+        if req.path() == "/guarded" {
+            return Err(ApiError::Unauthorized("no token".into()).into());
+        }
+        next.call(req).await
+    }
+
+    #[actix_rt::test]
+    async fn errors_follow_the_callers_language() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(guard))
+                .wrap(from_fn(localize_errors))
+                .route(
+                    "/",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(ApiError::NotFound("no such thing".into()))
+                    }),
+                ),
+        )
+        .await;
+        for (accept, hint) in [
+            ("pl-PL", "Sprawdź ścieżkę i zawarte w niej identyfikatory."),
+            ("fr", "Check the path and the ids in it."),
+        ] {
+            let req = TestRequest::get()
+                .uri("/")
+                .insert_header((header::ACCEPT_LANGUAGE, accept))
+                .to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let body: serde_json::Value = read_body_json(res).await;
+            assert_eq!(body["code"], "NOT_FOUND");
+            assert_eq!(body["message"], "no such thing");
+            assert_eq!(body["hint"], hint);
+        }
+        for (accept, hint) in [
+            ("pl", "Zaloguj się i wyślij token w nagłówku Authorization."),
+            (
+                "en",
+                "Log in and send the token in the Authorization header.",
+            ),
+        ] {
+            let req = TestRequest::get()
+                .uri("/guarded")
+                .insert_header((header::ACCEPT_LANGUAGE, accept))
+                .to_request();
+            let res = app.call(req).await.map(|res| res.into_parts().1);
+            let res = res.unwrap_or_else(|e| e.error_response());
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "UNAUTHORIZED");
+            assert_eq!(body["hint"], hint);
+        }
+    }
+}
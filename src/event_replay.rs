@@ -0,0 +1,342 @@
+use crate::errors::ApiError;
+use crate::events::{Event, JournalEntry};
+use crate::{ws, AppState};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::stream;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::Notify;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+const DEFAULT_SPEED: f64 = 1.0;
+const MAX_SPEED: f64 = 1000.0;
+// Quiet stretches of the session are shortened to this, so playback keeps moving
+const MAX_GAP: Duration = Duration::from_secs(5);
+// Proxies drop connections that stay silent for too long
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ReplayQuery {
+    // 2 plays the events twice as fast as they happened
+    speed: Option<f64>,
+    // Sequence number of the first event to play
+    from: Option<u64>,
+    // Only this wallet's events are played, besides the chain-wide ones
+    wallet: Option<String>,
+    // EventSource cannot set headers, so the /ws token can come in the URL
+    token: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ControlRequest {
+    paused: Option<bool>,
+    speed: Option<f64>,
+    // Jumps to the first event with this sequence number or a later one
+    seek: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PlaybackState {
+    id: String,
+    paused: bool,
+    speed: f64,
+    // Sequence number of the next event to play; null once everything was played
+    position: Option<u64>,
+    // Events in the replay, and the sequence numbers of the first and last one
+    events: usize,
+    first_seq: Option<u64>,
+    last_seq: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ReplayedEvent<'a> {
+    seq: u64,
+    // When the event was published, in Unix milliseconds
+    time_ms: u64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+struct Controls {
+    paused: bool,
+    speed: f64,
+    // Applied by the stream the next time it looks at the controls
+    seek: Option<u64>,
+    position: Option<u64>,
+}
+
+// A running replay, shared between its stream and POST /replay/{id}
+pub(crate) struct Playback {
+    // Session prefix of the caller, so sandbox sessions only control their own replays
+    owner: String,
+    events: usize,
+    first_seq: Option<u64>,
+    last_seq: Option<u64>,
+    controls: Mutex<Controls>,
+    changed: Notify,
+}
+
+impl Playback {
+    fn controls(&self) -> MutexGuard<'_, Controls> {
+        self.controls
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn state(&self, id: &str) -> PlaybackState {
+        let controls = self.controls();
+        PlaybackState {
+            id: id.to_string(),
+            paused: controls.paused,
+            speed: controls.speed,
+            position: controls.position,
+            events: self.events,
+            first_seq: self.first_seq,
+            last_seq: self.last_seq,
+        }
+    }
+}
+
+fn check_speed(speed: f64) -> Result<(), ApiError> {
+    // Also turns away NaN
+    if !(speed > 0.0 && speed <= MAX_SPEED) {
+        return Err(ApiError::InvalidRequest(format!(
+            "speed must be above 0 and at most {}",
+            MAX_SPEED
+        )));
+    }
+    Ok(())
+}
+
+fn frame<T: Serialize>(id: Option<u64>, kind: &str, payload: &T) -> Bytes {
+    let json = serde_json::to_string(payload).unwrap_or_default();
+    match id {
+        Some(id) => Bytes::from(format!("id: {id}\nevent: {kind}\ndata: {json}\n\n")),
+        None => Bytes::from(format!("event: {kind}\ndata: {json}\n\n")),
+    }
+}
+
+// One client's playback of the journal as it was when the client connected
+struct Replay {
+    data: web::Data<AppState>,
+    id: String,
+    playback: Arc<Playback>,
+    entries: Vec<JournalEntry>,
+    // Index of the next entry to play
+    next: usize,
+    // Publishing time of the entry played last, to space out the next one
+    last_time_ms: Option<u64>,
+    frames: VecDeque<Bytes>,
+}
+
+impl Replay {
+    fn seek(&mut self, seq: u64) {
+        self.next = self.entries.partition_point(|entry| entry.seq < seq);
+        self.last_time_ms = None;
+    }
+
+    // How long to wait before playing the next entry at `speed`
+    fn delay(&self, entry: &JournalEntry, speed: f64) -> Duration {
+        let Some(last) = self.last_time_ms else {
+            return Duration::ZERO;
+        };
+        let gap = entry.time_ms.saturating_sub(last) as f64 / speed;
+        Duration::from_millis(gap as u64).min(MAX_GAP)
+    }
+
+    fn play(&mut self) {
+        let entry = &self.entries[self.next];
+        let replayed = ReplayedEvent {
+            seq: entry.seq,
+            time_ms: entry.time_ms,
+            event: &entry.event,
+        };
+        let played = frame(Some(entry.seq), entry.event.kind(), &replayed);
+        self.frames.push_back(played);
+        self.last_time_ms = Some(entry.time_ms);
+        self.next += 1;
+        self.update_position();
+        if self.next == self.entries.len() {
+            self.push_state("end");
+        }
+    }
+
+    fn update_position(&self) {
+        self.playback.controls().position = self.entries.get(self.next).map(|entry| entry.seq);
+    }
+
+    fn push_state(&mut self, kind: &str) {
+        let state = self.playback.state(&self.id);
+        self.frames.push_back(frame(None, kind, &state));
+    }
+
+    async fn next_frame(mut self) -> Option<(Result<Bytes, actix_web::Error>, Self)> {
+        loop {
+            if let Some(frame) = self.frames.pop_front() {
+                return Some((Ok(frame), self));
+            }
+            let (paused, speed) = {
+                let controls = self.playback.controls();
+                (controls.paused, controls.speed)
+            };
+            // Paused or played to the end, the stream only waits for the controls
+            let wait = match self.entries.get(self.next) {
+                Some(entry) if !paused => Some(self.delay(entry, speed)),
+                _ => None,
+            };
+            let changed = actix_rt::time::timeout(
+                wait.unwrap_or(KEEP_ALIVE_INTERVAL),
+                self.playback.changed.notified(),
+            )
+            .await
+            .is_ok();
+            if changed {
+                let seek = self.playback.controls().seek.take();
+                if let Some(seq) = seek {
+                    self.seek(seq);
+                    self.update_position();
+                }
+                self.push_state("playback");
+            } else if wait.is_some() {
+                self.play();
+            } else {
+                return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), self));
+            }
+        }
+    }
+}
+
+// The controls go when the client disconnects
+impl Drop for Replay {
+    fn drop(&mut self) {
+        self.data.replays.remove(&self.id);
+    }
+}
+
+// Plays the events published so far as Server-Sent Events, spaced out as they
+// happened, so a new viewer can animate the session from the start. The first frame
+// carries the replay's id for POST /replay/{id}.
+#[utoipa::path(
+    get,
+    path = "/replay",
+    tag = "events",
+    params(ReplayQuery),
+    responses(
+        (status = 200, description = "A `replay` event, then the journal's events by type, `playback` after every control change and `end`", body = String, content_type = "text/event-stream"),
+    )
+)]
+pub(crate) async fn replay_events(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<ReplayQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /replay - speed={:?}, from={:?}, wallet={:?}",
+        query.speed, query.from, query.wallet
+    );
+    let speed = query.speed.unwrap_or(DEFAULT_SPEED);
+    check_speed(speed)?;
+    let subscriptions = ws::subscribe(
+        &data,
+        &http_req,
+        query.wallet.as_deref(),
+        query.token.as_deref(),
+        "GET /replay",
+    )?;
+    let entries: Vec<JournalEntry> = data
+        .events
+        .journal()
+        .into_iter()
+        .filter(|entry| subscriptions.wants(&entry.event))
+        .collect();
+
+    let id = Uuid::new_v4().simple().to_string();
+    let playback = Arc::new(Playback {
+        owner: data.wallet_name(&http_req, ""),
+        events: entries.len(),
+        first_seq: entries.first().map(|entry| entry.seq),
+        last_seq: entries.last().map(|entry| entry.seq),
+        controls: Mutex::new(Controls {
+            paused: false,
+            speed,
+            seek: None,
+            position: None,
+        }),
+        changed: Notify::new(),
+    });
+    data.replays.insert(id.clone(), playback.clone());
+    let mut replay = Replay {
+        data: data.clone(),
+        id,
+        playback,
+        entries,
+        next: 0,
+        last_time_ms: None,
+        frames: VecDeque::new(),
+    };
+    replay.seek(query.from.unwrap_or_default());
+    replay.update_position();
+    replay.push_state("replay");
+    if replay.next == replay.entries.len() {
+        replay.push_state("end");
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Keeps nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(stream::unfold(replay, Replay::next_frame)))
+}
+
+// Pauses, resumes, changes the speed of or seeks a running replay. The stream confirms
+// the change with a `playback` event.
+#[utoipa::path(
+    post,
+    path = "/replay/{id}",
+    tag = "events",
+    responses(
+        (status = 200, body = PlaybackState),
+    )
+)]
+pub(crate) async fn control_replay(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+    req: web::Json<ControlRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /replay/{} - paused={:?}, speed={:?}, seek={:?}",
+        id, req.paused, req.speed, req.seek
+    );
+    let owner = data.wallet_name(&http_req, "");
+    let playback = data
+        .replays
+        .get(id.as_str())
+        .filter(|playback| playback.owner == owner)
+        .map(|playback| playback.clone())
+        .ok_or_else(|| ApiError::NotFound(format!("Replay '{}' not found", id)))?;
+    if let Some(speed) = req.speed {
+        check_speed(speed)?;
+    }
+    {
+        let mut controls = playback.controls();
+        if let Some(paused) = req.paused {
+            controls.paused = paused;
+        }
+        if let Some(speed) = req.speed {
+            controls.speed = speed;
+        }
+        if req.seek.is_some() {
+            controls.seek = req.seek;
+        }
+    }
+    playback.changed.notify_one();
+    Ok(HttpResponse::Ok().json(playback.state(&id)))
+}
@@ -0,0 +1,218 @@
+use log::debug;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+// Events kept for GET /replay; older ones are dropped
+const JOURNAL_CAPACITY: usize = 10_000;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Events describing what happened on the demo chain. Handlers publish them,
+// event sinks (webhook, MQTT, NATS, Nostr) subscribe and react.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Event {
+    BlocksMined {
+        wallet: String,
+        address: String,
+        block_hashes: Vec<String>,
+    },
+    PaymentSent {
+        wallet: String,
+        to_address: String,
+        amount_sat: u64,
+        txid: String,
+    },
+    // A payment between two wallets of this server through POST /transfer
+    InternalTransfer {
+        wallet: String,
+        to_wallet: String,
+        to_address: String,
+        amount_sat: u64,
+        txid: String,
+    },
+    WalletReady {
+        wallet: String,
+    },
+    ChapterCompleted {
+        wallet: Option<String>,
+        chapter: String,
+    },
+    // An output paying a watched address (not in any wallet) was mined
+    AddressActivity {
+        address: String,
+        label: Option<String>,
+        txid: String,
+        vout: u32,
+        amount_sat: u64,
+        block_height: u64,
+    },
+    // The chain tip moved, by mining through this server or otherwise
+    BlockConnected {
+        height: u64,
+        hash: String,
+    },
+    // The node accepted a transaction into its mempool or saw it in a block (ZMQ rawtx)
+    TransactionSeen {
+        txid: String,
+        vsize: usize,
+    },
+    // A registered wallet saw a transaction enter the mempool or confirm
+    WalletTransaction {
+        wallet: String,
+        txid: String,
+        vout: u32,
+        category: String,
+        amount_sat: i64,
+        confirmations: i32,
+    },
+}
+
+impl Event {
+    pub(crate) const KINDS: [&'static str; 9] = [
+        "blocks_mined",
+        "payment_sent",
+        "internal_transfer",
+        "wallet_ready",
+        "chapter_completed",
+        "address_activity",
+        "block_connected",
+        "transaction_seen",
+        "wallet_transaction",
+    ];
+
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Event::BlocksMined { .. } => "blocks_mined",
+            Event::PaymentSent { .. } => "payment_sent",
+            Event::InternalTransfer { .. } => "internal_transfer",
+            Event::WalletReady { .. } => "wallet_ready",
+            Event::ChapterCompleted { .. } => "chapter_completed",
+            Event::AddressActivity { .. } => "address_activity",
+            Event::BlockConnected { .. } => "block_connected",
+            Event::TransactionSeen { .. } => "transaction_seen",
+            Event::WalletTransaction { .. } => "wallet_transaction",
+        }
+    }
+
+    // The wallet the event happened to, if any
+    pub(crate) fn wallet(&self) -> Option<&str> {
+        match self {
+            Event::BlocksMined { wallet, .. }
+            | Event::PaymentSent { wallet, .. }
+            | Event::InternalTransfer { wallet, .. }
+            | Event::WalletReady { wallet }
+            | Event::WalletTransaction { wallet, .. } => Some(wallet),
+            Event::ChapterCompleted { wallet, .. } => wallet.as_deref(),
+            Event::AddressActivity { .. }
+            | Event::BlockConnected { .. }
+            | Event::TransactionSeen { .. } => None,
+        }
+    }
+
+    // The receiving wallet, for events between two wallets of this server
+    pub(crate) fn counterparty(&self) -> Option<&str> {
+        match self {
+            Event::InternalTransfer { to_wallet, .. } => Some(to_wallet),
+            _ => None,
+        }
+    }
+}
+
+// A published event as the journal keeps it
+#[derive(Clone)]
+pub(crate) struct JournalEntry {
+    // Numbered from 1 in publishing order, never reused
+    pub(crate) seq: u64,
+    // Unix time in milliseconds
+    pub(crate) time_ms: u64,
+    pub(crate) event: Event,
+}
+
+#[derive(Default)]
+struct Journal {
+    entries: VecDeque<JournalEntry>,
+    last_seq: u64,
+}
+
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<Event>,
+    capacity: usize,
+    journal: Mutex<Journal>,
+}
+
+impl EventBus {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            capacity,
+            journal: Mutex::new(Journal::default()),
+        }
+    }
+
+    pub(crate) fn publish(&self, event: Event) {
+        debug!("Publishing event '{}'", event.kind());
+        self.record(&event);
+        // An error only means nobody is subscribed, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    // Events a subscriber can fall behind by before it misses some
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Waits until every subscriber has received the published events, or the timeout
+    // passes. Returns whether they all caught up.
+    pub(crate) async fn drained(&self, timeout: Duration) -> bool {
+        let wait = async {
+            while !self.sender.is_empty() {
+                actix_rt::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        };
+        actix_rt::time::timeout(timeout, wait).await.is_ok()
+    }
+
+    fn lock_journal(&self) -> MutexGuard<'_, Journal> {
+        self.journal
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn record(&self, event: &Event) {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        let mut journal = self.lock_journal();
+        journal.last_seq += 1;
+        let seq = journal.last_seq;
+        if journal.entries.len() == JOURNAL_CAPACITY {
+            journal.entries.pop_front();
+        }
+        journal.entries.push_back(JournalEntry {
+            seq,
+            time_ms,
+            event: event.clone(),
+        });
+    }
+
+    // The events published since the start or the last reset, oldest first
+    pub(crate) fn journal(&self) -> Vec<JournalEntry> {
+        let journal = self.lock_journal();
+        journal.entries.iter().cloned().collect()
+    }
+
+    // Forgets the events of a chain that is gone; numbering goes on
+    pub(crate) fn clear_journal(&self) {
+        let mut journal = self.lock_journal();
+        journal.entries.clear();
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
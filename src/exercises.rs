@@ -0,0 +1,738 @@
+use crate::db::unix_now;
+use crate::errors::ApiError;
+use crate::mining;
+use crate::rpc_pool::{self, Caller};
+use crate::scenario::{self, ScenarioRun, ScenarioStep, Step};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Txid};
+use bitcoincore_rpc::json::GetTransactionResult;
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// The stuck payment pays the minimum relay fee; the student has to get it to a rate
+// in this range
+const STUCK_FEE_RATE: f64 = 1.0;
+const TARGET_FEE_RATES: RangeInclusive<u64> = 5..=25;
+// Index of the setup step whose result the student is given
+const STUCK_PAYMENT_STEP: usize = 2;
+const PSBT_STEP: usize = 7;
+// Exercises are dropped this long after they were generated, and their wallets unloaded
+const EXERCISE_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Kind {
+    // A replaceable payment at 1 sat/vB to rescue with RBF or CPFP
+    StuckTransaction,
+    // A 2-of-3 multisig PSBT with one signature, to co-sign and broadcast
+    MissingSignature,
+}
+
+const KINDS: [Kind; 2] = [Kind::StuckTransaction, Kind::MissingSignature];
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct GenerateRequest {
+    // Picked at random when absent
+    kind: Option<Kind>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[schema(as = ExerciseStatus)]
+enum Status {
+    SettingUp,
+    Ready,
+    SetupFailed,
+    Solved,
+}
+
+// What each kind of exercise needs for its instructions and grading; wallet names are
+// as the student uses them
+#[derive(Clone)]
+enum Setup {
+    StuckTransaction {
+        sender: String,
+        recipient: String,
+        amount_sat: u64,
+        target_fee_rate: u64,
+    },
+    MissingSignature {
+        vault: String,
+        participants: Vec<String>,
+        signed_by: String,
+        shop: String,
+        amount_sat: u64,
+    },
+}
+
+#[derive(Clone)]
+pub(crate) struct Exercise {
+    id: String,
+    kind: Kind,
+    setup: Setup,
+    // Session prefix of the wallets, so sandbox sessions only see their own exercises
+    owner: String,
+    scenario_id: String,
+    created_at: i64,
+    solved_at: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ExerciseView {
+    id: String,
+    kind: Kind,
+    status: Status,
+    instructions: String,
+    wallets: Vec<String>,
+    // What the setup produced, e.g. the stuck txid; None until the setup is done
+    given: Option<Value>,
+    // The setup run, also at GET /scenario/{id}
+    scenario_id: String,
+    setup_error: Option<String>,
+    created_at: i64,
+    solved_at: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct GradeResponse {
+    solved: bool,
+    feedback: String,
+    exercise: ExerciseView,
+}
+
+// v4 uuids are random, which is plenty for picking exercise parameters
+fn random(range: RangeInclusive<u64>) -> u64 {
+    let span = u128::from(range.end() - range.start() + 1);
+    range.start() + (Uuid::new_v4().as_u128() % span) as u64
+}
+
+fn send(from: &str, to: &str, amount_sat: u64, fee_rate: Option<f64>) -> ScenarioStep {
+    Step::Send {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount_sat: Some(amount_sat),
+        amount_btc: None,
+        fee_rate,
+        replaceable: fee_rate.map(|_| true),
+    }
+    .into()
+}
+
+fn create_wallet(wallet: &str, fund_sat: Option<u64>) -> ScenarioStep {
+    Step::CreateWallet {
+        wallet: wallet.to_string(),
+        fund_sat,
+    }
+    .into()
+}
+
+impl Setup {
+    // Fresh wallets with random amounts, named after the exercise so several can run
+    // side by side
+    fn generate(kind: Kind, tag: &str) -> Self {
+        let wallet = |name: &str| format!("{tag}-{name}");
+        match kind {
+            Kind::StuckTransaction => Setup::StuckTransaction {
+                sender: wallet("alice"),
+                recipient: wallet("bob"),
+                amount_sat: random(10..=100) * 1000,
+                target_fee_rate: random(TARGET_FEE_RATES),
+            },
+            Kind::MissingSignature => {
+                let participants = vec![wallet("alice"), wallet("bob"), wallet("carol")];
+                Setup::MissingSignature {
+                    vault: wallet("vault"),
+                    signed_by: participants[random(0..=2) as usize].clone(),
+                    participants,
+                    shop: wallet("shop"),
+                    amount_sat: random(10..=150) * 1000,
+                }
+            }
+        }
+    }
+
+    fn steps(&self) -> Vec<ScenarioStep> {
+        match self {
+            Setup::StuckTransaction {
+                sender,
+                recipient,
+                amount_sat,
+                ..
+            } => vec![
+                create_wallet(sender, Some(random(200..=1000) * 1000)),
+                create_wallet(recipient, None),
+                send(sender, recipient, *amount_sat, Some(STUCK_FEE_RATE)),
+            ],
+            Setup::MissingSignature {
+                vault,
+                participants,
+                signed_by,
+                shop,
+                amount_sat,
+            } => vec![
+                create_wallet(&participants[0], Some(1_000_000)),
+                create_wallet(&participants[1], None),
+                create_wallet(&participants[2], None),
+                Step::CreateMultisig {
+                    wallet: vault.clone(),
+                    required: 2,
+                    wallets: participants.clone(),
+                }
+                .into(),
+                send(&participants[0], vault, random(200..=400) * 1000, None),
+                Step::WaitConfirmations {
+                    confirmations: 1,
+                    txid: None,
+                    wallet: None,
+                    miner: Some(participants[0].clone()),
+                    timeout_secs: None,
+                }
+                .into(),
+                create_wallet(shop, None),
+                Step::CreatePsbt {
+                    wallet: vault.clone(),
+                    to: shop.clone(),
+                    amount_sat: *amount_sat,
+                    signers: vec![signed_by.clone()],
+                }
+                .into(),
+            ],
+        }
+    }
+
+    fn wallets(&self) -> Vec<String> {
+        match self {
+            Setup::StuckTransaction {
+                sender, recipient, ..
+            } => vec![sender.clone(), recipient.clone()],
+            Setup::MissingSignature {
+                vault,
+                participants,
+                shop,
+                ..
+            } => participants.iter().chain([vault, shop]).cloned().collect(),
+        }
+    }
+
+    fn instructions(&self) -> String {
+        match self {
+            Setup::StuckTransaction {
+                sender,
+                recipient,
+                amount_sat,
+                target_fee_rate,
+            } => format!(
+                "{sender} paid {amount_sat} sat to {recipient} at {STUCK_FEE_RATE} sat/vB and the \
+                 payment is stuck in the mempool. Get it to at least {target_fee_rate} sat/vB \
+                 before it is mined: replace it with a higher fee (RBF), or spend one of its \
+                 outputs with a child that pays for both (CPFP)."
+            ),
+            Setup::MissingSignature {
+                vault,
+                participants,
+                signed_by,
+                shop,
+                amount_sat,
+            } => {
+                let others: Vec<&str> = participants
+                    .iter()
+                    .filter(|participant| *participant != signed_by)
+                    .map(String::as_str)
+                    .collect();
+                format!(
+                    "{vault} is a 2-of-3 multisig of {}. {signed_by} signed a PSBT paying \
+                     {amount_sat} sat to {shop}, but one signature is missing. Have {} sign \
+                     it, then finalize and broadcast it.",
+                    participants.join(", "),
+                    others.join(" or ")
+                )
+            }
+        }
+    }
+
+    // The parts of the setup's results the student works with
+    fn given(&self, run: &ScenarioRun) -> Option<Value> {
+        match self {
+            Setup::StuckTransaction {
+                sender,
+                target_fee_rate,
+                ..
+            } => {
+                let payment = run.result(STUCK_PAYMENT_STEP)?;
+                Some(json!({
+                    "txid": payment["txid"],
+                    "wallet": sender,
+                    "fee_rate_sat_vb": STUCK_FEE_RATE,
+                    "target_fee_rate_sat_vb": target_fee_rate,
+                }))
+            }
+            Setup::MissingSignature {
+                vault, signed_by, ..
+            } => {
+                let created = run.result(PSBT_STEP)?;
+                Some(json!({
+                    "psbt": created["psbt"],
+                    "multisig": vault,
+                    "signed_by": signed_by,
+                    "to_address": created["to_address"],
+                    "amount_sat": created["amount_sat"],
+                }))
+            }
+        }
+    }
+}
+
+fn view(data: &AppState, exercise: &Exercise) -> ExerciseView {
+    let run = data.scenarios.get(&exercise.scenario_id);
+    let (status, given, setup_error) = match run.as_deref() {
+        None => (
+            Status::SetupFailed,
+            None,
+            Some("the setup run is gone".to_string()),
+        ),
+        Some(run) => match run.succeeded() {
+            None => (Status::SettingUp, None, None),
+            Some(false) => (Status::SetupFailed, None, run.error().map(str::to_string)),
+            Some(true) if exercise.solved_at.is_some() => {
+                (Status::Solved, exercise.setup.given(run), None)
+            }
+            Some(true) => (Status::Ready, exercise.setup.given(run), None),
+        },
+    };
+    ExerciseView {
+        id: exercise.id.clone(),
+        kind: exercise.kind,
+        status,
+        instructions: exercise.setup.instructions(),
+        wallets: exercise.setup.wallets(),
+        given,
+        scenario_id: exercise.scenario_id.clone(),
+        setup_error,
+        created_at: exercise.created_at,
+        solved_at: exercise.solved_at,
+    }
+}
+
+fn find_exercise(data: &AppState, http_req: &HttpRequest, id: &str) -> Result<Exercise, ApiError> {
+    let owner = data.wallet_name(http_req, "");
+    data.exercises
+        .get(id)
+        .filter(|exercise| exercise.owner == owner)
+        .map(|exercise| exercise.clone())
+        .ok_or_else(|| ApiError::NotFound(format!("Exercise '{}' not found", id)))
+}
+
+// Fee rate in sat/vB the wallet transaction pays on its own
+fn fee_rate(tx: &GetTransactionResult) -> Result<f64, ApiError> {
+    let fee = tx
+        .fee
+        .ok_or_else(|| ApiError::Internal(format!("no fee known for {}", tx.info.txid)))?;
+    let vsize = tx
+        .transaction()
+        .map_err(|e| ApiError::Internal(format!("cannot decode {}: {e}", tx.info.txid)))?
+        .vsize();
+    Ok(fee.to_sat().unsigned_abs() as f64 / vsize as f64)
+}
+
+// Solved by a replacement at the target rate, or while the payment is unconfirmed,
+// by children that lift its package to the target rate
+fn grade_stuck_transaction(
+    data: &AppState,
+    caller: &Caller,
+    sender: &str,
+    txid: Txid,
+    target: f64,
+) -> Result<(bool, String), ApiError> {
+    let client = data
+        .wallet_client(&caller.wallet_name(sender))
+        .ok_or_else(|| ApiError::WalletNotFound(sender.to_string()))?;
+    let original = client
+        .get_transaction(&txid, None)
+        .map_err(|e| caller.rpc(e))?;
+    let mut best_replacement: Option<(Txid, f64)> = None;
+    for conflict in &original.info.wallet_conflicts {
+        let replacement = client
+            .get_transaction(conflict, None)
+            .map_err(|e| caller.rpc(e))?;
+        // Replaced in turn, or beaten by the original
+        if replacement.info.confirmations < 0 {
+            continue;
+        }
+        let rate = fee_rate(&replacement)?;
+        if rate >= target {
+            return Ok((
+                true,
+                format!("Replaced by {conflict} paying {rate:.1} sat/vB"),
+            ));
+        }
+        if best_replacement.is_none_or(|(_, best)| rate > best) {
+            best_replacement = Some((*conflict, rate));
+        }
+    }
+    if let Some((replacement, rate)) = best_replacement {
+        return Ok((
+            false,
+            format!(
+                "Replacement {replacement} pays {rate:.1} sat/vB, below the {target} sat/vB target"
+            ),
+        ));
+    }
+    if original.info.confirmations != 0 {
+        return Ok((
+            false,
+            format!("{txid} left the mempool before its fee was raised; generate a new exercise"),
+        ));
+    }
+
+    let entry = caller
+        .node_client(data)?
+        .get_mempool_entry(&txid)
+        .map_err(|e| caller.rpc(e))?;
+    if entry.descendant_count <= 1 {
+        return Ok((
+            false,
+            format!("{txid} still pays {STUCK_FEE_RATE} sat/vB and has no children"),
+        ));
+    }
+    let rate = entry.fees.descendant.to_sat() as f64 / entry.descendant_size as f64;
+    if rate >= target {
+        Ok((
+            true,
+            format!(
+                "{} child transaction(s) lift the package to {rate:.1} sat/vB",
+                entry.descendant_count - 1
+            ),
+        ))
+    } else {
+        Ok((
+            false,
+            format!(
+                "With its children {txid} pays {rate:.1} sat/vB, below the {target} sat/vB target"
+            ),
+        ))
+    }
+}
+
+// Solved once a payment of the amount to the shop's address spends the vault coins
+// the PSBT spends: the PSBT itself, or one made over from the same coins. A payment
+// from anywhere else does not count.
+fn grade_missing_signature(
+    data: &AppState,
+    caller: &Caller,
+    shop: &str,
+    psbt: &str,
+    address: &str,
+    amount_sat: u64,
+) -> Result<(bool, String), ApiError> {
+    let decoded: Value = caller
+        .node_client(data)?
+        .call("decodepsbt", &[json!(psbt)])
+        .map_err(|e| caller.rpc(e))?;
+    let vault_coins: HashSet<(String, u64)> = decoded["tx"]["vin"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|input| Some((input["txid"].as_str()?.to_string(), input["vout"].as_u64()?)))
+        .collect();
+    let client = data
+        .wallet_client(&caller.wallet_name(shop))
+        .ok_or_else(|| ApiError::WalletNotFound(shop.to_string()))?;
+    let address = Address::from_str(address)
+        .map_err(|e| ApiError::Internal(format!("invalid exercise address: {e}")))?
+        .assume_checked();
+    let received = client
+        .list_received_by_address(Some(&address), Some(0), Some(false), Some(true))
+        .map_err(|e| caller.rpc(e))?;
+    let mut paid_otherwise = false;
+    for txid in received.iter().flat_map(|entry| &entry.txids) {
+        let tx = client
+            .get_transaction(txid, None)
+            .map_err(|e| caller.rpc(e))?;
+        // Replaced or beaten by a conflicting transaction
+        if tx.info.confirmations < 0 {
+            continue;
+        }
+        let tx = tx
+            .transaction()
+            .map_err(|e| ApiError::Internal(format!("cannot decode {txid}: {e}")))?;
+        let paid: u64 = tx
+            .output
+            .iter()
+            .filter(|output| output.script_pubkey == address.script_pubkey())
+            .map(|output| output.value.to_sat())
+            .sum();
+        if paid < amount_sat {
+            continue;
+        }
+        let spends_vault = tx.input.iter().any(|input| {
+            let coin = &input.previous_output;
+            vault_coins.contains(&(coin.txid.to_string(), u64::from(coin.vout)))
+        });
+        if spends_vault {
+            return Ok((
+                true,
+                format!("{shop} received {paid} sat from the vault in {txid}"),
+            ));
+        }
+        paid_otherwise = true;
+    }
+    if paid_otherwise {
+        Ok((
+            false,
+            format!("{shop} was paid, but not with the vault's coins; sign and broadcast the PSBT"),
+        ))
+    } else {
+        Ok((
+            false,
+            format!("{shop} has not received the payment; the PSBT needs a second signature before it can be finalized and broadcast"),
+        ))
+    }
+}
+
+fn grade(
+    data: &AppState,
+    caller: &Caller,
+    setup: &Setup,
+    given: &Value,
+) -> Result<(bool, String), ApiError> {
+    match setup {
+        Setup::StuckTransaction {
+            sender,
+            target_fee_rate,
+            ..
+        } => {
+            let txid = given["txid"]
+                .as_str()
+                .and_then(|txid| Txid::from_str(txid).ok())
+                .ok_or_else(|| ApiError::Internal("the setup gave no txid".into()))?;
+            grade_stuck_transaction(data, caller, sender, txid, *target_fee_rate as f64)
+        }
+        Setup::MissingSignature {
+            shop, amount_sat, ..
+        } => {
+            let psbt = given["psbt"]
+                .as_str()
+                .ok_or_else(|| ApiError::Internal("the setup gave no PSBT".into()))?;
+            let address = given["to_address"]
+                .as_str()
+                .ok_or_else(|| ApiError::Internal("the setup gave no address".into()))?;
+            grade_missing_signature(data, caller, shop, psbt, address, *amount_sat)
+        }
+    }
+}
+
+// Scenario runs that set up exercises still kept, see scenario::sweep_finished
+pub(crate) fn setup_runs(data: &AppState) -> HashSet<String> {
+    data.exercises
+        .iter()
+        .map(|exercise| exercise.scenario_id.clone())
+        .collect()
+}
+
+// Unregisters and unloads the wallets of an expired exercise
+fn unload_wallets(data: &AppState, wallets: &[String]) {
+    let node = match data.config.create_node_client() {
+        Ok(node) => node,
+        Err(e) => {
+            warn!("Cannot unload the wallets of an expired exercise: {}", e);
+            return;
+        }
+    };
+    for wallet in wallets {
+        data.clients.remove(wallet);
+        mining::forget_miner(data, wallet);
+        // Sandbox wallets are not in the registry
+        if data.sandbox.is_none() {
+            if let Err(e) = data.db.unregister_wallet(wallet) {
+                error!(
+                    "Failed to remove wallet '{}' from the registry: {}",
+                    wallet, e
+                );
+            }
+        }
+        match node.unload_wallet(Some(wallet)) {
+            Ok(_) => {}
+            // Never created, or already unloaded
+            Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == -18 => {}
+            Err(e) => warn!("Failed to unload exercise wallet '{}': {}", wallet, e),
+        }
+    }
+}
+
+// Drops exercises older than EXERCISE_TTL with their setup runs, and unloads their
+// wallets so the node does not pile them up
+pub(crate) async fn sweep_expired(data: web::Data<AppState>) {
+    loop {
+        actix_rt::time::sleep(SWEEP_INTERVAL).await;
+        let cutoff = unix_now().saturating_sub(EXERCISE_TTL.as_secs() as i64);
+        let expired: Vec<Exercise> = data
+            .exercises
+            .iter()
+            .filter(|exercise| exercise.created_at < cutoff)
+            .map(|exercise| exercise.clone())
+            .collect();
+        for exercise in expired {
+            // A setup that is still running would create its wallets after the sweep
+            if data
+                .scenarios
+                .get(&exercise.scenario_id)
+                .is_some_and(|run| run.succeeded().is_none())
+            {
+                continue;
+            }
+            data.exercises.remove(&exercise.id);
+            data.scenarios.remove(&exercise.scenario_id);
+            let wallets: Vec<String> = exercise
+                .setup
+                .wallets()
+                .iter()
+                .map(|wallet| format!("{}{}", exercise.owner, wallet))
+                .collect();
+            info!(
+                "Exercise {} expired, unloading {} wallet(s)",
+                exercise.id,
+                wallets.len()
+            );
+            if let Err(e) =
+                rpc_pool::with_state(&data, move |data| unload_wallets(data, &wallets)).await
+            {
+                warn!(
+                    "Failed to unload the wallets of exercise {}: {}",
+                    exercise.id, e
+                );
+            }
+        }
+    }
+}
+
+// Sets the exercise up with a scenario run; it is ready once GET /exercises/{id}
+// says so
+#[utoipa::path(
+    post,
+    path = "/exercises/generate",
+    tag = "exercises",
+    responses(
+        (status = 202, body = ExerciseView),
+    )
+)]
+pub(crate) async fn generate(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<GenerateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    data.config.ensure_regtest("Exercises")?;
+    let kind = req
+        .kind
+        .unwrap_or_else(|| KINDS[random(0..=KINDS.len() as u64 - 1) as usize]);
+    let id = Uuid::new_v4().simple().to_string();
+    let setup = Setup::generate(kind, &format!("ex-{}", &id[..8]));
+    info!("POST /exercises/generate - kind={:?}, id={}", kind, id);
+    let run = scenario::start(
+        &data,
+        http_req.clone(),
+        Some(format!("exercise {id}")),
+        setup.steps(),
+    )?;
+    let exercise = Exercise {
+        id: id.clone(),
+        kind,
+        setup,
+        owner: data.wallet_name(&http_req, ""),
+        scenario_id: run.id().to_string(),
+        created_at: unix_now(),
+        solved_at: None,
+    };
+    data.exercises.insert(id, exercise.clone());
+    Ok(HttpResponse::Accepted().json(view(&data, &exercise)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/exercises/{id}",
+    tag = "exercises",
+    responses(
+        (status = 200, body = ExerciseView),
+    )
+)]
+pub(crate) async fn get_exercise(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /exercises/{}", id);
+    let exercise = find_exercise(&data, &http_req, &id)?;
+    Ok(HttpResponse::Ok().json(view(&data, &exercise)))
+}
+
+// Checks the chain for the student's solution; a solved exercise stays solved
+#[utoipa::path(
+    post,
+    path = "/exercises/{id}/grade",
+    tag = "exercises",
+    responses(
+        (status = 200, body = GradeResponse),
+    )
+)]
+pub(crate) async fn grade_exercise(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /exercises/{}/grade", id);
+    let mut exercise = find_exercise(&data, &http_req, &id)?;
+    let current = view(&data, &exercise);
+    let given = match (current.status, &current.given) {
+        (Status::Solved, _) => {
+            return Ok(HttpResponse::Ok().json(GradeResponse {
+                solved: true,
+                feedback: "Already solved".into(),
+                exercise: current,
+            }))
+        }
+        (Status::Ready, Some(given)) => given,
+        (Status::SetupFailed, _) => {
+            return Err(ApiError::Conflict(format!(
+                "The setup of exercise '{}' failed: {}",
+                id,
+                current.setup_error.unwrap_or_default()
+            )))
+        }
+        _ => {
+            return Err(ApiError::Conflict(format!(
+                "Exercise '{}' is still being set up",
+                id
+            )))
+        }
+    };
+    let (setup, given) = (exercise.setup.clone(), given.clone());
+    let (solved, feedback) = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        grade(data, caller, &setup, &given)
+    })
+    .await?;
+    if solved {
+        let solved_at = unix_now();
+        if let Some(mut stored) = data.exercises.get_mut(id.as_str()) {
+            stored.solved_at = Some(solved_at);
+        }
+        exercise.solved_at = Some(solved_at);
+        info!("Exercise {} solved: {}", id, feedback);
+    } else {
+        info!("Exercise {} not solved yet: {}", id, feedback);
+    }
+    Ok(HttpResponse::Ok().json(GradeResponse {
+        solved,
+        feedback,
+        exercise: view(&data, &exercise),
+    }))
+}
@@ -0,0 +1,427 @@
+use crate::channel::node_client;
+use crate::errors::ApiError;
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{BlockHash, CompactTarget, Target};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// RPC_INVALID_ADDRESS_OR_KEY: unknown block hash
+const RPC_BLOCK_NOT_FOUND: i32 = -5;
+// RPC_INVALID_PARAMETER: height above the tip
+const RPC_HEIGHT_OUT_OF_RANGE: i32 = -8;
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct BlockResponse {
+    hash: String,
+    pub(crate) height: u64,
+    // -1 when the block is no longer in the best chain
+    confirmations: i64,
+    time: u64,
+    median_time: u64,
+    version: i64,
+    merkle_root: String,
+    bits: String,
+    nonce: u64,
+    difficulty: f64,
+    chainwork: String,
+    pub(crate) previous_block_hash: Option<String>,
+    next_block_hash: Option<String>,
+    size: u64,
+    weight: u64,
+    tx_count: u64,
+    // Coinbase first, in block order
+    pub(crate) txids: Vec<String>,
+    // Wallet that mined the block through this server, if any
+    mined_by: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChainTipResponse {
+    chain: String,
+    height: u64,
+    // Headers the node knows of; above `height` while it is still syncing
+    headers: u64,
+    best_block_hash: String,
+    difficulty: f64,
+    median_time: u64,
+    initial_block_download: bool,
+    block: BlockResponse,
+}
+
+fn is_rpc_error(e: &RpcError, code: i32) -> bool {
+    matches!(e, RpcError::JsonRpc(JsonRpcError::Rpc(e)) if e.code == code)
+}
+
+pub(crate) fn block(
+    data: &AppState,
+    caller: &Caller,
+    node: &Client,
+    hash: &BlockHash,
+) -> Result<BlockResponse, RpcError> {
+    let block: Value = node.call("getblock", &[json!(hash), json!(1)])?;
+    let prefix = caller.wallet_name("");
+    let mined_by = match data.db.block_miner(&hash.to_string()) {
+        Ok(wallet) => wallet.and_then(|wallet| wallet.strip_prefix(&prefix).map(str::to_string)),
+        Err(e) => {
+            warn!("Failed to look up the miner of block {}: {}", hash, e);
+            None
+        }
+    };
+    let text = |name: &str| block[name].as_str().unwrap_or_default().to_string();
+    Ok(BlockResponse {
+        hash: text("hash"),
+        height: block["height"].as_u64().unwrap_or_default(),
+        confirmations: block["confirmations"].as_i64().unwrap_or_default(),
+        time: block["time"].as_u64().unwrap_or_default(),
+        median_time: block["mediantime"].as_u64().unwrap_or_default(),
+        version: block["version"].as_i64().unwrap_or_default(),
+        merkle_root: text("merkleroot"),
+        bits: text("bits"),
+        nonce: block["nonce"].as_u64().unwrap_or_default(),
+        difficulty: block["difficulty"].as_f64().unwrap_or_default(),
+        chainwork: text("chainwork"),
+        previous_block_hash: block["previousblockhash"].as_str().map(str::to_string),
+        next_block_hash: block["nextblockhash"].as_str().map(str::to_string),
+        size: block["size"].as_u64().unwrap_or_default(),
+        weight: block["weight"].as_u64().unwrap_or_default(),
+        tx_count: block["nTx"].as_u64().unwrap_or_default(),
+        txids: block["tx"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|txid| txid.as_str().map(str::to_string))
+            .collect(),
+        mined_by,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/block/{hash}",
+    tag = "explorer",
+    responses(
+        (status = 200, body = BlockResponse),
+    )
+)]
+pub(crate) async fn get_block(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /block/{}", hash);
+    let block_hash = BlockHash::from_str(&hash)
+        .map_err(|e| ApiError::InvalidRequest(format!("Invalid block hash: {}", e)))?;
+    let node = node_client(&data, &http_req)?;
+    let caller = Caller::of(&data, &http_req);
+    let found =
+        rpc_pool::with_state(&data, move |data| block(data, &caller, &node, &block_hash)).await?;
+    match found {
+        Ok(block) => Ok(HttpResponse::Ok().json(block)),
+        Err(e) if is_rpc_error(&e, RPC_BLOCK_NOT_FOUND) => {
+            Err(ApiError::NotFound(format!("Block {} not found", hash)))
+        }
+        Err(e) => {
+            error!("Failed to get block {}: {}", hash, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/block/height/{height}",
+    tag = "explorer",
+    responses(
+        (status = 200, body = BlockResponse),
+    )
+)]
+pub(crate) async fn get_block_at_height(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    height: web::Path<u64>,
+) -> Result<HttpResponse, ApiError> {
+    let height = height.into_inner();
+    info!("GET /block/height/{}", height);
+    let node = node_client(&data, &http_req)?;
+    let caller = Caller::of(&data, &http_req);
+    let found = rpc_pool::with_state(&data, move |data| {
+        node.get_block_hash(height)
+            .and_then(|hash| block(data, &caller, &node, &hash))
+    })
+    .await?;
+    match found {
+        Ok(block) => Ok(HttpResponse::Ok().json(block)),
+        Err(e) if is_rpc_error(&e, RPC_HEIGHT_OUT_OF_RANGE) => Err(ApiError::NotFound(format!(
+            "No block at height {} yet",
+            height
+        ))),
+        Err(e) => {
+            error!("Failed to get block at height {}: {}", height, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/chain/tip",
+    tag = "explorer",
+    responses(
+        (status = 200, body = ChainTipResponse),
+    )
+)]
+pub(crate) async fn get_chain_tip(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /chain/tip");
+    let node = node_client(&data, &http_req)?;
+    let caller = Caller::of(&data, &http_req);
+    // Read untyped, like getnetworkinfo: the typed result lags behind newer nodes
+    let tip = rpc_pool::with_state(&data, move |data| {
+        node.call::<Value>("getblockchaininfo", &[])
+            .and_then(|info| {
+                let hash = BlockHash::from_str(info["bestblockhash"].as_str().unwrap_or_default())
+                    .map_err(|e| {
+                        RpcError::ReturnedError(format!("invalid best block hash: {e}"))
+                    })?;
+                Ok(ChainTipResponse {
+                    chain: info["chain"].as_str().unwrap_or_default().to_string(),
+                    height: info["blocks"].as_u64().unwrap_or_default(),
+                    headers: info["headers"].as_u64().unwrap_or_default(),
+                    best_block_hash: hash.to_string(),
+                    difficulty: info["difficulty"].as_f64().unwrap_or_default(),
+                    median_time: info["mediantime"].as_u64().unwrap_or_default(),
+                    initial_block_download: info["initialblockdownload"].as_bool().unwrap_or(false),
+                    block: block(data, &caller, &node, &hash)?,
+                })
+            })
+    })
+    .await?;
+    match tip {
+        Ok(tip) => Ok(HttpResponse::Ok().json(tip)),
+        Err(e) => {
+            error!("Failed to get the chain tip: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct TargetResponse {
+    hash: String,
+    height: u64,
+    // The compact target from the header, as hex like in getblock
+    bits: String,
+    // bits is a base-256 float: the target is mantissa * 256^(exponent - 3)
+    exponent: u8,
+    mantissa: String,
+    // Both as 64 hex digits, so they line up digit by digit
+    target: String,
+    block_hash: String,
+    target_leading_zero_bits: u32,
+    hash_leading_zero_bits: u32,
+    // The proof of work: the hash, read as a number, is at most the target
+    meets_target: bool,
+    difficulty: f64,
+    // log2 of the hashes needed on average to find a block at this target
+    expected_hashes_log2: f64,
+    steps: Vec<String>,
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn target_response(node: &Client, hash: &BlockHash) -> Result<TargetResponse, RpcError> {
+    let header = node.get_block_header(hash)?;
+    let height = node.get_block_header_info(hash)?.height as u64;
+    Ok(explain_target(header.bits, hash, height))
+}
+
+fn explain_target(bits: CompactTarget, hash: &BlockHash, height: u64) -> TargetResponse {
+    let target = Target::from_compact(bits);
+    let bits = bits.to_consensus();
+    let exponent = (bits >> 24) as u8;
+    let mantissa = bits & 0x00ff_ffff;
+    let target_bytes = target.to_be_bytes();
+    // Block hashes are shown byte-reversed, which is the number in big-endian order
+    let hash_bytes: Vec<u8> = hash.to_byte_array().into_iter().rev().collect();
+    let target_zeros = leading_zero_bits(&target_bytes);
+    let hash_zeros = leading_zero_bits(&hash_bytes);
+    let meets_target = target.is_met_by(*hash);
+    let expected_hashes_log2 = target.to_work().log2();
+
+    let mut steps = vec![
+        format!("The header stores the target in 4 bytes, bits = {bits:08x}."),
+        format!(
+            "The first byte {exponent:#04x} is the exponent, the other three {mantissa:06x} the mantissa: target = {mantissa:#x} * 256^({exponent} - 3)."
+        ),
+    ];
+    if mantissa & 0x0080_0000 != 0 {
+        steps.push(
+            "The top bit of the mantissa is a sign bit, and a negative target counts as zero."
+                .to_string(),
+        );
+    }
+    steps.extend([
+        format!(
+            "Written out, the target is {} and starts with {target_zeros} zero bits.",
+            hex::encode(target_bytes)
+        ),
+        format!(
+            "The block hash {} starts with {hash_zeros} zero bits{}.",
+            hex::encode(&hash_bytes),
+            if meets_target {
+                ", and read as a number it is below the target, so the block is valid"
+            } else {
+                ", but it is above the target"
+            }
+        ),
+        format!(
+            "Each hash is below the target with a chance of about 1 in 2^{expected_hashes_log2:.1}, so that is how many hashes miners try on average."
+        ),
+    ]);
+    TargetResponse {
+        hash: hash.to_string(),
+        height,
+        bits: format!("{bits:08x}"),
+        exponent,
+        mantissa: format!("{mantissa:06x}"),
+        target: hex::encode(target_bytes),
+        block_hash: hex::encode(&hash_bytes),
+        target_leading_zero_bits: target_zeros,
+        hash_leading_zero_bits: hash_zeros,
+        meets_target,
+        difficulty: target.difficulty_float(),
+        expected_hashes_log2,
+        steps,
+    }
+}
+
+// Expands nBits into the full target next to the block hash: what the miner solved
+#[utoipa::path(
+    get,
+    path = "/block/{hash}/target",
+    tag = "explorer",
+    responses(
+        (status = 200, body = TargetResponse),
+    )
+)]
+pub(crate) async fn get_block_target(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /block/{}/target", hash);
+    let block_hash = BlockHash::from_str(&hash)
+        .map_err(|e| ApiError::InvalidRequest(format!("Invalid block hash: {}", e)))?;
+    let node = node_client(&data, &http_req)?;
+    let target = rpc_pool::blocking(move || target_response(&node, &block_hash)).await?;
+    match target {
+        Ok(target) => Ok(HttpResponse::Ok().json(target)),
+        Err(e) if is_rpc_error(&e, RPC_BLOCK_NOT_FOUND) => {
+            Err(ApiError::NotFound(format!("Block {} not found", hash)))
+        }
+        Err(e) => {
+            error!("Failed to get the target of block {}: {}", hash, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAINNET_GENESIS: &str =
+        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const REGTEST_GENESIS: &str =
+        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+
+    fn explain(bits: u32, hash: &str) -> TargetResponse {
+        let hash = BlockHash::from_str(hash).unwrap();
+        explain_target(CompactTarget::from_consensus(bits), &hash, 0)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            ((actual - expected) / expected).abs() < 1e-9,
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn regtest_bits_expand_to_the_easiest_target() {
+        let response = explain(0x207f_ffff, REGTEST_GENESIS);
+        assert_eq!(
+            (response.exponent, response.mantissa.as_str()),
+            (0x20, "7fffff")
+        );
+        assert_eq!(response.target, format!("7fffff{}", "00".repeat(29)));
+        assert_eq!(response.target_leading_zero_bits, 1);
+        assert_eq!(response.hash_leading_zero_bits, 4);
+        assert!(response.meets_target);
+        // What getblock reports on regtest
+        assert_close(response.difficulty, 4.656542373906925e-10);
+        assert_close(response.expected_hashes_log2, 1.0);
+    }
+
+    #[test]
+    fn mainnet_genesis_bits_are_difficulty_one() {
+        let response = explain(0x1d00_ffff, MAINNET_GENESIS);
+        assert_eq!(
+            (response.exponent, response.mantissa.as_str()),
+            (0x1d, "00ffff")
+        );
+        assert_eq!(response.target, format!("00000000ffff{}", "00".repeat(26)));
+        assert_eq!(response.target_leading_zero_bits, 32);
+        assert_eq!(response.block_hash, MAINNET_GENESIS);
+        assert_eq!(response.hash_leading_zero_bits, 43);
+        assert!(response.meets_target);
+        assert_eq!(response.difficulty, 1.0);
+        // The genesis block's chainwork, 0x100010001
+        assert_close(
+            response.expected_hashes_log2,
+            (0x1_0001_0001_u64 as f64).log2(),
+        );
+    }
+
+    #[test]
+    fn small_exponents_and_the_sign_bit() {
+        let response = explain(0x0312_3456, MAINNET_GENESIS);
+        assert_eq!(response.target, format!("{}123456", "00".repeat(29)));
+        assert_eq!(response.target_leading_zero_bits, 235);
+        assert!(!response.meets_target);
+        // Below 3 the exponent shifts mantissa bytes out
+        let response = explain(0x0200_3456, MAINNET_GENESIS);
+        assert_eq!(response.target, format!("{}34", "00".repeat(31)));
+        let response = explain(0x0100_3456, MAINNET_GENESIS);
+        assert_eq!(response.target, "00".repeat(32));
+
+        let response = explain(0x0492_3456, MAINNET_GENESIS);
+        assert_eq!(response.mantissa, "923456");
+        assert_eq!(response.target, "00".repeat(32));
+        assert!(!response.meets_target);
+        assert!(response.steps.iter().any(|step| step.contains("sign bit")));
+        assert!(!explain(0x0412_3456, MAINNET_GENESIS)
+            .steps
+            .iter()
+            .any(|step| step.contains("sign bit")));
+    }
+}
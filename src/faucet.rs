@@ -0,0 +1,206 @@
+use crate::errors::ApiError;
+use crate::rpc_pool::{self, PooledClient};
+use crate::{AppState, Config};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount, Network, Txid};
+use bitcoincore_rpc::bitcoincore_rpc_json::AddressType;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Coinbase outputs can only be spent after 100 confirmations
+pub(crate) const COINBASE_MATURITY: u64 = 100;
+// Leave room for the fee of the funding transaction
+const FEE_HEADROOM: Amount = Amount::from_sat(100_000);
+const MAX_MINING_ROUNDS: usize = 50;
+// Block rewards halve every 150 blocks on regtest, so large amounts take many rounds
+const MAX_FAUCET_SAT: u64 = 10 * 100_000_000;
+
+pub(crate) struct Funding {
+    pub(crate) txid: Txid,
+    // Blocks mined to the faucet wallet, the confirming block included
+    pub(crate) blocks_mined: u64,
+}
+
+// Sends `amount` from the faucet wallet, mining matured coins to it first
+// whenever its spendable balance is too low. The payment is confirmed with one
+// more block so the recipient can spend it right away.
+pub(crate) fn fund(config: &Config, to: &Address, amount: Amount) -> Result<Funding, RpcError> {
+    let client = config.create_client(&config.faucet_wallet)?;
+    ensure_loaded(&client, &config.faucet_wallet)?;
+    let (faucet_address, mut blocks_mined) = top_up(config, &client, amount + FEE_HEADROOM)?;
+
+    let txid = client.send_to_address(to, amount, Some("faucet"), None, None, None, None, None)?;
+    if config.network == Network::Regtest {
+        client.generate_to_address(1, &faucet_address)?;
+        blocks_mined += 1;
+    }
+    info!(
+        "Faucet sent {} sat to {} txid={}",
+        amount.to_sat(),
+        to,
+        txid
+    );
+    Ok(Funding { txid, blocks_mined })
+}
+
+// `fund` on the blocking thread pool, it may mine a few hundred blocks before paying
+pub(crate) async fn fund_blocking(
+    data: &web::Data<AppState>,
+    to: Address,
+    amount: Amount,
+) -> Result<Result<Funding, RpcError>, ApiError> {
+    rpc_pool::with_state(data, move |data| fund(&data.config, &to, amount)).await
+}
+
+pub(crate) fn ensure_loaded(client: &Client, wallet: &str) -> Result<(), RpcError> {
+    if client.list_wallets()?.iter().any(|loaded| loaded == wallet) {
+        return Ok(());
+    }
+    if client.load_wallet(wallet).is_err() {
+        info!("Creating faucet wallet '{}'", wallet);
+        client.create_wallet(wallet, None, None, None, None)?;
+    }
+    Ok(())
+}
+
+// Outside regtest the faucet wallet has to be funded by hand, e.g. from a public faucet
+fn top_up(config: &Config, client: &Client, needed: Amount) -> Result<(Address, u64), RpcError> {
+    let address = client
+        .get_new_address(Some("faucet"), Some(AddressType::Bech32))?
+        .require_network(config.network)
+        .map_err(|e| RpcError::ReturnedError(e.to_string()))?;
+
+    let mut blocks_mined = 0;
+    for _ in 0..MAX_MINING_ROUNDS {
+        let balance = client.get_balance(None, None)?;
+        if balance >= needed {
+            return Ok((address, blocks_mined));
+        }
+        if config.network != Network::Regtest {
+            return Err(RpcError::ReturnedError(format!(
+                "faucet has {} sat but needs {} sat; send {} coins to {}",
+                balance.to_sat(),
+                needed.to_sat(),
+                config.network,
+                address
+            )));
+        }
+        // The first round has to bury a coinbase deep enough to mature it
+        let blocks = if balance == Amount::ZERO {
+            COINBASE_MATURITY + 1
+        } else {
+            1
+        };
+        info!(
+            "Faucet balance {} sat is below {} sat, mining {} block(s)",
+            balance.to_sat(),
+            needed.to_sat(),
+            blocks
+        );
+        client.generate_to_address(blocks, &address)?;
+        blocks_mined += blocks;
+    }
+    Err(RpcError::ReturnedError(
+        "faucet could not mine enough spendable coins".into(),
+    ))
+}
+
+// A fresh address of the wallet to pay to
+pub(crate) async fn new_address(
+    data: &AppState,
+    client: PooledClient,
+) -> Result<Result<Address, String>, ApiError> {
+    let network = data.config.network;
+    rpc_pool::blocking(move || {
+        client
+            .get_new_address(Some("faucet"), Some(AddressType::Bech32))
+            .map_err(|e| e.to_string())
+            .and_then(|addr| addr.require_network(network).map_err(|e| e.to_string()))
+    })
+    .await
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct FaucetRequest {
+    wallet_name: String,
+    amount_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FaucetResponse {
+    txid: String,
+    address: String,
+    amount_sat: u64,
+    // Mined to the faucet wallet to mature coins and confirm the payment
+    blocks_mined: u64,
+}
+
+// Funds an existing wallet in one call: mines and matures coins for the faucet
+// wallet as needed, sends the amount and confirms it
+#[utoipa::path(
+    post,
+    path = "/faucet",
+    tag = "faucet",
+    responses(
+        (status = 200, body = FaucetResponse),
+    )
+)]
+pub(crate) async fn fund_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<FaucetRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /faucet - wallet='{}', amount={} sat",
+        req.wallet_name, req.amount_sat
+    );
+    data.config.ensure_regtest("The faucet")?;
+    if req.amount_sat == 0 || req.amount_sat > MAX_FAUCET_SAT {
+        return Err(ApiError::InvalidRequest(format!(
+            "amount_sat must be between 1 and {}",
+            MAX_FAUCET_SAT
+        )));
+    }
+    let wallet_name = data.wallet_name(&http_req, &req.wallet_name);
+    let address = match data.wallet_client(&wallet_name) {
+        Some(client) => match new_address(&data, client).await? {
+            Ok(address) => address,
+            Err(e) => {
+                error!(
+                    "Failed to get faucet address for wallet '{}': {}",
+                    wallet_name, e
+                );
+                return Err(ApiError::Internal(e));
+            }
+        },
+        None => {
+            warn!("POST /faucet - wallet '{}' not found", req.wallet_name);
+            return Err(ApiError::WalletNotFound(req.wallet_name.clone()));
+        }
+    };
+
+    if let Some(sandbox) = &data.sandbox {
+        sandbox
+            .reserve_allowance(&data, &http_req, req.amount_sat)
+            .await?;
+    }
+    match fund_blocking(&data, address.clone(), Amount::from_sat(req.amount_sat)).await? {
+        Ok(funding) => Ok(HttpResponse::Ok().json(FaucetResponse {
+            txid: funding.txid.to_string(),
+            address: address.to_string(),
+            amount_sat: req.amount_sat,
+            blocks_mined: funding.blocks_mined,
+        })),
+        Err(e) => {
+            if let Some(sandbox) = &data.sandbox {
+                sandbox
+                    .release_allowance(&data, &http_req, req.amount_sat)
+                    .await;
+            }
+            error!("Faucet failed to fund wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Amount, Txid};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct BumpFeeRequest {
+    // sat/vB; when absent the node estimates one for conf_target
+    fee_rate: Option<f64>,
+    conf_target: Option<u16>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BumpFeeResponse {
+    original_txid: String,
+    // The replacement, already broadcast
+    txid: String,
+    original_fee_sat: u64,
+    fee_sat: u64,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PsbtBumpFeeResponse {
+    txid: String,
+    // Unsigned replacement, to be signed by the external signer and broadcast
+    psbt: String,
+    original_fee_sat: u64,
+    fee_sat: u64,
+    // Non-fatal warnings from the node
+    errors: Vec<String>,
+}
+
+// Calls bumpfee or psbtbumpfee, which take the same options and report fees alike
+fn call_bump(
+    client: &Client,
+    method: &str,
+    txid: &Txid,
+    req: &BumpFeeRequest,
+) -> Result<Value, RpcError> {
+    let mut options = Map::new();
+    if let Some(fee_rate) = req.fee_rate {
+        options.insert("fee_rate".into(), json!(fee_rate));
+    }
+    if let Some(conf_target) = req.conf_target {
+        options.insert("conf_target".into(), json!(conf_target));
+    }
+    client.call(method, &[json!(txid), Value::Object(options)])
+}
+
+fn fee_sat(result: &Value, key: &str) -> u64 {
+    result[key]
+        .as_f64()
+        .and_then(|btc| Amount::from_btc(btc).ok())
+        .map(Amount::to_sat)
+        .unwrap_or_default()
+}
+
+fn bump_errors(result: &Value) -> Vec<String> {
+    result["errors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|error| error.as_str().map(str::to_string))
+        .collect()
+}
+
+fn bump_fee(
+    client: &Client,
+    txid: &Txid,
+    req: &BumpFeeRequest,
+) -> Result<BumpFeeResponse, RpcError> {
+    let result = call_bump(client, "bumpfee", txid, req)?;
+    let replacement = result["txid"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError("bumpfee returned no txid".into()))?;
+    Ok(BumpFeeResponse {
+        original_txid: txid.to_string(),
+        txid: replacement.to_string(),
+        original_fee_sat: fee_sat(&result, "origfee"),
+        fee_sat: fee_sat(&result, "fee"),
+        errors: bump_errors(&result),
+    })
+}
+
+fn psbt_bump_fee(
+    client: &Client,
+    txid: &Txid,
+    req: &BumpFeeRequest,
+) -> Result<PsbtBumpFeeResponse, RpcError> {
+    let result = call_bump(client, "psbtbumpfee", txid, req)?;
+    Ok(PsbtBumpFeeResponse {
+        txid: txid.to_string(),
+        psbt: result["psbt"].as_str().unwrap_or_default().to_string(),
+        original_fee_sat: fee_sat(&result, "origfee"),
+        fee_sat: fee_sat(&result, "fee"),
+        errors: bump_errors(&result),
+    })
+}
+
+fn parse_request(txid: &str, req: &BumpFeeRequest) -> Result<Txid, ApiError> {
+    let txid = match Txid::from_str(txid) {
+        Ok(txid) => txid,
+        Err(e) => {
+            warn!("Invalid txid format '{}': {}", txid, e);
+            return Err(ApiError::InvalidTxid(format!(
+                "Invalid transaction ID: {}",
+                e
+            )));
+        }
+    };
+    if req.fee_rate.is_some() && req.conf_target.is_some() {
+        return Err(ApiError::InvalidRequest(
+            "Give either fee_rate or conf_target, not both".into(),
+        ));
+    }
+    Ok(txid)
+}
+
+// Replaces a stuck wallet transaction with a higher-fee version and broadcasts it
+#[utoipa::path(
+    post,
+    path = "/tx/{walletid}/{txid}/bump",
+    tag = "fee-bump",
+    responses(
+        (status = 200, body = BumpFeeResponse),
+    )
+)]
+pub(crate) async fn bump_transaction(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+    req: Option<web::Json<BumpFeeRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, txid) = path.into_inner();
+    let req = req.map(web::Json::into_inner).unwrap_or_default();
+    info!(
+        "POST /tx/{}/{}/bump - fee_rate={:?}, conf_target={:?}",
+        walletid, txid, req.fee_rate, req.conf_target
+    );
+    let txid = parse_request(&txid, &req)?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("POST /tx/{}/{}/bump - wallet not found", walletid, txid);
+        return Err(ApiError::WalletNotFound(walletid));
+    };
+    let bumped = rpc_pool::blocking(move || bump_fee(&client, &txid, &req)).await?;
+    match bumped {
+        Ok(bump) => {
+            info!(
+                "Replaced {} with {} in wallet '{}': {} -> {} sat",
+                txid, bump.txid, wallet_name, bump.original_fee_sat, bump.fee_sat
+            );
+            // The replacement tracker would find it too, but only on its next poll
+            if let Err(e) =
+                data.db
+                    .record_replacement(&wallet_name, &bump.original_txid, &bump.txid)
+            {
+                error!("Failed to record replacement of {}: {}", txid, e);
+            }
+            Ok(HttpResponse::Ok().json(bump))
+        }
+        Err(e) => {
+            error!(
+                "bumpfee failed for {} in wallet '{}': {}",
+                txid, wallet_name, e
+            );
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+// Fee bump for wallets without private keys: nothing is broadcast, the returned
+// PSBT goes to the signer (e.g. /wallet/{walletid}/signer/process-psbt or /hwi/sign)
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/psbt-bumpfee/{txid}",
+    tag = "fee-bump",
+    responses(
+        (status = 200, body = PsbtBumpFeeResponse),
+    )
+)]
+pub(crate) async fn prepare_fee_bump(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+    req: Option<web::Json<BumpFeeRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, txid) = path.into_inner();
+    let req = req.map(web::Json::into_inner).unwrap_or_default();
+    info!(
+        "POST /wallet/{}/psbt-bumpfee/{} - fee_rate={:?}, conf_target={:?}",
+        walletid, txid, req.fee_rate, req.conf_target
+    );
+    let txid = parse_request(&txid, &req)?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("POST /wallet/{}/psbt-bumpfee - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid));
+    };
+    let prepared = rpc_pool::blocking(move || psbt_bump_fee(&client, &txid, &req)).await?;
+    match prepared {
+        Ok(bump) => {
+            info!(
+                "Prepared fee bump of {} in wallet '{}': {} -> {} sat",
+                txid, wallet_name, bump.original_fee_sat, bump.fee_sat
+            );
+            Ok(HttpResponse::Ok().json(bump))
+        }
+        Err(e) => {
+            error!(
+                "psbtbumpfee failed for {} in wallet '{}': {}",
+                txid, wallet_name, e
+            );
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::errors::ApiError;
+use crate::node::sat_per_vb;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use bitcoincore_rpc::json::EstimateMode;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::{IntoParams, ToSchema};
+
+// Targets reported by GET /fees when no conf_target is given
+const DEFAULT_CONF_TARGETS: [u16; 5] = [1, 3, 6, 12, 144];
+// estimatesmartfee accepts targets up to 1008 blocks
+const MAX_CONF_TARGET: u16 = 1008;
+// Guards against amounts typed into the fee rate field
+const MAX_FEE_RATE_SAT_VB: f64 = 10_000.0;
+
+// Fee settings for /send; without them the wallet estimates the fee and the recipient
+// gets the full amount
+#[derive(Deserialize, Default, Clone, ToSchema)]
+pub(crate) struct FeeOptions {
+    // sat/vB
+    pub(crate) fee_rate: Option<f64>,
+    #[serde(default)]
+    pub(crate) subtract_fee_from_amount: bool,
+    // Signal BIP125 replaceability so the fee can be bumped later; the wallet's
+    // walletrbf setting decides when absent
+    pub(crate) replaceable: Option<bool>,
+}
+
+impl FeeOptions {
+    pub(crate) fn is_set(&self) -> bool {
+        self.fee_rate.is_some() || self.subtract_fee_from_amount || self.replaceable.is_some()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ApiError> {
+        match self.fee_rate {
+            Some(rate) if !(rate > 0.0 && rate <= MAX_FEE_RATE_SAT_VB) => {
+                Err(ApiError::InvalidRequest(format!(
+                    "fee_rate must be above 0 and at most {} sat/vB",
+                    MAX_FEE_RATE_SAT_VB
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Positional sendtoaddress arguments with the fee options; the typed client has no
+// fee_rate parameter
+pub(crate) fn send_to_address_params(
+    address: &Address,
+    amount: Amount,
+    comment: Option<&str>,
+    fees: &FeeOptions,
+) -> Vec<Value> {
+    vec![
+        json!(address.to_string()),
+        json!(amount.to_btc()),
+        json!(comment),
+        Value::Null,
+        json!(fees.subtract_fee_from_amount),
+        json!(fees.replaceable),
+        Value::Null,
+        Value::Null,
+        Value::Null,
+        json!(fees.fee_rate),
+    ]
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    Economical,
+    Conservative,
+}
+
+impl From<Mode> for EstimateMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Economical => EstimateMode::Economical,
+            Mode::Conservative => EstimateMode::Conservative,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct FeesQuery {
+    conf_target: Option<u16>,
+    estimate_mode: Option<Mode>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FeeEstimate {
+    conf_target: u16,
+    // None when the node has not seen enough transactions, as on a fresh regtest chain
+    fee_rate_sat_vb: Option<f64>,
+    // The target the estimate was found for, which can be further out than asked
+    blocks: i64,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FeesResponse {
+    estimates: Vec<FeeEstimate>,
+    // Lowest fee rates the node relays and keeps in its mempool
+    min_relay_fee_sat_vb: f64,
+    mempool_min_fee_sat_vb: f64,
+}
+
+fn fetch_fees(
+    client: &Client,
+    targets: &[u16],
+    mode: Option<Mode>,
+) -> Result<FeesResponse, RpcError> {
+    let mut estimates = Vec::with_capacity(targets.len());
+    for &conf_target in targets {
+        let estimate = client.estimate_smart_fee(conf_target, mode.map(EstimateMode::from))?;
+        estimates.push(FeeEstimate {
+            conf_target,
+            fee_rate_sat_vb: estimate.fee_rate.map(sat_per_vb),
+            blocks: estimate.blocks,
+            errors: estimate.errors.unwrap_or_default(),
+        });
+    }
+    let mempool = client.get_mempool_info()?;
+    Ok(FeesResponse {
+        estimates,
+        min_relay_fee_sat_vb: sat_per_vb(mempool.min_relay_tx_fee),
+        mempool_min_fee_sat_vb: sat_per_vb(mempool.mempool_min_fee),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/fees",
+    tag = "fees",
+    params(FeesQuery),
+    responses((status = 200, body = FeesResponse))
+)]
+pub(crate) async fn get_fees(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<FeesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /fees - conf_target={:?}, estimate_mode={:?}",
+        query.conf_target, query.estimate_mode
+    );
+    let targets = match query.conf_target {
+        Some(target) if (1..=MAX_CONF_TARGET).contains(&target) => vec![target],
+        Some(_) => {
+            return Err(ApiError::InvalidRequest(format!(
+                "conf_target must be between 1 and {}",
+                MAX_CONF_TARGET
+            )))
+        }
+        None => DEFAULT_CONF_TARGETS.to_vec(),
+    };
+    let client = match data.config.create_node_client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create node RPC client: {}", e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let mode = query.estimate_mode;
+    let fetched = rpc_pool::blocking(move || fetch_fees(&client, &targets, mode)).await?;
+    match fetched {
+        Ok(fees) => Ok(HttpResponse::Ok().json(fees)),
+        Err(e) => {
+            error!("Failed to estimate fees: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::errors::Language;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+const SATS_PER_BTC: u64 = 100_000_000;
+
+// An amount with display strings, so frontends need not redo the unit math
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct FormattedAmount {
+    sat: u64,
+    // Plain BTC with all eight decimals, e.g. "0.00150000"
+    btc: String,
+    // Locale-aware strings, e.g. "150,000 sats" or "0.0015 BTC"
+    display_sat: String,
+    display_btc: String,
+}
+
+struct Conventions {
+    thousands: &'static str,
+    decimal: char,
+    // For one satoshi and for any other amount
+    sat_units: (&'static str, &'static str),
+}
+
+fn conventions(language: Language) -> Conventions {
+    match language {
+        Language::En => Conventions {
+            thousands: ",",
+            decimal: '.',
+            sat_units: ("sat", "sats"),
+        },
+        // Polish groups digits with a non-breaking space and uses a decimal comma
+        Language::Pl => Conventions {
+            thousands: "\u{a0}",
+            decimal: ',',
+            sat_units: ("sat", "sat"),
+        },
+    }
+}
+
+fn group_thousands(value: u64, separator: &str) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 * separator.len());
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// Integer math avoids float rounding in the decimals
+pub(crate) fn btc_string(sat: u64) -> String {
+    format!("{}.{:08}", sat / SATS_PER_BTC, sat % SATS_PER_BTC)
+}
+
+pub(crate) fn format_amount(sat: u64, language: Language) -> FormattedAmount {
+    let conventions = conventions(language);
+    let fraction = format!("{:08}", sat % SATS_PER_BTC);
+    // Trailing zeros are dropped for display, keeping at least one decimal
+    let fraction = match fraction.trim_end_matches('0') {
+        "" => "0",
+        trimmed => trimmed,
+    };
+    let sat_unit = match sat {
+        1 => conventions.sat_units.0,
+        _ => conventions.sat_units.1,
+    };
+    FormattedAmount {
+        sat,
+        btc: btc_string(sat),
+        display_sat: format!(
+            "{} {}",
+            group_thousands(sat, conventions.thousands),
+            sat_unit
+        ),
+        display_btc: format!(
+            "{}{}{} BTC",
+            group_thousands(sat / SATS_PER_BTC, conventions.thousands),
+            conventions.decimal,
+            fraction
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_in_every_unit_and_language() {
+        // sat, BTC, then the English and Polish display strings for sats and BTC
+        let cases = [
+            (0, "0.00000000", "0 sats", "0.0 BTC", "0 sat", "0,0 BTC"),
+            (
+                1,
+                "0.00000001",
+                "1 sat",
+                "0.00000001 BTC",
+                "1 sat",
+                "0,00000001 BTC",
+            ),
+            (
+                999,
+                "0.00000999",
+                "999 sats",
+                "0.00000999 BTC",
+                "999 sat",
+                "0,00000999 BTC",
+            ),
+            (
+                1_000,
+                "0.00001000",
+                "1,000 sats",
+                "0.00001 BTC",
+                "1\u{a0}000 sat",
+                "0,00001 BTC",
+            ),
+            (
+                150_000,
+                "0.00150000",
+                "150,000 sats",
+                "0.0015 BTC",
+                "150\u{a0}000 sat",
+                "0,0015 BTC",
+            ),
+            (
+                99_999_999,
+                "0.99999999",
+                "99,999,999 sats",
+                "0.99999999 BTC",
+                "99\u{a0}999\u{a0}999 sat",
+                "0,99999999 BTC",
+            ),
+            (
+                100_000_000,
+                "1.00000000",
+                "100,000,000 sats",
+                "1.0 BTC",
+                "100\u{a0}000\u{a0}000 sat",
+                "1,0 BTC",
+            ),
+            (
+                123_456_789_012,
+                "1234.56789012",
+                "123,456,789,012 sats",
+                "1,234.56789012 BTC",
+                "123\u{a0}456\u{a0}789\u{a0}012 sat",
+                "1\u{a0}234,56789012 BTC",
+            ),
+            (
+                u64::MAX,
+                "184467440737.09551615",
+                "18,446,744,073,709,551,615 sats",
+                "184,467,440,737.09551615 BTC",
+                "18\u{a0}446\u{a0}744\u{a0}073\u{a0}709\u{a0}551\u{a0}615 sat",
+                "184\u{a0}467\u{a0}440\u{a0}737,09551615 BTC",
+            ),
+        ];
+        for (sat, btc, en_sat, en_btc, pl_sat, pl_btc) in cases {
+            assert_eq!(btc_string(sat), btc);
+            for (language, display_sat, display_btc) in [
+                (Language::En, en_sat, en_btc),
+                (Language::Pl, pl_sat, pl_btc),
+            ] {
+                let formatted = format_amount(sat, language);
+                assert_eq!(formatted.sat, sat);
+                assert_eq!(formatted.btc, btc);
+                assert_eq!(formatted.display_sat, display_sat, "{sat} in {language:?}");
+                assert_eq!(formatted.display_btc, display_btc, "{sat} in {language:?}");
+            }
+        }
+    }
+}
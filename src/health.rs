@@ -0,0 +1,108 @@
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpResponse};
+use bitcoincore_rpc::{Error as RpcError, RpcApi};
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+struct HealthResponse {
+    status: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReadyResponse {
+    ready: bool,
+    // Network the server is configured for
+    network: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<NodeStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct NodeStatus {
+    version: u64,
+    subversion: String,
+    chain: String,
+    blocks: u64,
+    initial_block_download: bool,
+    // Round trip of getblockchaininfo
+    latency_ms: u64,
+}
+
+fn node_status(data: &AppState) -> Result<NodeStatus, RpcError> {
+    let client = data.config.create_node_client()?;
+    let started = Instant::now();
+    let chain: Value = client.call("getblockchaininfo", &[])?;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    // Read untyped, see node::fetch_policy
+    let network: Value = client.call("getnetworkinfo", &[])?;
+    Ok(NodeStatus {
+        version: network["version"].as_u64().unwrap_or_default(),
+        subversion: network["subversion"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        chain: chain["chain"].as_str().unwrap_or_default().to_string(),
+        blocks: chain["blocks"].as_u64().unwrap_or_default(),
+        initial_block_download: chain["initialblockdownload"].as_bool().unwrap_or_default(),
+        latency_ms,
+    })
+}
+
+// The process is up; says nothing about bitcoind
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses(
+        (status = 200, body = HealthResponse),
+    )
+)]
+pub(crate) async fn health() -> HttpResponse {
+    debug!("GET /health");
+    HttpResponse::Ok().json(HealthResponse { status: "ok" })
+}
+
+// Ready once bitcoind answers and runs the configured network, 503 otherwise
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "health",
+    responses(
+        (status = 200, body = ReadyResponse),
+        (status = 503, description = "bitcoind is unreachable or runs another network", body = ReadyResponse),
+    )
+)]
+pub(crate) async fn ready(data: web::Data<AppState>) -> HttpResponse {
+    debug!("GET /ready");
+    let network = data.config.network.to_core_arg().to_string();
+    let status = rpc_pool::with_state(&data, node_status).await;
+    let (node, error) = match status {
+        Ok(Ok(node)) if node.chain != network => {
+            let error = format!("node runs {}, but network={}", node.chain, network);
+            (Some(node), Some(error))
+        }
+        Ok(Ok(node)) => (Some(node), None),
+        Ok(Err(e)) => (None, Some(format!("bitcoind is unreachable: {e}"))),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let response = ReadyResponse {
+        ready: error.is_none(),
+        network,
+        node,
+        error,
+    };
+    match &response.error {
+        None => HttpResponse::Ok().json(response),
+        Some(error) => {
+            warn!("GET /ready - not ready: {}", error);
+            HttpResponse::ServiceUnavailable().json(response)
+        }
+    }
+}
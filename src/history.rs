@@ -0,0 +1,182 @@
+use crate::errors::ApiError;
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::rpc_pool::{self, Caller};
+use crate::{AppState, TxStatus};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::json::{GetTransactionResultDetailCategory, ListTransactionResult};
+use bitcoincore_rpc::{Client, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 500;
+// How many newer entries may push the cursor's entry back before the cursor expires
+const MAX_CURSOR_DRIFT: usize = 5000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct HistoryQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HistoryEntry {
+    txid: String,
+    #[schema(value_type = String)]
+    category: GetTransactionResultDetailCategory,
+    address: Option<String>,
+    vout: u32,
+    // Negative for sends
+    amount_sat: i64,
+    fee_sat: Option<i64>,
+    label: Option<String>,
+    time: u64,
+    block_hash: Option<String>,
+    block_height: Option<u32>,
+    status: TxStatus,
+    abandoned: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HistoryPage {
+    wallet: String,
+    // Newest first
+    #[serde(flatten)]
+    page: Page<HistoryEntry>,
+}
+
+impl From<ListTransactionResult> for HistoryEntry {
+    fn from(tx: ListTransactionResult) -> Self {
+        HistoryEntry {
+            txid: tx.info.txid.to_string(),
+            category: tx.detail.category,
+            address: tx
+                .detail
+                .address
+                .map(|address| address.assume_checked().to_string()),
+            vout: tx.detail.vout,
+            amount_sat: tx.detail.amount.to_sat(),
+            fee_sat: tx.detail.fee.map(|fee| fee.to_sat()),
+            label: tx.detail.label,
+            time: tx.info.time,
+            block_hash: tx.info.blockhash.map(|hash| hash.to_string()),
+            block_height: tx.info.blockheight,
+            status: TxStatus::of(&tx.info),
+            abandoned: tx.detail.abandoned.unwrap_or_default(),
+        }
+    }
+}
+
+// A transaction has one entry per output, and a payment to yourself both a send and a
+// receive entry
+fn entry_id(tx: &ListTransactionResult) -> String {
+    format!(
+        "{}:{}:{:?}",
+        tx.info.txid, tx.detail.vout, tx.detail.category
+    )
+}
+
+// listtransactions can only skip from the newest entry, so the cursor holds how far
+// back its entry was and which entry it was. Newer transactions push that entry
+// further back; the scan resumes where it was and reads on until it finds it.
+fn entries_after(
+    client: &Client,
+    wallet_name: &str,
+    caller: &Caller,
+    request: &PageRequest,
+) -> Result<Vec<(usize, ListTransactionResult)>, ApiError> {
+    let (mut skip, boundary) = match &request.after {
+        Some(after) => (after.at.max(0) as usize, Some(after.id.as_str())),
+        None => (0, None),
+    };
+    let mut found = boundary.is_none();
+    let mut entries = Vec::new();
+    while entries.len() < request.fetch() {
+        let batch = client
+            .list_transactions(None, Some(request.fetch()), Some(skip), Some(true))
+            .map_err(|e| {
+                error!(
+                    "Failed to list transactions for wallet '{}': {}",
+                    wallet_name, e
+                );
+                caller.rpc(e)
+            })?;
+        let read = batch.len();
+        // Each batch comes oldest first
+        for (index, tx) in batch.into_iter().rev().enumerate() {
+            if found {
+                entries.push((skip + index, tx));
+            } else if boundary == Some(entry_id(&tx).as_str()) {
+                found = true;
+            }
+        }
+        skip += read;
+        if read < request.fetch() {
+            break;
+        }
+        if !found
+            && request.after.as_ref().is_some_and(|after| {
+                skip.saturating_sub(after.at.max(0) as usize) > MAX_CURSOR_DRIFT
+            })
+        {
+            break;
+        }
+    }
+    if !found {
+        return Err(ApiError::InvalidRequest(
+            "The cursor no longer matches a transaction, start again from the first page".into(),
+        ));
+    }
+    entries.truncate(request.fetch());
+    Ok(entries)
+}
+
+// Wallet timeline backed by listtransactions. A transaction touching several outputs
+// appears once per output, as bitcoind reports it.
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/transactions",
+    tag = "history",
+    params(HistoryQuery),
+    responses(
+        (status = 200, body = HistoryPage),
+    )
+)]
+pub(crate) async fn list_transactions(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /wallet/{}/transactions - limit={:?}, cursor={:?}",
+        walletid, query.limit, query.cursor
+    );
+    let request = PageRequest::new(
+        query.limit,
+        query.cursor.as_deref(),
+        DEFAULT_PAGE_SIZE,
+        MAX_PAGE_SIZE,
+    )?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/transactions - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let caller = Caller::of(&data, &http_req);
+    let (request, entries) = rpc_pool::blocking(move || {
+        let entries = entries_after(&client, &wallet_name, &caller, &request);
+        (request, entries)
+    })
+    .await?;
+    let entries = entries?;
+    let page = request.page(entries, |(position, tx)| {
+        Cursor::new(*position as i64, entry_id(tx))
+    });
+    Ok(HttpResponse::Ok().json(HistoryPage {
+        wallet: walletid.into_inner(),
+        page: page.map(|(_, tx)| HistoryEntry::from(tx)),
+    }))
+}
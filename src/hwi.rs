@@ -0,0 +1,284 @@
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bitcoincore_rpc::bitcoin::Network;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::process::Command;
+use utoipa::ToSchema;
+
+// Signing waits for the user to confirm on the device, so this is generous
+const HWI_TIMEOUT: Duration = Duration::from_secs(120);
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct WatchOnlyRequest {
+    fingerprint: String,
+    wallet_name: String,
+    #[serde(default)]
+    account: u32,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[schema(as = HwiSignPsbtRequest)]
+pub(crate) struct SignPsbtRequest {
+    fingerprint: String,
+    psbt: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct WatchOnlyResponse {
+    wallet_name: String,
+    imported_descriptors: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(as = HwiSignPsbtResponse)]
+struct SignPsbtResponse {
+    psbt: String,
+    signed: bool,
+}
+
+// Runs `hwi` against the configured chain and parses its JSON output. HWI reports
+// most failures as {"error": ..., "code": ...} with a zero exit status. `args` go
+// after `--`, so a value starting with a dash is never read as an option.
+async fn run_hwi(
+    hwi_path: &str,
+    network: Network,
+    fingerprint: Option<&str>,
+    subcommand: &str,
+    options: &[String],
+    args: &[String],
+) -> Result<Value, String> {
+    let mut command = Command::new(hwi_path);
+    command.args(["--chain", network.to_core_arg()]);
+    if let Some(fingerprint) = fingerprint {
+        command.args(["--fingerprint", fingerprint]);
+    }
+    command.arg(subcommand).args(options);
+    if !args.is_empty() {
+        command.arg("--").args(args);
+    }
+    // A device that never answers must not keep hwi running
+    command.kill_on_drop(true);
+    debug!("Running {:?}", command);
+
+    let output = match actix_rt::time::timeout(HWI_TIMEOUT, command.output()).await {
+        Ok(output) => output.map_err(|e| format!("failed to run hwi: {e}"))?,
+        Err(_) => {
+            return Err(format!(
+                "hwi did not finish within {} s",
+                HWI_TIMEOUT.as_secs()
+            ))
+        }
+    };
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let value: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("unexpected hwi output: {e}"))?;
+    match value.get("error") {
+        Some(error) => Err(error.as_str().unwrap_or_default().to_string()),
+        None => Ok(value),
+    }
+}
+
+// A master key fingerprint as HWI prints it, 8 hex digits
+fn check_fingerprint(fingerprint: &str) -> Result<(), ApiError> {
+    if fingerprint.len() == 8 && fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidRequest(format!(
+            "Invalid fingerprint '{fingerprint}', expected 8 hex digits"
+        )))
+    }
+}
+
+// The PSBT must be base64 and start with the PSBT magic bytes before hwi sees it
+fn check_psbt(psbt: &str) -> Result<(), ApiError> {
+    let bytes = STANDARD
+        .decode(psbt)
+        .map_err(|e| ApiError::InvalidRequest(format!("The PSBT is not valid base64: {e}")))?;
+    if !bytes.starts_with(PSBT_MAGIC) {
+        return Err(ApiError::InvalidRequest("The data is not a PSBT".into()));
+    }
+    Ok(())
+}
+
+fn hwi_path(data: &AppState) -> Result<String, ApiError> {
+    data.config
+        .hwi_path
+        .clone()
+        .ok_or_else(|| ApiError::NotFound("Hardware wallet integration is disabled".into()))
+}
+
+fn create_watch_only(client: &Client, name: &str, descriptors: &Value) -> Result<usize, RpcError> {
+    client.create_wallet(name, Some(true), Some(true), None, None)?;
+
+    let mut requests = Vec::new();
+    for (key, internal) in [("receive", false), ("internal", true)] {
+        for desc in descriptors[key].as_array().into_iter().flatten() {
+            requests.push(json!({
+                "desc": desc,
+                "timestamp": "now",
+                "active": true,
+                "internal": internal,
+                "range": [0, 1000],
+            }));
+        }
+    }
+    let results: Vec<Value> = client.call("importdescriptors", &[json!(requests)])?;
+    if let Some(failed) = results
+        .iter()
+        .find(|result| result["success"] != json!(true))
+    {
+        return Err(RpcError::ReturnedError(format!(
+            "descriptor import failed: {}",
+            failed["error"]
+        )));
+    }
+    Ok(requests.len())
+}
+
+#[utoipa::path(get, path = "/hwi/devices", tag = "hwi", responses((status = 200, body = Object)))]
+pub(crate) async fn list_devices(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("GET /hwi/devices");
+    let hwi = hwi_path(&data)?;
+    let network = data.config.network;
+    match run_hwi(&hwi, network, None, "enumerate", &[], &[]).await {
+        Ok(devices) => Ok(HttpResponse::Ok().json(devices)),
+        Err(e) => {
+            error!("hwi enumerate failed: {}", e);
+            Err(ApiError::Device(e))
+        }
+    }
+}
+
+// Fetches the device's account descriptors into a fresh watch-only wallet
+#[utoipa::path(
+    post,
+    path = "/hwi/watch-only",
+    tag = "hwi",
+    responses(
+        (status = 200, body = WatchOnlyResponse),
+    )
+)]
+pub(crate) async fn create_watch_only_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<WatchOnlyRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /hwi/watch-only - fingerprint='{}', wallet='{}', account={}",
+        req.fingerprint, req.wallet_name, req.account
+    );
+    let hwi = hwi_path(&data)?;
+    check_fingerprint(&req.fingerprint)?;
+    let options = ["--account".to_string(), req.account.to_string()];
+    let descriptors = run_hwi(
+        &hwi,
+        data.config.network,
+        Some(&req.fingerprint),
+        "getdescriptors",
+        &options,
+        &[],
+    )
+    .await;
+    let descriptors = match descriptors {
+        Ok(descriptors) => descriptors,
+        Err(e) => {
+            error!("hwi getdescriptors failed: {}", e);
+            return Err(ApiError::Device(e));
+        }
+    };
+
+    let wallet_name = data.wallet_name(&http_req, &req.wallet_name);
+    let client = match data.config.create_client(&wallet_name) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create RPC client for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let name = wallet_name.clone();
+    let (client, imported) = rpc_pool::blocking(move || {
+        let imported = create_watch_only(&client, &name, &descriptors);
+        (client, imported)
+    })
+    .await?;
+    let imported = match imported {
+        Ok(imported) => imported,
+        Err(e) => {
+            error!(
+                "Failed to create watch-only wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+
+    info!(
+        "Watch-only wallet '{}' tracks device {}",
+        wallet_name, req.fingerprint
+    );
+    data.events.publish(Event::WalletReady {
+        wallet: wallet_name.clone(),
+    });
+    data.register_wallet(wallet_name, client);
+    Ok(HttpResponse::Ok().json(WatchOnlyResponse {
+        wallet_name: req.wallet_name.clone(),
+        imported_descriptors: imported,
+    }))
+}
+
+// Passes a PSBT to the device; the user confirms the spend on the device itself
+#[utoipa::path(
+    post,
+    path = "/hwi/sign",
+    tag = "hwi",
+    responses(
+        (status = 200, body = SignPsbtResponse),
+    )
+)]
+pub(crate) async fn sign_psbt(
+    data: web::Data<AppState>,
+    req: web::Json<SignPsbtRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /hwi/sign - fingerprint='{}'", req.fingerprint);
+    let hwi = hwi_path(&data)?;
+    check_fingerprint(&req.fingerprint)?;
+    check_psbt(&req.psbt)?;
+    let signed = run_hwi(
+        &hwi,
+        data.config.network,
+        Some(&req.fingerprint),
+        "signtx",
+        &[],
+        std::slice::from_ref(&req.psbt),
+    )
+    .await;
+    match signed {
+        Ok(result) => {
+            let psbt = result["psbt"].as_str().unwrap_or_default().to_string();
+            let signed = result["signed"].as_bool().unwrap_or(false);
+            if !signed {
+                warn!("Device {} did not sign the PSBT", req.fingerprint);
+            }
+            Ok(HttpResponse::Ok().json(SignPsbtResponse { psbt, signed }))
+        }
+        Err(e) => {
+            error!("hwi signtx failed: {}", e);
+            Err(ApiError::Device(e))
+        }
+    }
+}
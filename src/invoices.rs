@@ -0,0 +1,460 @@
+use crate::db::{unix_now, ConfirmationTier, Invoice};
+use crate::errors::ApiError;
+use crate::keypool;
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::Address;
+use bitcoincore_rpc::json::{AddressType, GetTransactionResultDetailCategory};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const MAX_TIERS: usize = 10;
+const MAX_CONFIRMATIONS: u32 = 100;
+
+// Small payments are accepted straight from the mempool, larger ones wait for blocks
+fn default_policy() -> Vec<ConfirmationTier> {
+    vec![
+        ConfirmationTier {
+            below_sat: Some(10_000),
+            confirmations: 0,
+        },
+        ConfirmationTier {
+            below_sat: Some(1_000_000),
+            confirmations: 1,
+        },
+        ConfirmationTier {
+            below_sat: None,
+            confirmations: 6,
+        },
+    ]
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateInvoiceRequest {
+    amount_sat: u64,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct PolicyRequest {
+    // Ordered by amount; an empty list restores the default policy
+    tiers: Vec<ConfirmationTier>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PolicyResponse {
+    wallet: String,
+    default: bool,
+    tiers: Vec<ConfirmationTier>,
+}
+
+#[derive(Serialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum InvoiceStatus {
+    Unpaid,
+    PartiallyPaid,
+    Confirming,
+    Paid,
+}
+
+#[derive(Serialize, ToSchema)]
+struct InvoiceResponse {
+    id: String,
+    wallet: String,
+    address: String,
+    amount_sat: u64,
+    description: Option<String>,
+    created_at: i64,
+    status: InvoiceStatus,
+    // Unconfirmed payments included
+    received_sat: u64,
+    // Confirmations of the payment completing the amount; None until fully paid
+    confirmations: Option<u32>,
+    required_confirmations: u32,
+    remaining_confirmations: u32,
+    reason: String,
+}
+
+// The policy tier an amount falls in, and the amounts that tier covers in words
+fn required_confirmations(tiers: &[ConfirmationTier], amount_sat: u64) -> (u32, String) {
+    let mut lower = None;
+    for tier in tiers {
+        if tier.below_sat.is_none_or(|below| amount_sat < below) {
+            let range = match (lower, tier.below_sat) {
+                (None, Some(below)) => format!("under {below} sat"),
+                (Some(lower), Some(below)) => format!("from {lower} sat to under {below} sat"),
+                (Some(lower), None) => format!("of {lower} sat and more"),
+                (None, None) => "of any amount".to_string(),
+            };
+            return (tier.confirmations, range);
+        }
+        lower = tier.below_sat;
+    }
+    // Policies are validated to end with an unbounded tier
+    (0, "of any amount".to_string())
+}
+
+fn validate_policy(tiers: &[ConfirmationTier]) -> Result<(), ApiError> {
+    if tiers.len() > MAX_TIERS {
+        return Err(ApiError::InvalidRequest(format!(
+            "A policy has at most {} tiers",
+            MAX_TIERS
+        )));
+    }
+    if let Some(tier) = tiers
+        .iter()
+        .find(|tier| tier.confirmations > MAX_CONFIRMATIONS)
+    {
+        return Err(ApiError::InvalidRequest(format!(
+            "{} confirmations is more than the maximum of {}",
+            tier.confirmations, MAX_CONFIRMATIONS
+        )));
+    }
+    let (last, bounded) = match tiers.split_last() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    if last.below_sat.is_some() {
+        return Err(ApiError::InvalidRequest(
+            "The last tier must leave below_sat out so that it covers every larger amount".into(),
+        ));
+    }
+    let mut previous = 0;
+    for tier in bounded {
+        match tier.below_sat {
+            Some(below) if below > previous => previous = below,
+            Some(_) => {
+                return Err(ApiError::InvalidRequest(
+                    "Tiers must be ordered by strictly increasing below_sat".into(),
+                ))
+            }
+            None => {
+                return Err(ApiError::InvalidRequest(
+                    "Only the last tier can leave below_sat out".into(),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// The wallet's own policy, or the default one
+fn policy(data: &AppState, wallet: &str) -> Result<(Vec<ConfirmationTier>, bool), ApiError> {
+    Ok(match data.db.confirmation_policy(wallet)? {
+        Some(tiers) => (tiers, false),
+        None => (default_policy(), true),
+    })
+}
+
+// Amount received by the address, and the confirmations of the payment that completed
+// `amount_sat`, counting the most confirmed payments first
+fn received(
+    client: &Client,
+    address: &Address,
+    amount_sat: u64,
+) -> Result<(u64, Option<u32>), RpcError> {
+    let Some(entry) = client
+        .list_received_by_address(Some(address), Some(0), Some(true), Some(true))?
+        .into_iter()
+        .next()
+    else {
+        return Ok((0, None));
+    };
+    let mut payments = Vec::with_capacity(entry.txids.len());
+    for txid in &entry.txids {
+        let tx = client.get_transaction(txid, Some(true))?;
+        let amount: u64 = tx
+            .details
+            .iter()
+            .filter(|detail| detail.category == GetTransactionResultDetailCategory::Receive)
+            .filter(|detail| {
+                detail
+                    .address
+                    .as_ref()
+                    .is_some_and(|detail_address| detail_address == address.as_unchecked())
+            })
+            .map(|detail| detail.amount.to_sat().max(0) as u64)
+            .sum();
+        // Conflicted transactions have negative confirmations
+        if let Ok(confirmations) = u32::try_from(tx.info.confirmations) {
+            payments.push((confirmations, amount));
+        }
+    }
+    payments.sort_by_key(|(confirmations, _)| Reverse(*confirmations));
+
+    let mut total = 0;
+    let mut confirmations = None;
+    for (payment_confirmations, amount) in payments {
+        total += amount;
+        if confirmations.is_none() && total >= amount_sat {
+            confirmations = Some(payment_confirmations);
+        }
+    }
+    Ok((total, confirmations))
+}
+
+fn invoice_view(
+    data: &AppState,
+    caller: &Caller,
+    client: &Client,
+    walletid: &str,
+    invoice: Invoice,
+) -> Result<InvoiceResponse, ApiError> {
+    let (tiers, default) = policy(data, &invoice.wallet)?;
+    let (required, range) = required_confirmations(&tiers, invoice.amount_sat);
+    let address = Address::from_str(&invoice.address)
+        .map_err(|e| ApiError::Internal(format!("Stored invoice address is invalid: {e}")))?
+        .assume_checked();
+    let (received_sat, confirmations) = match received(client, &address, invoice.amount_sat) {
+        Ok(received) => received,
+        Err(e) => {
+            error!(
+                "Failed to check payments to invoice '{}' of wallet '{}': {}",
+                invoice.id, invoice.wallet, e
+            );
+            return Err(caller.rpc(e));
+        }
+    };
+    let remaining = confirmations.map_or(required, |confirmations| {
+        required.saturating_sub(confirmations)
+    });
+    let status = match confirmations {
+        Some(_) if remaining == 0 => InvoiceStatus::Paid,
+        Some(_) => InvoiceStatus::Confirming,
+        None if received_sat > 0 => InvoiceStatus::PartiallyPaid,
+        None => InvoiceStatus::Unpaid,
+    };
+
+    let mut reason = vec![format!(
+        "The {} policy of this wallet requires {} confirmation(s) for invoices {}.",
+        if default { "default" } else { "custom" },
+        required,
+        range
+    )];
+    reason.push(match status {
+        InvoiceStatus::Unpaid => "No payment has been seen yet.".to_string(),
+        InvoiceStatus::PartiallyPaid => format!(
+            "Received {} of {} sat, {} sat missing.",
+            received_sat,
+            invoice.amount_sat,
+            invoice.amount_sat - received_sat
+        ),
+        InvoiceStatus::Confirming => format!(
+            "The payment has {} confirmation(s), {} more needed.",
+            confirmations.unwrap_or_default(),
+            remaining
+        ),
+        InvoiceStatus::Paid => "The invoice is paid.".to_string(),
+    });
+
+    Ok(InvoiceResponse {
+        id: invoice.id,
+        wallet: walletid.to_string(),
+        address: invoice.address,
+        amount_sat: invoice.amount_sat,
+        description: invoice.description,
+        created_at: invoice.created_at,
+        status,
+        received_sat,
+        confirmations,
+        required_confirmations: required,
+        remaining_confirmations: remaining,
+        reason: reason.join(" "),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/invoices",
+    tag = "invoices",
+    responses(
+        (status = 200, body = InvoiceResponse),
+    )
+)]
+pub(crate) async fn create_invoice(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<CreateInvoiceRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /wallet/{}/invoices - amount={} sat",
+        walletid, req.amount_sat
+    );
+    if req.amount_sat == 0 {
+        return Err(ApiError::InvalidRequest("Amount must be positive".into()));
+    }
+    let walletid = walletid.into_inner();
+    let req = req.into_inner();
+    let view = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let wallet_name = caller.wallet_name(&walletid);
+        let Some(client) = data.wallet_client(&wallet_name) else {
+            warn!("POST /wallet/{}/invoices - wallet not found", walletid);
+            return Err(ApiError::WalletNotFound(walletid));
+        };
+
+        let id = Uuid::new_v4().simple().to_string();
+        let label = format!("invoice {id}");
+        let address = match client
+            .get_new_address(Some(&label), Some(AddressType::Bech32))
+            .map(|address| address.assume_checked())
+        {
+            Ok(address) => address,
+            Err(e) => {
+                error!(
+                    "Failed to get an invoice address for wallet '{}': {}",
+                    wallet_name, e
+                );
+                return Err(caller.rpc(e));
+            }
+        };
+        keypool::warn_if_low(&client, &walletid);
+        if let Err(e) = data
+            .db
+            .set_label(&wallet_name, &address.to_string(), &label)
+        {
+            error!(
+                "Failed to save address '{}' in address book: {}",
+                address, e
+            );
+        }
+        let invoice = Invoice {
+            id,
+            wallet: wallet_name.clone(),
+            address: address.to_string(),
+            amount_sat: req.amount_sat,
+            description: req.description,
+            created_at: unix_now(),
+        };
+        data.db.insert_invoice(&invoice)?;
+        info!(
+            "Created invoice '{}' for {} sat to {} in wallet '{}'",
+            invoice.id, invoice.amount_sat, invoice.address, wallet_name
+        );
+        invoice_view(data, caller, &client, &walletid, invoice)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(view))
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/invoices/{id}",
+    tag = "invoices",
+    responses(
+        (status = 200, body = InvoiceResponse),
+    )
+)]
+pub(crate) async fn get_invoice(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, id) = path.into_inner();
+    info!("GET /wallet/{}/invoices/{}", walletid, id);
+    let view = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let wallet_name = caller.wallet_name(&walletid);
+        let Some(client) = data.wallet_client(&wallet_name) else {
+            warn!(
+                "GET /wallet/{}/invoices/{} - wallet not found",
+                walletid, id
+            );
+            return Err(ApiError::WalletNotFound(walletid));
+        };
+        let Some(invoice) = data.db.invoice(&wallet_name, &id)? else {
+            return Err(ApiError::NotFound(format!(
+                "Invoice '{}' not found in wallet '{}'",
+                id, walletid
+            )));
+        };
+        invoice_view(data, caller, &client, &walletid, invoice)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(view))
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/confirmation-policy",
+    tag = "invoices",
+    responses(
+        (status = 200, body = PolicyResponse),
+    )
+)]
+pub(crate) async fn get_policy(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/confirmation-policy", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if data.wallet_client(&wallet_name).is_none() {
+        warn!(
+            "GET /wallet/{}/confirmation-policy - wallet not found",
+            walletid
+        );
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    }
+    let (tiers, default) = policy(&data, &wallet_name)?;
+    Ok(HttpResponse::Ok().json(PolicyResponse {
+        wallet: walletid.into_inner(),
+        default,
+        tiers,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/confirmation-policy",
+    tag = "invoices",
+    responses(
+        (status = 200, body = PolicyResponse),
+    )
+)]
+pub(crate) async fn set_policy(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<PolicyRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /wallet/{}/confirmation-policy - {} tier(s)",
+        walletid,
+        req.tiers.len()
+    );
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if data.wallet_client(&wallet_name).is_none() {
+        warn!(
+            "POST /wallet/{}/confirmation-policy - wallet not found",
+            walletid
+        );
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    }
+    validate_policy(&req.tiers)?;
+    if req.tiers.is_empty() {
+        data.db.remove_confirmation_policy(&wallet_name)?;
+        info!(
+            "Wallet '{}' uses the default confirmation policy",
+            wallet_name
+        );
+    } else {
+        data.db.set_confirmation_policy(&wallet_name, &req.tiers)?;
+        info!(
+            "Wallet '{}' confirmation policy set to {} tier(s)",
+            wallet_name,
+            req.tiers.len()
+        );
+    }
+    let (tiers, default) = policy(&data, &wallet_name)?;
+    Ok(HttpResponse::Ok().json(PolicyResponse {
+        wallet: walletid.into_inner(),
+        default,
+        tiers,
+    }))
+}
@@ -0,0 +1,170 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+// Legacy wallets hand out addresses from this pool; below this many keys a demo
+// is a few clicks away from "Keypool ran out"
+const LOW_KEYPOOL_SIZE: u64 = 10;
+// keypoolrefill derives every key up front, so very large pools take a while
+const MAX_KEYPOOL_SIZE: usize = 10_000;
+
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct RefillRequest {
+    // Defaults to the node's -keypool setting
+    new_size: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct KeypoolResponse {
+    wallet: String,
+    descriptors: bool,
+    // Pre-generated receiving keys; on descriptor wallets, per descriptor
+    keypool_size: u64,
+    // Pre-generated change keys, when the wallet keeps them separately
+    keypool_size_hd_internal: Option<u64>,
+    // Unix time of the oldest key in the pool (legacy wallets only)
+    keypool_oldest: Option<u64>,
+    // Encrypted and locked, so the pool cannot be topped up until it is unlocked
+    locked: bool,
+    warning: Option<String>,
+}
+
+fn keypool_status(client: &Client, wallet: &str) -> Result<KeypoolResponse, RpcError> {
+    // Read untyped: the typed result does not have the `descriptors` flag
+    let info: Value = client.call("getwalletinfo", &[])?;
+    let descriptors = info["descriptors"].as_bool().unwrap_or(false);
+    let keypool_size = info["keypoolsize"].as_u64().unwrap_or_default();
+    let locked = info["unlocked_until"].as_u64() == Some(0);
+
+    let warning = if !descriptors && keypool_size < LOW_KEYPOOL_SIZE {
+        Some(format!(
+            "Only {} key(s) left in the keypool of this legacy wallet. Refill it with POST /wallet/{}/keypool/refill{}.",
+            keypool_size,
+            wallet,
+            if locked { " after unlocking the wallet" } else { "" }
+        ))
+    } else if locked && keypool_size < LOW_KEYPOOL_SIZE {
+        Some(format!(
+            "Only {} key(s) left and the wallet is locked. Unlock it so the keypool can be topped up.",
+            keypool_size
+        ))
+    } else {
+        None
+    };
+
+    Ok(KeypoolResponse {
+        wallet: wallet.to_string(),
+        descriptors,
+        keypool_size,
+        keypool_size_hd_internal: info["keypoolsize_hd_internal"].as_u64(),
+        keypool_oldest: info["keypoololdest"].as_u64(),
+        locked,
+        warning,
+    })
+}
+
+// Logs when an address request has left the keypool nearly empty
+pub(crate) fn warn_if_low(client: &Client, wallet: &str) {
+    match keypool_status(client, wallet) {
+        Ok(KeypoolResponse {
+            warning: Some(warning),
+            ..
+        }) => warn!("Wallet '{}': {}", wallet, warning),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to check keypool of wallet '{}': {}", wallet, e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/keypool",
+    tag = "keypool",
+    responses(
+        (status = 200, body = KeypoolResponse),
+    )
+)]
+pub(crate) async fn get_keypool(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/keypool", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/keypool - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let wallet = walletid.clone();
+    let status = rpc_pool::blocking(move || keypool_status(&client, &wallet)).await?;
+    match status {
+        Ok(status) => Ok(HttpResponse::Ok().json(status)),
+        Err(e) => {
+            error!("Failed to read keypool of wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/keypool/refill",
+    tag = "keypool",
+    responses(
+        (status = 200, body = KeypoolResponse),
+    )
+)]
+pub(crate) async fn refill_keypool(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: Option<web::Json<RefillRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.map(web::Json::into_inner).unwrap_or_default();
+    info!(
+        "POST /wallet/{}/keypool/refill - new_size={:?}",
+        walletid, req.new_size
+    );
+    if req.new_size.is_some_and(|size| size > MAX_KEYPOOL_SIZE) {
+        return Err(ApiError::InvalidRequest(format!(
+            "new_size must be at most {}",
+            MAX_KEYPOOL_SIZE
+        )));
+    }
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!(
+            "POST /wallet/{}/keypool/refill - wallet not found",
+            walletid
+        );
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let wallet = walletid.clone();
+    let refilled = rpc_pool::blocking(move || {
+        client
+            .key_pool_refill(req.new_size)
+            .and_then(|()| keypool_status(&client, &wallet))
+    })
+    .await?;
+    match refilled {
+        Ok(status) => {
+            info!(
+                "Refilled keypool of wallet '{}' to {} key(s)",
+                wallet_name, status.keypool_size
+            );
+            Ok(HttpResponse::Ok().json(status))
+        }
+        Err(e) => {
+            error!(
+                "Failed to refill keypool of wallet '{}': {}",
+                wallet_name, e
+            );
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::errors::ApiError;
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::bip32::{Xpriv, Xpub};
+use bitcoincore_rpc::bitcoin::secp256k1::Secp256k1;
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// RPC_WALLET_ERROR: listdescriptors on a legacy wallet, or private descriptors of a
+// watch-only wallet
+const RPC_WALLET_ERROR: i32 = -4;
+
+// `expose_seed=true` enables GET /wallet/{id}/seed, on regtest only
+pub(crate) fn seed_enabled_from_env() -> bool {
+    env::var("expose_seed").is_ok_and(|value| value.trim().eq_ignore_ascii_case("true"))
+}
+
+#[derive(Serialize, ToSchema)]
+struct WalletDescriptor {
+    descriptor: String,
+    active: bool,
+    // Change addresses rather than receive addresses
+    internal: bool,
+    range: Option<Value>,
+    next_index: Option<u64>,
+    // Block time from which the wallet looks for the descriptor's transactions
+    timestamp: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DescriptorsResponse {
+    wallet: String,
+    descriptors: Vec<WalletDescriptor>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MasterKey {
+    fingerprint: String,
+    xprv: String,
+    xpub: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SeedResponse {
+    wallet: String,
+    // Bitcoin Core has no mnemonic, see `note`
+    mnemonic: Option<String>,
+    note: &'static str,
+    master_keys: Vec<MasterKey>,
+    descriptors: Vec<WalletDescriptor>,
+}
+
+fn list_descriptors(
+    caller: &Caller,
+    client: &Client,
+    walletid: &str,
+    private: bool,
+) -> Result<Vec<WalletDescriptor>, ApiError> {
+    let listed: Value = match client.call("listdescriptors", &[json!(private)]) {
+        Ok(listed) => listed,
+        Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == RPC_WALLET_ERROR => {
+            return Err(ApiError::InvalidRequest(format!(
+                "Wallet '{}' cannot list its descriptors: {}",
+                walletid, e.message
+            )))
+        }
+        Err(e) => {
+            error!("Failed to list descriptors of wallet '{}': {}", walletid, e);
+            return Err(caller.rpc(e));
+        }
+    };
+    Ok(listed["descriptors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|entry| WalletDescriptor {
+            descriptor: entry["desc"].as_str().unwrap_or_default().to_string(),
+            active: entry["active"].as_bool().unwrap_or(false),
+            internal: entry["internal"].as_bool().unwrap_or(false),
+            range: entry.get("range").cloned(),
+            next_index: entry["next_index"]
+                .as_u64()
+                .or_else(|| entry["next"].as_u64()),
+            timestamp: entry["timestamp"].as_u64(),
+        })
+        .collect())
+}
+
+// The extended private keys inside the descriptors. A wallet made by Bitcoin Core
+// derives all of them from one master key.
+fn master_keys(descriptors: &[WalletDescriptor]) -> Vec<MasterKey> {
+    let secp = Secp256k1::new();
+    let keys: BTreeSet<&str> = descriptors
+        .iter()
+        .flat_map(|descriptor| {
+            descriptor
+                .descriptor
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|token| token.starts_with("xprv") || token.starts_with("tprv"))
+        })
+        .collect();
+    keys.into_iter()
+        .filter_map(|key| Xpriv::from_str(key).ok())
+        .map(|xprv| MasterKey {
+            fingerprint: xprv.fingerprint(&secp).to_string(),
+            xpub: Xpub::from_priv(&secp, &xprv).to_string(),
+            xprv: xprv.to_string(),
+        })
+        .collect()
+}
+
+// The wallet's public descriptors, which are safe to share: they give away which
+// addresses belong to the wallet, not how to spend from them
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/descriptors",
+    tag = "keys",
+    responses(
+        (status = 200, body = DescriptorsResponse),
+    )
+)]
+pub(crate) async fn get_descriptors(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/descriptors", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/descriptors - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let caller = Caller::of(&data, &http_req);
+    let wallet = walletid.clone();
+    let descriptors =
+        rpc_pool::blocking(move || list_descriptors(&caller, &client, &wallet, false)).await??;
+    Ok(HttpResponse::Ok().json(DescriptorsResponse {
+        wallet: walletid.into_inner(),
+        descriptors,
+    }))
+}
+
+// Shows the wallet's private key material, to show that whoever has it owns the
+// coins. Off unless `expose_seed` is set, and never outside regtest.
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/seed",
+    tag = "keys",
+    responses(
+        (status = 200, body = SeedResponse),
+    )
+)]
+pub(crate) async fn get_seed(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/seed", walletid);
+    if !data.config.expose_seed {
+        return Err(ApiError::NotFound("Seed display is disabled".into()));
+    }
+    data.config.ensure_regtest("Seed display")?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/seed - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let caller = Caller::of(&data, &http_req);
+    let wallet = walletid.clone();
+    let descriptors =
+        rpc_pool::blocking(move || list_descriptors(&caller, &client, &wallet, true)).await??;
+    let master_keys = master_keys(&descriptors);
+    warn!(
+        "Private keys of wallet '{}' were displayed through /seed",
+        wallet_name
+    );
+    Ok(HttpResponse::Ok().json(SeedResponse {
+        wallet: walletid.into_inner(),
+        mnemonic: None,
+        note: "Bitcoin Core wallets start from a random master key, not a BIP39 mnemonic. The master private key and the descriptors below are the backup: anyone holding them can spend the wallet's coins.",
+        master_keys,
+        descriptors,
+    }))
+}
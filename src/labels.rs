@@ -0,0 +1,480 @@
+use crate::errors::{ApiError, Language};
+use crate::format::{format_amount, FormattedAmount};
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// RPC_WALLET_INVALID_LABEL_NAME: getaddressesbylabel for a label no address has
+const RPC_WALLET_INVALID_LABEL_NAME: i32 = -11;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SetLabelRequest {
+    label: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct RelabelRequest {
+    // Matched against the current label; `*` matches any run of characters, `?` a single one
+    pattern: String,
+    label: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LabeledAddress {
+    address: String,
+    // receive, or send for addresses the wallet paid to
+    purpose: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressOwner {
+    // Under the caller's name, without the sandbox scope
+    wallet: String,
+    is_mine: bool,
+    is_watch_only: bool,
+    label: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressOwners {
+    address: String,
+    owners: Vec<AddressOwner>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Relabeled {
+    address: String,
+    old_label: String,
+    label: String,
+}
+
+#[derive(Serialize, Default, ToSchema)]
+struct LabelUsage {
+    label: String,
+    address_count: usize,
+    total_received_sat: u64,
+    total_received_formatted: Option<FormattedAmount>,
+}
+
+// Every address in the wallet that has a label entry, paired with that label
+pub(crate) fn labeled_addresses(client: &Client) -> Result<Vec<(String, String)>, RpcError> {
+    let mut labeled = Vec::new();
+    let labels: Vec<String> = client.call("listlabels", &[])?;
+    for label in labels {
+        let addresses: Map<String, Value> = client.call("getaddressesbylabel", &[json!(label)])?;
+        labeled.extend(
+            addresses
+                .into_iter()
+                .map(|(address, _)| (address, label.clone())),
+        );
+    }
+    Ok(labeled)
+}
+
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it is currently expanded to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Groups the wallet's addresses by label; the unlabeled ones are reported under ""
+fn label_usage(client: &Client, language: Language) -> Result<Vec<LabelUsage>, RpcError> {
+    // minconf 0, include empty, include watch-only
+    let received: Vec<Value> = client.call(
+        "listreceivedbyaddress",
+        &[json!(0), json!(true), json!(true)],
+    )?;
+    let mut usage: BTreeMap<String, LabelUsage> = BTreeMap::new();
+    for entry in received {
+        let label = entry["label"].as_str().unwrap_or_default().to_string();
+        let amount = entry["amount"]
+            .as_f64()
+            .and_then(|btc| Amount::from_btc(btc).ok())
+            .unwrap_or(Amount::ZERO);
+        let group = usage.entry(label.clone()).or_insert_with(|| LabelUsage {
+            label,
+            ..Default::default()
+        });
+        group.address_count += 1;
+        group.total_received_sat += amount.to_sat();
+    }
+    Ok(usage
+        .into_values()
+        .map(|group| LabelUsage {
+            total_received_formatted: Some(format_amount(group.total_received_sat, language)),
+            ..group
+        })
+        .collect())
+}
+
+// Labels are written to the node first and then mirrored into the address book
+fn apply_label(
+    data: &AppState,
+    caller: &Caller,
+    client: &Client,
+    wallet: &str,
+    address: &str,
+    label: &str,
+) -> Result<(), ApiError> {
+    client
+        .call::<Value>("setlabel", &[json!(address), json!(label)])
+        .map_err(|e| {
+            error!(
+                "setlabel failed for '{}' in wallet '{}': {}",
+                address, wallet, e
+            );
+            caller.rpc(e)
+        })?;
+    data.db.set_label(wallet, address, label).map_err(|e| {
+        error!(
+            "Failed to mirror label for '{}' into address book: {}",
+            address, e
+        );
+        ApiError::Storage(format!(
+            "Label set on node but not saved in address book: {e}"
+        ))
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/address/{address}/label",
+    tag = "labels",
+    responses(
+        (status = 200, body = Object),
+    )
+)]
+pub(crate) async fn set_label(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+    req: web::Json<SetLabelRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, address) = path.into_inner();
+    info!(
+        "POST /wallet/{}/address/{}/label - label='{}'",
+        walletid, address, req.label
+    );
+    let req = req.into_inner();
+    let (label, labeled) = (req.label.clone(), address.clone());
+    rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let wallet_name = caller.wallet_name(&walletid);
+        let Some(client) = data.wallet_client(&wallet_name) else {
+            warn!(
+                "POST /wallet/{}/address/{}/label - wallet not found",
+                walletid, labeled
+            );
+            return Err(ApiError::WalletNotFound(walletid));
+        };
+        apply_label(data, caller, &client, &wallet_name, &labeled, &label)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "address": address, "label": req.label })))
+}
+
+// Renames every address whose current label matches the pattern
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/relabel",
+    tag = "labels",
+    responses(
+        (status = 200, body = [Relabeled]),
+    )
+)]
+pub(crate) async fn relabel(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<RelabelRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /wallet/{}/relabel - pattern='{}', label='{}'",
+        walletid, req.pattern, req.label
+    );
+    let walletid = walletid.into_inner();
+    let req = req.into_inner();
+    let relabeled = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let wallet_name = caller.wallet_name(&walletid);
+        let Some(client) = data.wallet_client(&wallet_name) else {
+            warn!("POST /wallet/{}/relabel - wallet not found", walletid);
+            return Err(ApiError::WalletNotFound(walletid));
+        };
+        let addresses = match labeled_addresses(&client) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                error!("Failed to list labels for wallet '{}': {}", wallet_name, e);
+                return Err(caller.rpc(e));
+            }
+        };
+
+        let mut relabeled = Vec::new();
+        for (address, old_label) in addresses {
+            if old_label == req.label || !matches_pattern(&req.pattern, &old_label) {
+                continue;
+            }
+            apply_label(data, caller, &client, &wallet_name, &address, &req.label)?;
+            relabeled.push(Relabeled {
+                address,
+                old_label,
+                label: req.label.clone(),
+            });
+        }
+        info!(
+            "Relabeled {} address(es) in wallet '{}'",
+            relabeled.len(),
+            wallet_name
+        );
+        Ok(relabeled)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(relabeled))
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/labels",
+    tag = "labels",
+    responses(
+        (status = 200, body = [LabelUsage]),
+    )
+)]
+pub(crate) async fn list_labels(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/labels", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/labels - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let language = Language::from_request(&http_req);
+    let usage = rpc_pool::blocking(move || label_usage(&client, language)).await?;
+    match usage {
+        Ok(usage) => Ok(HttpResponse::Ok().json(usage)),
+        Err(e) => {
+            error!("Failed to list labels for wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+fn addresses_by_label(client: &Client, label: &str) -> Result<Vec<LabeledAddress>, RpcError> {
+    let addresses: Map<String, Value> = client.call("getaddressesbylabel", &[json!(label)])?;
+    Ok(addresses
+        .into_iter()
+        .map(|(address, entry)| LabeledAddress {
+            address,
+            purpose: entry["purpose"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect())
+}
+
+// A label that no address carries is reported as not found, not as an empty list
+fn label_not_found(caller: &Caller, wallet: &str, label: &str, e: RpcError) -> ApiError {
+    match e {
+        RpcError::JsonRpc(JsonRpcError::Rpc(e)) if e.code == RPC_WALLET_INVALID_LABEL_NAME => {
+            ApiError::NotFound(format!(
+                "Wallet '{wallet}' has no address labeled '{label}'"
+            ))
+        }
+        e => {
+            error!(
+                "getaddressesbylabel failed for '{}' in wallet '{}': {}",
+                label, wallet, e
+            );
+            caller.rpc(e)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/labels/{label}/addresses",
+    tag = "labels",
+    responses(
+        (status = 200, body = [LabeledAddress]),
+    )
+)]
+pub(crate) async fn list_label_addresses(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, label) = path.into_inner();
+    info!("GET /wallet/{}/labels/{}/addresses", walletid, label);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!(
+            "GET /wallet/{}/labels/{}/addresses - wallet not found",
+            walletid, label
+        );
+        return Err(ApiError::WalletNotFound(walletid));
+    };
+    let caller = Caller::of(&data, &http_req);
+    let addresses = rpc_pool::blocking(move || {
+        addresses_by_label(&client, &label)
+            .map_err(|e| label_not_found(&caller, &walletid, &label, e))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(addresses))
+}
+
+// Moves every address of one label to another. Unlike relabel the name is matched
+// exactly, so labels containing `*` or `?` can be renamed too.
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/labels/{label}/rename",
+    tag = "labels",
+    responses(
+        (status = 200, body = [Relabeled]),
+    )
+)]
+pub(crate) async fn rename_label(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+    req: web::Json<SetLabelRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, old_label) = path.into_inner();
+    info!(
+        "POST /wallet/{}/labels/{}/rename - label='{}'",
+        walletid, old_label, req.label
+    );
+    let req = req.into_inner();
+    let relabeled = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let wallet_name = caller.wallet_name(&walletid);
+        let Some(client) = data.wallet_client(&wallet_name) else {
+            warn!(
+                "POST /wallet/{}/labels/{}/rename - wallet not found",
+                walletid, old_label
+            );
+            return Err(ApiError::WalletNotFound(walletid));
+        };
+        let addresses = addresses_by_label(&client, &old_label)
+            .map_err(|e| label_not_found(caller, &walletid, &old_label, e))?;
+
+        let mut relabeled = Vec::new();
+        if old_label != req.label {
+            for LabeledAddress { address, .. } in addresses {
+                apply_label(data, caller, &client, &wallet_name, &address, &req.label)?;
+                relabeled.push(Relabeled {
+                    address,
+                    old_label: old_label.clone(),
+                    label: req.label.clone(),
+                });
+            }
+        }
+        info!(
+            "Renamed label '{}' to '{}' on {} address(es) in wallet '{}'",
+            old_label,
+            req.label,
+            relabeled.len(),
+            wallet_name
+        );
+        Ok(relabeled)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(relabeled))
+}
+
+// Asks every loaded wallet the caller can see whether the address is its own. An
+// address can belong to more than one, e.g. a wallet and a watch-only copy of it.
+#[utoipa::path(
+    get,
+    path = "/address/{address}/owner",
+    tag = "labels",
+    responses(
+        (status = 200, body = AddressOwners),
+    )
+)]
+pub(crate) async fn get_address_owner(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    address: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /address/{}/owner", address);
+    let address = address.into_inner();
+    let parsed = Address::from_str(&address)
+        .map_err(|e| e.to_string())
+        .and_then(|addr| {
+            addr.require_network(data.config.network)
+                .map_err(|e| e.to_string())
+        })
+        .map_err(|e| ApiError::InvalidAddress(format!("Invalid address: {}", e)))?;
+    let owners = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let node = caller.node_client(data)?;
+        let loaded = node.list_wallets().map_err(|e| {
+            error!("Failed to list loaded wallets: {}", e);
+            caller.rpc(e)
+        })?;
+
+        let prefix = caller.wallet_name("");
+        let mut owners = Vec::new();
+        for wallet in loaded {
+            let Some(shown) = wallet.strip_prefix(&prefix) else {
+                continue;
+            };
+            let Some(client) = data.wallet_client(&wallet) else {
+                continue;
+            };
+            let info: Value = match client.call("getaddressinfo", &[json!(parsed.to_string())]) {
+                Ok(info) => info,
+                Err(e) => {
+                    warn!(
+                        "getaddressinfo failed for '{}' in wallet '{}': {}",
+                        parsed, wallet, e
+                    );
+                    continue;
+                }
+            };
+            let is_mine = info["ismine"].as_bool().unwrap_or(false);
+            let is_watch_only = info["iswatchonly"].as_bool().unwrap_or(false);
+            if is_mine || is_watch_only {
+                owners.push(AddressOwner {
+                    wallet: shown.to_string(),
+                    is_mine,
+                    is_watch_only,
+                    label: info["labels"][0].as_str().map(str::to_string),
+                });
+            }
+        }
+        Ok(owners)
+    })
+    .await?;
+    if owners.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "No loaded wallet owns address {}",
+            address
+        )));
+    }
+    Ok(HttpResponse::Ok().json(AddressOwners { address, owners }))
+}
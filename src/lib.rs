@@ -1,50 +1,282 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use bitcoincore_rpc::bitcoin::Network::Regtest;
-use bitcoincore_rpc::bitcoin::{Address, Amount, Network, Txid};
-use bitcoincore_rpc::bitcoincore_rpc_json::{AddressType, GetTransactionResult};
-use bitcoincore_rpc::json::LoadWalletResult;
-use bitcoincore_rpc::{Auth, Client, Error as RpcError, RpcApi};
-use dotenv as env;
-use serde::ser::SerializeStruct;
-use serde::{Deserialize, Serialize, Serializer};
-use std::str::FromStr;
 use actix_cors::Cors;
 use actix_web::http::header;
-use actix_web::middleware::Logger as ActixLogger;
+use actix_web::middleware::{from_fn, Condition};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash, Network, Txid};
+use bitcoincore_rpc::bitcoincore_rpc_json::{
+    AddressType, Bip125Replaceable, GetTransactionResult, GetTransactionResultDetailCategory,
+};
+use bitcoincore_rpc::json::LoadWalletResult;
+use bitcoincore_rpc::{Auth, Client, Error as RpcError, RpcApi};
 use dashmap::DashMap;
+use dotenv as env;
 use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing_actix_web::TracingLogger;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+mod activity;
+mod admin;
+#[cfg(feature = "async-rpc")]
+mod async_rpc;
+mod auth;
+mod backup;
+mod balances;
+mod chain_events;
+mod change;
+mod channel;
+mod cohort;
+mod dashboard;
+mod db;
+mod derivation;
+mod descriptors;
+mod dust;
+mod encryption;
+mod errors;
+mod event_replay;
+mod events;
+mod exercises;
+mod explorer;
+mod faucet;
+mod fee_bump;
+mod fees;
+mod format;
+mod health;
+mod history;
+mod hwi;
+mod invoices;
+mod keypool;
+mod keys;
+mod labels;
+mod logging;
+mod mempool;
+mod metrics;
+mod mining;
+mod multisig;
+mod musig;
+mod node;
+mod op_return;
+mod openapi;
+mod outbox;
+mod pagination;
+mod playground;
+mod propagation;
+mod proxy;
+mod psbt;
+mod rate_limit;
+mod raw_tx;
+mod recording;
+mod registry;
+mod reload;
+mod reorg;
+mod replacements;
+mod reservations;
+mod reset;
+mod routes;
+mod rpc_pool;
+mod sandbox;
+mod scenario;
+mod self_test;
+mod signer;
+mod signing;
+mod sinks;
+mod sse;
+mod stale_blocks;
+mod store;
+mod tls;
+mod transfer;
+mod tx_watch;
+mod utxos;
+mod wallet_export;
+mod watch;
+mod watch_only;
+mod ws;
+mod zmq;
+
+use change::{ChangeOptions, SentPayment};
+use db::{Storage, StorageBackend};
+use errors::{ApiError, Language};
+use events::{Event, EventBus};
+use fees::FeeOptions;
+use format::{format_amount, FormattedAmount};
+use proxy::ProxyConfig;
+use recording::Recorder;
+use reload::Settings;
+use reservations::ReservationOptions;
+use rpc_pool::{ClientPool, PooledClient};
+use sandbox::{Sandbox, SandboxConfig};
+use sinks::SinksConfig;
+use store::{begin_idempotent, Idempotency, SharedStore, StateBackend};
+use zmq::ZmqConfig;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_RPC_POOL_SIZE: usize = 4;
+// In-flight requests get this long to complete once a shutdown signal arrives
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+// All interfaces, so the service is reachable when running inside Docker
+const DEFAULT_BIND_HOST: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_BIND_PORT: u16 = 8021;
 
 // Request/Response structs for API
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct CreateWalletRequest {
     name: String,
+    fund_with_sats: Option<u64>,
+    // Creates a watch-only wallet, to be filled with POST /wallet/{id}/import-descriptor.
+    // Ignored when the wallet already exists.
+    disable_private_keys: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, ToSchema)]
+struct CreateWalletResponse {
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    result: LoadWalletResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    funding_txid: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
 struct CreateWalletAddress {
     wallet_name: String,
     name: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct MineBlockRequest {
     wallet_name: String,
     address: String,
     blocks: u64,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct SendBitcoinRequest {
     from_wallet: String,
     to_address: String,
     amount: u64,
     message: Option<String>,
+    #[serde(flatten)]
+    change: ChangeOptions,
+    #[serde(flatten)]
+    fees: FeeOptions,
+    #[serde(flatten)]
+    reservations: ReservationOptions,
+}
+
+#[derive(Serialize, ToSchema)]
+struct WalletSummary {
+    name: String,
+    // Loaded in bitcoind
+    loaded: bool,
+    // Has a client in this server, so the other endpoints can use it
+    registered: bool,
+    // Only known for registered wallets
+    balance_sat: Option<u64>,
+    balance_formatted: Option<FormattedAmount>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ChapterCompletedRequest {
+    chapter: String,
+    wallet_name: Option<String>,
 }
 
 // AppState to hold shared configuration
 struct AppState {
     config: Config,
-    clients: DashMap<String, Client>,
+    clients: DashMap<String, Arc<ClientPool>>,
+    events: EventBus,
+    store: Arc<dyn SharedStore>,
+    sandbox: Option<Sandbox>,
+    settings: RwLock<Settings>,
+    db: Box<dyn Storage>,
+    recorder: Recorder,
+    // MuSig2 playground sessions, by id
+    musig: DashMap<String, musig::MusigSession>,
+    // Scenario runs by id; finished ones are dropped by scenario::sweep_finished
+    scenarios: DashMap<String, scenario::ScenarioRun>,
+    // Generated exercises by id, dropped by exercises::sweep_expired
+    exercises: DashMap<String, exercises::Exercise>,
+    // Running event replays by id, removed when their stream closes
+    replays: DashMap<String, Arc<event_replay::Playback>>,
+    auto_mine: Mutex<Option<mining::AutoMine>>,
+    // Only with `metrics=true`
+    metrics: Option<metrics::Metrics>,
+    // Awaited JSON-RPC client for the hot paths, see rpc_pool::call
+    #[cfg(feature = "async-rpc")]
+    rpc: async_rpc::AsyncRpcClient,
+}
+
+impl AppState {
+    // Snapshot of the settings that can be reloaded at runtime
+    fn settings(&self) -> Settings {
+        match self.settings.read() {
+            Ok(settings) => settings.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    // Maps a wallet name from the API onto the wallet name used in bitcoind
+    fn wallet_name(&self, http_req: &HttpRequest, name: &str) -> String {
+        if let Some(sandbox) = &self.sandbox {
+            return sandbox.scoped_name(http_req, name);
+        }
+        match auth::user(http_req) {
+            Some(username) => auth::scoped_name(&username, name),
+            None => name.to_string(),
+        }
+    }
+
+    // Makes a wallet available to the API. Outside sandbox mode it is also saved,
+    // so it is registered again after a restart; sandbox wallets die with their session.
+    fn register_wallet(&self, wallet: String, client: Client) {
+        if self.sandbox.is_none() {
+            if let Err(e) = self.db.register_wallet(&wallet) {
+                error!("Failed to save wallet '{}' in the registry: {}", wallet, e);
+            }
+        }
+        let pool = self.config.create_pool(&wallet, client);
+        self.clients.insert(wallet, pool);
+    }
+
+    // Client for the wallet; one that bitcoind has loaded but the API never registered
+    // (e.g. loaded with bitcoin-cli) gets a client on first use, which is cached
+    fn wallet_client(&self, wallet: &str) -> Option<PooledClient> {
+        if let Some(pool) = self.clients.get(wallet) {
+            return Some(pool.get());
+        }
+        match self
+            .config
+            .create_node_client()
+            .and_then(|node| node.list_wallets())
+        {
+            Ok(loaded) if loaded.iter().any(|name| name == wallet) => {}
+            Ok(_) => return None,
+            Err(e) => {
+                warn!("Cannot check whether wallet '{}' is loaded: {}", wallet, e);
+                return None;
+            }
+        }
+        let client = match self.config.create_client(wallet) {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to create RPC client for wallet '{}': {}", wallet, e);
+                return None;
+            }
+        };
+        info!("Created RPC client for loaded wallet '{}'", wallet);
+        let pool = self.config.create_pool(wallet, client);
+        Some(self.clients.entry(wallet.to_string()).or_insert(pool).get())
+    }
 }
 
 #[derive(Debug)]
@@ -53,25 +285,46 @@ struct Config {
     rpc_user: String,
     rpc_password: String,
     server_url: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct TransactionDetails {
-    txid: String,
-    miner_input_address: String,
-    miner_input_amount: f64,
-    trader_output_address: String,
-    trader_output_amount: f64,
-    miner_change_address: String,
-    miner_change_amount: f64,
-    fee: f64,
-    block_height: u64,
-    confirmation_block_hash: String,
+    sinks: SinksConfig,
+    state: StateBackend,
+    storage: StorageBackend,
+    faucet_wallet: String,
+    sandbox: Option<SandboxConfig>,
+    hwi_path: Option<String>,
+    // Shared with bitcoind, which writes the wallet backups there
+    backup_dir: Option<PathBuf>,
+    proxy: Option<ProxyConfig>,
+    bind_addrs: Vec<SocketAddr>,
+    unix_socket: Option<PathBuf>,
+    zmq: Option<ZmqConfig>,
+    network: Network,
+    outbox: bool,
+    auto_mine_interval: Option<Duration>,
+    metrics_interval: Option<Duration>,
+    ws_tokens: Option<ws::WsTokens>,
+    // Other nodes of the toy network, watched for block propagation
+    network_nodes: Vec<propagation::NetworkNode>,
+    reset: reset::ResetConfig,
+    self_test: self_test::SelfTestMode,
+    // GET /wallet/{id}/seed shows private keys on regtest
+    expose_seed: bool,
+    signing: Option<signing::ResponseSigner>,
+    // Users mode: accounts with JWT sessions, each owning the wallets it creates
+    auth: Option<auth::AuthConfig>,
+    // Unlocks the admin routes; required for them in sandbox mode
+    admin_token: Option<auth::AdminToken>,
+    // Webhook hosts of transaction watchers that may resolve to private addresses
+    webhook_allowed_hosts: Arc<HashSet<String>>,
+    // Serves HTTPS instead of HTTP on the TCP addresses
+    tls: Option<tls::TlsConfig>,
+    shutdown_timeout: Duration,
+    // RPC clients, and so connections to bitcoind, per registered wallet
+    rpc_pool_size: usize,
 }
 
 impl Config {
     fn from_env() -> Result<Self, RpcError> {
-        Ok(Self {
+        let config = Self {
             rpc_user: env::var("user").map_err(|_| {
                 RpcError::ReturnedError("cannot load username from env file".into())
             })?,
@@ -80,152 +333,516 @@ impl Config {
             })?,
             rpc_url: env::var("rpc_url")
                 .map_err(|_| RpcError::ReturnedError("cannot load rpc-url from env file".into()))?,
-            server_url: env::var("server_url")
-                .map_err(|_| RpcError::ReturnedError("cannot load server-url from env file".into()))?,
-        })
+            server_url: env::var("server_url").map_err(|_| {
+                RpcError::ReturnedError("cannot load server-url from env file".into())
+            })?,
+            sinks: SinksConfig::from_env()?,
+            state: StateBackend::from_env()?,
+            storage: StorageBackend::from_env()?,
+            faucet_wallet: env::var("faucet_wallet").unwrap_or_else(|_| "faucet".into()),
+            sandbox: SandboxConfig::from_env()?,
+            hwi_path: env::var("hwi_path").ok(),
+            backup_dir: env::var("backup_dir").ok().map(PathBuf::from),
+            proxy: ProxyConfig::from_env(),
+            bind_addrs: bind_addrs_from_env()?,
+            unix_socket: env::var("bind_unix_socket").ok().map(PathBuf::from),
+            zmq: ZmqConfig::from_env(),
+            network: network_from_env()?,
+            outbox: outbox::enabled_from_env(),
+            auto_mine_interval: mining::interval_from_env()?,
+            metrics_interval: metrics::interval_from_env()?,
+            ws_tokens: ws::WsTokens::from_env(),
+            network_nodes: propagation::nodes_from_env()?,
+            reset: reset::ResetConfig::from_env()?,
+            self_test: self_test::SelfTestMode::from_env()?,
+            expose_seed: keys::seed_enabled_from_env(),
+            signing: signing::ResponseSigner::from_env()?,
+            auth: auth::AuthConfig::from_env()?,
+            admin_token: auth::AdminToken::from_env()?,
+            webhook_allowed_hosts: tx_watch::allowed_hosts_from_env(),
+            tls: tls::TlsConfig::from_env()?,
+            shutdown_timeout: shutdown_timeout_from_env()?,
+            rpc_pool_size: rpc_pool_size_from_env()?,
+        };
+        // Both scope the wallet names, sandbox sessions by cookie and users by token
+        if config.sandbox.is_some() && config.auth.is_some() {
+            return Err(RpcError::ReturnedError(
+                "sandbox_mode and jwt_secret cannot be combined".into(),
+            ));
+        }
+        if let Some(proxy) = &config.proxy {
+            proxy.check_direct_connections(&config.sinks, config.zmq.as_ref())?;
+        }
+        Ok(config)
+    }
+
+    // Mining on demand and the node clock are only under our control on regtest
+    fn ensure_regtest(&self, feature: &str) -> Result<(), ApiError> {
+        if self.network == Network::Regtest {
+            Ok(())
+        } else {
+            Err(ApiError::UnsupportedOnNetwork(format!(
+                "{feature} is only available on regtest, the server runs on {}",
+                self.network
+            )))
+        }
+    }
+
+    // Client for node-level RPCs that are not tied to a wallet
+    fn create_node_client(&self) -> Result<Client, RpcError> {
+        debug!("Creating node RPC client at {}", self.rpc_url);
+        self.rpc_client(&self.rpc_url)
     }
 
     fn create_client(&self, wallet: &str) -> Result<Client, RpcError> {
         let url = format!("{}/wallet/{}", self.rpc_url, wallet);
         debug!("Creating RPC client for wallet '{}' at {}", wallet, url);
-        Client::new(
-            url.as_str(),
-            Auth::UserPass(self.rpc_user.clone(), self.rpc_password.clone()),
-        )
+        self.rpc_client(&url)
+    }
+
+    // Adds clients to the one the wallet was set up with; they connect on first use
+    fn create_pool(&self, wallet: &str, first: Client) -> Arc<ClientPool> {
+        let mut clients = vec![first];
+        while clients.len() < self.rpc_pool_size {
+            match self.create_client(wallet) {
+                Ok(client) => clients.push(client),
+                Err(e) => {
+                    warn!("Wallet '{}' gets a smaller RPC client pool: {}", wallet, e);
+                    break;
+                }
+            }
+        }
+        ClientPool::new(clients)
+    }
+
+    fn rpc_client(&self, url: &str) -> Result<Client, RpcError> {
+        match &self.proxy {
+            Some(proxy) => proxy.rpc_client(url, &self.rpc_user, &self.rpc_password),
+            None => Client::new(
+                url,
+                Auth::UserPass(self.rpc_user.clone(), self.rpc_password.clone()),
+            ),
+        }
     }
 }
 
+// `bind_address` takes a comma separated list such as "127.0.0.1:8021,[::1]:8021".
+// With only `bind_unix_socket` set the server listens on the socket alone, which
+// suits deployments behind a local reverse proxy.
+// `network` is one of regtest (the default), signet or testnet and must match the node
+fn network_from_env() -> Result<Network, RpcError> {
+    match env::var("network").as_deref().map(str::trim) {
+        Err(_) | Ok("") | Ok("regtest") => Ok(Network::Regtest),
+        Ok("signet") => Ok(Network::Signet),
+        Ok("testnet") | Ok("test") => Ok(Network::Testnet),
+        Ok(other) => Err(RpcError::ReturnedError(format!(
+            "unsupported network '{other}', expected regtest, signet or testnet"
+        ))),
+    }
+}
+
+// `shutdown_timeout_secs` bounds the wait for in-flight requests on ctrl-c or SIGTERM
+fn shutdown_timeout_from_env() -> Result<Duration, RpcError> {
+    match env::var("shutdown_timeout_secs") {
+        Err(_) => Ok(DEFAULT_SHUTDOWN_TIMEOUT),
+        Ok(secs) => secs.trim().parse().map(Duration::from_secs).map_err(|_| {
+            RpcError::ReturnedError(format!(
+                "invalid shutdown_timeout_secs '{secs}', expected a number of seconds"
+            ))
+        }),
+    }
+}
+
+// `rpc_pool_size` is how many concurrent RPC calls one wallet can have in flight
+fn rpc_pool_size_from_env() -> Result<usize, RpcError> {
+    match env::var("rpc_pool_size") {
+        Err(_) => Ok(DEFAULT_RPC_POOL_SIZE),
+        Ok(size) => match size.trim().parse::<usize>() {
+            Ok(size) if size > 0 => Ok(size),
+            _ => Err(RpcError::ReturnedError(format!(
+                "invalid rpc_pool_size '{size}', expected a positive number"
+            ))),
+        },
+    }
+}
+
+fn bind_addrs_from_env() -> Result<Vec<SocketAddr>, RpcError> {
+    let host = env::var("bind_host").ok();
+    let port = env::var("bind_port").ok();
+    let addrs = match env::var("bind_address") {
+        Ok(_) if host.is_some() || port.is_some() => {
+            return Err(RpcError::ReturnedError(
+                "bind_address cannot be combined with bind_host or bind_port".into(),
+            ))
+        }
+        Ok(addrs) => addrs,
+        Err(_) if host.is_none() && port.is_none() && env::var("bind_unix_socket").is_ok() => {
+            return Ok(Vec::new())
+        }
+        Err(_) => return single_bind_addr(host.as_deref(), port.as_deref()).map(|addr| vec![addr]),
+    };
+    addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            SocketAddr::from_str(addr)
+                .map_err(|e| RpcError::ReturnedError(format!("invalid bind_address '{addr}': {e}")))
+        })
+        .collect()
+}
+
+// `bind_host` (an IPv4 or IPv6 address) and `bind_port`, each with its default
+fn single_bind_addr(host: Option<&str>, port: Option<&str>) -> Result<SocketAddr, RpcError> {
+    let host = match host.map(str::trim) {
+        None => DEFAULT_BIND_HOST,
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|e| RpcError::ReturnedError(format!("invalid bind_host '{host}': {e}")))?,
+    };
+    let port = match port.map(str::trim) {
+        None => DEFAULT_BIND_PORT,
+        Some(port) => port
+            .parse()
+            .map_err(|e| RpcError::ReturnedError(format!("invalid bind_port '{port}': {e}")))?,
+    };
+    Ok(SocketAddr::new(host, port))
+}
+
 // API handlers
+#[utoipa::path(
+    post,
+    path = "/wallet",
+    tag = "wallets",
+    responses(
+        (status = 200, body = CreateWalletResponse),
+    )
+)]
 async fn create_wallet(
     data: web::Data<AppState>,
+    http_req: HttpRequest,
     req: web::Json<CreateWalletRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     info!("POST /wallet - creating or loading wallet '{}'", req.name);
+    let watch_only = req.disable_private_keys.unwrap_or(false);
+    if watch_only && req.fund_with_sats.is_some() {
+        return Err(ApiError::InvalidRequest(
+            "A watch-only wallet has no addresses to fund until descriptors are imported".into(),
+        ));
+    }
+    if req.fund_with_sats == Some(0) {
+        return Err(ApiError::InvalidRequest(
+            "fund_with_sats must be positive, leave it out for an empty wallet".into(),
+        ));
+    }
+    let wallet_name = data.wallet_name(&http_req, &req.name);
     let config = &data.config;
-    let client = match config.create_client(&req.name) {
+    let client = match config.create_client(&wallet_name) {
         Ok(client) => client,
         Err(e) => {
-            error!("Failed to create RPC client for wallet '{}': {}", req.name, e);
-            return HttpResponse::InternalServerError().body(e.to_string());
+            error!(
+                "Failed to create RPC client for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
         }
     };
 
-    match get_wallet(&client, &req.name) {
-        Ok(result) => {
-            info!("Wallet '{}' is ready (loaded or created)", req.name);
-            let clients = &data.clients;
-            clients.insert(req.name.clone(), client);
-            HttpResponse::Ok().json(result)
+    // Creating a wallet takes a while, so it runs off the worker, and so does picking
+    // the address of the starting balance
+    let name = wallet_name.clone();
+    let (network, funded) = (data.config.network, req.fund_with_sats.is_some());
+    let (client, loaded, funding_address) = rpc_pool::blocking(move || {
+        let loaded = get_wallet(&client, &name, watch_only);
+        let funding_address = (loaded.is_ok() && funded).then(|| {
+            client
+                .get_new_address(Some("starting balance"), Some(AddressType::Bech32))
+                .map_err(|e| e.to_string())
+                .and_then(|addr| addr.require_network(network).map_err(|e| e.to_string()))
+        });
+        (client, loaded, funding_address)
+    })
+    .await?;
+    let result = match loaded {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Failed to load/create wallet '{}': {}", wallet_name, e);
+            return Err(ApiError::rpc(&http_req, e));
         }
+    };
+    info!("Wallet '{}' is ready (loaded or created)", wallet_name);
+    data.events.publish(Event::WalletReady {
+        wallet: wallet_name.clone(),
+    });
+
+    let funding_txid = match req.fund_with_sats.zip(funding_address) {
+        Some((sats, address)) => match fund_new_wallet(&data, &http_req, address, sats).await {
+            Ok(txid) => Some(txid.to_string()),
+            Err(e) => {
+                data.register_wallet(wallet_name, client);
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+
+    data.register_wallet(wallet_name, client);
+    Ok(HttpResponse::Ok().json(CreateWalletResponse {
+        result,
+        funding_txid,
+    }))
+}
+
+// Hands a freshly created wallet its starting balance from the faucet
+async fn fund_new_wallet(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    address: Result<Address, String>,
+    sats: u64,
+) -> Result<Txid, ApiError> {
+    let address = address.map_err(|e| {
+        error!("Failed to get funding address: {}", e);
+        ApiError::Internal(e)
+    })?;
+
+    if let Some(sandbox) = &data.sandbox {
+        sandbox.reserve_allowance(data, http_req, sats).await?;
+    }
+    match faucet::fund_blocking(data, address, Amount::from_sat(sats)).await? {
+        Ok(funding) => Ok(funding.txid),
         Err(e) => {
-            error!("Failed to load/create wallet '{}': {}", req.name, e);
-            HttpResponse::InternalServerError().body(e.to_string())
+            if let Some(sandbox) = &data.sandbox {
+                sandbox.release_allowance(data, http_req, sats).await;
+            }
+            error!("Faucet failed to fund new wallet: {}", e);
+            Err(ApiError::Internal(format!(
+                "Wallet is ready but funding failed: {e}"
+            )))
         }
     }
 }
 
 // Generate spendable balances in the Miner wallet
+#[utoipa::path(post, path = "/address", tag = "wallets", responses((status = 200, body = String)))]
 async fn create_address(
     data: web::Data<AppState>,
+    http_req: HttpRequest,
     req: web::Json<CreateWalletAddress>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     info!(
         "POST /address - wallet='{}', label='{}'",
         req.wallet_name, req.name
     );
-    let clients = &data.clients;
-    if let Some(client) = clients.get(&req.wallet_name) {
-        let address =
-            match client.get_new_address(Some(req.name.as_str()), Some(AddressType::Bech32)) {
-                Ok(addr) => match addr.require_network(Network::Regtest) {
-                    Ok(addr) => addr,
-                    Err(e) => {
-                        error!("Generated address wrong network for wallet '{}': {}", req.wallet_name, e);
-                        return HttpResponse::BadRequest()
-                            .body(format!("Address generated with error: {e}"));
-                    }
-                },
+    let wallet_name = data.wallet_name(&http_req, &req.wallet_name);
+    if let Some(client) = data.wallet_client(&wallet_name) {
+        // The keypool is checked on the same trip off the worker
+        let (label, shown) = (req.name.clone(), req.wallet_name.clone());
+        let generated = rpc_pool::blocking(move || {
+            let generated = client.get_new_address(Some(&label), Some(AddressType::Bech32));
+            if generated.is_ok() {
+                keypool::warn_if_low(&client, &shown);
+            }
+            generated
+        })
+        .await?;
+        let address = match generated {
+            Ok(addr) => match addr.require_network(data.config.network) {
+                Ok(addr) => addr,
                 Err(e) => {
-                    error!("Failed to get new address for wallet '{}': {}", req.wallet_name, e);
-                    return HttpResponse::BadRequest()
-                        .body(format!("Failed to generate a new address: {e}"))
+                    error!(
+                        "Generated address wrong network for wallet '{}': {}",
+                        req.wallet_name, e
+                    );
+                    return Err(ApiError::InvalidAddress(format!(
+                        "Address generated with error: {e}"
+                    )));
                 }
-            };
-        info!("New address generated for wallet '{}': {}", req.wallet_name, address);
-        HttpResponse::Ok().json(address)
+            },
+            Err(e) => {
+                error!(
+                    "Failed to get new address for wallet '{}': {}",
+                    req.wallet_name, e
+                );
+                return Err(ApiError::rpc(&http_req, e));
+            }
+        };
+        info!(
+            "New address generated for wallet '{}': {}",
+            req.wallet_name, address
+        );
+        if let Err(e) = data
+            .db
+            .set_label(&wallet_name, &address.to_string(), &req.name)
+        {
+            error!(
+                "Failed to save address '{}' in address book: {}",
+                address, e
+            );
+        }
+        Ok(HttpResponse::Ok().json(address))
     } else {
         warn!("POST /address - wallet '{}' not found", req.wallet_name);
-        HttpResponse::NotFound().body("No such wallet")
+        Err(ApiError::WalletNotFound(req.wallet_name.clone()))
     }
 }
 
-async fn get_balance(
+// Wallets on disk, loaded in bitcoind or registered here, for a wallet picker
+#[utoipa::path(
+    get,
+    path = "/wallets",
+    tag = "wallets",
+    responses(
+        (status = 200, body = [WalletSummary]),
+    )
+)]
+async fn list_wallets(
     data: web::Data<AppState>,
-    walletid: web::Path<String>,
-) -> impl Responder {
-    info!("GET /wallet/{}/balance", walletid);
-    let clients = &data.clients;
-    if let Some(client) = clients.get(walletid.as_str()) {
-        match client.get_wallet_info() {
-            Ok(info) => {
-                debug!("Wallet '{}' balance: {} sat", walletid, info.balance.to_sat());
-                HttpResponse::Ok().json(info.balance.to_sat())
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallets");
+    // In sandbox mode only the caller's own wallets are listed, under their unscoped names
+    let prefix = data.wallet_name(&http_req, "");
+    let language = Language::from_request(&http_req);
+    let caller = rpc_pool::Caller::of(&data, &http_req);
+    let wallets = rpc_pool::with_state(&data, move |data| {
+        let node = caller.node_client(data)?;
+        let loaded: Vec<String> = match node.list_wallets() {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                error!("Failed to list loaded wallets: {}", e);
+                return Err(caller.rpc(e));
             }
+        };
+        let on_disk: Vec<String> = match node.call::<serde_json::Value>("listwalletdir", &[]) {
+            Ok(dir) => dir["wallets"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|wallet| wallet["name"].as_str().map(str::to_string))
+                .collect(),
             Err(e) => {
-                error!("Failed to get balance for wallet '{}': {}", walletid, e);
-                HttpResponse::InternalServerError().body(e.to_string())
+                error!("Failed to list wallet directory: {}", e);
+                return Err(caller.rpc(e));
             }
-        }
-    } else { 
-        warn!("GET /wallet/{}/balance - wallet not found", walletid);
-        HttpResponse::NotFound().body("No such wallet") 
-    }
+        };
+
+        let mut names: std::collections::BTreeSet<String> =
+            loaded.iter().chain(&on_disk).cloned().collect();
+        names.extend(data.clients.iter().map(|entry| entry.key().clone()));
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                let shown = name.strip_prefix(&prefix)?.to_string();
+                let client = data.clients.get(&name).map(|pool| pool.get());
+                let balance_sat =
+                    client
+                        .as_ref()
+                        .and_then(|client| match client.get_balance(None, None) {
+                            Ok(balance) => Some(balance.to_sat()),
+                            Err(e) => {
+                                warn!("Failed to get balance for wallet '{}': {}", name, e);
+                                None
+                            }
+                        });
+                Some(WalletSummary {
+                    loaded: loaded.contains(&name),
+                    registered: client.is_some(),
+                    balance_sat,
+                    balance_formatted: balance_sat.map(|sat| format_amount(sat, language)),
+                    name: shown,
+                })
+            })
+            .collect::<Vec<WalletSummary>>())
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(wallets))
 }
 
+#[utoipa::path(post, path = "/mine", tag = "mining", responses((status = 200, body = [String])))]
 async fn mine_blocks(
     data: web::Data<AppState>,
+    http_req: HttpRequest,
     req: web::Json<MineBlockRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     info!(
         "POST /mine - wallet='{}', address='{}', blocks={}",
         req.wallet_name, req.address, req.blocks
     );
-    let clients = &data.clients;
-    if let Some(client) = clients.get(&req.wallet_name) {
+    data.config.ensure_regtest("Mining")?;
+    let wallet_name = data.wallet_name(&http_req, &req.wallet_name);
+    if let Some(client) = data.wallet_client(&wallet_name) {
         let address = match Address::from_str(&req.address) {
-            Ok(addr) => match addr.require_network(Network::Regtest) {
+            Ok(addr) => match addr.require_network(data.config.network) {
                 Ok(addr) => addr,
                 Err(e) => {
-                    error!("Mine request wrong network for wallet '{}': {}", req.wallet_name, e);
-                    return HttpResponse::BadRequest().body(format!("Invalid network: {}", e))
+                    error!(
+                        "Mine request wrong network for wallet '{}': {}",
+                        req.wallet_name, e
+                    );
+                    return Err(ApiError::InvalidAddress(format!("Invalid network: {}", e)));
                 }
             },
             Err(e) => {
-                error!("Mine request invalid address for wallet '{}': {}", req.wallet_name, e);
-                return HttpResponse::BadRequest().body(format!("Invalid address: {}", e))
-            },
+                error!(
+                    "Mine request invalid address for wallet '{}': {}",
+                    req.wallet_name, e
+                );
+                return Err(ApiError::InvalidAddress(format!("Invalid address: {}", e)));
+            }
         };
 
-        match client.generate_to_address(req.blocks, &address) {
+        let params = vec![json!(req.blocks), json!(address.to_string())];
+        let mined: Result<Vec<BlockHash>, _> =
+            rpc_pool::call(&data, client, &wallet_name, "generatetoaddress", params).await?;
+        match mined {
             Ok(block_hashes) => {
-                info!("Mined {} blocks to {} for wallet '{}'", req.blocks, req.address, req.wallet_name);
-                HttpResponse::Ok().json(block_hashes)
+                info!(
+                    "Mined {} blocks to {} for wallet '{}'",
+                    req.blocks, req.address, req.wallet_name
+                );
+                let hashes = block_hashes.clone();
+                rpc_pool::with_state(&data, move |data| {
+                    mining::record_blocks(
+                        data,
+                        &wallet_name,
+                        &address,
+                        &hashes,
+                        mining::SOURCE_MANUAL,
+                    )
+                })
+                .await?;
+                Ok(HttpResponse::Ok().json(block_hashes))
             }
             Err(e) => {
-                error!("Failed to mine blocks for wallet '{}': {}", req.wallet_name, e);
-                HttpResponse::InternalServerError().body(e.to_string())
+                error!(
+                    "Failed to mine blocks for wallet '{}': {}",
+                    req.wallet_name, e
+                );
+                Err(ApiError::rpc(&http_req, e))
             }
         }
     } else {
         warn!("POST /mine - wallet '{}' not found", req.wallet_name);
-        HttpResponse::NotFound().body("Wallet not found")
+        Err(ApiError::WalletNotFound(req.wallet_name.clone()))
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/send",
+    tag = "wallets",
+    responses(
+        (status = 200, description = "The txid, or the payment and its change output when `report_change` is set", body = String),
+        (status = 202, description = "Queued while the node is unreachable", body = db::OutboxEntry),
+    )
+)]
 async fn send_bitcoin(
     data: web::Data<AppState>,
+    http_req: HttpRequest,
     req: web::Json<SendBitcoinRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     info!(
         "POST /send - from='{}', to='{}', amount_sat={}, has_message={}",
         req.from_wallet,
@@ -233,179 +850,593 @@ async fn send_bitcoin(
         req.amount,
         req.message.as_ref().map(|m| !m.is_empty()).unwrap_or(false)
     );
-    let clients = &data.clients;
-    if let Some(client) = clients.get(&req.from_wallet) {
+
+    let from_wallet = data.wallet_name(&http_req, &req.from_wallet);
+    if data.config.outbox {
+        if req.change.is_set() || req.fees.is_set() || req.reservations.is_set() {
+            return Err(ApiError::InvalidRequest(
+                "Change, fee and reservation options are not supported in outbox mode".into(),
+            ));
+        }
+        return outbox::enqueue(&data, &http_req, &from_wallet, &req).await;
+    }
+
+    // Retried requests carrying the same Idempotency-Key must not pay twice
+    let idempotency_key = http_req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|key| format!("{}send:{}:{}", store::IDEMPOTENCY_PREFIX, from_wallet, key));
+    if let Some(key) = &idempotency_key {
+        match begin_idempotent(data.store.as_ref(), key, IDEMPOTENCY_TTL).await {
+            Ok(Idempotency::Started) => {}
+            Ok(Idempotency::Completed(txid)) => {
+                info!("POST /send - replaying idempotent result txid={}", txid);
+                return Ok(HttpResponse::Ok().json(txid));
+            }
+            Ok(Idempotency::InProgress) => {
+                warn!(
+                    "POST /send - idempotent request for wallet '{}' still in progress",
+                    from_wallet
+                );
+                return Err(ApiError::Conflict(
+                    "A request with this Idempotency-Key is still in progress".into(),
+                ));
+            }
+            Err(e) => {
+                error!("Failed to check idempotency key: {}", e);
+                return Err(ApiError::Storage(e));
+            }
+        }
+    }
+
+    let result = send_payment(&data, &http_req, &from_wallet, &req).await;
+
+    if let Some(key) = &idempotency_key {
+        let stored = match &result {
+            Ok(sent) => {
+                data.store
+                    .set(key, &sent.txid.to_string(), Some(IDEMPOTENCY_TTL))
+                    .await
+            }
+            Err(_) => data.store.delete(key).await,
+        };
+        if let Err(e) = stored {
+            error!("Failed to record idempotency key: {}", e);
+        }
+    }
+
+    result.map(|sent| {
+        if req.change.report_change {
+            HttpResponse::Ok().json(sent)
+        } else {
+            HttpResponse::Ok().json(sent.txid.to_string())
+        }
+    })
+}
+
+async fn send_payment(
+    data: &AppState,
+    http_req: &HttpRequest,
+    from_wallet: &str,
+    req: &SendBitcoinRequest,
+) -> Result<SentPayment, ApiError> {
+    if let Some(client) = data.wallet_client(from_wallet) {
         let to_address = match Address::from_str(&req.to_address) {
-            Ok(addr) => match addr.require_network(Regtest) {
+            Ok(addr) => match addr.require_network(data.config.network) {
                 Ok(addr) => addr,
                 Err(e) => {
-                    error!("Send invalid network from wallet '{}': {}", req.from_wallet, e);
-                    return HttpResponse::BadRequest().body(format!("Invalid network: {}", e))
+                    error!(
+                        "Send invalid network from wallet '{}': {}",
+                        req.from_wallet, e
+                    );
+                    return Err(ApiError::InvalidAddress(format!("Invalid network: {}", e)));
                 }
             },
             Err(e) => {
-                error!("Send invalid address for wallet '{}': {}", req.from_wallet, e);
-                return HttpResponse::BadRequest().body(format!("Invalid address: {}", e))
+                error!(
+                    "Send invalid address for wallet '{}': {}",
+                    req.from_wallet, e
+                );
+                return Err(ApiError::InvalidAddress(format!("Invalid address: {}", e)));
+            }
+        };
+
+        if req.change.change_address.is_some() && req.change.change_type.is_some() {
+            return Err(ApiError::InvalidRequest(
+                "Give either change_address or change_type, not both".into(),
+            ));
+        }
+        req.fees.validate()?;
+        reservations::check_spend(data, http_req, from_wallet, req.amount, &req.reservations)
+            .await?;
+        let change_address = match req.change.change_address.as_deref().map(Address::from_str) {
+            None => None,
+            Some(Ok(addr)) => match addr.require_network(data.config.network) {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    return Err(ApiError::InvalidAddress(format!(
+                        "Invalid change address network: {}",
+                        e
+                    )))
+                }
             },
+            Some(Err(e)) => {
+                return Err(ApiError::InvalidAddress(format!(
+                    "Invalid change address: {}",
+                    e
+                )))
+            }
         };
 
         let amount = Amount::from_sat(req.amount);
-        match client.send_to_address(
-            &to_address,
-            amount,
-            req.message.as_deref(),
-            None,
-            None,
-            None,
-            None,
-            None,
-        ) {
-            Ok(txid) => {
-                info!("Sent {} sat from '{}' to '{}' txid={}", req.amount, req.from_wallet, req.to_address, txid);
-                HttpResponse::Ok().json(txid.to_string())
+        let network = data.config.network;
+        // The funded-PSBT path gives control over change, but cannot store the wallet comment
+        let result = if req.change.is_set() {
+            let (change, fees) = (req.change.clone(), req.fees.clone());
+            rpc_pool::blocking(move || {
+                change::send(
+                    &client,
+                    network,
+                    &to_address,
+                    amount,
+                    change_address.as_ref(),
+                    &change,
+                    &fees,
+                )
+            })
+            .await?
+        } else {
+            let params = fees::send_to_address_params(
+                &to_address,
+                amount,
+                req.message.as_deref(),
+                &req.fees,
+            );
+            rpc_pool::call(data, client, from_wallet, "sendtoaddress", params)
+                .await?
+                .map(|txid| SentPayment { txid, change: None })
+        };
+        match result {
+            Ok(sent) => {
+                info!(
+                    "Sent {} sat from '{}' to '{}' txid={}",
+                    req.amount, req.from_wallet, req.to_address, sent.txid
+                );
+                reservations::consume(data, from_wallet, req.amount, &req.reservations);
+                data.events.publish(Event::PaymentSent {
+                    wallet: from_wallet.to_string(),
+                    to_address: req.to_address.clone(),
+                    amount_sat: req.amount,
+                    txid: sent.txid.to_string(),
+                });
+                Ok(sent)
             }
             Err(e) => {
                 error!("Failed to send from wallet '{}': {}", req.from_wallet, e);
-                HttpResponse::BadRequest().body(e.to_string())
+                Err(ApiError::rpc(http_req, e))
             }
         }
     } else {
         warn!("POST /send - wallet '{}' not found", req.from_wallet);
-        HttpResponse::NotFound().body("Wallet not found")
-    }
-}
-
-struct GetTransactionResultWrapper(GetTransactionResult);
-
-impl Serialize for GetTransactionResultWrapper {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut tx = serializer.serialize_struct("Transaction", 10)?;
-        tx.serialize_field("txid", &self.0.info.txid.to_string())?;
-        tx.serialize_field("blockhash", &self.0.info.blockhash)?;
-        tx.serialize_field("blockindex", &self.0.info.blockindex)?;
-        tx.serialize_field("blockheight", &self.0.info.blockheight.expect("REASON"))?;
-        tx.serialize_field("bip125_replaceable", &self.0.info.bip125_replaceable)?;
-        tx.serialize_field("blocktime", &self.0.info.blocktime)?;
-        tx.serialize_field("confirmations", &self.0.info.confirmations)?;
-        tx.serialize_field("time", &self.0.info.time)?;
-        tx.serialize_field("timereceived", &self.0.info.timereceived)?;
-        tx.serialize_field("wallet_conflicts", &self.0.info.wallet_conflicts)?;
-        tx.serialize_field("amount", &self.0.amount.to_btc())?;
-        // todo: fix below
-        for detail in self.0.details.iter() {
-            tx.serialize_field("address", &detail.address)?;
-            tx.serialize_field("vout", &detail.vout)?;
-            tx.serialize_field("category", &detail.category)?;
-            tx.serialize_field("label", &detail.label)?;
-        }
-        if let Some(fee) = &self.0.fee {
-            tx.serialize_field("fee", &fee.to_btc())?;
-        }
+        Err(ApiError::WalletNotFound(req.from_wallet.clone()))
+    }
+}
 
-        let encoded_tx = hex::encode(&self.0.hex);
-        tx.serialize_field("hex", &encoded_tx)?;
+// Lets the frontend announce story progress so integrations can react to it
+#[utoipa::path(
+    post,
+    path = "/chapter",
+    tag = "chapters",
+    responses(
+        (status = 200, description = "The chapter completion was recorded"),
+    )
+)]
+async fn complete_chapter(
+    data: web::Data<AppState>,
+    req: web::Json<ChapterCompletedRequest>,
+) -> impl Responder {
+    info!(
+        "POST /chapter - chapter='{}', wallet={:?}",
+        req.chapter, req.wallet_name
+    );
+    let req = req.into_inner();
+    data.events.publish(Event::ChapterCompleted {
+        wallet: req.wallet_name,
+        chapter: req.chapter,
+    });
+    HttpResponse::Ok().finish()
+}
 
-        tx.end()
-    }
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum TxState {
+    Pending,
+    Confirmed,
+    // A conflicting transaction confirmed, so this one never will
+    Conflicted,
 }
 
-async fn get_transaction(data: web::Data<AppState>, path: web::Path<(String, String)>) -> impl Responder {
-    let (walletid, txid) = path.into_inner();
-    info!("GET /tx/{}/{}", walletid, txid);
-    if let Some(client) = data.clients.get(walletid.as_str()) {
-        let txid = match Txid::from_str(&txid) {
-            Ok(id) => id,
-            Err(e) => {
-                warn!("Invalid txid format '{}': {}", txid, e);
-                return HttpResponse::BadRequest().body(format!("Invalid transaction ID: {}", e))
-            }
+// Summarises where the transaction is, so clients need not interpret null block fields
+#[derive(Serialize, ToSchema)]
+struct TxStatus {
+    state: TxState,
+    confirmed: bool,
+    confirmations: i32,
+    block_height: Option<u32>,
+}
+
+impl TxStatus {
+    fn of(info: &bitcoincore_rpc::bitcoincore_rpc_json::WalletTxInfo) -> Self {
+        let state = match info.confirmations {
+            n if n > 0 => TxState::Confirmed,
+            0 => TxState::Pending,
+            _ => TxState::Conflicted,
         };
+        TxStatus {
+            confirmed: info.confirmations > 0,
+            state,
+            confirmations: info.confirmations,
+            block_height: info.blockheight,
+        }
+    }
+}
 
-        match client.get_transaction(&txid, None) {
-            Ok(tx) => HttpResponse::Ok().json(GetTransactionResultWrapper(tx)),
-            Err(e) => {
-                error!("Transaction '{}' not found for wallet '{}': {}", txid, walletid, e);
-                HttpResponse::NotFound().body(e.to_string())
-            }
+// One wallet output or input of the transaction; a payment to yourself has both
+#[derive(Serialize, ToSchema)]
+struct TxDetail {
+    address: Option<String>,
+    vout: u32,
+    #[schema(value_type = String)]
+    category: GetTransactionResultDetailCategory,
+    // Negative for sends
+    amount: f64,
+    label: Option<String>,
+    // Only on sends
+    fee: Option<f64>,
+    abandoned: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TransactionResponse {
+    txid: String,
+    // Block fields are null until the transaction confirms
+    blockhash: Option<String>,
+    blockindex: Option<usize>,
+    blockheight: Option<u32>,
+    blocktime: Option<u64>,
+    #[schema(value_type = String)]
+    bip125_replaceable: Bip125Replaceable,
+    confirmations: i32,
+    time: u64,
+    timereceived: u64,
+    wallet_conflicts: Vec<String>,
+    status: TxStatus,
+    amount: f64,
+    fee: Option<f64>,
+    details: Vec<TxDetail>,
+    hex: String,
+}
+
+impl From<GetTransactionResult> for TransactionResponse {
+    fn from(tx: GetTransactionResult) -> Self {
+        TransactionResponse {
+            txid: tx.info.txid.to_string(),
+            blockhash: tx.info.blockhash.map(|hash| hash.to_string()),
+            blockindex: tx.info.blockindex,
+            blockheight: tx.info.blockheight,
+            blocktime: tx.info.blocktime,
+            bip125_replaceable: tx.info.bip125_replaceable,
+            confirmations: tx.info.confirmations,
+            time: tx.info.time,
+            timereceived: tx.info.timereceived,
+            wallet_conflicts: tx
+                .info
+                .wallet_conflicts
+                .iter()
+                .map(Txid::to_string)
+                .collect(),
+            status: TxStatus::of(&tx.info),
+            amount: tx.amount.to_btc(),
+            fee: tx.fee.map(|fee| fee.to_btc()),
+            details: tx
+                .details
+                .into_iter()
+                .map(|detail| TxDetail {
+                    address: detail
+                        .address
+                        .map(|address| address.assume_checked().to_string()),
+                    vout: detail.vout,
+                    category: detail.category,
+                    amount: detail.amount.to_btc(),
+                    label: detail.label,
+                    fee: detail.fee.map(|fee| fee.to_btc()),
+                    abandoned: detail.abandoned,
+                })
+                .collect(),
+            hex: hex::encode(&tx.hex),
         }
-    } else {
-        warn!("GET /tx - no active clients for wallet '{}'", walletid);
-        HttpResponse::ServiceUnavailable().body("No active clients")
     }
 }
 
-async fn get_mempool_entry(data: web::Data<AppState>, path: web::Path<(String, String)>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/tx/{walletid}/{txid}",
+    tag = "wallets",
+    responses(
+        (status = 200, body = TransactionResponse),
+    )
+)]
+async fn get_transaction(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
     let (walletid, txid) = path.into_inner();
-    info!("GET /mempool/{}/{}", walletid, txid);
-    if let Some(client) = data.clients.get(walletid.as_str()) {
+    info!("GET /tx/{}/{}", walletid, txid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if let Some(client) = data.wallet_client(&wallet_name) {
         let txid = match Txid::from_str(&txid) {
             Ok(id) => id,
             Err(e) => {
                 warn!("Invalid txid format '{}': {}", txid, e);
-                return HttpResponse::BadRequest().body(format!("Invalid transaction ID: {}", e))
+                return Err(ApiError::InvalidTxid(format!(
+                    "Invalid transaction ID: {}",
+                    e
+                )));
             }
         };
 
-        match client.get_mempool_entry(&txid) {
-            Ok(entry) => HttpResponse::Ok().json(entry),
+        let params = vec![json!(txid)];
+        match rpc_pool::call::<GetTransactionResult>(
+            &data,
+            client,
+            &wallet_name,
+            "gettransaction",
+            params,
+        )
+        .await?
+        {
+            Ok(tx) => Ok(HttpResponse::Ok().json(TransactionResponse::from(tx))),
             Err(e) => {
-                error!("Mempool entry '{}' not found for wallet '{}': {}", txid, walletid, e);
-                HttpResponse::NotFound().body(e.to_string())
+                error!(
+                    "Failed to get transaction '{}' for wallet '{}': {}",
+                    txid, wallet_name, e
+                );
+                Err(ApiError::rpc_not_found(&http_req, e))
             }
         }
     } else {
-        warn!("GET /mempool - no active clients for wallet '{}'", walletid);
-        HttpResponse::ServiceUnavailable().body("No active clients")
+        warn!("GET /tx - no active clients for wallet '{}'", wallet_name);
+        Err(ApiError::WalletNotFound(walletid))
     }
 }
 
 pub async fn run_server() -> std::io::Result<()> {
-    // Initialize logger with a sensible default so logs appear in Docker even if RUST_LOG is not set
-    let env = env_logger::Env::default().default_filter_or("info,actix_web=info");
-    env_logger::Builder::from_env(env).init();
+    // Logs use a sensible default filter so they appear in Docker even if RUST_LOG is not set
+    let settings = Settings::from_env().map_err(std::io::Error::other)?;
+    let log_format = logging::LogFormat::from_env().map_err(std::io::Error::other)?;
+    logging::init(&settings.log_filter, log_format).map_err(std::io::Error::other)?;
+    reload::warn_if_any_origin(&settings);
 
     let config = Config::from_env().expect("Failed to load config");
     info!(
         "Starting server with config: server_url={}, rpc_url={}",
         config.server_url, config.rpc_url
     );
-    let server_url = config.server_url.clone();
+    self_test::run(&config)
+        .await
+        .map_err(std::io::Error::other)?;
+    let events = EventBus::new(256);
+
+    sinks::start(&config.sinks, &events).await;
+    let store = store::connect(&config.state)
+        .await
+        .map_err(std::io::Error::other)?;
+    actix_rt::spawn(store::sweep_expired(store.clone()));
+    let db = db::open(&config.storage).map_err(std::io::Error::other)?;
+
+    let sandbox = config.sandbox.clone().map(Sandbox::new);
+    let sandbox_mode = sandbox.is_some();
+    let metrics = match config.metrics_interval {
+        Some(_) => Some(metrics::Metrics::new().map_err(std::io::Error::other)?),
+        None => None,
+    };
+    let metrics_mode = metrics.is_some();
+    #[cfg(feature = "async-rpc")]
+    let rpc = async_rpc::AsyncRpcClient::new(&config).map_err(std::io::Error::other)?;
     let app_state = web::Data::new(AppState {
         config,
         clients: DashMap::new(),
+        events,
+        store,
+        sandbox,
+        settings: RwLock::new(settings),
+        db,
+        recorder: Recorder::from_env(),
+        musig: DashMap::new(),
+        scenarios: DashMap::new(),
+        exercises: DashMap::new(),
+        replays: DashMap::new(),
+        auto_mine: Mutex::new(None),
+        metrics,
+        #[cfg(feature = "async-rpc")]
+        rpc,
     });
+    let recording_mode = app_state.recorder.is_enabled();
+    let signing_mode = app_state.config.signing.is_some();
+    let auth_mode = app_state.config.auth.is_some();
+    if sandbox_mode {
+        info!("Sandbox mode enabled, wallets are scoped to browser sessions");
+        actix_rt::spawn(sandbox::collect_idle_sessions(app_state.clone()));
+    }
+    if let Some(rate_limit) = app_state.settings().rate_limit {
+        info!("Rate limiting enabled for {:?}", rate_limit);
+    }
+    #[cfg(unix)]
+    actix_rt::spawn(reload::reload_on_sighup(app_state.clone()));
+    actix_rt::spawn(registry::restore_wallets(app_state.clone()));
+    if app_state.config.outbox {
+        info!("Outbox mode enabled, /send queues payments");
+        actix_rt::spawn(outbox::run_worker(app_state.clone()));
+    }
+    if let Some(interval) = app_state.config.auto_mine_interval {
+        actix_rt::spawn(mining::run_auto_miner(app_state.clone(), interval));
+    }
+    if let Some(interval) = app_state.config.metrics_interval {
+        actix_rt::spawn(metrics::collect_chain_metrics(app_state.clone(), interval));
+    }
+    actix_rt::spawn(reorg::track_confirmations(app_state.clone()));
+    actix_rt::spawn(watch::scan_watched_addresses(app_state.clone()));
+    actix_rt::spawn(tx_watch::run_tx_watchers(app_state.clone()));
+    actix_rt::spawn(exercises::sweep_expired(app_state.clone()));
+    actix_rt::spawn(scenario::sweep_finished(app_state.clone()));
+    actix_rt::spawn(activity::record_wallet_events(app_state.clone()));
+    actix_rt::spawn(replacements::track_replacements(app_state.clone()));
+    if let Some(zmq) = &app_state.config.zmq {
+        zmq::spawn_listeners(&app_state, zmq);
+    }
+    actix_rt::spawn(chain_events::watch_chain(app_state.clone()));
+    if !app_state.config.network_nodes.is_empty() {
+        actix_rt::spawn(propagation::watch_propagation(app_state.clone()));
+    }
 
-    // Bind to all interfaces so the service is reachable when running inside Docker
-    let bind_addr = "0.0.0.0:8021";
-    info!("Binding HTTP server at {}", bind_addr);
-    HttpServer::new(move || {
+    let bind_addrs = app_state.config.bind_addrs.clone();
+    let unix_socket = app_state.config.unix_socket.clone();
+    let tls = app_state.config.tls.clone();
+    let shutdown_timeout = app_state.config.shutdown_timeout;
+    let shutdown_state = app_state.clone();
+    let api_doc = openapi::ApiDoc::openapi();
+    let mut server = HttpServer::new(move || {
+        // Origins are checked per request so a config reload takes effect immediately
+        let state = app_state.clone();
         let cors = Cors::default()
-            .allowed_origin(server_url.as_str())
-            .allowed_methods(vec!["GET", "POST", "OPTIONS"])
-            .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT, header::CONTENT_TYPE])
+            .allowed_origin_fn(move |origin, _| {
+                let settings = state.settings();
+                settings.cors_allow_any
+                    || settings
+                        .cors_origins
+                        .iter()
+                        .any(|allowed| allowed.as_bytes() == origin.as_bytes())
+            })
+            .allowed_methods(vec!["GET", "POST", "DELETE", "OPTIONS"])
+            .allowed_headers(vec![
+                header::AUTHORIZATION,
+                header::ACCEPT,
+                header::CONTENT_TYPE,
+                rate_limit::API_KEY_HEADER,
+            ])
+            .expose_headers(vec![
+                signing::SIGNATURE_HEADER,
+                signing::SIGNATURE_ALGORITHM_HEADER,
+                routes::DEPRECATION_HEADER,
+                header::LINK,
+                header::RETRY_AFTER,
+                logging::REQUEST_ID_HEADER,
+            ])
+            .supports_credentials()
             .max_age(3600);
         App::new()
-            .wrap(ActixLogger::default())
+            // Innermost, so it sees the sandbox session and the user
+            .wrap(Condition::new(
+                recording_mode,
+                from_fn(recording::record_middleware),
+            ))
+            .wrap(Condition::new(
+                sandbox_mode,
+                from_fn(sandbox::session_middleware),
+            ))
+            .wrap(from_fn(rate_limit::rate_limit_middleware))
+            .wrap(from_fn(auth::admin_middleware))
+            .wrap(Condition::new(auth_mode, from_fn(auth::auth_middleware)))
+            .wrap(from_fn(errors::localize_errors))
+            .wrap(Condition::new(
+                signing_mode,
+                from_fn(signing::sign_middleware),
+            ))
+            .wrap(Condition::new(
+                metrics_mode,
+                from_fn(metrics::track_requests),
+            ))
+            // Inside the request span, so the access log carries the request id
+            .wrap(from_fn(logging::log_request))
+            .wrap(TracingLogger::<logging::RequestSpan>::new())
             .wrap(cors)
+            .wrap(from_fn(reload::listed_origin_credentials))
             .app_data(app_state.clone())
-            .route("/wallet", web::post().to(create_wallet))
-            .route("/address", web::post().to(create_address))
-            .route("/mine", web::post().to(mine_blocks))
-            .route("/wallet/{walletid}/balance", web::get().to(get_balance))
-            .route("/send", web::post().to(send_bitcoin))
-            .route("/tx/{walletid}/{txid}", web::get().to(get_transaction))
-            .route("/mempool/{walletid}/{txid}", web::get().to(get_mempool_entry))
-    })
-    .bind(bind_addr)?
-    .run()
-    .await
+            .route("/metrics", web::get().to(metrics::export))
+            .route("/health", web::get().to(health::health))
+            .route("/ready", web::get().to(health::ready))
+            .service(
+                SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-doc/openapi.json", api_doc.clone()),
+            )
+            .service(web::scope(routes::V1_PREFIX).configure(routes::v1))
+            // Last, as the empty scope takes every path left
+            .service(
+                web::scope("")
+                    .wrap(from_fn(routes::deprecated_alias))
+                    .configure(routes::v1),
+            )
+    });
+    for addr in bind_addrs {
+        match &tls {
+            Some(tls) => {
+                info!("Binding HTTPS server at {}", addr);
+                server = server.bind_rustls_0_23(addr, tls.server_config())?;
+            }
+            None => {
+                info!("Binding HTTP server at {}", addr);
+                server = server.bind(addr)?;
+            }
+        }
+    }
+    if let Some(path) = unix_socket {
+        #[cfg(unix)]
+        {
+            remove_stale_socket(&path)?;
+            info!("Binding HTTP server at unix:{}", path.display());
+            server = server.bind_uds(&path)?;
+        }
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "cannot bind {}: unix sockets are not supported",
+                path.display()
+            ),
+        ));
+    }
+    // On ctrl-c or SIGTERM the server stops accepting connections and lets the
+    // in-flight requests complete before run() returns
+    server
+        .shutdown_timeout(shutdown_timeout.as_secs())
+        .run()
+        .await?;
+    info!("Server stopped, unloading wallets");
+    registry::unload_wallets(&shutdown_state);
+    Ok(())
 }
 
-fn get_wallet(rpc: &Client, wallet_name: &str) -> bitcoincore_rpc::Result<LoadWalletResult> {
-    info!("Checking wallet '{}' existence and loading/creating as needed", wallet_name);
+// A socket file left behind by a previous run would make the bind fail
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+fn get_wallet(
+    rpc: &Client,
+    wallet_name: &str,
+    disable_private_keys: bool,
+) -> bitcoincore_rpc::Result<LoadWalletResult> {
+    info!(
+        "Checking wallet '{}' existence and loading/creating as needed",
+        wallet_name
+    );
     // Check if wallet exists
     let wallets = rpc.list_wallets()?;
     let wallet_exists = wallets.iter().any(|wallet| wallet == wallet_name);
@@ -432,7 +1463,7 @@ fn get_wallet(rpc: &Client, wallet_name: &str) -> bitcoincore_rpc::Result<LoadWa
     } else {
         // Try creating a new wallet
         info!("Creating new wallet '{}'", wallet_name);
-        rpc.create_wallet(wallet_name, None, None, None, None)
+        rpc.create_wallet(wallet_name, Some(disable_private_keys), None, None, None)
             .map_err(|e| {
                 if e.to_string().contains("code: -4") {
                     error!("Wallet '{}' already exists but was not listed", wallet_name);
@@ -0,0 +1,153 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::HttpMessage;
+use dotenv as env;
+use log::info;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::Span;
+use tracing_actix_web::{RequestId, RootSpanBuilder};
+use tracing_log::AsLog;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+pub(crate) const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LogFormat {
+    Text,
+    // One JSON object per line, with the request's span fields, for log shippers
+    Json,
+}
+
+impl LogFormat {
+    // `log_format` is text (the default) or json; it is only read at startup
+    pub(crate) fn from_env() -> Result<Self, String> {
+        match env::var("log_format").as_deref().map(str::trim) {
+            Err(_) | Ok("text") => Ok(Self::Text),
+            Ok("json") => Ok(Self::Json),
+            Ok(other) => Err(format!(
+                "unknown log_format '{other}', expected text or json"
+            )),
+        }
+    }
+}
+
+// Swaps the filter when the configuration is reloaded
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// Filters use the RUST_LOG syntax, e.g. "debug,actix_web=info"
+pub(crate) fn parse_filter(filter: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(filter).map_err(|e| format!("invalid log filter '{filter}': {e}"))
+}
+
+// Records from the `log` macros, ours and the RPC client's, are forwarded to tracing,
+// so they carry the span of the request they were made for
+pub(crate) fn init(filter: &str, format: LogFormat) -> Result<(), String> {
+    let (filter, handle) = reload::Layer::new(parse_filter(filter)?);
+    let (text, json) = match format {
+        LogFormat::Text => (Some(fmt::layer()), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false),
+            ),
+        ),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .try_init()
+        .map_err(|e| format!("cannot install the logger: {e}"))?;
+    // Only the first call installs a subscriber, so there is one handle
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+pub(crate) fn set_filter(filter: &str) -> Result<(), String> {
+    let Some(handle) = FILTER.get() else {
+        return Ok(());
+    };
+    let filter = parse_filter(filter)?;
+    // The `log` macros check their own max level before anything reaches tracing
+    log::set_max_level(
+        filter
+            .max_level_hint()
+            .map_or(log::LevelFilter::Trace, |level| level.as_log()),
+    );
+    handle
+        .reload(filter)
+        .map_err(|e| format!("cannot swap the log filter: {e}"))
+}
+
+// Keeps the request span short: every log line of the request repeats it
+pub(crate) struct RequestSpan;
+
+impl RootSpanBuilder for RequestSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %request.method(),
+            path = %request.path(),
+        )
+    }
+
+    // The outcome is logged by log_request, inside the span
+    fn on_request_end<B: MessageBody>(
+        _span: Span,
+        _outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+    }
+}
+
+// Logs every response with its latency and hands the request id to the client, so a
+// failed call can be found in the logs
+pub(crate) async fn log_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let request_id = req.extensions().get::<RequestId>().map(ToString::to_string);
+    let peer = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "-".into());
+    let request_line = format!("{} {}", req.method(), req.path());
+    let log = |status: StatusCode| {
+        info!(
+            "{} \"{}\" {} {:.6}",
+            peer,
+            request_line,
+            status.as_u16(),
+            started.elapsed().as_secs_f64()
+        )
+    };
+    // Middleware that turns a request away answers with an error, which becomes
+    // the response further out
+    let mut res = match next.call(req).await {
+        Ok(res) => res,
+        Err(e) => {
+            log(e.as_response_error().status_code());
+            return Err(e);
+        }
+    };
+    log(res.status());
+    if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
@@ -0,0 +1,423 @@
+use crate::errors::{ApiError, Language};
+use crate::format::{format_amount, FormattedAmount};
+use crate::node::sat_per_vb;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Amount, Txid};
+use bitcoincore_rpc::json::{GetMempoolEntryResult, GetMempoolInfoResult};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+// Upper bounds (sat/vB) of the fee rate histogram buckets; the last bucket is open-ended
+const FEE_RATE_BUCKETS: [f64; 7] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+#[derive(Deserialize, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MempoolSort {
+    // Next in line for a block first
+    #[default]
+    FeeRate,
+    // Longest waiting first
+    Time,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct MempoolQuery {
+    #[serde(default)]
+    sort: MempoolSort,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MempoolInfoResponse {
+    tx_count: usize,
+    total_vsize: usize,
+    memory_usage_bytes: usize,
+    max_memory_bytes: usize,
+    // Share of the mempool memory limit in use, 0-100
+    memory_usage_percent: f64,
+    total_fee_sat: Option<u64>,
+    min_relay_fee_sat_vb: f64,
+    mempool_min_fee_sat_vb: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MempoolTransaction {
+    txid: String,
+    vsize: u64,
+    fee_sat: u64,
+    fee_rate_sat_vb: f64,
+    // Rate of the transaction together with its unconfirmed parents, which miners go by
+    ancestor_fee_rate_sat_vb: f64,
+    entered_at: u64,
+    // Unconfirmed transactions it spends from
+    depends: Vec<String>,
+    signals_rbf: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FeeRateBucket {
+    // Fee rates from min (inclusive) up to max (exclusive); no max for the last bucket
+    min_sat_vb: f64,
+    max_sat_vb: Option<f64>,
+    tx_count: usize,
+    total_vsize: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MempoolSummary {
+    total_fee_sat: u64,
+    total_vsize: u64,
+    min_fee_rate_sat_vb: Option<f64>,
+    median_fee_rate_sat_vb: Option<f64>,
+    max_fee_rate_sat_vb: Option<f64>,
+    // Roughly what the next block would take, at 1,000,000 vB per block
+    blocks_to_clear: u64,
+    fee_rate_histogram: Vec<FeeRateBucket>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MempoolOverview {
+    info: MempoolInfoResponse,
+    summary: MempoolSummary,
+    total: usize,
+    offset: usize,
+    limit: usize,
+    // Pass as `offset` for the next page; null on the last page
+    next_offset: Option<usize>,
+    transactions: Vec<MempoolTransaction>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MempoolEntryResponse {
+    txid: String,
+    wtxid: String,
+    vsize: u64,
+    weight: Option<u64>,
+    entered_at: u64,
+    entered_at_height: u64,
+    fee_sat: u64,
+    fee_formatted: FormattedAmount,
+    modified_fee_sat: u64,
+    fee_rate_sat_vb: f64,
+    ancestor_count: u64,
+    ancestor_vsize: u64,
+    ancestor_fee_sat: u64,
+    ancestor_fee_rate_sat_vb: f64,
+    descendant_count: u64,
+    descendant_vsize: u64,
+    descendant_fee_sat: u64,
+    descendant_fee_rate_sat_vb: f64,
+    depends: Vec<String>,
+    spent_by: Vec<String>,
+    // Opted into replacement via BIP125 sequence numbers
+    signals_rbf: bool,
+    // Whether this node would accept a replacement, which full RBF allows regardless of signaling
+    replaceable: bool,
+    unbroadcast: Option<bool>,
+    assessment: String,
+}
+
+pub(crate) fn fee_rate(fee: Amount, vsize: u64) -> f64 {
+    if vsize == 0 {
+        return 0.0;
+    }
+    fee.to_sat() as f64 / vsize as f64
+}
+
+// Explains, most pressing reason first, why the transaction has not confirmed yet
+fn assess(
+    entry: &GetMempoolEntryResult,
+    mempool_min_fee: f64,
+    next_block_fee: Option<f64>,
+) -> String {
+    let own_rate = fee_rate(entry.fees.modified, entry.vsize);
+    let ancestor_rate = fee_rate(entry.fees.ancestor, entry.ancestor_size);
+    // Miners pick transactions together with their unconfirmed parents
+    let effective_rate = own_rate.min(ancestor_rate);
+
+    let mut reasons = Vec::new();
+    if effective_rate < mempool_min_fee {
+        reasons.push(format!(
+            "Its fee rate of {effective_rate:.2} sat/vB is below the mempool minimum of {mempool_min_fee:.2} sat/vB, \
+             so nodes may drop it. Bump the fee (RBF) or spend it with a higher-fee child (CPFP)."
+        ));
+    } else if entry.ancestor_count > 1 && ancestor_rate < own_rate {
+        reasons.push(format!(
+            "It depends on {} unconfirmed parent transaction(s) and the package pays only {ancestor_rate:.2} sat/vB, \
+             so the low-fee parents hold it back.",
+            entry.ancestor_count - 1
+        ));
+    } else if let Some(needed) = next_block_fee.filter(|needed| effective_rate < *needed) {
+        reasons.push(format!(
+            "Its fee rate of {effective_rate:.2} sat/vB is below the ~{needed:.2} sat/vB estimated for the next block."
+        ));
+    } else {
+        reasons.push(
+            "Its fee is competitive. On regtest blocks are only found when someone mines, so mine a block to confirm it."
+                .to_string(),
+        );
+    }
+    if entry.unbroadcast == Some(true) {
+        reasons.push(
+            "No peer has acknowledged it yet, so other nodes may not know about it.".to_string(),
+        );
+    }
+    reasons.join(" ")
+}
+
+fn describe_entry(
+    client: &Client,
+    txid: &Txid,
+    language: Language,
+) -> Result<MempoolEntryResponse, RpcError> {
+    let entry = client.get_mempool_entry(txid)?;
+    let mempool = client.get_mempool_info()?;
+    let mempool_min_fee = sat_per_vb(mempool.mempool_min_fee);
+    // Regtest rarely has enough data for an estimate, in which case it is skipped
+    let next_block_fee = client
+        .estimate_smart_fee(1, None)
+        .ok()
+        .and_then(|estimate| estimate.fee_rate)
+        .map(sat_per_vb);
+
+    Ok(MempoolEntryResponse {
+        txid: txid.to_string(),
+        wtxid: entry.wtxid.to_string(),
+        vsize: entry.vsize,
+        weight: entry.weight,
+        entered_at: entry.time,
+        entered_at_height: entry.height,
+        fee_sat: entry.fees.base.to_sat(),
+        fee_formatted: format_amount(entry.fees.base.to_sat(), language),
+        modified_fee_sat: entry.fees.modified.to_sat(),
+        fee_rate_sat_vb: fee_rate(entry.fees.modified, entry.vsize),
+        ancestor_count: entry.ancestor_count,
+        ancestor_vsize: entry.ancestor_size,
+        ancestor_fee_sat: entry.fees.ancestor.to_sat(),
+        ancestor_fee_rate_sat_vb: fee_rate(entry.fees.ancestor, entry.ancestor_size),
+        descendant_count: entry.descendant_count,
+        descendant_vsize: entry.descendant_size,
+        descendant_fee_sat: entry.fees.descendant.to_sat(),
+        descendant_fee_rate_sat_vb: fee_rate(entry.fees.descendant, entry.descendant_size),
+        depends: entry.depends.iter().map(Txid::to_string).collect(),
+        spent_by: entry.spent_by.iter().map(Txid::to_string).collect(),
+        signals_rbf: entry.bip125_replaceable,
+        replaceable: entry.bip125_replaceable || mempool.full_rbf.unwrap_or(true),
+        unbroadcast: entry.unbroadcast,
+        assessment: assess(&entry, mempool_min_fee, next_block_fee),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/mempool/{walletid}/{txid}",
+    tag = "mempool",
+    responses(
+        (status = 200, body = MempoolEntryResponse),
+    )
+)]
+pub(crate) async fn get_mempool_entry(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, txid) = path.into_inner();
+    info!("GET /mempool/{}/{}", walletid, txid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if let Some(client) = data.wallet_client(wallet_name.as_str()) {
+        let txid = match Txid::from_str(&txid) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid txid format '{}': {}", txid, e);
+                return Err(ApiError::InvalidTxid(format!(
+                    "Invalid transaction ID: {}",
+                    e
+                )));
+            }
+        };
+
+        let language = Language::from_request(&http_req);
+        let entry = rpc_pool::blocking(move || describe_entry(&client, &txid, language)).await?;
+        match entry {
+            Ok(entry) => Ok(HttpResponse::Ok().json(entry)),
+            Err(e) => {
+                error!(
+                    "Failed to get mempool entry '{}' for wallet '{}': {}",
+                    txid, wallet_name, e
+                );
+                Err(ApiError::rpc_not_found(&http_req, e))
+            }
+        }
+    } else {
+        warn!(
+            "GET /mempool - no active clients for wallet '{}'",
+            wallet_name
+        );
+        Err(ApiError::WalletNotFound(walletid))
+    }
+}
+
+// Mempool pressure at a glance; the minimum fee rises above the relay fee once it fills up
+#[utoipa::path(
+    get,
+    path = "/mempool/info",
+    tag = "mempool",
+    responses(
+        (status = 200, body = MempoolInfoResponse),
+    )
+)]
+pub(crate) async fn get_mempool_info(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /mempool/info");
+    let mempool = rpc_pool::call_node::<GetMempoolInfoResult>(&data, "getmempoolinfo", vec![]);
+    match mempool.await? {
+        Ok(mempool) => Ok(HttpResponse::Ok().json(info_response(&mempool))),
+        Err(e) => {
+            error!("Failed to fetch mempool info: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+fn info_response(mempool: &GetMempoolInfoResult) -> MempoolInfoResponse {
+    MempoolInfoResponse {
+        tx_count: mempool.size,
+        total_vsize: mempool.bytes,
+        memory_usage_bytes: mempool.usage,
+        max_memory_bytes: mempool.max_mempool,
+        memory_usage_percent: if mempool.max_mempool == 0 {
+            0.0
+        } else {
+            mempool.usage as f64 * 100.0 / mempool.max_mempool as f64
+        },
+        total_fee_sat: mempool.total_fee.map(|fee| fee.to_sat()),
+        min_relay_fee_sat_vb: sat_per_vb(mempool.min_relay_tx_fee),
+        mempool_min_fee_sat_vb: sat_per_vb(mempool.mempool_min_fee),
+    }
+}
+
+fn summarize(transactions: &[MempoolTransaction]) -> MempoolSummary {
+    // Sorted by fee rate, highest first
+    let rates: Vec<f64> = transactions.iter().map(|tx| tx.fee_rate_sat_vb).collect();
+    let total_vsize: u64 = transactions.iter().map(|tx| tx.vsize).sum();
+    let mut histogram: Vec<FeeRateBucket> = std::iter::once(0.0)
+        .chain(FEE_RATE_BUCKETS)
+        .zip(FEE_RATE_BUCKETS.map(Some).into_iter().chain([None]))
+        .map(|(min_sat_vb, max_sat_vb)| FeeRateBucket {
+            min_sat_vb,
+            max_sat_vb,
+            tx_count: 0,
+            total_vsize: 0,
+        })
+        .collect();
+    for tx in transactions {
+        let bucket = FEE_RATE_BUCKETS
+            .iter()
+            .position(|max| tx.fee_rate_sat_vb < *max)
+            .unwrap_or(FEE_RATE_BUCKETS.len());
+        histogram[bucket].tx_count += 1;
+        histogram[bucket].total_vsize += tx.vsize;
+    }
+    MempoolSummary {
+        total_fee_sat: transactions.iter().map(|tx| tx.fee_sat).sum(),
+        total_vsize,
+        min_fee_rate_sat_vb: rates.last().copied(),
+        median_fee_rate_sat_vb: rates.get(rates.len() / 2).copied(),
+        max_fee_rate_sat_vb: rates.first().copied(),
+        blocks_to_clear: total_vsize.div_ceil(1_000_000),
+        fee_rate_histogram: histogram,
+    }
+}
+
+fn overview(client: &Client, query: &MempoolQuery) -> Result<MempoolOverview, RpcError> {
+    let info = client.get_mempool_info()?;
+    let mut transactions: Vec<MempoolTransaction> = client
+        .get_raw_mempool_verbose()?
+        .into_iter()
+        .map(|(txid, entry)| MempoolTransaction {
+            txid: txid.to_string(),
+            vsize: entry.vsize,
+            fee_sat: entry.fees.base.to_sat(),
+            fee_rate_sat_vb: fee_rate(entry.fees.modified, entry.vsize),
+            ancestor_fee_rate_sat_vb: fee_rate(entry.fees.ancestor, entry.ancestor_size),
+            entered_at: entry.time,
+            depends: entry.depends.iter().map(Txid::to_string).collect(),
+            signals_rbf: entry.bip125_replaceable,
+        })
+        .collect();
+    transactions.sort_by(|a, b| {
+        b.fee_rate_sat_vb
+            .total_cmp(&a.fee_rate_sat_vb)
+            .then(a.entered_at.cmp(&b.entered_at))
+            .then(a.txid.cmp(&b.txid))
+    });
+    let summary = summarize(&transactions);
+    if let MempoolSort::Time = query.sort {
+        transactions.sort_by(|a, b| a.entered_at.cmp(&b.entered_at).then(a.txid.cmp(&b.txid)));
+    }
+
+    let total = transactions.len();
+    let offset = query.offset.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let transactions: Vec<MempoolTransaction> =
+        transactions.into_iter().skip(offset).take(limit).collect();
+    Ok(MempoolOverview {
+        info: info_response(&info),
+        summary,
+        total,
+        offset,
+        limit,
+        next_offset: Some(offset.saturating_add(limit)).filter(|next| *next < total),
+        transactions,
+    })
+}
+
+// The whole "waiting room": pool statistics and a page of the transactions in it
+#[utoipa::path(
+    get,
+    path = "/mempool",
+    tag = "mempool",
+    params(MempoolQuery),
+    responses(
+        (status = 200, body = MempoolOverview),
+    )
+)]
+pub(crate) async fn get_mempool(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<MempoolQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /mempool - offset={:?}, limit={:?}",
+        query.offset, query.limit
+    );
+    let client = match data.config.create_node_client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create node RPC client: {}", e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let query = query.into_inner();
+    match rpc_pool::blocking(move || overview(&client, &query)).await? {
+        Ok(overview) => Ok(HttpResponse::Ok().json(overview)),
+        Err(e) => {
+            error!("Failed to fetch the mempool: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use bitcoincore_rpc::{Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{debug, error, info, warn};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::time::{Duration, Instant};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+
+// `metrics=true` serves /metrics; the chain gauges are refreshed every
+// `metrics_interval_secs` (15 by default)
+pub(crate) fn interval_from_env() -> Result<Option<Duration>, RpcError> {
+    if !env::var("metrics").is_ok_and(|value| value.trim().eq_ignore_ascii_case("true")) {
+        return Ok(None);
+    }
+    match env::var("metrics_interval_secs") {
+        Err(_) => Ok(Some(DEFAULT_INTERVAL)),
+        Ok(secs) => match secs.trim().parse::<u64>() {
+            Ok(secs) if secs > 0 => Ok(Some(Duration::from_secs(secs))),
+            _ => Err(RpcError::ReturnedError(format!(
+                "invalid metrics_interval_secs '{secs}', expected a positive number of seconds"
+            ))),
+        },
+    }
+}
+
+pub(crate) struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    // 0 when the last collection could not reach the node
+    node_up: IntGauge,
+    chain_height: IntGauge,
+    mempool_transactions: IntGauge,
+    mempool_bytes: IntGauge,
+    wallets: IntGauge,
+    faucet_balance: IntGauge,
+    auto_mined_blocks: IntGauge,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("cct".into()), None)?;
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )?;
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to answer HTTP requests, by route",
+            ),
+            &["method", "route"],
+        )?;
+        let gauge = |name: &str, help: &str| -> Result<IntGauge, prometheus::Error> {
+            let gauge = IntGauge::new(name, help)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let metrics = Self {
+            node_up: gauge("node_up", "Whether the last collection reached bitcoind")?,
+            chain_height: gauge("chain_height", "Height of the node's best block")?,
+            mempool_transactions: gauge(
+                "mempool_transactions",
+                "Transactions in the node's mempool",
+            )?,
+            mempool_bytes: gauge(
+                "mempool_bytes",
+                "Virtual size of the transactions in the mempool",
+            )?,
+            wallets: gauge("wallets", "Wallets registered with the API")?,
+            faucet_balance: gauge(
+                "faucet_balance_sat",
+                "Spendable balance of the faucet wallet",
+            )?,
+            auto_mined_blocks: gauge(
+                "auto_mined_blocks",
+                "Blocks mined by the auto-miner and recorded in the database",
+            )?,
+            http_requests,
+            http_duration,
+            registry,
+        };
+        metrics
+            .registry
+            .register(Box::new(metrics.http_requests.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.http_duration.clone()))?;
+        Ok(metrics)
+    }
+
+    // Each gauge is kept at its last value when its source cannot be read
+    fn collect(&self, data: &AppState) {
+        self.wallets.set(data.clients.len() as i64);
+        match data.db.miner_stats() {
+            Ok(stats) => self
+                .auto_mined_blocks
+                .set(stats.iter().map(|miner| miner.auto_blocks as i64).sum()),
+            Err(e) => warn!("Metrics cannot read the mined blocks: {}", e),
+        }
+
+        let node = match data.config.create_node_client() {
+            Ok(node) => node,
+            Err(e) => {
+                warn!("Metrics cannot create a node client: {}", e);
+                self.node_up.set(0);
+                return;
+            }
+        };
+        match node.get_block_count() {
+            Ok(height) => {
+                self.node_up.set(1);
+                self.chain_height.set(height as i64);
+            }
+            Err(e) => {
+                debug!("Metrics cannot reach the node: {}", e);
+                self.node_up.set(0);
+                return;
+            }
+        }
+        match node.get_mempool_info() {
+            Ok(mempool) => {
+                self.mempool_transactions.set(mempool.size as i64);
+                self.mempool_bytes.set(mempool.bytes as i64);
+            }
+            Err(e) => warn!("Metrics cannot read the mempool: {}", e),
+        }
+        // The faucet wallet is only loaded once the faucet was used
+        if let Some(faucet) = data.wallet_client(&data.config.faucet_wallet) {
+            match faucet.get_balances() {
+                Ok(balances) => self
+                    .faucet_balance
+                    .set(balances.mine.trusted.to_sat() as i64),
+                Err(e) => warn!("Metrics cannot read the faucet balance: {}", e),
+            }
+        }
+    }
+}
+
+pub(crate) async fn collect_chain_metrics(data: web::Data<AppState>, interval: Duration) {
+    if data.metrics.is_none() {
+        return;
+    }
+    info!("Collecting chain metrics every {} s", interval.as_secs());
+    loop {
+        // The collection waits on bitcoind, so it runs on the blocking pool
+        let collected = rpc_pool::with_state(&data, |data| {
+            if let Some(metrics) = &data.metrics {
+                metrics.collect(data);
+            }
+        });
+        if let Err(e) = collected.await {
+            warn!("Failed to collect chain metrics: {}", e);
+        }
+        actix_rt::time::sleep(interval).await;
+    }
+}
+
+// Counts requests by route pattern, so wallet names and txids do not become labels
+pub(crate) async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let metrics = req.app_data::<web::Data<AppState>>().cloned();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+    let res = next.call(req).await?;
+    if let Some(metrics) = metrics.as_ref().and_then(|data| data.metrics.as_ref()) {
+        metrics
+            .http_requests
+            .with_label_values(&[method.as_str(), route.as_str(), res.status().as_str()])
+            .inc();
+        metrics
+            .http_duration
+            .with_label_values(&[method.as_str(), route.as_str()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    Ok(res)
+}
+
+// Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"),
+    )
+)]
+pub(crate) async fn export(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    debug!("GET /metrics");
+    let Some(metrics) = &data.metrics else {
+        return Err(ApiError::NotFound("Metrics are disabled".into()));
+    };
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut body) {
+        error!("Failed to encode metrics: {}", e);
+        return Err(ApiError::Internal(e.to_string()));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body))
+}
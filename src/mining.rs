@@ -0,0 +1,545 @@
+use crate::db::unix_now;
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::{rpc_pool, AppState};
+use actix_rt::task::JoinHandle;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, BlockHash};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::{Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+pub(crate) const SOURCE_AUTO: &str = "auto";
+pub(crate) const SOURCE_MANUAL: &str = "manual";
+const MAX_WEIGHT: u32 = 1000;
+const MAX_AUTO_MINE_INTERVAL_SECS: u64 = 86_400;
+
+// `auto_mine_interval_secs` starts the auto-miner, which mines one block per interval
+// for one of the registered miners
+pub(crate) fn interval_from_env() -> Result<Option<Duration>, RpcError> {
+    match env::var("auto_mine_interval_secs") {
+        Err(_) => Ok(None),
+        Ok(secs) => match secs.trim().parse::<u64>() {
+            Ok(secs) if secs > 0 => Ok(Some(Duration::from_secs(secs))),
+            _ => Err(RpcError::ReturnedError(format!(
+                "invalid auto_mine_interval_secs '{secs}', expected a positive number of seconds"
+            ))),
+        },
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct MinerRequest {
+    wallet_name: String,
+    // Relative hash rate; 0 stops the wallet from mining
+    weight: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MinerView {
+    wallet: String,
+    weight: u32,
+    // Expected fraction of the auto-mined blocks
+    share: f64,
+    auto_blocks: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MinersResponse {
+    interval_secs: Option<u64>,
+    miners: Vec<MinerView>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LeaderboardEntry {
+    rank: usize,
+    wallet: String,
+    blocks: u64,
+    auto_blocks: u64,
+    manual_blocks: u64,
+    // Fraction of all blocks mined through this server
+    share: f64,
+    last_mined_at: i64,
+}
+
+// Smooth weighted round robin: every round each miner gains its weight, and the one
+// furthest ahead mines and pays back the total. Over any stretch of blocks each miner
+// gets its share, spread out instead of in bursts.
+#[derive(Default)]
+struct Scheduler {
+    current: HashMap<String, i64>,
+}
+
+impl Scheduler {
+    fn next(&mut self, miners: &[(String, u32)]) -> Option<String> {
+        self.current
+            .retain(|wallet, _| miners.iter().any(|(miner, _)| miner == wallet));
+        let total: i64 = miners.iter().map(|(_, weight)| i64::from(*weight)).sum();
+        let mut chosen: Option<(&String, i64)> = None;
+        for (wallet, weight) in miners {
+            let current = self.current.entry(wallet.clone()).or_default();
+            *current += i64::from(*weight);
+            if chosen.is_none_or(|(_, best)| *current > best) {
+                chosen = Some((wallet, *current));
+            }
+        }
+        let (wallet, _) = chosen?;
+        if let Some(current) = self.current.get_mut(wallet) {
+            *current -= total;
+        }
+        Some(wallet.clone())
+    }
+}
+
+// Journals who mined the blocks, for the leaderboard, and tells the event sinks
+pub(crate) fn record_blocks(
+    data: &AppState,
+    wallet: &str,
+    address: &Address,
+    block_hashes: &[BlockHash],
+    source: &str,
+) {
+    match data.config.create_node_client() {
+        // Other blocks may have been mined meanwhile, so the tip says nothing about
+        // these; each block's height is asked for
+        Ok(node) => {
+            for hash in block_hashes {
+                let height = match node.get_block_header_info(hash) {
+                    Ok(header) => header.height as u64,
+                    Err(e) => {
+                        warn!(
+                            "Cannot attribute block {} to wallet '{}': {}",
+                            hash, wallet, e
+                        );
+                        continue;
+                    }
+                };
+                if let Err(e) =
+                    data.db
+                        .record_mined_block(&hash.to_string(), height, wallet, source)
+                {
+                    error!(
+                        "Failed to record block {} for wallet '{}': {}",
+                        hash, wallet, e
+                    );
+                }
+            }
+        }
+        Err(e) => warn!(
+            "Cannot attribute {} block(s) to wallet '{}': {}",
+            block_hashes.len(),
+            wallet,
+            e
+        ),
+    }
+    data.events.publish(Event::BlocksMined {
+        wallet: wallet.to_string(),
+        address: address.to_string(),
+        block_hashes: block_hashes.iter().map(|hash| hash.to_string()).collect(),
+    });
+}
+
+// Stops a wallet that is gone from mining, so the auto-miner does not keep picking it
+pub(crate) fn forget_miner(data: &AppState, wallet: &str) {
+    match data.db.remove_miner(wallet) {
+        Ok(true) => info!("Wallet '{}' is gone and stopped mining", wallet),
+        Ok(false) => {}
+        Err(e) => error!("Failed to remove miner '{}': {}", wallet, e),
+    }
+}
+
+fn mine_for(data: &AppState, wallet: &str) -> Result<BlockHash, String> {
+    let client = data
+        .wallet_client(wallet)
+        .ok_or_else(|| "wallet not found".to_string())?;
+    let address = client
+        .get_new_address(Some("auto-mined"), Some(AddressType::Bech32))
+        .map_err(|e| e.to_string())?
+        .require_network(data.config.network)
+        .map_err(|e| e.to_string())?;
+    let block_hashes = client
+        .generate_to_address(1, &address)
+        .map_err(|e| e.to_string())?;
+    record_blocks(data, wallet, &address, &block_hashes, SOURCE_AUTO);
+    block_hashes
+        .first()
+        .copied()
+        .ok_or_else(|| "the node mined no block".to_string())
+}
+
+pub(crate) async fn run_auto_miner(data: web::Data<AppState>, interval: Duration) {
+    if let Err(e) = data.config.ensure_regtest("Auto-mining") {
+        warn!("Auto-miner not started: {}", e);
+        return;
+    }
+    info!("Auto-miner mines a block every {:?}", interval);
+    let mut scheduler = Scheduler::default();
+    loop {
+        actix_rt::time::sleep(interval).await;
+        let miners = match data.db.miners() {
+            Ok(miners) => miners,
+            Err(e) => {
+                error!("Failed to read the registered miners: {}", e);
+                continue;
+            }
+        };
+        let Some(wallet) = scheduler.next(&miners) else {
+            continue;
+        };
+        if let Err(e) = mine_for(&data, &wallet) {
+            warn!("Auto-miner could not mine for wallet '{}': {}", wallet, e);
+        }
+    }
+}
+
+fn miners_response(data: &AppState, http_req: &HttpRequest) -> Result<MinersResponse, ApiError> {
+    let prefix = data.wallet_name(http_req, "");
+    let all = data.db.miners()?;
+    let total: u32 = all.iter().map(|(_, weight)| weight).sum();
+    let auto_blocks: HashMap<String, u64> = data
+        .db
+        .miner_stats()?
+        .into_iter()
+        .map(|stats| (stats.wallet, stats.auto_blocks))
+        .collect();
+    let miners = all
+        .iter()
+        .filter_map(|(wallet, weight)| {
+            Some(MinerView {
+                wallet: wallet.strip_prefix(&prefix)?.to_string(),
+                weight: *weight,
+                share: f64::from(*weight) / f64::from(total.max(1)),
+                auto_blocks: auto_blocks.get(wallet).copied().unwrap_or_default(),
+            })
+        })
+        .collect();
+    Ok(MinersResponse {
+        interval_secs: data
+            .config
+            .auto_mine_interval
+            .map(|interval| interval.as_secs()),
+        miners,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/mine/miners",
+    tag = "mining",
+    responses(
+        (status = 200, body = MinersResponse),
+    )
+)]
+pub(crate) async fn set_miner(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<MinerRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /mine/miners - wallet='{}', weight={}",
+        req.wallet_name, req.weight
+    );
+    data.config.ensure_regtest("Auto-mining")?;
+    if req.weight > MAX_WEIGHT {
+        return Err(ApiError::InvalidRequest(format!(
+            "weight must be at most {}",
+            MAX_WEIGHT
+        )));
+    }
+    let wallet_name = data.wallet_name(&http_req, &req.wallet_name);
+    if req.weight == 0 {
+        if !data.db.remove_miner(&wallet_name)? {
+            return Err(ApiError::NotFound(format!(
+                "Wallet '{}' is not a miner",
+                req.wallet_name
+            )));
+        }
+        info!("Wallet '{}' stopped mining", wallet_name);
+    } else {
+        if data.wallet_client(&wallet_name).is_none() {
+            warn!("POST /mine/miners - wallet '{}' not found", req.wallet_name);
+            return Err(ApiError::WalletNotFound(req.wallet_name.clone()));
+        }
+        data.db.set_miner(&wallet_name, req.weight)?;
+        info!("Wallet '{}' mines with weight {}", wallet_name, req.weight);
+    }
+    Ok(HttpResponse::Ok().json(miners_response(&data, &http_req)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/mine/miners",
+    tag = "mining",
+    responses(
+        (status = 200, body = MinersResponse),
+    )
+)]
+pub(crate) async fn list_miners(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /mine/miners");
+    Ok(HttpResponse::Ok().json(miners_response(&data, &http_req)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/mine/leaderboard",
+    tag = "mining",
+    responses(
+        (status = 200, body = [LeaderboardEntry]),
+    )
+)]
+pub(crate) async fn leaderboard(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /mine/leaderboard");
+    let prefix = data.wallet_name(&http_req, "");
+    let stats: Vec<_> = data
+        .db
+        .miner_stats()?
+        .into_iter()
+        .filter(|stats| stats.wallet.starts_with(&prefix))
+        .collect();
+    let total: u64 = stats.iter().map(|stats| stats.blocks).sum();
+    let entries: Vec<LeaderboardEntry> = stats
+        .into_iter()
+        .enumerate()
+        .map(|(index, stats)| LeaderboardEntry {
+            rank: index + 1,
+            wallet: stats.wallet[prefix.len()..].to_string(),
+            blocks: stats.blocks,
+            auto_blocks: stats.auto_blocks,
+            manual_blocks: stats.blocks - stats.auto_blocks,
+            share: stats.blocks as f64 / total.max(1) as f64,
+            last_mined_at: stats.last_mined_at,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct AutoMineRequest {
+    // Mines to a fresh address of the wallet, or to a fixed address
+    wallet: Option<String>,
+    address: Option<String>,
+    interval_secs: Option<u64>,
+    enabled: Option<bool>,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+struct AutoMineStatus {
+    wallet: Option<String>,
+    address: Option<String>,
+    interval_secs: u64,
+    started_at: i64,
+    blocks_mined: u64,
+    last_block_hash: Option<String>,
+    last_mined_at: Option<i64>,
+    // Why the last block could not be mined; the miner keeps trying
+    last_error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AutoMineResponse {
+    enabled: bool,
+    #[serde(flatten)]
+    status: Option<AutoMineStatus>,
+}
+
+#[derive(Clone)]
+enum AutoMineTarget {
+    // The scoped wallet name
+    Wallet(String),
+    Address(Address),
+}
+
+// The miner started with POST /mine/auto; at most one runs at a time
+pub(crate) struct AutoMine {
+    status: AutoMineStatus,
+    task: JoinHandle<()>,
+}
+
+fn auto_mine(data: &AppState) -> MutexGuard<'_, Option<AutoMine>> {
+    data.auto_mine
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn mine_to_address(data: &AppState, address: &Address) -> Result<BlockHash, String> {
+    data.config
+        .create_node_client()
+        .and_then(|node| node.generate_to_address(1, address))
+        .map_err(|e| e.to_string())?
+        .first()
+        .copied()
+        .ok_or_else(|| "the node mined no block".to_string())
+}
+
+async fn run_auto_mine(data: web::Data<AppState>, target: AutoMineTarget, interval: Duration) {
+    loop {
+        actix_rt::time::sleep(interval).await;
+        let mined = {
+            let (data, target) = (data.clone(), target.clone());
+            rpc_pool::blocking(move || match &target {
+                AutoMineTarget::Wallet(wallet) => mine_for(&data, wallet),
+                AutoMineTarget::Address(address) => mine_to_address(&data, address),
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|mined| mined)
+        };
+        if let Err(e) = &mined {
+            warn!("Auto-mining failed: {}", e);
+        }
+        if let Some(auto_mine) = auto_mine(&data).as_mut() {
+            let status = &mut auto_mine.status;
+            match mined {
+                Ok(hash) => {
+                    status.blocks_mined += 1;
+                    status.last_block_hash = Some(hash.to_string());
+                    status.last_mined_at = Some(unix_now());
+                    status.last_error = None;
+                }
+                Err(e) => status.last_error = Some(e),
+            }
+        }
+    }
+}
+
+fn auto_mine_response(current: &Option<AutoMine>) -> AutoMineResponse {
+    let status = current.as_ref().map(|auto_mine| auto_mine.status.clone());
+    AutoMineResponse {
+        enabled: status.is_some(),
+        status,
+    }
+}
+
+fn abort_auto_mine(current: &mut Option<AutoMine>) {
+    if let Some(previous) = current.take() {
+        previous.task.abort();
+        info!(
+            "Stopped auto-mining after {} block(s)",
+            previous.status.blocks_mined
+        );
+    }
+}
+
+// Starts mining one block per interval in the background, replacing a miner started
+// earlier. `enabled: false` stops it.
+#[utoipa::path(
+    post,
+    path = "/mine/auto",
+    tag = "mining",
+    responses(
+        (status = 200, body = AutoMineResponse),
+    )
+)]
+pub(crate) async fn start_auto_mine(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<AutoMineRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /mine/auto - wallet={:?}, address={:?}, interval_secs={:?}, enabled={:?}",
+        req.wallet, req.address, req.interval_secs, req.enabled
+    );
+    data.config.ensure_regtest("Auto-mining")?;
+    if !req.enabled.unwrap_or(true) {
+        let mut current = auto_mine(&data);
+        abort_auto_mine(&mut current);
+        return Ok(HttpResponse::Ok().json(auto_mine_response(&current)));
+    }
+    let interval_secs = req.interval_secs.ok_or_else(|| {
+        ApiError::InvalidRequest("interval_secs is needed to start auto-mining".into())
+    })?;
+    if !(1..=MAX_AUTO_MINE_INTERVAL_SECS).contains(&interval_secs) {
+        return Err(ApiError::InvalidRequest(format!(
+            "interval_secs must be between 1 and {}",
+            MAX_AUTO_MINE_INTERVAL_SECS
+        )));
+    }
+    let target = match (&req.wallet, &req.address) {
+        (Some(wallet), None) => {
+            let wallet_name = data.wallet_name(&http_req, wallet);
+            if data.wallet_client(&wallet_name).is_none() {
+                warn!("POST /mine/auto - wallet '{}' not found", wallet);
+                return Err(ApiError::WalletNotFound(wallet.clone()));
+            }
+            AutoMineTarget::Wallet(wallet_name)
+        }
+        (None, Some(address)) => {
+            let address = Address::from_str(address)
+                .map_err(|e| e.to_string())
+                .and_then(|addr| {
+                    addr.require_network(data.config.network)
+                        .map_err(|e| e.to_string())
+                })
+                .map_err(|e| ApiError::InvalidAddress(format!("Invalid address: {}", e)))?;
+            AutoMineTarget::Address(address)
+        }
+        _ => {
+            return Err(ApiError::InvalidRequest(
+                "Give either wallet or address".into(),
+            ))
+        }
+    };
+
+    let status = AutoMineStatus {
+        wallet: req.wallet.clone(),
+        address: req.address.clone(),
+        interval_secs,
+        started_at: unix_now(),
+        blocks_mined: 0,
+        last_block_hash: None,
+        last_mined_at: None,
+        last_error: None,
+    };
+    // One guard for the swap, so two concurrent starts cannot both leave a miner running
+    let mut current = auto_mine(&data);
+    abort_auto_mine(&mut current);
+    let task = actix_rt::spawn(run_auto_mine(
+        data.clone(),
+        target,
+        Duration::from_secs(interval_secs),
+    ));
+    *current = Some(AutoMine { status, task });
+    info!("Auto-mining a block every {} s", interval_secs);
+    Ok(HttpResponse::Ok().json(auto_mine_response(&current)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/mine/auto",
+    tag = "mining",
+    responses(
+        (status = 200, body = AutoMineResponse),
+    )
+)]
+pub(crate) async fn get_auto_mine(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("GET /mine/auto");
+    Ok(HttpResponse::Ok().json(auto_mine_response(&auto_mine(&data))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/mine/auto",
+    tag = "mining",
+    responses(
+        (status = 200, body = AutoMineResponse),
+    )
+)]
+pub(crate) async fn stop_auto_mine(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("DELETE /mine/auto");
+    let mut current = auto_mine(&data);
+    abort_auto_mine(&mut current);
+    Ok(HttpResponse::Ok().json(auto_mine_response(&current)))
+}
@@ -0,0 +1,414 @@
+use crate::db::{unix_now, MultisigParticipant, MultisigWallet};
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// Standardness allows at most 15 keys in a P2WSH multisig that relays everywhere
+const MAX_PARTICIPANTS: usize = 15;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateMultisigRequest {
+    // Name of the watch-only wallet that tracks the multisig address
+    name: String,
+    // Signatures needed to spend, the N in N-of-M
+    required: u32,
+    // Registered wallets contributing one key each
+    wallets: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct FundMultisigRequest {
+    from_wallet: String,
+    amount_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MultisigResponse {
+    name: String,
+    address: String,
+    descriptor: String,
+    required: u32,
+    total: usize,
+    participants: Vec<MultisigParticipant>,
+    // None right after creation, or when the wallet is not loaded
+    balance_sat: Option<u64>,
+    created_at: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FundMultisigResponse {
+    txid: String,
+    address: String,
+    amount_sat: u64,
+}
+
+fn multisig_view(data: &AppState, caller: &Caller, multisig: MultisigWallet) -> MultisigResponse {
+    let prefix = caller.wallet_name("");
+    let unscoped = |wallet: &str| wallet.strip_prefix(&prefix).unwrap_or(wallet).to_string();
+    let balance_sat = data.wallet_client(&multisig.wallet).and_then(|client| {
+        match client.get_balance(None, None) {
+            Ok(balance) => Some(balance.to_sat()),
+            Err(e) => {
+                warn!(
+                    "Failed to get balance of multisig wallet '{}': {}",
+                    multisig.wallet, e
+                );
+                None
+            }
+        }
+    });
+    MultisigResponse {
+        name: unscoped(&multisig.wallet),
+        address: multisig.address,
+        descriptor: multisig.descriptor,
+        required: multisig.required,
+        total: multisig.participants.len(),
+        participants: multisig
+            .participants
+            .into_iter()
+            .map(|participant| MultisigParticipant {
+                wallet: unscoped(&participant.wallet),
+                ..participant
+            })
+            .collect(),
+        balance_sat,
+        created_at: multisig.created_at,
+    }
+}
+
+// A fresh key of the wallet, with its origin so the wallet recognises it in PSBTs
+fn participant_key(
+    client: &Client,
+    wallet: &str,
+    label: &str,
+) -> Result<MultisigParticipant, RpcError> {
+    let address = client
+        .get_new_address(Some(label), Some(AddressType::Bech32))?
+        .assume_checked();
+    let info: Value = client.call("getaddressinfo", &[json!(address.to_string())])?;
+    let public_key = info["pubkey"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError(format!("no public key for {address}")))?;
+    // desc looks like wpkh([d34db33f/84h/1h/0h/0/3]03ab...)#checksum
+    let key_origin = info["desc"].as_str().and_then(|desc| {
+        let start = desc.find('[')?;
+        let end = desc.find(']')?;
+        Some(desc[start..=end].to_string())
+    });
+    Ok(MultisigParticipant {
+        wallet: wallet.to_string(),
+        public_key: public_key.to_string(),
+        key_origin,
+    })
+}
+
+// The multisig lives in its own watch-only descriptor wallet, which builds the PSBTs;
+// the participants' wallets only sign
+fn create_watch_only(
+    node: &Client,
+    client: &Client,
+    name: &str,
+    descriptor: &str,
+) -> Result<String, RpcError> {
+    let info: Value = node.call("getdescriptorinfo", &[json!(descriptor)])?;
+    let descriptor = info["descriptor"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError("getdescriptorinfo returned no descriptor".into()))?
+        .to_string();
+    let addresses: Vec<String> = node.call("deriveaddresses", &[json!(descriptor)])?;
+    let address = addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| RpcError::ReturnedError("the descriptor has no address".into()))?;
+
+    // createwallet name disable_private_keys blank passphrase avoid_reuse descriptors
+    client.call::<Value>(
+        "createwallet",
+        &[
+            json!(name),
+            json!(true),
+            json!(true),
+            json!(""),
+            json!(false),
+            json!(true),
+        ],
+    )?;
+    let results: Vec<Value> = client.call(
+        "importdescriptors",
+        &[json!([{ "desc": descriptor, "timestamp": "now", "label": "multisig" }])],
+    )?;
+    if let Some(failed) = results
+        .iter()
+        .find(|result| result["success"] != json!(true))
+    {
+        return Err(RpcError::ReturnedError(format!(
+            "descriptor import failed: {}",
+            failed["error"]
+        )));
+    }
+    Ok(address)
+}
+
+// Takes a key from each wallet and sets up the watch-only multisig wallet `name`
+pub(crate) async fn create(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    name: &str,
+    required: u32,
+    wallets: &[String],
+) -> Result<MultisigWallet, ApiError> {
+    let (name, wallets) = (name.to_string(), wallets.to_vec());
+    let (multisig, client) = rpc_pool::for_caller(data, http_req, move |data, caller| {
+        set_up(data, caller, &name, required, &wallets)
+    })
+    .await?;
+    data.events.publish(Event::WalletReady {
+        wallet: multisig.wallet.clone(),
+    });
+    data.register_wallet(multisig.wallet.clone(), client);
+    Ok(multisig)
+}
+
+fn set_up(
+    data: &AppState,
+    caller: &Caller,
+    name: &str,
+    required: u32,
+    wallets: &[String],
+) -> Result<(MultisigWallet, Client), ApiError> {
+    let total = wallets.len();
+    if !(2..=MAX_PARTICIPANTS).contains(&total) {
+        return Err(ApiError::InvalidRequest(format!(
+            "A multisig needs between 2 and {} wallets",
+            MAX_PARTICIPANTS
+        )));
+    }
+    if required == 0 || required as usize > total {
+        return Err(ApiError::InvalidRequest(format!(
+            "required must be between 1 and {}",
+            total
+        )));
+    }
+    if wallets.iter().collect::<HashSet<_>>().len() != total {
+        return Err(ApiError::InvalidRequest(
+            "Each wallet can contribute only one key".into(),
+        ));
+    }
+    if wallets.iter().any(|wallet| wallet == name) {
+        return Err(ApiError::InvalidRequest(
+            "The multisig wallet needs a name of its own".into(),
+        ));
+    }
+    let wallet_name = caller.wallet_name(name);
+    if data.wallet_client(&wallet_name).is_some() {
+        return Err(ApiError::Conflict(format!(
+            "Wallet '{}' already exists",
+            name
+        )));
+    }
+
+    let label = format!("multisig {}", name);
+    let mut participants = Vec::with_capacity(total);
+    for walletid in wallets {
+        let participant_wallet = caller.wallet_name(walletid);
+        let Some(client) = data.wallet_client(&participant_wallet) else {
+            warn!("POST /multisig - wallet '{}' not found", walletid);
+            return Err(ApiError::WalletNotFound(walletid.clone()));
+        };
+        match participant_key(&client, &participant_wallet, &label) {
+            Ok(participant) => participants.push(participant),
+            Err(e) => {
+                error!(
+                    "Failed to get a key from wallet '{}': {}",
+                    participant_wallet, e
+                );
+                return Err(caller.rpc(e));
+            }
+        }
+    }
+    let keys: Vec<String> = participants
+        .iter()
+        .map(|participant| {
+            format!(
+                "{}{}",
+                participant.key_origin.as_deref().unwrap_or_default(),
+                participant.public_key
+            )
+        })
+        .collect();
+    // sortedmulti, so the address does not depend on the order of the wallets
+    let descriptor = format!("wsh(sortedmulti({},{}))", required, keys.join(","));
+
+    let node = caller.node_client(data)?;
+    let client = match data.config.create_client(&wallet_name) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create RPC client for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(caller.rpc(e));
+        }
+    };
+    let address = match create_watch_only(&node, &client, &wallet_name, &descriptor) {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Failed to create multisig wallet '{}': {}", wallet_name, e);
+            return Err(caller.rpc(e));
+        }
+    };
+    let multisig = MultisigWallet {
+        wallet: wallet_name.clone(),
+        descriptor,
+        address,
+        required,
+        participants,
+        created_at: unix_now(),
+    };
+    data.db.insert_multisig(&multisig)?;
+
+    info!(
+        "Created {}-of-{} multisig wallet '{}' at {}",
+        required, total, wallet_name, multisig.address
+    );
+    Ok((multisig, client))
+}
+
+#[utoipa::path(
+    post,
+    path = "/multisig",
+    tag = "multisig",
+    responses(
+        (status = 200, body = MultisigResponse),
+    )
+)]
+pub(crate) async fn create_multisig(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<CreateMultisigRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /multisig - name='{}', {}-of-{} {:?}",
+        req.name,
+        req.required,
+        req.wallets.len(),
+        req.wallets
+    );
+    let multisig = create(&data, &http_req, &req.name, req.required, &req.wallets).await?;
+    let view = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        Ok(multisig_view(data, caller, multisig))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(view))
+}
+
+#[utoipa::path(
+    get,
+    path = "/multisig/{name}",
+    tag = "multisig",
+    responses(
+        (status = 200, body = MultisigResponse),
+    )
+)]
+pub(crate) async fn get_multisig(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /multisig/{}", name);
+    let wallet_name = data.wallet_name(&http_req, &name);
+    let Some(multisig) = data.db.multisig(&wallet_name)? else {
+        return Err(ApiError::NotFound(format!(
+            "'{}' is not a multisig wallet",
+            name
+        )));
+    };
+    let view = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        Ok(multisig_view(data, caller, multisig))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(view))
+}
+
+#[utoipa::path(
+    post,
+    path = "/multisig/{name}/fund",
+    tag = "multisig",
+    responses(
+        (status = 200, body = FundMultisigResponse),
+    )
+)]
+pub(crate) async fn fund_multisig(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    name: web::Path<String>,
+    req: web::Json<FundMultisigRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /multisig/{}/fund - from='{}', amount={} sat",
+        name, req.from_wallet, req.amount_sat
+    );
+    if req.amount_sat == 0 {
+        return Err(ApiError::InvalidRequest("Amount must be positive".into()));
+    }
+    let wallet_name = data.wallet_name(&http_req, &name);
+    let Some(multisig) = data.db.multisig(&wallet_name)? else {
+        return Err(ApiError::NotFound(format!(
+            "'{}' is not a multisig wallet",
+            name
+        )));
+    };
+    let address = Address::from_str(&multisig.address)
+        .map_err(|e| ApiError::Internal(format!("Stored multisig address is invalid: {e}")))?
+        .assume_checked();
+    let from_wallet = data.wallet_name(&http_req, &req.from_wallet);
+    let Some(client) = data.wallet_client(&from_wallet) else {
+        warn!(
+            "POST /multisig/{}/fund - wallet '{}' not found",
+            name, req.from_wallet
+        );
+        return Err(ApiError::WalletNotFound(req.from_wallet.clone()));
+    };
+    let amount = Amount::from_sat(req.amount_sat);
+    let sent = rpc_pool::blocking(move || {
+        client.send_to_address(&address, amount, None, None, None, None, None, None)
+    })
+    .await?;
+    match sent {
+        Ok(txid) => {
+            info!(
+                "Funded multisig '{}' with {} sat from '{}': {}",
+                wallet_name, req.amount_sat, from_wallet, txid
+            );
+            data.events.publish(Event::PaymentSent {
+                wallet: from_wallet,
+                to_address: multisig.address.clone(),
+                amount_sat: req.amount_sat,
+                txid: txid.to_string(),
+            });
+            Ok(HttpResponse::Ok().json(FundMultisigResponse {
+                txid: txid.to_string(),
+                address: multisig.address,
+                amount_sat: req.amount_sat,
+            }))
+        }
+        Err(e) => {
+            error!(
+                "Failed to fund multisig '{}' from '{}': {}",
+                wallet_name, from_wallet, e
+            );
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,1228 @@
+use crate::channel::node_client;
+use crate::errors::ApiError;
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::consensus::encode::serialize_hex;
+use bitcoincore_rpc::bitcoin::hashes::hex::FromHex;
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoincore_rpc::bitcoin::secp256k1::{
+    rand, schnorr, All, Message, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey,
+};
+use bitcoincore_rpc::bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::RpcApi;
+use dashmap::mapref::one::{Ref, RefMut};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// The playground keeps secret keys in memory, so sessions are few and short-lived
+const MAX_SESSIONS: usize = 100;
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_FEE_SAT: u64 = 500;
+// Smallest P2TR output the node relays
+const P2TR_DUST_SAT: u64 = 330;
+const ONE: [u8; 32] = {
+    let mut one = [0; 32];
+    one[31] = 1;
+    one
+};
+
+// MuSig2 as specified in BIP327, built on the secp256k1 point and scalar operations;
+// the secp256k1 release that rust-bitcoin 0.31 uses has no musig module
+
+// An integer modulo the curve order. SecretKey cannot hold zero, so zero is None.
+#[derive(Clone, Copy)]
+struct Num(Option<SecretKey>);
+
+impl Num {
+    const ZERO: Num = Num(None);
+
+    fn one() -> Num {
+        Num(SecretKey::from_slice(&ONE).ok())
+    }
+
+    // Hash outputs at or above the curve order occur with probability ~2^-128
+    fn from_bytes(bytes: [u8; 32]) -> Result<Num, ApiError> {
+        if bytes == [0; 32] {
+            return Ok(Num::ZERO);
+        }
+        SecretKey::from_slice(&bytes)
+            .map(|key| Num(Some(key)))
+            .map_err(|_| ApiError::Internal("value is not below the curve order".into()))
+    }
+
+    fn add(self, other: Num) -> Num {
+        match (self.0, other.0) {
+            (None, _) => other,
+            (_, None) => self,
+            // Only fails when the sum is zero
+            (Some(a), Some(b)) => Num(a.add_tweak(&Scalar::from(b)).ok()),
+        }
+    }
+
+    fn mul(self, other: Num) -> Num {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => Num(a.mul_tweak(&Scalar::from(b)).ok()),
+            _ => Num::ZERO,
+        }
+    }
+
+    fn neg(self) -> Num {
+        Num(self.0.map(SecretKey::negate))
+    }
+
+    fn bytes(self) -> [u8; 32] {
+        self.0.map(|key| key.secret_bytes()).unwrap_or([0; 32])
+    }
+
+    // 1 or -1, as used to keep x-only keys and nonces at an even y
+    fn sign_of(point: &PublicKey) -> Num {
+        if has_even_y(point) {
+            Num::one()
+        } else {
+            Num::one().neg()
+        }
+    }
+}
+
+// Points may add up to infinity, which is None
+fn add_points(a: Option<PublicKey>, b: Option<PublicKey>) -> Option<PublicKey> {
+    match (a, b) {
+        (None, point) | (point, None) => point,
+        (Some(a), Some(b)) => a.combine(&b).ok(),
+    }
+}
+
+fn mul_point(secp: &Secp256k1<All>, point: &PublicKey, k: Num) -> Option<PublicKey> {
+    k.0.and_then(|k| point.mul_tweak(secp, &Scalar::from(k)).ok())
+}
+
+fn mul_g(secp: &Secp256k1<All>, k: Num) -> Option<PublicKey> {
+    k.0.map(|k| PublicKey::from_secret_key(secp, &k))
+}
+
+fn has_even_y(point: &PublicKey) -> bool {
+    point.serialize()[0] == 0x02
+}
+
+fn xbytes(point: &PublicKey) -> [u8; 32] {
+    point.x_only_public_key().0.serialize()
+}
+
+fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    for part in parts {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn infinity(what: &str) -> ApiError {
+    ApiError::Internal(format!("{what} is the point at infinity"))
+}
+
+struct KeyAggContext {
+    // Aggregate key, tweaked once the taproot tweak is applied
+    q: PublicKey,
+    gacc: Num,
+    tacc: Num,
+}
+
+// KeyAgg: each key is weighted by a coefficient that commits to the whole key list,
+// which stops a participant from choosing a key that cancels out the others
+fn key_agg(
+    secp: &Secp256k1<All>,
+    keys: &[PublicKey],
+) -> Result<(KeyAggContext, Vec<Num>), ApiError> {
+    let serialized: Vec<[u8; 33]> = keys.iter().map(PublicKey::serialize).collect();
+    let parts: Vec<&[u8]> = serialized.iter().map(|key| &key[..]).collect();
+    let list_hash = tagged_hash("KeyAgg list", &parts);
+    let second = serialized.iter().find(|key| **key != serialized[0]);
+
+    let mut q = None;
+    let mut coefficients = Vec::with_capacity(keys.len());
+    for (key, key_bytes) in keys.iter().zip(&serialized) {
+        let coefficient = if Some(key_bytes) == second {
+            Num::one()
+        } else {
+            Num::from_bytes(tagged_hash("KeyAgg coefficient", &[&list_hash, key_bytes]))?
+        };
+        q = add_points(q, mul_point(secp, key, coefficient));
+        coefficients.push(coefficient);
+    }
+    let context = KeyAggContext {
+        q: q.ok_or_else(|| infinity("the aggregate key"))?,
+        gacc: Num::one(),
+        tacc: Num::ZERO,
+    };
+    Ok((context, coefficients))
+}
+
+// ApplyTweak with an x-only tweak, here the BIP341 key path tweak
+fn apply_xonly_tweak(
+    secp: &Secp256k1<All>,
+    context: &mut KeyAggContext,
+    tweak: [u8; 32],
+) -> Result<(), ApiError> {
+    let g = Num::sign_of(&context.q);
+    let t = Num::from_bytes(tweak)?;
+    let q = if has_even_y(&context.q) {
+        context.q
+    } else {
+        context.q.negate(secp)
+    };
+    context.q = add_points(Some(q), mul_g(secp, t)).ok_or_else(|| infinity("the tweaked key"))?;
+    context.gacc = g.mul(context.gacc);
+    context.tacc = t.add(g.mul(context.tacc));
+    Ok(())
+}
+
+struct SecretNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+}
+
+struct SessionValues {
+    b: Num,
+    r: PublicKey,
+    e: Num,
+}
+
+// An aggregate nonce point may be infinity, which BIP327 encodes as 33 zero bytes
+fn nonce_bytes(nonce: &[Option<PublicKey>; 2]) -> Vec<u8> {
+    nonce
+        .iter()
+        .flat_map(|point| point.map(|point| point.serialize()).unwrap_or([0; 33]))
+        .collect()
+}
+
+fn session_values(
+    secp: &Secp256k1<All>,
+    context: &KeyAggContext,
+    aggregate_nonce: &[Option<PublicKey>; 2],
+    message: &[u8; 32],
+) -> Result<SessionValues, ApiError> {
+    let q = xbytes(&context.q);
+    let b = Num::from_bytes(tagged_hash(
+        "MuSig/noncecoef",
+        &[&nonce_bytes(aggregate_nonce), &q, message],
+    ))?;
+    let r2 = aggregate_nonce[1].and_then(|point| mul_point(secp, &point, b));
+    let r = match add_points(aggregate_nonce[0], r2) {
+        Some(r) => r,
+        // BIP327 falls back to the generator so the protocol cannot be stalled
+        None => mul_g(secp, Num::one()).ok_or_else(|| infinity("the generator"))?,
+    };
+    let e = Num::from_bytes(tagged_hash(
+        "BIP0340/challenge",
+        &[&xbytes(&r), &q, message],
+    ))?;
+    Ok(SessionValues { b, r, e })
+}
+
+fn partial_sign(
+    context: &KeyAggContext,
+    values: &SessionValues,
+    nonce: SecretNonce,
+    secret_key: &SecretKey,
+    coefficient: Num,
+) -> Num {
+    let (mut k1, mut k2) = (Num(Some(nonce.k1)), Num(Some(nonce.k2)));
+    if !has_even_y(&values.r) {
+        k1 = k1.neg();
+        k2 = k2.neg();
+    }
+    let d = Num::sign_of(&context.q)
+        .mul(context.gacc)
+        .mul(Num(Some(*secret_key)));
+    k1.add(values.b.mul(k2))
+        .add(values.e.mul(coefficient).mul(d))
+}
+
+// What each cosigner checks before aggregating, so a bad partial signature is blamed
+// on its signer instead of producing an invalid transaction
+fn partial_verify(
+    secp: &Secp256k1<All>,
+    context: &KeyAggContext,
+    values: &SessionValues,
+    signature: Num,
+    public_nonce: &[PublicKey; 2],
+    public_key: &PublicKey,
+    coefficient: Num,
+) -> bool {
+    let mut re = add_points(
+        Some(public_nonce[0]),
+        mul_point(secp, &public_nonce[1], values.b),
+    );
+    if !has_even_y(&values.r) {
+        re = re.map(|point| point.negate(secp));
+    }
+    let g = Num::sign_of(&context.q).mul(context.gacc);
+    let expected = add_points(
+        re,
+        mul_point(secp, public_key, values.e.mul(coefficient).mul(g)),
+    );
+    mul_g(secp, signature) == expected
+}
+
+// Adds up the partial signatures, plus the challenge times the tweak that no
+// participant's share covers, into the BIP340 signature for the output key
+fn aggregate_signature(
+    context: &KeyAggContext,
+    values: &SessionValues,
+    partial_signatures: impl Iterator<Item = Num>,
+) -> Result<schnorr::Signature, ApiError> {
+    let s = partial_signatures
+        .fold(Num::ZERO, Num::add)
+        .add(values.e.mul(Num::sign_of(&context.q)).mul(context.tacc));
+    schnorr::Signature::from_slice(&[xbytes(&values.r), s.bytes()].concat())
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+struct Participant {
+    name: String,
+    secret_key: SecretKey,
+    public_key: PublicKey,
+    coefficient: Num,
+    // Taken when signing, so a nonce can never sign twice
+    secret_nonce: Option<SecretNonce>,
+    public_nonce: Option<[PublicKey; 2]>,
+    partial_signature: Option<Num>,
+}
+
+struct Spend {
+    tx: Transaction,
+    prevout: TxOut,
+    sighash: [u8; 32],
+    values: SessionValues,
+}
+
+pub(crate) struct MusigSession {
+    created_at: Instant,
+    // Wallet prefix of the caller that created it, see AppState::wallet_name
+    owner: String,
+    participants: Vec<Participant>,
+    internal_key: XOnlyPublicKey,
+    key_agg: KeyAggContext,
+    address: Address,
+    aggregate_nonce: Option<[PublicKey; 2]>,
+    spend: Option<Spend>,
+    broadcast_txid: Option<String>,
+}
+
+impl MusigSession {
+    fn round(&self) -> &'static str {
+        if self.broadcast_txid.is_some() {
+            "broadcast"
+        } else if self.spend.is_some() {
+            "partially_signed"
+        } else if self.aggregate_nonce.is_some() {
+            "nonces_exchanged"
+        } else {
+            "keys_aggregated"
+        }
+    }
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct CreateSessionRequest {
+    participants: Option<[String; 2]>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SignRequest {
+    // The coin sent to the session's address
+    txid: String,
+    vout: u32,
+    to_address: String,
+    fee_sat: Option<u64>,
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct AggregateRequest {
+    #[serde(default)]
+    broadcast: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ParticipantView {
+    name: String,
+    public_key: String,
+    key_agg_coefficient: String,
+    public_nonce: Option<String>,
+    partial_signature: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SessionResponse {
+    id: String,
+    round: &'static str,
+    participants: Vec<ParticipantView>,
+    // Aggregate of the participants' keys, before the taproot tweak
+    internal_key: String,
+    // The key in the output, which the final signature verifies against
+    output_key: String,
+    address: String,
+    aggregate_nonce: Option<String>,
+    explanation: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PartialSignatureView {
+    name: String,
+    partial_signature: String,
+    valid: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SignResponse {
+    id: String,
+    round: &'static str,
+    sighash: String,
+    partial_signatures: Vec<PartialSignatureView>,
+    explanation: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Annotation {
+    part: String,
+    note: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AggregateResponse {
+    id: String,
+    round: &'static str,
+    txid: String,
+    hex: String,
+    signature: String,
+    vsize: usize,
+    fee_sat: u64,
+    broadcast: bool,
+    annotations: Vec<Annotation>,
+}
+
+fn session_view(id: &str, session: &MusigSession, explanation: Vec<String>) -> SessionResponse {
+    SessionResponse {
+        id: id.to_string(),
+        round: session.round(),
+        participants: session
+            .participants
+            .iter()
+            .map(|participant| ParticipantView {
+                name: participant.name.clone(),
+                public_key: participant.public_key.to_string(),
+                key_agg_coefficient: hex::encode(participant.coefficient.bytes()),
+                public_nonce: participant
+                    .public_nonce
+                    .as_ref()
+                    .map(|nonce| hex::encode(nonce_bytes(&nonce.map(Some)))),
+                partial_signature: participant
+                    .partial_signature
+                    .map(|signature| hex::encode(signature.bytes())),
+            })
+            .collect(),
+        internal_key: session.internal_key.to_string(),
+        output_key: hex::encode(xbytes(&session.key_agg.q)),
+        address: session.address.to_string(),
+        aggregate_nonce: session
+            .aggregate_nonce
+            .as_ref()
+            .map(|nonce| hex::encode(nonce_bytes(&nonce.map(Some)))),
+        explanation,
+    }
+}
+
+fn new_session(
+    data: &AppState,
+    owner: String,
+    names: [String; 2],
+) -> Result<MusigSession, ApiError> {
+    let secp = Secp256k1::new();
+    let mut rng = rand::thread_rng();
+    let secret_keys = [SecretKey::new(&mut rng), SecretKey::new(&mut rng)];
+    let public_keys: Vec<PublicKey> = secret_keys
+        .iter()
+        .map(|key| PublicKey::from_secret_key(&secp, key))
+        .collect();
+
+    let (mut key_agg, coefficients) = key_agg(&secp, &public_keys)?;
+    let internal_key = key_agg.q.x_only_public_key().0;
+    apply_xonly_tweak(
+        &secp,
+        &mut key_agg,
+        tagged_hash("TapTweak", &[&internal_key.serialize()]),
+    )?;
+    let address = Address::p2tr(&secp, internal_key, None, data.config.network);
+    // The tweak above must land on the same output key as rust-bitcoin's
+    if address.script_pubkey().as_bytes()[2..] != xbytes(&key_agg.q) {
+        return Err(ApiError::Internal(
+            "the tweaked aggregate key does not match the taproot address".into(),
+        ));
+    }
+
+    let participants = names
+        .into_iter()
+        .zip(secret_keys)
+        .zip(public_keys)
+        .zip(coefficients)
+        .map(
+            |(((name, secret_key), public_key), coefficient)| Participant {
+                name,
+                secret_key,
+                public_key,
+                coefficient,
+                secret_nonce: None,
+                public_nonce: None,
+                partial_signature: None,
+            },
+        )
+        .collect();
+    Ok(MusigSession {
+        created_at: Instant::now(),
+        owner,
+        participants,
+        internal_key,
+        key_agg,
+        address,
+        aggregate_nonce: None,
+        spend: None,
+        broadcast_txid: None,
+    })
+}
+
+fn session_not_found(id: &str) -> ApiError {
+    ApiError::NotFound(format!("No MuSig2 session '{id}'"))
+}
+
+// Another caller's session is reported as missing, like their wallets are
+fn owned_session<'a>(
+    data: &'a AppState,
+    http_req: &HttpRequest,
+    id: &str,
+) -> Result<Ref<'a, String, MusigSession>, ApiError> {
+    let owner = data.wallet_name(http_req, "");
+    data.musig
+        .get(id)
+        .filter(|session| session.owner == owner)
+        .ok_or_else(|| session_not_found(id))
+}
+
+fn owned_session_mut<'a>(
+    data: &'a AppState,
+    http_req: &HttpRequest,
+    id: &str,
+) -> Result<RefMut<'a, String, MusigSession>, ApiError> {
+    let owner = data.wallet_name(http_req, "");
+    data.musig
+        .get_mut(id)
+        .filter(|session| session.owner == owner)
+        .ok_or_else(|| session_not_found(id))
+}
+
+// Round 1: both participants publish their keys and everyone computes the same aggregate
+#[utoipa::path(
+    post,
+    path = "/musig/session",
+    tag = "musig",
+    responses(
+        (status = 200, body = SessionResponse),
+    )
+)]
+pub(crate) async fn create_session(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: Option<web::Json<CreateSessionRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.map(web::Json::into_inner).unwrap_or_default();
+    let names = req
+        .participants
+        .unwrap_or_else(|| ["Alice".to_string(), "Bob".to_string()]);
+    info!("POST /musig/session - participants={:?}", names);
+    if names[0] == names[1] {
+        return Err(ApiError::InvalidRequest(
+            "The participants need different names".into(),
+        ));
+    }
+    data.musig
+        .retain(|_, session| session.created_at.elapsed() < SESSION_TTL);
+    if data.musig.len() >= MAX_SESSIONS {
+        return Err(ApiError::TooManyRequests(
+            "Too many MuSig2 sessions, try again later".into(),
+        ));
+    }
+
+    let session = new_session(&data, data.wallet_name(&http_req, ""), names)?;
+    let id = Uuid::new_v4().simple().to_string();
+    let [first, second] = [&session.participants[0].name, &session.participants[1].name];
+    let explanation = vec![
+        format!("{first} and {second} each created a key pair and shared the public key."),
+        "Each public key is multiplied by a key aggregation coefficient, a hash of all keys, so nobody can pick a key that cancels out the other's.".to_string(),
+        "The weighted keys add up to the internal key. Taproot then tweaks it into the output key in the address.".to_string(),
+        format!("Coins sent to {} can only be spent if {first} and {second} both sign. On chain it looks like any single-key taproot output.", session.address),
+    ];
+    let response = session_view(&id, &session, explanation);
+    info!(
+        "Created MuSig2 session {} with address {}",
+        id, session.address
+    );
+    data.musig.insert(id, session);
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    get,
+    path = "/musig/{id}",
+    tag = "musig",
+    responses(
+        (status = 200, body = SessionResponse),
+    )
+)]
+pub(crate) async fn get_session(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /musig/{}", id);
+    let session = owned_session(&data, &http_req, &id)?;
+    Ok(HttpResponse::Ok().json(session_view(&id, &session, Vec::new())))
+}
+
+// Round 2: each participant commits to two secret nonces and publishes their points
+#[utoipa::path(
+    post,
+    path = "/musig/{id}/nonces",
+    tag = "musig",
+    responses(
+        (status = 200, body = SessionResponse),
+    )
+)]
+pub(crate) async fn exchange_nonces(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /musig/{}/nonces", id);
+    let mut session = owned_session_mut(&data, &http_req, &id)?;
+    if session.spend.is_some() {
+        return Err(ApiError::Conflict(
+            "The nonces were already used to sign, start a new session".into(),
+        ));
+    }
+
+    let secp = Secp256k1::new();
+    let mut rng = rand::thread_rng();
+    for participant in session.participants.iter_mut() {
+        let nonce = SecretNonce {
+            k1: SecretKey::new(&mut rng),
+            k2: SecretKey::new(&mut rng),
+        };
+        participant.public_nonce = Some([
+            PublicKey::from_secret_key(&secp, &nonce.k1),
+            PublicKey::from_secret_key(&secp, &nonce.k2),
+        ]);
+        participant.secret_nonce = Some(nonce);
+    }
+    let mut aggregate = [None, None];
+    for participant in &session.participants {
+        if let Some(nonce) = &participant.public_nonce {
+            aggregate[0] = add_points(aggregate[0], Some(nonce[0]));
+            aggregate[1] = add_points(aggregate[1], Some(nonce[1]));
+        }
+    }
+    let [Some(r1), Some(r2)] = aggregate else {
+        return Err(infinity("the aggregate nonce"));
+    };
+    session.aggregate_nonce = Some([r1, r2]);
+
+    let explanation = vec![
+        "Each participant picked two random secret nonces and published the matching points. Two nonces instead of one make the protocol safe when many sessions run at once.".to_string(),
+        "The points are added up into the aggregate nonce. This round does not depend on the transaction, so it can happen before anyone knows what will be signed.".to_string(),
+        "A secret nonce must never sign twice: reusing it would reveal the secret key. This session discards them after signing.".to_string(),
+    ];
+    Ok(HttpResponse::Ok().json(session_view(&id, &session, explanation)))
+}
+
+fn fetch_prevout(
+    data: &AppState,
+    caller: &Caller,
+    txid: &Txid,
+    vout: u32,
+) -> Result<TxOut, ApiError> {
+    let node = caller.node_client(data)?;
+    let out: Value = node
+        .call("gettxout", &[json!(txid), json!(vout), json!(true)])
+        .map_err(|e| {
+            error!("gettxout failed for {}:{}: {}", txid, vout, e);
+            caller.rpc(e)
+        })?;
+    if out.is_null() {
+        return Err(ApiError::NotFound(format!(
+            "{txid}:{vout} does not exist or is already spent"
+        )));
+    }
+    let value = out["value"]
+        .as_f64()
+        .and_then(|btc| Amount::from_btc(btc).ok())
+        .unwrap_or(Amount::ZERO);
+    let script = out["scriptPubKey"]["hex"]
+        .as_str()
+        .and_then(|hex| Vec::<u8>::from_hex(hex).ok())
+        .unwrap_or_default();
+    Ok(TxOut {
+        value,
+        script_pubkey: ScriptBuf::from_bytes(script),
+    })
+}
+
+// Round 3: with the transaction known, each participant signs their share
+#[utoipa::path(
+    post,
+    path = "/musig/{id}/sign",
+    tag = "musig",
+    responses(
+        (status = 200, body = SignResponse),
+    )
+)]
+pub(crate) async fn sign(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+    req: web::Json<SignRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /musig/{}/sign - outpoint={}:{}, to='{}'",
+        id, req.txid, req.vout, req.to_address
+    );
+    let txid = Txid::from_str(&req.txid)
+        .map_err(|e| ApiError::InvalidTxid(format!("Invalid transaction ID: {}", e)))?;
+    let to_address = Address::from_str(&req.to_address)
+        .map_err(|e| ApiError::InvalidAddress(format!("Invalid address: {}", e)))?
+        .require_network(data.config.network)
+        .map_err(|e| ApiError::InvalidAddress(format!("Invalid network: {}", e)))?;
+    let fee = Amount::from_sat(req.fee_sat.unwrap_or(DEFAULT_FEE_SAT));
+
+    // Looked up before the session is locked, so the RPC does not block other sessions
+    let vout = req.vout;
+    let prevout = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        fetch_prevout(data, caller, &txid, vout)
+    })
+    .await?;
+    let mut session = owned_session_mut(&data, &http_req, &id)?;
+    if prevout.script_pubkey != session.address.script_pubkey() {
+        return Err(ApiError::InvalidRequest(format!(
+            "{}:{} does not pay the session address {}",
+            txid, req.vout, session.address
+        )));
+    }
+    let Some(aggregate_nonce) = session.aggregate_nonce else {
+        return Err(ApiError::Conflict(
+            "Exchange nonces with POST /musig/{id}/nonces first".into(),
+        ));
+    };
+    if session.spend.is_some() {
+        return Err(ApiError::Conflict(
+            "This session has already signed a transaction".into(),
+        ));
+    }
+    let amount = match prevout.value.checked_sub(fee) {
+        Some(amount) if amount.to_sat() >= P2TR_DUST_SAT => amount,
+        _ => {
+            return Err(ApiError::InvalidRequest(format!(
+                "The coin holds {} sat, too little to pay a {} sat fee",
+                prevout.value.to_sat(),
+                fee.to_sat()
+            )))
+        }
+    };
+
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid,
+                vout: req.vout,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: to_address.script_pubkey(),
+        }],
+    };
+    let sighash = SighashCache::new(&tx)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&[&prevout]), TapSighashType::Default)
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .to_byte_array();
+
+    let secp = Secp256k1::new();
+    let values = session_values(
+        &secp,
+        &session.key_agg,
+        &aggregate_nonce.map(Some),
+        &sighash,
+    )?;
+    let session = &mut *session;
+    let mut partial_signatures = Vec::with_capacity(session.participants.len());
+    for participant in session.participants.iter_mut() {
+        let (Some(nonce), Some(public_nonce)) =
+            (participant.secret_nonce.take(), participant.public_nonce)
+        else {
+            return Err(ApiError::Conflict("A participant has no nonce".into()));
+        };
+        let signature = partial_sign(
+            &session.key_agg,
+            &values,
+            nonce,
+            &participant.secret_key,
+            participant.coefficient,
+        );
+        let valid = partial_verify(
+            &secp,
+            &session.key_agg,
+            &values,
+            signature,
+            &public_nonce,
+            &participant.public_key,
+            participant.coefficient,
+        );
+        if !valid {
+            warn!(
+                "MuSig2 session {}: partial signature of '{}' does not verify",
+                id, participant.name
+            );
+        }
+        participant.partial_signature = Some(signature);
+        partial_signatures.push(PartialSignatureView {
+            name: participant.name.clone(),
+            partial_signature: hex::encode(signature.bytes()),
+            valid,
+        });
+    }
+    session.spend = Some(Spend {
+        tx,
+        prevout,
+        sighash,
+        values,
+    });
+
+    let explanation = vec![
+        "The message is the BIP341 signature hash of the spending transaction, the same one a single signer would sign.".to_string(),
+        "A nonce coefficient, hashed from the aggregate nonce, the key and the message, combines the two aggregate nonces into the signature nonce R.".to_string(),
+        "Each partial signature is s = k1 + b*k2 + e*a*x: the participant's nonces, weighted by the nonce coefficient b, plus the challenge e times their key aggregation coefficient a times their secret key x.".to_string(),
+        "Each partial signature is checked against its signer's public key and nonces, so a faulty signer is caught before aggregation.".to_string(),
+    ];
+    Ok(HttpResponse::Ok().json(SignResponse {
+        id: id.into_inner(),
+        round: session.round(),
+        sighash: hex::encode(sighash),
+        partial_signatures,
+        explanation,
+    }))
+}
+
+// Round 4: anyone can add up the partial signatures into one Schnorr signature
+#[utoipa::path(
+    post,
+    path = "/musig/{id}/aggregate",
+    tag = "musig",
+    responses(
+        (status = 200, body = AggregateResponse),
+    )
+)]
+pub(crate) async fn aggregate(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+    req: Option<web::Json<AggregateRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.map(web::Json::into_inner).unwrap_or_default();
+    info!("POST /musig/{}/aggregate - broadcast={}", id, req.broadcast);
+    let secp = Secp256k1::new();
+    let (tx, signature, prevout, output_key) = {
+        let session = owned_session(&data, &http_req, &id)?;
+        let Some(spend) = &session.spend else {
+            return Err(ApiError::Conflict(
+                "Sign with POST /musig/{id}/sign first".into(),
+            ));
+        };
+        let signature = aggregate_signature(
+            &session.key_agg,
+            &spend.values,
+            session
+                .participants
+                .iter()
+                .filter_map(|participant| participant.partial_signature),
+        )?;
+        let output_key = session.key_agg.q.x_only_public_key().0;
+        if let Err(e) = secp.verify_schnorr(
+            &signature,
+            &Message::from_digest(spend.sighash),
+            &output_key,
+        ) {
+            error!(
+                "MuSig2 session {}: aggregate signature does not verify: {}",
+                id, e
+            );
+            return Err(ApiError::Internal(format!(
+                "The aggregate signature does not verify: {e}"
+            )));
+        }
+        let mut tx = spend.tx.clone();
+        tx.input[0].witness = Witness::from_slice(&[signature.as_ref()]);
+        (tx, signature, spend.prevout.clone(), output_key)
+    };
+
+    let txid = tx.txid();
+    let fee_sat = prevout.value.to_sat() - tx.output[0].value.to_sat();
+    let vsize = tx.vsize();
+    let mut broadcast = false;
+    if req.broadcast {
+        let node = node_client(&data, &http_req)?;
+        let signed = tx.clone();
+        let sent = rpc_pool::blocking(move || node.send_raw_transaction(&signed)).await?;
+        if let Err(e) = sent {
+            error!("Failed to broadcast MuSig2 spend {}: {}", txid, e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+        info!("Broadcast MuSig2 spend {}", txid);
+        broadcast = true;
+        if let Some(mut session) = data.musig.get_mut(id.as_str()) {
+            session.broadcast_txid = Some(txid.to_string());
+        }
+    }
+
+    let signature_bytes = signature.as_ref();
+    let input = &tx.input[0];
+    let annotations = vec![
+        Annotation {
+            part: "version".into(),
+            note: "Version 2 and locktime 0, like any ordinary payment.".into(),
+        },
+        Annotation {
+            part: "input 0".into(),
+            note: format!(
+                "Spends {} holding {} sat, locked to the output key {}.",
+                input.previous_output,
+                prevout.value.to_sat(),
+                output_key
+            ),
+        },
+        Annotation {
+            part: "input 0 witness".into(),
+            note: format!(
+                "A single 64-byte Schnorr signature: R = {} and s = {}. s is the sum of both partial signatures plus the taproot tweak term.",
+                hex::encode(&signature_bytes[..32]),
+                hex::encode(&signature_bytes[32..])
+            ),
+        },
+        Annotation {
+            part: "output 0".into(),
+            note: format!(
+                "Pays {} sat to {}.",
+                tx.output[0].value.to_sat(),
+                tx.output[0].script_pubkey.to_hex_string()
+            ),
+        },
+        Annotation {
+            part: "fee".into(),
+            note: format!(
+                "{} sat for {} vbytes ({:.2} sat/vB).",
+                fee_sat,
+                vsize,
+                fee_sat as f64 / vsize as f64
+            ),
+        },
+        Annotation {
+            part: "privacy".into(),
+            note: "Nothing on chain shows that two people signed: the spend is indistinguishable from a single-key taproot key path spend.".into(),
+        },
+    ];
+    Ok(HttpResponse::Ok().json(AggregateResponse {
+        id: id.into_inner(),
+        round: if broadcast { "broadcast" } else { "complete" },
+        txid: txid.to_string(),
+        hex: serialize_hex(&tx),
+        signature: hex::encode(signature_bytes),
+        vsize,
+        fee_sat,
+        broadcast,
+        annotations,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::key::TapTweak;
+
+    // Test vectors from BIP327, as bundled with libsecp256k1's musig module
+
+    fn key(hex: &str) -> PublicKey {
+        PublicKey::from_str(hex).unwrap()
+    }
+
+    fn bytes32(hex: &str) -> [u8; 32] {
+        <[u8; 32]>::from_hex(hex).unwrap()
+    }
+
+    fn nonce(hex: &str) -> [Option<PublicKey>; 2] {
+        let bytes = Vec::<u8>::from_hex(hex).unwrap();
+        let point = |part: &[u8]| PublicKey::from_slice(part).ok();
+        [point(&bytes[..33]), point(&bytes[33..])]
+    }
+
+    fn coefficient_of(keys: &[PublicKey], coefficients: &[Num], key: &PublicKey) -> Num {
+        let index = keys.iter().position(|k| k == key).unwrap();
+        coefficients[index]
+    }
+
+    const SK: &str = "7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671";
+    const SECNONCE: &str = "508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F7";
+    const MSG: &str = "F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF";
+    const SIGN_KEYS: [&str; 3] = [
+        "03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9",
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661",
+    ];
+    const SIGN_PUBNONCES: [&str; 3] = [
+        "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+        "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046",
+    ];
+    const AGGNONCE: &str = "028465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD61037496A3CC86926D452CAFCFD55D25972CA1675D549310DE296BFF42F72EEEA8C9";
+
+    fn secret_nonce() -> SecretNonce {
+        let bytes = Vec::<u8>::from_hex(SECNONCE).unwrap();
+        SecretNonce {
+            k1: SecretKey::from_slice(&bytes[..32]).unwrap(),
+            k2: SecretKey::from_slice(&bytes[32..]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn key_agg_vectors() {
+        let secp = Secp256k1::new();
+        let keys = [
+            key("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            key("03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"),
+            key("023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66"),
+        ];
+        let cases: [(&[usize], &str); 4] = [
+            (
+                &[0, 1, 2],
+                "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C",
+            ),
+            (
+                &[2, 1, 0],
+                "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B",
+            ),
+            (
+                &[0, 0, 0],
+                "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935",
+            ),
+            (
+                &[0, 0, 1, 1],
+                "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E",
+            ),
+        ];
+        for (indices, expected) in cases {
+            let keys: Vec<PublicKey> = indices.iter().map(|&i| keys[i]).collect();
+            let (context, _) = key_agg(&secp, &keys).unwrap();
+            assert_eq!(xbytes(&context.q), bytes32(expected), "keys {indices:?}");
+        }
+    }
+
+    #[test]
+    fn sign_verify_vectors() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_str(SK).unwrap();
+        let keys = SIGN_KEYS.map(key);
+        // The second aggregate nonce is the point at infinity twice
+        let cases: [(&[usize], &str, &str); 4] = [
+            (
+                &[0, 1, 2],
+                AGGNONCE,
+                "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB",
+            ),
+            (
+                &[1, 0, 2],
+                AGGNONCE,
+                "9FF2F7AAA856150CC8819254218D3ADEEB0535269051897724F9DB3789513A52",
+            ),
+            (
+                &[1, 2, 0],
+                AGGNONCE,
+                "FA23C359F6FAC4E7796BB93BC9F0532A95468C539BA20FF86D7C76ED92227900",
+            ),
+            (
+                &[0, 1],
+                "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+                "AE386064B26105404798F75DE2EB9AF5EDA5387B064B83D049CB7C5E08879531",
+            ),
+        ];
+        for (indices, aggnonce, expected) in cases {
+            let keys: Vec<PublicKey> = indices.iter().map(|&i| keys[i]).collect();
+            let (context, coefficients) = key_agg(&secp, &keys).unwrap();
+            let coefficient = coefficient_of(&keys, &coefficients, &SIGN_KEYS.map(key)[0]);
+            let values = session_values(&secp, &context, &nonce(aggnonce), &bytes32(MSG)).unwrap();
+            let signature =
+                partial_sign(&context, &values, secret_nonce(), &secret_key, coefficient);
+            assert_eq!(signature.bytes(), bytes32(expected), "keys {indices:?}");
+            let [Some(r1), Some(r2)] = nonce(SIGN_PUBNONCES[0]) else {
+                panic!("invalid public nonce");
+            };
+            assert!(partial_verify(
+                &secp,
+                &context,
+                &values,
+                signature,
+                &[r1, r2],
+                &keys[indices.iter().position(|&i| i == 0).unwrap()],
+                coefficient,
+            ));
+        }
+    }
+
+    #[test]
+    fn verify_fail_vectors() {
+        let secp = Secp256k1::new();
+        let keys = SIGN_KEYS.map(key);
+        let (context, coefficients) = key_agg(&secp, &keys).unwrap();
+        let values = session_values(&secp, &context, &nonce(AGGNONCE), &bytes32(MSG)).unwrap();
+        let verify = |signature: &str, signer: usize| {
+            let [Some(r1), Some(r2)] = nonce(SIGN_PUBNONCES[signer]) else {
+                panic!("invalid public nonce");
+            };
+            partial_verify(
+                &secp,
+                &context,
+                &values,
+                Num::from_bytes(bytes32(signature)).unwrap(),
+                &[r1, r2],
+                &keys[signer],
+                coefficients[signer],
+            )
+        };
+        // The negation of a valid signature
+        assert!(!verify(
+            "FED54434AD4CFE953FC527DC6A5E5BE8F6234907B7C187559557CE87A0541C46",
+            0
+        ));
+        // A valid signature attributed to the wrong signer
+        assert!(!verify(
+            "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB",
+            1
+        ));
+        // Not below the curve order
+        assert!(Num::from_bytes(bytes32(
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn xonly_tweak_vector() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_str(SK).unwrap();
+        let keys = [
+            key("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            key("02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"),
+            key(SIGN_KEYS[0]),
+        ];
+        let (mut context, coefficients) = key_agg(&secp, &keys).unwrap();
+        apply_xonly_tweak(
+            &secp,
+            &mut context,
+            bytes32("E8F791FF9225A2AF0102AFFF4A9A723D9612A682A25EBE79802B263CDFCD83BB"),
+        )
+        .unwrap();
+        let values = session_values(&secp, &context, &nonce(AGGNONCE), &bytes32(MSG)).unwrap();
+        let signature = partial_sign(
+            &context,
+            &values,
+            secret_nonce(),
+            &secret_key,
+            coefficients[2],
+        );
+        assert_eq!(
+            signature.bytes(),
+            bytes32("E28A5C66E61E178C2BA19DB77B6CF9F7E2F0F56C17918CD13135E60CC848FE91")
+        );
+    }
+
+    // The whole protocol, as the handlers run it, ends in a signature for the taproot
+    // output key
+    #[test]
+    fn taproot_round_trip() {
+        let secp = Secp256k1::new();
+        let mut rng = rand::thread_rng();
+        let secret_keys = [SecretKey::new(&mut rng), SecretKey::new(&mut rng)];
+        let keys = secret_keys.map(|key| PublicKey::from_secret_key(&secp, &key));
+        let (mut context, coefficients) = key_agg(&secp, &keys).unwrap();
+        let internal_key = context.q.x_only_public_key().0;
+        apply_xonly_tweak(
+            &secp,
+            &mut context,
+            tagged_hash("TapTweak", &[&internal_key.serialize()]),
+        )
+        .unwrap();
+
+        let nonces = [0, 1].map(|_| SecretNonce {
+            k1: SecretKey::new(&mut rng),
+            k2: SecretKey::new(&mut rng),
+        });
+        let public_nonces = nonces.each_ref().map(|nonce| {
+            [
+                PublicKey::from_secret_key(&secp, &nonce.k1),
+                PublicKey::from_secret_key(&secp, &nonce.k2),
+            ]
+        });
+        let aggregate_nonce =
+            [0, 1].map(|i| add_points(Some(public_nonces[0][i]), Some(public_nonces[1][i])));
+        let message = [7; 32];
+        let values = session_values(&secp, &context, &aggregate_nonce, &message).unwrap();
+        let partial_signatures: Vec<Num> = nonces
+            .into_iter()
+            .zip(&secret_keys)
+            .zip(&coefficients)
+            .map(|((nonce, key), coefficient)| {
+                partial_sign(&context, &values, nonce, key, *coefficient)
+            })
+            .collect();
+        for i in 0..2 {
+            assert!(partial_verify(
+                &secp,
+                &context,
+                &values,
+                partial_signatures[i],
+                &public_nonces[i],
+                &keys[i],
+                coefficients[i],
+            ));
+        }
+
+        let signature =
+            aggregate_signature(&context, &values, partial_signatures.into_iter()).unwrap();
+        let output_key = internal_key.tap_tweak(&secp, None).0.to_inner();
+        assert_eq!(output_key, context.q.x_only_public_key().0);
+        secp.verify_schnorr(&signature, &Message::from_digest(message), &output_key)
+            .unwrap();
+    }
+}
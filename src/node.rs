@@ -0,0 +1,121 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+// bitcoind does not expose -dustrelayfee over RPC, so the default policy value is reported
+const DEFAULT_DUST_RELAY_FEE_SAT_VB: f64 = 3.0;
+
+// Output size plus the (witness-discounted) size of the input that later spends it,
+// as used by Bitcoin Core's GetDustThreshold
+const DUST_SIZES: [(&str, u64); 5] = [
+    ("p2pkh", 34 + 148),
+    ("p2sh", 32 + 148),
+    ("p2wpkh", 31 + 67),
+    ("p2wsh", 43 + 67),
+    ("p2tr", 43 + 67),
+];
+
+#[derive(Serialize, ToSchema)]
+struct DustThreshold {
+    output_type: &'static str,
+    min_value_sat: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct NodePolicyResponse {
+    version: u64,
+    subversion: String,
+    min_relay_fee_sat_vb: f64,
+    incremental_relay_fee_sat_vb: f64,
+    mempool_min_fee_sat_vb: f64,
+    dust_relay_fee_sat_vb: f64,
+    dust_thresholds: Vec<DustThreshold>,
+    max_mempool_bytes: usize,
+    mempool_usage_bytes: usize,
+    full_rbf: bool,
+    rbf_policy: &'static str,
+}
+
+// Fee rates come back from bitcoind in BTC/kvB
+pub(crate) fn sat_per_vb(btc_per_kvb: Amount) -> f64 {
+    btc_per_kvb.to_sat() as f64 / 1000.0
+}
+
+fn fetch_policy(client: &Client) -> Result<NodePolicyResponse, RpcError> {
+    // getnetworkinfo is read untyped: newer nodes report `warnings` as a list,
+    // which the typed result cannot parse
+    let network: Value = client.call("getnetworkinfo", &[])?;
+    let mempool = client.get_mempool_info()?;
+
+    let btc_field = |name: &str| {
+        network[name]
+            .as_f64()
+            .and_then(|btc| Amount::from_btc(btc).ok())
+            .unwrap_or(Amount::ZERO)
+    };
+    // Nodes that do not report the flag are assumed to use the Core 28+ full RBF default
+    let full_rbf = mempool.full_rbf.unwrap_or(true);
+
+    Ok(NodePolicyResponse {
+        version: network["version"].as_u64().unwrap_or_default(),
+        subversion: network["subversion"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        min_relay_fee_sat_vb: sat_per_vb(btc_field("relayfee")),
+        incremental_relay_fee_sat_vb: sat_per_vb(btc_field("incrementalfee")),
+        mempool_min_fee_sat_vb: sat_per_vb(mempool.mempool_min_fee),
+        dust_relay_fee_sat_vb: DEFAULT_DUST_RELAY_FEE_SAT_VB,
+        dust_thresholds: DUST_SIZES
+            .iter()
+            .map(|(output_type, size)| DustThreshold {
+                output_type,
+                min_value_sat: (*size as f64 * DEFAULT_DUST_RELAY_FEE_SAT_VB) as u64,
+            })
+            .collect(),
+        max_mempool_bytes: mempool.max_mempool,
+        mempool_usage_bytes: mempool.usage,
+        full_rbf,
+        rbf_policy: if full_rbf {
+            "full: any unconfirmed transaction can be replaced by one paying a higher fee"
+        } else {
+            "opt-in: only transactions signaling BIP125 can be replaced"
+        },
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/node/policy",
+    tag = "node",
+    responses(
+        (status = 200, body = NodePolicyResponse),
+    )
+)]
+pub(crate) async fn get_policy(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /node/policy");
+    let client = match data.config.create_node_client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create node RPC client: {}", e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    match rpc_pool::blocking(move || fetch_policy(&client)).await? {
+        Ok(policy) => Ok(HttpResponse::Ok().json(policy)),
+        Err(e) => {
+            error!("Failed to fetch node policy: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::change::sign_and_broadcast;
+use crate::channel::node_client;
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::fees::FeeOptions;
+use crate::reservations::{self, ReservationOptions};
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::hashes::hex::FromHex;
+use bitcoincore_rpc::bitcoin::script::Instruction;
+use bitcoincore_rpc::bitcoin::{Address, Amount, Script, Transaction, Txid};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// Nodes relay OP_RETURN outputs of up to 83 script bytes by default (-datacarriersize),
+// which leaves 80 bytes for the data after the opcode and the push
+const MAX_MESSAGE_BYTES: usize = 80;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum MessageEncoding {
+    #[default]
+    Utf8,
+    Hex,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct OpReturnSendRequest {
+    from_wallet: String,
+    message: String,
+    #[serde(default)]
+    encoding: MessageEncoding,
+    // An optional payment in the same transaction; without it only the fee is spent
+    to_address: Option<String>,
+    amount: Option<u64>,
+    #[serde(flatten)]
+    fees: FeeOptions,
+    #[serde(flatten)]
+    reservations: ReservationOptions,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpReturnSendResponse {
+    txid: String,
+    // Output holding the message
+    vout: u32,
+    message_hex: String,
+    message_bytes: usize,
+    script_hex: String,
+}
+
+fn message_bytes(req: &OpReturnSendRequest) -> Result<Vec<u8>, ApiError> {
+    let bytes = match req.encoding {
+        MessageEncoding::Utf8 => req.message.as_bytes().to_vec(),
+        MessageEncoding::Hex => Vec::<u8>::from_hex(&req.message)
+            .map_err(|e| ApiError::InvalidRequest(format!("Invalid hex message: {}", e)))?,
+    };
+    if bytes.is_empty() || bytes.len() > MAX_MESSAGE_BYTES {
+        return Err(ApiError::InvalidRequest(format!(
+            "The message must be between 1 and {} bytes, it is {}",
+            MAX_MESSAGE_BYTES,
+            bytes.len()
+        )));
+    }
+    Ok(bytes)
+}
+
+// Writes the message into an OP_RETURN output, optionally next to a payment. The
+// wallet funds and signs the transaction like any other.
+#[utoipa::path(
+    post,
+    path = "/send/with-opreturn",
+    tag = "op-return",
+    responses(
+        (status = 200, body = OpReturnSendResponse),
+    )
+)]
+pub(crate) async fn send_with_op_return(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<OpReturnSendRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /send/with-opreturn - from='{}', to={:?}, amount_sat={:?}, message_len={}",
+        req.from_wallet,
+        req.to_address,
+        req.amount,
+        req.message.len()
+    );
+    let message = message_bytes(&req)?;
+    req.fees.validate()?;
+    let payment = match (&req.to_address, req.amount) {
+        (Some(address), Some(amount)) => {
+            let address = Address::from_str(address)
+                .map_err(|e| e.to_string())
+                .and_then(|addr| {
+                    addr.require_network(data.config.network)
+                        .map_err(|e| e.to_string())
+                })
+                .map_err(|e| ApiError::InvalidAddress(format!("Invalid address: {}", e)))?;
+            Some((address, Amount::from_sat(amount)))
+        }
+        (None, None) => None,
+        _ => {
+            return Err(ApiError::InvalidRequest(
+                "Give both to_address and amount for a payment, or neither".into(),
+            ))
+        }
+    };
+    if payment.is_none() && req.fees.subtract_fee_from_amount {
+        return Err(ApiError::InvalidRequest(
+            "subtract_fee_from_amount needs a payment".into(),
+        ));
+    }
+
+    let from_wallet = data.wallet_name(&http_req, &req.from_wallet);
+    let Some(client) = data.wallet_client(&from_wallet) else {
+        warn!(
+            "POST /send/with-opreturn - wallet '{}' not found",
+            req.from_wallet
+        );
+        return Err(ApiError::WalletNotFound(req.from_wallet.clone()));
+    };
+    let amount_sat = payment.as_ref().map_or(0, |(_, amount)| amount.to_sat());
+    reservations::check_spend(
+        &data,
+        &http_req,
+        &from_wallet,
+        amount_sat,
+        &req.reservations,
+    )
+    .await?;
+
+    let mut outputs = Vec::new();
+    if let Some((address, amount)) = &payment {
+        outputs.push(json!({ address.to_string(): amount.to_btc() }));
+    }
+    outputs.push(json!({ "data": hex::encode(&message) }));
+    let mut funding = Map::new();
+    if let Some(fee_rate) = req.fees.fee_rate {
+        funding.insert("fee_rate".into(), json!(fee_rate));
+    }
+    if req.fees.subtract_fee_from_amount {
+        funding.insert("subtractFeeFromOutputs".into(), json!([0]));
+    }
+    if let Some(replaceable) = req.fees.replaceable {
+        funding.insert("replaceable".into(), json!(replaceable));
+    }
+    let sent = rpc_pool::blocking(move || {
+        client
+            .call::<Value>(
+                "walletcreatefundedpsbt",
+                &[json!([]), json!(outputs), json!(0), Value::Object(funding)],
+            )
+            .and_then(|funded| sign_and_broadcast(&client, &funded))
+    })
+    .await?;
+    let (tx, txid) = match sent {
+        Ok(sent) => sent,
+        Err(e) => {
+            error!(
+                "Failed to send OP_RETURN message from wallet '{}': {}",
+                req.from_wallet, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+
+    info!(
+        "Wrote {} byte message from '{}' txid={}",
+        message.len(),
+        req.from_wallet,
+        txid
+    );
+    reservations::consume(&data, &from_wallet, amount_sat, &req.reservations);
+    if let Some((address, amount)) = &payment {
+        data.events.publish(Event::PaymentSent {
+            wallet: from_wallet.clone(),
+            to_address: address.to_string(),
+            amount_sat: amount.to_sat(),
+            txid: txid.to_string(),
+        });
+    }
+    let (vout, output) = tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, output)| output.script_pubkey.is_op_return())
+        .ok_or_else(|| ApiError::Internal("the transaction has no OP_RETURN output".into()))?;
+    Ok(HttpResponse::Ok().json(OpReturnSendResponse {
+        txid: txid.to_string(),
+        vout: vout as u32,
+        message_hex: hex::encode(&message),
+        message_bytes: message.len(),
+        script_hex: hex::encode(output.script_pubkey.as_bytes()),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpReturnMessage {
+    vout: u32,
+    // utf8 when the bytes are valid UTF-8 text, hex otherwise
+    encoding: MessageEncoding,
+    // The text, or the hex of the bytes
+    message: String,
+    message_hex: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpReturnMessagesResponse {
+    txid: String,
+    messages: Vec<OpReturnMessage>,
+}
+
+// The bytes pushed after OP_RETURN, joined; other opcodes carry no message
+fn op_return_data(script: &Script) -> Vec<u8> {
+    script
+        .instructions()
+        .skip(1)
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_vec()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn messages(tx: &Transaction) -> Vec<OpReturnMessage> {
+    tx.output
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| output.script_pubkey.is_op_return())
+        .map(|(vout, output)| {
+            let data = op_return_data(&output.script_pubkey);
+            let message_hex = hex::encode(&data);
+            let (encoding, message) = match String::from_utf8(data) {
+                Ok(text) => (MessageEncoding::Utf8, text),
+                Err(_) => (MessageEncoding::Hex, message_hex.clone()),
+            };
+            OpReturnMessage {
+                vout: vout as u32,
+                encoding,
+                message,
+                message_hex,
+            }
+        })
+        .collect()
+}
+
+// The wallet knows its own transactions even after they left the mempool; others
+// come from the node, which needs -txindex once they are confirmed
+fn transaction(node: &Client, wallet: &Client, txid: &Txid) -> Result<Transaction, RpcError> {
+    match wallet.get_transaction(txid, None) {
+        Ok(tx) => deserialize(&tx.hex).map_err(|e| RpcError::ReturnedError(e.to_string())),
+        Err(_) => node.get_raw_transaction(txid, None),
+    }
+}
+
+// Reads the messages other wallets wrote with POST /send/with-opreturn, or anyone else
+#[utoipa::path(
+    get,
+    path = "/tx/{walletid}/{txid}/message",
+    tag = "op-return",
+    responses(
+        (status = 200, body = OpReturnMessagesResponse),
+    )
+)]
+pub(crate) async fn get_message(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, txid) = path.into_inner();
+    info!("GET /tx/{}/{}/message", walletid, txid);
+    let txid = match Txid::from_str(&txid) {
+        Ok(txid) => txid,
+        Err(e) => {
+            warn!("Invalid txid format '{}': {}", txid, e);
+            return Err(ApiError::InvalidTxid(format!(
+                "Invalid transaction ID: {}",
+                e
+            )));
+        }
+    };
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /tx/{}/{}/message - wallet not found", walletid, txid);
+        return Err(ApiError::WalletNotFound(walletid));
+    };
+    let node = node_client(&data, &http_req)?;
+    let tx = rpc_pool::blocking(move || transaction(&node, &client, &txid)).await?;
+    let tx = match tx {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!(
+                "Failed to get transaction '{}' for wallet '{}': {}",
+                txid, wallet_name, e
+            );
+            return Err(ApiError::rpc_not_found(&http_req, e));
+        }
+    };
+    let messages = messages(&tx);
+    if messages.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "Transaction {} carries no OP_RETURN message",
+            txid
+        )));
+    }
+    Ok(HttpResponse::Ok().json(OpReturnMessagesResponse {
+        txid: txid.to_string(),
+        messages,
+    }))
+}
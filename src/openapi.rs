@@ -0,0 +1,205 @@
+use crate::errors::ErrorBody;
+use crate::routes;
+use utoipa::openapi::{ContentBuilder, OpenApi as Spec, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+
+// Served at /api-doc/openapi.json and browsable at /swagger-ui/
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Coin Comic Tales",
+        description = "A Bitcoin regtest API server that demonstrates basic wallet operations and transaction handling."
+    ),
+    paths(
+        crate::metrics::export,
+        crate::health::health,
+        crate::health::ready,
+        crate::auth::register,
+        crate::auth::login,
+        crate::auth::current_user,
+        crate::create_wallet,
+        crate::list_wallets,
+        crate::balances::get_balances,
+        crate::dashboard::get_dashboard,
+        crate::create_address,
+        crate::labels::get_address_owner,
+        crate::mine_blocks,
+        crate::mining::set_miner,
+        crate::mining::list_miners,
+        crate::mining::leaderboard,
+        crate::mining::start_auto_mine,
+        crate::mining::get_auto_mine,
+        crate::mining::stop_auto_mine,
+        crate::balances::get_wallet_balance,
+        crate::balances::get_wallet_balances,
+        crate::sse::wallet_events,
+        crate::keypool::get_keypool,
+        crate::derivation::get_derivation,
+        crate::keys::get_descriptors,
+        crate::keys::get_seed,
+        crate::keypool::refill_keypool,
+        crate::encryption::get_encryption,
+        crate::encryption::encrypt_wallet,
+        crate::encryption::unlock_wallet,
+        crate::encryption::lock_wallet,
+        crate::encryption::change_passphrase,
+        crate::labels::set_label,
+        crate::labels::relabel,
+        crate::activity::wallet_activity,
+        crate::utxos::list_wallet_utxos,
+        crate::dust::sweep_dust,
+        crate::invoices::create_invoice,
+        crate::invoices::get_invoice,
+        crate::invoices::get_policy,
+        crate::invoices::set_policy,
+        crate::reservations::list_reservations,
+        crate::reservations::set_reservation,
+        crate::history::list_transactions,
+        crate::labels::list_labels,
+        crate::labels::list_label_addresses,
+        crate::labels::rename_label,
+        crate::reorg::reorg_report,
+        crate::wallet_export::import_wallet,
+        crate::backup::restore_wallet,
+        crate::backup::backup_wallet,
+        crate::wallet_export::export_wallet,
+        crate::watch_only::import_descriptor,
+        crate::send_bitcoin,
+        crate::op_return::send_with_op_return,
+        crate::transfer::transfer,
+        crate::outbox::list_outbox,
+        crate::replacements::get_replacements,
+        crate::get_transaction,
+        crate::raw_tx::get_raw_transaction,
+        crate::op_return::get_message,
+        crate::fee_bump::bump_transaction,
+        crate::raw_tx::decode_transaction,
+        crate::psbt::create,
+        crate::psbt::sign,
+        crate::psbt::finalize_psbt,
+        crate::psbt::broadcast,
+        crate::multisig::create_multisig,
+        crate::multisig::get_multisig,
+        crate::multisig::fund_multisig,
+        crate::musig::create_session,
+        crate::musig::get_session,
+        crate::musig::exchange_nonces,
+        crate::musig::sign,
+        crate::musig::aggregate,
+        crate::playground::decode_address,
+        crate::scenario::run_scenario,
+        crate::scenario::get_scenario,
+        crate::scenario::choose,
+        crate::exercises::generate,
+        crate::exercises::get_exercise,
+        crate::exercises::grade_exercise,
+        crate::cohort::create_cohort,
+        crate::cohort::list_cohorts,
+        crate::cohort::cohort_dashboard,
+        crate::cohort::provision_students,
+        crate::cohort::student_faucet,
+        crate::channel::open_channel,
+        crate::channel::get_channel,
+        crate::channel::pay,
+        crate::channel::close,
+        crate::watch::watch_address,
+        crate::watch::list_watched,
+        crate::watch::address_history,
+        crate::tx_watch::watch_tx,
+        crate::tx_watch::list_tx_watchers,
+        crate::tx_watch::get_tx_watcher,
+        crate::tx_watch::remove_tx_watcher,
+        crate::fees::get_fees,
+        crate::explorer::get_block_at_height,
+        crate::explorer::get_block,
+        crate::explorer::get_block_target,
+        crate::explorer::get_chain_tip,
+        crate::stale_blocks::get_stale_blocks,
+        crate::reorg::invalidate_block,
+        crate::reorg::reconsider_block,
+        crate::reorg::simulate_reorg,
+        crate::propagation::get_propagation,
+        crate::propagation::get_mempool_diff,
+        crate::mempool::get_mempool,
+        crate::mempool::get_mempool_info,
+        crate::mempool::get_mempool_entry,
+        crate::descriptors::validate_descriptor,
+        crate::complete_chapter,
+        crate::recording::start_recording,
+        crate::recording::list_recordings,
+        crate::recording::stop_recording,
+        crate::recording::get_recording,
+        crate::recording::rewind,
+        crate::event_replay::replay_events,
+        crate::event_replay::control_replay,
+        crate::ws::events_socket,
+        crate::faucet::fund_wallet,
+        crate::sandbox::request_faucet,
+        crate::admin::set_mocktime,
+        crate::admin::advance_mocktime,
+        crate::admin::reload_config,
+        crate::reset::reset,
+        crate::node::get_policy,
+        crate::hwi::list_devices,
+        crate::hwi::create_watch_only_wallet,
+        crate::hwi::sign_psbt,
+        crate::signer::list_signers,
+        crate::signing::get_signing_key,
+        crate::signing::verify,
+        crate::signer::create_wallet,
+        crate::signer::display_address,
+        crate::signer::sign_psbt,
+        crate::fee_bump::prepare_fee_bump,
+    ),
+    components(schemas(ErrorBody)),
+    modifiers(&ErrorResponses, &Versioned)
+)]
+pub(crate) struct ApiDoc;
+
+// The handlers document their paths without the version prefix they are mounted at
+struct Versioned;
+
+impl Modify for Versioned {
+    fn modify(&self, openapi: &mut Spec) {
+        let paths = std::mem::take(&mut openapi.paths.paths);
+        openapi.paths.paths = paths
+            .into_iter()
+            .map(|(path, item)| match path.as_str() {
+                "/metrics" | "/health" | "/ready" => (path, item),
+                _ => (format!("{}{}", routes::V1_PREFIX, path), item),
+            })
+            .collect();
+    }
+}
+
+// Every endpoint can fail with the same error body, see errors.rs for the codes
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut Spec) {
+        let error = ResponseBuilder::new()
+            .description("The request failed; `code` tells why")
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Some(Ref::from_schema_name("ErrorBody")))
+                    .build(),
+            )
+            .build();
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ];
+            for operation in operations.into_iter().flatten() {
+                operation
+                    .responses
+                    .responses
+                    .insert("default".into(), error.clone().into());
+            }
+        }
+    }
+}
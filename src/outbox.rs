@@ -0,0 +1,388 @@
+use crate::db::{unix_now, OutboxEntry};
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::{change, reservations, rpc_pool, sandbox};
+use crate::{AppState, SendBitcoinRequest};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount, Txid};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+const WORKER_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_LIST_LIMIT: usize = 100;
+const MAX_LIST_LIMIT: usize = 1000;
+
+pub(crate) const STATUS_QUEUED: &str = "queued";
+// Signed once; retries broadcast this same transaction
+pub(crate) const STATUS_SIGNED: &str = "signed";
+pub(crate) const STATUS_BROADCAST: &str = "broadcast";
+pub(crate) const STATUS_CONFIRMED: &str = "confirmed";
+pub(crate) const STATUS_FAILED: &str = "failed";
+
+// `send_outbox=true` makes /send queue payments instead of sending them right away
+pub(crate) fn enabled_from_env() -> bool {
+    env::var("send_outbox").is_ok_and(|value| value.trim().eq_ignore_ascii_case("true"))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct OutboxQuery {
+    status: Option<String>,
+    limit: Option<usize>,
+}
+
+// Queues the payment durably; it is validated now, but sent by the worker
+pub(crate) async fn enqueue(
+    data: &AppState,
+    http_req: &HttpRequest,
+    from_wallet: &str,
+    req: &SendBitcoinRequest,
+) -> Result<HttpResponse, ApiError> {
+    if data.wallet_client(from_wallet).is_none() {
+        warn!("POST /send - wallet '{}' not found", req.from_wallet);
+        return Err(ApiError::WalletNotFound(req.from_wallet.clone()));
+    }
+    if let Err(e) = Address::from_str(&req.to_address)
+        .map_err(|e| e.to_string())
+        .and_then(|addr| {
+            addr.require_network(data.config.network)
+                .map_err(|e| e.to_string())
+        })
+    {
+        return Err(ApiError::InvalidAddress(format!("Invalid address: {}", e)));
+    }
+    if req.amount == 0 {
+        return Err(ApiError::InvalidRequest("Amount must be positive".into()));
+    }
+    // Reserved funds stay out of reach of queued payments too
+    reservations::check_spend(data, http_req, from_wallet, req.amount, &req.reservations).await?;
+
+    let idempotency_key = http_req
+        .headers()
+        .get(crate::IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|key| format!("{}:{}", from_wallet, key));
+    // A sandbox session can only queue what its allowance covers
+    if let Some(sandbox) = &data.sandbox {
+        sandbox
+            .reserve_allowance(data, http_req, req.amount)
+            .await?;
+    }
+    let id = Uuid::new_v4().to_string();
+    let now = unix_now();
+    let queued = data.db.enqueue_outbox(&OutboxEntry {
+        id: id.clone(),
+        wallet: from_wallet.to_string(),
+        to_address: req.to_address.clone(),
+        amount_sat: req.amount,
+        message: req.message.clone(),
+        status: STATUS_QUEUED.into(),
+        txid: None,
+        attempts: 0,
+        last_error: None,
+        idempotency_key,
+        created_at: now,
+        updated_at: now,
+        tx_hex: None,
+    });
+    // A retried request gets the entry queued the first time, which is already paid for
+    if queued.as_ref().map_or(true, |entry| entry.id != id) {
+        if let Some(sandbox) = &data.sandbox {
+            sandbox.release_allowance(data, http_req, req.amount).await;
+        }
+    }
+    let entry = queued?;
+    info!(
+        "Queued payment {} of {} sat from '{}'",
+        entry.id, entry.amount_sat, from_wallet
+    );
+    Ok(HttpResponse::Accepted().json(shown(data, http_req, entry)))
+}
+
+// In sandbox mode wallets are shown without their session prefix
+fn shown(data: &AppState, http_req: &HttpRequest, mut entry: OutboxEntry) -> OutboxEntry {
+    let prefix = data.wallet_name(http_req, "");
+    if let Some(wallet) = entry.wallet.strip_prefix(&prefix) {
+        entry.wallet = wallet.to_string();
+    }
+    entry
+}
+
+#[utoipa::path(
+    get,
+    path = "/outbox",
+    tag = "outbox",
+    params(OutboxQuery),
+    responses(
+        (status = 200, body = [OutboxEntry]),
+    )
+)]
+pub(crate) async fn list_outbox(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<OutboxQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /outbox - status={:?}", query.status);
+    let statuses: Vec<&str> = query.status.as_deref().into_iter().collect();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let prefix = data.wallet_name(&http_req, "");
+    let entries: Vec<OutboxEntry> = data
+        .db
+        .outbox_entries(&statuses, limit)?
+        .into_iter()
+        .filter(|entry| entry.wallet.starts_with(&prefix))
+        .map(|entry| shown(&data, &http_req, entry))
+        .collect();
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+// Only node hiccups are retried. Nothing has been broadcast while the payment is
+// built, and a signed one is retried with the same transaction, so a lost reply
+// cannot pay twice. Insufficient funds (-6) and wallet errors (-4) are final.
+fn is_retryable(e: &RpcError) -> bool {
+    match e {
+        RpcError::JsonRpc(JsonRpcError::Rpc(rpc)) => rpc.code == -28,
+        RpcError::JsonRpc(JsonRpcError::Transport(_)) | RpcError::Io(_) => true,
+        _ => false,
+    }
+}
+
+// What the worker has to do once a payment is given up on
+struct Failed {
+    wallet: String,
+    amount_sat: u64,
+}
+
+// Funds and signs the payment. Its coins stay locked, so later entries do not pick
+// them while it waits to be broadcast.
+fn sign(data: &AppState, client: &Client, entry: &OutboxEntry) -> Result<(Txid, String), RpcError> {
+    let address = Address::from_str(&entry.to_address)
+        .map_err(|e| RpcError::ReturnedError(e.to_string()))
+        .and_then(|addr| {
+            addr.require_network(data.config.network)
+                .map_err(|e| RpcError::ReturnedError(e.to_string()))
+        })?;
+    let amount = Amount::from_sat(entry.amount_sat);
+    let funded: Value = client.call(
+        "walletcreatefundedpsbt",
+        &[
+            json!([]),
+            json!([{ address.to_string(): amount.to_btc() }]),
+            json!(0),
+            json!({ "lockUnspents": true }),
+        ],
+    )?;
+    let (tx, hex) = change::sign(client, &funded)?;
+    Ok((tx.txid(), hex))
+}
+
+// Gives the coins of a signed payment that will not be sent back to the wallet
+fn unlock_inputs(client: &Client, hex: &str) {
+    let inputs: Value = match client.call("decoderawtransaction", &[json!(hex)]) {
+        Ok(tx) => tx,
+        Err(e) => {
+            warn!("Cannot decode a failed queued payment: {}", e);
+            return;
+        }
+    };
+    let outpoints: Vec<Value> = inputs["vin"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|input| json!({ "txid": input["txid"], "vout": input["vout"] }))
+        .collect();
+    if let Err(e) = client.call::<bool>("lockunspent", &[json!(true), json!(outpoints)]) {
+        warn!(
+            "Failed to unlock the coins of a failed queued payment: {}",
+            e
+        );
+    }
+}
+
+fn fail(data: &AppState, entry: &OutboxEntry, e: &RpcError) -> Option<Failed> {
+    warn!("Queued payment {} failed: {}", entry.id, e);
+    if let Err(e) =
+        data.db
+            .update_outbox(&entry.id, STATUS_FAILED, None, Some(&e.to_string()), true)
+    {
+        error!("Failed to update queued payment {}: {}", entry.id, e);
+    }
+    Some(Failed {
+        wallet: entry.wallet.clone(),
+        amount_sat: entry.amount_sat,
+    })
+}
+
+fn retry_later(data: &AppState, entry: &OutboxEntry, status: &str, e: &RpcError) {
+    debug!("Queued payment {} not sent yet: {}", entry.id, e);
+    if let Err(e) = data
+        .db
+        .update_outbox(&entry.id, status, None, Some(&e.to_string()), true)
+    {
+        error!("Failed to update queued payment {}: {}", entry.id, e);
+    }
+}
+
+fn broadcast(data: &AppState, entry: &OutboxEntry) -> Option<Failed> {
+    let Some(client) = data.wallet_client(&entry.wallet) else {
+        // Registered wallets may not be restored yet after a restart
+        let _ = data.db.update_outbox(
+            &entry.id,
+            &entry.status,
+            None,
+            Some("wallet is not registered"),
+            true,
+        );
+        return None;
+    };
+    let (txid, hex) = match (&entry.txid, &entry.tx_hex) {
+        (Some(txid), Some(hex)) if entry.status == STATUS_SIGNED => match Txid::from_str(txid) {
+            Ok(txid) => (txid, hex.clone()),
+            Err(e) => return fail(data, entry, &RpcError::ReturnedError(e.to_string())),
+        },
+        _ => match sign(data, &client, entry) {
+            Ok((txid, hex)) => {
+                if let Err(e) = data.db.sign_outbox(&entry.id, &txid.to_string(), &hex) {
+                    // Without the stored transaction a retry could pay twice
+                    error!("Failed to store signed payment {}: {}", entry.id, e);
+                    unlock_inputs(&client, &hex);
+                    return None;
+                }
+                (txid, hex)
+            }
+            Err(e) if is_retryable(&e) => {
+                retry_later(data, entry, STATUS_QUEUED, &e);
+                return None;
+            }
+            Err(e) => return fail(data, entry, &e),
+        },
+    };
+    // A reply lost on an earlier attempt may hide that it was broadcast already
+    let sent = match client.get_transaction(&txid, None) {
+        Ok(_) => Ok(txid),
+        Err(_) => match client.send_raw_transaction(hex.as_str()) {
+            // -27: already in the chain
+            Err(RpcError::JsonRpc(JsonRpcError::Rpc(rpc))) if rpc.code == -27 => Ok(txid),
+            result => result,
+        },
+    };
+    match sent {
+        Ok(txid) => {
+            info!("Broadcast queued payment {} as {}", entry.id, txid);
+            data.events.publish(Event::PaymentSent {
+                wallet: entry.wallet.clone(),
+                to_address: entry.to_address.clone(),
+                amount_sat: entry.amount_sat,
+                txid: txid.to_string(),
+            });
+            if let Err(e) = data.db.update_outbox(
+                &entry.id,
+                STATUS_BROADCAST,
+                Some(&txid.to_string()),
+                None,
+                true,
+            ) {
+                error!("Failed to update queued payment {}: {}", entry.id, e);
+            }
+            None
+        }
+        Err(e) if is_retryable(&e) => {
+            retry_later(data, entry, STATUS_SIGNED, &e);
+            None
+        }
+        Err(e) => {
+            unlock_inputs(&client, &hex);
+            fail(data, entry, &e)
+        }
+    }
+}
+
+fn check_confirmation(data: &AppState, entry: &OutboxEntry) {
+    let (Some(client), Some(txid)) = (
+        data.wallet_client(&entry.wallet),
+        entry
+            .txid
+            .as_deref()
+            .and_then(|txid| Txid::from_str(txid).ok()),
+    ) else {
+        return;
+    };
+    let (status, last_error) = match client.get_transaction(&txid, None) {
+        Ok(tx) if tx.info.confirmations > 0 => (STATUS_CONFIRMED, None),
+        // A conflicting transaction confirmed instead
+        Ok(tx) if tx.info.confirmations < 0 => (STATUS_FAILED, Some("transaction was conflicted")),
+        Ok(_) => return,
+        Err(e) => {
+            debug!("Cannot check queued payment {}: {}", entry.id, e);
+            return;
+        }
+    };
+    info!("Queued payment {} is {}", entry.id, status);
+    if let Err(e) = data
+        .db
+        .update_outbox(&entry.id, status, None, last_error, false)
+    {
+        error!("Failed to update queued payment {}: {}", entry.id, e);
+    }
+}
+
+// Goes through the backlog, oldest first so payments leave in the order they were
+// made, on the blocking thread pool
+fn process(data: &AppState) -> Vec<Failed> {
+    let pending = match data.db.outbox_backlog(
+        &[STATUS_QUEUED, STATUS_SIGNED, STATUS_BROADCAST],
+        MAX_LIST_LIMIT,
+    ) {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Failed to read the outbox: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut failed = Vec::new();
+    for entry in &pending {
+        if entry.status == STATUS_BROADCAST {
+            check_confirmation(data, entry);
+        } else {
+            failed.extend(broadcast(data, entry));
+        }
+    }
+    failed
+}
+
+// Sends queued payments in order and follows broadcast ones until they confirm
+pub(crate) async fn run_worker(data: web::Data<AppState>) {
+    loop {
+        actix_rt::time::sleep(WORKER_INTERVAL).await;
+        let state = data.clone();
+        let failed = match rpc_pool::blocking(move || process(&state)).await {
+            Ok(failed) => failed,
+            Err(e) => {
+                error!("Outbox worker failed: {}", e);
+                continue;
+            }
+        };
+        // Payments that never left give their sandbox allowance back
+        let Some(sandbox) = &data.sandbox else {
+            continue;
+        };
+        for payment in failed {
+            if let Some(session) = sandbox::session_of(&payment.wallet) {
+                sandbox
+                    .release_session_allowance(&data, session, payment.amount_sat)
+                    .await;
+            }
+        }
+    }
+}
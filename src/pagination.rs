@@ -0,0 +1,176 @@
+use crate::errors::ApiError;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use utoipa::ToSchema;
+
+// Listings take `limit` and `cursor` query parameters and answer with `items` and a
+// `next_cursor`. Items keep a stable order, so new ones arriving between two requests
+// neither repeat nor skip items, and stores seek to the cursor instead of counting
+// past an offset.
+
+// Where a page ended: the last item's sort key, usually a time or height, and an id
+// that breaks ties. Clients only see it encoded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Cursor {
+    pub(crate) at: i64,
+    pub(crate) id: String,
+}
+
+impl Cursor {
+    pub(crate) fn new(at: i64, id: impl Into<String>) -> Self {
+        Cursor { at, id: id.into() }
+    }
+
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        serde_json::from_slice(&hex::decode(cursor).ok()?).ok()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Page<T> {
+    pub(crate) items: Vec<T>,
+    // Pass as `cursor` to get the next page; null on the last page
+    pub(crate) next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub(crate) fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+// The `limit` and `cursor` of a listing request
+pub(crate) struct PageRequest {
+    pub(crate) limit: usize,
+    // Only items past this one belong on the page
+    pub(crate) after: Option<Cursor>,
+}
+
+impl PageRequest {
+    // `limit` defaults to `default_limit` and is capped at `max_limit`
+    pub(crate) fn new(
+        limit: Option<usize>,
+        cursor: Option<&str>,
+        default_limit: usize,
+        max_limit: usize,
+    ) -> Result<Self, ApiError> {
+        let after = match cursor {
+            None => None,
+            Some(cursor) => Some(
+                Cursor::decode(cursor)
+                    .ok_or_else(|| ApiError::InvalidRequest("Invalid cursor".into()))?,
+            ),
+        };
+        Ok(PageRequest {
+            limit: limit.unwrap_or(default_limit).clamp(1, max_limit),
+            after,
+        })
+    }
+
+    // Rows to read: one more than fits on the page tells whether another page follows
+    pub(crate) fn fetch(&self) -> usize {
+        self.limit + 1
+    }
+
+    // Whether an item of a newest-first listing belongs past the cursor
+    pub(crate) fn is_older(&self, cursor: &Cursor) -> bool {
+        self.after.as_ref().is_none_or(|after| cursor < after)
+    }
+
+    // Pages rows already read in order from after the cursor, at most `fetch()` of them
+    pub(crate) fn page<T>(&self, mut rows: Vec<T>, cursor: impl Fn(&T) -> Cursor) -> Page<T> {
+        let next_cursor = if rows.len() > self.limit {
+            rows.truncate(self.limit);
+            rows.last().map(|row| cursor(row).encode())
+        } else {
+            None
+        };
+        Page {
+            items: rows,
+            next_cursor,
+        }
+    }
+
+    // Pages a listing assembled in memory, newest first
+    pub(crate) fn paginate<T>(&self, mut items: Vec<T>, cursor: impl Fn(&T) -> Cursor) -> Page<T> {
+        items.sort_by_key(|item| Reverse(cursor(item)));
+        let rows = items
+            .into_iter()
+            .filter(|item| self.is_older(&cursor(item)))
+            .take(self.fetch())
+            .collect();
+        self.page(rows, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(limit: usize, cursor: Option<&str>) -> PageRequest {
+        PageRequest::new(Some(limit), cursor, 10, 100).unwrap()
+    }
+
+    fn key(item: &(i64, &str)) -> Cursor {
+        Cursor::new(item.0, item.1)
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor::new(1_700_000_000, "txid:1");
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert!(Cursor::decode("not hex").is_none());
+        assert!(Cursor::decode(&hex::encode("[1,2]")).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_cursors() {
+        assert!(matches!(
+            PageRequest::new(None, Some("zz"), 10, 100),
+            Err(ApiError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn limit_defaults_and_caps() {
+        assert_eq!(PageRequest::new(None, None, 10, 100).unwrap().limit, 10);
+        assert_eq!(request(0, None).limit, 1);
+        assert_eq!(request(1000, None).limit, 100);
+    }
+
+    #[test]
+    fn pages_newest_first_without_gaps() {
+        // Two items share a time, the id breaks the tie
+        let items = vec![(3, "c"), (1, "a"), (2, "b"), (2, "bb"), (4, "d")];
+        let first = request(2, None).paginate(items.clone(), key);
+        assert_eq!(first.items, vec![(4, "d"), (3, "c")]);
+        let second = request(2, first.next_cursor.as_deref()).paginate(items.clone(), key);
+        assert_eq!(second.items, vec![(2, "bb"), (2, "b")]);
+        let last = request(2, second.next_cursor.as_deref()).paginate(items, key);
+        assert_eq!(last.items, vec![(1, "a")]);
+        assert!(last.next_cursor.is_none());
+    }
+
+    #[test]
+    fn new_items_do_not_shift_later_pages() {
+        let mut items = vec![(1, "a"), (2, "b"), (3, "c")];
+        let first = request(2, None).paginate(items.clone(), key);
+        items.push((5, "e"));
+        let second = request(2, first.next_cursor.as_deref()).paginate(items, key);
+        assert_eq!(second.items, vec![(1, "a")]);
+    }
+
+    #[test]
+    fn a_full_last_page_has_no_next_cursor() {
+        let page = request(2, None).page(vec![(2, "b"), (1, "a")], key);
+        assert_eq!(page.items.len(), 2);
+        assert!(page.next_cursor.is_none());
+    }
+}
@@ -0,0 +1,444 @@
+use crate::errors::ApiError;
+use actix_web::{web, HttpResponse};
+use bitcoincore_rpc::bitcoin::base58;
+use bitcoincore_rpc::bitcoin::hashes::{sha256d, Hash};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Addresses are taken apart by hand rather than with Address::from_str, so every
+// intermediate value can be shown
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CHECKSUM_LEN: usize = 6;
+// What the checksum polynomial leaves over a valid string (BIP173, BIP350)
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const BASE58_CHECKSUM_LEN: usize = 4;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct DecodeAddressRequest {
+    address: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "encoding", rename_all = "snake_case")]
+enum Encoding {
+    // bech32 for witness version 0, bech32m from version 1 (taproot) on
+    Bech32 {
+        variant: &'static str,
+        hrp: String,
+        // Everything after the last '1', checksum included
+        data_part: String,
+        // The data part as 5-bit numbers, one per character
+        data_values: Vec<u8>,
+        witness_version: u8,
+        program_hex: String,
+        program_length: usize,
+        checksum: String,
+    },
+    Base58check {
+        version_byte: u8,
+        payload_hex: String,
+        checksum_hex: String,
+    },
+}
+
+#[derive(Serialize, ToSchema)]
+struct DecodedAddress {
+    address: String,
+    #[serde(flatten)]
+    encoding: Encoding,
+    network: &'static str,
+    address_type: &'static str,
+    script_pubkey_hex: String,
+    // How the address was taken apart, one sentence per step
+    steps: Vec<String>,
+}
+
+fn hrp_network(hrp: &str) -> &'static str {
+    match hrp {
+        "bc" => "mainnet",
+        "tb" => "testnet or signet",
+        "bcrt" => "regtest",
+        _ => "unknown",
+    }
+}
+
+// BIP173 checksum: a BCH code over GF(32), computed as a polynomial remainder
+fn polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ u32::from(*value);
+        for (bit, generator) in GENERATOR.iter().enumerate() {
+            if (top >> bit) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+// The hrp goes into the checksum as its high bits, a zero, then its low bits
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let bytes = hrp.as_bytes();
+    bytes
+        .iter()
+        .map(|byte| byte >> 5)
+        .chain([0])
+        .chain(bytes.iter().map(|byte| byte & 31))
+        .collect()
+}
+
+// Regroups 5-bit values into bytes; leftover bits must be zero padding
+fn five_to_eight(values: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for value in values {
+        acc = (acc << 5) | u32::from(*value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || (acc & ((1 << bits) - 1)) != 0 {
+        return Err("the program has non-zero padding bits".into());
+    }
+    Ok(bytes)
+}
+
+fn decode_bech32(address: &str) -> Result<DecodedAddress, String> {
+    let mut steps = Vec::new();
+    if address.chars().any(|c| c.is_ascii_lowercase())
+        && address.chars().any(|c| c.is_ascii_uppercase())
+    {
+        return Err("bech32 addresses are all lowercase or all uppercase, not mixed".into());
+    }
+    let lower = address.to_ascii_lowercase();
+    let separator = lower
+        .rfind('1')
+        .ok_or("a bech32 address has a '1' between its hrp and data part")?;
+    let (hrp, data_part) = (&lower[..separator], &lower[separator + 1..]);
+    if hrp.is_empty() || data_part.len() < BECH32_CHECKSUM_LEN + 1 {
+        return Err("the hrp or the data part is too short".into());
+    }
+    steps.push(format!(
+        "The last '1' separates the human-readable part '{hrp}' ({}) from the data part '{data_part}'.",
+        hrp_network(hrp)
+    ));
+
+    let data_values = data_part
+        .bytes()
+        .map(|c| {
+            BECH32_CHARSET
+                .iter()
+                .position(|known| *known == c)
+                .map(|value| value as u8)
+                .ok_or_else(|| format!("'{}' is not in the bech32 alphabet", c as char))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    steps.push(format!(
+        "Each data character is a 5-bit number, its position in the alphabet '{}': {:?}.",
+        std::str::from_utf8(BECH32_CHARSET).unwrap_or_default(),
+        data_values
+    ));
+
+    let checksum_start = data_values.len() - BECH32_CHECKSUM_LEN;
+    let checksum = data_part[checksum_start..].to_string();
+    let residue = polymod(&[hrp_expand(hrp), data_values.clone()].concat());
+    let variant = match residue {
+        BECH32_CONST => "bech32",
+        BECH32M_CONST => "bech32m",
+        _ => {
+            return Err(format!(
+                "the checksum '{checksum}' does not match, the address has a typo"
+            ))
+        }
+    };
+    steps.push(format!(
+        "The last 6 characters '{checksum}' are the checksum. Run through the BCH polynomial together with the hrp, the whole string leaves {residue:#x}, the constant of {variant}."
+    ));
+
+    let witness_version = data_values[0];
+    if witness_version > 16 {
+        return Err(format!("witness version {witness_version} does not exist"));
+    }
+    let expected = if witness_version == 0 {
+        "bech32"
+    } else {
+        "bech32m"
+    };
+    if variant != expected {
+        return Err(format!(
+            "witness version {witness_version} must use {expected}, but the checksum is {variant}"
+        ));
+    }
+    steps.push(format!(
+        "The first data value is the witness version: {witness_version}."
+    ));
+
+    let program = five_to_eight(&data_values[1..checksum_start])?;
+    if !(2..=40).contains(&program.len())
+        || (witness_version == 0 && ![20, 32].contains(&program.len()))
+    {
+        return Err(format!(
+            "a version {witness_version} witness program cannot be {} bytes long",
+            program.len()
+        ));
+    }
+    let address_type = match (witness_version, program.len()) {
+        (0, 20) => "p2wpkh",
+        (0, 32) => "p2wsh",
+        (1, 32) => "p2tr",
+        _ => "unknown witness program",
+    };
+    steps.push(format!(
+        "The {} values before the checksum, regrouped from 5 into 8 bits, give the {}-byte witness program {}: {}.",
+        checksum_start - 1,
+        program.len(),
+        hex::encode(&program),
+        match address_type {
+            "p2wpkh" => "the HASH160 of a public key",
+            "p2wsh" => "the SHA256 of a witness script",
+            "p2tr" => "an x-only taproot output key",
+            _ => "a program for a future soft fork",
+        }
+    ));
+
+    // OP_0 is 0x00, OP_1..OP_16 are 0x51..0x60
+    let version_opcode = if witness_version == 0 {
+        0
+    } else {
+        0x50 + witness_version
+    };
+    let script_pubkey = [&[version_opcode, program.len() as u8], program.as_slice()].concat();
+    steps.push(format!(
+        "The output script is the version opcode followed by a push of the program: {}.",
+        hex::encode(&script_pubkey)
+    ));
+
+    Ok(DecodedAddress {
+        address: address.to_string(),
+        network: hrp_network(hrp),
+        address_type,
+        script_pubkey_hex: hex::encode(script_pubkey),
+        steps,
+        encoding: Encoding::Bech32 {
+            variant,
+            hrp: hrp.to_string(),
+            data_part: data_part.to_string(),
+            data_values,
+            witness_version,
+            program_length: program.len(),
+            program_hex: hex::encode(program),
+            checksum,
+        },
+    })
+}
+
+fn decode_base58(address: &str) -> Result<DecodedAddress, String> {
+    let mut steps = Vec::new();
+    let bytes = base58::decode(address).map_err(|e| format!("not valid base58: {e}"))?;
+    let leading_ones = address.bytes().take_while(|c| *c == b'1').count();
+    steps.push(format!(
+        "Read as a base58 number (alphabet without 0, O, I and l), the address is the {} bytes {}. Each leading '1' stands for a zero byte, here {}.",
+        bytes.len(),
+        hex::encode(&bytes),
+        leading_ones
+    ));
+    if bytes.len() != 1 + 20 + BASE58_CHECKSUM_LEN {
+        return Err(format!(
+            "a legacy address is 25 bytes, a version, a 20-byte hash and a checksum, not {}",
+            bytes.len()
+        ));
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - BASE58_CHECKSUM_LEN);
+    let computed = sha256d::Hash::hash(body);
+    let computed = &computed.as_byte_array()[..BASE58_CHECKSUM_LEN];
+    if computed != checksum {
+        return Err(format!(
+            "the checksum {} does not match {}, the address has a typo",
+            hex::encode(checksum),
+            hex::encode(computed)
+        ));
+    }
+    steps.push(format!(
+        "The last 4 bytes {} are the checksum: the first 4 bytes of the double SHA256 of the other bytes, which match.",
+        hex::encode(checksum)
+    ));
+
+    let (version_byte, payload) = (body[0], &body[1..]);
+    let (network, address_type) = match version_byte {
+        0x00 => ("mainnet", "p2pkh"),
+        0x05 => ("mainnet", "p2sh"),
+        0x6f => ("testnet, signet or regtest", "p2pkh"),
+        0xc4 => ("testnet, signet or regtest", "p2sh"),
+        other => return Err(format!("unknown version byte {other:#04x}")),
+    };
+    steps.push(format!(
+        "The first byte {version_byte:#04x} is the version: a {address_type} address on {network}. The 20 bytes after it are {}.",
+        if address_type == "p2pkh" {
+            "the HASH160 of a public key"
+        } else {
+            "the HASH160 of a redeem script"
+        }
+    ));
+
+    // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG, or OP_HASH160 <hash> OP_EQUAL
+    let script_pubkey = if address_type == "p2pkh" {
+        [&[0x76, 0xa9, 0x14], payload, &[0x88, 0xac]].concat()
+    } else {
+        [&[0xa9, 0x14], payload, &[0x87]].concat()
+    };
+    steps.push(format!(
+        "The output script built around the hash is {}.",
+        hex::encode(&script_pubkey)
+    ));
+
+    Ok(DecodedAddress {
+        address: address.to_string(),
+        network,
+        address_type,
+        script_pubkey_hex: hex::encode(script_pubkey),
+        steps,
+        encoding: Encoding::Base58check {
+            version_byte,
+            payload_hex: hex::encode(payload),
+            checksum_hex: hex::encode(checksum),
+        },
+    })
+}
+
+// Segwit addresses start with their hrp and a '1', which base58 addresses never
+// have after a bc/tb/bcrt prefix
+fn looks_like_bech32(address: &str) -> bool {
+    let lower = address.to_ascii_lowercase();
+    ["bc1", "tb1", "bcrt1"]
+        .iter()
+        .any(|prefix| lower.starts_with(prefix))
+}
+
+// Explains an address without asking the node, so it works for any network
+#[utoipa::path(
+    post,
+    path = "/playground/decode-address",
+    tag = "playground",
+    responses(
+        (status = 200, body = DecodedAddress),
+    )
+)]
+pub(crate) async fn decode_address(
+    req: web::Json<DecodeAddressRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /playground/decode-address - address='{}'",
+        req.address
+    );
+    let address = req.address.trim();
+    let decoded = if looks_like_bech32(address) {
+        decode_bech32(address)
+    } else {
+        decode_base58(address)
+    };
+    match decoded {
+        Ok(decoded) => Ok(HttpResponse::Ok().json(decoded)),
+        Err(e) => {
+            warn!("Cannot decode address '{}': {}", address, e);
+            Err(ApiError::InvalidAddress(format!("Invalid address: {}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(address: &str) -> Result<String, String> {
+        let decoded = if looks_like_bech32(address) {
+            decode_bech32(address)
+        } else {
+            decode_base58(address)
+        };
+        decoded.map(|decoded| decoded.script_pubkey_hex)
+    }
+
+    // Valid addresses of BIP173 (witness version 0) and BIP350
+    #[test]
+    fn decodes_segwit_vectors() {
+        for (address, expected) in [
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs",
+                "5210751e76e8199196d454941c45d1b3a323",
+            ),
+            ("BC1SW50QGDZ25J", "6002751e"),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+        ] {
+            assert_eq!(script(address).as_deref(), Ok(expected), "{address}");
+        }
+    }
+
+    #[test]
+    fn reports_the_variant() {
+        let decoded =
+            decode_bech32("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0")
+                .unwrap();
+        assert_eq!(decoded.address_type, "p2tr");
+        assert!(matches!(
+            decoded.encoding,
+            Encoding::Bech32 {
+                variant: "bech32m",
+                witness_version: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_segwit_addresses() {
+        for address in [
+            // Typo in the data part
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5",
+            // Mixed case
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kV8f3t4",
+            // A 16-byte version 0 program
+            "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+            // Version 2 with a bech32 checksum, valid before BIP350
+            "bc1zw508d6qejxtdg4y5r3zarvaryvqyzf3du",
+            // 'b' is not in the alphabet
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3tb",
+        ] {
+            assert!(script(address).is_err(), "{address}");
+        }
+    }
+
+    #[test]
+    fn decodes_base58_addresses() {
+        assert_eq!(
+            script("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa").as_deref(),
+            Ok("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac")
+        );
+        assert!(script("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb").is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use crate::errors::ApiError;
+use crate::mempool::fee_rate;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpResponse};
+use bitcoincore_rpc::bitcoin::{BlockHash, Txid};
+use bitcoincore_rpc::json::GetMempoolEntryResult;
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// The node at rpc_url, which the rest of the server talks to
+pub(crate) const PRIMARY_NODE: &str = "primary";
+pub(crate) const SOURCE_ZMQ: &str = "zmq";
+const SOURCE_POLL: &str = "poll";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Blocks walked back from a new tip, so blocks arriving between two polls get a time too
+const MAX_CATCH_UP_BLOCKS: usize = 20;
+// RPC_INVALID_ADDRESS_OR_KEY: the node does not have the block
+const RPC_BLOCK_NOT_FOUND: i32 = -5;
+// Divergent transactions listed in a mempool diff
+const MAX_DIVERGENT_TXS: usize = 500;
+
+#[derive(Debug, Clone)]
+pub(crate) struct NetworkNode {
+    pub(crate) name: String,
+    pub(crate) rpc_url: String,
+}
+
+// `network_nodes` lists the other nodes of the toy network as a comma separated list
+// of name=rpc_url pairs, e.g. "bob=http://bitcoin-bob:18443". They share the RPC
+// credentials of the primary node.
+pub(crate) fn nodes_from_env() -> Result<Vec<NetworkNode>, RpcError> {
+    let Ok(value) = env::var("network_nodes") else {
+        return Ok(Vec::new());
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((name, rpc_url))
+                if !name.trim().is_empty() && name.trim() != PRIMARY_NODE =>
+            {
+                Ok(NetworkNode {
+                    name: name.trim().to_string(),
+                    rpc_url: rpc_url.trim().to_string(),
+                })
+            }
+            _ => Err(RpcError::ReturnedError(format!(
+                "invalid network_nodes entry '{entry}', expected name=rpc_url with a name other than '{PRIMARY_NODE}'"
+            ))),
+        })
+        .collect()
+}
+
+#[derive(Serialize, ToSchema)]
+struct NodeSighting {
+    node: String,
+    // None when the node could not be asked, see `error`
+    has_block: Option<bool>,
+    // Unix time in milliseconds; None for blocks the node had before it was watched
+    seen_at_ms: Option<i64>,
+    // Behind the first node that saw the block
+    delay_ms: Option<i64>,
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PropagationResponse {
+    block_hash: String,
+    height: Option<u64>,
+    first_seen_at_ms: Option<i64>,
+    node_count: usize,
+    nodes_with_block: usize,
+    // Time from the first to the last sighting, once every node has the block
+    propagation_ms: Option<i64>,
+    // Earliest first, then the nodes that have not seen the block
+    nodes: Vec<NodeSighting>,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+pub(crate) fn record_sighting(data: &AppState, node: &str, block_hash: &str, source: &str) {
+    match data
+        .db
+        .record_block_sighting(block_hash, node, now_ms(), source)
+    {
+        Ok(true) => debug!("Node '{}' has block {} ({})", node, block_hash, source),
+        Ok(false) => {}
+        Err(e) => error!(
+            "Failed to record that node '{}' has block {}: {}",
+            node, block_hash, e
+        ),
+    }
+}
+
+fn node_clients(data: &AppState) -> Vec<(String, Result<Client, RpcError>)> {
+    let mut clients = vec![(PRIMARY_NODE.to_string(), data.config.create_node_client())];
+    clients.extend(
+        data.config
+            .network_nodes
+            .iter()
+            .map(|node| (node.name.clone(), data.config.rpc_client(&node.rpc_url))),
+    );
+    clients
+}
+
+struct NodeWatch {
+    name: String,
+    client: Client,
+    tip: Option<BlockHash>,
+}
+
+impl NodeWatch {
+    fn poll(&mut self, data: &AppState) -> Result<(), RpcError> {
+        let tip = self.client.get_best_block_hash()?;
+        let Some(last_tip) = self.tip.replace(tip) else {
+            // Blocks the node had before it was watched get no time
+            return Ok(());
+        };
+        let mut hash = tip;
+        for _ in 0..MAX_CATCH_UP_BLOCKS {
+            if hash == last_tip {
+                break;
+            }
+            record_sighting(data, &self.name, &hash.to_string(), SOURCE_POLL);
+            hash = self.client.get_block_header(&hash)?.prev_blockhash;
+        }
+        Ok(())
+    }
+}
+
+// Polls the tip of every node of the toy network and records when each block showed
+// up where. The primary node's blocks are also timed by the ZMQ listener, when enabled.
+pub(crate) async fn watch_propagation(data: web::Data<AppState>) {
+    let mut watches: Vec<NodeWatch> = node_clients(&data)
+        .into_iter()
+        .filter_map(|(name, client)| match client {
+            Ok(client) => Some(NodeWatch {
+                name,
+                client,
+                tip: None,
+            }),
+            Err(e) => {
+                warn!("Cannot watch node '{}' for block propagation: {}", name, e);
+                None
+            }
+        })
+        .collect();
+    info!(
+        "Watching block propagation across {} node(s)",
+        watches.len()
+    );
+    loop {
+        let polled = rpc_pool::with_state(&data, move |data| {
+            for watch in &mut watches {
+                if let Err(e) = watch.poll(data) {
+                    debug!("Cannot read the tip of node '{}': {}", watch.name, e);
+                }
+            }
+            watches
+        });
+        watches = match polled.await {
+            Ok(watches) => watches,
+            Err(e) => {
+                error!("Block propagation watching stopped: {}", e);
+                return;
+            }
+        };
+        actix_rt::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Whether the node has the block, and its height
+fn has_block(client: &Client, hash: &BlockHash) -> Result<Option<u64>, RpcError> {
+    match client.get_block_header_info(hash) {
+        Ok(header) => Ok(Some(header.height as u64)),
+        Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == RPC_BLOCK_NOT_FOUND => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/network/propagation/{blockhash}",
+    tag = "propagation",
+    responses(
+        (status = 200, body = PropagationResponse),
+    )
+)]
+pub(crate) async fn get_propagation(
+    data: web::Data<AppState>,
+    blockhash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /network/propagation/{}", blockhash);
+    let hash = BlockHash::from_str(&blockhash)
+        .map_err(|e| ApiError::InvalidRequest(format!("Invalid block hash: {}", e)))?;
+    let sightings = data.db.block_sightings(&hash.to_string())?;
+
+    let answers = rpc_pool::with_state(&data, move |data| {
+        node_clients(data)
+            .into_iter()
+            .map(|(name, client)| {
+                let answer = client.and_then(|client| has_block(&client, &hash));
+                (name, answer)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    let mut height = None;
+    let mut nodes: Vec<NodeSighting> = Vec::new();
+    for (name, answer) in answers {
+        let sighting = sightings.iter().find(|sighting| sighting.node == name);
+        let (has, error) = match answer {
+            Ok(block_height) => {
+                height = height.or(block_height);
+                (Some(block_height.is_some() || sighting.is_some()), None)
+            }
+            Err(e) => {
+                warn!("Cannot ask node '{}' about block {}: {}", name, hash, e);
+                (sighting.map(|_| true), Some(e.to_string()))
+            }
+        };
+        nodes.push(NodeSighting {
+            node: name,
+            has_block: has,
+            seen_at_ms: sighting.map(|sighting| sighting.seen_at_ms),
+            delay_ms: None,
+            source: sighting.map(|sighting| sighting.source.clone()),
+            error,
+        });
+    }
+    let nodes_with_block = nodes
+        .iter()
+        .filter(|node| node.has_block == Some(true))
+        .count();
+    if nodes_with_block == 0 && nodes.iter().all(|node| node.error.is_none()) {
+        return Err(ApiError::NotFound(format!(
+            "No node has block {}",
+            blockhash
+        )));
+    }
+
+    let first_seen_at_ms = nodes.iter().filter_map(|node| node.seen_at_ms).min();
+    for node in &mut nodes {
+        node.delay_ms = node
+            .seen_at_ms
+            .zip(first_seen_at_ms)
+            .map(|(seen, first)| seen - first);
+    }
+    nodes.sort_by_key(|node| (node.seen_at_ms.is_none(), node.seen_at_ms));
+    let propagation_ms = if nodes.iter().all(|node| node.seen_at_ms.is_some()) {
+        nodes.iter().filter_map(|node| node.delay_ms).max()
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(PropagationResponse {
+        block_hash: hash.to_string(),
+        height,
+        first_seen_at_ms,
+        node_count: nodes.len(),
+        nodes_with_block,
+        propagation_ms,
+        nodes,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct NodeMempool {
+    node: String,
+    // None when the node could not be asked, see `error`
+    tx_count: Option<usize>,
+    // Transactions no other node has
+    unique_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Presence {
+    node: String,
+    // Unix time the transaction entered this node's mempool
+    entered_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DivergentTransaction {
+    txid: String,
+    vsize: u64,
+    fee_rate_sat_vb: f64,
+    // Earliest first
+    present_on: Vec<Presence>,
+    missing_on: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MempoolDiffResponse {
+    nodes: Vec<NodeMempool>,
+    // In the mempool of every node that answered
+    common_count: usize,
+    divergent_count: usize,
+    // Highest fee rate first, at most 500
+    divergent: Vec<DivergentTransaction>,
+}
+
+// Compares the mempools of all nodes of the toy network. A transaction missing on some
+// nodes is either still on its way or was refused by their relay policy.
+#[utoipa::path(
+    get,
+    path = "/network/mempool-diff",
+    tag = "propagation",
+    responses(
+        (status = 200, body = MempoolDiffResponse),
+    )
+)]
+pub(crate) async fn get_mempool_diff(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("GET /network/mempool-diff");
+    if data.config.network_nodes.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "Only one node is configured; list the others in network_nodes".into(),
+        ));
+    }
+    let mut nodes = Vec::new();
+    let mut mempools: Vec<(String, HashMap<Txid, GetMempoolEntryResult>)> = Vec::new();
+    let answers = rpc_pool::with_state(&data, |data| {
+        node_clients(data)
+            .into_iter()
+            .map(|(name, client)| {
+                let answer = client.and_then(|client| client.get_raw_mempool_verbose());
+                (name, answer)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    for (name, answer) in answers {
+        match answer {
+            Ok(mempool) => {
+                nodes.push(NodeMempool {
+                    node: name.clone(),
+                    tx_count: Some(mempool.len()),
+                    unique_count: 0,
+                    error: None,
+                });
+                mempools.push((name, mempool));
+            }
+            Err(e) => {
+                warn!("Cannot read the mempool of node '{}': {}", name, e);
+                nodes.push(NodeMempool {
+                    node: name,
+                    tx_count: None,
+                    unique_count: 0,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+
+    let txids: BTreeSet<&Txid> = mempools
+        .iter()
+        .flat_map(|(_, mempool)| mempool.keys())
+        .collect();
+    let mut common_count = 0;
+    let mut divergent = Vec::new();
+    for txid in txids {
+        let (present, missing): (Vec<_>, Vec<_>) = mempools
+            .iter()
+            .partition(|(_, mempool)| mempool.contains_key(txid));
+        if missing.is_empty() {
+            common_count += 1;
+            continue;
+        }
+        if let [(node, _)] = present.as_slice() {
+            if let Some(entry) = nodes.iter_mut().find(|entry| &entry.node == node) {
+                entry.unique_count += 1;
+            }
+        }
+        let Some(entry) = present.first().and_then(|(_, mempool)| mempool.get(txid)) else {
+            continue;
+        };
+        let mut present_on: Vec<Presence> = present
+            .iter()
+            .filter_map(|(node, mempool)| {
+                Some(Presence {
+                    node: node.clone(),
+                    entered_at: mempool.get(txid)?.time,
+                })
+            })
+            .collect();
+        present_on.sort_by_key(|presence| presence.entered_at);
+        divergent.push(DivergentTransaction {
+            txid: txid.to_string(),
+            vsize: entry.vsize,
+            fee_rate_sat_vb: fee_rate(entry.fees.base, entry.vsize),
+            present_on,
+            missing_on: missing.iter().map(|(node, _)| node.clone()).collect(),
+        });
+    }
+    let divergent_count = divergent.len();
+    divergent.sort_by(|a, b| b.fee_rate_sat_vb.total_cmp(&a.fee_rate_sat_vb));
+    divergent.truncate(MAX_DIVERGENT_TXS);
+    Ok(HttpResponse::Ok().json(MempoolDiffResponse {
+        nodes,
+        common_count,
+        divergent_count,
+        divergent,
+    }))
+}
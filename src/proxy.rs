@@ -0,0 +1,112 @@
+use crate::sinks::SinksConfig;
+use crate::zmq::ZmqConfig;
+use bitcoincore_rpc::{Client, Error as RpcError};
+use dotenv as env;
+use std::fmt;
+
+// SOCKS5 proxy for outbound connections, e.g. Tor's 127.0.0.1:9050
+#[derive(Clone)]
+pub(crate) struct ProxyConfig {
+    pub(crate) addr: String,
+    pub(crate) auth: Option<(String, String)>,
+}
+
+impl fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("addr", &self.addr)
+            .field(
+                "auth",
+                &self.auth.as_ref().map(|(user, _)| (user, "<redacted>")),
+            )
+            .finish()
+    }
+}
+
+impl ProxyConfig {
+    // `socks5_proxy` enables the proxy; `socks5_proxy_user`/`socks5_proxy_password`
+    // are optional and can be used for Tor stream isolation
+    pub(crate) fn from_env() -> Option<Self> {
+        let addr = match env::var("socks5_proxy") {
+            Ok(addr) if !addr.trim().is_empty() => addr.trim().to_string(),
+            _ => return None,
+        };
+        let auth = match (
+            env::var("socks5_proxy_user"),
+            env::var("socks5_proxy_password"),
+        ) {
+            (Ok(user), Ok(password)) => Some((user, password)),
+            _ => None,
+        };
+        Some(Self { addr, auth })
+    }
+
+    // Only the RPC and HTTP clients go through the proxy. MQTT, NATS, Nostr and ZMQ
+    // open their own connections, which would leave the real address behind the
+    // proxy's back, so the server refuses to start with any of them.
+    pub(crate) fn check_direct_connections(
+        &self,
+        sinks: &SinksConfig,
+        zmq: Option<&ZmqConfig>,
+    ) -> Result<(), RpcError> {
+        let direct: Vec<&str> = [
+            ("mqtt", sinks.mqtt.is_some()),
+            ("nats", sinks.nats.is_some()),
+            ("nostr", sinks.nostr.is_some()),
+            ("zmq", zmq.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect();
+        if direct.is_empty() {
+            return Ok(());
+        }
+        Err(RpcError::ReturnedError(format!(
+            "socks5_proxy cannot be combined with {}, which connect around the proxy",
+            direct.join(", ")
+        )))
+    }
+
+    // The RPC transport connects to the node address through the proxy. The node
+    // host is resolved locally, so it must be an IP or a locally resolvable name.
+    pub(crate) fn rpc_client(
+        &self,
+        url: &str,
+        user: &str,
+        password: &str,
+    ) -> Result<Client, RpcError> {
+        let client = jsonrpc::Client::http_proxy(
+            url,
+            Some(user.to_string()),
+            Some(password.to_string()),
+            &self.addr,
+            self.auth
+                .as_ref()
+                .map(|(user, pass)| (user.as_str(), pass.as_str())),
+        )
+        .map_err(|e| RpcError::JsonRpc(e.into()))?;
+        Ok(Client::from_jsonrpc(client))
+    }
+
+    // The async RPC client goes through the proxy like rpc_client, resolving the node
+    // host locally
+    #[cfg(feature = "async-rpc")]
+    pub(crate) fn async_rpc_proxy(&self) -> Result<reqwest::Proxy, String> {
+        let proxy = reqwest::Proxy::all(format!("socks5://{}", self.addr))
+            .map_err(|e| format!("invalid socks5 proxy '{}': {e}", self.addr))?;
+        Ok(match &self.auth {
+            Some((user, password)) => proxy.basic_auth(user, password),
+            None => proxy,
+        })
+    }
+
+    // socks5h lets the proxy resolve host names, so .onion webhook URLs work too
+    pub(crate) fn reqwest_proxy(&self) -> Result<reqwest::Proxy, String> {
+        let proxy = reqwest::Proxy::all(format!("socks5h://{}", self.addr))
+            .map_err(|e| format!("invalid socks5 proxy '{}': {e}", self.addr))?;
+        Ok(match &self.auth {
+            Some((user, password)) => proxy.basic_auth(user, password),
+            None => proxy,
+        })
+    }
+}
@@ -0,0 +1,401 @@
+use crate::errors::ApiError;
+use crate::rpc_pool::{self, Caller, PooledClient};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct PsbtOutput {
+    address: String,
+    amount_sat: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct PsbtInput {
+    txid: String,
+    vout: u32,
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct CreatePsbtRequest {
+    wallet_name: String,
+    outputs: Vec<PsbtOutput>,
+    // Coins to spend; the wallet picks its own when empty
+    #[serde(default)]
+    inputs: Vec<PsbtInput>,
+    // Let the wallet add more coins to the given inputs if they are not enough
+    add_inputs: Option<bool>,
+    // sat/vB
+    fee_rate: Option<f64>,
+    change_address: Option<String>,
+    locktime: Option<u32>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SignPsbtRequest {
+    wallet_name: String,
+    psbt: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct FinalizePsbtRequest {
+    psbt: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct BroadcastRequest {
+    // Either a complete PSBT or the finalized transaction hex
+    psbt: Option<String>,
+    hex: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct CreatePsbtResponse {
+    pub(crate) psbt: String,
+    pub(crate) fee_sat: u64,
+    // None when the wallet did not need a change output
+    change_vout: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SignPsbtResponse {
+    pub(crate) psbt: String,
+    // Every input has all the signatures it needs
+    pub(crate) complete: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FinalizePsbtResponse {
+    // Still a PSBT while signatures are missing
+    psbt: Option<String>,
+    hex: Option<String>,
+    complete: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BroadcastResponse {
+    txid: String,
+}
+
+fn parse_address(data: &AppState, address: &str) -> Result<Address, ApiError> {
+    Address::from_str(address)
+        .map_err(|e| ApiError::InvalidAddress(format!("Invalid address '{address}': {e}")))?
+        .require_network(data.config.network)
+        .map_err(|e| ApiError::InvalidAddress(format!("Invalid network for '{address}': {e}")))
+}
+
+fn resolve_wallet(
+    data: &AppState,
+    caller: &Caller,
+    walletid: &str,
+) -> Result<PooledClient, ApiError> {
+    let wallet_name = caller.wallet_name(walletid);
+    data.wallet_client(&wallet_name).ok_or_else(|| {
+        warn!("PSBT request for unknown wallet '{}'", walletid);
+        ApiError::WalletNotFound(walletid.to_string())
+    })
+}
+
+fn create_psbt(
+    client: &Client,
+    req: &CreatePsbtRequest,
+    outputs: &[(Address, Amount)],
+    change_address: Option<&Address>,
+) -> Result<CreatePsbtResponse, RpcError> {
+    let inputs: Vec<Value> = req
+        .inputs
+        .iter()
+        .map(|input| json!({ "txid": input.txid, "vout": input.vout }))
+        .collect();
+    let outputs: Vec<Value> = outputs
+        .iter()
+        .map(|(address, amount)| json!({ address.to_string(): amount.to_btc() }))
+        .collect();
+    let mut options = Map::new();
+    if let Some(add_inputs) = req.add_inputs {
+        options.insert("add_inputs".into(), json!(add_inputs));
+    }
+    if let Some(fee_rate) = req.fee_rate {
+        options.insert("fee_rate".into(), json!(fee_rate));
+    }
+    if let Some(address) = change_address {
+        options.insert("changeAddress".into(), json!(address.to_string()));
+    }
+    let funded: Value = client.call(
+        "walletcreatefundedpsbt",
+        &[
+            json!(inputs),
+            json!(outputs),
+            json!(req.locktime.unwrap_or(0)),
+            Value::Object(options),
+            // Keep BIP32 derivation paths, so other signers can find their keys
+            json!(true),
+        ],
+    )?;
+    Ok(CreatePsbtResponse {
+        psbt: funded["psbt"].as_str().unwrap_or_default().to_string(),
+        fee_sat: funded["fee"]
+            .as_f64()
+            .and_then(|btc| Amount::from_btc(btc).ok())
+            .map(Amount::to_sat)
+            .unwrap_or_default(),
+        // changepos is -1 without change
+        change_vout: funded["changepos"]
+            .as_i64()
+            .and_then(|pos| u32::try_from(pos).ok()),
+    })
+}
+
+// Adds the wallet's signatures. walletprocesspsbt psbt sign sighashtype bip32derivs
+// finalize: finalizing is left to /psbt/finalize, so signers that come later still see
+// an unfinalized PSBT
+pub(crate) fn sign_psbt(client: &Client, psbt: &str) -> Result<SignPsbtResponse, RpcError> {
+    let processed: Value = client.call(
+        "walletprocesspsbt",
+        &[
+            json!(psbt.trim()),
+            json!(true),
+            json!("ALL"),
+            json!(true),
+            json!(false),
+        ],
+    )?;
+    Ok(SignPsbtResponse {
+        psbt: processed["psbt"].as_str().unwrap_or_default().to_string(),
+        complete: processed["complete"].as_bool().unwrap_or(false),
+    })
+}
+
+fn finalize(client: &Client, psbt: &str) -> Result<FinalizePsbtResponse, RpcError> {
+    let finalized: Value = client.call("finalizepsbt", &[json!(psbt)])?;
+    Ok(FinalizePsbtResponse {
+        psbt: finalized["psbt"].as_str().map(str::to_string),
+        hex: finalized["hex"].as_str().map(str::to_string),
+        complete: finalized["complete"].as_bool().unwrap_or(false),
+    })
+}
+
+fn build(
+    data: &AppState,
+    caller: &Caller,
+    req: &CreatePsbtRequest,
+) -> Result<CreatePsbtResponse, ApiError> {
+    if req.outputs.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "A PSBT needs at least one output".into(),
+        ));
+    }
+    let outputs = req
+        .outputs
+        .iter()
+        .map(|output| {
+            if output.amount_sat == 0 {
+                return Err(ApiError::InvalidRequest("Amount must be positive".into()));
+            }
+            Ok((
+                parse_address(data, &output.address)?,
+                Amount::from_sat(output.amount_sat),
+            ))
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    let change_address = match req.change_address.as_deref() {
+        Some(address) => Some(parse_address(data, address)?),
+        // A multisig wallet has no change keys, so change goes back to its address
+        None => data
+            .db
+            .multisig(&caller.wallet_name(&req.wallet_name))?
+            .map(|multisig| parse_address(data, &multisig.address))
+            .transpose()?,
+    };
+    let client = resolve_wallet(data, caller, &req.wallet_name)?;
+    match create_psbt(&client, req, &outputs, change_address.as_ref()) {
+        Ok(created) => {
+            info!(
+                "Created PSBT in wallet '{}' with fee {} sat",
+                req.wallet_name, created.fee_sat
+            );
+            Ok(created)
+        }
+        Err(e) => {
+            error!(
+                "walletcreatefundedpsbt failed for wallet '{}': {}",
+                req.wallet_name, e
+            );
+            Err(caller.rpc(e))
+        }
+    }
+}
+
+// A PSBT from `wallet` paying `amount_sat` to `address`, with the wallet's own coins
+pub(crate) fn create_payment(
+    data: &AppState,
+    caller: &Caller,
+    wallet: &str,
+    address: &str,
+    amount_sat: u64,
+) -> Result<CreatePsbtResponse, ApiError> {
+    let req = CreatePsbtRequest {
+        wallet_name: wallet.to_string(),
+        outputs: vec![PsbtOutput {
+            address: address.to_string(),
+            amount_sat,
+        }],
+        ..CreatePsbtRequest::default()
+    };
+    build(data, caller, &req)
+}
+
+#[utoipa::path(
+    post,
+    path = "/psbt/create",
+    tag = "psbt",
+    responses(
+        (status = 200, body = CreatePsbtResponse),
+    )
+)]
+pub(crate) async fn create(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<CreatePsbtRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /psbt/create - wallet='{}', outputs={}, inputs={}",
+        req.wallet_name,
+        req.outputs.len(),
+        req.inputs.len()
+    );
+    let req = req.into_inner();
+    let created = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        build(data, caller, &req)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(created))
+}
+
+// Adds the wallet's signatures; several wallets sign one after another by passing
+// the returned PSBT on
+#[utoipa::path(
+    post,
+    path = "/psbt/sign",
+    tag = "psbt",
+    responses(
+        (status = 200, body = SignPsbtResponse),
+    )
+)]
+pub(crate) async fn sign(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<SignPsbtRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /psbt/sign - wallet='{}'", req.wallet_name);
+    let req = req.into_inner();
+    let signed = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let client = resolve_wallet(data, caller, &req.wallet_name)?;
+        match sign_psbt(&client, &req.psbt) {
+            Ok(signed) => {
+                info!(
+                    "Wallet '{}' signed PSBT, complete={}",
+                    req.wallet_name, signed.complete
+                );
+                Ok(signed)
+            }
+            Err(e) => {
+                error!(
+                    "walletprocesspsbt failed for wallet '{}': {}",
+                    req.wallet_name, e
+                );
+                Err(caller.rpc(e))
+            }
+        }
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(signed))
+}
+
+#[utoipa::path(
+    post,
+    path = "/psbt/finalize",
+    tag = "psbt",
+    responses(
+        (status = 200, body = FinalizePsbtResponse),
+    )
+)]
+pub(crate) async fn finalize_psbt(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<FinalizePsbtRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /psbt/finalize");
+    let psbt = req.into_inner().psbt;
+    let finalized = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let node = caller.node_client(data)?;
+        finalize(&node, psbt.trim()).map_err(|e| {
+            error!("finalizepsbt failed: {}", e);
+            caller.rpc(e)
+        })
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(finalized))
+}
+
+#[utoipa::path(
+    post,
+    path = "/psbt/broadcast",
+    tag = "psbt",
+    responses(
+        (status = 200, body = BroadcastResponse),
+    )
+)]
+pub(crate) async fn broadcast(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<BroadcastRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /psbt/broadcast - psbt={}, hex={}",
+        req.psbt.is_some(),
+        req.hex.is_some()
+    );
+    let req = req.into_inner();
+    let txid = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let node = caller.node_client(data)?;
+        let hex = match (&req.psbt, &req.hex) {
+            (Some(_), Some(_)) | (None, None) => {
+                return Err(ApiError::InvalidRequest(
+                    "Give either psbt or hex, not both".into(),
+                ))
+            }
+            (None, Some(hex)) => hex.trim().to_string(),
+            (Some(psbt), None) => match finalize(&node, psbt.trim()) {
+                Ok(FinalizePsbtResponse {
+                    hex: Some(hex),
+                    complete: true,
+                    ..
+                }) => hex,
+                Ok(_) => {
+                    return Err(ApiError::InvalidRequest(
+                        "The PSBT is missing signatures, sign it with every wallet first".into(),
+                    ))
+                }
+                Err(e) => {
+                    error!("finalizepsbt failed: {}", e);
+                    return Err(caller.rpc(e));
+                }
+            },
+        };
+        node.call::<String>("sendrawtransaction", &[json!(hex)])
+            .map_err(|e| {
+                error!("sendrawtransaction failed: {}", e);
+                caller.rpc(e)
+            })
+    })
+    .await?;
+    info!("Broadcast PSBT transaction {}", txid);
+    Ok(HttpResponse::Ok().json(BroadcastResponse { txid }))
+}
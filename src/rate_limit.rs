@@ -0,0 +1,252 @@
+use crate::errors::ApiError;
+use crate::store::SharedStore;
+use crate::{routes, ws, AppState};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderName;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest};
+use log::{error, warn};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+pub(crate) const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+// Everything that mines, moves coins or runs a scenario
+const DEFAULT_PATHS: &str =
+    "/mine,/mine/auto,/send,/send/with-opreturn,/transfer,/faucet,/sandbox/faucet,/scenario/run";
+const BUCKET_PREFIX: &str = "ratelimit:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ClientKey {
+    Ip,
+    // The X-API-Key header if it is one of `rate_limit_api_keys`, otherwise the IP
+    ApiKey,
+}
+
+// Debug shows how many keys there are, not the keys
+#[derive(Clone, Default)]
+struct ApiKeys(Vec<String>);
+
+impl fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKeys({})", self.0.len())
+    }
+}
+
+impl ApiKeys {
+    fn position(&self, key: &str) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|known| ws::constant_time_eq(known.as_bytes(), key.as_bytes()))
+    }
+}
+
+// Rate limiting is opt-in via `rate_limit_per_minute`, the refill rate of each bucket.
+// It is part of the reloadable settings, see reload::Settings.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct RateLimitConfig {
+    per_minute: u32,
+    burst: u32,
+    // Unversioned paths, each with its own bucket per client
+    paths: Vec<String>,
+    key: ClientKey,
+    #[serde(skip)]
+    api_keys: ApiKeys,
+}
+
+impl RateLimitConfig {
+    pub(crate) fn load(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let Some(value) = var("rate_limit_per_minute") else {
+            return Ok(None);
+        };
+        let per_minute = positive(&value, "rate_limit_per_minute")?;
+        let burst = match var("rate_limit_burst") {
+            Some(value) => positive(&value, "rate_limit_burst")?,
+            None => per_minute,
+        };
+        let paths = parse_paths(&var("rate_limit_paths").unwrap_or_else(|| DEFAULT_PATHS.into()));
+        let key = match var("rate_limit_by").as_deref().map(str::trim) {
+            None | Some("ip") => ClientKey::Ip,
+            Some("api_key") => ClientKey::ApiKey,
+            Some(other) => {
+                return Err(format!(
+                    "unknown rate_limit_by '{other}', expected ip or api_key"
+                ))
+            }
+        };
+        let api_keys: Vec<String> = var("rate_limit_api_keys")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect();
+        if key == ClientKey::ApiKey && api_keys.is_empty() {
+            return Err("rate_limit_by=api_key needs rate_limit_api_keys".into());
+        }
+        Ok(Some(Self {
+            per_minute,
+            burst,
+            paths,
+            key,
+            api_keys: ApiKeys(api_keys),
+        }))
+    }
+
+    // The limited path a request goes to, spelled as the router matches it
+    fn limited_path<'a>(&self, http_req: &'a HttpRequest) -> Option<&'a str> {
+        let path = routes::route_path(http_req);
+        self.paths
+            .iter()
+            .any(|limited| limited == path)
+            .then_some(path)
+    }
+}
+
+fn parse_paths(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(|path| format!("/{}", path.trim_matches('/')))
+        .collect()
+}
+
+fn positive(value: &str, name: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{name} must be a positive number")),
+    }
+}
+
+// Token buckets per client and path, kept in the shared store so every replica draws
+// from the same bucket
+impl RateLimitConfig {
+    // Time for one token to come back
+    fn interval(&self) -> Duration {
+        Duration::from_secs(60) / self.per_minute
+    }
+
+    // Takes a token, or says how long until the next one is available
+    async fn take(&self, store: &dyn SharedStore, key: &str) -> Result<(), Duration> {
+        match store.take_token(key, self.burst, self.interval()).await {
+            Ok(taken) => taken,
+            // Better to let a request through than to fail every one of them
+            Err(e) => {
+                error!("Failed to check the rate limit: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    // Known API keys are told apart by their position in `rate_limit_api_keys`, which
+    // keeps the keys themselves out of the store
+    fn client(&self, req: &ServiceRequest) -> String {
+        let api_key = match self.key {
+            ClientKey::Ip => None,
+            ClientKey::ApiKey => req
+                .headers()
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|key| self.api_keys.position(key)),
+        };
+        match api_key {
+            Some(index) => format!("key:{index}"),
+            // Unix socket clients share one bucket
+            None => format!(
+                "ip:{}",
+                req.peer_addr()
+                    .map(|addr| addr.ip().to_string())
+                    .unwrap_or_else(|| "local".into())
+            ),
+        }
+    }
+}
+
+// Answers 429 with Retry-After once a client has used up its bucket for a limited path.
+// The limits are read on every request, so a reload applies to the next one.
+pub(crate) async fn rate_limit_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await;
+    };
+    let Some(limits) = data.settings().rate_limit else {
+        return next.call(req).await;
+    };
+    let Some(path) = limits.limited_path(req.request()) else {
+        return next.call(req).await;
+    };
+    let client = limits.client(&req);
+    let key = format!("{BUCKET_PREFIX}{client}:{path}");
+    if let Err(retry_after) = limits.take(data.store.as_ref(), &key).await {
+        warn!(
+            "{} {} - rate limit exceeded for {}",
+            req.method(),
+            req.path(),
+            client
+        );
+        return Err(ApiError::RateLimited { retry_after }.into());
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Option<RateLimitConfig>, String> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        RateLimitConfig::load(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn limits_load_from_the_settings() {
+        assert!(load(&[]).unwrap().is_none());
+        let limits = load(&[("rate_limit_per_minute", "30")]).unwrap().unwrap();
+        assert_eq!((limits.per_minute, limits.burst), (30, 30));
+        assert_eq!(limits.paths, parse_paths(DEFAULT_PATHS));
+        let limits = load(&[
+            ("rate_limit_per_minute", "30"),
+            ("rate_limit_burst", "5"),
+            ("rate_limit_paths", "mine/, /send"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(limits.burst, 5);
+        assert_eq!(limits.paths, ["/mine", "/send"]);
+        assert!(load(&[("rate_limit_per_minute", "0")]).is_err());
+        assert!(load(&[("rate_limit_per_minute", "1"), ("rate_limit_by", "api_key")]).is_err());
+    }
+
+    #[test]
+    fn limited_paths_match_however_the_path_is_spelled() {
+        let config = RateLimitConfig {
+            per_minute: 1,
+            burst: 1,
+            paths: parse_paths(DEFAULT_PATHS),
+            key: ClientKey::Ip,
+            api_keys: ApiKeys::default(),
+        };
+        for (uri, limited) in [
+            ("/mine", Some("/mine")),
+            ("/%6Dine", Some("/mine")),
+            ("/api/v1/%73end", Some("/send")),
+            (
+                "/api/v1/send/with-%6Fpreturn?fee=1",
+                Some("/send/with-opreturn"),
+            ),
+            ("/sandbox%2Ffaucet", None),
+            ("/wallets", None),
+        ] {
+            let http_req = TestRequest::post().uri(uri).to_http_request();
+            assert_eq!(config.limited_path(&http_req), limited, "{uri}");
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::channel::node_client;
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Amount, Txid};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// RPC_DESERIALIZATION_ERROR: the hex is not a transaction
+const RPC_DESERIALIZATION_ERROR: i32 = -22;
+// Parent transactions looked up per decode; each costs up to two RPC calls, and a
+// posted transaction may have thousands of inputs
+const MAX_RESOLVED_PARENTS: usize = 100;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct DecodeRequest {
+    hex: String,
+    // Lets inputs spending this wallet's coins be resolved without -txindex
+    wallet_name: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DecodedOutput {
+    n: Option<u32>,
+    value_sat: u64,
+    // pubkeyhash, witness_v0_keyhash, witness_v1_taproot, nulldata, ...
+    script_type: String,
+    // None for scripts without an address, e.g. OP_RETURN
+    address: Option<String>,
+    script_hex: String,
+    asm: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DecodedInput {
+    // None for the coinbase input
+    txid: Option<String>,
+    vout: Option<u32>,
+    coinbase: bool,
+    sequence: u64,
+    witness: Vec<String>,
+    // The coin being spent; None when the node cannot look it up
+    prevout: Option<DecodedOutput>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DecodedTransaction {
+    txid: String,
+    wtxid: String,
+    version: i64,
+    locktime: u64,
+    size: u64,
+    vsize: u64,
+    weight: u64,
+    inputs: Vec<DecodedInput>,
+    outputs: Vec<DecodedOutput>,
+    // Only known when every spent coin could be looked up
+    fee_sat: Option<u64>,
+    block_hash: Option<String>,
+    confirmations: Option<u64>,
+}
+
+fn sat(btc: &Value) -> u64 {
+    btc.as_f64()
+        .and_then(|btc| Amount::from_btc(btc).ok())
+        .map(Amount::to_sat)
+        .unwrap_or_default()
+}
+
+fn output(value: &Value, n: Option<u32>) -> DecodedOutput {
+    let script = &value["scriptPubKey"];
+    DecodedOutput {
+        n,
+        value_sat: sat(&value["value"]),
+        script_type: script["type"].as_str().unwrap_or("unknown").to_string(),
+        address: script["address"].as_str().map(str::to_string),
+        script_hex: script["hex"].as_str().unwrap_or_default().to_string(),
+        asm: script["asm"].as_str().unwrap_or_default().to_string(),
+    }
+}
+
+// Reads the JSON shared by decoderawtransaction and verbose getrawtransaction
+fn decode(tx: &Value) -> DecodedTransaction {
+    let inputs: Vec<DecodedInput> = tx["vin"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|vin| DecodedInput {
+            txid: vin["txid"].as_str().map(str::to_string),
+            vout: vin["vout"].as_u64().map(|vout| vout as u32),
+            coinbase: vin.get("coinbase").is_some(),
+            sequence: vin["sequence"].as_u64().unwrap_or_default(),
+            witness: vin["txinwitness"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|item| item.as_str().map(str::to_string))
+                .collect(),
+            // Only present with getrawtransaction verbosity 2
+            prevout: vin.get("prevout").map(|prevout| output(prevout, None)),
+        })
+        .collect();
+    let outputs: Vec<DecodedOutput> = tx["vout"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|vout| output(vout, vout["n"].as_u64().map(|n| n as u32)))
+        .collect();
+    DecodedTransaction {
+        txid: tx["txid"].as_str().unwrap_or_default().to_string(),
+        wtxid: tx["hash"].as_str().unwrap_or_default().to_string(),
+        version: tx["version"].as_i64().unwrap_or_default(),
+        locktime: tx["locktime"].as_u64().unwrap_or_default(),
+        size: tx["size"].as_u64().unwrap_or_default(),
+        vsize: tx["vsize"].as_u64().unwrap_or_default(),
+        weight: tx["weight"].as_u64().unwrap_or_default(),
+        fee_sat: None,
+        block_hash: tx["blockhash"].as_str().map(str::to_string),
+        confirmations: tx["confirmations"].as_u64(),
+        inputs,
+        outputs,
+    }
+}
+
+fn with_fee(mut tx: DecodedTransaction) -> DecodedTransaction {
+    let spent: Option<u64> = tx
+        .inputs
+        .iter()
+        .map(|input| input.prevout.as_ref().map(|prevout| prevout.value_sat))
+        .sum();
+    let created: u64 = tx.outputs.iter().map(|output| output.value_sat).sum();
+    let is_coinbase = tx.inputs.iter().any(|input| input.coinbase);
+    tx.fee_sat = spent
+        .filter(|_| !is_coinbase)
+        .and_then(|spent| spent.checked_sub(created));
+    tx
+}
+
+// Verbose getrawtransaction; the block hash from the wallet lets it work without -txindex
+fn raw_transaction(node: &Client, wallet: Option<&Client>, txid: &Txid) -> Result<Value, RpcError> {
+    let block_hash = wallet
+        .and_then(|wallet| wallet.get_transaction(txid, None).ok())
+        .and_then(|tx| tx.info.blockhash);
+    match block_hash {
+        Some(block_hash) => node.call(
+            "getrawtransaction",
+            &[json!(txid), json!(2), json!(block_hash)],
+        ),
+        None => node.call("getrawtransaction", &[json!(txid), json!(2)]),
+    }
+}
+
+// decoderawtransaction does not show the coins being spent, so they are looked up one
+// parent at a time. Inputs past the first MAX_RESOLVED_PARENTS parents keep no prevout.
+fn resolve_prevouts(node: &Client, wallet: Option<&Client>, tx: &mut DecodedTransaction) {
+    let mut parents: HashMap<Txid, Option<Value>> = HashMap::new();
+    for input in tx.inputs.iter_mut().filter(|input| input.prevout.is_none()) {
+        let (Some(txid), Some(vout)) = (
+            input
+                .txid
+                .as_deref()
+                .and_then(|txid| Txid::from_str(txid).ok()),
+            input.vout,
+        ) else {
+            continue;
+        };
+        if !parents.contains_key(&txid) {
+            if parents.len() == MAX_RESOLVED_PARENTS {
+                warn!(
+                    "Not looking up more than {} parent transactions",
+                    MAX_RESOLVED_PARENTS
+                );
+                break;
+            }
+            let parent = raw_transaction(node, wallet, &txid)
+                .inspect_err(|e| warn!("Cannot look up input {}:{}: {}", txid, vout, e))
+                .ok();
+            parents.insert(txid, parent);
+        }
+        input.prevout = parents[&txid]
+            .as_ref()
+            .and_then(|parent| parent["vout"].as_array())
+            .and_then(|outputs| outputs.get(vout as usize))
+            .map(|prevout| output(prevout, None));
+    }
+}
+
+// Wallet transactions that left the mempool without confirming are only known to the wallet
+fn wallet_transaction(node: &Client, wallet: &Client, txid: &Txid) -> Result<Value, RpcError> {
+    let tx = wallet.get_transaction(txid, None)?;
+    node.call("decoderawtransaction", &[json!(hex::encode(&tx.hex))])
+}
+
+#[utoipa::path(
+    get,
+    path = "/tx/{walletid}/{txid}/raw",
+    tag = "raw-tx",
+    responses(
+        (status = 200, body = DecodedTransaction),
+    )
+)]
+pub(crate) async fn get_raw_transaction(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, txid) = path.into_inner();
+    info!("GET /tx/{}/{}/raw", walletid, txid);
+    let txid = match Txid::from_str(&txid) {
+        Ok(txid) => txid,
+        Err(e) => {
+            warn!("Invalid txid format '{}': {}", txid, e);
+            return Err(ApiError::InvalidTxid(format!(
+                "Invalid transaction ID: {}",
+                e
+            )));
+        }
+    };
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /tx/{}/{}/raw - wallet not found", walletid, txid);
+        return Err(ApiError::WalletNotFound(walletid));
+    };
+    let node = node_client(&data, &http_req)?;
+    let decoded = rpc_pool::blocking(move || {
+        raw_transaction(&node, Some(&client), &txid)
+            .or_else(|_| wallet_transaction(&node, &client, &txid))
+            .map(|raw| {
+                let mut tx = decode(&raw);
+                resolve_prevouts(&node, Some(&client), &mut tx);
+                tx
+            })
+    })
+    .await?;
+    match decoded {
+        Ok(tx) => Ok(HttpResponse::Ok().json(with_fee(tx))),
+        Err(e) => {
+            error!(
+                "Failed to get transaction '{}' for wallet '{}': {}",
+                txid, wallet_name, e
+            );
+            Err(ApiError::rpc_not_found(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/decode",
+    tag = "raw-tx",
+    responses(
+        (status = 200, body = DecodedTransaction),
+    )
+)]
+pub(crate) async fn decode_transaction(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<DecodeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /decode - {} hex chars, wallet={:?}",
+        req.hex.len(),
+        req.wallet_name
+    );
+    let wallet = match &req.wallet_name {
+        Some(walletid) => {
+            let wallet_name = data.wallet_name(&http_req, walletid);
+            match data.wallet_client(&wallet_name) {
+                Some(client) => Some(client),
+                None => {
+                    warn!("POST /decode - wallet '{}' not found", walletid);
+                    return Err(ApiError::WalletNotFound(walletid.clone()));
+                }
+            }
+        }
+        None => None,
+    };
+    let node = node_client(&data, &http_req)?;
+    let hex = req.hex.trim().to_string();
+    let decoded = rpc_pool::blocking(move || {
+        node.call::<Value>("decoderawtransaction", &[json!(hex)])
+            .map(|raw| {
+                let mut tx = decode(&raw);
+                resolve_prevouts(&node, wallet.as_deref(), &mut tx);
+                tx
+            })
+    })
+    .await?;
+    match decoded {
+        Ok(tx) => Ok(HttpResponse::Ok().json(with_fee(tx))),
+        Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == RPC_DESERIALIZATION_ERROR => Err(
+            ApiError::InvalidRequest(format!("Not a raw transaction: {}", e.message)),
+        ),
+        Err(e) => {
+            error!("Failed to decode transaction: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use crate::db::unix_now;
+use crate::errors::ApiError;
+use crate::{routes, AppState};
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse};
+use dashmap::DashMap;
+use dotenv as env;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+// Bodies above this size are left out of the fixture
+const MAX_RECORDED_BODY: usize = 256 * 1024;
+const REDACTED: &str = "[redacted]";
+// Request and response fields whose values never end up in a fixture
+const SENSITIVE_FIELDS: [&str; 10] = [
+    "password",
+    "passphrase",
+    "private_key",
+    "privkey",
+    "wif",
+    "seed",
+    "mnemonic",
+    "xprv",
+    "tprv",
+    "token",
+];
+// Not recorded: the recording controls themselves, replays, the event socket and
+// the API docs
+const SKIPPED_PREFIXES: [&str; 5] = ["/recordings", "/replay", "/ws", "/api-doc/", "/swagger-ui/"];
+// Event streams never end, so their body cannot be captured
+const SKIPPED_SUFFIXES: [&str; 1] = ["/events"];
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+struct Exchange {
+    method: String,
+    path: String,
+    query: String,
+    request_body: Option<Value>,
+    status: u16,
+    content_type: Option<String>,
+    response_body: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct Fixture {
+    name: String,
+    recorded_at: i64,
+    exchanges: Vec<Exchange>,
+}
+
+struct ActiveRecording {
+    name: String,
+    // Sandbox session prefix of whoever started it, so other visitors are not captured
+    scope: String,
+    started_at: i64,
+    exchanges: Vec<Exchange>,
+}
+
+pub(crate) struct Recorder {
+    // `recording_dir`; recording and replay are off without it
+    dir: Option<PathBuf>,
+    active: Mutex<Option<ActiveRecording>>,
+    // How far each replayed request has got through its recorded responses
+    cursors: DashMap<String, usize>,
+}
+
+impl Recorder {
+    pub(crate) fn from_env() -> Self {
+        Recorder {
+            dir: env::var("recording_dir").ok().map(PathBuf::from),
+            active: Mutex::new(None),
+            cursors: DashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    fn dir(&self) -> Result<&PathBuf, ApiError> {
+        self.dir.as_ref().ok_or_else(|| {
+            ApiError::Conflict("Recording is disabled, set recording_dir to enable it".into())
+        })
+    }
+
+    fn active(&self) -> std::sync::MutexGuard<'_, Option<ActiveRecording>> {
+        match self.active.lock() {
+            Ok(active) => active,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn fixture_path(&self, name: &str) -> Result<PathBuf, ApiError> {
+        if name.is_empty()
+            || name.len() > 64
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ApiError::InvalidRequest(
+                "Recording names may only use letters, digits, '-' and '_' (up to 64)".into(),
+            ));
+        }
+        Ok(self.dir()?.join(format!("{name}.json")))
+    }
+
+    fn load(&self, name: &str) -> Result<Fixture, ApiError> {
+        let path = self.fixture_path(name)?;
+        let contents = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                ApiError::NotFound(format!("No recording named '{name}'"))
+            }
+            _ => ApiError::Storage(e.to_string()),
+        })?;
+        serde_json::from_str(&contents).map_err(|e| ApiError::Storage(e.to_string()))
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if is_sensitive(key) {
+                    *field = Value::String(REDACTED.into());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        // Extended private keys can turn up inside descriptors
+        Value::String(text) if text.contains("xprv") || text.contains("tprv") => {
+            *text = REDACTED.into();
+        }
+        _ => {}
+    }
+}
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_FIELDS
+        .iter()
+        .any(|sensitive| key.contains(sensitive))
+}
+
+// The query without sensitive parameters such as `access_token`, so tokens stay out
+// of fixtures and replays match without one
+fn recorded_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| !is_sensitive(pair.split('=').next().unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Bodies are kept as JSON when they parse, otherwise as text
+fn recorded_body(bytes: &[u8]) -> Option<Value> {
+    if bytes.is_empty() || bytes.len() > MAX_RECORDED_BODY {
+        return None;
+    }
+    let mut value = serde_json::from_slice(bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()));
+    redact(&mut value);
+    Some(value)
+}
+
+fn is_recording(data: &AppState, http_req: &HttpRequest) -> bool {
+    let path = routes::unversioned(http_req.path());
+    if SKIPPED_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+        || SKIPPED_SUFFIXES.iter().any(|suffix| path.ends_with(suffix))
+    {
+        return false;
+    }
+    let scope = data.wallet_name(http_req, "");
+    data.recorder
+        .active()
+        .as_ref()
+        .is_some_and(|active| active.scope == scope)
+}
+
+// Captures request/response pairs while a recording is running
+pub(crate) async fn record_middleware(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    if !is_recording(&data, req.request()) {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    let request_body = req.extract::<web::Bytes>().await?;
+    req.set_payload(Payload::from(request_body.clone()));
+    let method = req.method().to_string();
+    let path = routes::unversioned(req.path()).to_string();
+    let query = recorded_query(req.query_string());
+
+    let res = next.call(req).await?;
+    let status = res.status().as_u16();
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (http_req, response) = res.into_parts();
+    let (response, response_body) = response.into_parts();
+    let response_body = body::to_bytes(response_body)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("failed to read response body"))?;
+
+    if let Some(active) = data.recorder.active().as_mut() {
+        active.exchanges.push(Exchange {
+            method,
+            path,
+            query,
+            request_body: recorded_body(&request_body),
+            status,
+            content_type,
+            response_body: recorded_body(&response_body),
+        });
+    }
+    let response = response.set_body(BoxBody::new(response_body));
+    Ok(ServiceResponse::new(http_req, response))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct StartRequest {
+    name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RecordingSummary {
+    name: String,
+    exchanges: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/recordings",
+    tag = "recording",
+    responses(
+        (status = 200, body = RecordingSummary),
+    )
+)]
+pub(crate) async fn start_recording(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<StartRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /recordings - name='{}'", req.name);
+    data.recorder.fixture_path(&req.name)?;
+    let mut active = data.recorder.active();
+    if let Some(running) = active.as_ref() {
+        return Err(ApiError::Conflict(format!(
+            "Recording '{}' is still running, stop it first",
+            running.name
+        )));
+    }
+    *active = Some(ActiveRecording {
+        name: req.name.clone(),
+        scope: data.wallet_name(&http_req, ""),
+        started_at: unix_now(),
+        exchanges: Vec::new(),
+    });
+    info!("Started recording '{}'", req.name);
+    Ok(HttpResponse::Ok().json(RecordingSummary {
+        name: req.name.clone(),
+        exchanges: 0,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recordings/stop",
+    tag = "recording",
+    responses(
+        (status = 200, body = RecordingSummary),
+    )
+)]
+pub(crate) async fn stop_recording(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /recordings/stop");
+    data.recorder.dir()?;
+    let scope = data.wallet_name(&http_req, "");
+    let recording = {
+        let mut active = data.recorder.active();
+        match active.as_ref() {
+            Some(running) if running.scope == scope => active.take(),
+            Some(_) => {
+                return Err(ApiError::Conflict(
+                    "The running recording was started by another session".into(),
+                ))
+            }
+            None => None,
+        }
+    };
+    let Some(recording) = recording else {
+        return Err(ApiError::Conflict("No recording is running".into()));
+    };
+
+    let fixture = Fixture {
+        name: recording.name,
+        recorded_at: recording.started_at,
+        exchanges: recording.exchanges,
+    };
+    let path = data.recorder.fixture_path(&fixture.name)?;
+    let contents =
+        serde_json::to_string_pretty(&fixture).map_err(|e| ApiError::Internal(e.to_string()))?;
+    if let Err(e) =
+        std::fs::create_dir_all(data.recorder.dir()?).and_then(|()| std::fs::write(&path, contents))
+    {
+        error!("Failed to write recording to {}: {}", path.display(), e);
+        return Err(ApiError::Storage(e.to_string()));
+    }
+    info!(
+        "Saved recording '{}' with {} exchange(s) to {}",
+        fixture.name,
+        fixture.exchanges.len(),
+        path.display()
+    );
+    Ok(HttpResponse::Ok().json(RecordingSummary {
+        name: fixture.name,
+        exchanges: fixture.exchanges.len(),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/recordings",
+    tag = "recording",
+    responses(
+        (status = 200, body = [String]),
+    )
+)]
+pub(crate) async fn list_recordings(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("GET /recordings");
+    let dir = data.recorder.dir()?;
+    let mut names: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()?
+                    .strip_suffix(".json")
+                    .map(str::to_string)
+            })
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(ApiError::Storage(e.to_string())),
+    };
+    names.sort();
+    Ok(HttpResponse::Ok().json(names))
+}
+
+#[utoipa::path(
+    get,
+    path = "/recordings/{name}",
+    tag = "recording",
+    responses(
+        (status = 200, body = Fixture),
+    )
+)]
+pub(crate) async fn get_recording(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /recordings/{}", name);
+    Ok(HttpResponse::Ok().json(data.recorder.load(&name)?))
+}
+
+// Serves a recording back: each request gets the recorded responses to the same
+// method, path and query in order, and the last one again once they run out
+pub(crate) async fn replay(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (name, tail) = path.into_inner();
+    let method = http_req.method().to_string();
+    let path = format!("/{tail}");
+    let query = recorded_query(http_req.query_string());
+    info!("{} /replay/{}{}", method, name, path);
+
+    let fixture = data.recorder.load(&name)?;
+    let matching: Vec<&Exchange> = fixture
+        .exchanges
+        .iter()
+        .filter(|exchange| {
+            exchange.method == method && exchange.path == path && exchange.query == query
+        })
+        .collect();
+    let Some(last) = matching.last() else {
+        warn!("Recording '{}' has no {} {}", name, method, path);
+        return Err(ApiError::NotFound(format!(
+            "Recording '{name}' has no response for {method} {path}"
+        )));
+    };
+    let position = {
+        let mut cursor = data
+            .recorder
+            .cursors
+            .entry(format!("{name} {method} {path}?{query}"))
+            .or_insert(0);
+        let position = *cursor;
+        *cursor += 1;
+        position
+    };
+    let exchange = matching.get(position).unwrap_or(last);
+    let status = StatusCode::from_u16(exchange.status).unwrap_or(StatusCode::OK);
+    let mut response = HttpResponse::build(status);
+    let is_json = exchange
+        .content_type
+        .as_deref()
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    Ok(match (&exchange.response_body, &exchange.content_type) {
+        (Some(body), _) if is_json => response.json(body),
+        // Text bodies were stored as a JSON string
+        (Some(Value::String(text)), Some(content_type)) => response
+            .content_type(content_type.as_str())
+            .body(text.clone()),
+        (Some(body), _) => response.json(body),
+        (None, _) => response.finish(),
+    })
+}
+
+// Starts the replay of a recording from its first response again
+#[utoipa::path(
+    post,
+    path = "/recordings/{name}/rewind",
+    tag = "recording",
+    responses(
+        (status = 204, description = "The chain was rewound to the start of the recording"),
+    )
+)]
+pub(crate) async fn rewind(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /recordings/{}/rewind", name);
+    data.recorder.load(&name)?;
+    let prefix = format!("{name} ");
+    data.recorder
+        .cursors
+        .retain(|key, _| !key.starts_with(&prefix));
+    Ok(HttpResponse::NoContent().finish())
+}
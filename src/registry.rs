@@ -0,0 +1,130 @@
+use crate::events::Event;
+use crate::mining;
+use crate::AppState;
+use actix_web::web;
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use std::time::Duration;
+
+// bitcoind may still be starting, e.g. under docker-compose
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: usize = 60;
+
+enum Restored {
+    Registered,
+    // bitcoind no longer has the wallet, e.g. after the regtest chain was wiped
+    Gone,
+}
+
+fn restore_wallet(data: &AppState, loaded: &[String], name: &str) -> Result<Restored, RpcError> {
+    let client = data.config.create_client(name)?;
+    if !loaded.iter().any(|wallet| wallet == name) {
+        match client.load_wallet(name) {
+            Ok(_) => info!("Loaded registered wallet '{}'", name),
+            Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == -18 => {
+                return Ok(Restored::Gone)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let pool = data.config.create_pool(name, client);
+    data.clients.insert(name.to_string(), pool);
+    data.events.publish(Event::WalletReady {
+        wallet: name.to_string(),
+    });
+    Ok(Restored::Registered)
+}
+
+fn restore_all(data: &AppState, node: &Client, wallets: &[String]) -> Result<(), RpcError> {
+    let loaded = node.list_wallets()?;
+    for name in wallets {
+        match restore_wallet(data, &loaded, name) {
+            Ok(Restored::Registered) => {}
+            Ok(Restored::Gone) => {
+                warn!(
+                    "Registered wallet '{}' no longer exists, forgetting it",
+                    name
+                );
+                if let Err(e) = data.db.unregister_wallet(name) {
+                    error!(
+                        "Failed to remove wallet '{}' from the registry: {}",
+                        name, e
+                    );
+                }
+                mining::forget_miner(data, name);
+            }
+            Err(e) => warn!("Failed to restore registered wallet '{}': {}", name, e),
+        }
+    }
+    Ok(())
+}
+
+// Registers the wallets from previous runs again, so the API works without re-POSTing /wallet
+pub(crate) async fn restore_wallets(data: web::Data<AppState>) {
+    // Sandbox wallets belong to sessions, which do not survive a restart
+    if data.sandbox.is_some() {
+        return;
+    }
+    let wallets = match data.db.registered_wallets() {
+        Ok(wallets) if wallets.is_empty() => return,
+        Ok(wallets) => wallets,
+        Err(e) => {
+            error!("Failed to read the wallet registry: {}", e);
+            return;
+        }
+    };
+    for attempt in 1..=MAX_ATTEMPTS {
+        match data
+            .config
+            .create_node_client()
+            .and_then(|node| restore_all(&data, &node, &wallets))
+        {
+            Ok(()) => {
+                info!("Restored {} registered wallet(s)", data.clients.len());
+                return;
+            }
+            Err(e) => warn!(
+                "Cannot restore registered wallets yet (attempt {}/{}): {}",
+                attempt, MAX_ATTEMPTS, e
+            ),
+        }
+        actix_rt::time::sleep(RETRY_INTERVAL).await;
+    }
+    error!("Gave up restoring registered wallets, register them again with POST /wallet");
+}
+
+// Unloads the wallets the API has clients for once the server has stopped, so the
+// next start does not find them still loaded. Registered wallets are loaded again then.
+pub(crate) fn unload_wallets(data: &AppState) {
+    let wallets: Vec<String> = data
+        .clients
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect();
+    data.clients.clear();
+    if wallets.is_empty() {
+        return;
+    }
+    let node = match data.config.create_node_client() {
+        Ok(node) => node,
+        Err(e) => {
+            error!("Cannot unload wallets on shutdown: {}", e);
+            return;
+        }
+    };
+    let mut unloaded = 0;
+    for wallet in &wallets {
+        match node.unload_wallet(Some(wallet)) {
+            Ok(_) => unloaded += 1,
+            // Already unloaded, e.g. by bitcoin-cli
+            Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == -18 => {}
+            Err(e) => warn!("Failed to unload wallet '{}' on shutdown: {}", wallet, e),
+        }
+    }
+    info!(
+        "Unloaded {}/{} wallet(s) on shutdown",
+        unloaded,
+        wallets.len()
+    );
+}
@@ -0,0 +1,152 @@
+use crate::rate_limit::RateLimitConfig;
+use crate::{logging, AppState};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::web;
+use bitcoincore_rpc::bitcoin::Amount;
+use dotenv as env;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+const DEFAULT_LOG_FILTER: &str = "info,actix_web=info";
+
+// Settings that can change while the server runs, without dropping connections
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct Settings {
+    pub(crate) cors_origins: Vec<String>,
+    // Any origin is allowed, for local frontend development only
+    pub(crate) cors_allow_any: bool,
+    pub(crate) log_filter: String,
+    pub(crate) sandbox_faucet_allowance_sat: u64,
+    pub(crate) rate_limit: Option<RateLimitConfig>,
+}
+
+impl Settings {
+    pub(crate) fn from_env() -> Result<Self, String> {
+        Self::load(|name| env::var(name).ok())
+    }
+
+    fn load(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        // The frontend served from server_url is always allowed
+        let mut cors_origins: Vec<String> = var("server_url").into_iter().collect();
+        for origin in var("cors_origins").unwrap_or_default().split(',') {
+            let origin = origin.trim();
+            if !origin.is_empty() && !cors_origins.iter().any(|known| known == origin) {
+                cors_origins.push(origin.to_string());
+            }
+        }
+
+        let cors_allow_any = match var("cors_allow_any").as_deref().map(str::trim) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(_) => return Err("cors_allow_any must be true or false".to_string()),
+        };
+
+        let log_filter = var("log_level")
+            .or_else(|| var("RUST_LOG"))
+            .unwrap_or_else(|| DEFAULT_LOG_FILTER.to_string());
+        logging::parse_filter(&log_filter)?;
+
+        let sandbox_faucet_allowance_sat = match var("sandbox_faucet_allowance_sat") {
+            Some(value) => value
+                .parse()
+                .map_err(|_| "sandbox_faucet_allowance_sat must be a number of sats".to_string())?,
+            None => Amount::ONE_BTC.to_sat(),
+        };
+
+        let rate_limit = RateLimitConfig::load(&var)?;
+
+        Ok(Self {
+            cors_origins,
+            cors_allow_any,
+            log_filter,
+            sandbox_faucet_allowance_sat,
+            rate_limit,
+        })
+    }
+
+    // dotenv only reads .env once, so a reload parses the file again and lets its
+    // values take precedence over what the process started with. The iterator API is
+    // deprecated in favour of loading into the process environment, which is not
+    // safe to modify once worker threads are running.
+    #[allow(deprecated)]
+    fn reload_from_env() -> Result<Self, String> {
+        let file: HashMap<String, String> = match env::dotenv_iter() {
+            Ok(iter) => iter.filter_map(Result::ok).collect(),
+            Err(_) => HashMap::new(),
+        };
+        Self::load(|name| file.get(name).cloned().or_else(|| std::env::var(name).ok()))
+    }
+}
+
+pub(crate) fn warn_if_any_origin(settings: &Settings) {
+    if settings.cors_allow_any {
+        warn!("cors_allow_any is on, any website can call the API; use it for development only");
+    }
+}
+
+// With cors_allow_any, origins that are not listed are let in without credentials, so
+// a random website cannot act with a visitor's session cookie. Cors only has a fixed
+// setting for credentials, and cors_allow_any changes on a reload.
+pub(crate) async fn listed_origin_credentials(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let listed = match (
+        req.app_data::<web::Data<AppState>>(),
+        req.headers().get(header::ORIGIN),
+    ) {
+        (Some(data), Some(origin)) => data
+            .settings()
+            .cors_origins
+            .iter()
+            .any(|allowed| allowed.as_bytes() == origin.as_bytes()),
+        _ => true,
+    };
+    let mut res = next.call(req).await?;
+    if !listed {
+        res.headers_mut()
+            .remove(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+    }
+    Ok(res)
+}
+
+// Re-reads the reloadable settings and applies them to the running server
+pub(crate) fn reload(data: &AppState) -> Result<Settings, String> {
+    let settings = Settings::reload_from_env()?;
+    logging::set_filter(&settings.log_filter)?;
+    let mut current = data
+        .settings
+        .write()
+        .map_err(|_| "settings lock poisoned".to_string())?;
+    *current = settings.clone();
+    info!("Configuration reloaded: {:?}", settings);
+    warn_if_any_origin(&settings);
+    Ok(settings)
+}
+
+#[cfg(unix)]
+pub(crate) async fn reload_on_sighup(data: web::Data<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!(
+                "Cannot listen for SIGHUP, config reload only via HTTP: {}",
+                e
+            );
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        if let Err(e) = reload(&data) {
+            error!("Configuration reload failed: {}", e);
+        }
+    }
+}
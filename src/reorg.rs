@@ -0,0 +1,501 @@
+use crate::channel::node_client;
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::{mining, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, BlockHash};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+const TIP_POLL_INTERVAL: Duration = Duration::from_secs(10);
+// Recent history is enough to catch the short reorgs the demo produces
+const RECENT_TRANSACTIONS: usize = 1000;
+// Deep enough to bury a transaction past the usual confirmation targets
+const MAX_REORG_DEPTH: u64 = 100;
+// RPC_INVALID_ADDRESS_OR_KEY: unknown block hash
+const RPC_BLOCK_NOT_FOUND: i32 = -5;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum OrphanStatus {
+    // Back in the mempool, waiting to be mined again
+    InMempool,
+    // Unconfirmed and not in the mempool, e.g. evicted or never re-accepted
+    Unconfirmed,
+    // A conflicting transaction confirmed instead, so this one never can
+    Conflicted,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OrphanedTransaction {
+    txid: String,
+    previous_block_hash: String,
+    previous_block_height: u64,
+    status: OrphanStatus,
+    in_mempool: bool,
+    conflicts: Vec<String>,
+    recommended_action: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReorgReport {
+    wallet: String,
+    tip_height: u64,
+    orphaned: Vec<OrphanedTransaction>,
+}
+
+// Remembers the block of every confirmed wallet transaction
+fn snapshot_confirmations(
+    data: &AppState,
+    wallet: &str,
+    client: &Client,
+) -> Result<usize, RpcError> {
+    let transactions: Vec<Value> = client.call(
+        "listtransactions",
+        &[
+            json!("*"),
+            json!(RECENT_TRANSACTIONS),
+            json!(0),
+            json!(true),
+        ],
+    )?;
+    let mut recorded = 0;
+    for tx in transactions {
+        let (Some(txid), Some(block_hash), Some(height)) = (
+            tx["txid"].as_str(),
+            tx["blockhash"].as_str(),
+            tx["blockheight"].as_u64(),
+        ) else {
+            continue;
+        };
+        if tx["confirmations"].as_i64().unwrap_or_default() <= 0 {
+            continue;
+        }
+        match data
+            .db
+            .record_confirmation(wallet, txid, block_hash, height)
+        {
+            Ok(()) => recorded += 1,
+            Err(e) => error!("Failed to record confirmation of {}: {}", txid, e),
+        }
+    }
+    Ok(recorded)
+}
+
+// Polls the chain tip and snapshots loaded wallets whenever it moves, so the state
+// before a reorg is known when the report is requested
+pub(crate) async fn track_confirmations(data: web::Data<AppState>) {
+    let mut last_tip: Option<BlockHash> = None;
+    loop {
+        actix_rt::time::sleep(TIP_POLL_INTERVAL).await;
+
+        let tip = match rpc_pool::call_node::<BlockHash>(&data, "getbestblockhash", vec![]).await {
+            Ok(Ok(tip)) => tip,
+            Ok(Err(e)) => {
+                debug!("Cannot read chain tip for confirmation tracking: {}", e);
+                continue;
+            }
+            Err(e) => {
+                debug!("Cannot read chain tip for confirmation tracking: {}", e);
+                continue;
+            }
+        };
+        if last_tip == Some(tip) {
+            continue;
+        }
+        last_tip = Some(tip);
+
+        let snapshots = rpc_pool::with_state(&data, |data| {
+            let wallets: Vec<String> = data
+                .clients
+                .iter()
+                .map(|entry| entry.key().clone())
+                .collect();
+            for wallet in wallets {
+                let Some(client) = data.clients.get(&wallet).map(|pool| pool.get()) else {
+                    continue;
+                };
+                match snapshot_confirmations(data, &wallet, &client) {
+                    Ok(recorded) => debug!(
+                        "Recorded {} confirmation(s) for wallet '{}'",
+                        recorded, wallet
+                    ),
+                    Err(e) => warn!(
+                        "Failed to snapshot confirmations for wallet '{}': {}",
+                        wallet, e
+                    ),
+                }
+            }
+        });
+        if let Err(e) = snapshots.await {
+            warn!("Failed to snapshot confirmations: {}", e);
+        }
+    }
+}
+
+fn recommended_action(status: OrphanStatus) -> &'static str {
+    match status {
+        OrphanStatus::InMempool => "Nothing to do; mine a block and it confirms again.",
+        OrphanStatus::Unconfirmed => {
+            "Rebroadcast it with sendrawtransaction, or abandon it to free its coins for a new payment."
+        }
+        OrphanStatus::Conflicted => {
+            "A conflicting transaction confirmed instead, so this payment will never confirm. Check the new balance and pay again if needed."
+        }
+    }
+}
+
+fn build_report(data: &AppState, wallet: &str, client: &Client) -> Result<ReorgReport, String> {
+    // Transactions confirmed since the last snapshot are picked up before comparing
+    snapshot_confirmations(data, wallet, client).map_err(|e| e.to_string())?;
+    let recorded = data.db.confirmations(wallet).map_err(|e| e.to_string())?;
+    let tip_height = client.get_block_count().map_err(|e| e.to_string())?;
+
+    let mut orphaned = Vec::new();
+    for (txid, block_hash, block_height) in recorded {
+        let tx: Value = match client.call("gettransaction", &[json!(txid), json!(true)]) {
+            Ok(tx) => tx,
+            Err(e) => {
+                warn!(
+                    "Recorded transaction {} no longer in wallet '{}': {}",
+                    txid, wallet, e
+                );
+                continue;
+            }
+        };
+        let confirmations = tx["confirmations"].as_i64().unwrap_or_default();
+        if confirmations > 0 {
+            continue;
+        }
+        let in_mempool = client
+            .call::<Value>("getmempoolentry", &[json!(txid)])
+            .is_ok();
+        let status = if confirmations < 0 {
+            OrphanStatus::Conflicted
+        } else if in_mempool {
+            OrphanStatus::InMempool
+        } else {
+            OrphanStatus::Unconfirmed
+        };
+        let conflicts = tx["walletconflicts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|conflict| conflict.as_str().map(str::to_string))
+            .collect();
+        orphaned.push(OrphanedTransaction {
+            txid,
+            previous_block_hash: block_hash,
+            previous_block_height: block_height,
+            status,
+            in_mempool,
+            conflicts,
+            recommended_action: recommended_action(status),
+        });
+    }
+    Ok(ReorgReport {
+        wallet: wallet.to_string(),
+        tip_height,
+        orphaned,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/reorg-report",
+    tag = "reorg",
+    responses(
+        (status = 200, body = ReorgReport),
+    )
+)]
+pub(crate) async fn reorg_report(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/reorg-report", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    // wallet_client may ask the node for a wallet loaded outside the API, so it runs
+    // off the async threads too
+    let report = rpc_pool::with_state(&data, move |data| {
+        let client = data.wallet_client(&wallet_name)?;
+        Some(build_report(data, &wallet_name, &client))
+    })
+    .await?;
+    match report {
+        None => {
+            warn!("GET /wallet/{}/reorg-report - wallet not found", walletid);
+            Err(ApiError::WalletNotFound(walletid.into_inner()))
+        }
+        Some(Ok(mut report)) => {
+            report.wallet = walletid.into_inner();
+            Ok(HttpResponse::Ok().json(report))
+        }
+        Some(Err(e)) => {
+            error!(
+                "Failed to build reorg report for wallet '{}': {}",
+                walletid, e
+            );
+            Err(ApiError::Internal(e))
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SimulateReorgRequest {
+    // Wallet that mines the competing branch
+    wallet_name: String,
+    // Blocks of the current chain that get replaced, 1 by default
+    depth: Option<u64>,
+    // The new branch is this many blocks longer than the replaced one, 1 by default
+    extra_blocks: Option<u64>,
+    // false mines empty blocks, so the replaced blocks' transactions stay unconfirmed
+    include_mempool: Option<bool>,
+}
+
+struct ReorgPlan {
+    depth: u64,
+    extra_blocks: u64,
+    include_mempool: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChainStateResponse {
+    tip_height: u64,
+    tip_hash: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SimulatedReorg {
+    fork_height: u64,
+    old_tip: String,
+    // The replaced blocks, now a stale branch; lowest first
+    orphaned_blocks: Vec<String>,
+    new_blocks: Vec<String>,
+    tip_height: u64,
+}
+
+fn parse_block_hash(hash: &str) -> Result<BlockHash, ApiError> {
+    BlockHash::from_str(hash)
+        .map_err(|e| ApiError::InvalidRequest(format!("Invalid block hash: {}", e)))
+}
+
+fn chain_state(node: &Client) -> Result<ChainStateResponse, RpcError> {
+    let tip_height = node.get_block_count()?;
+    Ok(ChainStateResponse {
+        tip_height,
+        tip_hash: node.get_block_hash(tip_height)?.to_string(),
+    })
+}
+
+// invalidateblock and reconsiderblock on a block hash, answering with the new tip
+async fn change_block_validity(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    hash: &str,
+    method: &'static str,
+) -> Result<HttpResponse, ApiError> {
+    data.config.ensure_regtest("Reorg simulation")?;
+    let block_hash = parse_block_hash(hash)?;
+    let node = node_client(data, http_req)?;
+    let changed = rpc_pool::blocking(move || {
+        node.call::<Value>(method, &[json!(block_hash)])
+            .and_then(|_| chain_state(&node))
+    })
+    .await?;
+    match changed {
+        Ok(state) => {
+            info!(
+                "{} {}: tip is now {} at height {}",
+                method, block_hash, state.tip_hash, state.tip_height
+            );
+            Ok(HttpResponse::Ok().json(state))
+        }
+        Err(RpcError::JsonRpc(JsonRpcError::Rpc(e))) if e.code == RPC_BLOCK_NOT_FOUND => Err(
+            ApiError::NotFound(format!("Block {} not found", block_hash)),
+        ),
+        Err(e) => {
+            error!("{} {} failed: {}", method, block_hash, e);
+            Err(ApiError::rpc(http_req, e))
+        }
+    }
+}
+
+// Marks the block and its descendants invalid; the node falls back to the best valid chain
+#[utoipa::path(
+    post,
+    path = "/chain/invalidate/{blockhash}",
+    tag = "reorg",
+    responses(
+        (status = 200, body = ChainStateResponse),
+    )
+)]
+pub(crate) async fn invalidate_block(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /chain/invalidate/{}", hash);
+    change_block_validity(&data, &http_req, &hash, "invalidateblock").await
+}
+
+// Undoes invalidateblock; the node switches back if that branch has the most work
+#[utoipa::path(
+    post,
+    path = "/chain/reconsider/{blockhash}",
+    tag = "reorg",
+    responses(
+        (status = 200, body = ChainStateResponse),
+    )
+)]
+pub(crate) async fn reconsider_block(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /chain/reconsider/{}", hash);
+    change_block_validity(&data, &http_req, &hash, "reconsiderblock").await
+}
+
+fn mine_branch(
+    node: &Client,
+    address: &Address,
+    blocks: u64,
+    include_mempool: bool,
+) -> Result<Vec<BlockHash>, RpcError> {
+    if include_mempool {
+        return node.generate_to_address(blocks, address);
+    }
+    (0..blocks)
+        .map(|_| {
+            let block: Value =
+                node.call("generateblock", &[json!(address.to_string()), json!([])])?;
+            BlockHash::from_str(block["hash"].as_str().unwrap_or_default())
+                .map_err(|e| RpcError::ReturnedError(format!("invalid block hash: {e}")))
+        })
+        .collect()
+}
+
+// Replaces the last `depth` blocks with a longer branch, like a miner that found
+// blocks faster in private. The replaced blocks stay known to the node as a stale
+// branch, and their transactions go back to the mempool.
+fn mine_competing_branch(
+    data: &AppState,
+    wallet_name: &str,
+    client: &Client,
+    plan: &ReorgPlan,
+) -> Result<SimulatedReorg, RpcError> {
+    let tip_height = client.get_block_count()?;
+    if plan.depth > tip_height {
+        return Err(RpcError::ReturnedError(format!(
+            "cannot replace {} blocks of a chain at height {}",
+            plan.depth, tip_height
+        )));
+    }
+    let fork_height = tip_height - plan.depth;
+    let orphaned_blocks = ((fork_height + 1)..=tip_height)
+        .map(|height| client.get_block_hash(height))
+        .collect::<Result<Vec<_>, _>>()?;
+    let address = client
+        .get_new_address(Some("reorg"), Some(AddressType::Bech32))?
+        .require_network(data.config.network)
+        .map_err(|e| RpcError::ReturnedError(e.to_string()))?;
+
+    client.invalidate_block(&orphaned_blocks[0])?;
+    let mined = mine_branch(
+        client,
+        &address,
+        plan.depth + plan.extra_blocks,
+        plan.include_mempool,
+    );
+    // Valid again, but with less work than the new branch: a block that lost the race
+    client.reconsider_block(&orphaned_blocks[0])?;
+    let new_blocks = mined?;
+    mining::record_blocks(
+        data,
+        wallet_name,
+        &address,
+        &new_blocks,
+        mining::SOURCE_MANUAL,
+    );
+
+    Ok(SimulatedReorg {
+        fork_height,
+        old_tip: orphaned_blocks[orphaned_blocks.len() - 1].to_string(),
+        orphaned_blocks: orphaned_blocks
+            .iter()
+            .map(|hash| hash.to_string())
+            .collect(),
+        new_blocks: new_blocks.iter().map(|hash| hash.to_string()).collect(),
+        tip_height: client.get_block_count()?,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/chain/reorg",
+    tag = "reorg",
+    responses(
+        (status = 200, body = SimulatedReorg),
+    )
+)]
+pub(crate) async fn simulate_reorg(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<SimulateReorgRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let plan = ReorgPlan {
+        depth: req.depth.unwrap_or(1),
+        extra_blocks: req.extra_blocks.unwrap_or(1),
+        include_mempool: req.include_mempool.unwrap_or(true),
+    };
+    info!(
+        "POST /chain/reorg - wallet='{}', depth={}, extra_blocks={}, include_mempool={}",
+        req.wallet_name, plan.depth, plan.extra_blocks, plan.include_mempool
+    );
+    data.config.ensure_regtest("Reorg simulation")?;
+    if !(1..=MAX_REORG_DEPTH).contains(&plan.depth) {
+        return Err(ApiError::InvalidRequest(format!(
+            "depth must be between 1 and {}",
+            MAX_REORG_DEPTH
+        )));
+    }
+    if !(1..=MAX_REORG_DEPTH).contains(&plan.extra_blocks) {
+        return Err(ApiError::InvalidRequest(format!(
+            "extra_blocks must be between 1 and {}",
+            MAX_REORG_DEPTH
+        )));
+    }
+    let wallet_name = data.wallet_name(&http_req, &req.wallet_name);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("POST /chain/reorg - wallet '{}' not found", req.wallet_name);
+        return Err(ApiError::WalletNotFound(req.wallet_name.clone()));
+    };
+    let reorg = rpc_pool::with_state(&data, move |data| {
+        mine_competing_branch(data, &wallet_name, &client, &plan)
+    })
+    .await?;
+    match reorg {
+        Ok(reorg) => {
+            info!(
+                "Reorged {} block(s) at height {} into {} new block(s)",
+                reorg.orphaned_blocks.len(),
+                reorg.fork_height,
+                reorg.new_blocks.len()
+            );
+            Ok(HttpResponse::Ok().json(reorg))
+        }
+        Err(RpcError::ReturnedError(e)) => Err(ApiError::InvalidRequest(e)),
+        Err(e) => {
+            error!("Failed to simulate a reorg: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::db::StorageResult;
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpResponse};
+use bitcoincore_rpc::bitcoin::Txid;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const RECENT_TRANSACTIONS: usize = 1000;
+
+#[derive(Serialize, ToSchema)]
+struct LineageEntry {
+    txid: String,
+    replaces: Option<String>,
+    replaced_by: Vec<String>,
+    // When the replacement of the previous transaction was first seen
+    detected_at: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReplacementHistory {
+    txid: String,
+    root_txid: String,
+    // Transactions at the end of the chain, i.e. the ones that can still confirm
+    current_txids: Vec<String>,
+    // In order of replacement, starting with the original payment
+    lineage: Vec<LineageEntry>,
+}
+
+// Picks up replacements the wallet knows about from bumpfee and similar
+fn record_wallet_replacements(
+    data: &AppState,
+    wallet: &str,
+    client: &Client,
+) -> Result<usize, RpcError> {
+    let transactions: Vec<Value> = client.call(
+        "listtransactions",
+        &[
+            json!("*"),
+            json!(RECENT_TRANSACTIONS),
+            json!(0),
+            json!(true),
+        ],
+    )?;
+    let mut recorded = 0;
+    for tx in &transactions {
+        let Some(txid) = tx["txid"].as_str() else {
+            continue;
+        };
+        let edges = [
+            tx["replaced_by_txid"]
+                .as_str()
+                .map(|replacement| (txid, replacement)),
+            tx["replaces_txid"]
+                .as_str()
+                .map(|original| (original, txid)),
+        ];
+        for (original, replacement) in edges.into_iter().flatten() {
+            match data.db.record_replacement(wallet, original, replacement) {
+                Ok(true) => {
+                    info!(
+                        "Transaction {} was replaced by {} in wallet '{}'",
+                        original, replacement, wallet
+                    );
+                    recorded += 1;
+                }
+                Ok(false) => {}
+                Err(e) => error!("Failed to record replacement of {}: {}", original, e),
+            }
+        }
+    }
+    Ok(recorded)
+}
+
+pub(crate) async fn track_replacements(data: web::Data<AppState>) {
+    loop {
+        actix_rt::time::sleep(POLL_INTERVAL).await;
+        let checked = rpc_pool::with_state(&data, |data| {
+            let wallets: Vec<String> = data
+                .clients
+                .iter()
+                .map(|entry| entry.key().clone())
+                .collect();
+            for wallet in wallets {
+                let Some(client) = data.clients.get(&wallet).map(|pool| pool.get()) else {
+                    continue;
+                };
+                match record_wallet_replacements(data, &wallet, &client) {
+                    Ok(0) => {}
+                    Ok(recorded) => debug!(
+                        "Recorded {} replacement(s) for wallet '{}'",
+                        recorded, wallet
+                    ),
+                    Err(e) => warn!(
+                        "Failed to check replacements for wallet '{}': {}",
+                        wallet, e
+                    ),
+                }
+            }
+        });
+        if let Err(e) = checked.await {
+            warn!("Failed to check replacements: {}", e);
+        }
+    }
+}
+
+fn lineage(data: &AppState, txid: &str) -> StorageResult<ReplacementHistory> {
+    // Walk back to the original payment; the seen set guards against cycles
+    let mut root = txid.to_string();
+    let mut seen = HashSet::from([root.clone()]);
+    while let Some(original) = data.db.replaces(&root)? {
+        if !seen.insert(original.clone()) {
+            break;
+        }
+        root = original;
+    }
+
+    let mut entries = Vec::new();
+    let mut current_txids = Vec::new();
+    let mut seen = HashSet::from([root.clone()]);
+    let mut queue = VecDeque::from([(root.clone(), None, None)]);
+    while let Some((txid, replaces, detected_at)) = queue.pop_front() {
+        let replaced_by = data.db.replaced_by(&txid)?;
+        if replaced_by.is_empty() {
+            current_txids.push(txid.clone());
+        }
+        for (replacement, at) in &replaced_by {
+            if seen.insert(replacement.clone()) {
+                queue.push_back((replacement.clone(), Some(txid.clone()), Some(*at)));
+            }
+        }
+        entries.push(LineageEntry {
+            txid,
+            replaces,
+            replaced_by: replaced_by
+                .into_iter()
+                .map(|(replacement, _)| replacement)
+                .collect(),
+            detected_at,
+        });
+    }
+    Ok(ReplacementHistory {
+        txid: txid.to_string(),
+        root_txid: root,
+        current_txids,
+        lineage: entries,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/tx/{txid}/replacements",
+    tag = "replacements",
+    responses(
+        (status = 200, body = ReplacementHistory),
+    )
+)]
+pub(crate) async fn get_replacements(
+    data: web::Data<AppState>,
+    txid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /tx/{}/replacements", txid);
+    if let Err(e) = Txid::from_str(&txid) {
+        warn!("Invalid txid format '{}': {}", txid, e);
+        return Err(ApiError::InvalidTxid(format!(
+            "Invalid transaction ID: {}",
+            e
+        )));
+    }
+    match lineage(&data, &txid) {
+        Ok(history) => Ok(HttpResponse::Ok().json(history)),
+        Err(e) => {
+            error!("Failed to load replacements of {}: {}", txid, e);
+            Err(e.into())
+        }
+    }
+}
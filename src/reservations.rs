@@ -0,0 +1,319 @@
+use crate::db::Reservation;
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::json::GetBalancesResult;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const MAX_BUCKET_NAME_LEN: usize = 64;
+
+// Reservation settings for the send endpoints
+#[derive(Deserialize, Default, ToSchema)]
+pub(crate) struct ReservationOptions {
+    // Spend from this bucket: its funds may be used and it shrinks by the amount sent
+    pub(crate) bucket: Option<String>,
+    // Reservations are soft limits; this sends anyway
+    #[serde(default)]
+    pub(crate) ignore_reservations: bool,
+}
+
+impl ReservationOptions {
+    pub(crate) fn is_set(&self) -> bool {
+        self.bucket.is_some() || self.ignore_reservations
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SetReservationRequest {
+    // 0 removes the bucket
+    amount: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ReservationSummary {
+    reserved_sat: u64,
+    // Spendable balance outside every bucket
+    unreserved_sat: u64,
+    buckets: Vec<Reservation>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReservationsResponse {
+    wallet: String,
+    balance_sat: u64,
+    #[serde(flatten)]
+    summary: ReservationSummary,
+}
+
+pub(crate) fn summary(buckets: Vec<Reservation>, balance_sat: u64) -> ReservationSummary {
+    let reserved_sat = buckets
+        .iter()
+        .fold(0u64, |sum, bucket| sum.saturating_add(bucket.amount_sat));
+    ReservationSummary {
+        reserved_sat,
+        unreserved_sat: balance_sat.saturating_sub(reserved_sat),
+        buckets,
+    }
+}
+
+// The reserved funds a send may use: all of its bucket, none of the others
+fn from_bucket_sat(buckets: &[Reservation], bucket: Option<&str>) -> Result<u64, ApiError> {
+    let Some(name) = bucket else {
+        return Ok(0);
+    };
+    buckets
+        .iter()
+        .find(|bucket| bucket.bucket == name)
+        .map(|bucket| bucket.amount_sat)
+        .ok_or_else(|| {
+            ApiError::InvalidRequest(format!("There is no reservation '{}' to spend from", name))
+        })
+}
+
+impl ReservationSummary {
+    // The unreserved funds plus the bucket a send spends from, which cannot hold more
+    // than the balance
+    fn allowed_sat(&self, from_bucket_sat: u64, balance_sat: u64) -> u64 {
+        self.unreserved_sat
+            .saturating_add(from_bucket_sat.min(balance_sat))
+    }
+}
+
+// Refuses a send that would dip into reserved funds, unless it spends from the bucket
+// it needs or ignores the reservations. Fees are not counted.
+pub(crate) async fn check_spend(
+    data: &AppState,
+    http_req: &HttpRequest,
+    wallet: &str,
+    amount_sat: u64,
+    options: &ReservationOptions,
+) -> Result<(), ApiError> {
+    if options.ignore_reservations {
+        return Ok(());
+    }
+    let buckets = data.db.reservations(wallet)?;
+    if buckets.is_empty() && options.bucket.is_none() {
+        return Ok(());
+    }
+    let from_bucket = from_bucket_sat(&buckets, options.bucket.as_deref())?;
+    let balance_sat = trusted_balance(data, http_req, wallet).await?;
+    let summary = summary(buckets, balance_sat);
+    let allowed = summary.allowed_sat(from_bucket, balance_sat);
+    if amount_sat > allowed {
+        let names: Vec<&str> = summary
+            .buckets
+            .iter()
+            .map(|bucket| bucket.bucket.as_str())
+            .collect();
+        warn!(
+            "Send of {} sat from wallet '{}' blocked by reservations ({} sat allowed)",
+            amount_sat, wallet, allowed
+        );
+        return Err(ApiError::Conflict(format!(
+            "Sending {} sat would use funds reserved for {}; {} sat can be spent. Spend from a reservation with `bucket`, or set `ignore_reservations`",
+            amount_sat,
+            names.join(", "),
+            allowed
+        )));
+    }
+    Ok(())
+}
+
+// Shrinks the bucket a send spent from. The payment went out already, so a failure
+// here is only logged.
+pub(crate) fn consume(
+    data: &AppState,
+    wallet: &str,
+    amount_sat: u64,
+    options: &ReservationOptions,
+) {
+    let Some(bucket) = &options.bucket else {
+        return;
+    };
+    if options.ignore_reservations {
+        return;
+    }
+    if let Err(e) = data.db.release_reservation(wallet, bucket, amount_sat) {
+        error!(
+            "Failed to release {} sat from reservation '{}' of wallet '{}': {}",
+            amount_sat, bucket, wallet, e
+        );
+    }
+}
+
+async fn trusted_balance(
+    data: &AppState,
+    http_req: &HttpRequest,
+    wallet: &str,
+) -> Result<u64, ApiError> {
+    let Some(client) = data.wallet_client(wallet) else {
+        return Err(ApiError::WalletNotFound(wallet.to_string()));
+    };
+    let balances: Result<GetBalancesResult, _> =
+        rpc_pool::call(data, client, wallet, "getbalances", vec![]).await?;
+    match balances {
+        Ok(balances) => Ok(balances.mine.trusted.to_sat()),
+        Err(e) => {
+            error!("Failed to get balance for wallet '{}': {}", wallet, e);
+            Err(ApiError::rpc(http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/reservations",
+    tag = "reservations",
+    responses(
+        (status = 200, body = ReservationsResponse),
+    )
+)]
+pub(crate) async fn list_reservations(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/reservations", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if data.wallet_client(&wallet_name).is_none() {
+        warn!("GET /wallet/{}/reservations - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    }
+    let balance_sat = trusted_balance(&data, &http_req, &wallet_name).await?;
+    let buckets = data.db.reservations(&wallet_name)?;
+    Ok(HttpResponse::Ok().json(ReservationsResponse {
+        wallet: walletid.into_inner(),
+        balance_sat,
+        summary: summary(buckets, balance_sat),
+    }))
+}
+
+// Sets aside `amount` under the bucket, replacing what it held. All buckets together
+// cannot hold more than the spendable balance.
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/reservations/{bucket}",
+    tag = "reservations",
+    responses(
+        (status = 200, body = ReservationsResponse),
+    )
+)]
+pub(crate) async fn set_reservation(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    path: web::Path<(String, String)>,
+    req: web::Json<SetReservationRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (walletid, bucket) = path.into_inner();
+    info!(
+        "POST /wallet/{}/reservations/{} - amount_sat={}",
+        walletid, bucket, req.amount
+    );
+    if bucket.trim().is_empty() || bucket.len() > MAX_BUCKET_NAME_LEN {
+        return Err(ApiError::InvalidRequest(format!(
+            "Bucket names are 1 to {} characters long",
+            MAX_BUCKET_NAME_LEN
+        )));
+    }
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if data.wallet_client(&wallet_name).is_none() {
+        warn!(
+            "POST /wallet/{}/reservations/{} - wallet not found",
+            walletid, bucket
+        );
+        return Err(ApiError::WalletNotFound(walletid));
+    }
+    // Stored as i64
+    if i64::try_from(req.amount).is_err() {
+        return Err(ApiError::InvalidRequest(format!(
+            "amount must be at most {} sat",
+            i64::MAX
+        )));
+    }
+    let balance_sat = trusted_balance(&data, &http_req, &wallet_name).await?;
+    let others = data
+        .db
+        .reservations(&wallet_name)?
+        .iter()
+        .filter(|reservation| reservation.bucket != bucket)
+        .fold(0u64, |sum, reservation| {
+            sum.saturating_add(reservation.amount_sat)
+        });
+    if others
+        .checked_add(req.amount)
+        .is_none_or(|total| total > balance_sat)
+    {
+        return Err(ApiError::InvalidRequest(format!(
+            "Cannot reserve {} sat: the spendable balance is {} sat and {} sat are reserved in other buckets",
+            req.amount, balance_sat, others
+        )));
+    }
+    data.db.set_reservation(&wallet_name, &bucket, req.amount)?;
+    info!(
+        "Wallet '{}' reserves {} sat for '{}'",
+        wallet_name, req.amount, bucket
+    );
+    let buckets = data.db.reservations(&wallet_name)?;
+    Ok(HttpResponse::Ok().json(ReservationsResponse {
+        wallet: walletid,
+        balance_sat,
+        summary: summary(buckets, balance_sat),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(name: &str, amount_sat: u64) -> Reservation {
+        Reservation {
+            bucket: name.to_string(),
+            amount_sat,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn summary_splits_the_balance() {
+        let summary = summary(vec![bucket("rent", 60_000), bucket("tax", 30_000)], 100_000);
+        assert_eq!(summary.reserved_sat, 90_000);
+        assert_eq!(summary.unreserved_sat, 10_000);
+    }
+
+    #[test]
+    fn summary_of_an_overreserved_wallet() {
+        let summary = summary(
+            vec![bucket("rent", 60_000), bucket("tax", u64::MAX)],
+            50_000,
+        );
+        assert_eq!(summary.reserved_sat, u64::MAX);
+        assert_eq!(summary.unreserved_sat, 0);
+    }
+
+    #[test]
+    fn spending_from_a_bucket() {
+        let buckets = vec![bucket("rent", 60_000), bucket("tax", 30_000)];
+        assert_eq!(from_bucket_sat(&buckets, None).unwrap(), 0);
+        assert_eq!(from_bucket_sat(&buckets, Some("rent")).unwrap(), 60_000);
+        assert!(matches!(
+            from_bucket_sat(&buckets, Some("holiday")),
+            Err(ApiError::InvalidRequest(_))
+        ));
+
+        let summary = summary(buckets, 100_000);
+        // Only the unreserved funds without a bucket
+        assert_eq!(summary.allowed_sat(0, 100_000), 10_000);
+        // Plus the whole bucket spent from, but not the other one
+        assert_eq!(summary.allowed_sat(60_000, 100_000), 70_000);
+    }
+
+    #[test]
+    fn a_bucket_is_capped_at_the_balance() {
+        let summary = summary(vec![bucket("rent", 60_000)], 40_000);
+        assert_eq!(summary.unreserved_sat, 0);
+        assert_eq!(summary.allowed_sat(60_000, 40_000), 40_000);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::errors::ApiError;
+use crate::store::IDEMPOTENCY_PREFIX;
+use crate::AppState;
+use crate::{rpc_pool, watch};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+const NODE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// bitcoind takes a moment to flush and exit, and a bit longer to start again
+const MAX_STOP_POLLS: usize = 60;
+const MAX_START_POLLS: usize = 120;
+
+// What POST /admin/reset does to the chain after the wallets are gone
+#[derive(Clone, Debug)]
+pub(crate) enum ResetStrategy {
+    // Leave the chain as it is
+    None,
+    // Invalidate the block at height 1, which takes the node back to genesis
+    Invalidate,
+    // Stop the node, delete its regtest directory and start it with `start_command`
+    Restart {
+        datadir: PathBuf,
+        start_command: String,
+    },
+}
+
+impl ResetStrategy {
+    fn name(&self) -> &'static str {
+        match self {
+            ResetStrategy::None => "none",
+            ResetStrategy::Invalidate => "invalidate",
+            ResetStrategy::Restart { .. } => "restart",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ResetConfig {
+    strategy: ResetStrategy,
+    // bitcoind's -walletdir, so wallet files can be deleted and not only unloaded
+    wallet_dir: Option<PathBuf>,
+}
+
+impl ResetConfig {
+    // `reset_strategy` is none, invalidate or restart. Restarting needs the node's
+    // `reset_datadir` and a `reset_start_command`; without `reset_wallet_dir` the
+    // wallets are looked for in the datadir.
+    pub(crate) fn from_env() -> Result<Self, RpcError> {
+        let datadir = env::var("reset_datadir").ok().map(PathBuf::from);
+        let strategy = match env::var("reset_strategy").as_deref().map(str::trim) {
+            Err(_) | Ok("") | Ok("none") => ResetStrategy::None,
+            Ok("invalidate") => ResetStrategy::Invalidate,
+            Ok("restart") => ResetStrategy::Restart {
+                datadir: datadir.clone().ok_or_else(|| {
+                    RpcError::ReturnedError("reset_strategy=restart needs reset_datadir".into())
+                })?,
+                start_command: env::var("reset_start_command").map_err(|_| {
+                    RpcError::ReturnedError(
+                        "reset_strategy=restart needs reset_start_command".into(),
+                    )
+                })?,
+            },
+            Ok(other) => {
+                return Err(RpcError::ReturnedError(format!(
+                    "unsupported reset_strategy '{other}', expected none, invalidate or restart"
+                )))
+            }
+        };
+        let wallet_dir = env::var("reset_wallet_dir")
+            .ok()
+            .map(PathBuf::from)
+            .or_else(|| datadir.map(|datadir| datadir.join("regtest").join("wallets")));
+        Ok(Self {
+            strategy,
+            wallet_dir,
+        })
+    }
+
+    pub(crate) fn wallet_dir(&self) -> Option<&Path> {
+        self.wallet_dir.as_deref()
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ResetQuery {
+    // false only wipes the wallets and keeps the chain
+    chain: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ResetResponse {
+    unloaded_wallets: Vec<String>,
+    // Empty when the server does not know bitcoind's wallet directory
+    deleted_wallets: Vec<String>,
+    // The strategy applied to the chain, none if it was kept
+    strategy: &'static str,
+    height_before: u64,
+    height_after: u64,
+}
+
+// Names of every wallet in bitcoind's wallet directory, loaded or not
+fn wallets_on_disk(node: &Client) -> Result<Vec<String>, RpcError> {
+    let listed: Value = node.call("listwalletdir", &[])?;
+    Ok(listed["wallets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|wallet| wallet["name"].as_str().map(str::to_string))
+        .collect())
+}
+
+// Height, loaded wallets and wallets on disk before the reset
+fn inventory(node: &Client) -> Result<(u64, Vec<String>, Vec<String>), RpcError> {
+    Ok((
+        node.get_block_count()?,
+        node.list_wallets()?,
+        wallets_on_disk(node)?,
+    ))
+}
+
+fn unload_wallets(node: &Client, loaded: Vec<String>) -> Result<Vec<String>, RpcError> {
+    let mut unloaded = Vec::new();
+    for wallet in loaded {
+        if let Err(e) = node.unload_wallet(Some(&wallet)) {
+            error!("Failed to unload wallet '{}': {}", wallet, e);
+            return Err(e);
+        }
+        unloaded.push(wallet);
+    }
+    Ok(unloaded)
+}
+
+// Wallet names come from bitcoind, but are checked to stay inside the wallet directory
+fn delete_wallet_files(wallet_dir: &Path, name: &str) -> Result<(), String> {
+    let relative = Path::new(name);
+    if name.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!("refusing to delete wallet '{name}'"));
+    }
+    let path = wallet_dir.join(relative);
+    let removed = if path.is_dir() {
+        std::fs::remove_dir_all(&path)
+    } else {
+        std::fs::remove_file(&path)
+    };
+    removed.map_err(|e| format!("cannot delete {}: {e}", path.display()))
+}
+
+fn invalidate_to_genesis(node: &Client) -> Result<(), RpcError> {
+    if node.get_block_count()? == 0 {
+        return Ok(());
+    }
+    let first = node.get_block_hash(1)?;
+    node.invalidate_block(&first)
+}
+
+async fn restart_node(
+    data: &web::Data<AppState>,
+    datadir: &Path,
+    start_command: &str,
+) -> Result<(), String> {
+    rpc_pool::call_node::<Value>(data, "stop", vec![])
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("cannot stop bitcoind: {e}"))?;
+    let mut stopped = false;
+    for _ in 0..MAX_STOP_POLLS {
+        actix_rt::time::sleep(NODE_POLL_INTERVAL).await;
+        if !matches!(node_height(data).await, Ok(Ok(_))) {
+            stopped = true;
+            break;
+        }
+    }
+    if !stopped {
+        return Err("bitcoind did not stop".into());
+    }
+    // The RPC server closes before the node releases its files
+    actix_rt::time::sleep(NODE_POLL_INTERVAL).await;
+
+    let chain_dir = datadir.join("regtest");
+    if chain_dir.exists() {
+        std::fs::remove_dir_all(&chain_dir)
+            .map_err(|e| format!("cannot delete {}: {e}", chain_dir.display()))?;
+    }
+    info!("Deleted {}, starting bitcoind", chain_dir.display());
+    Command::new("sh")
+        .args(["-c", start_command])
+        .spawn()
+        .map_err(|e| format!("cannot run reset_start_command: {e}"))?;
+
+    for _ in 0..MAX_START_POLLS {
+        actix_rt::time::sleep(NODE_POLL_INTERVAL).await;
+        // Fails with RPC_IN_WARMUP until the node has loaded its chain
+        if matches!(node_height(data).await, Ok(Ok(_))) {
+            return Ok(());
+        }
+    }
+    Err("bitcoind did not come back after the restart".into())
+}
+
+async fn node_height(data: &web::Data<AppState>) -> Result<Result<u64, RpcError>, ApiError> {
+    rpc_pool::call_node(data, "getblockcount", vec![]).await
+}
+
+// Unloads and deletes every wallet and returns the chain to a clean state, so an
+// episode can start over. Regtest only.
+#[utoipa::path(
+    post,
+    path = "/admin/reset",
+    tag = "reset",
+    params(ResetQuery),
+    responses(
+        (status = 200, body = ResetResponse),
+    )
+)]
+pub(crate) async fn reset(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<ResetQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let reset_chain = query.chain.unwrap_or(true);
+    let reset_config = &data.config.reset;
+    info!(
+        "POST /admin/reset - chain={}, strategy={}",
+        reset_chain,
+        reset_config.strategy.name()
+    );
+    data.config.ensure_regtest("Resetting")?;
+    let node = match data.config.create_node_client() {
+        Ok(node) => node,
+        Err(e) => {
+            error!("Failed to create node RPC client: {}", e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let (node, listed) = rpc_pool::blocking(move || {
+        let listed = inventory(&node);
+        (node, listed)
+    })
+    .await?;
+    let (height_before, loaded, on_disk) = listed.map_err(|e| ApiError::rpc(&http_req, e))?;
+
+    // The server's own view of the wallets goes first, so no request picks them up again
+    data.clients.clear();
+    if let Err(e) = data.db.clear_chain_state() {
+        error!("Failed to clear the database: {}", e);
+        return Err(ApiError::Storage(e.to_string()));
+    }
+    // Watched addresses are scanned again on the new chain
+    if let Err(e) = watch::rewind(&data).await {
+        error!("Failed to rewind the watch list: {}", e);
+        return Err(ApiError::Storage(e));
+    }
+    data.events.clear_journal();
+    // A replayed key would answer with a txid from the wiped chain
+    if let Err(e) = data.store.delete_prefix(IDEMPOTENCY_PREFIX).await {
+        error!("Failed to clear the idempotency keys: {}", e);
+        return Err(ApiError::Storage(e));
+    }
+
+    let (node, unloaded) = rpc_pool::blocking(move || {
+        let unloaded = unload_wallets(&node, loaded);
+        (node, unloaded)
+    })
+    .await?;
+    let unloaded_wallets = unloaded.map_err(|e| ApiError::rpc(&http_req, e))?;
+    let mut deleted_wallets = Vec::new();
+    if let Some(wallet_dir) = &reset_config.wallet_dir {
+        for wallet in on_disk {
+            match delete_wallet_files(wallet_dir, &wallet) {
+                Ok(()) => deleted_wallets.push(wallet),
+                Err(e) => warn!("Failed to delete wallet '{}': {}", wallet, e),
+            }
+        }
+    } else {
+        warn!("reset_wallet_dir is not set, wallets were only unloaded");
+    }
+
+    let strategy = if reset_chain {
+        &reset_config.strategy
+    } else {
+        &ResetStrategy::None
+    };
+    match strategy {
+        ResetStrategy::None => {}
+        ResetStrategy::Invalidate => {
+            if let Err(e) = rpc_pool::blocking(move || invalidate_to_genesis(&node)).await? {
+                error!("Failed to invalidate the chain: {}", e);
+                return Err(ApiError::rpc(&http_req, e));
+            }
+        }
+        ResetStrategy::Restart {
+            datadir,
+            start_command,
+        } => {
+            if let Err(e) = restart_node(&data, datadir, start_command).await {
+                error!("Failed to restart bitcoind: {}", e);
+                return Err(ApiError::Internal(e));
+            }
+            // The node forgot its mocktime along with everything else
+            if let Err(e) = data.store.delete(crate::admin::MOCKTIME_KEY).await {
+                error!("Failed to forget mocktime: {}", e);
+            }
+        }
+    }
+
+    let height_after = node_height(&data)
+        .await?
+        .map_err(|e| ApiError::rpc(&http_req, e))?;
+    info!(
+        "Reset done: {} wallet(s) unloaded, {} deleted, height {} -> {}",
+        unloaded_wallets.len(),
+        deleted_wallets.len(),
+        height_before,
+        height_after
+    );
+    Ok(HttpResponse::Ok().json(ResetResponse {
+        unloaded_wallets,
+        deleted_wallets,
+        strategy: strategy.name(),
+        height_before,
+        height_after,
+    }))
+}
@@ -0,0 +1,386 @@
+use crate::{
+    activity, admin, auth, backup, balances, channel, cohort, complete_chapter, create_address,
+    create_wallet, dashboard, derivation, descriptors, dust, encryption, event_replay, exercises,
+    explorer, faucet, fee_bump, fees, get_transaction, history, hwi, invoices, keypool, keys,
+    labels, list_wallets, mempool, mine_blocks, mining, multisig, musig, node, op_return, outbox,
+    playground, propagation, psbt, raw_tx, recording, reorg, replacements, reservations, reset,
+    sandbox, scenario, send_bitcoin, signer, signing, sse, stale_blocks, transfer, tx_watch, utxos,
+    wallet_export, watch, watch_only, ws,
+};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest};
+
+// The API is served under /api/v1. A v2 gets its own scope next to it, with its own
+// route table pointing at the handlers that did not change and an error handler
+// wrapping the scope for the new error model, so v1 clients keep their responses.
+pub(crate) const V1_PREFIX: &str = "/api/v1";
+
+pub(crate) const DEPRECATION_HEADER: HeaderName = HeaderName::from_static("deprecation");
+
+// Mounted at /api/v1, and at the root as deprecated aliases until clients have moved
+pub(crate) fn v1(cfg: &mut web::ServiceConfig) {
+    cfg.route("/auth/register", web::post().to(auth::register))
+        .route("/auth/login", web::post().to(auth::login))
+        .route("/auth/me", web::get().to(auth::current_user))
+        .route("/wallet", web::post().to(create_wallet))
+        .route("/wallets", web::get().to(list_wallets))
+        .route("/balances", web::get().to(balances::get_balances))
+        .route("/dashboard", web::get().to(dashboard::get_dashboard))
+        .route("/address", web::post().to(create_address))
+        .route(
+            "/address/{address}/owner",
+            web::get().to(labels::get_address_owner),
+        )
+        .route("/mine", web::post().to(mine_blocks))
+        .route("/mine/miners", web::post().to(mining::set_miner))
+        .route("/mine/miners", web::get().to(mining::list_miners))
+        .route("/mine/leaderboard", web::get().to(mining::leaderboard))
+        .route("/mine/auto", web::post().to(mining::start_auto_mine))
+        .route("/mine/auto", web::get().to(mining::get_auto_mine))
+        .route("/mine/auto", web::delete().to(mining::stop_auto_mine))
+        .route(
+            "/wallet/{walletid}/balance",
+            web::get().to(balances::get_wallet_balance),
+        )
+        .route(
+            "/wallet/{walletid}/balances",
+            web::get().to(balances::get_wallet_balances),
+        )
+        .route(
+            "/wallet/{walletid}/events",
+            web::get().to(sse::wallet_events),
+        )
+        .route(
+            "/wallet/{walletid}/keypool",
+            web::get().to(keypool::get_keypool),
+        )
+        .route(
+            "/wallet/{walletid}/derivation",
+            web::get().to(derivation::get_derivation),
+        )
+        .route(
+            "/wallet/{walletid}/descriptors",
+            web::get().to(keys::get_descriptors),
+        )
+        .route("/wallet/{walletid}/seed", web::get().to(keys::get_seed))
+        .route(
+            "/wallet/{walletid}/keypool/refill",
+            web::post().to(keypool::refill_keypool),
+        )
+        .route(
+            "/wallet/{walletid}/encryption",
+            web::get().to(encryption::get_encryption),
+        )
+        .route(
+            "/wallet/{walletid}/encrypt",
+            web::post().to(encryption::encrypt_wallet),
+        )
+        .route(
+            "/wallet/{walletid}/unlock",
+            web::post().to(encryption::unlock_wallet),
+        )
+        .route(
+            "/wallet/{walletid}/lock",
+            web::post().to(encryption::lock_wallet),
+        )
+        .route(
+            "/wallet/{walletid}/passphrase",
+            web::post().to(encryption::change_passphrase),
+        )
+        .route(
+            "/wallet/{walletid}/address/{address}/label",
+            web::post().to(labels::set_label),
+        )
+        .route(
+            "/wallet/{walletid}/relabel",
+            web::post().to(labels::relabel),
+        )
+        .route(
+            "/wallet/{walletid}/activity",
+            web::get().to(activity::wallet_activity),
+        )
+        .route(
+            "/wallet/{walletid}/utxos",
+            web::get().to(utxos::list_wallet_utxos),
+        )
+        .route(
+            "/wallet/{walletid}/sweep-dust",
+            web::post().to(dust::sweep_dust),
+        )
+        .route(
+            "/wallet/{walletid}/invoices",
+            web::post().to(invoices::create_invoice),
+        )
+        .route(
+            "/wallet/{walletid}/invoices/{id}",
+            web::get().to(invoices::get_invoice),
+        )
+        .route(
+            "/wallet/{walletid}/confirmation-policy",
+            web::get().to(invoices::get_policy),
+        )
+        .route(
+            "/wallet/{walletid}/confirmation-policy",
+            web::post().to(invoices::set_policy),
+        )
+        .route(
+            "/wallet/{walletid}/reservations",
+            web::get().to(reservations::list_reservations),
+        )
+        .route(
+            "/wallet/{walletid}/reservations/{bucket}",
+            web::post().to(reservations::set_reservation),
+        )
+        .route(
+            "/wallet/{walletid}/transactions",
+            web::get().to(history::list_transactions),
+        )
+        .route(
+            "/wallet/{walletid}/labels",
+            web::get().to(labels::list_labels),
+        )
+        .route(
+            "/wallet/{walletid}/labels/{label}/addresses",
+            web::get().to(labels::list_label_addresses),
+        )
+        .route(
+            "/wallet/{walletid}/labels/{label}/rename",
+            web::post().to(labels::rename_label),
+        )
+        .route(
+            "/wallet/{walletid}/reorg-report",
+            web::get().to(reorg::reorg_report),
+        )
+        .route(
+            "/wallet/import",
+            web::post().to(wallet_export::import_wallet),
+        )
+        .route("/wallet/restore", web::post().to(backup::restore_wallet))
+        .route(
+            "/wallet/{walletid}/backup",
+            web::post().to(backup::backup_wallet),
+        )
+        .route(
+            "/wallet/{walletid}/export",
+            web::post().to(wallet_export::export_wallet),
+        )
+        .route(
+            "/wallet/{walletid}/import-descriptor",
+            web::post().to(watch_only::import_descriptor),
+        )
+        .route("/send", web::post().to(send_bitcoin))
+        .route(
+            "/send/with-opreturn",
+            web::post().to(op_return::send_with_op_return),
+        )
+        .route("/transfer", web::post().to(transfer::transfer))
+        .route("/outbox", web::get().to(outbox::list_outbox))
+        // Registered before /tx/{walletid}/{txid}, which would match it too
+        .route(
+            "/tx/{txid}/replacements",
+            web::get().to(replacements::get_replacements),
+        )
+        .route("/tx/{walletid}/{txid}", web::get().to(get_transaction))
+        .route(
+            "/tx/{walletid}/{txid}/raw",
+            web::get().to(raw_tx::get_raw_transaction),
+        )
+        .route(
+            "/tx/{walletid}/{txid}/message",
+            web::get().to(op_return::get_message),
+        )
+        .route(
+            "/tx/{walletid}/{txid}/bump",
+            web::post().to(fee_bump::bump_transaction),
+        )
+        .route("/decode", web::post().to(raw_tx::decode_transaction))
+        .route("/psbt/create", web::post().to(psbt::create))
+        .route("/psbt/sign", web::post().to(psbt::sign))
+        .route("/psbt/finalize", web::post().to(psbt::finalize_psbt))
+        .route("/psbt/broadcast", web::post().to(psbt::broadcast))
+        .route("/multisig", web::post().to(multisig::create_multisig))
+        .route("/multisig/{name}", web::get().to(multisig::get_multisig))
+        .route(
+            "/multisig/{name}/fund",
+            web::post().to(multisig::fund_multisig),
+        )
+        .route("/musig/session", web::post().to(musig::create_session))
+        .route("/musig/{id}", web::get().to(musig::get_session))
+        .route("/musig/{id}/nonces", web::post().to(musig::exchange_nonces))
+        .route("/musig/{id}/sign", web::post().to(musig::sign))
+        .route("/musig/{id}/aggregate", web::post().to(musig::aggregate))
+        .route(
+            "/playground/decode-address",
+            web::post().to(playground::decode_address),
+        )
+        .route("/scenario/run", web::post().to(scenario::run_scenario))
+        .route("/scenario/{id}", web::get().to(scenario::get_scenario))
+        .route("/scenario/{id}/choose", web::post().to(scenario::choose))
+        .route("/exercises/generate", web::post().to(exercises::generate))
+        .route("/exercises/{id}", web::get().to(exercises::get_exercise))
+        .route(
+            "/exercises/{id}/grade",
+            web::post().to(exercises::grade_exercise),
+        )
+        .route("/cohorts", web::post().to(cohort::create_cohort))
+        .route("/cohorts", web::get().to(cohort::list_cohorts))
+        .route("/cohorts/{name}", web::get().to(cohort::cohort_dashboard))
+        .route(
+            "/cohorts/{name}/students",
+            web::post().to(cohort::provision_students),
+        )
+        .route(
+            "/cohorts/{name}/students/{student}/faucet",
+            web::post().to(cohort::student_faucet),
+        )
+        .route("/channel", web::post().to(channel::open_channel))
+        .route("/channel/{id}", web::get().to(channel::get_channel))
+        .route("/channel/{id}/pay", web::post().to(channel::pay))
+        .route("/channel/{id}/close", web::post().to(channel::close))
+        .route("/watch-address", web::post().to(watch::watch_address))
+        .route("/watch-address", web::get().to(watch::list_watched))
+        .route(
+            "/watch-address/{address}",
+            web::get().to(watch::address_history),
+        )
+        .route("/watch/tx", web::post().to(tx_watch::watch_tx))
+        .route("/watch/tx", web::get().to(tx_watch::list_tx_watchers))
+        .route("/watch/tx/{id}", web::get().to(tx_watch::get_tx_watcher))
+        .route(
+            "/watch/tx/{id}",
+            web::delete().to(tx_watch::remove_tx_watcher),
+        )
+        .route("/fees", web::get().to(fees::get_fees))
+        .route(
+            "/block/height/{height}",
+            web::get().to(explorer::get_block_at_height),
+        )
+        .route("/block/{hash}", web::get().to(explorer::get_block))
+        .route(
+            "/block/{hash}/target",
+            web::get().to(explorer::get_block_target),
+        )
+        .route("/chain/tip", web::get().to(explorer::get_chain_tip))
+        .route(
+            "/chain/stale-blocks",
+            web::get().to(stale_blocks::get_stale_blocks),
+        )
+        .route(
+            "/chain/invalidate/{blockhash}",
+            web::post().to(reorg::invalidate_block),
+        )
+        .route(
+            "/chain/reconsider/{blockhash}",
+            web::post().to(reorg::reconsider_block),
+        )
+        .route("/chain/reorg", web::post().to(reorg::simulate_reorg))
+        .route(
+            "/network/propagation/{blockhash}",
+            web::get().to(propagation::get_propagation),
+        )
+        .route(
+            "/network/mempool-diff",
+            web::get().to(propagation::get_mempool_diff),
+        )
+        .route("/mempool", web::get().to(mempool::get_mempool))
+        .route("/mempool/info", web::get().to(mempool::get_mempool_info))
+        .route(
+            "/mempool/{walletid}/{txid}",
+            web::get().to(mempool::get_mempool_entry),
+        )
+        .route(
+            "/validate-descriptor",
+            web::post().to(descriptors::validate_descriptor),
+        )
+        .route("/chapter", web::post().to(complete_chapter))
+        .route("/recordings", web::post().to(recording::start_recording))
+        .route("/recordings", web::get().to(recording::list_recordings))
+        .route(
+            "/recordings/stop",
+            web::post().to(recording::stop_recording),
+        )
+        .route(
+            "/recordings/{name}",
+            web::get().to(recording::get_recording),
+        )
+        .route(
+            "/recordings/{name}/rewind",
+            web::post().to(recording::rewind),
+        )
+        .route("/replay", web::get().to(event_replay::replay_events))
+        .route("/replay/{id}", web::post().to(event_replay::control_replay))
+        .route(
+            "/replay/{name}/{tail:.*}",
+            web::route().to(recording::replay),
+        )
+        .route("/ws", web::get().to(ws::events_socket))
+        .route("/faucet", web::post().to(faucet::fund_wallet))
+        .route("/sandbox/faucet", web::post().to(sandbox::request_faucet))
+        .route("/admin/mocktime", web::post().to(admin::set_mocktime))
+        .route(
+            "/admin/mocktime/advance",
+            web::post().to(admin::advance_mocktime),
+        )
+        .route("/admin/reload-config", web::post().to(admin::reload_config))
+        .route("/admin/reset", web::post().to(reset::reset))
+        .route("/node/policy", web::get().to(node::get_policy))
+        .route("/hwi/devices", web::get().to(hwi::list_devices))
+        .route(
+            "/hwi/watch-only",
+            web::post().to(hwi::create_watch_only_wallet),
+        )
+        .route("/hwi/sign", web::post().to(hwi::sign_psbt))
+        .route("/signer/list", web::get().to(signer::list_signers))
+        .route("/signing/key", web::get().to(signing::get_signing_key))
+        .route("/signing/verify", web::post().to(signing::verify))
+        .route("/signer/wallet", web::post().to(signer::create_wallet))
+        .route(
+            "/wallet/{walletid}/signer/display-address",
+            web::post().to(signer::display_address),
+        )
+        .route(
+            "/wallet/{walletid}/signer/process-psbt",
+            web::post().to(signer::sign_psbt),
+        )
+        .route(
+            "/wallet/{walletid}/psbt-bumpfee/{txid}",
+            web::post().to(fee_bump::prepare_fee_bump),
+        );
+}
+
+// Marks responses of the unversioned paths as deprecated and links their /api/v1
+// successor
+pub(crate) async fn deprecated_alias(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let successor = format!("<{}{}>; rel=\"successor-version\"", V1_PREFIX, req.path());
+    let mut res = next.call(req).await?;
+    // Unknown paths have no successor either
+    if res.request().match_pattern().is_none() {
+        return Ok(res);
+    }
+    let headers = res.headers_mut();
+    headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, link);
+    }
+    Ok(res)
+}
+
+// The percent-decoded path the router matches, without the version prefix.
+// Access checks use it, since the raw path can spell the same route differently.
+pub(crate) fn route_path(http_req: &HttpRequest) -> &str {
+    unversioned(http_req.match_info().as_str())
+}
+
+// A request path as the unversioned alias spells it, so recordings replay either way
+pub(crate) fn unversioned(path: &str) -> &str {
+    match path.strip_prefix(V1_PREFIX) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
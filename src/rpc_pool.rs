@@ -0,0 +1,243 @@
+use crate::errors::{ApiError, Language};
+use crate::AppState;
+use actix_web::{web, HttpRequest};
+use bitcoincore_rpc::{Client, Error as RpcError};
+use log::error;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// A Client holds one HTTP connection and serializes the calls made through it, so each
+// wallet gets a few, and concurrent requests for the same wallet each get their own
+pub(crate) struct ClientPool {
+    clients: Vec<Client>,
+    // Checked out handles per client
+    in_use: Vec<AtomicUsize>,
+}
+
+impl ClientPool {
+    pub(crate) fn new(clients: Vec<Client>) -> Arc<Self> {
+        let in_use = clients.iter().map(|_| AtomicUsize::new(0)).collect();
+        Arc::new(Self { clients, in_use })
+    }
+
+    // Hands out the least busy client; once all are busy they are shared
+    pub(crate) fn get(self: &Arc<Self>) -> PooledClient {
+        let slot = self
+            .in_use
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, in_use)| in_use.load(Ordering::Relaxed))
+            .map_or(0, |(slot, _)| slot);
+        self.in_use[slot].fetch_add(1, Ordering::Relaxed);
+        PooledClient {
+            pool: Arc::clone(self),
+            slot,
+        }
+    }
+}
+
+// A client checked out of a pool. It owns its share of the pool, so it can be moved
+// into a blocking task, and is given back when dropped.
+pub(crate) struct PooledClient {
+    pool: Arc<ClientPool>,
+    slot: usize,
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.pool.clients[self.slot]
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        self.pool.in_use[self.slot].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// RPC calls block until bitcoind answers. Slow ones run on the blocking thread pool
+// instead, so they do not hold up the other requests of the same worker. The request's
+// span goes along, so the calls are still logged under its request id.
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let span = tracing::Span::current();
+    web::block(move || span.in_scope(f))
+        .await
+        .map_err(|e| ApiError::Internal(format!("RPC task failed: {e}")))
+}
+
+// Runs `f` with the app state on the blocking thread pool, for handlers that make
+// several RPCs in a row
+pub(crate) async fn with_state<T, F>(data: &web::Data<AppState>, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&AppState) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let data = data.clone();
+    blocking(move || f(&data)).await
+}
+
+// What code on the blocking thread pool needs of the request, which has to stay on
+// its worker: the prefix of the caller's wallets and the language of its errors
+#[derive(Clone)]
+pub(crate) struct Caller {
+    prefix: String,
+    language: Language,
+}
+
+impl Caller {
+    pub(crate) fn of(data: &AppState, http_req: &HttpRequest) -> Self {
+        Caller {
+            prefix: data.wallet_name(http_req, ""),
+            language: Language::from_request(http_req),
+        }
+    }
+
+    // See AppState::wallet_name
+    pub(crate) fn wallet_name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    // See ApiError::rpc
+    pub(crate) fn rpc(&self, error: RpcError) -> ApiError {
+        ApiError::Rpc {
+            error,
+            language: self.language,
+        }
+    }
+
+    // See channel::node_client
+    pub(crate) fn node_client(&self, data: &AppState) -> Result<Client, ApiError> {
+        data.config.create_node_client().map_err(|e| {
+            error!("Failed to create node RPC client: {}", e);
+            self.rpc(e)
+        })
+    }
+}
+
+// Runs `f` on the blocking thread pool for the caller of `http_req`, for handlers
+// whose RPCs go through helpers that report their own errors
+pub(crate) async fn for_caller<T, F>(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    f: F,
+) -> Result<T, ApiError>
+where
+    F: FnOnce(&AppState, &Caller) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    let caller = Caller::of(data, http_req);
+    with_state(data, move |data| f(data, &caller)).await?
+}
+
+// A wallet RPC on the hot paths. With the `async-rpc` feature it is awaited on the
+// async client and the pooled client only vouches that the wallet exists; otherwise
+// the pooled client makes it on the blocking thread pool.
+#[cfg(feature = "async-rpc")]
+pub(crate) async fn call<T>(
+    data: &AppState,
+    _client: PooledClient,
+    wallet: &str,
+    method: &'static str,
+    params: Vec<Value>,
+) -> Result<Result<T, RpcError>, ApiError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    Ok(data.rpc.call(Some(wallet), method, &params).await)
+}
+
+#[cfg(not(feature = "async-rpc"))]
+pub(crate) async fn call<T>(
+    _data: &AppState,
+    client: PooledClient,
+    _wallet: &str,
+    method: &'static str,
+    params: Vec<Value>,
+) -> Result<Result<T, RpcError>, ApiError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    use bitcoincore_rpc::RpcApi;
+    blocking(move || client.call(method, &params)).await
+}
+
+// Several wallet RPCs sent as one JSON-RPC batch, so they cost a single round trip.
+// The results come back in the order of the calls; the outer error is a failure of
+// the batch as a whole.
+#[cfg(feature = "async-rpc")]
+pub(crate) async fn batch(
+    data: &AppState,
+    _client: PooledClient,
+    wallet: &str,
+    calls: Vec<(&'static str, Vec<Value>)>,
+) -> Result<Result<Vec<Result<Value, RpcError>>, RpcError>, ApiError> {
+    Ok(data.rpc.batch(Some(wallet), &calls).await)
+}
+
+#[cfg(not(feature = "async-rpc"))]
+pub(crate) async fn batch(
+    _data: &AppState,
+    client: PooledClient,
+    _wallet: &str,
+    calls: Vec<(&'static str, Vec<Value>)>,
+) -> Result<Result<Vec<Result<Value, RpcError>>, RpcError>, ApiError> {
+    blocking(move || {
+        let jsonrpc = client.get_jsonrpc_client();
+        let params: Vec<Vec<_>> = calls
+            .iter()
+            .map(|(_, params)| params.iter().map(jsonrpc::arg).collect())
+            .collect();
+        let requests: Vec<_> = calls
+            .iter()
+            .zip(&params)
+            .map(|((method, _), params)| jsonrpc.build_request(method, params))
+            .collect();
+        let responses = jsonrpc.send_batch(&requests)?;
+        Ok(responses
+            .into_iter()
+            .map(|response| match response {
+                Some(response) => response.result().map_err(RpcError::JsonRpc),
+                None => Err(RpcError::ReturnedError(
+                    "no response to a call of the batch".into(),
+                )),
+            })
+            .collect())
+    })
+    .await
+}
+
+// A node RPC, see call
+#[cfg(feature = "async-rpc")]
+pub(crate) async fn call_node<T>(
+    data: &AppState,
+    method: &'static str,
+    params: Vec<Value>,
+) -> Result<Result<T, RpcError>, ApiError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    Ok(data.rpc.call(None, method, &params).await)
+}
+
+#[cfg(not(feature = "async-rpc"))]
+pub(crate) async fn call_node<T>(
+    data: &AppState,
+    method: &'static str,
+    params: Vec<Value>,
+) -> Result<Result<T, RpcError>, ApiError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    use bitcoincore_rpc::RpcApi;
+    let client = data.config.create_node_client();
+    blocking(move || client?.call(method, &params)).await
+}
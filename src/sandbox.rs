@@ -0,0 +1,354 @@
+use crate::errors::ApiError;
+use crate::{faucet, mining, rpc_pool, AppState};
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::{Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const SESSION_COOKIE: &str = "cct_session";
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub(crate) struct SandboxConfig {
+    pub(crate) idle_timeout: Duration,
+}
+
+impl SandboxConfig {
+    // Sandbox mode is opt-in via sandbox_mode=true
+    pub(crate) fn from_env() -> Result<Option<Self>, RpcError> {
+        if !matches!(env::var("sandbox_mode").as_deref(), Ok("true")) {
+            return Ok(None);
+        }
+        let idle_timeout_secs = match env::var("sandbox_idle_timeout_secs") {
+            Ok(value) => value.parse().map_err(|_| {
+                RpcError::ReturnedError("sandbox_idle_timeout_secs must be a number".into())
+            })?,
+            Err(_) => 30 * 60,
+        };
+        Ok(Some(Self {
+            idle_timeout: Duration::from_secs(idle_timeout_secs),
+        }))
+    }
+}
+
+// Session id attached to every request by the session middleware
+#[derive(Clone)]
+struct SandboxSession(String);
+
+pub(crate) struct Sandbox {
+    config: SandboxConfig,
+}
+
+impl Sandbox {
+    pub(crate) fn new(config: SandboxConfig) -> Self {
+        Self { config }
+    }
+
+    // Wallet names are namespaced per session so visitors never see each other's wallets
+    pub(crate) fn scoped_name(&self, http_req: &HttpRequest, name: &str) -> String {
+        format!("sb-{}-{}", session_id(http_req), name)
+    }
+
+    // Counts `amount` against the session's faucet allowance, which can be
+    // changed by a config reload
+    pub(crate) async fn reserve_allowance(
+        &self,
+        data: &AppState,
+        http_req: &HttpRequest,
+        amount: u64,
+    ) -> Result<(), ApiError> {
+        let delta = match i64::try_from(amount) {
+            Ok(delta) if delta > 0 => delta,
+            _ => {
+                return Err(ApiError::InvalidRequest(format!(
+                    "amount must be between 1 and {} sat",
+                    i64::MAX
+                )))
+            }
+        };
+        let key = faucet_key(&session_id(http_req));
+        let used = data
+            .store
+            .increment_by(&key, delta, self.config.idle_timeout)
+            .await
+            .map_err(|e| {
+                error!("Failed to track faucet allowance: {}", e);
+                ApiError::Storage(e)
+            })?;
+        let allowance = data.settings().sandbox_faucet_allowance_sat;
+        if u64::try_from(used).unwrap_or(0) > allowance {
+            self.release_allowance(data, http_req, amount).await;
+            warn!("Faucet allowance exceeded for sandbox session");
+            return Err(ApiError::TooManyRequests(format!(
+                "Faucet allowance of {} sat for this session exceeded",
+                allowance
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn release_allowance(
+        &self,
+        data: &AppState,
+        http_req: &HttpRequest,
+        amount: u64,
+    ) {
+        self.release_session_allowance(data, &session_id(http_req), amount)
+            .await
+    }
+
+    // For work finished outside the request, e.g. by the outbox worker
+    pub(crate) async fn release_session_allowance(
+        &self,
+        data: &AppState,
+        session: &str,
+        amount: u64,
+    ) {
+        // Such an amount was never reserved
+        let Ok(delta) = i64::try_from(amount) else {
+            return;
+        };
+        let key = faucet_key(session);
+        if let Err(e) = data
+            .store
+            .increment_by(&key, -delta, self.config.idle_timeout)
+            .await
+        {
+            error!("Failed to refund faucet allowance: {}", e);
+        }
+    }
+}
+
+// Session a scoped wallet name belongs to
+pub(crate) fn session_of(wallet: &str) -> Option<&str> {
+    let session = wallet.strip_prefix("sb-")?.get(..32)?;
+    is_valid_session_id(session).then_some(session)
+}
+
+fn session_id(http_req: &HttpRequest) -> String {
+    http_req
+        .extensions()
+        .get::<SandboxSession>()
+        .map(|session| session.0.clone())
+        .unwrap_or_else(|| "anonymous".into())
+}
+
+fn session_key(session: &str) -> String {
+    format!("sandbox:session:{session}")
+}
+
+// Held by the replica that unloads an expired session's wallets
+fn collect_key(session: &str) -> String {
+    format!("sandbox:collect:{session}")
+}
+
+fn faucet_key(session: &str) -> String {
+    format!("sandbox:faucet:{session}")
+}
+
+// Ids end up in wallet names, so anything but our own uuid format is replaced
+fn is_valid_session_id(id: &str) -> bool {
+    id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Assigns an anonymous session cookie and keeps the session alive while it is in use
+pub(crate) async fn session_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let existing = req
+        .cookie(SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|id| is_valid_session_id(id));
+    let is_new = existing.is_none();
+    let session = existing.unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+    if let Some(data) = req.app_data::<web::Data<AppState>>() {
+        if let Some(sandbox) = &data.sandbox {
+            let ttl = sandbox.config.idle_timeout;
+            if let Err(e) = data
+                .store
+                .set(&session_key(&session), "active", Some(ttl))
+                .await
+            {
+                error!("Failed to refresh sandbox session: {}", e);
+            }
+            // The faucet allowance lasts as long as the session, not from its first use
+            if let Err(e) = data.store.expire(&faucet_key(&session), ttl).await {
+                error!("Failed to refresh sandbox faucet allowance: {}", e);
+            }
+        }
+    }
+    req.extensions_mut().insert(SandboxSession(session.clone()));
+
+    let mut res = next.call(req).await?;
+    if is_new {
+        // Lax keeps the cookie off cross-site POSTs, so other sites cannot spend
+        // the session's allowance
+        let cookie = Cookie::build(SESSION_COOKIE, session)
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .finish();
+        res.response_mut().add_cookie(&cookie)?;
+    }
+    Ok(res)
+}
+
+// Session wallets the node has loaded or this replica has a client for, by session.
+// Any replica may have created them, so they are found by their scoped names.
+async fn session_wallets(
+    data: &web::Data<AppState>,
+) -> Result<HashMap<String, HashSet<String>>, String> {
+    let loaded = rpc_pool::with_state(data, |data| {
+        let node = data
+            .config
+            .create_node_client()
+            .map_err(|e| format!("cannot create a node client: {e}"))?;
+        node.list_wallets().map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    let local = data.clients.iter().map(|entry| entry.key().clone());
+    let mut sessions: HashMap<String, HashSet<String>> = HashMap::new();
+    for wallet in loaded.into_iter().chain(local) {
+        if let Some(session) = session_of(&wallet) {
+            sessions
+                .entry(session.to_string())
+                .or_default()
+                .insert(wallet);
+        }
+    }
+    Ok(sessions)
+}
+
+// Unloads the wallets of sessions that have been idle for longer than the timeout
+pub(crate) async fn collect_idle_sessions(data: web::Data<AppState>) {
+    if data.sandbox.is_none() {
+        return;
+    }
+    loop {
+        actix_rt::time::sleep(GC_INTERVAL).await;
+
+        let sessions = match session_wallets(&data).await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                warn!("Failed to list sandbox wallets: {}", e);
+                continue;
+            }
+        };
+        for (session, wallets) in sessions {
+            match data.store.get(&session_key(&session)).await {
+                Ok(Some(_)) => continue,
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to check sandbox session: {}", e);
+                    continue;
+                }
+            }
+            for wallet in &wallets {
+                data.clients.remove(wallet);
+                mining::forget_miner(&data, wallet);
+            }
+            // Every replica drops its clients, one of them unloads the wallets
+            match data
+                .store
+                .set_if_absent(&collect_key(&session), "collecting", Some(GC_INTERVAL))
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("Failed to claim expired sandbox session: {}", e);
+                    continue;
+                }
+            }
+            info!(
+                "Sandbox session expired, unloading {} wallet(s)",
+                wallets.len()
+            );
+            for wallet in wallets {
+                let name = wallet.clone();
+                let unloaded = rpc_pool::with_state(&data, move |data| {
+                    let node = data.config.create_node_client()?;
+                    node.unload_wallet(Some(&name))
+                });
+                match unloaded.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("Failed to unload sandbox wallet '{}': {}", wallet, e),
+                    Err(e) => warn!("Failed to unload sandbox wallet '{}': {}", wallet, e),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SandboxFaucetRequest {
+    wallet_name: String,
+    amount: u64,
+}
+
+// Sends coins from the faucet to a session wallet, within the session's allowance
+#[utoipa::path(
+    post,
+    path = "/sandbox/faucet",
+    tag = "sandbox",
+    responses(
+        (status = 200, body = String),
+    )
+)]
+pub(crate) async fn request_faucet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<SandboxFaucetRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /sandbox/faucet - wallet='{}', amount_sat={}",
+        req.wallet_name, req.amount
+    );
+    let Some(sandbox) = &data.sandbox else {
+        return Err(ApiError::NotFound("Sandbox mode is disabled".into()));
+    };
+    let wallet = sandbox.scoped_name(&http_req, &req.wallet_name);
+    let address = match data.wallet_client(&wallet) {
+        Some(client) => match faucet::new_address(&data, client).await? {
+            Ok(address) => address,
+            Err(e) => {
+                error!(
+                    "Failed to get faucet address for wallet '{}': {}",
+                    wallet, e
+                );
+                return Err(ApiError::Internal(e));
+            }
+        },
+        None => {
+            warn!("POST /sandbox/faucet - wallet '{}' not found", wallet);
+            return Err(ApiError::WalletNotFound(req.wallet_name.clone()));
+        }
+    };
+
+    sandbox
+        .reserve_allowance(&data, &http_req, req.amount)
+        .await?;
+    match faucet::fund_blocking(&data, address, Amount::from_sat(req.amount)).await? {
+        Ok(funding) => Ok(HttpResponse::Ok().json(funding.txid.to_string())),
+        Err(e) => {
+            sandbox
+                .release_allowance(&data, &http_req, req.amount)
+                .await;
+            error!("Faucet failed to fund wallet '{}': {}", wallet, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
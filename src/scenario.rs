@@ -0,0 +1,1395 @@
+use crate::change::ChangeOptions;
+use crate::db::unix_now;
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::fees::FeeOptions;
+use crate::reservations::ReservationOptions;
+use crate::rpc_pool::{self, Caller};
+use crate::{
+    auth, exercises, fund_new_wallet, get_wallet, mining, multisig, psbt, send_payment, AppState,
+    SendBitcoinRequest,
+};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash, Txid};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::RpcApi;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const MAX_STEPS: usize = 100;
+const MAX_BLOCKS_PER_STEP: u64 = 1000;
+const MAX_WAIT_CONFIRMATIONS: u32 = 100;
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(600);
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_CHOICE_TIMEOUT: Duration = Duration::from_secs(3600);
+const CHOICE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Longest a wait or a choice may be given; runs are not meant to idle for days
+const MAX_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+// Runs one client may have going at the same time
+const MAX_RUNNING_PER_CLIENT: usize = 4;
+// Finished runs are dropped this long after they ended
+const FINISHED_RUN_TTL: Duration = Duration::from_secs(60 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const YAML_CONTENT_TYPES: [&str; 3] = ["application/yaml", "application/x-yaml", "text/yaml"];
+// Jumps allow loops, so a run stops after this many steps
+const MAX_EXECUTED_STEPS: usize = 1000;
+
+// One line of a storyline; wallet names are as in the rest of the API
+#[derive(Deserialize, Clone, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum Step {
+    // Creates or loads the wallet, optionally with a starting balance from the faucet
+    CreateWallet {
+        wallet: String,
+        fund_sat: Option<u64>,
+    },
+    Mine {
+        wallet: String,
+        blocks: u64,
+    },
+    // `to` is a wallet, which gets a new address, or an address. The wallet picks the
+    // fee unless `fee_rate` (sat/vB) is given.
+    Send {
+        from: String,
+        to: String,
+        amount_sat: Option<u64>,
+        amount_btc: Option<f64>,
+        fee_rate: Option<f64>,
+        replaceable: Option<bool>,
+    },
+    // Creates a watch-only multisig wallet from one key of each wallet, like POST /multisig
+    CreateMultisig {
+        wallet: String,
+        required: u32,
+        wallets: Vec<String>,
+    },
+    // Has `wallet` build a PSBT paying `to` and the `signers` sign it one after
+    // another; nothing is broadcast
+    CreatePsbt {
+        wallet: String,
+        to: String,
+        amount_sat: u64,
+        #[serde(default)]
+        signers: Vec<String>,
+    },
+    // Waits for a payment, by default the scenario's last one. With `miner` set the
+    // missing blocks are mined to that wallet, otherwise someone else has to mine them.
+    WaitConfirmations {
+        confirmations: u32,
+        txid: Option<String>,
+        // Wallet the txid belongs to; needed with `txid`
+        wallet: Option<String>,
+        miner: Option<String>,
+        timeout_secs: Option<u64>,
+    },
+    Chapter {
+        chapter: String,
+        wallet: Option<String>,
+    },
+    // Pauses until POST /scenario/{id}/choose picks one of the options, or the
+    // `default` is taken after `timeout_secs`
+    Choice {
+        prompt: String,
+        options: Vec<ChoiceOption>,
+        default: Option<String>,
+        timeout_secs: Option<u64>,
+    },
+    // Jumps to `goto` when the condition holds, otherwise to `else_goto` or on
+    Branch {
+        condition: Condition,
+        goto: String,
+        else_goto: Option<String>,
+    },
+    Goto {
+        goto: String,
+    },
+    // Finishes the run without the remaining steps
+    End,
+    // Remembers the chain tip and the last payment, for choices that rewind
+    Checkpoint {
+        checkpoint: String,
+    },
+}
+
+// A step with an optional id for jumps to aim at
+#[derive(Deserialize, Clone, ToSchema)]
+pub(crate) struct ScenarioStep {
+    id: Option<String>,
+    #[serde(flatten)]
+    step: Step,
+}
+
+impl From<Step> for ScenarioStep {
+    fn from(step: Step) -> Self {
+        ScenarioStep { id: None, step }
+    }
+}
+
+#[derive(Deserialize, Clone, ToSchema)]
+pub(crate) struct ChoiceOption {
+    id: String,
+    text: String,
+    // The step to continue at, by default the next one
+    goto: Option<String>,
+    // A checkpoint to go back to; blocks mined since are invalidated
+    rewind: Option<String>,
+}
+
+#[derive(Deserialize, Clone, ToSchema)]
+#[serde(tag = "check", rename_all = "snake_case")]
+pub(crate) enum Condition {
+    BalanceAtLeast { wallet: String, amount_sat: u64 },
+    HeightAtLeast { height: u64 },
+    // The scenario's last payment has at least this many confirmations
+    Confirmed { confirmations: u32 },
+}
+
+impl Condition {
+    fn describe(&self) -> String {
+        match self {
+            Condition::BalanceAtLeast { wallet, amount_sat } => {
+                format!("{wallet} has at least {amount_sat} sat")
+            }
+            Condition::HeightAtLeast { height } => format!("the chain reached height {height}"),
+            Condition::Confirmed { confirmations } => {
+                format!("the last payment has {confirmations} confirmation(s)")
+            }
+        }
+    }
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::CreateWallet {
+                wallet,
+                fund_sat: Some(sats),
+            } => format!("create wallet {wallet} with {sats} sat"),
+            Step::CreateWallet { wallet, .. } => format!("create wallet {wallet}"),
+            Step::Mine { wallet, blocks } => format!("mine {blocks} block(s) to {wallet}"),
+            Step::Send {
+                from,
+                to,
+                amount_sat,
+                amount_btc,
+                fee_rate,
+                ..
+            } => {
+                let amount = match (amount_sat, amount_btc) {
+                    (Some(sats), _) => format!("{sats} sat"),
+                    (_, btc) => format!("{} BTC", btc.unwrap_or_default()),
+                };
+                match fee_rate {
+                    Some(rate) => format!("send {amount} {from} -> {to} at {rate} sat/vB"),
+                    None => format!("send {amount} {from} -> {to}"),
+                }
+            }
+            Step::CreateMultisig {
+                wallet,
+                required,
+                wallets,
+            } => format!(
+                "create {required}-of-{} multisig {wallet} of {}",
+                wallets.len(),
+                wallets.join(", ")
+            ),
+            Step::CreatePsbt {
+                wallet,
+                to,
+                amount_sat,
+                signers,
+            } if signers.is_empty() => {
+                format!("create PSBT paying {amount_sat} sat {wallet} -> {to}")
+            }
+            Step::CreatePsbt {
+                wallet,
+                to,
+                amount_sat,
+                signers,
+            } => format!(
+                "create PSBT paying {amount_sat} sat {wallet} -> {to}, signed by {}",
+                signers.join(", ")
+            ),
+            Step::WaitConfirmations {
+                confirmations,
+                txid,
+                ..
+            } => format!(
+                "wait for {confirmations} confirmation(s) of {}",
+                txid.as_deref().unwrap_or("the last payment")
+            ),
+            Step::Chapter { chapter, .. } => format!("complete chapter {chapter}"),
+            Step::Choice { prompt, .. } => format!("choose: {prompt}"),
+            Step::Branch {
+                condition, goto, ..
+            } => format!("if {} go to {goto}", condition.describe()),
+            Step::Goto { goto } => format!("go to {goto}"),
+            Step::End => "end the story".into(),
+            Step::Checkpoint { checkpoint } => format!("checkpoint {checkpoint}"),
+        }
+    }
+
+    // Step ids the step may jump to
+    fn targets(&self) -> Vec<&str> {
+        match self {
+            Step::Choice { options, .. } => options
+                .iter()
+                .filter_map(|option| option.goto.as_deref())
+                .collect(),
+            Step::Branch {
+                goto, else_goto, ..
+            } => [Some(goto.as_str()), else_goto.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Step::Goto { goto } => vec![goto.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Step::Mine { blocks, .. } if !(1..=MAX_BLOCKS_PER_STEP).contains(blocks) => Err(
+                format!("blocks must be between 1 and {MAX_BLOCKS_PER_STEP}"),
+            ),
+            Step::Send {
+                fee_rate: Some(rate),
+                ..
+            } if !rate.is_finite() || *rate <= 0.0 => {
+                Err("fee_rate must be a positive sat/vB rate".into())
+            }
+            Step::Send {
+                amount_sat,
+                amount_btc,
+                ..
+            } => match (amount_sat, amount_btc) {
+                (Some(0), None) => Err("amount_sat must be positive".into()),
+                (Some(_), None) => Ok(()),
+                (None, Some(btc)) if *btc > 0.0 && Amount::from_btc(*btc).is_ok() => Ok(()),
+                (None, Some(_)) => Err("amount_btc must be a positive amount".into()),
+                _ => Err("give either amount_sat or amount_btc".into()),
+            },
+            Step::CreatePsbt { amount_sat: 0, .. } => Err("amount_sat must be positive".into()),
+            Step::WaitConfirmations {
+                timeout_secs: Some(secs),
+                ..
+            }
+            | Step::Choice {
+                timeout_secs: Some(secs),
+                ..
+            } if *secs > MAX_TIMEOUT_SECS => {
+                Err(format!("timeout_secs must be at most {MAX_TIMEOUT_SECS}"))
+            }
+            Step::WaitConfirmations {
+                confirmations,
+                txid,
+                wallet,
+                ..
+            } => {
+                if !(1..=MAX_WAIT_CONFIRMATIONS).contains(confirmations) {
+                    Err(format!(
+                        "confirmations must be between 1 and {MAX_WAIT_CONFIRMATIONS}"
+                    ))
+                } else if txid.is_some() && wallet.is_none() {
+                    Err("a txid needs the wallet it belongs to".into())
+                } else {
+                    Ok(())
+                }
+            }
+            Step::Choice {
+                options, default, ..
+            } => {
+                let mut ids = HashSet::new();
+                if options.is_empty() {
+                    Err("a choice needs options".into())
+                } else if let Some(option) = options.iter().find(|option| !ids.insert(&option.id)) {
+                    Err(format!("option '{}' is given twice", option.id))
+                } else if let Some(option) = options
+                    .iter()
+                    .find(|option| option.goto.is_some() && option.rewind.is_some())
+                {
+                    Err(format!("option '{}' has both goto and rewind", option.id))
+                } else if default
+                    .as_ref()
+                    .is_some_and(|default| !ids.contains(default))
+                {
+                    Err("the default is not one of the options".into())
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Where every step id is, after checking that the jumps and rewinds lead somewhere
+fn positions(steps: &[ScenarioStep]) -> Result<HashMap<String, usize>, String> {
+    let mut positions = HashMap::new();
+    for (index, step) in steps.iter().enumerate() {
+        if let Some(id) = &step.id {
+            if positions.insert(id.clone(), index).is_some() {
+                return Err(format!("Step {index}: id '{id}' is used twice"));
+            }
+        }
+    }
+    let checkpoints: HashSet<&str> = steps
+        .iter()
+        .filter_map(|step| match &step.step {
+            Step::Checkpoint { checkpoint } => Some(checkpoint.as_str()),
+            _ => None,
+        })
+        .collect();
+    for (index, step) in steps.iter().enumerate() {
+        if let Some(target) = step
+            .step
+            .targets()
+            .into_iter()
+            .find(|target| !positions.contains_key(*target))
+        {
+            return Err(format!("Step {index}: no step has id '{target}'"));
+        }
+        if let Step::Choice { options, .. } = &step.step {
+            if let Some(rewind) = options
+                .iter()
+                .filter_map(|option| option.rewind.as_deref())
+                .find(|rewind| !checkpoints.contains(rewind))
+            {
+                return Err(format!("Step {index}: no checkpoint is called '{rewind}'"));
+            }
+        }
+    }
+    Ok(positions)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct RunScenarioRequest {
+    name: Option<String>,
+    steps: Vec<ScenarioStep>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ChooseRequest {
+    choice: String,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[schema(as = ScenarioStatus)]
+enum Status {
+    Pending,
+    Running,
+    // Paused at a choice step, see `pending_choice`
+    WaitingForChoice,
+    Done,
+    Failed,
+    // Not run because an earlier step failed, or a jump went past it
+    Skipped,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+struct StepProgress {
+    index: usize,
+    description: String,
+    status: Status,
+    // What the step produced, e.g. a txid or block hashes
+    result: Option<Value>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+struct ChoiceView {
+    id: String,
+    text: String,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+struct PendingChoice {
+    step: usize,
+    prompt: String,
+    options: Vec<ChoiceView>,
+    default: Option<String>,
+    expires_at: i64,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+struct ChoiceRecord {
+    step: usize,
+    choice: String,
+    // Taken because nobody chose in time
+    by_default: bool,
+    chosen_at: i64,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+struct CheckpointRecord {
+    name: String,
+    step: usize,
+    height: u64,
+    block_hash: String,
+    created_at: i64,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub(crate) struct ScenarioRun {
+    id: String,
+    name: Option<String>,
+    status: Status,
+    // Index of the step being run
+    current_step: Option<usize>,
+    steps: Vec<StepProgress>,
+    // Step indexes in the order they ran
+    path: Vec<usize>,
+    pending_choice: Option<PendingChoice>,
+    choices: Vec<ChoiceRecord>,
+    checkpoints: Vec<CheckpointRecord>,
+    started_at: i64,
+    finished_at: Option<i64>,
+    // Set by POST /scenario/{id}/choose, taken by the run
+    #[serde(skip)]
+    chosen: Option<String>,
+    // Wallet prefix of the caller that started the run, who alone sees and steers it
+    #[serde(skip)]
+    owner: String,
+    // Who counts against MAX_RUNNING_PER_CLIENT: the owner, or its IP when every
+    // caller shares the empty prefix
+    #[serde(skip)]
+    client: String,
+}
+
+// Where the run goes after a step
+enum Flow {
+    Next,
+    Goto(usize),
+    End,
+}
+
+struct SavedCheckpoint {
+    step: usize,
+    height: u64,
+    block_hash: BlockHash,
+    last_payment: Option<LastPayment>,
+}
+
+// What the steps of one run share
+struct RunState {
+    positions: HashMap<String, usize>,
+    last_payment: Option<LastPayment>,
+    checkpoints: HashMap<String, SavedCheckpoint>,
+}
+
+// The scenario's last payment, which wait steps default to
+#[derive(Clone)]
+struct LastPayment {
+    wallet: String,
+    txid: Txid,
+}
+
+fn update(data: &AppState, id: &str, change: impl FnOnce(&mut ScenarioRun)) {
+    if let Some(mut run) = data.scenarios.get_mut(id) {
+        change(&mut run);
+    }
+}
+
+fn new_address(data: &AppState, wallet: &str, label: &str) -> Result<Address, ApiError> {
+    let Some(client) = data.wallet_client(wallet) else {
+        return Err(ApiError::WalletNotFound(wallet.to_string()));
+    };
+    client
+        .get_new_address(Some(label), Some(AddressType::Bech32))
+        .map_err(|e| e.to_string())
+        .and_then(|address| {
+            address
+                .require_network(data.config.network)
+                .map_err(|e| e.to_string())
+        })
+        .map_err(ApiError::Internal)
+}
+
+fn mine(data: &AppState, caller: &Caller, wallet: &str, blocks: u64) -> Result<Value, ApiError> {
+    data.config.ensure_regtest("Mining")?;
+    let wallet_name = caller.wallet_name(wallet);
+    let address = new_address(data, &wallet_name, "scenario")?;
+    let block_hashes = data
+        .wallet_client(&wallet_name)
+        .ok_or_else(|| ApiError::WalletNotFound(wallet.to_string()))?
+        .generate_to_address(blocks, &address)
+        .map_err(|e| caller.rpc(e))?;
+    mining::record_blocks(
+        data,
+        &wallet_name,
+        &address,
+        &block_hashes,
+        mining::SOURCE_MANUAL,
+    );
+    Ok(json!({ "address": address.to_string(), "block_hashes": block_hashes }))
+}
+
+async fn create_wallet(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    wallet: &str,
+    fund_sat: Option<u64>,
+) -> Result<Value, ApiError> {
+    let wallet_name = data.wallet_name(http_req, wallet);
+    let client = data
+        .config
+        .create_client(&wallet_name)
+        .map_err(|e| ApiError::rpc(http_req, e))?;
+    let (name, network) = (wallet_name.clone(), data.config.network);
+    let (client, loaded, funding_address) = rpc_pool::blocking(move || {
+        let loaded = get_wallet(&client, &name, false);
+        let funding_address = (loaded.is_ok() && fund_sat.is_some()).then(|| {
+            client
+                .get_new_address(Some("starting balance"), Some(AddressType::Bech32))
+                .map_err(|e| e.to_string())
+                .and_then(|addr| addr.require_network(network).map_err(|e| e.to_string()))
+        });
+        (client, loaded, funding_address)
+    })
+    .await?;
+    loaded.map_err(|e| ApiError::rpc(http_req, e))?;
+    data.events.publish(Event::WalletReady {
+        wallet: wallet_name.clone(),
+    });
+    let funding = match fund_sat.zip(funding_address) {
+        Some((sats, address)) => Some(fund_new_wallet(data, http_req, address, sats).await),
+        None => None,
+    };
+    // Registered even if funding failed, like POST /wallet does
+    data.register_wallet(wallet_name, client);
+    let funding_txid = funding.transpose()?.map(|txid| txid.to_string());
+    Ok(json!({ "wallet": wallet, "funding_txid": funding_txid }))
+}
+
+// A new address of the wallet `to`, the address of a multisig wallet, or `to` itself
+fn receive_address(data: &AppState, caller: &Caller, to: &str) -> Result<String, ApiError> {
+    let to_wallet = caller.wallet_name(to);
+    if data.wallet_client(&to_wallet).is_none() {
+        return Ok(to.to_string());
+    }
+    // Watch-only multisig wallets cannot hand out new addresses
+    match data.db.multisig(&to_wallet)? {
+        Some(multisig) => Ok(multisig.address),
+        None => Ok(new_address(data, &to_wallet, "scenario")?.to_string()),
+    }
+}
+
+async fn send(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    from: &str,
+    to: &str,
+    amount: Amount,
+    fees: FeeOptions,
+    name: Option<&str>,
+) -> Result<(LastPayment, Value), ApiError> {
+    let to = to.to_string();
+    let to_address = rpc_pool::for_caller(data, http_req, move |data, caller| {
+        receive_address(data, caller, &to)
+    })
+    .await?;
+    let from_wallet = data.wallet_name(http_req, from);
+    let req = SendBitcoinRequest {
+        from_wallet: from.to_string(),
+        to_address: to_address.clone(),
+        amount: amount.to_sat(),
+        message: name.map(|name| format!("scenario {name}")),
+        change: ChangeOptions::default(),
+        fees,
+        reservations: ReservationOptions::default(),
+    };
+    let sent = send_payment(data, http_req, &from_wallet, &req).await?;
+    Ok((
+        LastPayment {
+            wallet: from_wallet,
+            txid: sent.txid,
+        },
+        json!({ "txid": sent.txid.to_string(), "to_address": to_address, "amount_sat": amount.to_sat() }),
+    ))
+}
+
+async fn create_multisig(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    wallet: &str,
+    required: u32,
+    wallets: &[String],
+) -> Result<Value, ApiError> {
+    let multisig = multisig::create(data, http_req, wallet, required, wallets).await?;
+    Ok(json!({ "wallet": wallet, "address": multisig.address, "descriptor": multisig.descriptor }))
+}
+
+fn create_psbt(
+    data: &AppState,
+    caller: &Caller,
+    wallet: &str,
+    to: &str,
+    amount_sat: u64,
+    signers: &[String],
+) -> Result<Value, ApiError> {
+    let to_address = receive_address(data, caller, to)?;
+    let created = psbt::create_payment(data, caller, wallet, &to_address, amount_sat)?;
+    let mut psbt = created.psbt;
+    let mut complete = false;
+    for signer in signers {
+        let client = data
+            .wallet_client(&caller.wallet_name(signer))
+            .ok_or_else(|| ApiError::WalletNotFound(signer.clone()))?;
+        let signed = psbt::sign_psbt(&client, &psbt).map_err(|e| caller.rpc(e))?;
+        psbt = signed.psbt;
+        complete = signed.complete;
+    }
+    Ok(json!({
+        "psbt": psbt,
+        "to_address": to_address,
+        "amount_sat": amount_sat,
+        "fee_sat": created.fee_sat,
+        "signed_by": signers,
+        "complete": complete,
+    }))
+}
+
+fn confirmations(data: &AppState, caller: &Caller, payment: &LastPayment) -> Result<i32, ApiError> {
+    Ok(data
+        .wallet_client(&payment.wallet)
+        .ok_or_else(|| ApiError::WalletNotFound(payment.wallet.clone()))?
+        .get_transaction(&payment.txid, None)
+        .map_err(|e| caller.rpc(e))?
+        .info
+        .confirmations)
+}
+
+async fn wait_confirmations(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    payment: &LastPayment,
+    confirmations: u32,
+    miner: Option<&str>,
+    timeout: Duration,
+) -> Result<Value, ApiError> {
+    let started = Instant::now();
+    let mut mined = 0;
+    loop {
+        let polled = payment.clone();
+        let current = rpc_pool::for_caller(data, http_req, move |data, caller| {
+            self::confirmations(data, caller, &polled)
+        })
+        .await?;
+        if current < 0 {
+            return Err(ApiError::Conflict(format!(
+                "Transaction {} conflicts with a confirmed one and will never confirm",
+                payment.txid
+            )));
+        }
+        if current as u32 >= confirmations {
+            return Ok(json!({
+                "txid": payment.txid.to_string(),
+                "confirmations": current,
+                "blocks_mined": mined,
+            }));
+        }
+        match miner {
+            Some(miner) => {
+                let missing = u64::from(confirmations - current as u32);
+                let miner = miner.to_string();
+                rpc_pool::for_caller(data, http_req, move |data, caller| {
+                    mine(data, caller, &miner, missing)
+                })
+                .await?;
+                mined += missing;
+            }
+            None if started.elapsed() >= timeout => {
+                return Err(ApiError::Conflict(format!(
+                    "Transaction {} has {} of {} confirmation(s) after {} s",
+                    payment.txid,
+                    current,
+                    confirmations,
+                    timeout.as_secs()
+                )));
+            }
+            None => actix_rt::time::sleep(WAIT_POLL_INTERVAL).await,
+        }
+    }
+}
+
+fn check(
+    data: &AppState,
+    caller: &Caller,
+    condition: &Condition,
+    last_payment: Option<&LastPayment>,
+) -> Result<bool, ApiError> {
+    match condition {
+        Condition::BalanceAtLeast { wallet, amount_sat } => {
+            let balance = data
+                .wallet_client(&caller.wallet_name(wallet))
+                .ok_or_else(|| ApiError::WalletNotFound(wallet.clone()))?
+                .get_balance(None, None)
+                .map_err(|e| caller.rpc(e))?;
+            Ok(balance.to_sat() >= *amount_sat)
+        }
+        Condition::HeightAtLeast { height } => {
+            let tip = caller
+                .node_client(data)?
+                .get_block_count()
+                .map_err(|e| caller.rpc(e))?;
+            Ok(tip >= *height)
+        }
+        Condition::Confirmed { confirmations } => {
+            let payment = last_payment
+                .ok_or_else(|| ApiError::InvalidRequest("No payment to check yet".into()))?;
+            let current = self::confirmations(data, caller, payment)?;
+            Ok(current >= 0 && current as u32 >= *confirmations)
+        }
+    }
+}
+
+async fn checkpoint(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    id: &str,
+    index: usize,
+    name: &str,
+    state: &mut RunState,
+) -> Result<Value, ApiError> {
+    let (height, block_hash) = rpc_pool::for_caller(data, http_req, |data, caller| {
+        let node = caller.node_client(data)?;
+        let height = node.get_block_count().map_err(|e| caller.rpc(e))?;
+        let block_hash = node.get_block_hash(height).map_err(|e| caller.rpc(e))?;
+        Ok((height, block_hash))
+    })
+    .await?;
+    state.checkpoints.insert(
+        name.to_string(),
+        SavedCheckpoint {
+            step: index,
+            height,
+            block_hash,
+            last_payment: state.last_payment.clone(),
+        },
+    );
+    update(data, id, |run| {
+        run.checkpoints.retain(|checkpoint| checkpoint.name != name);
+        run.checkpoints.push(CheckpointRecord {
+            name: name.to_string(),
+            step: index,
+            height,
+            block_hash: block_hash.to_string(),
+            created_at: unix_now(),
+        });
+    });
+    Ok(json!({ "checkpoint": name, "height": height, "block_hash": block_hash.to_string() }))
+}
+
+// Invalidates the blocks mined after the checkpoint; their transactions go back to
+// the mempool. Returns how many blocks were taken off the chain.
+fn rewind(
+    data: &AppState,
+    caller: &Caller,
+    height: u64,
+    block_hash: BlockHash,
+) -> Result<u64, ApiError> {
+    data.config.ensure_regtest("Rewinding")?;
+    let node = caller.node_client(data)?;
+    let tip = node.get_block_count().map_err(|e| caller.rpc(e))?;
+    if tip <= height {
+        return Ok(0);
+    }
+    let at_checkpoint = node.get_block_hash(height).map_err(|e| caller.rpc(e))?;
+    if at_checkpoint != block_hash {
+        return Err(ApiError::Conflict(format!(
+            "The block at height {} changed since the checkpoint, the chain was reorganized",
+            height
+        )));
+    }
+    let first = node.get_block_hash(height + 1).map_err(|e| caller.rpc(e))?;
+    node.invalidate_block(&first).map_err(|e| caller.rpc(e))?;
+    Ok(tip - height)
+}
+
+// Shows the choice in the run and waits for POST /scenario/{id}/choose. Returns the
+// option id and whether it is the default, taken after the timeout.
+async fn wait_for_choice(
+    data: &AppState,
+    id: &str,
+    index: usize,
+    prompt: &str,
+    options: &[ChoiceOption],
+    default: Option<&str>,
+    timeout: Duration,
+) -> Result<(String, bool), ApiError> {
+    update(data, id, |run| {
+        run.status = Status::WaitingForChoice;
+        run.chosen = None;
+        run.pending_choice = Some(PendingChoice {
+            step: index,
+            prompt: prompt.to_string(),
+            options: options
+                .iter()
+                .map(|option| ChoiceView {
+                    id: option.id.clone(),
+                    text: option.text.clone(),
+                })
+                .collect(),
+            default: default.map(str::to_string),
+            expires_at: unix_now().saturating_add(timeout.as_secs() as i64),
+        });
+    });
+    let started = Instant::now();
+    let chosen = loop {
+        let chosen = data
+            .scenarios
+            .get_mut(id)
+            .and_then(|mut run| run.chosen.take());
+        if chosen.is_some() || started.elapsed() >= timeout {
+            break chosen;
+        }
+        actix_rt::time::sleep(CHOICE_POLL_INTERVAL).await;
+    };
+    update(data, id, |run| {
+        run.status = Status::Running;
+        run.pending_choice = None;
+    });
+    let (choice, by_default) = match (chosen, default) {
+        (Some(choice), _) => (choice, false),
+        (None, Some(default)) => (default.to_string(), true),
+        (None, None) => {
+            return Err(ApiError::Conflict(format!(
+                "No choice was made within {} s",
+                timeout.as_secs()
+            )))
+        }
+    };
+    update(data, id, |run| {
+        run.choices.push(ChoiceRecord {
+            step: index,
+            choice: choice.clone(),
+            by_default,
+            chosen_at: unix_now(),
+        })
+    });
+    Ok((choice, by_default))
+}
+
+async fn execute(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    id: &str,
+    name: Option<&str>,
+    index: usize,
+    step: &Step,
+    state: &mut RunState,
+) -> Result<(Value, Flow), ApiError> {
+    let last_payment = &mut state.last_payment;
+    let result = match step {
+        Step::CreateWallet { wallet, fund_sat } => {
+            create_wallet(data, http_req, wallet, *fund_sat).await?
+        }
+        Step::Mine { wallet, blocks } => {
+            let (wallet, blocks) = (wallet.clone(), *blocks);
+            rpc_pool::for_caller(data, http_req, move |data, caller| {
+                mine(data, caller, &wallet, blocks)
+            })
+            .await?
+        }
+        Step::Send {
+            from,
+            to,
+            amount_sat,
+            amount_btc,
+            fee_rate,
+            replaceable,
+        } => {
+            let amount = match (amount_sat, amount_btc) {
+                (Some(sats), _) => Amount::from_sat(*sats),
+                (None, btc) => Amount::from_btc(btc.unwrap_or_default())
+                    .map_err(|e| ApiError::InvalidRequest(e.to_string()))?,
+            };
+            let fees = FeeOptions {
+                fee_rate: *fee_rate,
+                replaceable: *replaceable,
+                ..FeeOptions::default()
+            };
+            let (payment, result) = send(data, http_req, from, to, amount, fees, name).await?;
+            *last_payment = Some(payment);
+            result
+        }
+        Step::CreateMultisig {
+            wallet,
+            required,
+            wallets,
+        } => create_multisig(data, http_req, wallet, *required, wallets).await?,
+        Step::CreatePsbt {
+            wallet,
+            to,
+            amount_sat,
+            signers,
+        } => {
+            let (wallet, to, amount_sat, signers) =
+                (wallet.clone(), to.clone(), *amount_sat, signers.clone());
+            rpc_pool::for_caller(data, http_req, move |data, caller| {
+                create_psbt(data, caller, &wallet, &to, amount_sat, &signers)
+            })
+            .await?
+        }
+        Step::WaitConfirmations {
+            confirmations,
+            txid,
+            wallet,
+            miner,
+            timeout_secs,
+        } => {
+            let given;
+            let payment = match (txid, wallet) {
+                (Some(txid), Some(wallet)) => {
+                    given = LastPayment {
+                        wallet: data.wallet_name(http_req, wallet),
+                        txid: Txid::from_str(txid).map_err(|e| {
+                            ApiError::InvalidTxid(format!("Invalid transaction ID: {}", e))
+                        })?,
+                    };
+                    &given
+                }
+                _ => last_payment
+                    .as_ref()
+                    .ok_or_else(|| ApiError::InvalidRequest("No payment to wait for yet".into()))?,
+            };
+            let timeout = timeout_secs.map_or(DEFAULT_WAIT_TIMEOUT, Duration::from_secs);
+            wait_confirmations(
+                data,
+                http_req,
+                payment,
+                *confirmations,
+                miner.as_deref(),
+                timeout,
+            )
+            .await?
+        }
+        Step::Chapter { chapter, wallet } => {
+            data.events.publish(Event::ChapterCompleted {
+                wallet: wallet
+                    .as_deref()
+                    .map(|wallet| data.wallet_name(http_req, wallet)),
+                chapter: chapter.clone(),
+            });
+            json!({ "chapter": chapter })
+        }
+        Step::Checkpoint { checkpoint: name } => {
+            checkpoint(data, http_req, id, index, name, state).await?
+        }
+        Step::Branch {
+            condition,
+            goto,
+            else_goto,
+        } => {
+            let (condition, last_payment) = (condition.clone(), state.last_payment.clone());
+            let met = rpc_pool::for_caller(data, http_req, move |data, caller| {
+                check(data, caller, &condition, last_payment.as_ref())
+            })
+            .await?;
+            let target = if met { Some(goto) } else { else_goto.as_ref() };
+            let flow = target.map_or(Flow::Next, |target| Flow::Goto(state.positions[target]));
+            return Ok((json!({ "condition_met": met }), flow));
+        }
+        Step::Goto { goto } => {
+            return Ok((json!({ "goto": goto }), Flow::Goto(state.positions[goto])));
+        }
+        Step::End => return Ok((json!({}), Flow::End)),
+        Step::Choice {
+            prompt,
+            options,
+            default,
+            timeout_secs,
+        } => {
+            let timeout = timeout_secs.map_or(DEFAULT_CHOICE_TIMEOUT, Duration::from_secs);
+            let (choice, by_default) = wait_for_choice(
+                data,
+                id,
+                index,
+                prompt,
+                options,
+                default.as_deref(),
+                timeout,
+            )
+            .await?;
+            let Some(option) = options.iter().find(|option| option.id == choice) else {
+                return Err(ApiError::Internal(format!("unknown choice '{choice}'")));
+            };
+            let mut result = json!({ "choice": choice, "by_default": by_default });
+            let flow = match (&option.goto, &option.rewind) {
+                (Some(goto), _) => Flow::Goto(state.positions[goto]),
+                (None, Some(name)) => {
+                    let saved = state.checkpoints.get(name).ok_or_else(|| {
+                        ApiError::InvalidRequest(format!("Checkpoint '{name}' was not reached yet"))
+                    })?;
+                    let (height, block_hash) = (saved.height, saved.block_hash);
+                    let invalidated = rpc_pool::for_caller(data, http_req, move |data, caller| {
+                        rewind(data, caller, height, block_hash)
+                    })
+                    .await?;
+                    result["blocks_invalidated"] = json!(invalidated);
+                    result["rewound_to"] = json!(name);
+                    state.last_payment = saved.last_payment.clone();
+                    Flow::Goto(saved.step + 1)
+                }
+                (None, None) => Flow::Next,
+            };
+            return Ok((result, flow));
+        }
+    };
+    Ok((result, Flow::Next))
+}
+
+// Runs the steps in order, following the jumps, and stops at the first failure. A
+// step run again shows its latest outcome.
+async fn run(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: String,
+    name: Option<String>,
+    steps: Vec<ScenarioStep>,
+    positions: HashMap<String, usize>,
+) {
+    let mut state = RunState {
+        positions,
+        last_payment: None,
+        checkpoints: HashMap::new(),
+    };
+    let mut failed = false;
+    let mut index = 0;
+    let mut executed = 0;
+    while index < steps.len() {
+        executed += 1;
+        if executed > MAX_EXECUTED_STEPS {
+            warn!("Scenario {} ran {} steps, stopping", id, MAX_EXECUTED_STEPS);
+            update(&data, &id, |run| {
+                run.steps[index].status = Status::Failed;
+                run.steps[index].error = Some(format!(
+                    "The run reached {MAX_EXECUTED_STEPS} steps, it probably loops"
+                ));
+            });
+            failed = true;
+            break;
+        }
+        let step = &steps[index].step;
+        update(&data, &id, |run| {
+            run.current_step = Some(index);
+            run.path.push(index);
+            run.steps[index].status = Status::Running;
+            run.steps[index].error = None;
+        });
+        info!("Scenario {} step {}: {}", id, index, step.describe());
+        let outcome = execute(
+            &data,
+            &http_req,
+            &id,
+            name.as_deref(),
+            index,
+            step,
+            &mut state,
+        )
+        .await;
+        let flow = match outcome {
+            Ok((result, flow)) => {
+                update(&data, &id, |run| {
+                    run.steps[index].status = Status::Done;
+                    run.steps[index].result = Some(result);
+                });
+                flow
+            }
+            Err(e) => {
+                warn!("Scenario {} failed at step {}: {}", id, index, e);
+                update(&data, &id, |run| {
+                    run.steps[index].status = Status::Failed;
+                    run.steps[index].error = Some(e.to_string());
+                });
+                failed = true;
+                break;
+            }
+        };
+        match flow {
+            Flow::Next => index += 1,
+            Flow::Goto(target) => index = target,
+            Flow::End => break,
+        }
+    }
+    update(&data, &id, |run| {
+        for step in &mut run.steps {
+            if step.status == Status::Pending {
+                step.status = Status::Skipped;
+            }
+        }
+        run.status = if failed { Status::Failed } else { Status::Done };
+        run.current_step = None;
+        run.finished_at = Some(unix_now());
+    });
+    info!("Scenario {} finished, failed={}", id, failed);
+}
+
+impl ScenarioRun {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    // None while the run is going on, then whether every step it ran succeeded
+    pub(crate) fn succeeded(&self) -> Option<bool> {
+        match self.status {
+            Status::Done => Some(true),
+            Status::Failed => Some(false),
+            _ => None,
+        }
+    }
+
+    // The error of the step that stopped the run
+    pub(crate) fn error(&self) -> Option<&str> {
+        self.steps.iter().find_map(|step| step.error.as_deref())
+    }
+
+    // What the step at `index` produced, once it is done
+    pub(crate) fn result(&self, index: usize) -> Option<&Value> {
+        self.steps.get(index)?.result.as_ref()
+    }
+}
+
+// Drops runs that finished more than FINISHED_RUN_TTL ago. Exercises drop their setup
+// runs themselves.
+pub(crate) async fn sweep_finished(data: web::Data<AppState>) {
+    loop {
+        actix_rt::time::sleep(SWEEP_INTERVAL).await;
+        let cutoff = unix_now().saturating_sub(FINISHED_RUN_TTL.as_secs() as i64);
+        let exercise_runs = exercises::setup_runs(&data);
+        let before = data.scenarios.len();
+        data.scenarios.retain(|id, run| {
+            run.finished_at.is_none_or(|finished| finished >= cutoff) || exercise_runs.contains(id)
+        });
+        let dropped = before.saturating_sub(data.scenarios.len());
+        if dropped > 0 {
+            debug!("Dropped {} finished scenario run(s)", dropped);
+        }
+    }
+}
+
+// Checks the steps and starts them in the background; the progress is at
+// GET /scenario/{id}
+pub(crate) fn start(
+    data: &web::Data<AppState>,
+    http_req: HttpRequest,
+    name: Option<String>,
+    steps: Vec<ScenarioStep>,
+) -> Result<ScenarioRun, ApiError> {
+    if steps.is_empty() || steps.len() > MAX_STEPS {
+        return Err(ApiError::InvalidRequest(format!(
+            "A scenario has between 1 and {} steps",
+            MAX_STEPS
+        )));
+    }
+    for (index, step) in steps.iter().enumerate() {
+        step.step
+            .validate()
+            .map_err(|e| ApiError::InvalidRequest(format!("Step {}: {}", index, e)))?;
+    }
+    let positions = positions(&steps).map_err(ApiError::InvalidRequest)?;
+    // Invalidating blocks takes them away from everyone on the node
+    let rewinds = steps.iter().any(|step| {
+        matches!(&step.step, Step::Choice { options, .. }
+            if options.iter().any(|option| option.rewind.is_some()))
+    });
+    if rewinds && !auth::is_admin(data, &http_req) {
+        return Err(ApiError::Forbidden(
+            "Choices that rewind to a checkpoint are only available to admins".into(),
+        ));
+    }
+
+    let owner = data.wallet_name(&http_req, "");
+    let client = if owner.is_empty() {
+        // Unix socket clients share one count
+        format!(
+            "ip:{}",
+            http_req
+                .peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "local".into())
+        )
+    } else {
+        owner.clone()
+    };
+    let running = data
+        .scenarios
+        .iter()
+        .filter(|run| run.client == client && run.succeeded().is_none())
+        .count();
+    if running >= MAX_RUNNING_PER_CLIENT {
+        return Err(ApiError::TooManyRequests(format!(
+            "At most {MAX_RUNNING_PER_CLIENT} scenario runs can go on at the same time"
+        )));
+    }
+
+    let id = Uuid::new_v4().simple().to_string();
+    let scenario = ScenarioRun {
+        id: id.clone(),
+        name: name.clone(),
+        status: Status::Running,
+        current_step: None,
+        steps: steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| StepProgress {
+                index,
+                description: step.step.describe(),
+                status: Status::Pending,
+                result: None,
+                error: None,
+            })
+            .collect(),
+        path: Vec::new(),
+        pending_choice: None,
+        choices: Vec::new(),
+        checkpoints: Vec::new(),
+        started_at: unix_now(),
+        finished_at: None,
+        chosen: None,
+        owner,
+        client,
+    };
+    data.scenarios.insert(id.clone(), scenario.clone());
+    // Steps log under the request that started the run
+    actix_rt::spawn(run(data.clone(), http_req, id, name, steps, positions).in_current_span());
+    Ok(scenario)
+}
+
+// Takes the scenario as JSON, or as YAML with a YAML content type
+#[utoipa::path(
+    post,
+    path = "/scenario/run",
+    tag = "scenario",
+    request_body(content(
+        (RunScenarioRequest = "application/json"),
+        (RunScenarioRequest = "application/yaml"),
+    )),
+    responses(
+        (status = 202, body = ScenarioRun),
+    )
+)]
+pub(crate) async fn run_scenario(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let yaml = http_req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            YAML_CONTENT_TYPES
+                .iter()
+                .any(|yaml| mime.eq_ignore_ascii_case(yaml))
+        });
+    let req: RunScenarioRequest = if yaml {
+        serde_yaml::from_slice(&body)
+            .map_err(|e| ApiError::InvalidRequest(format!("Invalid YAML scenario: {e}")))?
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| ApiError::InvalidRequest(format!("Invalid JSON scenario: {e}")))?
+    };
+    info!(
+        "POST /scenario/run - name={:?}, {} step(s)",
+        req.name,
+        req.steps.len()
+    );
+    let scenario = start(&data, http_req, req.name, req.steps)?;
+    Ok(HttpResponse::Accepted().json(scenario))
+}
+
+#[utoipa::path(
+    get,
+    path = "/scenario/{id}",
+    tag = "scenario",
+    responses(
+        (status = 200, body = ScenarioRun),
+    )
+)]
+pub(crate) async fn get_scenario(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /scenario/{}", id);
+    let owner = data.wallet_name(&http_req, "");
+    match data
+        .scenarios
+        .get(id.as_str())
+        .filter(|run| run.owner == owner)
+    {
+        Some(run) => Ok(HttpResponse::Ok().json(run.clone())),
+        None => {
+            error!("Scenario run '{}' not found", id);
+            Err(ApiError::NotFound(format!(
+                "Scenario run '{}' not found",
+                id
+            )))
+        }
+    }
+}
+
+// Answers the choice a run is waiting at; the run picks it up and moves on
+#[utoipa::path(
+    post,
+    path = "/scenario/{id}/choose",
+    tag = "scenario",
+    responses(
+        (status = 202, body = ScenarioRun),
+    )
+)]
+pub(crate) async fn choose(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+    req: web::Json<ChooseRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /scenario/{}/choose - choice='{}'", id, req.choice);
+    // Only the caller that started the run steers it
+    let owner = data.wallet_name(&http_req, "");
+    let run = data
+        .scenarios
+        .get_mut(id.as_str())
+        .filter(|run| run.owner == owner);
+    let Some(mut run) = run else {
+        warn!("Scenario run '{}' not found", id);
+        return Err(ApiError::NotFound(format!(
+            "Scenario run '{}' not found",
+            id
+        )));
+    };
+    let Some(pending) = &run.pending_choice else {
+        return Err(ApiError::Conflict(format!(
+            "Scenario run '{}' is not waiting for a choice",
+            id
+        )));
+    };
+    if !pending.options.iter().any(|option| option.id == req.choice) {
+        let expected: Vec<&str> = pending
+            .options
+            .iter()
+            .map(|option| option.id.as_str())
+            .collect();
+        return Err(ApiError::InvalidRequest(format!(
+            "Unknown choice '{}', expected one of: {}",
+            req.choice,
+            expected.join(", ")
+        )));
+    }
+    if run.chosen.is_some() {
+        return Err(ApiError::Conflict("A choice was already made".into()));
+    }
+    run.chosen = Some(req.choice.clone());
+    Ok(HttpResponse::Accepted().json(run.clone()))
+}
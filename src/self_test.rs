@@ -0,0 +1,271 @@
+use crate::faucet;
+use crate::Config;
+use bitcoincore_rpc::bitcoin::Network;
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use dotenv as env;
+use log::{error, info, warn};
+use serde_json::Value;
+use std::path::Path;
+use std::time::Duration;
+use zeromq::{Socket, SubSocket};
+
+// bitcoind may still be starting, e.g. under docker-compose
+const NODE_WAIT: Duration = Duration::from_secs(30);
+const NODE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+const ZMQ_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// `self_test` runs the checks at startup: `warn` only logs failures, `strict` refuses
+// to start on them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SelfTestMode {
+    Off,
+    Warn,
+    Strict,
+}
+
+impl SelfTestMode {
+    pub(crate) fn from_env() -> Result<Self, RpcError> {
+        match env::var("self_test").as_deref().map(str::trim) {
+            Err(_) | Ok("") | Ok("off") => Ok(Self::Off),
+            Ok("warn") => Ok(Self::Warn),
+            Ok("strict") => Ok(Self::Strict),
+            Ok(other) => Err(RpcError::ReturnedError(format!(
+                "unsupported self_test '{other}', expected off, warn or strict"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Ok,
+    Failed,
+    // Nothing to check, e.g. ZMQ is not configured
+    Skipped,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Failed => "FAILED",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<String, String>) -> Self {
+        let (status, detail) = match result {
+            Ok(detail) => (Status::Ok, detail),
+            Err(detail) => (Status::Failed, detail),
+        };
+        Self {
+            name: name.into(),
+            status,
+            detail,
+        }
+    }
+
+    fn skipped(name: impl Into<String>, detail: &str) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Skipped,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+// Waits out RPC_IN_WARMUP and a node that is not listening yet
+async fn wait_for_node(client: &Client) -> Result<Value, RpcError> {
+    let mut waited = Duration::ZERO;
+    loop {
+        match client.call::<Value>("getnetworkinfo", &[]) {
+            Ok(info) => return Ok(info),
+            Err(e) if waited >= NODE_WAIT => return Err(e),
+            Err(_) => {
+                actix_rt::time::sleep(NODE_RETRY_INTERVAL).await;
+                waited += NODE_RETRY_INTERVAL;
+            }
+        }
+    }
+}
+
+fn check_chain(client: &Client, network: Network) -> Result<String, String> {
+    let info: Value = client
+        .call("getblockchaininfo", &[])
+        .map_err(|e| e.to_string())?;
+    let chain = info["chain"].as_str().unwrap_or_default();
+    let height = info["blocks"].as_u64().unwrap_or_default();
+    if chain == network.to_core_arg() {
+        Ok(format!("{chain} at height {height}"))
+    } else {
+        Err(format!(
+            "node runs {chain}, but network={}; set `network` to match the node",
+            network.to_core_arg()
+        ))
+    }
+}
+
+// Loading the faucet wallet, or creating it, and handing out an address writes to
+// the wallet directory
+fn check_wallet(config: &Config) -> Result<String, String> {
+    let client = config
+        .create_client(&config.faucet_wallet)
+        .map_err(|e| e.to_string())?;
+    faucet::ensure_loaded(&client, &config.faucet_wallet)
+        .and_then(|()| client.get_new_address(Some("self-test"), Some(AddressType::Bech32)))
+        .map(|_| format!("faucet wallet '{}' is usable", config.faucet_wallet))
+        .map_err(|e| {
+            format!(
+                "cannot load or create the faucet wallet '{}', is the wallet directory writable? {e}",
+                config.faucet_wallet
+            )
+        })
+}
+
+fn check_wallet_dir(dir: &Path) -> Result<String, String> {
+    let probe = dir.join(".coin-comic-tales-self-test");
+    std::fs::write(&probe, b"")
+        .and_then(|()| std::fs::remove_file(&probe))
+        .map(|()| format!("{} is writable", dir.display()))
+        .map_err(|e| format!("{} is not writable: {e}", dir.display()))
+}
+
+// The node has to publish the topic, and the endpoint has to accept connections
+async fn check_zmq(notifications: &[Value], topic: &str, endpoint: &str) -> Result<String, String> {
+    let published = notifications
+        .iter()
+        .any(|notification| notification["type"].as_str() == Some(&format!("pub{topic}")));
+    if !published {
+        return Err(format!(
+            "the node does not publish {topic}; start bitcoind with -zmqpub{topic}={endpoint}"
+        ));
+    }
+    let mut socket = SubSocket::new();
+    match actix_rt::time::timeout(ZMQ_CONNECT_TIMEOUT, socket.connect(endpoint)).await {
+        Ok(Ok(())) => {
+            let _ = socket.close().await;
+            Ok(format!("{endpoint} is reachable"))
+        }
+        Ok(Err(e)) => Err(format!("cannot connect to {endpoint}: {e}")),
+        Err(_) => Err(format!("timed out connecting to {endpoint}")),
+    }
+}
+
+async fn run_checks(config: &Config) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let node = match config.create_node_client() {
+        Ok(node) => node,
+        Err(e) => {
+            checks.push(Check::new("node", Err(e.to_string())));
+            return checks;
+        }
+    };
+    match wait_for_node(&node).await {
+        Ok(info) => checks.push(Check::new(
+            "node",
+            Ok(format!(
+                "{} at {}",
+                info["subversion"].as_str().unwrap_or_default(),
+                config.rpc_url
+            )),
+        )),
+        Err(e) => {
+            checks.push(Check::new(
+                "node",
+                Err(format!("cannot reach {}: {e}", config.rpc_url)),
+            ));
+            // Every other check needs the node
+            return checks;
+        }
+    }
+    checks.push(Check::new("chain", check_chain(&node, config.network)));
+    checks.push(Check::new("wallet", check_wallet(config)));
+    checks.push(match config.reset.wallet_dir() {
+        Some(dir) => Check::new("wallet_dir", check_wallet_dir(dir)),
+        None => Check::skipped("wallet_dir", "reset_wallet_dir is not set"),
+    });
+
+    match &config.zmq {
+        None => checks.push(Check::skipped("zmq", "not configured")),
+        Some(zmq) => {
+            let notifications: Vec<Value> =
+                node.call("getzmqnotifications", &[]).unwrap_or_default();
+            for (topic, endpoint) in [("rawblock", &zmq.rawblock), ("rawtx", &zmq.rawtx)] {
+                if let Some(endpoint) = endpoint {
+                    checks.push(Check::new(
+                        format!("zmq {topic}"),
+                        check_zmq(&notifications, topic, endpoint).await,
+                    ));
+                }
+            }
+        }
+    }
+
+    for peer in &config.network_nodes {
+        let result = config
+            .rpc_client(&peer.rpc_url)
+            .and_then(|client| client.get_block_count())
+            .map(|height| format!("{} at height {height}", peer.rpc_url))
+            .map_err(|e| format!("cannot reach {}: {e}", peer.rpc_url));
+        checks.push(Check::new(format!("node {}", peer.name), result));
+    }
+    checks
+}
+
+fn log_table(checks: &[Check]) {
+    let width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .max()
+        .unwrap_or_default();
+    info!("Self-test:");
+    for check in checks {
+        let line = format!(
+            "  {:<width$}  {:<7}  {}",
+            check.name,
+            check.status.label(),
+            check.detail
+        );
+        match check.status {
+            Status::Failed => error!("{}", line),
+            _ => info!("{}", line),
+        }
+    }
+}
+
+// Checks the configuration against the node before serving, so a misconfiguration
+// shows up at startup instead of in the first failing request. Err in strict mode
+// when a check failed.
+pub(crate) async fn run(config: &Config) -> Result<(), String> {
+    if config.self_test == SelfTestMode::Off {
+        return Ok(());
+    }
+    let checks = run_checks(config).await;
+    log_table(&checks);
+    let failed: Vec<&str> = checks
+        .iter()
+        .filter(|check| check.status == Status::Failed)
+        .map(|check| check.name.as_str())
+        .collect();
+    if failed.is_empty() {
+        info!("Self-test passed");
+        return Ok(());
+    }
+    let summary = format!("self-test failed: {}", failed.join(", "));
+    match config.self_test {
+        SelfTestMode::Strict => Err(summary),
+        _ => {
+            warn!("{}, starting anyway", summary);
+            Ok(())
+        }
+    }
+}
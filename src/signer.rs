@@ -0,0 +1,234 @@
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+// These flows require bitcoind to run with -signer=<path to HWI or another signer>
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateSignerWalletRequest {
+    name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct DisplayAddressRequest {
+    address: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ProcessPsbtRequest {
+    psbt: String,
+    #[serde(default)]
+    broadcast: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ProcessPsbtResponse {
+    psbt: String,
+    complete: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    txid: Option<String>,
+}
+
+fn create_signer_wallet(client: &Client, name: &str) -> Result<Value, RpcError> {
+    // createwallet name disable_private_keys blank passphrase avoid_reuse descriptors load_on_startup external_signer
+    client.call(
+        "createwallet",
+        &[
+            json!(name),
+            json!(true),
+            json!(true),
+            json!(""),
+            json!(false),
+            json!(true),
+            Value::Null,
+            json!(true),
+        ],
+    )
+}
+
+// walletprocesspsbt hands the PSBT to the external signer; complete transactions
+// can be finalized and broadcast in the same call
+fn process_psbt(
+    client: &Client,
+    psbt: &str,
+    broadcast: bool,
+) -> Result<ProcessPsbtResponse, RpcError> {
+    let processed: Value = client.call("walletprocesspsbt", &[json!(psbt), json!(true)])?;
+    let psbt = processed["psbt"].as_str().unwrap_or_default().to_string();
+    let complete = processed["complete"].as_bool().unwrap_or(false);
+    if !(broadcast && complete) {
+        return Ok(ProcessPsbtResponse {
+            psbt,
+            complete,
+            txid: None,
+        });
+    }
+
+    let finalized: Value = client.call("finalizepsbt", &[json!(psbt)])?;
+    let hex = finalized["hex"]
+        .as_str()
+        .ok_or_else(|| RpcError::ReturnedError("signed PSBT could not be finalized".into()))?;
+    let txid: String = client.call("sendrawtransaction", &[json!(hex)])?;
+    Ok(ProcessPsbtResponse {
+        psbt,
+        complete,
+        txid: Some(txid),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/signer/list",
+    tag = "signer",
+    responses(
+        (status = 200, body = Object),
+    )
+)]
+pub(crate) async fn list_signers(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /signer/list");
+    match rpc_pool::call_node::<Value>(&data, "enumeratesigners", vec![]).await? {
+        Ok(signers) => Ok(HttpResponse::Ok().json(signers)),
+        Err(e) => {
+            error!("enumeratesigners failed: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/signer/wallet",
+    tag = "signer",
+    responses(
+        (status = 200, body = Object),
+    )
+)]
+pub(crate) async fn create_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<CreateSignerWalletRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /signer/wallet - name='{}'", req.name);
+    let wallet_name = data.wallet_name(&http_req, &req.name);
+    let client = match data.config.create_client(&wallet_name) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create RPC client for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let name = wallet_name.clone();
+    let (client, created) = rpc_pool::blocking(move || {
+        let created = create_signer_wallet(&client, &name);
+        (client, created)
+    })
+    .await?;
+    let result = match created {
+        Ok(result) => result,
+        Err(e) => {
+            error!(
+                "Failed to create external signer wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+
+    info!("External signer wallet '{}' created", wallet_name);
+    data.events.publish(Event::WalletReady {
+        wallet: wallet_name.clone(),
+    });
+    data.register_wallet(wallet_name, client);
+    Ok(HttpResponse::Ok().json(result))
+}
+
+// Shows the address on the signer's screen so the user can verify it there
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/signer/display-address",
+    tag = "signer",
+    responses(
+        (status = 200, body = Object),
+    )
+)]
+pub(crate) async fn display_address(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<DisplayAddressRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /wallet/{}/signer/display-address - address='{}'",
+        walletid, req.address
+    );
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!(
+            "POST /wallet/{}/signer/display-address - wallet not found",
+            walletid
+        );
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let params = vec![json!(req.address)];
+    match rpc_pool::call::<Value>(&data, client, &wallet_name, "walletdisplayaddress", params)
+        .await?
+    {
+        Ok(result) => Ok(HttpResponse::Ok().json(result)),
+        Err(e) => {
+            error!(
+                "walletdisplayaddress failed for wallet '{}': {}",
+                wallet_name, e
+            );
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/signer/process-psbt",
+    tag = "signer",
+    responses(
+        (status = 200, body = ProcessPsbtResponse),
+    )
+)]
+pub(crate) async fn sign_psbt(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<ProcessPsbtRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /wallet/{}/signer/process-psbt - broadcast={}",
+        walletid, req.broadcast
+    );
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!(
+            "POST /wallet/{}/signer/process-psbt - wallet not found",
+            walletid
+        );
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    let req = req.into_inner();
+    match rpc_pool::blocking(move || process_psbt(&client, &req.psbt, req.broadcast)).await? {
+        Ok(result) => Ok(HttpResponse::Ok().json(result)),
+        Err(e) => {
+            error!("Failed to process PSBT for wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
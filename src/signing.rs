@@ -0,0 +1,225 @@
+use crate::errors::ApiError;
+use crate::AppState;
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use bitcoincore_rpc::bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use bitcoincore_rpc::Error as RpcError;
+use dotenv as env;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+pub(crate) const SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-signature");
+pub(crate) const SIGNATURE_ALGORITHM_HEADER: HeaderName =
+    HeaderName::from_static("x-signature-algorithm");
+
+// Signs every response body, so a recorded result can later be shown to come from
+// this server unchanged
+#[derive(Clone)]
+pub(crate) enum ResponseSigner {
+    // Only the server can check the signature, through POST /signing/verify
+    Hmac(Vec<u8>),
+    // Anyone with the public key from GET /signing/key can check it
+    Ed25519(SigningKey),
+}
+
+// Keep the key out of any debug output
+impl fmt::Debug for ResponseSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ResponseSigner")
+            .field(&self.algorithm())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseSigner {
+    // `response_signing` is hmac or ed25519, keyed with the hex `response_signing_key`,
+    // which for ed25519 is the 32-byte secret key
+    pub(crate) fn from_env() -> Result<Option<Self>, RpcError> {
+        let algorithm = match env::var("response_signing").as_deref().map(str::trim) {
+            Err(_) | Ok("") | Ok("off") => return Ok(None),
+            Ok(algorithm) => algorithm.to_string(),
+        };
+        let key = env::var("response_signing_key")
+            .ok()
+            .and_then(|key| hex::decode(key.trim()).ok())
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| {
+                RpcError::ReturnedError("response_signing needs a hex response_signing_key".into())
+            })?;
+        match algorithm.as_str() {
+            "hmac" => Ok(Some(Self::Hmac(key))),
+            "ed25519" => {
+                let secret: [u8; 32] = key.try_into().map_err(|_| {
+                    RpcError::ReturnedError("an ed25519 response_signing_key is 32 bytes".into())
+                })?;
+                Ok(Some(Self::Ed25519(SigningKey::from_bytes(&secret))))
+            }
+            other => Err(RpcError::ReturnedError(format!(
+                "unsupported response_signing '{other}', expected off, hmac or ed25519"
+            ))),
+        }
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self {
+            Self::Hmac(_) => "hmac-sha256",
+            Self::Ed25519(_) => "ed25519",
+        }
+    }
+
+    fn hmac(key: &[u8], body: &[u8]) -> [u8; 32] {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+        engine.input(body);
+        hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+    }
+
+    fn sign(&self, body: &[u8]) -> String {
+        match self {
+            Self::Hmac(key) => hex::encode(Self::hmac(key, body)),
+            Self::Ed25519(key) => hex::encode(key.sign(body).to_bytes()),
+        }
+    }
+
+    fn verify(&self, body: &[u8], signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature.trim()) else {
+            return false;
+        };
+        match self {
+            // Compared in constant time, so the check does not leak the expected value
+            Self::Hmac(key) => {
+                let expected = Self::hmac(key, body);
+                signature.len() == expected.len()
+                    && signature
+                        .iter()
+                        .zip(expected)
+                        .fold(0, |diff, (a, b)| diff | (a ^ b))
+                        == 0
+            }
+            Self::Ed25519(key) => Signature::from_slice(&signature)
+                .is_ok_and(|signature| key.verifying_key().verify(body, &signature).is_ok()),
+        }
+    }
+
+    fn public_key(&self) -> Option<String> {
+        match self {
+            Self::Hmac(_) => None,
+            Self::Ed25519(key) => Some(hex::encode(key.verifying_key().to_bytes())),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct SigningKeyResponse {
+    algorithm: &'static str,
+    // Hex ed25519 public key; none for hmac, whose key is secret
+    public_key: Option<String>,
+    signature_header: &'static str,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct VerifyRequest {
+    // The response body exactly as received
+    body: String,
+    signature: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct VerifyResponse {
+    algorithm: &'static str,
+    valid: bool,
+}
+
+// Upgraded connections and event streams never end, so there is no body to sign
+fn is_streaming<B>(res: &ServiceResponse<B>) -> bool {
+    res.status() == StatusCode::SWITCHING_PROTOCOLS
+        || res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"))
+}
+
+// Adds X-Signature over the exact response body bytes and X-Signature-Algorithm
+pub(crate) async fn sign_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let signer = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|data| data.config.signing.clone());
+    let res = next.call(req).await?;
+    let Some(signer) = signer else {
+        return Ok(res.map_into_boxed_body());
+    };
+    if is_streaming(&res) {
+        return Ok(res.map_into_boxed_body());
+    }
+    let (http_req, response) = res.into_parts();
+    let (mut response, response_body) = response.into_parts();
+    let response_body = body::to_bytes(response_body)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("failed to read response body"))?;
+    let headers = response.headers_mut();
+    if let Ok(signature) = HeaderValue::from_str(&signer.sign(&response_body)) {
+        headers.insert(SIGNATURE_HEADER, signature);
+    }
+    headers.insert(
+        SIGNATURE_ALGORITHM_HEADER,
+        HeaderValue::from_static(signer.algorithm()),
+    );
+    let response = response.set_body(BoxBody::new(response_body));
+    Ok(ServiceResponse::new(http_req, response))
+}
+
+fn signer(data: &AppState) -> Result<&ResponseSigner, ApiError> {
+    data.config
+        .signing
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("Response signing is disabled".into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/signing/key",
+    tag = "signing",
+    responses(
+        (status = 200, body = SigningKeyResponse),
+    )
+)]
+pub(crate) async fn get_signing_key(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    info!("GET /signing/key");
+    let signer = signer(&data)?;
+    Ok(HttpResponse::Ok().json(SigningKeyResponse {
+        algorithm: signer.algorithm(),
+        public_key: signer.public_key(),
+        signature_header: "X-Signature",
+    }))
+}
+
+// Checks a body and signature pair, e.g. from a recorded exercise result
+#[utoipa::path(
+    post,
+    path = "/signing/verify",
+    tag = "signing",
+    responses(
+        (status = 200, body = VerifyResponse),
+    )
+)]
+pub(crate) async fn verify(
+    data: web::Data<AppState>,
+    req: web::Json<VerifyRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /signing/verify - body_len={}", req.body.len());
+    let signer = signer(&data)?;
+    Ok(HttpResponse::Ok().json(VerifyResponse {
+        algorithm: signer.algorithm(),
+        valid: signer.verify(req.body.as_bytes(), &req.signature),
+    }))
+}
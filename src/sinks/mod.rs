@@ -0,0 +1,216 @@
+use crate::events::{Event, EventBus};
+use crate::proxy::ProxyConfig;
+use async_trait::async_trait;
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::Error as RpcError;
+use dotenv as env;
+use log::{error, info, warn};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+mod mqtt;
+mod nats;
+mod nostr;
+mod webhook;
+
+pub(crate) use mqtt::MqttConfig;
+pub(crate) use nats::NatsConfig;
+pub(crate) use nostr::NostrConfig;
+pub(crate) use webhook::WebhookConfig;
+
+// Anything that can fan events out to external infrastructure
+#[async_trait(?Send)]
+pub(crate) trait EventSink {
+    fn name(&self) -> &'static str;
+
+    async fn publish(&self, event: &Event) -> Result<(), String>;
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SinksConfig {
+    pub(crate) webhook: Option<WebhookConfig>,
+    pub(crate) mqtt: Option<MqttConfig>,
+    pub(crate) nats: Option<NatsConfig>,
+    pub(crate) nostr: Option<NostrConfig>,
+}
+
+impl SinksConfig {
+    // `event_sinks` selects which sinks to run (e.g. "webhook,nats"). When it is not
+    // set, every sink that has its settings configured is enabled.
+    pub(crate) fn from_env() -> Result<Self, RpcError> {
+        let all = Self {
+            webhook: webhook_from_env(),
+            mqtt: mqtt_from_env()?,
+            nats: nats_from_env(),
+            nostr: nostr_from_env()?,
+        };
+        let names = match env::var("event_sinks") {
+            Ok(names) => names,
+            Err(_) => return Ok(all),
+        };
+
+        let mut config = Self::default();
+        for name in names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            match name {
+                "webhook" => config.webhook = selected(name, &all.webhook)?,
+                "mqtt" => config.mqtt = selected(name, &all.mqtt)?,
+                "nats" => config.nats = selected(name, &all.nats)?,
+                "nostr" => config.nostr = selected(name, &all.nostr)?,
+                other => {
+                    return Err(RpcError::ReturnedError(format!(
+                        "unknown event sink '{other}' in event_sinks"
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn selected<T: Clone>(name: &str, sink: &Option<T>) -> Result<Option<T>, RpcError> {
+    sink.clone().map(Some).ok_or_else(|| {
+        RpcError::ReturnedError(format!(
+            "event sink '{name}' is selected but not configured"
+        ))
+    })
+}
+
+// Builds every configured sink and gives each one its own subscription,
+// so a slow sink never holds back the others
+pub(crate) async fn start(config: &SinksConfig, bus: &EventBus) {
+    if let Some(webhook) = &config.webhook {
+        match webhook::WebhookSink::new(webhook.clone()) {
+            Ok(sink) => spawn_sink(Box::new(sink), bus),
+            Err(e) => error!("Webhook sink disabled: {}", e),
+        }
+    }
+    if let Some(mqtt) = &config.mqtt {
+        let (sink, eventloop) = mqtt::MqttSink::new(mqtt.clone());
+        actix_rt::spawn(mqtt::drive_connection(eventloop));
+        spawn_sink(Box::new(sink), bus);
+    }
+    if let Some(nats) = &config.nats {
+        match nats::NatsSink::connect(nats.clone()).await {
+            Ok(sink) => spawn_sink(Box::new(sink), bus),
+            Err(e) => error!("NATS sink disabled: {}", e),
+        }
+    }
+    if let Some(nostr) = &config.nostr {
+        match nostr::NostrSink::new(nostr.clone()) {
+            Ok(sink) => spawn_sink(Box::new(sink), bus),
+            Err(e) => error!("Nostr sink disabled: {}", e),
+        }
+    }
+}
+
+fn spawn_sink(sink: Box<dyn EventSink>, bus: &EventBus) {
+    info!("Event sink '{}' started", sink.name());
+    actix_rt::spawn(forward_events(sink, bus.subscribe()));
+}
+
+async fn forward_events(sink: Box<dyn EventSink>, mut events: Receiver<Event>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if let Err(e) = sink.publish(&event).await {
+                    error!(
+                        "Event sink '{}' failed to publish '{}': {}",
+                        sink.name(),
+                        event.kind(),
+                        e
+                    );
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(
+                    "Event sink '{}' lagged behind, skipped {} event(s)",
+                    sink.name(),
+                    skipped
+                );
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+fn webhook_from_env() -> Option<WebhookConfig> {
+    let urls = list_from_env("webhook_urls");
+    if urls.is_empty() {
+        return None;
+    }
+    Some(WebhookConfig {
+        urls,
+        proxy: ProxyConfig::from_env(),
+    })
+}
+
+fn mqtt_from_env() -> Result<Option<MqttConfig>, RpcError> {
+    let host = match env::var("mqtt_host") {
+        Ok(host) if !host.trim().is_empty() => host.trim().to_string(),
+        _ => return Ok(None),
+    };
+    let port = match env::var("mqtt_port") {
+        Ok(port) => port
+            .parse()
+            .map_err(|_| RpcError::ReturnedError("mqtt_port must be a valid port".into()))?,
+        Err(_) => 1883,
+    };
+    Ok(Some(MqttConfig {
+        host,
+        port,
+        client_id: env::var("mqtt_client_id").unwrap_or_else(|_| "coin-comic-tales-rs".into()),
+        topic_prefix: env::var("mqtt_topic_prefix")
+            .unwrap_or_else(|_| "coin-comic-tales".into())
+            .trim_end_matches('/')
+            .to_string(),
+    }))
+}
+
+fn nats_from_env() -> Option<NatsConfig> {
+    let url = env::var("nats_url")
+        .ok()
+        .filter(|url| !url.trim().is_empty())?;
+    Some(NatsConfig {
+        url,
+        subject_prefix: env::var("nats_subject_prefix")
+            .unwrap_or_else(|_| "coin-comic-tales".into())
+            .trim_end_matches('.')
+            .to_string(),
+    })
+}
+
+fn nostr_from_env() -> Result<Option<NostrConfig>, RpcError> {
+    let relays = list_from_env("nostr_relays");
+    if relays.is_empty() {
+        return Ok(None);
+    }
+    let secret_key = env::var("nostr_secret_key").map_err(|_| {
+        RpcError::ReturnedError("nostr_relays is set but nostr_secret_key is missing".into())
+    })?;
+    let big_payment_sat = match env::var("nostr_big_payment_sat") {
+        Ok(value) => value.parse().map_err(|_| {
+            RpcError::ReturnedError("nostr_big_payment_sat must be a number of sats".into())
+        })?,
+        Err(_) => Amount::ONE_BTC.to_sat(),
+    };
+    Ok(Some(NostrConfig {
+        relays,
+        secret_key,
+        big_payment_sat,
+    }))
+}
+
+fn list_from_env(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,78 @@
+use super::EventSink;
+use crate::events::Event;
+use async_trait::async_trait;
+use log::{debug, warn};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
+use std::time::Duration;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub(crate) struct MqttConfig {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) client_id: String,
+    pub(crate) topic_prefix: String,
+}
+
+impl MqttConfig {
+    // Every event kind gets its own subtopic so props can subscribe selectively
+    fn topic_for(&self, event: &Event) -> String {
+        let suffix = match event {
+            Event::BlocksMined { .. } => "block",
+            Event::PaymentSent { .. } | Event::InternalTransfer { .. } => "tx",
+            Event::WalletReady { .. } => "wallet",
+            Event::ChapterCompleted { .. } => "chapter",
+            Event::AddressActivity { .. } => "watch",
+            Event::BlockConnected { .. } => "chain",
+            Event::TransactionSeen { .. } => "rawtx",
+            Event::WalletTransaction { .. } => "wallet_tx",
+        };
+        format!("{}/{}", self.topic_prefix, suffix)
+    }
+}
+
+pub(crate) struct MqttSink {
+    config: MqttConfig,
+    client: AsyncClient,
+}
+
+impl MqttSink {
+    pub(crate) fn new(config: MqttConfig) -> (Self, EventLoop) {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, eventloop) = AsyncClient::new(options, 64);
+        (Self { config, client }, eventloop)
+    }
+}
+
+#[async_trait(?Send)]
+impl EventSink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn publish(&self, event: &Event) -> Result<(), String> {
+        let topic = self.config.topic_for(event);
+        let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        self.client
+            .publish(topic.as_str(), QoS::AtLeastOnce, false, payload)
+            .await
+            .map_err(|e| e.to_string())?;
+        debug!("Queued MQTT message on '{}'", topic);
+        Ok(())
+    }
+}
+
+// The event loop drives the actual network I/O and must be polled continuously
+pub(crate) async fn drive_connection(mut eventloop: EventLoop) {
+    loop {
+        if let Err(e) = eventloop.poll().await {
+            warn!(
+                "MQTT connection error: {}. Retrying in {:?}",
+                e, RECONNECT_DELAY
+            );
+            actix_rt::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
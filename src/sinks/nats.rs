@@ -0,0 +1,45 @@
+use super::EventSink;
+use crate::events::Event;
+use async_trait::async_trait;
+use log::debug;
+
+#[derive(Debug, Clone)]
+pub(crate) struct NatsConfig {
+    pub(crate) url: String,
+    pub(crate) subject_prefix: String,
+}
+
+pub(crate) struct NatsSink {
+    config: NatsConfig,
+    client: async_nats::Client,
+}
+
+impl NatsSink {
+    // The client keeps retrying in the background if the server is not up yet
+    pub(crate) async fn connect(config: NatsConfig) -> Result<Self, String> {
+        let client = async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect(config.url.as_str())
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Self { config, client })
+    }
+}
+
+#[async_trait(?Send)]
+impl EventSink for NatsSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn publish(&self, event: &Event) -> Result<(), String> {
+        let subject = format!("{}.{}", self.config.subject_prefix, event.kind());
+        let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        self.client
+            .publish(subject.clone(), payload.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        debug!("Published '{}' to NATS subject '{}'", event.kind(), subject);
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use super::EventSink;
+use crate::events::Event;
+use async_trait::async_trait;
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
+use bitcoincore_rpc::bitcoin::secp256k1::{Keypair, Message, Secp256k1};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const TEXT_NOTE_KIND: u32 = 1;
+const RELAY_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub(crate) struct NostrConfig {
+    pub(crate) relays: Vec<String>,
+    pub(crate) secret_key: String,
+    pub(crate) big_payment_sat: u64,
+}
+
+// Keep the secret key out of any debug output
+impl fmt::Debug for NostrConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NostrConfig")
+            .field("relays", &self.relays)
+            .field("big_payment_sat", &self.big_payment_sat)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct NostrSink {
+    config: NostrConfig,
+    keypair: Keypair,
+    secp: Secp256k1<bitcoincore_rpc::bitcoin::secp256k1::All>,
+}
+
+impl NostrSink {
+    pub(crate) fn new(config: NostrConfig) -> Result<Self, String> {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_str(&secp, &config.secret_key)
+            .map_err(|e| format!("invalid nostr secret key: {e}"))?;
+        Ok(Self {
+            config,
+            keypair,
+            secp,
+        })
+    }
+
+    // Only notable story moments are worth a public note
+    fn content_for(&self, event: &Event) -> Option<String> {
+        match event {
+            Event::BlocksMined {
+                wallet,
+                block_hashes,
+                ..
+            } => block_hashes.last().map(|tip| {
+                format!(
+                    "{} mined {} block(s). New chain tip: {}",
+                    wallet,
+                    block_hashes.len(),
+                    tip
+                )
+            }),
+            Event::PaymentSent {
+                wallet,
+                to_address,
+                amount_sat,
+                txid,
+            } if *amount_sat >= self.config.big_payment_sat => Some(format!(
+                "Big payment! {} sent {} sat to {} (txid {})",
+                wallet, amount_sat, to_address, txid
+            )),
+            Event::PaymentSent { .. }
+            | Event::InternalTransfer { .. }
+            | Event::WalletReady { .. }
+            | Event::AddressActivity { .. }
+            | Event::BlockConnected { .. }
+            | Event::TransactionSeen { .. }
+            | Event::WalletTransaction { .. } => None,
+            Event::ChapterCompleted { wallet, chapter } => Some(match wallet {
+                Some(wallet) => format!("{} completed chapter '{}'", wallet, chapter),
+                None => format!("Chapter '{}' completed", chapter),
+            }),
+        }
+    }
+
+    // Builds a NIP-01 text note: id is the sha256 of the canonical serialization,
+    // sig is a BIP-340 schnorr signature over the id
+    fn sign_note(&self, content: &str) -> Value {
+        let pubkey = self.keypair.x_only_public_key().0.to_string();
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let tags = json!([["t", "coincomictales"]]);
+        let canonical = json!([0, pubkey, created_at, TEXT_NOTE_KIND, tags, content]).to_string();
+        let id = sha256::Hash::hash(canonical.as_bytes()).to_byte_array();
+        let sig = self
+            .secp
+            .sign_schnorr_no_aux_rand(&Message::from_digest(id), &self.keypair);
+
+        json!({
+            "id": hex::encode(id),
+            "pubkey": pubkey,
+            "created_at": created_at,
+            "kind": TEXT_NOTE_KIND,
+            "tags": tags,
+            "content": content,
+            "sig": hex::encode(sig.as_ref()),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl EventSink for NostrSink {
+    fn name(&self) -> &'static str {
+        "nostr"
+    }
+
+    async fn publish(&self, event: &Event) -> Result<(), String> {
+        let Some(content) = self.content_for(event) else {
+            return Ok(());
+        };
+        let note = self.sign_note(&content);
+        let message = json!(["EVENT", note]).to_string();
+
+        let mut failures = Vec::new();
+        for relay in &self.config.relays {
+            match publish_to_relay(relay, &message).await {
+                Ok(()) => debug!("Published nostr note {} to {}", note["id"], relay),
+                Err(e) => failures.push(format!("{relay}: {e}")),
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; "))
+        }
+    }
+}
+
+async fn publish_to_relay(relay: &str, message: &str) -> Result<(), String> {
+    let (mut stream, _) = connect_async(relay).await.map_err(|e| e.to_string())?;
+    stream
+        .send(WsMessage::text(message))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Relays answer with ["OK", <id>, <accepted>, <reason>]
+    match actix_rt::time::timeout(RELAY_ACK_TIMEOUT, stream.next()).await {
+        Ok(Some(Ok(WsMessage::Text(ack)))) => debug!("Relay {} answered: {}", relay, ack),
+        Ok(_) => debug!("Relay {} closed without acknowledging", relay),
+        Err(_) => warn!(
+            "Relay {} did not acknowledge within {:?}",
+            relay, RELAY_ACK_TIMEOUT
+        ),
+    }
+    let _ = stream.close(None).await;
+    Ok(())
+}
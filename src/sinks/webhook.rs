@@ -0,0 +1,63 @@
+use super::EventSink;
+use crate::events::Event;
+use crate::proxy::ProxyConfig;
+use async_trait::async_trait;
+use log::debug;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub(crate) struct WebhookConfig {
+    pub(crate) urls: Vec<String>,
+    pub(crate) proxy: Option<ProxyConfig>,
+}
+
+pub(crate) struct WebhookSink {
+    config: WebhookConfig,
+    http: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub(crate) fn new(config: WebhookConfig) -> Result<Self, String> {
+        let mut http = reqwest::Client::builder();
+        if let Some(proxy) = &config.proxy {
+            http = http.proxy(proxy.reqwest_proxy()?);
+        }
+        Ok(Self {
+            config,
+            http: http.build().map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl EventSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    // Every event is POSTed as JSON to each configured URL
+    async fn publish(&self, event: &Event) -> Result<(), String> {
+        let mut failures = Vec::new();
+        for url in &self.config.urls {
+            let result = self
+                .http
+                .post(url)
+                .timeout(REQUEST_TIMEOUT)
+                .json(event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => debug!("Delivered '{}' to webhook {}", event.kind(), url),
+                Err(e) => failures.push(format!("{url}: {e}")),
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; "))
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use crate::balances::BalanceBreakdown;
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::{rpc_pool, ws, AppState};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::Txid;
+use bitcoincore_rpc::json::GetBalancesResult;
+use futures_util::stream;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use utoipa::IntoParams;
+
+// Proxies drop connections that stay silent for too long
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// Transactions get confirmation updates until they are this deep
+const FOLLOWED_CONFIRMATIONS: i32 = 6;
+const MAX_FOLLOWED_TRANSACTIONS: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct EventsQuery {
+    // EventSource cannot set headers either, so the token can come in the URL
+    token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BalanceUpdate<'a> {
+    wallet: &'a str,
+    #[serde(flatten)]
+    balance: BalanceBreakdown,
+}
+
+#[derive(Serialize)]
+struct IncomingTransaction<'a> {
+    wallet: &'a str,
+    txid: &'a str,
+    vout: u32,
+    category: &'a str,
+    amount_sat: i64,
+    confirmations: i32,
+}
+
+#[derive(Serialize)]
+struct ConfirmationUpdate<'a> {
+    wallet: &'a str,
+    txid: String,
+    // Negative once a conflicting transaction confirmed instead
+    confirmations: i32,
+    block_hash: Option<String>,
+    block_height: Option<u32>,
+}
+
+// SSE frames waiting for the stream to be polled
+#[derive(Default)]
+struct Frames {
+    queue: VecDeque<Bytes>,
+    next_id: u64,
+}
+
+impl Frames {
+    fn push<T: Serialize>(&mut self, kind: &str, payload: &T) {
+        let Ok(json) = serde_json::to_string(payload) else {
+            return;
+        };
+        self.next_id += 1;
+        self.queue.push_back(Bytes::from(format!(
+            "id: {}\nevent: {kind}\ndata: {json}\n\n",
+            self.next_id
+        )));
+    }
+}
+
+// A followed transaction's last confirmation count, and the block it is in
+struct Followed {
+    confirmations: i32,
+    block_hash: Option<String>,
+    block_height: Option<u32>,
+}
+
+// One client's view of a wallet
+struct WalletStream {
+    data: web::Data<AppState>,
+    // Name in bitcoind, and as the client knows it
+    wallet: String,
+    shown: String,
+    events: Receiver<Event>,
+    balance: Option<BalanceBreakdown>,
+    // Wallet transactions with fewer than FOLLOWED_CONFIRMATIONS
+    followed: HashMap<Txid, Followed>,
+    // Height of the last block announced, to tell a block on top from a reorg
+    tip: Option<u64>,
+    frames: Frames,
+}
+
+impl WalletStream {
+    fn follow(&mut self, txid: Txid, confirmations: i32) {
+        if (0..FOLLOWED_CONFIRMATIONS).contains(&confirmations)
+            && self.followed.len() < MAX_FOLLOWED_TRANSACTIONS
+        {
+            self.followed.entry(txid).or_insert(Followed {
+                confirmations,
+                block_hash: None,
+                block_height: None,
+            });
+        }
+    }
+
+    // A block on top of the last one adds a confirmation to every confirmed followed
+    // transaction, which needs no RPC. Unconfirmed ones that it mines come in as
+    // wallet transactions.
+    fn deepen_confirmations(&mut self) {
+        let (frames, shown) = (&mut self.frames, &self.shown);
+        self.followed.retain(|txid, tx| {
+            if tx.confirmations <= 0 {
+                return true;
+            }
+            tx.confirmations += 1;
+            let update = ConfirmationUpdate {
+                wallet: shown,
+                txid: txid.to_string(),
+                confirmations: tx.confirmations,
+                block_hash: tx.block_hash.clone(),
+                block_height: tx.block_height,
+            };
+            frames.push("confirmation", &update);
+            tx.confirmations < FOLLOWED_CONFIRMATIONS
+        });
+    }
+
+    // Sends a `balance` frame when the balance differs from the last one sent
+    async fn refresh_balance(&mut self) {
+        let Some(client) = self.data.wallet_client(&self.wallet) else {
+            return;
+        };
+        let balances = rpc_pool::call::<GetBalancesResult>(
+            &self.data,
+            client,
+            &self.wallet,
+            "getbalances",
+            vec![],
+        )
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|balances| balances.map_err(|e| e.to_string()));
+        match balances {
+            Ok(balances) => {
+                let balance = BalanceBreakdown::from(balances);
+                if self.balance != Some(balance) {
+                    self.balance = Some(balance);
+                    let update = BalanceUpdate {
+                        wallet: &self.shown,
+                        balance,
+                    };
+                    self.frames.push("balance", &update);
+                }
+            }
+            Err(e) => warn!(
+                "Event stream cannot read balance of '{}': {}",
+                self.wallet, e
+            ),
+        }
+    }
+
+    // Sends a `confirmation` frame for every followed transaction whose depth changed.
+    // The followed transactions are read in one batch.
+    async fn refresh_confirmations(&mut self) {
+        if self.followed.is_empty() {
+            return;
+        }
+        let Some(client) = self.data.wallet_client(&self.wallet) else {
+            return;
+        };
+        let txids: Vec<Txid> = self.followed.keys().copied().collect();
+        let calls = txids
+            .iter()
+            .map(|txid| ("gettransaction", vec![json!(txid)]))
+            .collect();
+        let results = rpc_pool::batch(&self.data, client, &self.wallet, calls)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|results| results.map_err(|e| e.to_string()));
+        let results = match results {
+            Ok(results) => results,
+            Err(e) => {
+                debug!("Event stream cannot read transactions: {}", e);
+                return;
+            }
+        };
+        for (txid, result) in txids.into_iter().zip(results) {
+            let tx = match result {
+                Ok(tx) => tx,
+                Err(e) => {
+                    debug!("Event stream cannot read transaction {}: {}", txid, e);
+                    continue;
+                }
+            };
+            let confirmations = tx["confirmations"].as_i64().unwrap_or_default() as i32;
+            let block_hash = tx["blockhash"].as_str().map(str::to_string);
+            let block_height = tx["blockheight"].as_u64().map(|height| height as u32);
+            let changed = self
+                .followed
+                .get(&txid)
+                .is_none_or(|followed| followed.confirmations != confirmations);
+            if changed {
+                let update = ConfirmationUpdate {
+                    wallet: &self.shown,
+                    txid: txid.to_string(),
+                    confirmations,
+                    block_hash: block_hash.clone(),
+                    block_height,
+                };
+                self.frames.push("confirmation", &update);
+            }
+            if (0..FOLLOWED_CONFIRMATIONS).contains(&confirmations) {
+                self.followed.insert(
+                    txid,
+                    Followed {
+                        confirmations,
+                        block_hash,
+                        block_height,
+                    },
+                );
+            } else {
+                self.followed.remove(&txid);
+            }
+        }
+    }
+
+    async fn handle(&mut self, event: &Event) {
+        match event {
+            Event::WalletTransaction {
+                wallet,
+                txid,
+                vout,
+                category,
+                amount_sat,
+                confirmations,
+            } if *wallet == self.wallet => {
+                let Ok(parsed) = Txid::from_str(txid) else {
+                    return;
+                };
+                // Incoming payments are announced when they are first seen; a followed
+                // one that confirms gets a confirmation update instead
+                if *amount_sat > 0 && (*confirmations == 0 || !self.followed.contains_key(&parsed))
+                {
+                    let incoming = IncomingTransaction {
+                        wallet: &self.shown,
+                        txid,
+                        vout: *vout,
+                        category,
+                        amount_sat: *amount_sat,
+                        confirmations: *confirmations,
+                    };
+                    self.frames.push("transaction", &incoming);
+                }
+                // Followed first so the refresh records its block
+                self.follow(parsed, *confirmations);
+                self.refresh_confirmations().await;
+                self.refresh_balance().await;
+            }
+            Event::PaymentSent { wallet, txid, .. } if *wallet == self.wallet => {
+                if let Ok(txid) = Txid::from_str(txid) {
+                    self.follow(txid, 0);
+                }
+                self.refresh_balance().await;
+            }
+            // Blocks only cost RPCs when they touch the wallet: a reorg or a gap can
+            // move any transaction, and immature coins may have matured
+            Event::BlockConnected { height, .. } => {
+                let on_top = self.tip.is_some_and(|tip| tip + 1 == *height);
+                self.tip = Some(*height);
+                if on_top {
+                    self.deepen_confirmations();
+                } else {
+                    self.refresh_confirmations().await;
+                }
+                if !on_top || self.balance.is_none_or(|balance| balance.has_immature()) {
+                    self.refresh_balance().await;
+                }
+            }
+            _ if event.wallet() == Some(self.wallet.as_str())
+                || event.counterparty() == Some(self.wallet.as_str()) =>
+            {
+                self.refresh_balance().await
+            }
+            _ => {}
+        }
+    }
+}
+
+// Pushes the wallet's balance changes, incoming transactions and confirmation updates
+// as Server-Sent Events, for frontends that cannot use /ws
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/events",
+    tag = "events",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = "`balance`, `transaction` and `confirmation` events", body = String, content_type = "text/event-stream"),
+    )
+)]
+pub(crate) async fn wallet_events(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/events", walletid);
+    let allowed = ws::granted_wallets(
+        &data,
+        &http_req,
+        query.token.as_deref(),
+        "GET /wallet/events",
+    )?;
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    if allowed
+        .as_ref()
+        .is_some_and(|allowed| !allowed.contains(&wallet_name))
+    {
+        return Err(ApiError::Unauthorized(format!(
+            "The token does not grant access to wallet '{}'",
+            walletid
+        )));
+    }
+    if data.wallet_client(&wallet_name).is_none() {
+        warn!("GET /wallet/{}/events - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    }
+
+    let mut state = WalletStream {
+        events: data.events.subscribe(),
+        data: data.clone(),
+        wallet: wallet_name,
+        shown: walletid.into_inner(),
+        balance: None,
+        followed: HashMap::new(),
+        tip: None,
+        frames: Frames::default(),
+    };
+    // The first frame is the current balance
+    state.refresh_balance().await;
+    let frames = stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(frame) = state.frames.queue.pop_front() {
+                return Some((Ok::<_, actix_web::Error>(frame), state));
+            }
+            match actix_rt::time::timeout(KEEP_ALIVE_INTERVAL, state.events.recv()).await {
+                Err(_) => return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), state)),
+                Ok(Ok(event)) => state.handle(&event).await,
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    warn!("Event stream lagged behind, skipped {} event(s)", skipped);
+                    state.tip = None;
+                    state.refresh_confirmations().await;
+                    state.refresh_balance().await;
+                }
+                Ok(Err(RecvError::Closed)) => return None,
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Keeps nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(frames))
+}
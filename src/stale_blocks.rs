@@ -0,0 +1,239 @@
+use crate::channel::node_client;
+use crate::errors::ApiError;
+use crate::explorer::{block, BlockResponse};
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::rpc_pool::{self, Caller};
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::BlockHash;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct StaleBlocksQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StaleBlock {
+    // getchaintips status of the branch: valid-fork, valid-headers or invalid
+    // (e.g. after invalidateblock)
+    branch_status: String,
+    // Last block the branch shares with the active chain
+    fork_height: u64,
+    branch_length: u64,
+    // When the server's nodes first had the block, in Unix milliseconds
+    first_seen_ms: Option<i64>,
+    block: BlockResponse,
+    // The active chain's block at the same height, if the chain is that long yet
+    winner: Option<BlockResponse>,
+    // Transactions of one block but not the other, coinbases left out
+    only_in_stale: Vec<String>,
+    only_in_winner: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StaleBlocksResponse {
+    tip_height: u64,
+    // Highest first
+    #[serde(flatten)]
+    page: Page<StaleBlock>,
+}
+
+// A branch off the active chain: its tip, its length and its status
+struct Branch {
+    tip: BlockHash,
+    height: u64,
+    length: u64,
+    status: String,
+}
+
+fn branches(node: &Client) -> Result<Vec<Branch>, RpcError> {
+    let tips: Vec<Value> = node.call("getchaintips", &[])?;
+    Ok(tips
+        .iter()
+        // headers-only branches have no block data to show
+        .filter(|tip| !matches!(tip["status"].as_str(), Some("active" | "headers-only")))
+        .filter_map(|tip| {
+            Some(Branch {
+                tip: BlockHash::from_str(tip["hash"].as_str()?).ok()?,
+                height: tip["height"].as_u64()?,
+                length: tip["branchlen"].as_u64()?,
+                status: tip["status"].as_str()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+fn first_seen(data: &AppState, hash: &BlockHash) -> Option<i64> {
+    match data.db.block_sightings(&hash.to_string()) {
+        Ok(sightings) => sightings.iter().map(|sighting| sighting.seen_at_ms).min(),
+        Err(e) => {
+            warn!("Failed to read sightings of block {}: {}", hash, e);
+            None
+        }
+    }
+}
+
+fn difference(block: &BlockResponse, other: Option<&BlockResponse>) -> Vec<String> {
+    let others: HashSet<&String> = other.into_iter().flat_map(|other| &other.txids).collect();
+    block
+        .txids
+        .iter()
+        .skip(1)
+        .filter(|txid| !others.contains(txid))
+        .cloned()
+        .collect()
+}
+
+fn block_at(
+    data: &AppState,
+    caller: &Caller,
+    node: &Client,
+    height: u64,
+) -> Result<BlockResponse, RpcError> {
+    let hash = node.get_block_hash(height)?;
+    block(data, caller, node, &hash)
+}
+
+// A block of a branch, found by walking the branch's headers
+struct BranchBlock<'a> {
+    branch: &'a Branch,
+    hash: BlockHash,
+    height: u64,
+}
+
+fn cursor(block: &BranchBlock) -> Cursor {
+    Cursor::new(block.height as i64, block.hash.to_string())
+}
+
+// Walks each branch back from its tip over the headers, which are cheap to read, and
+// only reads the blocks that make the page. Branches left behind by resetting the
+// chain can be long, so each walk stops once it has a page's worth of blocks.
+fn stale_blocks(
+    data: &AppState,
+    caller: &Caller,
+    node: &Client,
+    request: &PageRequest,
+) -> Result<StaleBlocksResponse, RpcError> {
+    let tip_height = node.get_block_count()?;
+    let branches = branches(node)?;
+    let mut candidates = Vec::new();
+    for branch in &branches {
+        let mut hash = branch.tip;
+        let mut found = 0;
+        for depth in 0..branch.length {
+            let candidate = BranchBlock {
+                branch,
+                hash,
+                height: branch.height - depth,
+            };
+            if request.is_older(&cursor(&candidate)) {
+                candidates.push(candidate);
+                found += 1;
+                if found == request.fetch() {
+                    break;
+                }
+            }
+            match node.get_block_header_info(&hash) {
+                Ok(header) => match header.previous_block_hash {
+                    Some(previous) => hash = previous,
+                    None => break,
+                },
+                Err(e) => {
+                    warn!("Cannot read stale block header {}: {}", hash, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    let page = request.paginate(candidates, cursor);
+    let mut stale_blocks = Vec::new();
+    for BranchBlock { branch, hash, .. } in page.items {
+        // The node may no longer have the data, e.g. after pruning
+        let block = match block(data, caller, node, &hash) {
+            Ok(block) => block,
+            Err(e) => {
+                warn!("Cannot read stale block {}: {}", hash, e);
+                continue;
+            }
+        };
+        let winner = if block.height <= tip_height {
+            Some(block_at(data, caller, node, block.height)?)
+        } else {
+            None
+        };
+        stale_blocks.push(StaleBlock {
+            branch_status: branch.status.clone(),
+            fork_height: branch.height.saturating_sub(branch.length),
+            branch_length: branch.length,
+            first_seen_ms: first_seen(data, &hash),
+            only_in_stale: difference(&block, winner.as_ref()),
+            only_in_winner: winner
+                .as_ref()
+                .map(|winner| difference(winner, Some(&block)))
+                .unwrap_or_default(),
+            block,
+            winner,
+        });
+    }
+    Ok(StaleBlocksResponse {
+        tip_height,
+        page: Page {
+            items: stale_blocks,
+            next_cursor: page.next_cursor,
+        },
+    })
+}
+
+// Blocks that lost a reorg race, from the node's chain tips, with the block that won
+// and what the server recorded about both
+#[utoipa::path(
+    get,
+    path = "/chain/stale-blocks",
+    tag = "stale-blocks",
+    params(StaleBlocksQuery),
+    responses(
+        (status = 200, body = StaleBlocksResponse),
+    )
+)]
+pub(crate) async fn get_stale_blocks(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<StaleBlocksQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /chain/stale-blocks - limit={:?}, cursor={:?}",
+        query.limit, query.cursor
+    );
+    let request = PageRequest::new(
+        query.limit,
+        query.cursor.as_deref(),
+        DEFAULT_LIMIT,
+        MAX_LIMIT,
+    )?;
+    let node = node_client(&data, &http_req)?;
+    let caller = Caller::of(&data, &http_req);
+    let listed = rpc_pool::with_state(&data, move |data| {
+        stale_blocks(data, &caller, &node, &request)
+    })
+    .await?;
+    match listed {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            error!("Failed to list stale blocks: {}", e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
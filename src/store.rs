@@ -0,0 +1,525 @@
+use async_trait::async_trait;
+use bitcoincore_rpc::Error as RpcError;
+use dashmap::DashMap;
+use dotenv as env;
+use log::{info, warn};
+use redis::aio::ConnectionManager;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Shared state (idempotency keys, session data, ...) that must be
+// consistent across server replicas lives behind this trait
+#[async_trait]
+pub(crate) trait SharedStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, String>;
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), String>;
+
+    // Returns false when the key already exists
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, String>;
+
+    // Every key that starts with the prefix
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, String>;
+
+    async fn delete(&self, key: &str) -> Result<(), String>;
+
+    // Moves the expiry of an existing key to `ttl` from now; a missing key stays missing
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), String>;
+
+    // Deletes every key that starts with the prefix
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), String>;
+
+    // Adds delta to a counter and returns the new value. The ttl only applies
+    // when the counter is created.
+    async fn increment_by(&self, key: &str, delta: i64, ttl: Duration) -> Result<i64, String>;
+
+    // Token bucket of `burst` tokens that gains one every `interval`. Takes a token, or
+    // says how long until the next one. A full bucket is the same as no key at all.
+    async fn take_token(
+        &self,
+        key: &str,
+        burst: u32,
+        interval: Duration,
+    ) -> Result<Result<(), Duration>, String>;
+
+    // Frees the expired keys of stores that do not expire them on their own
+    async fn delete_expired(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum StateBackend {
+    Memory,
+    Redis { url: String, key_prefix: String },
+}
+
+impl StateBackend {
+    pub(crate) fn from_env() -> Result<Self, RpcError> {
+        match env::var("state_backend").as_deref() {
+            Err(_) | Ok("memory") => Ok(Self::Memory),
+            Ok("redis") => Ok(Self::Redis {
+                url: env::var("redis_url").map_err(|_| {
+                    RpcError::ReturnedError(
+                        "state_backend is redis but redis_url is missing".into(),
+                    )
+                })?,
+                key_prefix: env::var("redis_key_prefix")
+                    .unwrap_or_else(|_| "coin-comic-tales:".into()),
+            }),
+            Ok(other) => Err(RpcError::ReturnedError(format!(
+                "unknown state_backend '{other}', expected memory or redis"
+            ))),
+        }
+    }
+}
+
+pub(crate) async fn connect(backend: &StateBackend) -> Result<Arc<dyn SharedStore>, String> {
+    match backend {
+        StateBackend::Memory => {
+            info!("Using in-memory shared state");
+            Ok(Arc::new(MemoryStore::default()))
+        }
+        StateBackend::Redis { url, key_prefix } => {
+            info!("Using Redis shared state at {}", url);
+            let client = redis::Client::open(url.as_str()).map_err(|e| e.to_string())?;
+            let connection = ConnectionManager::new(client)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(Arc::new(RedisStore {
+                connection,
+                key_prefix: key_prefix.clone(),
+            }))
+        }
+    }
+}
+
+struct MemoryEntry {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl MemoryEntry {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Instant::now())
+    }
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    entries: DashMap<String, MemoryEntry>,
+}
+
+impl MemoryStore {
+    fn drop_if_expired(&self, key: &str) {
+        self.entries.remove_if(key, |_, entry| entry.is_expired());
+    }
+}
+
+#[async_trait]
+impl SharedStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, String> {
+        self.drop_if_expired(key);
+        Ok(self.entries.get(key).map(|entry| entry.value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), String> {
+        self.entries.insert(
+            key.to_string(),
+            MemoryEntry {
+                value: value.to_string(),
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+            },
+        );
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, String> {
+        self.drop_if_expired(key);
+        let mut inserted = false;
+        self.entries.entry(key.to_string()).or_insert_with(|| {
+            inserted = true;
+            MemoryEntry {
+                value: value.to_string(),
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+            }
+        });
+        Ok(inserted)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), String> {
+        self.drop_if_expired(key);
+        if let Some(mut entry) = self.entries.get_mut(key) {
+            entry.expires_at = Some(Instant::now() + ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), String> {
+        self.entries.retain(|key, _| !key.starts_with(prefix));
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .entries
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix) && !entry.is_expired())
+            .map(|entry| entry.key().clone())
+            .collect())
+    }
+
+    async fn increment_by(&self, key: &str, delta: i64, ttl: Duration) -> Result<i64, String> {
+        self.drop_if_expired(key);
+        let mut entry = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| MemoryEntry {
+                value: "0".into(),
+                expires_at: Some(Instant::now() + ttl),
+            });
+        let next = entry
+            .value
+            .parse::<i64>()
+            .map_err(|_| format!("key '{key}' does not hold a counter"))?
+            + delta;
+        entry.value = next.to_string();
+        Ok(next)
+    }
+
+    // The entry expires when the bucket is full again, which is all the state it needs
+    async fn take_token(
+        &self,
+        key: &str,
+        burst: u32,
+        interval: Duration,
+    ) -> Result<Result<(), Duration>, String> {
+        let now = Instant::now();
+        let mut entry = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| MemoryEntry {
+                value: String::new(),
+                expires_at: Some(now),
+            });
+        let full_at = entry.expires_at.unwrap_or(now).max(now) + interval;
+        let wait = (full_at - now).saturating_sub(interval * burst);
+        if !wait.is_zero() {
+            return Ok(Err(wait));
+        }
+        entry.expires_at = Some(full_at);
+        Ok(Ok(()))
+    }
+
+    async fn delete_expired(&self) -> Result<(), String> {
+        self.entries.retain(|_, entry| !entry.is_expired());
+        Ok(())
+    }
+}
+
+// The same bucket as MemoryStore::take_token: the key holds the time, in microseconds
+// of the Redis clock, at which the bucket is full again. Returns the wait, 0 if a token
+// was taken.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+local interval = tonumber(ARGV[1])
+local full_at = math.max(tonumber(redis.call('GET', KEYS[1]) or now), now) + interval
+local wait = full_at - now - interval * tonumber(ARGV[2])
+if wait > 0 then
+  return wait
+end
+redis.call('SET', KEYS[1], string.format('%d', full_at), 'PX', math.ceil((full_at - now) / 1000))
+return 0
+"#;
+
+// Keys looked at per SCAN round trip
+const SCAN_COUNT: usize = 500;
+
+// So a prefix matches itself literally in a SCAN pattern
+fn glob_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+struct RedisStore {
+    connection: ConnectionManager,
+    key_prefix: String,
+}
+
+impl RedisStore {
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+
+    // The Redis keys, with the key prefix, that start with the prefix
+    async fn scan(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let pattern = format!("{}*", glob_escape(&self.key(prefix)));
+        let mut found = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, keys): (u64, Vec<String>) = self
+                .query(
+                    redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(&pattern)
+                        .arg("COUNT")
+                        .arg(SCAN_COUNT),
+                )
+                .await?;
+            found.extend(keys);
+            if next == 0 {
+                return Ok(found);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn query<T: redis::FromRedisValue>(&self, cmd: &redis::Cmd) -> Result<T, String> {
+        // ConnectionManager is a cheap handle onto a shared multiplexed connection
+        let mut connection = self.connection.clone();
+        cmd.query_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl SharedStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, String> {
+        self.query(redis::cmd("GET").arg(self.key(key))).await
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), String> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.key(key)).arg(value);
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis() as u64);
+        }
+        self.query(&cmd).await
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, String> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.key(key)).arg(value).arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis() as u64);
+        }
+        let reply: Option<String> = self.query(&cmd).await?;
+        Ok(reply.is_some())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.query(redis::cmd("DEL").arg(self.key(key))).await
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), String> {
+        self.query(
+            redis::cmd("PEXPIRE")
+                .arg(self.key(key))
+                .arg(ttl.as_millis() as u64),
+        )
+        .await
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), String> {
+        for keys in self.scan(prefix).await?.chunks(SCAN_COUNT) {
+            self.query::<()>(redis::cmd("DEL").arg(keys)).await?;
+        }
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .scan(prefix)
+            .await?
+            .into_iter()
+            .filter_map(|key| Some(key.strip_prefix(&self.key_prefix)?.to_string()))
+            .collect())
+    }
+
+    async fn increment_by(&self, key: &str, delta: i64, ttl: Duration) -> Result<i64, String> {
+        let key = self.key(key);
+        let value: i64 = self
+            .query(redis::cmd("INCRBY").arg(&key).arg(delta))
+            .await?;
+        // A counter without expiry was just created by INCRBY
+        let remaining: i64 = self.query(redis::cmd("PTTL").arg(&key)).await?;
+        if remaining == -1 {
+            self.query::<()>(redis::cmd("PEXPIRE").arg(&key).arg(ttl.as_millis() as u64))
+                .await?;
+        }
+        Ok(value)
+    }
+
+    async fn take_token(
+        &self,
+        key: &str,
+        burst: u32,
+        interval: Duration,
+    ) -> Result<Result<(), Duration>, String> {
+        let wait_us: u64 = self
+            .query(
+                redis::cmd("EVAL")
+                    .arg(TAKE_TOKEN_SCRIPT)
+                    .arg(1)
+                    .arg(self.key(key))
+                    .arg(interval.as_micros() as u64)
+                    .arg(burst),
+            )
+            .await?;
+        Ok(match wait_us {
+            0 => Ok(()),
+            wait_us => Err(Duration::from_micros(wait_us)),
+        })
+    }
+}
+
+// Every idempotency key starts with this, so a reset can drop them all
+pub(crate) const IDEMPOTENCY_PREFIX: &str = "idempotency:";
+const IDEMPOTENCY_PENDING: &str = "__pending__";
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Keeps the in-memory store from collecting keys nobody reads again
+pub(crate) async fn sweep_expired(store: Arc<dyn SharedStore>) {
+    loop {
+        actix_rt::time::sleep(SWEEP_INTERVAL).await;
+        if let Err(e) = store.delete_expired().await {
+            warn!("Failed to drop expired shared state: {}", e);
+        }
+    }
+}
+
+pub(crate) enum Idempotency {
+    // First time this key is seen, the caller must finish or abandon it
+    Started,
+    Completed(String),
+    InProgress,
+}
+
+pub(crate) async fn begin_idempotent(
+    store: &dyn SharedStore,
+    key: &str,
+    ttl: Duration,
+) -> Result<Idempotency, String> {
+    if store
+        .set_if_absent(key, IDEMPOTENCY_PENDING, Some(ttl))
+        .await?
+    {
+        return Ok(Idempotency::Started);
+    }
+    match store.get(key).await? {
+        Some(result) if result != IDEMPOTENCY_PENDING => Ok(Idempotency::Completed(result)),
+        _ => Ok(Idempotency::InProgress),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[actix_rt::test]
+    async fn keys_lists_live_entries_under_a_prefix() {
+        let store = MemoryStore::default();
+        assert!(store.set_if_absent("watch:a", "1", None).await.unwrap());
+        assert!(!store.set_if_absent("watch:a", "2", None).await.unwrap());
+        store
+            .set("watch:b", "1", Some(Duration::from_millis(10)))
+            .await
+            .unwrap();
+        store.set("other", "1", None).await.unwrap();
+        let mut keys = store.keys("watch:").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["watch:a", "watch:b"]);
+        actix_rt::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.keys("watch:").await.unwrap(), ["watch:a"]);
+        assert_eq!(store.get("watch:a").await.unwrap().as_deref(), Some("1"));
+    }
+
+    #[actix_rt::test]
+    async fn expire_extends_only_existing_keys() {
+        let store = MemoryStore::default();
+        let ttl = Duration::from_millis(30);
+        store.increment_by("counter", 5, ttl).await.unwrap();
+        actix_rt::time::sleep(Duration::from_millis(20)).await;
+        store.expire("counter", ttl).await.unwrap();
+        actix_rt::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.get("counter").await.unwrap().as_deref(), Some("5"));
+        store.expire("missing", ttl).await.unwrap();
+        assert_eq!(store.get("missing").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn take_token_allows_a_burst() {
+        let store = MemoryStore::default();
+        for _ in 0..3 {
+            assert_eq!(store.take_token("ip", 3, HOUR).await.unwrap(), Ok(()));
+        }
+        let wait = store.take_token("ip", 3, HOUR).await.unwrap().unwrap_err();
+        assert!(wait > HOUR - Duration::from_secs(1) && wait <= HOUR);
+        // Other keys have their own bucket
+        assert_eq!(store.take_token("other", 3, HOUR).await.unwrap(), Ok(()));
+    }
+
+    #[actix_rt::test]
+    async fn take_token_refills_over_time() {
+        let store = MemoryStore::default();
+        let interval = Duration::from_millis(50);
+        assert_eq!(store.take_token("ip", 1, interval).await.unwrap(), Ok(()));
+        assert!(store.take_token("ip", 1, interval).await.unwrap().is_err());
+        actix_rt::time::sleep(interval).await;
+        assert_eq!(store.take_token("ip", 1, interval).await.unwrap(), Ok(()));
+    }
+
+    #[actix_rt::test]
+    async fn a_refused_token_is_not_taken() {
+        let store = MemoryStore::default();
+        let interval = Duration::from_millis(50);
+        store.take_token("ip", 1, interval).await.unwrap().unwrap();
+        // Asking again while empty does not push the refill further out
+        for _ in 0..5 {
+            assert!(store.take_token("ip", 1, interval).await.unwrap().is_err());
+        }
+        actix_rt::time::sleep(interval).await;
+        assert_eq!(store.take_token("ip", 1, interval).await.unwrap(), Ok(()));
+    }
+
+    #[actix_rt::test]
+    async fn a_full_bucket_expires() {
+        let store = MemoryStore::default();
+        let interval = Duration::from_millis(20);
+        store.take_token("ip", 2, interval).await.unwrap().unwrap();
+        actix_rt::time::sleep(interval).await;
+        store.delete_expired().await.unwrap();
+        assert!(store.entries.is_empty());
+    }
+}
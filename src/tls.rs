@@ -0,0 +1,71 @@
+use bitcoincore_rpc::Error as RpcError;
+use dotenv as env;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// HTTPS for the TCP listeners, so browsers can reach the API without a reverse proxy
+#[derive(Clone)]
+pub(crate) struct TlsConfig {
+    cert: PathBuf,
+    server_config: ServerConfig,
+}
+
+// The loaded config holds the private key
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("cert", &self.cert)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsConfig {
+    // `tls_cert` is a PEM certificate chain, leaf first, and `tls_key` its PEM private
+    // key. Both are read at startup, so a bad file stops the server right away.
+    pub(crate) fn from_env() -> Result<Option<Self>, RpcError> {
+        let (cert, key) = match (env::var("tls_cert"), env::var("tls_key")) {
+            (Err(_), Err(_)) => return Ok(None),
+            (Ok(cert), Ok(key)) => (PathBuf::from(cert.trim()), PathBuf::from(key.trim())),
+            _ => {
+                return Err(RpcError::ReturnedError(
+                    "tls_cert and tls_key must be set together".into(),
+                ))
+            }
+        };
+        let chain = CertificateDer::pem_file_iter(&cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| {
+                RpcError::ReturnedError(format!("cannot read tls_cert '{}': {e}", cert.display()))
+            })?;
+        if chain.is_empty() {
+            return Err(RpcError::ReturnedError(format!(
+                "tls_cert '{}' holds no certificate",
+                cert.display()
+            )));
+        }
+        let private_key = PrivateKeyDer::from_pem_file(&key).map_err(|e| {
+            RpcError::ReturnedError(format!("cannot read tls_key '{}': {e}", key.display()))
+        })?;
+        let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| {
+                builder
+                    .with_no_client_auth()
+                    .with_single_cert(chain, private_key)
+            })
+            .map_err(|e| RpcError::ReturnedError(format!("invalid TLS certificate or key: {e}")))?;
+        Ok(Some(Self {
+            cert,
+            server_config,
+        }))
+    }
+
+    pub(crate) fn server_config(&self) -> ServerConfig {
+        self.server_config.clone()
+    }
+}
@@ -0,0 +1,152 @@
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::fees::{send_to_address_params, FeeOptions};
+use crate::keypool;
+use crate::reservations::{self, ReservationOptions};
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Amount, Txid};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::RpcApi;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct TransferRequest {
+    from_wallet: String,
+    to_wallet: String,
+    amount: u64,
+    // Kept as the comment in the sender's wallet
+    message: Option<String>,
+    #[serde(flatten)]
+    fees: FeeOptions,
+    #[serde(flatten)]
+    reservations: ReservationOptions,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TransferResponse {
+    txid: String,
+    from_wallet: String,
+    to_wallet: String,
+    // The fresh receive address of the destination wallet
+    to_address: String,
+    amount_sat: u64,
+}
+
+// Pays one wallet of the server from another without the caller fetching an
+// address first. The destination address is labeled with the sender's name.
+#[utoipa::path(
+    post,
+    path = "/transfer",
+    tag = "transfer",
+    responses(
+        (status = 200, body = TransferResponse),
+    )
+)]
+pub(crate) async fn transfer(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<TransferRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /transfer - from='{}', to='{}', amount_sat={}",
+        req.from_wallet, req.to_wallet, req.amount
+    );
+    if req.from_wallet == req.to_wallet {
+        return Err(ApiError::InvalidRequest(
+            "from_wallet and to_wallet must be different wallets".into(),
+        ));
+    }
+    req.fees.validate()?;
+    let from_wallet = data.wallet_name(&http_req, &req.from_wallet);
+    let to_wallet = data.wallet_name(&http_req, &req.to_wallet);
+    let Some(from_client) = data.wallet_client(&from_wallet) else {
+        warn!("POST /transfer - wallet '{}' not found", req.from_wallet);
+        return Err(ApiError::WalletNotFound(req.from_wallet.clone()));
+    };
+    let Some(to_client) = data.wallet_client(&to_wallet) else {
+        warn!("POST /transfer - wallet '{}' not found", req.to_wallet);
+        return Err(ApiError::WalletNotFound(req.to_wallet.clone()));
+    };
+    reservations::check_spend(
+        &data,
+        &http_req,
+        &from_wallet,
+        req.amount,
+        &req.reservations,
+    )
+    .await?;
+
+    let label = format!("transfer from {}", req.from_wallet);
+    let (network, address_label, shown) =
+        (data.config.network, label.clone(), req.to_wallet.clone());
+    let to_address = rpc_pool::blocking(move || {
+        let address = to_client
+            .get_new_address(Some(&address_label), Some(AddressType::Bech32))
+            .and_then(|address| {
+                address
+                    .require_network(network)
+                    .map_err(|e| bitcoincore_rpc::Error::ReturnedError(e.to_string()))
+            });
+        keypool::warn_if_low(&to_client, &shown);
+        address
+    })
+    .await?
+    .map_err(|e| {
+        error!(
+            "Failed to get new address for wallet '{}': {}",
+            req.to_wallet, e
+        );
+        ApiError::rpc(&http_req, e)
+    })?;
+    if let Err(e) = data
+        .db
+        .set_label(&to_wallet, &to_address.to_string(), &label)
+    {
+        error!(
+            "Failed to save address '{}' in address book: {}",
+            to_address, e
+        );
+    }
+
+    let comment = req
+        .message
+        .clone()
+        .unwrap_or_else(|| format!("transfer to {}", req.to_wallet));
+    let amount = Amount::from_sat(req.amount);
+    let params = send_to_address_params(&to_address, amount, Some(&comment), &req.fees);
+    let sent = rpc_pool::call::<Txid>(&data, from_client, &from_wallet, "sendtoaddress", params);
+    let txid = match sent.await? {
+        Ok(txid) => txid,
+        Err(e) => {
+            error!(
+                "Failed to transfer from wallet '{}' to '{}': {}",
+                req.from_wallet, req.to_wallet, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+
+    info!(
+        "Transferred {} sat from '{}' to '{}' txid={}",
+        req.amount, req.from_wallet, req.to_wallet, txid
+    );
+    reservations::consume(&data, &from_wallet, req.amount, &req.reservations);
+    data.events.publish(Event::InternalTransfer {
+        wallet: from_wallet,
+        to_wallet,
+        to_address: to_address.to_string(),
+        amount_sat: req.amount,
+        txid: txid.to_string(),
+    });
+    Ok(HttpResponse::Ok().json(TransferResponse {
+        txid: txid.to_string(),
+        from_wallet: req.from_wallet.clone(),
+        to_wallet: req.to_wallet.clone(),
+        to_address: to_address.to_string(),
+        amount_sat: req.amount,
+    }))
+}
@@ -0,0 +1,515 @@
+use crate::db::{unix_now, TxWatcher};
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::{rpc_pool, AppState, Config};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::Txid;
+use bitcoincore_rpc::RpcApi;
+use dotenv as env;
+use log::{debug, error, info, warn};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+// Checked on every new block, and on this interval for nodes without ZMQ
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_CONFIRMATIONS: u32 = 1;
+const MAX_CONFIRMATIONS: u32 = 1000;
+// Deliveries are retried on the next checks, then the watcher gives up
+const MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_NOTIFIED: &str = "notified";
+const STATUS_FAILED: &str = "failed";
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct WatchTxRequest {
+    txid: String,
+    wallet: String,
+    confirmations: Option<u32>,
+    webhook_url: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct TxWatchersQuery {
+    status: Option<String>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+// Body POSTed to the webhook
+#[derive(Serialize)]
+struct TxConfirmed<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    watcher_id: &'a str,
+    wallet: &'a str,
+    txid: &'a str,
+    confirmations: u32,
+    required_confirmations: u32,
+    block_hash: Option<String>,
+    block_height: Option<u32>,
+}
+
+// `webhook_allowed_hosts`: hosts that may be private, e.g. a frontend on localhost
+pub(crate) fn allowed_hosts_from_env() -> Arc<HashSet<String>> {
+    let hosts = env::var("webhook_allowed_hosts").unwrap_or_default();
+    Arc::new(
+        hosts
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect(),
+    )
+}
+
+// Webhooks are given by API callers, so they must not reach the server's own network:
+// loopback, private, link-local and other non-routable addresses are refused
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Shared address space (carrier-grade NAT)
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+// Resolves webhook hosts to their public addresses only, so a name cannot be pointed
+// at a private address after the watcher was accepted
+struct PublicResolver {
+    allowed: Arc<HashSet<String>>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowed = self.allowed.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = public_addrs(&allowed, &host, 0).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn public_addrs(
+    allowed: &HashSet<String>,
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("cannot resolve {host}: {e}"))?
+        .collect();
+    if allowed.contains(&host.to_ascii_lowercase()) {
+        return Ok(addrs);
+    }
+    let public: Vec<SocketAddr> = addrs
+        .into_iter()
+        .filter(|addr| is_public(addr.ip()))
+        .collect();
+    if public.is_empty() {
+        return Err(format!("{host} does not resolve to a public address"));
+    }
+    Ok(public)
+}
+
+// Checked when the watcher is created; deliveries go through PublicResolver again
+async fn check_webhook_url(allowed: &HashSet<String>, url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|_| "webhook_url must be a URL".to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("webhook_url must be an http or https URL".into());
+    }
+    // Literal addresses skip the resolver, so they are checked here and in deliver
+    if let Some(ip) = literal_ip(&url) {
+        return check_literal(allowed, ip);
+    }
+    let host = url.host_str().ok_or("webhook_url needs a host")?;
+    let port = url.port_or_known_default().unwrap_or_default();
+    public_addrs(allowed, host, port).await.map(|_| ())
+}
+
+fn literal_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+fn check_literal(allowed: &HashSet<String>, ip: IpAddr) -> Result<(), String> {
+    if is_public(ip) || allowed.contains(&ip.to_string()) {
+        Ok(())
+    } else {
+        Err(format!("{ip} is not a public address"))
+    }
+}
+
+fn http_client(config: &Config) -> Result<reqwest::Client, String> {
+    let mut http = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        // A redirect could lead anywhere, private addresses included
+        .redirect(Policy::none())
+        .dns_resolver(Arc::new(PublicResolver {
+            allowed: config.webhook_allowed_hosts.clone(),
+        }));
+    if let Some(proxy) = &config.proxy {
+        http = http.proxy(proxy.reqwest_proxy()?);
+    }
+    http.build().map_err(|e| e.to_string())
+}
+
+// In sandbox mode wallets are shown without their session prefix
+fn shown(data: &AppState, http_req: &HttpRequest, mut watcher: TxWatcher) -> TxWatcher {
+    let prefix = data.wallet_name(http_req, "");
+    if let Some(wallet) = watcher.wallet.strip_prefix(&prefix) {
+        watcher.wallet = wallet.to_string();
+    }
+    watcher
+}
+
+// Looks up a watcher of the caller's session
+fn find_watcher(data: &AppState, http_req: &HttpRequest, id: &str) -> Result<TxWatcher, ApiError> {
+    let prefix = data.wallet_name(http_req, "");
+    data.db
+        .tx_watcher(id)?
+        .filter(|watcher| watcher.wallet.starts_with(&prefix))
+        .ok_or_else(|| ApiError::NotFound(format!("Transaction watcher '{}' not found", id)))
+}
+
+// Waits for the next block; a closed bus leaves the polling to the caller's timeout
+async fn next_block(events: &mut Receiver<Event>) {
+    loop {
+        match events.recv().await {
+            Ok(Event::BlockConnected { .. }) | Err(RecvError::Lagged(_)) => return,
+            Ok(_) => {}
+            Err(RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+// What the webhook is sent once the transaction is deep enough, or why the watcher
+// has to give up
+enum Check {
+    Waiting,
+    Confirmed(String),
+    Failed(String),
+}
+
+fn check(data: &AppState, watcher: &TxWatcher) -> Check {
+    let Some(client) = data.wallet_client(&watcher.wallet) else {
+        // Registered wallets may not be restored yet after a restart
+        debug!(
+            "Wallet '{}' of transaction watcher {} is not registered",
+            watcher.wallet, watcher.id
+        );
+        return Check::Waiting;
+    };
+    let tx = match Txid::from_str(&watcher.txid) {
+        Ok(txid) => client.get_transaction(&txid, None),
+        Err(e) => return Check::Failed(e.to_string()),
+    };
+    let tx = match tx {
+        Ok(tx) => tx,
+        Err(e) => {
+            warn!(
+                "Cannot check transaction {} of watcher {}: {}",
+                watcher.txid, watcher.id, e
+            );
+            return Check::Waiting;
+        }
+    };
+    // Negative confirmations mean a conflicting transaction was mined instead
+    if tx.info.confirmations < 0 {
+        return Check::Failed("the transaction was replaced by a conflicting one".into());
+    }
+    let confirmations = tx.info.confirmations as u32;
+    if confirmations < watcher.confirmations {
+        return Check::Waiting;
+    }
+    let body = TxConfirmed {
+        kind: "tx_confirmed",
+        watcher_id: &watcher.id,
+        wallet: &watcher.wallet,
+        txid: &watcher.txid,
+        confirmations,
+        required_confirmations: watcher.confirmations,
+        block_hash: tx.info.blockhash.map(|hash| hash.to_string()),
+        block_height: tx.info.blockheight,
+    };
+    match serde_json::to_string(&body) {
+        Ok(body) => Check::Confirmed(body),
+        Err(e) => Check::Failed(e.to_string()),
+    }
+}
+
+async fn deliver(
+    data: &AppState,
+    http: &reqwest::Client,
+    url: &str,
+    body: String,
+) -> Result<(), String> {
+    if let Some(ip) = reqwest::Url::parse(url).ok().as_ref().and_then(literal_ip) {
+        check_literal(&data.config.webhook_allowed_hosts, ip)?;
+    }
+    http.post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+async fn check_watchers(data: &web::Data<AppState>, http: &reqwest::Client) {
+    let watchers = match data.db.tx_watchers(&[STATUS_PENDING]) {
+        Ok(watchers) => watchers,
+        Err(e) => {
+            error!("Failed to load transaction watchers: {}", e);
+            return;
+        }
+    };
+    // The transactions are looked up on the blocking pool, the webhooks are awaited
+    let checked = rpc_pool::with_state(data, move |data| {
+        watchers
+            .into_iter()
+            .map(|watcher| {
+                let check = check(data, &watcher);
+                (watcher, check)
+            })
+            .collect::<Vec<_>>()
+    });
+    let checked = match checked.await {
+        Ok(checked) => checked,
+        Err(e) => {
+            error!("Failed to check transaction watchers: {}", e);
+            return;
+        }
+    };
+    for (watcher, check) in checked {
+        let result = match check {
+            Check::Waiting => continue,
+            Check::Failed(reason) => {
+                warn!("Transaction watcher {} failed: {}", watcher.id, reason);
+                data.db
+                    .update_tx_watcher(&watcher.id, STATUS_FAILED, Some(&reason), false)
+            }
+            Check::Confirmed(body) => match deliver(data, http, &watcher.webhook_url, body).await {
+                Ok(()) => {
+                    info!(
+                        "Notified {} that {} has {} confirmation(s)",
+                        watcher.webhook_url, watcher.txid, watcher.confirmations
+                    );
+                    data.db
+                        .update_tx_watcher(&watcher.id, STATUS_NOTIFIED, None, false)
+                }
+                Err(e) => {
+                    warn!(
+                        "Webhook {} of transaction watcher {} failed: {}",
+                        watcher.webhook_url, watcher.id, e
+                    );
+                    let status = if watcher.attempts + 1 >= MAX_ATTEMPTS {
+                        STATUS_FAILED
+                    } else {
+                        STATUS_PENDING
+                    };
+                    data.db
+                        .update_tx_watcher(&watcher.id, status, Some(&e), true)
+                }
+            },
+        };
+        if let Err(e) = result {
+            error!("Failed to update transaction watcher {}: {}", watcher.id, e);
+        }
+    }
+}
+
+pub(crate) async fn run_tx_watchers(data: web::Data<AppState>) {
+    let http = match http_client(&data.config) {
+        Ok(http) => http,
+        Err(e) => {
+            error!("Transaction watchers are disabled: {}", e);
+            return;
+        }
+    };
+    let mut events = data.events.subscribe();
+    loop {
+        let _ = actix_rt::time::timeout(POLL_INTERVAL, next_block(&mut events)).await;
+        check_watchers(&data, &http).await;
+    }
+}
+
+// Calls `webhook_url` once the wallet transaction has `confirmations` confirmations,
+// so the frontend does not have to poll /tx
+#[utoipa::path(
+    post,
+    path = "/watch/tx",
+    tag = "tx-watch",
+    responses(
+        (status = 201, body = TxWatcher),
+    )
+)]
+pub(crate) async fn watch_tx(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<WatchTxRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let confirmations = req.confirmations.unwrap_or(DEFAULT_CONFIRMATIONS);
+    info!(
+        "POST /watch/tx - wallet='{}', txid={}, confirmations={}, webhook_url={}",
+        req.wallet, req.txid, confirmations, req.webhook_url
+    );
+    if !(1..=MAX_CONFIRMATIONS).contains(&confirmations) {
+        return Err(ApiError::InvalidRequest(format!(
+            "confirmations must be between 1 and {}",
+            MAX_CONFIRMATIONS
+        )));
+    }
+    if let Err(e) = check_webhook_url(&data.config.webhook_allowed_hosts, &req.webhook_url).await {
+        warn!("POST /watch/tx - webhook_url refused: {}", e);
+        return Err(ApiError::InvalidRequest(e));
+    }
+    let txid = Txid::from_str(&req.txid)
+        .map_err(|e| ApiError::InvalidTxid(format!("Invalid transaction ID: {}", e)))?;
+    let wallet_name = data.wallet_name(&http_req, &req.wallet);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("POST /watch/tx - wallet '{}' not found", req.wallet);
+        return Err(ApiError::WalletNotFound(req.wallet.clone()));
+    };
+    let params = vec![json!(txid)];
+    let found = rpc_pool::call::<Value>(&data, client, &wallet_name, "gettransaction", params);
+    if let Err(e) = found.await? {
+        warn!(
+            "POST /watch/tx - transaction {} not in wallet '{}': {}",
+            txid, wallet_name, e
+        );
+        return Err(ApiError::NotFound(format!(
+            "Transaction {} is not in wallet '{}'",
+            txid, req.wallet
+        )));
+    }
+
+    let watcher = TxWatcher {
+        id: Uuid::new_v4().to_string(),
+        wallet: wallet_name,
+        txid: txid.to_string(),
+        confirmations,
+        webhook_url: req.webhook_url.clone(),
+        status: STATUS_PENDING.into(),
+        attempts: 0,
+        last_error: None,
+        created_at: unix_now(),
+        notified_at: None,
+    };
+    data.db.insert_tx_watcher(&watcher)?;
+    info!("Watching {} for {} confirmation(s)", txid, confirmations);
+    Ok(HttpResponse::Created().json(shown(&data, &http_req, watcher)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/watch/tx",
+    tag = "tx-watch",
+    params(TxWatchersQuery),
+    responses(
+        (status = 200, description = "Oldest first", body = Page<TxWatcher>),
+    )
+)]
+pub(crate) async fn list_tx_watchers(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<TxWatchersQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /watch/tx - status={:?}, limit={:?}, cursor={:?}",
+        query.status, query.limit, query.cursor
+    );
+    let request = PageRequest::new(
+        query.limit,
+        query.cursor.as_deref(),
+        DEFAULT_PAGE_SIZE,
+        MAX_PAGE_SIZE,
+    )?;
+    let statuses: Vec<&str> = query.status.as_deref().into_iter().collect();
+    let prefix = data.wallet_name(&http_req, "");
+    let watchers =
+        data.db
+            .tx_watchers_page(&statuses, &prefix, request.after.as_ref(), request.fetch())?;
+    let page = request.page(watchers, |watcher| {
+        Cursor::new(watcher.created_at, watcher.id.as_str())
+    });
+    Ok(HttpResponse::Ok().json(page.map(|watcher| shown(&data, &http_req, watcher))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/watch/tx/{id}",
+    tag = "tx-watch",
+    responses(
+        (status = 200, body = TxWatcher),
+    )
+)]
+pub(crate) async fn get_tx_watcher(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /watch/tx/{}", id);
+    let watcher = find_watcher(&data, &http_req, &id)?;
+    Ok(HttpResponse::Ok().json(shown(&data, &http_req, watcher)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/watch/tx/{id}",
+    tag = "tx-watch",
+    responses(
+        (status = 204, description = "The watcher was removed"),
+    )
+)]
+pub(crate) async fn remove_tx_watcher(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("DELETE /watch/tx/{}", id);
+    find_watcher(&data, &http_req, &id)?;
+    data.db.remove_tx_watcher(&id)?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,164 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::json::{GetTransactionResultDetailCategory, ListUnspentResultEntry};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+// The wallet counts a coinbase as mature at this many confirmations
+pub(crate) const COINBASE_MATURITY_CONFIRMATIONS: u32 = 101;
+// Coinbase transactions are recognised from recent history only
+pub(crate) const RECENT_TRANSACTIONS: usize = 1000;
+
+#[derive(Serialize, ToSchema)]
+struct Utxo {
+    txid: String,
+    vout: u32,
+    address: Option<String>,
+    label: Option<String>,
+    amount_sat: u64,
+    confirmations: u32,
+    // The wallet holds the keys to spend it
+    spendable: bool,
+    // The wallet knows how to spend it, given the keys
+    solvable: bool,
+    // Considered safe for coin selection, i.e. not an unconfirmed payment from others
+    safe: bool,
+    coinbase: bool,
+    // A coinbase output that cannot be spent yet
+    maturing: bool,
+    blocks_until_mature: Option<u32>,
+    descriptor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct UtxoList {
+    wallet: String,
+    utxo_count: usize,
+    total_sat: u64,
+    spendable_sat: u64,
+    utxos: Vec<Utxo>,
+}
+
+impl Utxo {
+    fn from_entry(entry: ListUnspentResultEntry, coinbase: bool) -> Self {
+        Utxo {
+            txid: entry.txid.to_string(),
+            vout: entry.vout,
+            address: entry
+                .address
+                .map(|address| address.assume_checked().to_string()),
+            label: entry.label,
+            amount_sat: entry.amount.to_sat(),
+            confirmations: entry.confirmations,
+            spendable: entry.spendable,
+            solvable: entry.solvable,
+            safe: entry.safe,
+            coinbase,
+            maturing: false,
+            blocks_until_mature: None,
+            descriptor: entry.descriptor,
+        }
+    }
+}
+
+// listunspent leaves out immature coinbase outputs, so they are taken from the
+// transaction list, which also tells which mature outputs came from a coinbase
+fn list_utxos(client: &Client) -> Result<Vec<Utxo>, RpcError> {
+    let transactions =
+        client.list_transactions(None, Some(RECENT_TRANSACTIONS), None, Some(true))?;
+    let coinbase_txids: HashSet<_> = transactions
+        .iter()
+        .filter(|tx| tx.detail.category == GetTransactionResultDetailCategory::Generate)
+        .map(|tx| tx.info.txid)
+        .collect();
+
+    // minconf 0 includes unconfirmed outputs
+    let unspent = client.list_unspent(Some(0), None, None, Some(true), None)?;
+    let mut utxos: Vec<Utxo> = unspent
+        .into_iter()
+        .map(|entry| {
+            let coinbase = coinbase_txids.contains(&entry.txid);
+            Utxo::from_entry(entry, coinbase)
+        })
+        .collect();
+
+    for tx in transactions
+        .into_iter()
+        .filter(|tx| tx.detail.category == GetTransactionResultDetailCategory::Immature)
+    {
+        // Orphaned coinbases report negative confirmations and can never mature
+        let Ok(confirmations) = u32::try_from(tx.info.confirmations) else {
+            continue;
+        };
+        utxos.push(Utxo {
+            txid: tx.info.txid.to_string(),
+            vout: tx.detail.vout,
+            address: tx
+                .detail
+                .address
+                .map(|address| address.assume_checked().to_string()),
+            label: tx.detail.label,
+            amount_sat: tx.detail.amount.to_sat().unsigned_abs(),
+            confirmations,
+            spendable: false,
+            solvable: true,
+            safe: true,
+            coinbase: true,
+            maturing: true,
+            blocks_until_mature: Some(
+                COINBASE_MATURITY_CONFIRMATIONS.saturating_sub(confirmations),
+            ),
+            descriptor: None,
+        });
+    }
+    // Newest first
+    utxos.sort_by(|a, b| {
+        a.confirmations
+            .cmp(&b.confirmations)
+            .then(a.txid.cmp(&b.txid))
+    });
+    Ok(utxos)
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{walletid}/utxos",
+    tag = "utxos",
+    responses(
+        (status = 200, body = UtxoList),
+    )
+)]
+pub(crate) async fn list_wallet_utxos(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /wallet/{}/utxos", walletid);
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let Some(client) = data.wallet_client(&wallet_name) else {
+        warn!("GET /wallet/{}/utxos - wallet not found", walletid);
+        return Err(ApiError::WalletNotFound(walletid.into_inner()));
+    };
+    match rpc_pool::blocking(move || list_utxos(&client)).await? {
+        Ok(utxos) => Ok(HttpResponse::Ok().json(UtxoList {
+            wallet: walletid.into_inner(),
+            utxo_count: utxos.len(),
+            total_sat: utxos.iter().map(|utxo| utxo.amount_sat).sum(),
+            spendable_sat: utxos
+                .iter()
+                .filter(|utxo| utxo.spendable)
+                .map(|utxo| utxo.amount_sat)
+                .sum(),
+            utxos,
+        })),
+        Err(e) => {
+            error!("Failed to list UTXOs for wallet '{}': {}", wallet_name, e);
+            Err(ApiError::rpc(&http_req, e))
+        }
+    }
+}
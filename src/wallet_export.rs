@@ -0,0 +1,288 @@
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::labels::labeled_addresses;
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+const EXPORT_VERSION: u8 = 1;
+const KDF: &str = "argon2id";
+const SALT_LEN: usize = 16;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ExportWalletRequest {
+    passphrase: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ImportWalletRequest {
+    name: String,
+    passphrase: String,
+    export: EncryptedExport,
+}
+
+// The file handed to the user; only the ciphertext carries wallet material
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct EncryptedExport {
+    version: u8,
+    kdf: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WalletExport {
+    wallet_name: String,
+    exported_at: u64,
+    descriptors: Vec<Value>,
+    labels: Vec<AddressLabel>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AddressLabel {
+    address: String,
+    label: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ImportWalletResponse {
+    name: String,
+    imported_descriptors: usize,
+    imported_labels: usize,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>, String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("key derivation failed: {e}"))?;
+    Ok(key.into())
+}
+
+fn encrypt(export: &WalletExport, passphrase: &str) -> Result<EncryptedExport, String> {
+    let plaintext = serde_json::to_vec(export).map_err(|e| e.to_string())?;
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_ref())
+        .map_err(|_| "encryption failed".to_string())?;
+    Ok(EncryptedExport {
+        version: EXPORT_VERSION,
+        kdf: KDF.into(),
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+fn decrypt(encrypted: &EncryptedExport, passphrase: &str) -> Result<WalletExport, String> {
+    if encrypted.version != EXPORT_VERSION || encrypted.kdf != KDF {
+        return Err("unsupported export format".into());
+    }
+    let salt = hex::decode(&encrypted.salt).map_err(|_| "malformed salt")?;
+    let nonce = hex::decode(&encrypted.nonce).map_err(|_| "malformed nonce")?;
+    if nonce.len() != 12 {
+        return Err("malformed nonce".into());
+    }
+    let ciphertext = hex::decode(&encrypted.ciphertext).map_err(|_| "malformed ciphertext")?;
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| "wrong passphrase or corrupted export".to_string())?;
+    serde_json::from_slice(&plaintext).map_err(|e| e.to_string())
+}
+
+fn collect_export(client: &Client, wallet_name: &str) -> Result<WalletExport, RpcError> {
+    let listed: Value = client.call("listdescriptors", &[json!(true)])?;
+    let descriptors = listed["descriptors"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+
+    let labels = labeled_addresses(client)?
+        .into_iter()
+        .filter(|(_, label)| !label.is_empty())
+        .map(|(address, label)| AddressLabel { address, label })
+        .collect();
+
+    Ok(WalletExport {
+        wallet_name: wallet_name.to_string(),
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        descriptors,
+        labels,
+    })
+}
+
+// listdescriptors entries map almost one to one onto importdescriptors requests
+pub(crate) fn import_request(descriptor: &Value) -> Value {
+    let mut request = json!({
+        "desc": descriptor["desc"],
+        "timestamp": descriptor.get("timestamp").cloned().unwrap_or(json!("now")),
+        "active": descriptor["active"].as_bool().unwrap_or(false),
+        "internal": descriptor["internal"].as_bool().unwrap_or(false),
+    });
+    if let Some(range) = descriptor.get("range") {
+        request["range"] = range.clone();
+    }
+    if let Some(next) = descriptor
+        .get("next_index")
+        .or_else(|| descriptor.get("next"))
+    {
+        request["next_index"] = next.clone();
+    }
+    request
+}
+
+fn restore(client: &Client, name: &str, export: &WalletExport) -> Result<(), RpcError> {
+    client.create_wallet(name, None, Some(true), None, None)?;
+    let requests: Vec<Value> = export.descriptors.iter().map(import_request).collect();
+    let results: Vec<Value> = client.call("importdescriptors", &[json!(requests)])?;
+    if let Some(failed) = results
+        .iter()
+        .find(|result| result["success"] != json!(true))
+    {
+        return Err(RpcError::ReturnedError(format!(
+            "descriptor import failed: {}",
+            failed["error"]
+        )));
+    }
+    for label in &export.labels {
+        client.call::<Value>("setlabel", &[json!(label.address), json!(label.label)])?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/export",
+    tag = "wallet-export",
+    responses(
+        (status = 200, description = "The encrypted export, sent as an attachment", body = EncryptedExport),
+    )
+)]
+pub(crate) async fn export_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<ExportWalletRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/{}/export", walletid);
+    if req.passphrase.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "Passphrase must not be empty".into(),
+        ));
+    }
+    let wallet_name = data.wallet_name(&http_req, &walletid);
+    let export = match data.wallet_client(&wallet_name) {
+        Some(client) => {
+            let name = wallet_name.clone();
+            rpc_pool::blocking(move || collect_export(&client, &name)).await?
+        }
+        None => {
+            warn!("POST /wallet/{}/export - wallet not found", walletid);
+            return Err(ApiError::WalletNotFound(walletid.into_inner()));
+        }
+    };
+    let export = match export {
+        Ok(export) => export,
+        Err(e) => {
+            error!(
+                "Failed to collect export for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+
+    match encrypt(&export, &req.passphrase) {
+        Ok(encrypted) => Ok(HttpResponse::Ok()
+            .insert_header(header::ContentDisposition::attachment(format!(
+                "{}.cctwallet",
+                walletid
+            )))
+            .json(encrypted)),
+        Err(e) => {
+            error!(
+                "Failed to encrypt export for wallet '{}': {}",
+                wallet_name, e
+            );
+            Err(ApiError::Internal(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/import",
+    tag = "wallet-export",
+    responses(
+        (status = 200, body = ImportWalletResponse),
+    )
+)]
+pub(crate) async fn import_wallet(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ImportWalletRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /wallet/import - name='{}'", req.name);
+    let export = match decrypt(&req.export, &req.passphrase) {
+        Ok(export) => export,
+        Err(e) => {
+            warn!("POST /wallet/import - cannot decrypt export: {}", e);
+            return Err(ApiError::InvalidRequest(e));
+        }
+    };
+
+    let wallet_name = data.wallet_name(&http_req, &req.name);
+    let client = match data.config.create_client(&wallet_name) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create RPC client for wallet '{}': {}",
+                wallet_name, e
+            );
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let name = wallet_name.clone();
+    let (client, export, restored) = rpc_pool::blocking(move || {
+        let restored = restore(&client, &name, &export);
+        (client, export, restored)
+    })
+    .await?;
+    if let Err(e) = restored {
+        error!("Failed to import wallet '{}': {}", wallet_name, e);
+        return Err(ApiError::rpc(&http_req, e));
+    }
+
+    info!(
+        "Imported wallet '{}' from export of '{}'",
+        wallet_name, export.wallet_name
+    );
+    data.events.publish(Event::WalletReady {
+        wallet: wallet_name.clone(),
+    });
+    data.register_wallet(wallet_name, client);
+    Ok(HttpResponse::Ok().json(ImportWalletResponse {
+        name: req.name.clone(),
+        imported_descriptors: export.descriptors.len(),
+        imported_labels: export.labels.len(),
+    }))
+}
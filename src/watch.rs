@@ -0,0 +1,415 @@
+use crate::db::{unix_now, WatchedAddress, WatchedOutput};
+use crate::errors::{ApiError, Language};
+use crate::events::Event;
+use crate::format::{format_amount, FormattedAmount};
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::rpc_pool;
+use crate::store::SharedStore;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(10);
+// The watch list lives in the shared state, one key per address, so every replica
+// sees the same one. Each replica scans the blocks itself and keeps its own progress.
+const WATCH_PREFIX: &str = "watch:address:";
+const SCAN_CURSOR: &str = "watch";
+// Upper bound of blocks scanned per round, so a long catch-up does not stall the task
+const MAX_BLOCKS_PER_SCAN: u64 = 500;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct WatchAddressRequest {
+    address: String,
+    label: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct WatchedQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressHistory {
+    address: String,
+    total_received_sat: u64,
+    total_received_formatted: FormattedAmount,
+    outputs: Vec<WatchedOutput>,
+}
+
+fn watch_key(address: &str) -> String {
+    format!("{WATCH_PREFIX}{address}")
+}
+
+// Oldest first
+async fn watched_addresses(store: &dyn SharedStore) -> Result<Vec<WatchedAddress>, String> {
+    let mut watched = Vec::new();
+    for key in store.keys(WATCH_PREFIX).await? {
+        let Some(value) = store.get(&key).await? else {
+            continue;
+        };
+        match serde_json::from_str::<WatchedAddress>(&value) {
+            Ok(address) => watched.push(address),
+            Err(e) => warn!("Ignoring unreadable watched address '{}': {}", key, e),
+        }
+    }
+    watched.sort_by_key(cursor);
+    Ok(watched)
+}
+
+fn cursor(watched: &WatchedAddress) -> Cursor {
+    Cursor::new(watched.created_at, watched.address.as_str())
+}
+
+async fn save(store: &dyn SharedStore, watched: &WatchedAddress) -> Result<(), String> {
+    let value = serde_json::to_string(watched).map_err(|e| e.to_string())?;
+    store.set(&watch_key(&watched.address), &value, None).await
+}
+
+// The database keeps the watch list across restarts, the shared state shares it between
+// replicas. On startup the shared state gets the addresses it is missing, which refills
+// the in-memory store after a restart.
+pub(crate) async fn import_stored(data: &AppState) {
+    let stored = match data.db.watched_addresses() {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("Failed to read the stored watch list: {}", e);
+            return;
+        }
+    };
+    for watched in &stored {
+        let imported = match serde_json::to_string(watched) {
+            Ok(value) => {
+                data.store
+                    .set_if_absent(&watch_key(&watched.address), &value, None)
+                    .await
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = imported {
+            error!("Failed to share watched address {}: {}", watched.address, e);
+            return;
+        }
+    }
+}
+
+// Saves addresses other replicas started watching in this replica's database too
+fn store_shared(data: &AppState, shared: &[WatchedAddress]) {
+    let stored = match data.db.watched_addresses() {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("Failed to read the stored watch list: {}", e);
+            return;
+        }
+    };
+    for watched in shared {
+        if stored.iter().any(|known| known.address == watched.address) {
+            continue;
+        }
+        if let Err(e) = data.db.watch_address(watched) {
+            error!("Failed to save watched address {}: {}", watched.address, e);
+        }
+    }
+}
+
+// After a reset the chain is scanned again from the start
+pub(crate) async fn rewind(data: &AppState) -> Result<(), String> {
+    for mut watched in watched_addresses(data.store.as_ref()).await? {
+        watched.scanned_height = 0;
+        save(data.store.as_ref(), &watched).await?;
+    }
+    Ok(())
+}
+
+// Last block this replica scanned, if the node still has it on the active chain
+fn scanned_tip(data: &AppState, client: &Client) -> Option<u64> {
+    let hash = match data.db.chain_cursor(SCAN_CURSOR) {
+        Ok(hash) => BlockHash::from_str(&hash?).ok()?,
+        Err(e) => {
+            error!("Failed to load the watch scan cursor: {}", e);
+            return None;
+        }
+    };
+    let header = client.get_block_header_info(&hash).ok()?;
+    (header.confirmations >= 0).then_some(header.height as u64)
+}
+
+// Looks for outputs paying watched addresses in blocks after the last one scanned.
+// An address only counts in blocks above its `scanned_height`.
+fn scan_new_blocks(
+    data: &AppState,
+    client: &Client,
+    watched: &[WatchedAddress],
+) -> Result<(), String> {
+    let Some(first) = watched.iter().map(|watched| watched.scanned_height).min() else {
+        return Ok(());
+    };
+    let from = scanned_tip(data, client).unwrap_or(first).max(first) + 1;
+    let tip = client.get_block_count().map_err(|e| e.to_string())?;
+    if from > tip {
+        return Ok(());
+    }
+    let to = tip.min(from + MAX_BLOCKS_PER_SCAN - 1);
+    let scanned_from: HashMap<&str, u64> = watched
+        .iter()
+        .map(|watched| (watched.address.as_str(), watched.scanned_height))
+        .collect();
+    let labels: HashMap<&str, Option<String>> = watched
+        .iter()
+        .map(|watched| (watched.address.as_str(), watched.label.clone()))
+        .collect();
+
+    for height in from..=to {
+        let block = fetch_block(client, height).map_err(|e| e.to_string())?;
+        let block_hash = block["hash"].as_str().unwrap_or_default().to_string();
+        for tx in block["tx"].as_array().into_iter().flatten() {
+            let txid = tx["txid"].as_str().unwrap_or_default();
+            for output in tx["vout"].as_array().into_iter().flatten() {
+                let Some(address) = output["scriptPubKey"]["address"].as_str() else {
+                    continue;
+                };
+                if scanned_from
+                    .get(address)
+                    .is_none_or(|scanned| *scanned >= height)
+                {
+                    continue;
+                }
+                let output = WatchedOutput {
+                    txid: txid.to_string(),
+                    vout: output["n"].as_u64().unwrap_or_default() as u32,
+                    amount_sat: output["value"]
+                        .as_f64()
+                        .and_then(|btc| Amount::from_btc(btc).ok())
+                        .map(Amount::to_sat)
+                        .unwrap_or_default(),
+                    block_hash: block_hash.clone(),
+                    block_height: height,
+                };
+                match data.db.record_watched_output(address, &output) {
+                    Ok(true) => {
+                        info!(
+                            "Watched address {} received {} sat in {}",
+                            address, output.amount_sat, txid
+                        );
+                        data.events.publish(Event::AddressActivity {
+                            address: address.to_string(),
+                            label: labels.get(address).cloned().flatten(),
+                            txid: output.txid,
+                            vout: output.vout,
+                            amount_sat: output.amount_sat,
+                            block_height: height,
+                        });
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!(
+                        "Failed to record output for watched address {}: {}",
+                        address, e
+                    ),
+                }
+            }
+        }
+    }
+
+    let hash = client.get_block_hash(to).map_err(|e| e.to_string())?;
+    if let Err(e) = data.db.set_chain_cursor(SCAN_CURSOR, &hash.to_string()) {
+        error!("Failed to save the watch scan cursor: {}", e);
+    }
+    debug!("Scanned blocks {}..={} for watched addresses", from, to);
+    Ok(())
+}
+
+fn fetch_block(client: &Client, height: u64) -> Result<Value, RpcError> {
+    let hash = client.get_block_hash(height)?;
+    // Verbosity 2 includes decoded transactions with output addresses
+    client.call("getblock", &[json!(hash), json!(2)])
+}
+
+pub(crate) async fn scan_watched_addresses(data: web::Data<AppState>) {
+    import_stored(&data).await;
+    loop {
+        actix_rt::time::sleep(SCAN_INTERVAL).await;
+        let watched = match watched_addresses(data.store.as_ref()).await {
+            Ok(watched) => watched,
+            Err(e) => {
+                warn!("Failed to read the watch list: {}", e);
+                continue;
+            }
+        };
+        let scanned = rpc_pool::with_state(&data, move |data| {
+            store_shared(data, &watched);
+            let client = data
+                .config
+                .create_node_client()
+                .map_err(|e| format!("cannot create a node client: {e}"))?;
+            scan_new_blocks(data, &client, &watched)
+        });
+        match scanned.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Scanning blocks for watched addresses failed: {}", e),
+            Err(e) => warn!("Scanning blocks for watched addresses failed: {}", e),
+        }
+    }
+}
+
+// Watching starts at the current tip; earlier history is not scanned
+#[utoipa::path(
+    post,
+    path = "/watch-address",
+    tag = "watch",
+    responses(
+        (status = 201, body = Object),
+        (status = 200, description = "The address was already watched", body = Object),
+    )
+)]
+pub(crate) async fn watch_address(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<WatchAddressRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("POST /watch-address - address='{}'", req.address);
+    let address = match Address::from_str(&req.address)
+        .map_err(|e| e.to_string())
+        .and_then(|addr| {
+            addr.require_network(data.config.network)
+                .map_err(|e| e.to_string())
+        }) {
+        Ok(address) => address.to_string(),
+        Err(e) => {
+            warn!("POST /watch-address - invalid address: {}", e);
+            return Err(ApiError::InvalidAddress(format!("Invalid address: {}", e)));
+        }
+    };
+    let tip = match rpc_pool::call_node::<u64>(&data, "getblockcount", vec![]).await? {
+        Ok(tip) => tip,
+        Err(e) => {
+            error!("Failed to read chain tip: {}", e);
+            return Err(ApiError::rpc(&http_req, e));
+        }
+    };
+    let watched = WatchedAddress {
+        address: address.clone(),
+        label: req.label.clone(),
+        created_at: unix_now(),
+        scanned_height: tip,
+    };
+    if let Err(e) = data.db.watch_address(&watched) {
+        error!("Failed to save watched address {}: {}", address, e);
+        return Err(e.into());
+    }
+    let value = serde_json::to_string(&watched).map_err(|e| ApiError::Internal(e.to_string()))?;
+    match data
+        .store
+        .set_if_absent(&watch_key(&address), &value, None)
+        .await
+    {
+        Ok(true) => {
+            Ok(HttpResponse::Created().json(json!({ "address": address, "from_height": tip + 1 })))
+        }
+        Ok(false) => {
+            Ok(HttpResponse::Ok().json(json!({ "address": address, "already_watched": true })))
+        }
+        Err(e) => {
+            error!("Failed to save watched address {}: {}", address, e);
+            Err(ApiError::Storage(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/watch-address",
+    tag = "watch",
+    params(WatchedQuery),
+    responses(
+        (status = 200, description = "Oldest first", body = Page<WatchedAddress>),
+    )
+)]
+pub(crate) async fn list_watched(
+    data: web::Data<AppState>,
+    query: web::Query<WatchedQuery>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "GET /watch-address - limit={:?}, cursor={:?}",
+        query.limit, query.cursor
+    );
+    let request = PageRequest::new(
+        query.limit,
+        query.cursor.as_deref(),
+        DEFAULT_PAGE_SIZE,
+        MAX_PAGE_SIZE,
+    )?;
+    match watched_addresses(data.store.as_ref()).await {
+        Ok(watched) => {
+            let rows = watched
+                .into_iter()
+                .filter(|watched| {
+                    request
+                        .after
+                        .as_ref()
+                        .is_none_or(|after| cursor(watched) > *after)
+                })
+                .take(request.fetch())
+                .collect();
+            Ok(HttpResponse::Ok().json(request.page(rows, cursor)))
+        }
+        Err(e) => {
+            error!("Failed to list watched addresses: {}", e);
+            Err(ApiError::Storage(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/watch-address/{address}",
+    tag = "watch",
+    responses(
+        (status = 200, body = AddressHistory),
+    )
+)]
+pub(crate) async fn address_history(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    address: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    info!("GET /watch-address/{}", address);
+    let is_watched = match data.store.get(&watch_key(&address)).await {
+        Ok(watched) => watched.is_some(),
+        Err(e) => {
+            error!("Failed to read the watch list: {}", e);
+            return Err(ApiError::Storage(e));
+        }
+    };
+    if !is_watched {
+        return Err(ApiError::NotFound("Address is not watched".into()));
+    }
+    match data.db.watched_outputs(&address) {
+        Ok(outputs) => {
+            let total_received_sat = outputs.iter().map(|output| output.amount_sat).sum();
+            Ok(HttpResponse::Ok().json(AddressHistory {
+                address: address.into_inner(),
+                total_received_sat,
+                total_received_formatted: format_amount(
+                    total_received_sat,
+                    Language::from_request(&http_req),
+                ),
+                outputs,
+            }))
+        }
+        Err(e) => {
+            error!("Failed to load history of {}: {}", address, e);
+            Err(e.into())
+        }
+    }
+}
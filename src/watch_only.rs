@@ -0,0 +1,215 @@
+use crate::errors::ApiError;
+use crate::rpc_pool;
+use crate::wallet_export::import_request;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bitcoincore_rpc::{Client, Error as RpcError, RpcApi};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+// Addresses of a ranged descriptor the wallet looks for, like a fresh wallet's keypool
+const DEFAULT_RANGE_END: u64 = 1000;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ImportDescriptorRequest {
+    // Either a descriptor, or another wallet whose public descriptors are copied
+    descriptor: Option<String>,
+    from_wallet: Option<String>,
+    // Only for a descriptor of a single address
+    label: Option<String>,
+    // Scans the whole chain for past transactions; false only sees new ones
+    rescan: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ImportedDescriptor {
+    descriptor: String,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ImportDescriptorResponse {
+    wallet: String,
+    // The wallet has no private keys, so it can watch but not spend
+    watch_only: bool,
+    rescanned: bool,
+    imported: Vec<ImportedDescriptor>,
+}
+
+fn descriptor_request(
+    node: &Client,
+    descriptor: &str,
+    label: Option<&str>,
+    timestamp: &Value,
+) -> Result<Value, ApiError> {
+    // Adds the checksum and tells whether the descriptor is ranged
+    let info: Value = node
+        .call("getdescriptorinfo", &[json!(descriptor)])
+        .map_err(|e| ApiError::InvalidRequest(format!("Invalid descriptor: {}", e)))?;
+    let ranged = info["isrange"].as_bool().unwrap_or(false);
+    // getdescriptorinfo drops private keys from `descriptor`, so the checksum is added by hand
+    let checksum = info["checksum"].as_str().unwrap_or_default();
+    let mut request = json!({
+        "desc": format!("{}#{}", descriptor.split('#').next().unwrap_or_default(), checksum),
+        "timestamp": timestamp,
+    });
+    match (ranged, label) {
+        (true, Some(_)) => {
+            return Err(ApiError::InvalidRequest(
+                "A ranged descriptor cannot have a label".into(),
+            ))
+        }
+        (true, None) => request["range"] = json!([0, DEFAULT_RANGE_END]),
+        (false, Some(label)) => request["label"] = json!(label),
+        (false, None) => {}
+    }
+    Ok(request)
+}
+
+// The source wallet's public descriptors, inactive so the copy never hands out addresses
+fn copied_requests(source: &Client, timestamp: &Value) -> Result<Vec<Value>, RpcError> {
+    let listed: Value = source.call("listdescriptors", &[])?;
+    Ok(listed["descriptors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|entry| {
+            let mut request = import_request(entry);
+            request["active"] = json!(false);
+            if timestamp == "now" {
+                request["timestamp"] = timestamp.clone();
+            }
+            request
+        })
+        .collect())
+}
+
+// Imports descriptors into a wallet, typically a watch-only wallet observing another
+// character's addresses
+#[utoipa::path(
+    post,
+    path = "/wallet/{walletid}/import-descriptor",
+    tag = "watch-only",
+    responses(
+        (status = 200, body = ImportDescriptorResponse),
+    )
+)]
+pub(crate) async fn import_descriptor(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    walletid: web::Path<String>,
+    req: web::Json<ImportDescriptorRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!(
+        "POST /wallet/{}/import-descriptor - from_wallet={:?}, rescan={:?}",
+        walletid, req.from_wallet, req.rescan
+    );
+    let walletid = walletid.into_inner();
+    let req = req.into_inner();
+    // importdescriptors rescans the chain, which can take minutes
+    let response = rpc_pool::for_caller(&data, &http_req, move |data, caller| {
+        let wallet_name = caller.wallet_name(&walletid);
+        let Some(client) = data.wallet_client(&wallet_name) else {
+            warn!(
+                "POST /wallet/{}/import-descriptor - wallet not found",
+                walletid
+            );
+            return Err(ApiError::WalletNotFound(walletid));
+        };
+        let rescan = req.rescan.unwrap_or(true);
+        let timestamp = if rescan { json!(0) } else { json!("now") };
+
+        let requests = match (&req.descriptor, &req.from_wallet) {
+            (Some(descriptor), None) => {
+                let node = caller.node_client(data)?;
+                vec![descriptor_request(
+                    &node,
+                    descriptor,
+                    req.label.as_deref(),
+                    &timestamp,
+                )?]
+            }
+            (None, Some(from_wallet)) => {
+                if req.label.is_some() {
+                    return Err(ApiError::InvalidRequest(
+                        "label only applies to a single descriptor".into(),
+                    ));
+                }
+                let source_name = caller.wallet_name(from_wallet);
+                let Some(source) = data.wallet_client(&source_name) else {
+                    return Err(ApiError::WalletNotFound(from_wallet.clone()));
+                };
+                copied_requests(&source, &timestamp).map_err(|e| {
+                    error!(
+                        "Failed to list descriptors of wallet '{}': {}",
+                        source_name, e
+                    );
+                    caller.rpc(e)
+                })?
+            }
+            _ => {
+                return Err(ApiError::InvalidRequest(
+                    "Give either descriptor or from_wallet".into(),
+                ))
+            }
+        };
+
+        let results: Vec<Value> = match client.call("importdescriptors", &[json!(requests)]) {
+            Ok(results) => results,
+            Err(e) => {
+                error!(
+                    "importdescriptors failed for wallet '{}': {}",
+                    wallet_name, e
+                );
+                return Err(caller.rpc(e));
+            }
+        };
+        // e.g. private keys offered to a wallet that has them disabled
+        if let Some(failed) = results
+            .iter()
+            .find(|result| result["success"] != json!(true))
+        {
+            let message = failed["error"]["message"].as_str().unwrap_or_default();
+            warn!(
+                "Descriptor import into wallet '{}' failed: {}",
+                wallet_name, message
+            );
+            return Err(ApiError::InvalidRequest(format!(
+                "Descriptor import failed: {}",
+                message
+            )));
+        }
+        let imported = requests
+            .iter()
+            .zip(&results)
+            .map(|(request, result)| ImportedDescriptor {
+                descriptor: request["desc"].as_str().unwrap_or_default().to_string(),
+                warnings: result["warnings"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|warning| warning.as_str().map(str::to_string))
+                    .collect(),
+            })
+            .collect();
+        let watch_only = client
+            .get_wallet_info()
+            .map(|info| !info.private_keys_enabled)
+            .unwrap_or(false);
+        info!(
+            "Imported {} descriptor(s) into wallet '{}'",
+            requests.len(),
+            wallet_name
+        );
+        Ok(ImportDescriptorResponse {
+            wallet: walletid,
+            watch_only,
+            rescanned: rescan,
+            imported,
+        })
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
@@ -0,0 +1,402 @@
+use crate::errors::ApiError;
+use crate::events::Event;
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::{Message, Session};
+use dotenv as env;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::IntoParams;
+
+// Access tokens for /ws; each grants either every wallet or the listed ones
+#[derive(Clone)]
+pub(crate) struct WsTokens {
+    tokens: Vec<(String, Option<HashSet<String>>)>,
+}
+
+// Keep the tokens out of any debug output
+impl fmt::Debug for WsTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WsTokens")
+            .field("count", &self.tokens.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl WsTokens {
+    // `ws_tokens` takes a comma separated list; `token` grants every wallet,
+    // `token:alice|bob` only the listed wallets. Without it /ws is open.
+    pub(crate) fn from_env() -> Option<Self> {
+        let value = env::var("ws_tokens").ok()?;
+        let tokens: Vec<_> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((token, wallets)) => (
+                    token.to_string(),
+                    Some(
+                        wallets
+                            .split('|')
+                            .map(str::trim)
+                            .filter(|wallet| !wallet.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    ),
+                ),
+                None => (entry.to_string(), None),
+            })
+            .collect();
+        (!tokens.is_empty()).then_some(Self { tokens })
+    }
+
+    // The wallets the token grants (None for all); Err for an unknown token
+    fn grant(&self, token: &str) -> Result<Option<&HashSet<String>>, ()> {
+        self.tokens
+            .iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, wallets)| wallets.as_ref())
+            .ok_or(())
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct WsQuery {
+    // Only this wallet's events are pushed, besides the chain-wide ones
+    wallet: Option<String>,
+    // Browsers cannot set headers on a WebSocket, so the token can come in the URL
+    token: Option<String>,
+}
+
+// Commands clients send as JSON text frames to change what they receive
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Command {
+    Subscribe {
+        #[serde(default)]
+        wallets: Vec<String>,
+        #[serde(default)]
+        events: Vec<String>,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        wallets: Vec<String>,
+        #[serde(default)]
+        events: Vec<String>,
+    },
+    Subscriptions,
+}
+
+// Replies to commands, tagged like the events so clients parse every frame alike
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Subscriptions {
+        // None means every wallet (or event type) the connection may see
+        wallets: Option<BTreeSet<String>>,
+        events: Option<BTreeSet<String>>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// What one connection receives. Wallet names are as in bitcoind, i.e. scoped in
+// sandbox mode.
+pub(crate) struct Subscriptions {
+    // Prefix of the wallets the caller may see, empty outside sandbox mode
+    scope: String,
+    // Wallets the token grants, None for all
+    allowed: Option<HashSet<String>>,
+    wallets: Option<HashSet<String>>,
+    events: Option<HashSet<String>>,
+}
+
+impl Subscriptions {
+    // Chain-wide events go to everyone; wallet events only to the wallet's audience,
+    // which in sandbox mode is the session that owns it
+    pub(crate) fn wants(&self, event: &Event) -> bool {
+        if self
+            .events
+            .as_ref()
+            .is_some_and(|events| !events.contains(event.kind()))
+        {
+            return false;
+        }
+        match event.wallet() {
+            None => true,
+            Some(wallet) => event
+                .counterparty()
+                .into_iter()
+                .chain([wallet])
+                .any(|wallet| {
+                    wallet.starts_with(&self.scope)
+                        && self.allowed_wallet(wallet)
+                        && self
+                            .wallets
+                            .as_ref()
+                            .is_none_or(|wallets| wallets.contains(wallet))
+                }),
+        }
+    }
+
+    fn apply(&mut self, data: &AppState, http_req: &HttpRequest, command: Command) -> Reply {
+        match command {
+            Command::Subscribe { wallets, events } => {
+                if let Some(unknown) = events
+                    .iter()
+                    .find(|kind| !Event::KINDS.contains(&kind.as_str()))
+                {
+                    return error_reply(format!("Unknown event type '{unknown}'"));
+                }
+                let wallets: Vec<String> = wallets
+                    .iter()
+                    .map(|wallet| data.wallet_name(http_req, wallet))
+                    .collect();
+                if let Some(denied) = wallets.iter().find(|wallet| !self.allowed_wallet(wallet)) {
+                    return error_reply(format!(
+                        "The token does not grant access to wallet '{}'",
+                        self.unscoped(denied)
+                    ));
+                }
+                if !wallets.is_empty() {
+                    self.wallets
+                        .get_or_insert_with(HashSet::new)
+                        .extend(wallets);
+                }
+                if !events.is_empty() {
+                    self.events.get_or_insert_with(HashSet::new).extend(events);
+                }
+            }
+            Command::Unsubscribe { wallets, events } => {
+                if !wallets.is_empty() {
+                    let Some(subscribed) = &mut self.wallets else {
+                        return error_reply(
+                            "Subscribed to every wallet; subscribe to single wallets first".into(),
+                        );
+                    };
+                    for wallet in &wallets {
+                        subscribed.remove(&data.wallet_name(http_req, wallet));
+                    }
+                }
+                if !events.is_empty() {
+                    let Some(subscribed) = &mut self.events else {
+                        return error_reply(
+                            "Subscribed to every event type; subscribe to single types first"
+                                .into(),
+                        );
+                    };
+                    for kind in &events {
+                        subscribed.remove(kind);
+                    }
+                }
+            }
+            Command::Subscriptions => {}
+        }
+        self.reply()
+    }
+
+    fn allowed_wallet(&self, wallet: &str) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(wallet))
+    }
+
+    fn unscoped<'a>(&self, wallet: &'a str) -> &'a str {
+        wallet.strip_prefix(&self.scope).unwrap_or(wallet)
+    }
+
+    fn reply(&self) -> Reply {
+        Reply::Subscriptions {
+            wallets: self.wallets.as_ref().map(|wallets| {
+                wallets
+                    .iter()
+                    .map(|wallet| self.unscoped(wallet).to_string())
+                    .collect()
+            }),
+            events: self
+                .events
+                .as_ref()
+                .map(|events| events.iter().cloned().collect()),
+        }
+    }
+}
+
+fn error_reply(message: String) -> Reply {
+    Reply::Error { message }
+}
+
+async fn send_json<T: Serialize>(session: &mut Session, value: &T) -> bool {
+    match serde_json::to_string(value) {
+        Ok(text) => session.text(text).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+async fn push_events(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    mut session: Session,
+    mut messages: actix_ws::MessageStream,
+    mut subscriptions: Subscriptions,
+) {
+    let mut events = data.events.subscribe();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if subscriptions.wants(&event) && !send_json(&mut session, &event).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket client lagged behind, skipped {} event(s)", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<Command>(&text) {
+                        Ok(command) => subscriptions.apply(&data, &http_req, command),
+                        Err(e) => error_reply(format!("Invalid command: {e}")),
+                    };
+                    if !send_json(&mut session, &reply).await {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    debug!("WebSocket client closed the connection");
+                    return;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            },
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+fn request_token<'a>(http_req: &'a HttpRequest, query_token: Option<&'a str>) -> Option<&'a str> {
+    query_token.or_else(|| {
+        http_req
+            .headers()
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")
+    })
+}
+
+// The wallets (scoped) the caller's token grants, None for all or when `ws_tokens` is
+// not set. Event streams share the tokens with /ws.
+pub(crate) fn granted_wallets(
+    data: &AppState,
+    http_req: &HttpRequest,
+    query_token: Option<&str>,
+    endpoint: &str,
+) -> Result<Option<HashSet<String>>, ApiError> {
+    let Some(tokens) = &data.config.ws_tokens else {
+        return Ok(None);
+    };
+    let Some(token) = request_token(http_req, query_token) else {
+        warn!("{} - no token", endpoint);
+        return Err(ApiError::Unauthorized(format!(
+            "A token is required for {}",
+            http_req.path()
+        )));
+    };
+    match tokens.grant(token) {
+        Ok(wallets) => Ok(wallets.map(|wallets| {
+            wallets
+                .iter()
+                .map(|wallet| data.wallet_name(http_req, wallet))
+                .collect()
+        })),
+        Err(()) => {
+            warn!("{} - unknown token", endpoint);
+            Err(ApiError::Unauthorized("Unknown token".into()))
+        }
+    }
+}
+
+// Every event the caller may see, or with `wallet` only that wallet's besides the
+// chain-wide ones
+pub(crate) fn subscribe(
+    data: &AppState,
+    http_req: &HttpRequest,
+    wallet: Option<&str>,
+    token: Option<&str>,
+    endpoint: &str,
+) -> Result<Subscriptions, ApiError> {
+    let allowed = granted_wallets(data, http_req, token, endpoint)?;
+    let wallets = wallet.map(|wallet| HashSet::from([data.wallet_name(http_req, wallet)]));
+    let subscriptions = Subscriptions {
+        scope: data.wallet_name(http_req, ""),
+        allowed,
+        wallets,
+        events: None,
+    };
+    if let Some(wallet) = subscriptions
+        .wallets
+        .iter()
+        .flatten()
+        .find(|wallet| !subscriptions.allowed_wallet(wallet))
+    {
+        return Err(ApiError::Unauthorized(format!(
+            "The token does not grant access to wallet '{}'",
+            subscriptions.unscoped(wallet)
+        )));
+    }
+    Ok(subscriptions)
+}
+
+// Pushes events as JSON text frames, in the same format the webhook sink posts
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "events",
+    params(WsQuery),
+    responses(
+        (status = 101, description = "Switches to a WebSocket streaming events"),
+    )
+)]
+pub(crate) async fn events_socket(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<WsQuery>,
+) -> actix_web::Result<HttpResponse> {
+    info!("GET /ws - wallet={:?}", query.wallet);
+    // Checked before the upgrade, so a bad token gets a plain 401
+    let subscriptions = subscribe(
+        &data,
+        &http_req,
+        query.wallet.as_deref(),
+        query.token.as_deref(),
+        "GET /ws",
+    )?;
+    let (response, session, messages) = actix_ws::handle(&http_req, body)?;
+    actix_rt::spawn(push_events(
+        data,
+        http_req,
+        session,
+        messages,
+        subscriptions,
+    ));
+    Ok(response)
+}
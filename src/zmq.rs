@@ -0,0 +1,167 @@
+use crate::events::Event;
+use crate::propagation::{self, PRIMARY_NODE, SOURCE_ZMQ};
+use crate::rpc_pool;
+use crate::AppState;
+use actix_web::web;
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::{Address, Block, OutPoint, Transaction};
+use bitcoincore_rpc::json::GetTransactionResultDetailCategory;
+use bitcoincore_rpc::{Client, RpcApi};
+use dotenv as env;
+use log::{debug, info, warn};
+use std::time::Duration;
+use zeromq::{Socket, SocketRecv, SubSocket};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// bitcoind's -zmqpubrawblock / -zmqpubrawtx endpoints, e.g. tcp://127.0.0.1:28332
+#[derive(Debug, Clone)]
+pub(crate) struct ZmqConfig {
+    pub(crate) rawblock: Option<String>,
+    pub(crate) rawtx: Option<String>,
+}
+
+impl ZmqConfig {
+    pub(crate) fn from_env() -> Option<Self> {
+        let endpoint = |key| env::var(key).ok().filter(|value| !value.trim().is_empty());
+        let config = Self {
+            rawblock: endpoint("zmq_rawblock"),
+            rawtx: endpoint("zmq_rawtx"),
+        };
+        (config.rawblock.is_some() || config.rawtx.is_some()).then_some(config)
+    }
+}
+
+fn block_event(data: &AppState, payload: &[u8]) -> Option<Event> {
+    let block: Block = match deserialize(payload) {
+        Ok(block) => block,
+        Err(e) => {
+            warn!("Ignoring undecodable ZMQ block: {}", e);
+            return None;
+        }
+    };
+    let hash = block.block_hash();
+    // The coinbase carries the height since BIP34; ask the node when it does not
+    let height = block.bip34_block_height().ok().or_else(|| {
+        data.config
+            .create_node_client()
+            .and_then(|node| node.get_block_header_info(&hash))
+            .map(|header| header.height as u64)
+            .ok()
+    })?;
+    Some(Event::BlockConnected {
+        height,
+        hash: hash.to_string(),
+    })
+}
+
+// Whether the wallet owns the spent output; its send details are for the outputs it paid
+fn spends_wallet_coin(client: &Client, outpoint: &OutPoint) -> bool {
+    client
+        .get_transaction(&outpoint.txid, Some(true))
+        .is_ok_and(|tx| {
+            tx.details.iter().any(|detail| {
+                detail.vout == outpoint.vout
+                    && detail.category != GetTransactionResultDetailCategory::Send
+            })
+        })
+}
+
+// Whether the transaction pays to or spends from one of the registered wallets. The
+// addresses are asked rather than the wallet's history, which may not have the
+// transaction yet when the notification arrives.
+fn is_wallet_transaction(data: &AppState, tx: &Transaction) -> bool {
+    let addresses: Vec<Address> = tx
+        .output
+        .iter()
+        .filter_map(|output| Address::from_script(&output.script_pubkey, data.config.network).ok())
+        .collect();
+    let wallets: Vec<String> = data
+        .clients
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect();
+    wallets.iter().any(|wallet| {
+        let Some(client) = data.clients.get(wallet).map(|pool| pool.get()) else {
+            return false;
+        };
+        let receives = addresses.iter().any(|address| {
+            client
+                .get_address_info(address)
+                .is_ok_and(|info| info.is_mine == Some(true) || info.is_watchonly == Some(true))
+        });
+        receives
+            || tx
+                .input
+                .iter()
+                .any(|input| spends_wallet_coin(&client, &input.previous_output))
+    })
+}
+
+// Only transactions of registered wallets are published, not every one in the mempool
+fn transaction_event(data: &AppState, payload: &[u8]) -> Option<Event> {
+    match deserialize::<Transaction>(payload) {
+        Ok(tx) if !is_wallet_transaction(data, &tx) => None,
+        Ok(tx) => Some(Event::TransactionSeen {
+            txid: tx.txid().to_string(),
+            vsize: tx.vsize(),
+        }),
+        Err(e) => {
+            warn!("Ignoring undecodable ZMQ transaction: {}", e);
+            None
+        }
+    }
+}
+
+// One subscription per topic; bitcoind sends [topic, payload, sequence number]
+async fn subscribe(
+    data: &web::Data<AppState>,
+    endpoint: &str,
+    topic: &'static str,
+) -> zeromq::ZmqResult<()> {
+    let mut socket = SubSocket::new();
+    socket.connect(endpoint).await?;
+    socket.subscribe(topic).await?;
+    info!(
+        "Listening for ZMQ '{}' notifications on {}",
+        topic, endpoint
+    );
+    loop {
+        let message = socket.recv().await?;
+        let Some(payload) = message.get(1).map(|payload| payload.to_vec()) else {
+            continue;
+        };
+        // Both may ask the node, which is done on the blocking thread pool
+        let event = rpc_pool::with_state(data, move |data| match topic {
+            "rawblock" => block_event(data, &payload),
+            _ => transaction_event(data, &payload),
+        })
+        .await;
+        if let Ok(Some(event)) = event {
+            debug!("ZMQ '{}' notification: {}", topic, event.kind());
+            if let Event::BlockConnected { hash, .. } = &event {
+                propagation::record_sighting(data, PRIMARY_NODE, hash, SOURCE_ZMQ);
+            }
+            data.events.publish(event);
+        }
+    }
+}
+
+async fn listen(data: web::Data<AppState>, endpoint: String, topic: &'static str) {
+    loop {
+        if let Err(e) = subscribe(&data, &endpoint, topic).await {
+            warn!("ZMQ '{}' subscription to {} failed: {}", topic, endpoint, e);
+        }
+        actix_rt::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// Feeds node notifications into the event bus, so subscribers need not poll the RPC
+pub(crate) fn spawn_listeners(data: &web::Data<AppState>, config: &ZmqConfig) {
+    if let Some(endpoint) = &config.rawblock {
+        actix_rt::spawn(listen(data.clone(), endpoint.clone(), "rawblock"));
+    }
+    if let Some(endpoint) = &config.rawtx {
+        actix_rt::spawn(listen(data.clone(), endpoint.clone(), "rawtx"));
+    }
+}